@@ -0,0 +1,406 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions, BasicRejectOptions};
+use lapin::types::FieldTable;
+use lapin::Channel;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use startup_base::messaging::{self, Outcome};
+use tokio_util::sync::CancellationToken;
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::handler::{Handler, HeaderExtractor, Message};
+use crate::{Amqp, AmqpError};
+
+/// Delay before consuming a queue again if that failed while the connection was up,
+/// reconnecting is delayed by the backoff of [Amqp::channel] instead.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsumerConfig {
+    /// Number of unacknowledged messages per queue. This is also the number of messages
+    /// that are handled at the same time, so with a value larger than one, messages are
+    /// no longer handled in order.
+    #[serde(default = "default_prefetch")]
+    pub prefetch: u16,
+
+    /// Attempts to handle a message before it is rejected and dead-lettered.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Delay before a failed message is handled again. The delay doubles with every
+    /// attempt, up to `max_retry_backoff_ms`.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+
+    #[serde(default = "default_max_retry_backoff_ms")]
+    pub max_retry_backoff_ms: u64,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        Self {
+            prefetch: default_prefetch(),
+            max_attempts: default_max_attempts(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+            max_retry_backoff_ms: default_max_retry_backoff_ms(),
+        }
+    }
+}
+
+fn default_prefetch() -> u16 {
+    10
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_retry_backoff_ms() -> u64 {
+    30_000
+}
+
+/// Consumes queues and passes their messages to the handler registered for the queue.
+/// Every message is traced as a consumer span that continues the trace of the producer.
+///
+/// A message is acknowledged once its handler succeeded. If a handler fails, it is called
+/// again with an exponential backoff. After [ConsumerConfig::max_attempts] attempts, the
+/// message is rejected, just like messages that can not be deserialized. Rejected messages
+/// are dropped, or moved to the dead letter exchange of the queue if one is configured
+/// in its [QueueConfig](crate::QueueConfig).
+///
+/// If the connection is lost, the queues are consumed again once the connection was
+/// established again. Unacknowledged messages are then delivered again by the broker.
+///
+/// On shutdown, no new messages are received, but running handlers are allowed to finish.
+/// Messages waiting for a retry are returned to the queue.
+///
+/// The metrics `amqp.consumer.messages` and `amqp.consumer.duration` record every attempt
+/// to handle a message per queue and result.
+///
+/// Use like this:
+/// ```ignore
+/// Consumer::new(&amqp, &config.consumer)
+///     .handle("orders", |order: Message<Order>| async move { process(order.payload).await })
+///     .run(startup_http::shutdown_requested())
+///     .await?;
+/// ```
+pub struct Consumer {
+    amqp: Amqp,
+    config: ConsumerConfig,
+    handlers: Vec<(String, Handler)>,
+}
+
+impl Consumer {
+    pub fn new(amqp: &Amqp, config: &ConsumerConfig) -> Self {
+        Self {
+            amqp: amqp.clone(),
+            config: config.clone(),
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Handles the messages of a queue, deserializing their json payload.
+    pub fn handle<T, F, Fut, E>(mut self, queue: impl Into<String>, handler: F) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        self.handlers.push((queue.into(), messaging::json(handler)));
+        self
+    }
+
+    /// Handles the messages of a queue with their raw payload.
+    pub fn handle_raw<F, Fut, E>(mut self, queue: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Message<Vec<u8>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        self.handlers.push((queue.into(), messaging::raw(handler)));
+        self
+    }
+
+    /// Consumes messages until `shutdown` resolves, e.g. `startup_http::shutdown_requested()`.
+    /// Fails if a queue can not be consumed initially, e.g. because it does not exist.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), AmqpError> {
+        let shared = Arc::new(Shared::new(&self.config));
+        let stop = CancellationToken::new();
+
+        let mut tasks = Vec::with_capacity(self.handlers.len());
+
+        for (queue, handler) in self.handlers {
+            let subscription = subscribe(&self.amqp, &queue, shared.prefetch).await?;
+
+            info!("Consuming queue {}", queue);
+
+            tasks.push(tokio::spawn(consume(
+                self.amqp.clone(),
+                queue,
+                subscription,
+                handler,
+                shared.clone(),
+                stop.clone(),
+            )));
+        }
+
+        shutdown.await;
+
+        info!("Stopping amqp consumers");
+
+        stop.cancel();
+        futures_util::future::join_all(tasks).await;
+
+        Ok(())
+    }
+}
+
+/// A channel consuming a queue.
+struct Subscription {
+    channel: Channel,
+    deliveries: lapin::Consumer,
+}
+
+async fn subscribe(amqp: &Amqp, queue: &str, prefetch: u16) -> Result<Subscription, AmqpError> {
+    let consume_error = |source| AmqpError::Consume {
+        queue: queue.to_string(),
+        source,
+    };
+
+    let channel = amqp.channel().await?;
+
+    channel
+        .basic_qos(prefetch, BasicQosOptions::default())
+        .await
+        .map_err(consume_error)?;
+
+    let deliveries = channel
+        .basic_consume(queue, "", BasicConsumeOptions::default(), FieldTable::default())
+        .await
+        .map_err(consume_error)?;
+
+    Ok(Subscription { channel, deliveries })
+}
+
+async fn consume(
+    amqp: Amqp,
+    queue: String,
+    mut subscription: Subscription,
+    handler: Handler,
+    shared: Arc<Shared>,
+    stop: CancellationToken,
+) {
+    loop {
+        {
+            let results = subscription
+                .deliveries
+                .by_ref()
+                .take_until(stop.cancelled())
+                .filter_map(|delivery| async move {
+                    match delivery {
+                        Ok(delivery) => Some(delivery),
+                        Err(err) => {
+                            warn!("Failed to receive message: {}", err);
+                            None
+                        }
+                    }
+                })
+                .map(|delivery| handle_delivery(&queue, &handler, &shared, &stop, delivery))
+                .buffer_unordered(usize::from(shared.prefetch));
+
+            tokio::pin!(results);
+            while results.next().await.is_some() {}
+        }
+
+        if stop.is_cancelled() {
+            break;
+        }
+
+        // the stream ended without a shutdown, so the channel or connection was closed
+        warn!("Lost consumer of queue {}, consuming it again", queue);
+
+        subscription = loop {
+            let resubscribed = tokio::select! {
+                _ = stop.cancelled() => return,
+                resubscribed = subscribe(&amqp, &queue, shared.prefetch) => resubscribed,
+            };
+
+            match resubscribed {
+                Ok(subscription) => break subscription,
+                Err(err) => {
+                    warn!("Failed to consume queue {}: {:?}", queue, err);
+
+                    tokio::select! {
+                        _ = stop.cancelled() => return,
+                        _ = tokio::time::sleep(RESUBSCRIBE_DELAY) => {}
+                    }
+                }
+            }
+        };
+    }
+
+    // unacknowledged messages that were prefetched are returned to the queue
+    if let Err(err) = subscription.channel.close(200, "shutdown").await {
+        debug!("Failed to close channel of queue {}: {}", queue, err);
+    }
+
+    info!("Stopped consuming queue {}", queue);
+}
+
+/// What happens with a message once its handler is done.
+enum Settle {
+    Ack,
+    Reject,
+    Requeue,
+}
+
+async fn handle_delivery(
+    queue: &str,
+    handler: &Handler,
+    shared: &Shared,
+    stop: &CancellationToken,
+    delivery: Delivery,
+) {
+    let span = info_span!(
+        "amqp_consumer",
+        otel.name = %format!("{} process", queue),
+        otel.kind = "consumer",
+        otel.status_code = Empty,
+        messaging.system = "rabbitmq",
+        messaging.source = %queue,
+        messaging.rabbitmq.routing_key = %delivery.routing_key.as_str(),
+        messaging.rabbitmq.redelivered = delivery.redelivered,
+    );
+
+    // continue the trace of the producer
+    if let Some(headers) = delivery.properties.headers() {
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+        span.set_parent(parent);
+    }
+
+    let mut attempt = 1;
+
+    let settle = loop {
+        let started = Instant::now();
+        let outcome = handler(Message::new(&delivery), &delivery.data)
+            .instrument(span.clone())
+            .await;
+
+        match outcome {
+            Outcome::Handled => {
+                shared.record(queue, started.elapsed(), "success");
+                break Settle::Ack;
+            }
+
+            Outcome::Failed(err) if attempt < shared.max_attempts => {
+                shared.record(queue, started.elapsed(), "failure");
+
+                let backoff = shared.backoff(attempt);
+                warn!(parent: &span, "Failed to handle message from {} (attempt {}), retrying in {:?}: {}", queue, attempt, backoff, err);
+
+                tokio::select! {
+                    _ = stop.cancelled() => break Settle::Requeue,
+                    _ = tokio::time::sleep(backoff) => attempt += 1,
+                }
+            }
+
+            Outcome::Failed(err) => {
+                shared.record(queue, started.elapsed(), "failure");
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: &span, "Failed to handle message from {} after {} attempts, rejecting it: {}", queue, attempt, err);
+                break Settle::Reject;
+            }
+
+            Outcome::Invalid(err) => {
+                shared.record(queue, started.elapsed(), "invalid");
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: &span, "Rejecting invalid message from {}: {}", queue, err);
+                break Settle::Reject;
+            }
+        }
+    };
+
+    let acked = match settle {
+        Settle::Ack => delivery.acker.ack(BasicAckOptions::default()).await,
+        Settle::Reject => delivery.acker.reject(BasicRejectOptions { requeue: false }).await,
+        Settle::Requeue => {
+            let options = BasicNackOptions {
+                requeue: true,
+                ..Default::default()
+            };
+
+            delivery.acker.nack(options).await
+        }
+    };
+
+    if let Err(err) = acked {
+        warn!(parent: &span, "Failed to acknowledge message from {}: {}", queue, err);
+    }
+}
+
+/// State shared by all queues.
+struct Shared {
+    prefetch: u16,
+    max_attempts: u32,
+    retry_backoff_ms: u64,
+    max_retry_backoff_ms: u64,
+    messages: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Shared {
+    fn new(config: &ConsumerConfig) -> Self {
+        let meter = global::meter("startup-amqp");
+
+        let messages = meter
+            .u64_counter("amqp.consumer.messages")
+            .with_description("Attempts to handle a consumed message")
+            .init();
+
+        let duration = meter
+            .f64_histogram("amqp.consumer.duration")
+            .with_description("Duration of handling a message")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Self {
+            prefetch: config.prefetch.max(1),
+            max_attempts: config.max_attempts.max(1),
+            retry_backoff_ms: config.retry_backoff_ms,
+            max_retry_backoff_ms: config.max_retry_backoff_ms,
+            messages,
+            duration,
+        }
+    }
+
+    fn record(&self, queue: &str, elapsed: Duration, result: &'static str) {
+        let context = opentelemetry::Context::current();
+
+        let attributes = [
+            KeyValue::new("queue", queue.to_string()),
+            KeyValue::new("result", result),
+        ];
+
+        self.messages.add(&context, 1, &attributes);
+        self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+    }
+
+    /// Backoff before the next attempt of a message that failed `attempt` times.
+    fn backoff(&self, attempt: u32) -> Duration {
+        messaging::retry_backoff(self.retry_backoff_ms, self.max_retry_backoff_ms, attempt)
+    }
+}
@@ -0,0 +1,45 @@
+#[derive(Debug, thiserror::Error)]
+pub enum AmqpError {
+    #[error("failed to connect to the amqp broker")]
+    Connect(#[source] lapin::Error),
+
+    #[error("failed to open an amqp channel")]
+    Channel(#[source] lapin::Error),
+
+    #[error("failed to declare {kind} {name}")]
+    Topology {
+        kind: &'static str,
+        name: String,
+        #[source]
+        source: lapin::Error,
+    },
+
+    #[error("failed to serialize message for exchange {exchange:?} with routing key {routing_key:?}")]
+    Serialize {
+        exchange: String,
+        routing_key: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("failed to publish message to exchange {exchange:?} with routing key {routing_key:?}")]
+    Publish {
+        exchange: String,
+        routing_key: String,
+        #[source]
+        source: lapin::Error,
+    },
+
+    #[error("broker rejected message for exchange {exchange:?} with routing key {routing_key:?}")]
+    Nacked { exchange: String, routing_key: String },
+
+    #[error("no queue is bound to exchange {exchange:?} with routing key {routing_key:?}")]
+    Unroutable { exchange: String, routing_key: String },
+
+    #[error("failed to consume queue {queue}")]
+    Consume {
+        queue: String,
+        #[source]
+        source: lapin::Error,
+    },
+}
@@ -0,0 +1,86 @@
+use lapin::message::Delivery;
+use lapin::types::{AMQPValue, FieldTable};
+use opentelemetry::propagation::{Extractor, Injector};
+use startup_base::messaging::{self, is_trace_header, Envelope};
+
+/// A received message with its deserialized payload.
+#[derive(Debug, Clone)]
+pub struct Message<T> {
+    /// Exchange the message was published to, empty for the default exchange.
+    pub exchange: String,
+
+    pub routing_key: String,
+
+    /// True if the message was delivered before, e.g. to a consumer that crashed.
+    pub redelivered: bool,
+
+    /// Headers of the message with a string value, without the trace context.
+    pub headers: Vec<(String, String)>,
+
+    pub payload: T,
+}
+
+impl Message<()> {
+    pub(crate) fn new(delivery: &Delivery) -> Self {
+        let headers = delivery
+            .properties
+            .headers()
+            .iter()
+            .flat_map(|headers| headers.inner().iter())
+            .filter(|(name, _)| !is_trace_header(name.as_str()))
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), string_value(value)?.to_string())))
+            .collect();
+
+        Self {
+            exchange: delivery.exchange.as_str().to_string(),
+            routing_key: delivery.routing_key.as_str().to_string(),
+            redelivered: delivery.redelivered,
+            headers,
+            payload: (),
+        }
+    }
+}
+
+impl Envelope for Message<()> {
+    type Message<T> = Message<T>;
+
+    fn with_payload<U>(self, payload: U) -> Message<U> {
+        Message {
+            exchange: self.exchange,
+            routing_key: self.routing_key,
+            redelivered: self.redelivered,
+            headers: self.headers,
+            payload,
+        }
+    }
+}
+
+pub(crate) type Handler = messaging::Handler<Message<()>>;
+
+fn string_value(value: &AMQPValue) -> Option<&str> {
+    match value {
+        AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok(),
+        AMQPValue::ShortString(value) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+pub(crate) struct HeaderInjector<'a>(pub(crate) &'a mut FieldTable);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.into(), AMQPValue::LongString(value.into()));
+    }
+}
+
+pub(crate) struct HeaderExtractor<'a>(pub(crate) &'a FieldTable);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        string_value(self.0.inner().get(key)?)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.inner().keys().map(|key| key.as_str()).collect()
+    }
+}
@@ -0,0 +1,187 @@
+use lapin::options::{ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{Channel, ExchangeKind};
+use serde::{Deserialize, Serialize};
+
+use crate::AmqpError;
+
+/// Exchanges, queues and bindings that are declared on every connect. Declaring is
+/// idempotent, but fails if an entity already exists with different settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Topology {
+    #[serde(default)]
+    pub exchanges: Vec<ExchangeConfig>,
+
+    #[serde(default)]
+    pub queues: Vec<QueueConfig>,
+
+    #[serde(default)]
+    pub bindings: Vec<BindingConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExchangeConfig {
+    pub name: String,
+
+    #[serde(default)]
+    pub kind: ExchangeType,
+
+    /// Keep the exchange when the broker restarts.
+    #[serde(default = "default_true")]
+    pub durable: bool,
+
+    /// Delete the exchange once its last queue was unbound.
+    #[serde(default)]
+    pub auto_delete: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExchangeType {
+    Direct,
+    Fanout,
+    #[default]
+    Topic,
+    Headers,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueConfig {
+    pub name: String,
+
+    /// Keep the queue and its persistent messages when the broker restarts.
+    #[serde(default = "default_true")]
+    pub durable: bool,
+
+    /// Use a replicated quorum queue instead of a classic queue.
+    #[serde(default)]
+    pub quorum: bool,
+
+    /// Delete the queue once its last consumer is gone.
+    #[serde(default)]
+    pub auto_delete: bool,
+
+    /// Exchange that receives rejected and expired messages, see [crate::Consumer].
+    #[serde(default)]
+    pub dead_letter_exchange: Option<String>,
+
+    /// Routing key of dead-lettered messages. Defaults to their original routing key.
+    #[serde(default)]
+    pub dead_letter_routing_key: Option<String>,
+
+    /// Messages older than this are dropped, or dead-lettered if configured.
+    #[serde(default)]
+    pub message_ttl_ms: Option<u32>,
+
+    /// Maximum number of messages, the oldest messages are dropped, or dead-lettered if configured.
+    #[serde(default)]
+    pub max_length: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BindingConfig {
+    pub queue: String,
+    pub exchange: String,
+
+    /// Pattern of the routing keys, e.g. `orders.*` for a topic exchange.
+    #[serde(default)]
+    pub routing_key: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub(crate) async fn declare(channel: &Channel, topology: &Topology) -> Result<(), AmqpError> {
+    for exchange in &topology.exchanges {
+        let kind = match exchange.kind {
+            ExchangeType::Direct => ExchangeKind::Direct,
+            ExchangeType::Fanout => ExchangeKind::Fanout,
+            ExchangeType::Topic => ExchangeKind::Topic,
+            ExchangeType::Headers => ExchangeKind::Headers,
+        };
+
+        let options = ExchangeDeclareOptions {
+            durable: exchange.durable,
+            auto_delete: exchange.auto_delete,
+            ..Default::default()
+        };
+
+        channel
+            .exchange_declare(&exchange.name, kind, options, FieldTable::default())
+            .await
+            .map_err(|source| AmqpError::Topology {
+                kind: "exchange",
+                name: exchange.name.clone(),
+                source,
+            })?;
+    }
+
+    for queue in &topology.queues {
+        let options = QueueDeclareOptions {
+            durable: queue.durable,
+            auto_delete: queue.auto_delete,
+            ..Default::default()
+        };
+
+        channel
+            .queue_declare(&queue.name, options, queue_arguments(queue))
+            .await
+            .map_err(|source| AmqpError::Topology {
+                kind: "queue",
+                name: queue.name.clone(),
+                source,
+            })?;
+    }
+
+    for binding in &topology.bindings {
+        channel
+            .queue_bind(
+                &binding.queue,
+                &binding.exchange,
+                &binding.routing_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|source| AmqpError::Topology {
+                kind: "binding",
+                name: format!("{} -> {}", binding.exchange, binding.queue),
+                source,
+            })?;
+    }
+
+    Ok(())
+}
+
+fn queue_arguments(queue: &QueueConfig) -> FieldTable {
+    let mut arguments = FieldTable::default();
+
+    if queue.quorum {
+        arguments.insert("x-queue-type".into(), AMQPValue::LongString("quorum".into()));
+    }
+
+    if let Some(exchange) = &queue.dead_letter_exchange {
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString(exchange.as_str().into()),
+        );
+    }
+
+    if let Some(routing_key) = &queue.dead_letter_routing_key {
+        arguments.insert(
+            "x-dead-letter-routing-key".into(),
+            AMQPValue::LongString(routing_key.as_str().into()),
+        );
+    }
+
+    if let Some(ttl) = queue.message_ttl_ms {
+        arguments.insert("x-message-ttl".into(), AMQPValue::LongLongInt(i64::from(ttl)));
+    }
+
+    if let Some(max_length) = queue.max_length {
+        arguments.insert("x-max-length".into(), AMQPValue::LongLongInt(i64::from(max_length)));
+    }
+
+    arguments
+}
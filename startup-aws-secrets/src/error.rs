@@ -0,0 +1,31 @@
+#[derive(Debug, thiserror::Error)]
+pub enum AwsSecretsError {
+    #[error("failed to read the config")]
+    Config(#[source] Box<figment::Error>),
+
+    #[error("failed to fetch secret {name} from secrets manager")]
+    Secret {
+        name: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("failed to fetch parameter {name} from parameter store")]
+    Parameter {
+        name: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("secret {name} has no string value")]
+    NoValue { name: String },
+
+    #[error("secret {name} is not a json object")]
+    NotJson { name: String },
+
+    #[error("secret {name} has no key {key}")]
+    MissingKey { name: String, key: String },
+
+    #[error("config section {section} is not a map")]
+    NotASection { section: String },
+}
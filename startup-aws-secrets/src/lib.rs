@@ -0,0 +1,42 @@
+//! Resolves config values from AWS Secrets Manager and SSM Parameter Store at startup,
+//! for services running on ECS or EKS.
+//!
+//! * A string value `aws-secrets://name` is replaced by the secret, `aws-secrets://name#key`
+//!   by the value of the key of a json secret.
+//! * A string value `aws-ssm:///path/to/parameter` is replaced by the decrypted parameter.
+//! * The json secrets listed in `aws_secrets.sections` are merged into their config section,
+//!   e.g. the `username` and `password` of an RDS secret into the `database` section.
+//!
+//! Region and credentials are taken from the standard AWS chain, e.g. the environment or
+//! the task role. AWS is only called if the config references a secret.
+//!
+//! Use like this:
+//! ```ignore
+//! let config_yaml = include_str!("config.yaml");
+//!
+//! let secrets = AwsSecrets::load(config_yaml).await?;
+//! let config: Config = startup_base::init_with_provider(env!("CARGO_PKG_NAME"), config_yaml, secrets)?;
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+pub use crate::error::AwsSecretsError;
+pub use crate::secrets::AwsSecrets;
+
+mod error;
+mod secrets;
+
+/// The `aws_secrets` section of the config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AwsSecretsConfig {
+    /// Region of the secrets, defaults to the region of the AWS chain.
+    #[serde(default)]
+    pub region: Option<String>,
+
+    /// Json secrets to merge into a config section, by the dotted path of the section,
+    /// e.g. `database.credentials: prod/orders/database`.
+    #[serde(default)]
+    pub sections: BTreeMap<String, String>,
+}
@@ -0,0 +1,300 @@
+use std::collections::{BTreeSet, HashMap};
+
+use aws_config::{BehaviorVersion, Region};
+use figment::value::{Dict, Map, Value};
+use figment::{Figment, Metadata, Profile, Provider};
+use startup_base::DefaultConfig;
+
+use crate::{AwsSecretsConfig, AwsSecretsError};
+
+const SECRET_PREFIX: &str = "aws-secrets://";
+const PARAMETER_PREFIX: &str = "aws-ssm://";
+
+/// A figment provider with the config values resolved from AWS. Merge it over the config
+/// using [startup_base::init_with_provider]. It only provides the resolved values, so all other
+/// values keep the metadata of the provider they came from.
+pub struct AwsSecrets {
+    values: Dict,
+}
+
+impl AwsSecrets {
+    /// Resolves the secrets referenced by the config as read by [startup_base::init].
+    pub async fn load(default: impl Into<DefaultConfig<'_>>) -> Result<Self, AwsSecretsError> {
+        Self::resolve(startup_base::figment(default)).await
+    }
+
+    /// Resolves the secrets referenced by the config of the figment.
+    pub async fn resolve(figment: Figment) -> Result<Self, AwsSecretsError> {
+        let config: AwsSecretsConfig = match figment.contains("aws_secrets") {
+            true => figment.extract_inner("aws_secrets").map_err(config_error)?,
+            false => AwsSecretsConfig::default(),
+        };
+
+        let config_values: Dict = figment.extract().map_err(config_error)?;
+
+        let mut references = BTreeSet::new();
+        for value in config_values.values() {
+            collect_references(value, &mut references);
+        }
+
+        if references.is_empty() && config.sections.is_empty() {
+            return Ok(Self { values: Dict::new() });
+        }
+
+        let mut store = SecretStore::new(&config).await;
+
+        let mut resolved = HashMap::new();
+        for reference in references {
+            let value = store.resolve(&reference).await?;
+            resolved.insert(reference, value);
+        }
+
+        let mut values: Dict = config_values
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), resolve_references(value, &resolved)?)))
+            .collect();
+
+        for (section, name) in &config.sections {
+            if !is_section(&config_values, section) {
+                return Err(AwsSecretsError::NotASection {
+                    section: section.to_string(),
+                });
+            }
+
+            let secret = store.json(name).await?;
+            merge_section(&mut values, section, secret)?;
+        }
+
+        Ok(Self { values })
+    }
+}
+
+impl Provider for AwsSecrets {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("AWS secrets")
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        Ok(Profile::Default.collect(self.values.clone()))
+    }
+}
+
+struct SecretStore {
+    secrets: aws_sdk_secretsmanager::Client,
+    parameters: aws_sdk_ssm::Client,
+
+    /// Secrets by name, so a secret referenced multiple times is fetched only once.
+    cache: HashMap<String, String>,
+}
+
+impl SecretStore {
+    async fn new(config: &AwsSecretsConfig) -> Self {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+
+        if let Some(region) = &config.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+
+        let sdk_config = loader.load().await;
+
+        Self {
+            secrets: aws_sdk_secretsmanager::Client::new(&sdk_config),
+            parameters: aws_sdk_ssm::Client::new(&sdk_config),
+            cache: HashMap::new(),
+        }
+    }
+
+    async fn resolve(&mut self, reference: &str) -> Result<Value, AwsSecretsError> {
+        if let Some(name) = reference.strip_prefix(PARAMETER_PREFIX) {
+            return Ok(Value::from(self.parameter(name).await?));
+        }
+
+        let secret = reference.strip_prefix(SECRET_PREFIX).unwrap_or(reference);
+
+        match secret.split_once('#') {
+            None => Ok(Value::from(self.secret(secret).await?)),
+
+            Some((name, key)) => {
+                let mut json = self.json(name).await?;
+
+                let value = json.remove(key).ok_or_else(|| AwsSecretsError::MissingKey {
+                    name: name.to_string(),
+                    key: key.to_string(),
+                })?;
+
+                Ok(match value {
+                    serde_json::Value::String(value) => Value::from(value),
+                    value => json_to_value(name, value)?,
+                })
+            }
+        }
+    }
+
+    async fn secret(&mut self, name: &str) -> Result<String, AwsSecretsError> {
+        if let Some(secret) = self.cache.get(name) {
+            return Ok(secret.clone());
+        }
+
+        let response = self
+            .secrets
+            .get_secret_value()
+            .secret_id(name)
+            .send()
+            .await
+            .map_err(|err| AwsSecretsError::Secret {
+                name: name.to_string(),
+                source: Box::new(err),
+            })?;
+
+        let secret = response
+            .secret_string()
+            .ok_or_else(|| AwsSecretsError::NoValue { name: name.to_string() })?
+            .to_string();
+
+        self.cache.insert(name.to_string(), secret.clone());
+
+        Ok(secret)
+    }
+
+    async fn json(&mut self, name: &str) -> Result<serde_json::Map<String, serde_json::Value>, AwsSecretsError> {
+        let secret = self.secret(name).await?;
+
+        serde_json::from_str(&secret).map_err(|_| AwsSecretsError::NotJson { name: name.to_string() })
+    }
+
+    async fn parameter(&self, name: &str) -> Result<String, AwsSecretsError> {
+        let response = self
+            .parameters
+            .get_parameter()
+            .name(name)
+            .with_decryption(true)
+            .send()
+            .await
+            .map_err(|err| AwsSecretsError::Parameter {
+                name: name.to_string(),
+                source: Box::new(err),
+            })?;
+
+        let value = response
+            .parameter()
+            .and_then(|parameter| parameter.value())
+            .ok_or_else(|| AwsSecretsError::NoValue { name: name.to_string() })?;
+
+        Ok(value.to_string())
+    }
+}
+
+fn is_reference(text: &str) -> bool {
+    text.starts_with(SECRET_PREFIX) || text.starts_with(PARAMETER_PREFIX)
+}
+
+fn collect_references(value: &Value, references: &mut BTreeSet<String>) {
+    match value {
+        Value::String(_, text) if is_reference(text) => {
+            references.insert(text.clone());
+        }
+
+        Value::Dict(_, dict) => dict.values().for_each(|value| collect_references(value, references)),
+        Value::Array(_, values) => values.iter().for_each(|value| collect_references(value, references)),
+        _ => {}
+    }
+}
+
+/// Returns only the parts of the value that contain references, with the references replaced by
+/// their resolved values. Arrays are returned as a whole, as they are not merged element by element.
+fn resolve_references(value: &Value, resolved: &HashMap<String, Value>) -> Option<Value> {
+    match value {
+        Value::String(_, text) if is_reference(text) => resolved.get(text.as_str()).cloned(),
+
+        Value::Dict(_, dict) => {
+            let dict: Dict = dict
+                .iter()
+                .filter_map(|(key, value)| Some((key.clone(), resolve_references(value, resolved)?)))
+                .collect();
+
+            (!dict.is_empty()).then(|| Value::from(dict))
+        }
+
+        Value::Array(..) => {
+            let mut references = BTreeSet::new();
+            collect_references(value, &mut references);
+
+            if references.is_empty() {
+                return None;
+            }
+
+            let mut value = value.clone();
+            replace_references(&mut value, resolved);
+            Some(value)
+        }
+
+        _ => None,
+    }
+}
+
+fn replace_references(value: &mut Value, resolved: &HashMap<String, Value>) {
+    match value {
+        Value::String(_, text) if is_reference(text) => {
+            if let Some(secret) = resolved.get(text.as_str()) {
+                *value = secret.clone();
+            }
+        }
+
+        Value::Dict(_, dict) => dict.values_mut().for_each(|value| replace_references(value, resolved)),
+        Value::Array(_, values) => values.iter_mut().for_each(|value| replace_references(value, resolved)),
+        _ => {}
+    }
+}
+
+/// Returns true if the dotted path is a section of the config, or does not exist yet.
+fn is_section(values: &Dict, section: &str) -> bool {
+    let mut target = values;
+
+    for key in section.split('.') {
+        target = match target.get(key) {
+            None => return true,
+            Some(Value::Dict(_, dict)) => dict,
+            Some(_) => return false,
+        };
+    }
+
+    true
+}
+
+/// Merges the keys of the secret into the section at the dotted path, creating it if needed.
+fn merge_section(
+    values: &mut Dict,
+    section: &str,
+    secret: serde_json::Map<String, serde_json::Value>,
+) -> Result<(), AwsSecretsError> {
+    let mut target = values;
+
+    for key in section.split('.') {
+        let value = target
+            .entry(key.to_string())
+            .or_insert_with(|| Value::from(Dict::new()));
+
+        target = match value {
+            Value::Dict(_, dict) => dict,
+            _ => {
+                return Err(AwsSecretsError::NotASection {
+                    section: section.to_string(),
+                })
+            }
+        };
+    }
+
+    for (key, value) in secret {
+        target.insert(key, json_to_value(section, value)?);
+    }
+
+    Ok(())
+}
+
+fn json_to_value(name: &str, value: serde_json::Value) -> Result<Value, AwsSecretsError> {
+    Value::serialize(value).map_err(|_| AwsSecretsError::NotJson { name: name.to_string() })
+}
+
+fn config_error(err: figment::Error) -> AwsSecretsError {
+    AwsSecretsError::Config(Box::new(err))
+}
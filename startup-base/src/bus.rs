@@ -0,0 +1,129 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+
+/// Number of events of a type that are buffered for a subscriber before it lags behind.
+const CAPACITY: usize = 256;
+
+lazy_static::lazy_static! {
+    static ref CHANNELS: Mutex<HashMap<TypeId, Box<dyn Any + Send>>> = Mutex::new(HashMap::new());
+
+    static ref PUBLISHED: Counter<u64> = opentelemetry::global::meter("startup-base")
+        .u64_counter("event_bus.published")
+        .with_description("Events published on the in-process event bus")
+        .init();
+
+    static ref LAGGED: Counter<u64> = opentelemetry::global::meter("startup-base")
+        .u64_counter("event_bus.lagged")
+        .with_description("Events skipped by subscribers that could not keep up")
+        .init();
+}
+
+/// An event that can be published on the bus.
+pub trait Event: Clone + Send + Sync + 'static {}
+
+impl<T: Clone + Send + Sync + 'static> Event for T {}
+
+/// Events announced by the startup crates about the lifecycle of the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// Files watched by a `FileWatch` changed and their value was reloaded,
+    /// e.g. a certificate or the webhook secrets.
+    Reloaded { name: String },
+
+    /// This replica became the leader of the election with the name.
+    LeadershipGained { name: String },
+
+    /// This replica is no longer the leader of the election with the name.
+    LeadershipLost { name: String },
+
+    /// A component of the [health](crate::health) registry became healthy.
+    ComponentHealthy { component: String },
+
+    /// A component of the [health](crate::health) registry became unhealthy.
+    ComponentUnhealthy { component: String, reason: String },
+}
+
+/// Publishes the event to all current subscribers of its type, see [subscribe].
+/// Returns the number of subscribers. Events without subscribers are dropped.
+///
+/// Use like this: `bus::publish(OrderPlaced { id })`
+pub fn publish<E: Event>(event: E) -> usize {
+    let attributes = [KeyValue::new("event", short_type_name::<E>())];
+    PUBLISHED.add(&opentelemetry::Context::current(), 1, &attributes);
+
+    sender::<E>().send(event).unwrap_or(0)
+}
+
+/// Subscribes to all events of the type published from now on. Each subscriber buffers
+/// a bounded number of events, a subscriber that does not keep up skips the oldest events.
+///
+/// Use like this:
+/// ```ignore
+/// let mut events = bus::subscribe::<LifecycleEvent>();
+///
+/// while let Some(event) = events.recv().await {
+///     if let LifecycleEvent::LeadershipLost { .. } = event { pause_jobs() }
+/// }
+/// ```
+pub fn subscribe<E: Event>() -> Subscriber<E> {
+    Subscriber {
+        receiver: sender::<E>().subscribe(),
+    }
+}
+
+fn sender<E: Event>() -> broadcast::Sender<E> {
+    let mut channels = CHANNELS.lock();
+
+    let channel = channels
+        .entry(TypeId::of::<E>())
+        .or_insert_with(|| Box::new(broadcast::channel::<E>(CAPACITY).0));
+
+    channel
+        .downcast_ref::<broadcast::Sender<E>>()
+        .expect("channel of the event type")
+        .clone()
+}
+
+/// The name of the type without its module path, to label metrics.
+fn short_type_name<E>() -> &'static str {
+    let name = std::any::type_name::<E>();
+
+    // keeps the generic parameters of types like `Vec<String>` intact
+    let path_end = name.find('<').unwrap_or(name.len());
+    let start = name[..path_end].rfind("::").map_or(0, |index| index + 2);
+
+    &name[start..]
+}
+
+/// Receives the events of a type, see [subscribe].
+pub struct Subscriber<E> {
+    receiver: broadcast::Receiver<E>,
+}
+
+impl<E: Event> Subscriber<E> {
+    /// Waits for the next event. Events skipped because this subscriber lagged behind are
+    /// logged and counted in the `event_bus.lagged` metric.
+    pub async fn recv(&mut self) -> Option<E> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    let name = short_type_name::<E>();
+                    tracing::warn!("Subscriber of {} lagged behind, skipped {} events", name, skipped);
+
+                    let attributes = [KeyValue::new("event", name)];
+                    LAGGED.add(&opentelemetry::Context::current(), skipped, &attributes);
+                }
+
+                // the senders live as long as the process
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::eyre;
+use figment::Figment;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::preflight::{CheckResult, Preflight, PreflightReport};
+use crate::redact::{is_secret_key, redact_url, REDACTED};
+use crate::{DefaultConfig, EnvVariable};
+
+type Action<C> = Box<dyn FnOnce(C) -> Pin<Box<dyn Future<Output = color_eyre::Result<()>>>>>;
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Only plans the initialization of the components, see [Components](crate::components::Components).
+    #[arg(long, global = true)]
+    dry_run_init: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the service, this is the default.
+    Serve,
+
+    /// Runs the database migrations and exits.
+    Migrate,
+
+    /// Validates the config and exits.
+    CheckConfig,
+
+    /// Prints the config with secrets redacted and exits.
+    PrintConfig,
+
+    /// Validates the config, runs the preflight checks, prints the report and exits.
+    /// Fails if a check failed.
+    Preflight,
+
+    /// Prints the supported `APP_` environment variables with their defaults and exits.
+    PrintEnv {
+        #[arg(long, value_enum, default_value_t = EnvFormat::Markdown)]
+        format: EnvFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EnvFormat {
+    /// A markdown table with the type, default and description of each variable.
+    Markdown,
+
+    /// A dotenv file with the defaults and the descriptions as comments.
+    Dotenv,
+}
+
+/// A command line for the service with the subcommands `serve`, `migrate`, `check-config`,
+/// `preflight`, `print-config` and `print-env`. Without a subcommand, the service is served.
+///
+/// The config is built like in [init](crate::init), from the embedded config, the files of
+/// `APP_CONFIG` and the `APP_` environment variables. `serve` and `migrate` initialize logging
+/// and pass the config to their action. `check-config`, `print-config` and `print-env` only
+/// build the config, so they can run without the infrastructure of the service, e.g. in a
+/// deployment pipeline.
+/// `preflight` runs the [Preflight] checks built from the config, without serving, and prints
+/// a json report, e.g. in a Kubernetes init container.
+///
+/// Use like this:
+/// ```ignore
+/// startup_base::cli!("config.yaml")
+///     .serve(|config: Config| async move { run(config).await })
+///     .migrate(|config: Config| async move { migrate(config).await })
+///     .preflight(|config: Config| Preflight::new().check("db", async move { Ok(config.db.preflight(&MIGRATOR).await?) }))
+///     .run()
+///     .await
+/// ```
+pub struct Cli<C> {
+    service_name: &'static str,
+    config: DefaultConfig<'static>,
+    serve: Option<Action<C>>,
+    migrate: Option<Action<C>>,
+    preflight: Option<Box<dyn FnOnce(C) -> Preflight>>,
+}
+
+#[macro_export]
+macro_rules! cli {
+    ( $name:expr ) => {
+        $crate::cli::Cli::new(
+            env!("CARGO_PKG_NAME"),
+            $crate::DefaultConfig::from_path($name, include_str!($name)),
+        )
+    };
+}
+
+impl<C: Default + Serialize + DeserializeOwned + 'static> Cli<C> {
+    pub fn new(service_name: &'static str, config: impl Into<DefaultConfig<'static>>) -> Self {
+        Self {
+            service_name,
+            config: config.into(),
+            serve: None,
+            migrate: None,
+            preflight: None,
+        }
+    }
+
+    /// Runs the service for the `serve` command.
+    pub fn serve<F, Fut>(mut self, action: F) -> Self
+    where
+        F: FnOnce(C) -> Fut + 'static,
+        Fut: Future<Output = color_eyre::Result<()>> + 'static,
+    {
+        self.serve = Some(Box::new(move |config| Box::pin(action(config))));
+        self
+    }
+
+    /// Runs the database migrations for the `migrate` command.
+    pub fn migrate<F, Fut>(mut self, action: F) -> Self
+    where
+        F: FnOnce(C) -> Fut + 'static,
+        Fut: Future<Output = color_eyre::Result<()>> + 'static,
+    {
+        self.migrate = Some(Box::new(move |config| Box::pin(action(config))));
+        self
+    }
+
+    /// Builds the checks for the `preflight` command. Without checks, only the config is validated.
+    pub fn preflight<F>(mut self, checks: F) -> Self
+    where
+        F: FnOnce(C) -> Preflight + 'static,
+    {
+        self.preflight = Some(Box::new(checks));
+        self
+    }
+
+    /// Parses the command line and runs the command.
+    pub async fn run(self) -> color_eyre::Result<()> {
+        let args = Args::parse();
+
+        if args.dry_run_init {
+            crate::components::set_dry_run(true);
+        }
+
+        match args.command.unwrap_or(Command::Serve) {
+            Command::Serve => {
+                let serve = self.serve.ok_or_else(|| eyre!("no serve action configured"))?;
+                serve(crate::init(self.service_name, self.config)?).await
+            }
+
+            Command::Migrate => {
+                let migrate = self.migrate.ok_or_else(|| eyre!("no migrate action configured"))?;
+                migrate(crate::init(self.service_name, self.config)?).await
+            }
+
+            Command::CheckConfig => {
+                crate::figment_with_provider::<C, _>(self.config, Figment::new()).extract::<C>()?;
+                println!("Config of {} is valid", self.service_name);
+                Ok(())
+            }
+
+            Command::Preflight => {
+                let started = Instant::now();
+                let config = crate::figment_with_provider::<C, _>(self.config, Figment::new()).extract::<C>();
+
+                let report = match config {
+                    Ok(config) => {
+                        let config_check = CheckResult::new("config", started.elapsed(), None);
+
+                        let preflight = self.preflight.map(|checks| checks(config)).unwrap_or_default();
+                        let mut report = preflight.run().await;
+
+                        report.checks.insert(0, config_check);
+                        report
+                    }
+
+                    Err(err) => {
+                        let config_check = CheckResult::new("config", started.elapsed(), Some(err.to_string()));
+                        PreflightReport::new(vec![config_check])
+                    }
+                };
+
+                println!("{}", serde_json::to_string_pretty(&report)?);
+
+                if !report.ok {
+                    let failed: Vec<_> = report
+                        .checks
+                        .iter()
+                        .filter(|check| !check.ok)
+                        .map(|check| check.name.as_str())
+                        .collect();
+
+                    return Err(eyre!("preflight checks failed: {}", failed.join(", ")));
+                }
+
+                Ok(())
+            }
+
+            Command::PrintConfig => {
+                let config: C = crate::figment_with_provider::<C, _>(self.config, Figment::new()).extract()?;
+
+                let mut value = serde_yaml::to_value(&config)?;
+                redact(&mut value);
+
+                print!("{}", serde_yaml::to_string(&value)?);
+                Ok(())
+            }
+
+            Command::PrintEnv { format } => {
+                let variables = crate::env_variables::<C>(self.config)?;
+
+                match format {
+                    EnvFormat::Markdown => print_markdown(&variables),
+                    EnvFormat::Dotenv => print_dotenv(&variables),
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+fn print_markdown(variables: &[EnvVariable]) {
+    println!("| Variable | Type | Default | Description |");
+    println!("|---|---|---|---|");
+
+    for variable in variables {
+        let default = variable
+            .default
+            .as_deref()
+            .map(|value| format!("`{}`", value))
+            .unwrap_or_default();
+        let description = variable.description.as_deref().unwrap_or_default().replace('|', "\\|");
+        println!(
+            "| `{}` | {} | {} | {} |",
+            variable.name, variable.kind, default, description
+        );
+    }
+}
+
+fn print_dotenv(variables: &[EnvVariable]) {
+    for variable in variables {
+        if let Some(description) = &variable.description {
+            println!("# {}", description);
+        }
+
+        match &variable.default {
+            Some(default) => println!("{}={}", variable.name, default),
+            None => println!("# {}=", variable.name),
+        }
+    }
+}
+
+/// Replaces values with secret keys and passwords in urls.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                let is_secret = key.as_str().is_some_and(is_secret_key);
+
+                match is_secret && !value.is_null() {
+                    true => *value = Value::String(REDACTED.to_string()),
+                    false => redact(value),
+                }
+            }
+        }
+
+        Value::Sequence(values) => values.iter_mut().for_each(redact),
+
+        Value::String(text) => {
+            if let Some(redacted) = redact_url(text) {
+                *text = redacted;
+            }
+        }
+
+        _ => {}
+    }
+}
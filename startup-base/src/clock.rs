@@ -0,0 +1,187 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
+
+use parking_lot::Mutex;
+
+/// Future returned by [Clock::sleep].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Source of time for everything that depends on it, like token expiry, cache entries or
+/// schedules, so time can be controlled in tests using a [ManualClock] instead of real sleeps.
+///
+/// Components take the clock as an `Arc<dyn Clock>` and use the [SystemClock] by default.
+pub trait Clock: Send + Sync + 'static {
+    /// The current wall clock time, e.g. to compare with timestamps of other systems.
+    fn now(&self) -> SystemTime;
+
+    /// The current monotonic time, e.g. to measure durations and deadlines.
+    fn instant(&self) -> Instant;
+
+    /// Resolves once the duration passed on this clock.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+impl dyn Clock {
+    /// Ticks every period, starting immediately. Each tick is a full period after the previous
+    /// one, so ticks missed while the caller was busy are not made up for.
+    pub fn interval(&self, period: Duration) -> Interval<'_> {
+        Interval {
+            clock: self,
+            next: self.instant(),
+            period,
+        }
+    }
+}
+
+/// Clock that uses the time of the operating system and the timers of tokio.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The [SystemClock] as a shared clock, the default of all components.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Created by the `interval` method of a `dyn Clock`.
+pub struct Interval<'a> {
+    clock: &'a dyn Clock,
+    next: Instant,
+    period: Duration,
+}
+
+impl Interval<'_> {
+    /// Waits for the next tick and returns its time.
+    pub async fn tick(&mut self) -> Instant {
+        let now = self.clock.instant();
+
+        if self.next > now {
+            self.clock.sleep(self.next - now).await;
+        }
+
+        let tick = self.clock.instant();
+        self.next = tick + self.period;
+
+        tick
+    }
+}
+
+/// Clock for tests that only moves when it is advanced. Sleeps on this clock resolve as soon
+/// as the clock was advanced past their deadline. Clones share the time.
+///
+/// Use like this:
+/// ```ignore
+/// let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+/// let cache = Cache::memory_with_clock("users", &config, Arc::new(clock.clone()));
+///
+/// clock.advance(Duration::from_secs(301));
+/// ```
+#[derive(Clone)]
+pub struct ManualClock {
+    inner: Arc<Mutex<ManualState>>,
+}
+
+struct ManualState {
+    start: SystemTime,
+    origin: Instant,
+    elapsed: Duration,
+    sleeping: Vec<Waker>,
+}
+
+impl ManualClock {
+    /// Creates a clock that starts at the given wall clock time.
+    pub fn new(start: SystemTime) -> Self {
+        let state = ManualState {
+            start,
+            origin: Instant::now(),
+            elapsed: Duration::ZERO,
+            sleeping: Vec::new(),
+        };
+
+        Self {
+            inner: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Moves the clock forward and wakes the sleeps that are due.
+    pub fn advance(&self, duration: Duration) {
+        let sleeping = {
+            let mut state = self.inner.lock();
+            state.elapsed += duration;
+            std::mem::take(&mut state.sleeping)
+        };
+
+        // sleeps that are not due yet register again when polled
+        for waker in sleeping {
+            waker.wake();
+        }
+    }
+
+    /// Time the clock was advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        self.inner.lock().elapsed
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(SystemTime::now())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        let state = self.inner.lock();
+        state.start + state.elapsed
+    }
+
+    fn instant(&self) -> Instant {
+        let state = self.inner.lock();
+        state.origin + state.elapsed
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let deadline = self.elapsed() + duration;
+
+        Box::pin(ManualSleep {
+            inner: self.inner.clone(),
+            deadline,
+        })
+    }
+}
+
+struct ManualSleep {
+    inner: Arc<Mutex<ManualState>>,
+    deadline: Duration,
+}
+
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.inner.lock();
+
+        if state.elapsed >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        state.sleeping.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
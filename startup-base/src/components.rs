@@ -0,0 +1,234 @@
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use color_eyre::eyre;
+use parking_lot::Mutex;
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+type InitFn = Box<dyn FnOnce(Resources) -> Pin<Box<dyn Future<Output = eyre::Result<()>> + Send>> + Send>;
+
+struct Component {
+    depends_on: Vec<String>,
+    init: InitFn,
+}
+
+/// The components of a service, like the database pool, the jwt keys, kafka and the http
+/// server, with the components each of them depends on. They are initialized in phases:
+/// a phase starts once the components of the previous phases are initialized, and the
+/// components within a phase are initialized concurrently. Components hand over what they
+/// created to the components depending on them using the [Resources].
+///
+/// The database, the jwt keys, the kafka producer and the http server are available as
+/// components of `startup-db`, `startup-jwt`, `startup-kafka` and `startup-http`.
+///
+/// With `--dry-run-init` on the command line, see [Cli](crate::cli::Cli), or [set_dry_run],
+/// [Components::run] only returns the plan.
+///
+/// Use like this:
+/// ```ignore
+/// let initialization = Components::new()
+///     .add("db", &[], config.db.component(MIGRATOR))
+///     .add("jwt", &[], config.jwt.component())
+///     .add("kafka", &["db"], |resources| async move { start_consumer(resources.get::<PgPool>()?).await })
+///     .add("http", &["db", "jwt"], startup_http::component(&config.http, router))
+///     .run()
+///     .await?;
+///
+/// match initialization {
+///     Initialization::Initialized(resources) => resources.get::<Serving>()?.wait().await,
+///     Initialization::DryRun(plan) => Ok(print!("{}", plan)),
+/// }
+/// ```
+#[derive(Default)]
+pub struct Components {
+    components: BTreeMap<String, Component>,
+}
+
+impl Components {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a component that is initialized after the components it depends on.
+    pub fn add<F, Fut>(mut self, name: &str, depends_on: &[&str], init: F) -> Self
+    where
+        F: FnOnce(Resources) -> Fut + Send + 'static,
+        Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        let component = Component {
+            depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+            init: Box::new(move |resources| Box::pin(init(resources))),
+        };
+
+        if self.components.insert(name.to_string(), component).is_some() {
+            panic!("component {:?} was added twice", name);
+        }
+
+        self
+    }
+
+    /// The names of the components of each phase, in the order the phases are run.
+    /// Fails if a component depends on a missing component or on itself, directly or indirectly.
+    pub fn plan(&self) -> eyre::Result<Vec<Vec<String>>> {
+        for (name, component) in &self.components {
+            if let Some(missing) = component
+                .depends_on
+                .iter()
+                .find(|dependency| !self.components.contains_key(*dependency))
+            {
+                eyre::bail!("component {:?} depends on unknown component {:?}", name, missing);
+            }
+        }
+
+        let mut initialized = BTreeSet::new();
+        let mut phases = Vec::new();
+
+        while initialized.len() < self.components.len() {
+            let phase: Vec<String> = self
+                .components
+                .iter()
+                .filter(|(name, _)| !initialized.contains(*name))
+                .filter(|(_, component)| component.depends_on.iter().all(|dep| initialized.contains(dep)))
+                .map(|(name, _)| name.clone())
+                .collect();
+
+            if phase.is_empty() {
+                let cycle: Vec<&str> = self
+                    .components
+                    .keys()
+                    .filter(|name| !initialized.contains(*name))
+                    .map(String::as_str)
+                    .collect();
+
+                eyre::bail!("components depend on each other in a cycle: {}", cycle.join(", "));
+            }
+
+            initialized.extend(phase.iter().cloned());
+            phases.push(phase);
+        }
+
+        Ok(phases)
+    }
+
+    /// Initializes all components phase by phase, logging how long each component and phase
+    /// took. Fails with the first component that failed, the other components of its phase
+    /// are cancelled. Returns the resources the components created, or only the plan if
+    /// a dry run was requested, see [set_dry_run].
+    pub async fn run(mut self) -> eyre::Result<Initialization> {
+        let phases = self.plan()?;
+
+        if DRY_RUN.load(Ordering::Relaxed) {
+            return Ok(Initialization::DryRun(self.describe(&phases)));
+        }
+
+        let started = Instant::now();
+        let resources = Resources::default();
+
+        for (index, phase) in phases.iter().enumerate() {
+            let phase_started = Instant::now();
+            let mut tasks = tokio::task::JoinSet::new();
+
+            for name in phase {
+                let component = self.components.remove(name).expect("component of the plan");
+                let init = (component.init)(resources.clone());
+                let name = name.clone();
+
+                tasks.spawn(async move {
+                    let started = Instant::now();
+                    let result = init.await;
+                    (name, started.elapsed(), result)
+                });
+            }
+
+            while let Some(joined) = tasks.join_next().await {
+                let (name, elapsed, result) = joined.map_err(|err| eyre::eyre!("component panicked: {}", err))?;
+
+                if let Err(err) = result {
+                    return Err(err.wrap_err(format!("initialization of component {:?} failed", name)));
+                }
+
+                tracing::info!("Initialized component {} in {:.1?}", name, elapsed);
+            }
+
+            tracing::info!(
+                "Initialized phase {} of {} ({}) in {:.1?}",
+                index + 1,
+                phases.len(),
+                phase.join(", "),
+                phase_started.elapsed()
+            );
+        }
+
+        tracing::info!(
+            "Initialized {} components in {} phases in {:.1?}",
+            phases.iter().map(Vec::len).sum::<usize>(),
+            phases.len(),
+            started.elapsed()
+        );
+
+        Ok(Initialization::Initialized(resources))
+    }
+
+    fn describe(&self, phases: &[Vec<String>]) -> String {
+        let mut plan = String::from("Initialization plan:\n");
+
+        for (index, phase) in phases.iter().enumerate() {
+            plan.push_str(&format!("  phase {}:\n", index + 1));
+
+            for name in phase {
+                let depends_on = &self.components[name].depends_on;
+
+                if depends_on.is_empty() {
+                    plan.push_str(&format!("    {}\n", name));
+                } else {
+                    plan.push_str(&format!("    {} (after {})\n", name, depends_on.join(", ")));
+                }
+            }
+        }
+
+        plan
+    }
+}
+
+/// The result of [Components::run].
+pub enum Initialization {
+    /// All components were initialized, with the resources they created.
+    Initialized(Resources),
+
+    /// The plan of the initialization, printable, as a dry run was requested.
+    DryRun(String),
+}
+
+/// Makes [Components::run] only return the plan, set by the `--dry-run-init` flag of the [Cli](crate::cli::Cli).
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+/// What the components created, by type, e.g. a database pool. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct Resources {
+    values: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>,
+}
+
+impl Resources {
+    /// Adds a value, replacing the value of the same type.
+    pub fn insert<T: Clone + Send + Sync + 'static>(&self, value: T) {
+        self.values.lock().insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    /// A value added by a component this component depends on.
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> eyre::Result<T> {
+        self.values
+            .lock()
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+            .ok_or_else(|| eyre::eyre!("no resource of type {}", std::any::type_name::<T>()))
+    }
+}
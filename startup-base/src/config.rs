@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use figment::providers::{Format, Json, Toml, Yaml};
+use figment::Figment;
+
+/// Format of a config, detected by the extension of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Detects the format by the extension of the path, `.toml` or `.json`. Files with any
+    /// other extension, like `.yaml` or none at all, are yaml.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        match extension.to_ascii_lowercase().as_str() {
+            "toml" => ConfigFormat::Toml,
+            "json" => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+
+    pub(crate) fn merge_string(self, figment: Figment, content: &str) -> Figment {
+        match self {
+            ConfigFormat::Yaml => figment.merge(Yaml::string(content)),
+            ConfigFormat::Toml => figment.merge(Toml::string(content)),
+            ConfigFormat::Json => figment.merge(Json::string(content)),
+        }
+    }
+
+    /// Merges the file, a missing file is an error.
+    pub(crate) fn merge_file(self, figment: Figment, path: &Path) -> Figment {
+        match self {
+            ConfigFormat::Yaml => figment.merge(Yaml::file_exact(path)),
+            ConfigFormat::Toml => figment.merge(Toml::file_exact(path)),
+            ConfigFormat::Json => figment.merge(Json::file_exact(path)),
+        }
+    }
+}
+
+/// The config embedded into the service, usually with `include_str!`, and its format. A plain
+/// string is yaml, the macros like [init!](crate::init!) detect the format by the extension of
+/// the file.
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultConfig<'a> {
+    pub content: &'a str,
+    pub format: ConfigFormat,
+}
+
+impl<'a> DefaultConfig<'a> {
+    /// The content of the file at the path, in the format of its extension.
+    ///
+    /// Use like this: `DefaultConfig::from_path("config.toml", include_str!("config.toml"))`
+    pub fn from_path(path: &str, content: &'a str) -> Self {
+        Self {
+            content,
+            format: ConfigFormat::from_path(path),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for DefaultConfig<'a> {
+    fn from(content: &'a str) -> Self {
+        Self {
+            content,
+            format: ConfigFormat::Yaml,
+        }
+    }
+}
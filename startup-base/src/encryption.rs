@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::redact::{serialize_redacted, REDACTED};
+
+/// Length of the nonce of AES-GCM, prepended to the ciphertext.
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Values are encrypted with the key of the highest version, and decrypted with the key
+    /// of the version they were encrypted with. To rotate the key, add a key with a higher
+    /// version and remove the old one once no value encrypted with it is in use anymore.
+    pub keys: Vec<EncryptionKey>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptionKey {
+    pub version: u8,
+
+    /// 32 random bytes in base64, e.g. from `openssl rand -base64 32`.
+    #[serde(serialize_with = "serialize_redacted")]
+    pub secret: String,
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("version", &self.version)
+            .field("secret", &REDACTED)
+            .finish()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("no encryption key configured")]
+    NoKeys,
+
+    #[error("encryption key {version} is invalid: {reason}")]
+    InvalidKey { version: u8, reason: String },
+
+    #[error("encryption key {0} is configured twice")]
+    DuplicateKey(u8),
+
+    #[error("value was encrypted with unknown key {0}")]
+    UnknownKey(u8),
+
+    /// The value is malformed, was tampered with, or belongs to other associated data.
+    #[error("value can not be decrypted")]
+    Invalid,
+}
+
+/// Encrypts and authenticates values with AES-256-GCM, e.g. values placed in cookies, urls or
+/// kafka messages, using the versioned keys of the [EncryptionConfig]. The version of the key
+/// and a random nonce are stored with the ciphertext.
+///
+/// The associated data is authenticated, but not encrypted. Pass the purpose of the value, like
+/// the name of the cookie, so a value can not be used for a different purpose. Decrypt with the
+/// same associated data.
+///
+/// Use like this:
+/// ```ignore
+/// let encryption = Encryption::new(&config.encryption)?;
+///
+/// let token = encryption.encrypt_to_string(user_id.as_bytes(), b"unsubscribe");
+/// let user_id = encryption.decrypt_str(&token, b"unsubscribe")?;
+/// ```
+#[derive(Clone)]
+pub struct Encryption {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    keys: BTreeMap<u8, Aes256Gcm>,
+    current: u8,
+}
+
+impl Encryption {
+    pub fn new(config: &EncryptionConfig) -> Result<Self, EncryptionError> {
+        let mut keys = BTreeMap::new();
+
+        for key in &config.keys {
+            let invalid = |reason: String| EncryptionError::InvalidKey {
+                version: key.version,
+                reason,
+            };
+
+            let secret = STANDARD
+                .decode(key.secret.trim())
+                .map_err(|err| invalid(format!("not base64: {}", err)))?;
+
+            let cipher = Aes256Gcm::new_from_slice(&secret)
+                .map_err(|_| invalid(format!("expected 32 bytes, got {}", secret.len())))?;
+
+            if keys.insert(key.version, cipher).is_some() {
+                return Err(EncryptionError::DuplicateKey(key.version));
+            }
+        }
+
+        let current = *keys.keys().next_back().ok_or(EncryptionError::NoKeys)?;
+
+        Ok(Self {
+            inner: Arc::new(Inner { keys, current }),
+        })
+    }
+
+    /// Encrypts the value with the current key.
+    pub fn encrypt(&self, value: &[u8], associated_data: &[u8]) -> Vec<u8> {
+        let version = self.inner.current;
+        let cipher = &self.inner.keys[&version];
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = aad(version, associated_data);
+
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: value, aad: &aad })
+            .expect("encrypt with AES-GCM");
+
+        let mut encrypted = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+        encrypted.push(version);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+
+        encrypted
+    }
+
+    /// Decrypts a value encrypted by [Encryption::encrypt] with any of the keys.
+    pub fn decrypt(&self, encrypted: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let [version, rest @ ..] = encrypted else {
+            return Err(EncryptionError::Invalid);
+        };
+
+        if rest.len() < NONCE_LEN {
+            return Err(EncryptionError::Invalid);
+        }
+
+        let cipher = self
+            .inner
+            .keys
+            .get(version)
+            .ok_or(EncryptionError::UnknownKey(*version))?;
+
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let aad = aad(*version, associated_data);
+
+        cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| EncryptionError::Invalid)
+    }
+
+    /// Encrypts the value into url safe base64, for cookies and urls.
+    pub fn encrypt_to_string(&self, value: &[u8], associated_data: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(self.encrypt(value, associated_data))
+    }
+
+    /// Decrypts a value encrypted by [Encryption::encrypt_to_string].
+    pub fn decrypt_str(&self, encrypted: &str, associated_data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let encrypted = URL_SAFE_NO_PAD
+            .decode(encrypted)
+            .map_err(|_| EncryptionError::Invalid)?;
+
+        self.decrypt(&encrypted, associated_data)
+    }
+
+    /// True if the value was encrypted with the current key. Values encrypted with an
+    /// older key should be encrypted again, so the old key can be removed.
+    pub fn is_current(&self, encrypted: &[u8]) -> bool {
+        encrypted.first() == Some(&self.inner.current)
+    }
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("versions", &self.inner.keys.keys().collect::<Vec<_>>())
+            .field("current", &self.inner.current)
+            .finish()
+    }
+}
+
+/// The version is authenticated as well, so it can not be swapped.
+fn aad(version: u8, associated_data: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(1 + associated_data.len());
+    aad.push(version);
+    aad.extend_from_slice(associated_data);
+    aad
+}
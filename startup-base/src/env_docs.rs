@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+
+use figment::providers::Serialized;
+use figment::value::Value;
+use figment::Figment;
+use serde::Serialize;
+
+use crate::config::{ConfigFormat, DefaultConfig};
+use crate::provenance::format_value;
+use crate::redact::{is_secret_key, REDACTED};
+
+/// An `APP_` environment variable that overrides a config value.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvVariable {
+    /// The name of the variable, like `APP_HTTP__PORT`.
+    pub name: String,
+
+    /// The path of the config key, like `http.port`.
+    pub key: String,
+
+    /// The type of the default value, like `integer`, `string`, `list` or `optional`.
+    pub kind: &'static str,
+
+    /// The default value, with secrets and passwords in urls redacted. `None` if the value
+    /// is not set by default.
+    pub default: Option<String>,
+
+    /// The comment above the key in the yaml or toml config.
+    pub description: Option<String>,
+}
+
+/// Lists the `APP_` environment variables supported by the config, with the type and the
+/// default of their value, e.g. to generate and review the environment of deployment manifests.
+///
+/// The keys are taken from the defaults of the config type merged with the embedded config, like
+/// in [init](crate::init), without the environment of the current process. Keys that are not
+/// serialized, e.g. because of `skip_serializing_if`, are only listed if the embedded config
+/// mentions them. For a yaml or toml config, the descriptions are the comments right above the
+/// keys. A json config has no descriptions.
+///
+/// Use like this: `for variable in env_variables::<Config>(DefaultConfig::from_path("config.toml", include_str!("config.toml")))? { .. }`
+#[allow(clippy::result_large_err)]
+pub fn env_variables<'a, C: Default + Serialize>(
+    default: impl Into<DefaultConfig<'a>>,
+) -> Result<Vec<EnvVariable>, figment::Error> {
+    let default = default.into();
+    let figment = Figment::from(Serialized::defaults(C::default()));
+    let figment = default.format.merge_string(figment, default.content);
+
+    let descriptions = match default.format {
+        ConfigFormat::Yaml => yaml_comments(default.content),
+        ConfigFormat::Toml => toml_comments(default.content),
+        ConfigFormat::Json => BTreeMap::new(),
+    };
+
+    let mut variables = Vec::new();
+
+    if let Value::Dict(_, dict) = figment.find_value("")? {
+        for (key, value) in dict {
+            collect(&mut vec![key], &value, &descriptions, &mut variables);
+        }
+    }
+
+    Ok(variables)
+}
+
+fn collect(
+    path: &mut Vec<String>,
+    value: &Value,
+    descriptions: &BTreeMap<String, String>,
+    variables: &mut Vec<EnvVariable>,
+) {
+    if let Value::Dict(_, dict) = value {
+        if !dict.is_empty() {
+            for (key, value) in dict {
+                path.push(key.clone());
+                collect(path, value, descriptions, variables);
+                path.pop();
+            }
+
+            return;
+        }
+    }
+
+    let key = path.join(".");
+
+    let default = match value {
+        Value::Empty(..) => None,
+        Value::Dict(..) => Some("{}".to_string()),
+        _ if path.iter().any(|key| is_secret_key(key)) => Some(REDACTED.to_string()),
+        _ => Some(format_value(value)),
+    };
+
+    variables.push(EnvVariable {
+        name: format!("APP_{}", path.join("__").to_ascii_uppercase()),
+        kind: kind(value),
+        default,
+        description: descriptions.get(&key).cloned(),
+        key,
+    });
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::String(..) => "string",
+        Value::Char(..) => "char",
+        Value::Bool(..) => "boolean",
+        Value::Num(..) if value.to_i128().is_some() || value.to_u128().is_some() => "integer",
+        Value::Num(..) => "float",
+        Value::Empty(..) => "optional",
+        Value::Dict(..) => "map",
+        Value::Array(..) => "list",
+    }
+}
+
+/// Collects the comment lines right above the keys of the yaml, by the path of the key.
+/// Keys within lists are skipped.
+fn yaml_comments(yaml: &str) -> BTreeMap<String, String> {
+    let mut comments = BTreeMap::new();
+
+    // the keys of the enclosing mappings with their indentation
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut pending: Vec<&str> = Vec::new();
+
+    for line in yaml.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        if trimmed.is_empty() {
+            pending.clear();
+            continue;
+        }
+
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            pending.push(comment.trim());
+            continue;
+        }
+
+        while parents.last().is_some_and(|(parent, _)| *parent >= indent) {
+            parents.pop();
+        }
+
+        let key = match trimmed.split_once(':') {
+            Some((key, _)) if !trimmed.starts_with('-') => key.trim().trim_matches(['"', '\'']),
+            _ => {
+                pending.clear();
+                continue;
+            }
+        };
+
+        let path: Vec<&str> = parents.iter().map(|(_, key)| key.as_str()).chain([key]).collect();
+
+        if !pending.is_empty() {
+            comments.insert(path.join("."), pending.join(" "));
+            pending.clear();
+        }
+
+        parents.push((indent, key.to_string()));
+    }
+
+    comments
+}
+
+/// Collects the comment lines right above the keys and tables of the toml, by the path of the key.
+/// Keys within arrays of tables are skipped.
+fn toml_comments(toml: &str) -> BTreeMap<String, String> {
+    let mut comments = BTreeMap::new();
+
+    // the keys of the current table, none within an array of tables
+    let mut table: Option<Vec<String>> = Some(Vec::new());
+    let mut pending: Vec<&str> = Vec::new();
+
+    for line in toml.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            pending.clear();
+            continue;
+        }
+
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            pending.push(comment.trim());
+            continue;
+        }
+
+        let path = if trimmed.starts_with("[[") {
+            table = None;
+            None
+        } else if let Some(header) = trimmed.strip_prefix('[') {
+            let keys = toml_keys(header.split(']').next().unwrap_or_default());
+            table = Some(keys.clone());
+            Some(keys)
+        } else {
+            match (&table, trimmed.split_once('=')) {
+                (Some(table), Some((key, _))) => Some(table.iter().cloned().chain(toml_keys(key)).collect()),
+                _ => None,
+            }
+        };
+
+        if let Some(path) = path.filter(|_| !pending.is_empty()) {
+            comments.insert(path.join("."), pending.join(" "));
+        }
+
+        pending.clear();
+    }
+
+    comments
+}
+
+/// Splits a dotted key like `http."port"` into its parts.
+fn toml_keys(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|part| part.trim().trim_matches(['"', '\'']).to_string())
+        .collect()
+}
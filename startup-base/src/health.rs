@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::RwLock;
+
+use crate::bus::{self, LifecycleEvent};
+
+type Listener = Box<dyn Fn() + Send + Sync>;
+
+lazy_static::lazy_static! {
+    static ref COMPONENTS: RwLock<BTreeMap<String, HealthStatus>> = RwLock::new(BTreeMap::new());
+    static ref LISTENERS: RwLock<BTreeMap<u64, Listener>> = RwLock::new(BTreeMap::new());
+}
+
+static NEXT_LISTENER: AtomicU64 = AtomicU64::new(0);
+
+/// Health of a component of the service, e.g. the database connection or a kafka consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthStatus {
+    Healthy,
+
+    /// The component can not do its work, with a reason for the logs.
+    Unhealthy(String),
+}
+
+impl HealthStatus {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, HealthStatus::Healthy)
+    }
+}
+
+/// Marks the component as healthy.
+pub fn set_healthy(component: &str) {
+    set(component, HealthStatus::Healthy)
+}
+
+/// Marks the component as unhealthy. The service is unhealthy until the component recovers.
+pub fn set_unhealthy(component: &str, reason: impl Into<String>) {
+    set(component, HealthStatus::Unhealthy(reason.into()))
+}
+
+/// Removes a component that is no longer used.
+pub fn remove(component: &str) {
+    if COMPONENTS.write().remove(component).is_some() {
+        notify();
+    }
+}
+
+/// Returns the status of the component, or None if it never reported its health.
+pub fn status(component: &str) -> Option<HealthStatus> {
+    COMPONENTS.read().get(component).cloned()
+}
+
+/// Returns true if all components are healthy.
+pub fn is_healthy() -> bool {
+    COMPONENTS.read().values().all(HealthStatus::is_healthy)
+}
+
+/// Returns the status of all components, sorted by name.
+pub fn components() -> Vec<(String, HealthStatus)> {
+    COMPONENTS
+        .read()
+        .iter()
+        .map(|(name, status)| (name.clone(), status.clone()))
+        .collect()
+}
+
+/// Calls the listener whenever the status of a component changes, e.g. to
+/// forward the health to a health check protocol. Keep the listener short,
+/// it is called on the thread that changed the status.
+///
+/// The listener is removed once the returned [Subscription] is dropped.
+pub fn on_change(listener: impl Fn() + Send + Sync + 'static) -> Subscription {
+    let id = NEXT_LISTENER.fetch_add(1, Ordering::Relaxed);
+    LISTENERS.write().insert(id, Box::new(listener));
+    Subscription(id)
+}
+
+/// Keeps a listener registered by [on_change] until it is dropped.
+#[must_use = "the listener is removed once the subscription is dropped"]
+pub struct Subscription(u64);
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        LISTENERS.write().remove(&self.0);
+    }
+}
+
+fn set(component: &str, status: HealthStatus) {
+    let previous = COMPONENTS.write().insert(component.to_string(), status.clone());
+
+    if previous.as_ref() == Some(&status) {
+        return;
+    }
+
+    let component = component.to_string();
+
+    match &status {
+        HealthStatus::Healthy => {
+            tracing::info!("Component {} is healthy", component);
+            bus::publish(LifecycleEvent::ComponentHealthy { component });
+        }
+
+        HealthStatus::Unhealthy(reason) => {
+            tracing::warn!("Component {} is unhealthy: {}", component, reason);
+
+            let reason = reason.clone();
+            bus::publish(LifecycleEvent::ComponentUnhealthy { component, reason });
+        }
+    }
+
+    notify();
+}
+
+fn notify() {
+    for listener in LISTENERS.read().values() {
+        listener();
+    }
+}
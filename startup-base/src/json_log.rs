@@ -0,0 +1,48 @@
+use std::fmt;
+
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::fmt::format::{Format, Json, Writer};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Formats events as json lines, with the `trace_id` and `span_id` of the span of the
+/// event if it is traced, so the logs of a request can be found from its trace.
+pub(crate) struct JsonWithTrace(pub(crate) Format<Json>);
+
+impl<S, N> FormatEvent<S, N> for JsonWithTrace
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut line = String::new();
+        self.0.format_event(ctx, Writer::new(&mut line), event)?;
+
+        let ids = ctx
+            .parent_span()
+            .and_then(|span| span.extensions().get::<OtelData>().map(span_ids));
+
+        // the json object is followed by a new line
+        match (ids, line.trim_end().strip_suffix('}')) {
+            (Some((trace_id, span_id)), Some(object)) => writeln!(
+                writer,
+                "{},\"trace_id\":\"{}\",\"span_id\":\"{}\"}}",
+                object, trace_id, span_id
+            ),
+
+            _ => writer.write_str(&line),
+        }
+    }
+}
+
+/// The ids the span is exported with, see `tracing_opentelemetry::OpenTelemetryLayer`.
+fn span_ids(data: &OtelData) -> (TraceId, SpanId) {
+    let trace_id = match data.parent_cx.has_active_span() {
+        true => data.parent_cx.span().span_context().trace_id(),
+        false => data.builder.trace_id.unwrap_or(TraceId::INVALID),
+    };
+
+    (trace_id, data.builder.span_id.unwrap_or(SpanId::INVALID))
+}
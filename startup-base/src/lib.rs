@@ -1,7 +1,7 @@
 use atty::Stream;
-use figment::providers::{Env, Format, Yaml};
+use figment::providers::Env;
 use figment::Error;
-use figment::Figment;
+use figment::{Figment, Provider};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tracing_subscriber::reload::Handle;
@@ -11,48 +11,158 @@ use tracing_subscriber::{Layer, Registry};
 use parking_lot::RwLock;
 use tracing_subscriber::util::SubscriberInitExt;
 
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod bus;
+pub mod clock;
+pub mod components;
+mod config;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+mod env_docs;
+pub mod health;
+mod json_log;
+#[cfg(feature = "messaging")]
+pub mod messaging;
+pub mod preflight;
+mod provenance;
+pub mod redact;
+pub mod retry;
+pub mod sbom;
+pub mod shutdown;
+#[cfg(feature = "signature")]
+pub mod signature;
+pub mod systemd;
+pub mod tasks;
+pub mod warmup;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+pub use config::{ConfigFormat, DefaultConfig};
+pub use env_docs::{env_variables, EnvVariable};
+pub use provenance::{config_provenance, Provenance};
+pub use shutdown::on_shutdown;
+
+/// Environment variable with the paths of config files merged over the embedded config.
+const CONFIG_FILES_ENV: &str = "APP_CONFIG";
+
+type DynLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
 lazy_static::lazy_static! {
-    static ref TRACING_LAYER: RwLock<Option<Handle<Option<Box<dyn Layer<Registry>+Send+Sync>>, Registry>>> = RwLock::new(None);
+    static ref TRACING_LAYER: RwLock<Option<Handle<Option<DynLayer>, Registry>>> = RwLock::new(None);
+    static ref SERVICE_NAME: RwLock<Option<String>> = RwLock::new(None);
 }
 
 #[macro_export]
 macro_rules! init {
     ( $name:expr ) => {
-        $crate::init(env!("CARGO_PKG_NAME"), include_str!($name))
+        $crate::init(
+            env!("CARGO_PKG_NAME"),
+            $crate::DefaultConfig::from_path($name, include_str!($name)),
+        )
     };
 }
 
-fn extract<C: Serialize + DeserializeOwned>(default_yaml: &str) -> Result<C, Error> {
-    let config = Figment::new()
-        .merge(Yaml::string(default_yaml))
-        .merge(Env::prefixed("APP_").split("__"))
-        .extract()?;
+#[allow(clippy::result_large_err)]
+fn extract<C: Serialize + DeserializeOwned>(default: DefaultConfig) -> Result<C, Error> {
+    let config = figment(default).extract()?;
 
     Ok(config)
 }
 
-fn extract_with_default<C: Default + Serialize + DeserializeOwned>(default_yaml: &str) -> Result<C, Error> {
+fn figment_with_provider<C, P>(default: DefaultConfig, provider: P) -> Figment
+where
+    C: Default + Serialize,
+    P: Provider,
+{
     // serialize default config to use as a start
     let defaults = figment::providers::Serialized::defaults(C::default());
 
-    let config = Figment::from(defaults)
-        .merge(Yaml::string(default_yaml))
-        .merge(Env::prefixed("APP_").split("__"))
-        .extract()?;
+    Figment::from(defaults).merge(figment(default)).merge(provider)
+}
 
-    Ok(config)
+/// The raw config as read by [init], the embedded config merged with the files of `APP_CONFIG` and
+/// the `APP_` environment variables, e.g. to look for values that need to be resolved before the
+/// config is extracted.
+pub fn figment<'a>(default: impl Into<DefaultConfig<'a>>) -> Figment {
+    let default = default.into();
+    let mut figment = default.format.merge_string(Figment::new(), default.content);
+
+    // config files of the deployment, e.g. mounted from a ConfigMap, in the format of their
+    // extension. A missing file is an error
+    if let Some(paths) = std::env::var_os(CONFIG_FILES_ENV) {
+        for path in std::env::split_paths(&paths) {
+            if !path.as_os_str().is_empty() {
+                figment = ConfigFormat::from_path(&path).merge_file(figment, &path);
+            }
+        }
+    }
+
+    figment.merge(Env::prefixed("APP_").ignore(&["config"]).split("__"))
 }
 
 #[derive(Serialize, Deserialize)]
 struct BaseConfig {
     #[serde(default)]
     verbose: bool,
+
+    #[serde(default)]
+    log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+
+    /// One json object per line with the timestamp, level, target, fields and the current
+    /// spans, to be ingested by Loki or Elasticsearch without a separate parser. Events in a
+    /// traced span also have its `trace_id` and `span_id`.
+    Json,
+
+    /// Text when writing to a terminal, json otherwise.
+    Auto,
 }
 
-pub fn init<C: Default + Serialize + DeserializeOwned>(service_name: &str, config: &str) -> Result<C, Error> {
+/// Sets up logging and extracts the config of the service.
+///
+/// The config is built from the defaults of the config type, the embedded config, the config
+/// files listed in the `APP_CONFIG` environment variable and the `APP_` environment variables,
+/// each merged over the previous. `APP_CONFIG` is a list of paths like `PATH`, so the config of
+/// a service can be tuned without a rebuild: `APP_CONFIG=/etc/service/config.yaml:/etc/service/overrides.toml`
+///
+/// The embedded config and the files can be yaml, toml or json, detected by the extension of
+/// the file. A plain string passed as config is yaml.
+#[allow(clippy::result_large_err)]
+pub fn init<'a, C: Default + Serialize + DeserializeOwned>(
+    service_name: &str,
+    config: impl Into<DefaultConfig<'a>>,
+) -> Result<C, Error> {
+    init_with_provider(service_name, config, Figment::new())
+}
+
+/// Like [init], but merges the values of the provider over the config, e.g. secrets
+/// that were resolved from a secret store.
+#[allow(clippy::result_large_err)]
+pub fn init_with_provider<'a, C, P>(
+    service_name: &str,
+    config: impl Into<DefaultConfig<'a>>,
+    provider: P,
+) -> Result<C, Error>
+where
+    C: Default + Serialize + DeserializeOwned,
+    P: Provider,
+{
     // install error handler
     color_eyre::install().unwrap();
 
+    // before the config is extracted, so the defaults of its values can use it
+    *SERVICE_NAME.write() = Some(service_name.to_string());
+
+    let config = config.into();
+
     // parse base config
     let base_config: BaseConfig = extract(config)?;
 
@@ -68,25 +178,65 @@ pub fn init<C: Default + Serialize + DeserializeOwned>(service_name: &str, confi
     // set the handle so we can set the filter later on.
     *TRACING_LAYER.write() = Some(reload_handle);
 
+    // the logs are written to stdout
+    let terminal = atty::is(Stream::Stdout);
+
+    let json = match base_config.log_format {
+        LogFormat::Text => false,
+        LogFormat::Json => true,
+        LogFormat::Auto => !terminal,
+    };
+
     // a layer for logging based on the requested log level.
-    let log_layer = tracing_subscriber::fmt::layer()
-        .with_ansi(atty::is(Stream::Stderr))
-        .with_filter(loglevel);
+    let text_layer = (!json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(terminal)
+            .with_filter(loglevel)
+    });
+
+    let json_layer = json.then(|| {
+        let format = tracing_subscriber::fmt::format()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true);
+
+        tracing_subscriber::fmt::layer()
+            .json()
+            .event_format(json_log::JsonWithTrace(format))
+            .with_filter(loglevel)
+    });
 
     Registry::default()
         .with(dynamic_layer)
-        .with(log_layer)
+        .with(text_layer)
+        .with(json_layer)
         .init();
 
-    // extract and return app config
-    let config = extract_with_default(config)?;
+    // extract and return app config, remembering where its values came from
+    let figment = figment_with_provider::<C, P>(config, provider);
+    let config = figment.extract()?;
+    provenance::record(figment, std::any::type_name::<C>());
 
     tracing::info!("Starting application {:?} now", service_name);
 
     Ok(config)
 }
 
-pub fn replace_tracing_layer(layer: Option<Box<dyn Layer<Registry> + Send + Sync>>) -> color_eyre::Result<()> {
+/// The name of the service passed to [init], or the name of the executable if the service
+/// was not started by [init]. Used as default to identify the service, like the client id
+/// of its kafka connections.
+pub fn service_name() -> String {
+    if let Some(name) = SERVICE_NAME.read().as_ref() {
+        return name.clone();
+    }
+
+    std::env::current_exe()
+        .ok()
+        .and_then(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+pub fn replace_tracing_layer(layer: Option<DynLayer>) -> color_eyre::Result<()> {
     let handler = TRACING_LAYER.read();
 
     if let Some(handler) = handler.as_ref() {
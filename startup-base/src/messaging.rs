@@ -0,0 +1,88 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use crate::retry::RetryPolicy;
+
+/// A received message without its payload, which is added once it was deserialized.
+pub trait Envelope: Send + 'static {
+    type Message<T>;
+
+    fn with_payload<T>(self, payload: T) -> Self::Message<T>;
+}
+
+/// What happened to a message in its handler.
+pub enum Outcome {
+    Handled,
+
+    /// The handler failed, the message may be handled again.
+    Failed(String),
+
+    /// The payload could not be deserialized, handling it again fails again.
+    Invalid(String),
+}
+
+pub type Handler<M> = Arc<dyn Fn(M, &[u8]) -> Pin<Box<dyn Future<Output = Outcome> + Send>> + Send + Sync>;
+
+/// Wraps a handler that receives the json payload of a message.
+pub fn json<M, T, F, Fut, E>(handler: F) -> Handler<M>
+where
+    M: Envelope,
+    T: DeserializeOwned + Send + 'static,
+    F: Fn(M::Message<T>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), E>> + Send + 'static,
+    E: Display,
+{
+    Arc::new(
+        move |message: M, payload: &[u8]| match serde_json::from_slice(payload) {
+            Ok(payload) => outcome(handler(message.with_payload(payload))),
+            Err(err) => Box::pin(std::future::ready(Outcome::Invalid(err.to_string()))),
+        },
+    )
+}
+
+/// Wraps a handler that receives the raw payload of a message.
+pub fn raw<M, F, Fut, E>(handler: F) -> Handler<M>
+where
+    M: Envelope,
+    F: Fn(M::Message<Vec<u8>>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), E>> + Send + 'static,
+    E: Display,
+{
+    Arc::new(move |message: M, payload: &[u8]| outcome(handler(message.with_payload(payload.to_vec()))))
+}
+
+fn outcome<Fut, E>(result: Fut) -> Pin<Box<dyn Future<Output = Outcome> + Send>>
+where
+    Fut: Future<Output = Result<(), E>> + Send + 'static,
+    E: Display,
+{
+    Box::pin(async move {
+        match result.await {
+            Ok(()) => Outcome::Handled,
+            Err(err) => Outcome::Failed(err.to_string()),
+        }
+    })
+}
+
+/// Returns true for the headers of the trace context, which are not passed to the handlers.
+pub fn is_trace_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case("traceparent") || name.eq_ignore_ascii_case("tracestate")
+}
+
+/// Backoff before a message that failed `failures` times is handled again. It doubles
+/// with every failure, starting at `retry_backoff_ms`, up to `max_retry_backoff_ms`.
+pub fn retry_backoff(retry_backoff_ms: u64, max_retry_backoff_ms: u64, failures: u32) -> Duration {
+    let policy = RetryPolicy {
+        initial_backoff_millis: retry_backoff_ms,
+        max_backoff_millis: max_retry_backoff_ms,
+        jitter: 0.0,
+        ..RetryPolicy::default()
+    };
+
+    policy.backoff(failures)
+}
@@ -0,0 +1,132 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use serde::Serialize;
+
+type Check = Pin<Box<dyn Future<Output = eyre::Result<()>> + Send>>;
+
+/// Checks that the service can start, e.g. that the database is reachable and migrated
+/// or that the jwt keys can be fetched, without starting the service itself. The checks
+/// run concurrently, each of them fails if it does not finish within the timeout.
+///
+/// Run as `preflight` command of the [Cli](crate::cli::Cli), e.g. in a Kubernetes init container.
+///
+/// Use like this:
+/// ```ignore
+/// let report = Preflight::new()
+///     .check("db", async move { Ok(config.db.preflight(&MIGRATOR).await?) })
+///     .check("redis", async move { Ok(config.redis.preflight().await?) })
+///     .run()
+///     .await;
+/// ```
+pub struct Preflight {
+    checks: Vec<(String, Check)>,
+    timeout: Duration,
+}
+
+/// The outcome of the checks of a [Preflight].
+#[derive(Debug, Clone, Serialize)]
+pub struct PreflightReport {
+    /// True if all checks passed.
+    pub ok: bool,
+
+    pub checks: Vec<CheckResult>,
+}
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub ok: bool,
+    pub duration_ms: u64,
+
+    /// Why the check failed, with the chain of causes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Default for Preflight {
+    fn default() -> Self {
+        Self {
+            checks: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Preflight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time after which a check fails, defaults to 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a check that passes if the future succeeds.
+    pub fn check<F>(mut self, name: &str, check: F) -> Self
+    where
+        F: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        self.checks.push((name.to_string(), Box::pin(check)));
+        self
+    }
+
+    /// Runs all checks concurrently. Must be called within a tokio runtime.
+    pub async fn run(self) -> PreflightReport {
+        let timeout = self.timeout;
+
+        let handles: Vec<_> = self
+            .checks
+            .into_iter()
+            .map(|(name, check)| {
+                let handle = tokio::spawn(async move {
+                    let started = Instant::now();
+                    let result = tokio::time::timeout(timeout, check).await;
+                    (result, started.elapsed())
+                });
+
+                (name, handle)
+            })
+            .collect();
+
+        let mut checks = Vec::with_capacity(handles.len());
+
+        for (name, handle) in handles {
+            let (error, duration) = match handle.await {
+                Ok((Ok(Ok(())), duration)) => (None, duration),
+                Ok((Ok(Err(err)), duration)) => (Some(format!("{:#}", err)), duration),
+                Ok((Err(_), duration)) => (Some(format!("timed out after {:?}", timeout)), duration),
+                Err(err) => (Some(format!("check panicked: {}", err)), Duration::ZERO),
+            };
+
+            checks.push(CheckResult::new(name, duration, error));
+        }
+
+        PreflightReport::new(checks)
+    }
+}
+
+impl PreflightReport {
+    pub fn new(checks: Vec<CheckResult>) -> Self {
+        Self {
+            ok: checks.iter().all(|check| check.ok),
+            checks,
+        }
+    }
+}
+
+impl CheckResult {
+    pub fn new(name: impl Into<String>, duration: Duration, error: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            ok: error.is_none(),
+            duration_ms: duration.as_millis() as u64,
+            error,
+        }
+    }
+}
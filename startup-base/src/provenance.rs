@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use figment::value::Value;
+use figment::{Figment, Metadata, Profile, Source};
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::redact::{is_secret_key, redact_url, REDACTED};
+
+lazy_static::lazy_static! {
+    static ref CONFIG: RwLock<Option<Recorded>> = RwLock::new(None);
+}
+
+struct Recorded {
+    figment: Figment,
+
+    /// Name of the metadata of the defaults of the config type.
+    defaults: &'static str,
+}
+
+/// A config value and where it came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    /// The value, with secrets and passwords in urls redacted.
+    pub value: String,
+
+    /// Where the value came from, like `config toml`, `environment variable APP_HTTP__PORT`
+    /// or `defaults of the config type`.
+    pub source: String,
+}
+
+/// Records the figment the config was extracted from by [init](crate::init).
+pub(crate) fn record(figment: Figment, defaults: &'static str) {
+    *CONFIG.write() = Some(Recorded { figment, defaults });
+}
+
+/// The final value of every config key and where it came from, by the path of the key like
+/// `http.port`. Lists are reported as one value. Empty if the config was not built by
+/// [init](crate::init).
+///
+/// Use like this: `let port = &config_provenance()["http.port"]; info!("port {} from {}", port.value, port.source)`
+pub fn config_provenance() -> BTreeMap<String, Provenance> {
+    let config = CONFIG.read();
+
+    let Some(recorded) = config.as_ref() else {
+        return BTreeMap::new();
+    };
+
+    let mut provenance = BTreeMap::new();
+
+    if let Ok(Value::Dict(_, dict)) = recorded.figment.find_value("") {
+        for (key, value) in dict {
+            collect(recorded, &mut vec![key], &value, &mut provenance);
+        }
+    }
+
+    provenance
+}
+
+fn collect(recorded: &Recorded, path: &mut Vec<String>, value: &Value, provenance: &mut BTreeMap<String, Provenance>) {
+    if let Value::Dict(_, dict) = value {
+        for (key, value) in dict {
+            path.push(key.clone());
+            collect(recorded, path, value, provenance);
+            path.pop();
+        }
+
+        return;
+    }
+
+    let metadata = recorded.figment.get_metadata(value.tag());
+    let source = source(recorded, path, metadata);
+
+    let secret = path.iter().any(|key| is_secret_key(key));
+
+    let value = match secret {
+        true => REDACTED.to_string(),
+        false => format_value(value),
+    };
+
+    provenance.insert(path.join("."), Provenance { value, source });
+}
+
+fn source(recorded: &Recorded, path: &[String], metadata: Option<&Metadata>) -> String {
+    let Some(metadata) = metadata else {
+        return "unknown".to_string();
+    };
+
+    if metadata.name.ends_with("environment variable(s)") {
+        let keys = metadata.interpolate(&Profile::Default, path);
+        return format!("environment variable APP_{}", keys.replace('.', "__"));
+    }
+
+    if metadata.name == recorded.defaults {
+        return "defaults of the config type".to_string();
+    }
+
+    match &metadata.source {
+        Some(Source::File(path)) => format!("file {}", path.display()),
+        _ => match metadata.name.strip_suffix(" source string") {
+            // the embedded config, like `YAML source string`
+            Some(format) => format!("config {}", format.to_lowercase()),
+            None => metadata.name.to_string(),
+        },
+    }
+}
+
+/// Formats the value with the passwords and tokens of urls redacted, also in lists.
+pub(crate) fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(_, value) => redact_url(value).unwrap_or_else(|| value.clone()),
+        Value::Char(_, value) => value.to_string(),
+        Value::Bool(_, value) => value.to_string(),
+        Value::Num(..) => value
+            .to_u128()
+            .map(|value| value.to_string())
+            .or_else(|| value.to_i128().map(|value| value.to_string()))
+            .or_else(|| value.to_f64().map(|value| value.to_string()))
+            .unwrap_or_default(),
+        Value::Empty(..) => "null".to_string(),
+        Value::Dict(..) => "{..}".to_string(),
+        Value::Array(_, values) => {
+            let values: Vec<String> = values.iter().map(format_value).collect();
+            format!("[{}]", values.join(", "))
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How failed operations are retried, shared by the components that talk to other services,
+/// like the http client, the database connection, the kafka producer or webhook deliveries.
+///
+/// Every component classifies its failures, see [Failure]. Failures listed in `retry_on` are
+/// retried with an exponential backoff until `max_attempts` attempts were made, all others
+/// fail right away.
+///
+/// In the config:
+/// ```yaml
+/// retry:
+///   max_attempts: 5
+///   initial_backoff_millis: 200
+///   max_backoff_millis: 10000
+///   jitter: 0.5
+///   retry_on: [connection, timeout, throttled, unavailable]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one. Set to `1` to disable retries.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Backoff before the first retry. The backoff doubles with every further retry.
+    #[serde(default = "default_initial_backoff_millis")]
+    pub initial_backoff_millis: u64,
+
+    /// Upper limit for the backoff.
+    #[serde(default = "default_max_backoff_millis")]
+    pub max_backoff_millis: u64,
+
+    /// Share of the backoff that is randomized, from `0` to `1`, so that the instances of a
+    /// service do not retry in lockstep.
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+
+    /// Failures that are retried.
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<Failure>,
+}
+
+/// Why an attempt failed, as classified by the component that made it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// The connection could not be established or broke.
+    Connection,
+
+    /// There was no answer in time.
+    Timeout,
+
+    /// The other side asked to slow down, like with `429 Too Many Requests`.
+    Throttled,
+
+    /// The other side is temporarily unavailable, like with `503 Service Unavailable`.
+    Unavailable,
+
+    /// Any other failure, like `500 Internal Server Error`. Sending the same again
+    /// probably fails again.
+    Rejected,
+}
+
+impl Failure {
+    /// Classifies the status code of an http response, none if the status is no failure.
+    pub fn from_status(status: u16) -> Option<Self> {
+        match status {
+            100..=399 => None,
+            429 => Some(Failure::Throttled),
+            502..=504 => Some(Failure::Unavailable),
+            _ => Some(Failure::Rejected),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_millis: default_initial_backoff_millis(),
+            max_backoff_millis: default_max_backoff_millis(),
+            jitter: default_jitter(),
+            retry_on: default_retry_on(),
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff_millis() -> u64 {
+    100
+}
+
+fn default_max_backoff_millis() -> u64 {
+    5000
+}
+
+fn default_jitter() -> f64 {
+    0.5
+}
+
+fn default_retry_on() -> Vec<Failure> {
+    vec![
+        Failure::Connection,
+        Failure::Timeout,
+        Failure::Throttled,
+        Failure::Unavailable,
+    ]
+}
+
+impl RetryPolicy {
+    /// Returns true if the failure is retried, not counting the attempts.
+    pub fn retries(&self, failure: Failure) -> bool {
+        self.retry_on.contains(&failure)
+    }
+
+    /// Returns true if an operation that failed in the given attempt, starting at `1`,
+    /// is attempted again.
+    pub fn should_retry(&self, attempt: u32, failure: Failure) -> bool {
+        attempt < self.max_attempts && self.retries(failure)
+    }
+
+    /// Exponential backoff before the given retry, starting at `1`, randomized by the jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_millis
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(self.max_backoff_millis);
+
+        let randomized = (backoff as f64 * self.jitter.clamp(0.0, 1.0)) as u64;
+        let jitter = rand::thread_rng().gen_range(0..=randomized);
+
+        Duration::from_millis(backoff - randomized + jitter)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_millis)
+    }
+
+    /// Runs the operation until it succeeds or fails with a failure that is not retried,
+    /// classifying its errors using `classify`. The name describes the operation in the logs.
+    ///
+    /// Use like this: `policy.run("connect to redis", classify, || client.get_connection()).await?`
+    pub async fn run<T, E, F, Fut>(
+        &self,
+        name: &str,
+        classify: impl Fn(&E) -> Failure,
+        mut operation: F,
+    ) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let err = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            if !self.should_retry(attempt, classify(&err)) {
+                return Err(err);
+            }
+
+            let backoff = self.backoff(attempt);
+            tracing::warn!(
+                "Attempt {} to {} failed, retrying in {:?}: {}",
+                attempt,
+                name,
+                backoff,
+                err
+            );
+
+            tokio::time::sleep(backoff).await;
+
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+lazy_static::lazy_static! {
+    static ref BUILD: RwLock<Option<Build>> = RwLock::new(None);
+    static ref BACKENDS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+}
+
+/// What the service was built from, collected by `generate` in the build script of the service
+/// and included using the [sbom](crate::sbom!) macro.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Build {
+    pub name: &'static str,
+    pub version: &'static str,
+
+    /// Enabled features of the service.
+    pub features: &'static [&'static str],
+
+    /// All crates compiled into the service, without build and dev dependencies.
+    pub crates: &'static [Crate],
+}
+
+/// A crate compiled into the service.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Crate {
+    pub name: &'static str,
+    pub version: &'static str,
+
+    /// Where the crate came from, like `registry+https://github.com/rust-lang/crates.io-index`.
+    /// None for crates of the workspace and other path dependencies.
+    pub source: Option<&'static str>,
+
+    /// Enabled features.
+    pub features: &'static [&'static str],
+}
+
+/// The report served by the admin endpoint of `startup-http`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// None if the service did not [register] its build.
+    pub build: Option<Build>,
+
+    /// Enabled features of the `startup-*` crates.
+    pub startup_features: BTreeMap<&'static str, &'static [&'static str]>,
+
+    /// Backends in use by kind, like `tracing: zipkin` or `database: postgres`.
+    pub backends: BTreeMap<String, String>,
+}
+
+/// Includes the [Build] generated by the build script of the service.
+///
+/// Use like this: `startup_base::sbom::register(startup_base::sbom!())`, with this build script:
+/// ```ignore
+/// fn main() {
+///     startup_base::sbom::generate().expect("generate sbom");
+/// }
+/// ```
+#[macro_export]
+macro_rules! sbom {
+    () => {
+        include!(concat!(env!("OUT_DIR"), "/sbom.rs"))
+    };
+}
+
+/// Registers the build of the service for the [report].
+pub fn register(build: Build) {
+    *BUILD.write() = Some(build);
+}
+
+/// Records a backend in use, e.g. by the component connecting to it.
+/// A later backend of the same kind replaces the earlier one.
+pub fn register_backend(kind: &str, name: impl Into<String>) {
+    BACKENDS.write().insert(kind.to_string(), name.into());
+}
+
+/// The build of the service and the backends in use.
+pub fn report() -> Report {
+    let build = *BUILD.read();
+
+    let startup_features = build
+        .iter()
+        .flat_map(|build| build.crates)
+        .filter(|krate| krate.name.starts_with("startup-"))
+        .map(|krate| (krate.name, krate.features))
+        .collect();
+
+    Report {
+        build,
+        startup_features,
+        backends: BACKENDS.read().clone(),
+    }
+}
+
+#[cfg(feature = "sbom")]
+pub use generator::generate;
+
+#[cfg(feature = "sbom")]
+mod generator {
+    use std::collections::{BTreeSet, HashMap};
+    use std::fmt::Write;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use serde_json::Value;
+
+    /// Writes the [Build](super::Build) of the package to `$OUT_DIR/sbom.rs`, to be included
+    /// using the [sbom](crate::sbom!) macro. Call it in the build script of the service, with
+    /// `startup-base` as a build dependency with the `sbom` feature.
+    ///
+    /// The crates and their features are resolved using `cargo metadata` with the
+    /// features of the package that is built. Like in `Cargo.lock`, the features of a crate
+    /// include those enabled by build dependencies, e.g. `sbom` of `startup-base`.
+    pub fn generate() -> io::Result<()> {
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+        let manifest = PathBuf::from(build_env("CARGO_MANIFEST_DIR")?).join("Cargo.toml");
+
+        // the build script only knows the enabled features as CARGO_FEATURE_<NAME> variables
+        let declared = metadata(&cargo, &manifest, &["--no-deps"])?;
+
+        let features: Vec<&str> = root_package(&declared, &manifest)?["features"]
+            .as_object()
+            .into_iter()
+            .flat_map(|features| features.keys())
+            .map(String::as_str)
+            .filter(|feature| {
+                let name = format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"));
+                std::env::var_os(name).is_some()
+            })
+            .collect();
+
+        let features_arg = features.join(",");
+        let mut args = vec!["--no-default-features"];
+        if !features.is_empty() {
+            args.extend(["--features", &features_arg]);
+        }
+
+        let resolved = metadata(&cargo, &manifest, &args)?;
+        let root = root_package(&resolved, &manifest)?["id"].as_str().unwrap_or_default();
+
+        let packages: HashMap<&str, &Value> = array(&resolved["packages"])
+            .map(|package| (package["id"].as_str().unwrap_or_default(), package))
+            .collect();
+
+        let nodes: HashMap<&str, &Value> = array(&resolved["resolve"]["nodes"])
+            .map(|node| (node["id"].as_str().unwrap_or_default(), node))
+            .collect();
+
+        // the crates reachable from the package through normal dependencies
+        let mut reachable = BTreeSet::new();
+        let mut pending = vec![root];
+
+        while let Some(id) = pending.pop() {
+            for dep in nodes.get(id).into_iter().flat_map(|node| array(&node["deps"])) {
+                let normal = array(&dep["dep_kinds"]).any(|kind| kind["kind"].is_null());
+                let id = dep["pkg"].as_str().unwrap_or_default();
+
+                if normal && reachable.insert(id) {
+                    pending.push(id);
+                }
+            }
+        }
+
+        let mut crates = String::new();
+
+        for id in reachable {
+            let (Some(package), Some(node)) = (packages.get(id), nodes.get(id)) else {
+                continue;
+            };
+
+            let source = match package["source"].as_str() {
+                Some(source) => format!("Some({:?})", source),
+                None => "None".to_string(),
+            };
+
+            let features: Vec<&str> = array(&node["features"]).filter_map(Value::as_str).collect();
+
+            let _ = writeln!(
+                crates,
+                "        ::startup_base::sbom::Crate {{ name: {:?}, version: {:?}, source: {}, features: &{:?} }},",
+                package["name"].as_str().unwrap_or_default(),
+                package["version"].as_str().unwrap_or_default(),
+                source,
+                features,
+            );
+        }
+
+        let code = format!(
+            "::startup_base::sbom::Build {{\n    name: {:?},\n    version: {:?},\n    features: &{:?},\n    crates: &[\n{}    ],\n}}\n",
+            build_env("CARGO_PKG_NAME")?,
+            build_env("CARGO_PKG_VERSION")?,
+            features,
+            crates,
+        );
+
+        std::fs::write(Path::new(&build_env("OUT_DIR")?).join("sbom.rs"), code)?;
+
+        if let Some(workspace_root) = resolved["workspace_root"].as_str() {
+            println!(
+                "cargo:rerun-if-changed={}",
+                Path::new(workspace_root).join("Cargo.lock").display()
+            );
+        }
+
+        println!("cargo:rerun-if-changed={}", manifest.display());
+
+        Ok(())
+    }
+
+    fn build_env(name: &str) -> io::Result<String> {
+        std::env::var(name).map_err(|_| invalid_data(format!("{} is not set, call generate in a build script", name)))
+    }
+
+    fn metadata(cargo: &str, manifest: &Path, args: &[&str]) -> io::Result<Value> {
+        // the dependencies are already downloaded when the build script runs
+        let output = std::process::Command::new(cargo)
+            .args(["metadata", "--format-version", "1", "--offline", "--manifest-path"])
+            .arg(manifest)
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(invalid_data(format!("cargo metadata failed: {}", stderr.trim())));
+        }
+
+        serde_json::from_slice(&output.stdout).map_err(|err| invalid_data(format!("invalid cargo metadata: {}", err)))
+    }
+
+    fn root_package<'a>(metadata: &'a Value, manifest: &Path) -> io::Result<&'a Value> {
+        array(&metadata["packages"])
+            .find(|package| package["manifest_path"].as_str().map(Path::new) == Some(manifest))
+            .ok_or_else(|| invalid_data(format!("package {:?} not found in cargo metadata", manifest)))
+    }
+
+    fn array(value: &Value) -> impl Iterator<Item = &Value> {
+        value.as_array().into_iter().flatten()
+    }
+
+    fn invalid_data(message: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+}
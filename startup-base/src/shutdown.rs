@@ -0,0 +1,154 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio_util::sync::CancellationToken;
+
+/// Priorities of the hooks registered by the startup crates. Hooks with a lower priority run first.
+pub mod priority {
+    /// Hooks of the application, e.g. to stop accepting new work.
+    pub const APPLICATION: i32 = 0;
+
+    /// Consumers that finish their work and commit their progress, e.g. kafka consumers.
+    pub const CONSUMERS: i32 = 100;
+
+    /// Connections used by the consumers and the application, e.g. database pools.
+    pub const CONNECTIONS: i32 = 200;
+
+    /// Exporters of traces and metrics, so the teardown of everything else is still recorded.
+    pub const TELEMETRY: i32 = 300;
+}
+
+/// Time each hook gets to finish before the next hook runs.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+type HookFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Hook {
+    name: String,
+    priority: i32,
+    run: Box<dyn FnOnce() -> HookFuture + Send>,
+}
+
+lazy_static::lazy_static! {
+    static ref HOOKS: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+    static ref DONE: CancellationToken = CancellationToken::new();
+}
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+static GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers a hook that runs once the service shuts down, e.g. to close a connection pool.
+///
+/// Hooks run one after another, ordered by their priority, see [priority], and in reverse order of
+/// registration within the same priority. Each hook gets 10 seconds to finish.
+///
+/// The hooks run when the last running [TaskManager](crate::tasks::TaskManager) or server
+/// of the startup crates stopped, or when [run_hooks] is called.
+///
+/// Use like this: `startup_base::on_shutdown("outbox", priority::APPLICATION, || async move { outbox.flush().await })`
+pub fn on_shutdown<F, Fut>(name: impl Into<String>, priority: i32, hook: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let hook = Hook {
+        name: name.into(),
+        priority,
+        run: Box::new(move || Box::pin(hook())),
+    };
+
+    if STARTED.load(Ordering::SeqCst) {
+        tracing::warn!(
+            "Shutdown hook {} registered after the hooks ran, ignoring it",
+            hook.name
+        );
+        return;
+    }
+
+    HOOKS.lock().push(hook);
+}
+
+/// Waits for SIGINT or SIGTERM, the signals that stop a service. Only waits for SIGINT
+/// if the handler for SIGTERM can not be installed.
+pub async fn signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    let Ok(mut terminate) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) else {
+        tracing::warn!("Failed to install handler for SIGTERM, only waiting for SIGINT");
+        let _ = ctrl_c.await;
+        return;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+/// Runs the registered hooks. Hooks run only once, later calls wait for the first one to finish.
+pub async fn run_hooks() {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        DONE.cancelled().await;
+        return;
+    }
+
+    let mut hooks = std::mem::take(&mut *HOOKS.lock());
+
+    hooks.reverse();
+    hooks.sort_by_key(|hook| hook.priority);
+
+    for hook in hooks {
+        tracing::info!("Running shutdown hook {}", hook.name);
+
+        if tokio::time::timeout(HOOK_TIMEOUT, (hook.run)()).await.is_err() {
+            tracing::warn!("Shutdown hook {} did not finish within {:?}", hook.name, HOOK_TIMEOUT);
+        }
+    }
+
+    DONE.cancel();
+}
+
+/// Delays the shutdown hooks until it is released, e.g. while a server still drains its requests.
+/// With multiple guards, the hooks run once the last guard was released.
+pub struct ShutdownGuard(());
+
+/// Acquires a [ShutdownGuard] for a component that runs until the service shuts down.
+pub fn guard() -> ShutdownGuard {
+    GUARDS.fetch_add(1, Ordering::SeqCst);
+    ShutdownGuard(())
+}
+
+impl ShutdownGuard {
+    /// Releases the guard and waits until the hooks ran. The last guard runs the hooks.
+    pub async fn release(self) {
+        // the guard is released here and not on drop
+        std::mem::forget(self);
+
+        if GUARDS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            run_hooks().await;
+        } else {
+            DONE.cancelled().await;
+        }
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        // dropped without release, e.g. on error, so the other guards do not wait forever
+        if GUARDS.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+
+        // dropped outside of a runtime, e.g. while it shuts down after an error
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!("Skipped the shutdown hooks, the last guard was dropped outside of a runtime");
+            DONE.cancel();
+            return;
+        };
+
+        handle.spawn(run_hooks());
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use subtle::ConstantTimeEq;
+
+/// Prefix of a Standard Webhooks secret, followed by the base64 encoded key.
+pub const WEBHOOK_SECRET_PREFIX: &str = "whsec_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Computes the HMAC of the message, which is the concatenation of its parts.
+pub fn hmac(algorithm: SignatureAlgorithm, key: &[u8], message: &[&[u8]]) -> Vec<u8> {
+    match algorithm {
+        SignatureAlgorithm::Sha1 => mac::<Hmac<Sha1>>(key, message).finalize().into_bytes().to_vec(),
+        SignatureAlgorithm::Sha256 => mac::<Hmac<Sha256>>(key, message).finalize().into_bytes().to_vec(),
+        SignatureAlgorithm::Sha512 => mac::<Hmac<Sha512>>(key, message).finalize().into_bytes().to_vec(),
+    }
+}
+
+/// Verifies the HMAC of the message. Compares in constant time, so the signature can not be
+/// guessed byte by byte.
+pub fn verify_hmac(algorithm: SignatureAlgorithm, key: &[u8], message: &[&[u8]], signature: &[u8]) -> bool {
+    match algorithm {
+        SignatureAlgorithm::Sha1 => mac::<Hmac<Sha1>>(key, message).verify_slice(signature).is_ok(),
+        SignatureAlgorithm::Sha256 => mac::<Hmac<Sha256>>(key, message).verify_slice(signature).is_ok(),
+        SignatureAlgorithm::Sha512 => mac::<Hmac<Sha512>>(key, message).verify_slice(signature).is_ok(),
+    }
+}
+
+fn mac<M: Mac + KeyInit>(key: &[u8], message: &[&[u8]]) -> M {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("hmac accepts keys of any size");
+
+    for part in message {
+        mac.update(part);
+    }
+
+    mac
+}
+
+/// Compares two secrets in constant time. Only the length of the values is leaked.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Returns the key of a Standard Webhooks secret like `whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw`,
+/// or None if the secret is not in this format.
+pub fn webhook_key(secret: &str) -> Option<Vec<u8>> {
+    let encoded = secret.strip_prefix(WEBHOOK_SECRET_PREFIX)?;
+    STANDARD.decode(encoded).ok().filter(|key| !key.is_empty())
+}
+
+/// Returns the key of a webhook secret: the decoded key of a Standard Webhooks secret, or the
+/// bytes of the secret itself for senders like GitHub that use the secret as key.
+pub fn webhook_key_or_raw(secret: &str) -> Vec<u8> {
+    webhook_key(secret).unwrap_or_else(|| secret.as_bytes().to_vec())
+}
+
+/// Signs a webhook following the Standard Webhooks scheme: the signature is the base64 encoded
+/// HMAC-SHA256 of `{id}.{timestamp}.{body}`, and the header lists one `v1,<signature>` per key,
+/// separated by spaces.
+pub fn sign_webhook(keys: &[Vec<u8>], id: &str, timestamp: i64, body: &[u8]) -> String {
+    let timestamp = timestamp.to_string();
+    let message = [id.as_bytes(), b".", timestamp.as_bytes(), b".", body];
+
+    keys.iter()
+        .map(|key| hmac(SignatureAlgorithm::Sha256, key, &message))
+        .map(|signature| format!("v1,{}", STANDARD.encode(signature)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Verifies a webhook signed by [sign_webhook]. Fails if no signature of the header matches the key
+/// or the timestamp is further than `tolerance` away from `now`, to reject replayed payloads.
+pub fn verify_webhook(
+    key: &[u8],
+    id: &str,
+    timestamp: &str,
+    body: &[u8],
+    signatures: &str,
+    now: i64,
+    tolerance: Duration,
+) -> bool {
+    let Ok(parsed) = timestamp.parse::<i64>() else {
+        return false;
+    };
+
+    if now.abs_diff(parsed) > tolerance.as_secs() {
+        return false;
+    }
+
+    let message = [id.as_bytes(), b".", timestamp.as_bytes(), b".", body];
+
+    signatures
+        .split_whitespace()
+        .filter_map(|signature| signature.strip_prefix("v1,"))
+        .filter_map(|signature| STANDARD.decode(signature).ok())
+        .any(|signature| verify_hmac(SignatureAlgorithm::Sha256, key, &message, &signature))
+}
@@ -0,0 +1,117 @@
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre;
+use tokio_util::sync::CancellationToken;
+
+use crate::health;
+
+/// Sends the state to the service manager, e.g. `READY=1`, see `sd_notify(3)`.
+/// Returns false without doing anything if the service does not run under systemd.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    send(&path, state)?;
+
+    Ok(true)
+}
+
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    // a socket in the abstract namespace, see unix(7)
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            let message = "abstract notify sockets are only supported on linux";
+            return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+        }
+    }
+
+    socket.send_to(state.as_bytes(), Path::new(path))?;
+
+    Ok(())
+}
+
+/// The watchdog interval configured with `WatchdogSec`, if the watchdog is enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    // the watchdog is meant for a different process
+    if let Some(pid) = std::env::var_os("WATCHDOG_PID") {
+        if pid.to_str().and_then(|pid| pid.parse().ok()) != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Notifies systemd about the state of the service, so it can run as a `Type=notify` unit.
+/// Sends `READY=1` once all components are healthy, see [health], and `STOPPING=1` when
+/// the task is cancelled. With `WatchdogSec` configured, the watchdog is notified at half the
+/// interval as long as the service is healthy, so systemd restarts a service that stays
+/// unhealthy for longer than the interval.
+///
+/// Does nothing if the service does not run under systemd.
+///
+/// Use like this: `TaskManager::new(&config.tasks).task("systemd", systemd::run)`
+pub async fn run(cancel: CancellationToken) -> eyre::Result<()> {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        cancel.cancelled().await;
+        return Ok(());
+    }
+
+    // the health of components is polled, as listeners can not be removed once the task stops
+    while !health::is_healthy() {
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(Duration::from_millis(250)) => {},
+        }
+    }
+
+    tracing::info!("Service is healthy, notifying systemd");
+    notify("READY=1")?;
+
+    let watchdog = watchdog_interval();
+
+    loop {
+        let pet = async {
+            match watchdog {
+                Some(interval) => tokio::time::sleep(interval / 2).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = pet => {},
+        }
+
+        if health::is_healthy() {
+            notify("WATCHDOG=1")?;
+        } else {
+            tracing::warn!("Service is unhealthy, not notifying the systemd watchdog");
+        }
+    }
+
+    notify("STOPPING=1")?;
+
+    Ok(())
+}
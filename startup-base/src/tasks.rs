@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+pub use tokio_util::sync::CancellationToken;
+
+use crate::{health, shutdown};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TasksConfig {
+    /// Delay before the first restart of a failed task. Doubles with every
+    /// further failure, up to `max_backoff_millis`.
+    #[serde(default = "default_initial_backoff_millis")]
+    pub initial_backoff_millis: u64,
+
+    #[serde(default = "default_max_backoff_millis")]
+    pub max_backoff_millis: u64,
+
+    /// Time each task gets to stop after it was cancelled before it is aborted.
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+}
+
+fn default_initial_backoff_millis() -> u64 {
+    1000
+}
+
+fn default_max_backoff_millis() -> u64 {
+    60_000
+}
+
+fn default_shutdown_timeout_seconds() -> u64 {
+    10
+}
+
+impl Default for TasksConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_millis: default_initial_backoff_millis(),
+            max_backoff_millis: default_max_backoff_millis(),
+            shutdown_timeout_seconds: default_shutdown_timeout_seconds(),
+        }
+    }
+}
+
+type TaskFn = Box<dyn Fn(CancellationToken) -> JoinHandle<eyre::Result<()>> + Send + Sync>;
+
+struct Task {
+    name: String,
+    start: TaskFn,
+}
+
+/// Supervises long-running background tasks like queue consumers. A task that fails, panics
+/// or returns before it was cancelled is restarted with an exponential backoff. Each task reports
+/// its health to the [health] registry under its name, and is unhealthy while it waits for a restart.
+///
+/// A task receives a [CancellationToken] and should return soon after it was cancelled.
+/// On shutdown, the tasks are stopped one after another in the reverse order of registration,
+/// so a task can rely on the tasks registered before it for as long as it runs. The
+/// [shutdown hooks](crate::on_shutdown) run after all tasks stopped.
+///
+/// Use like this:
+/// ```ignore
+/// TaskManager::new(&config.tasks)
+///     .task("outbox", move |cancel| publish_outbox(pool.clone(), cancel))
+///     .run()
+///     .await;
+/// ```
+pub struct TaskManager {
+    config: TasksConfig,
+    tasks: Vec<Task>,
+}
+
+impl TaskManager {
+    pub fn new(config: &TasksConfig) -> Self {
+        Self {
+            config: config.clone(),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Registers a task with a unique name.
+    pub fn task<F, Fut>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn(CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        let start: TaskFn = Box::new(move |cancel| tokio::spawn(f(cancel)));
+
+        self.tasks.push(Task {
+            name: name.to_string(),
+            start,
+        });
+
+        self
+    }
+
+    /// Runs the tasks until the process receives SIGINT or SIGTERM.
+    pub async fn run(self) {
+        let signal = async {
+            shutdown::signal().await;
+            tracing::info!("Received signal, stopping background tasks");
+        };
+
+        self.run_until(signal).await
+    }
+
+    /// Runs the tasks until `shutdown` resolves, then stops them in reverse order of registration.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) {
+        let guard = shutdown::guard();
+
+        let timeout = Duration::from_secs(self.config.shutdown_timeout_seconds);
+
+        let supervisors: Vec<_> = self
+            .tasks
+            .into_iter()
+            .map(|task| {
+                let cancel = CancellationToken::new();
+                let name = task.name.clone();
+                let handle = tokio::spawn(supervise(task, self.config.clone(), cancel.clone()));
+                (name, cancel, handle)
+            })
+            .collect();
+
+        shutdown.await;
+
+        for (name, cancel, mut handle) in supervisors.into_iter().rev() {
+            tracing::info!("Stopping task {}", name);
+            cancel.cancel();
+
+            if tokio::time::timeout(timeout, &mut handle).await.is_err() {
+                tracing::warn!("Task {} did not stop within {:?}, aborting it", name, timeout);
+                handle.abort();
+            }
+
+            health::remove(&name);
+        }
+
+        guard.release().await;
+    }
+}
+
+async fn supervise(task: Task, config: TasksConfig, cancel: CancellationToken) {
+    let initial_backoff = Duration::from_millis(config.initial_backoff_millis);
+    let max_backoff = Duration::from_millis(config.max_backoff_millis).max(initial_backoff);
+
+    let mut backoff = initial_backoff;
+
+    loop {
+        tracing::info!("Starting task {}", task.name);
+        health::set_healthy(&task.name);
+
+        let started = Instant::now();
+
+        // aborting the handle on drop also stops the task when the shutdown timed out
+        let mut handle = AbortOnDrop((task.start)(cancel.child_token()));
+
+        let reason = match (&mut handle.0).await {
+            Ok(Ok(())) if cancel.is_cancelled() => return,
+            Ok(Ok(())) => "task returned unexpectedly".to_string(),
+            Ok(Err(err)) => format!("task failed: {:?}", err),
+            Err(err) if err.is_panic() => "task panicked".to_string(),
+            Err(err) => format!("task stopped: {}", err),
+        };
+
+        if cancel.is_cancelled() {
+            tracing::warn!("Task {} stopped during shutdown: {}", task.name, reason);
+            return;
+        }
+
+        // a task that ran for a while before failing starts over with a short backoff
+        if started.elapsed() > max_backoff {
+            backoff = initial_backoff;
+        }
+
+        tracing::warn!("Restarting task {} in {:?}, {}", task.name, backoff, reason);
+        health::set_unhealthy(&task.name, reason);
+
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(backoff) => {},
+        }
+
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
+
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::health;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmupConfig {
+    /// Startup fails if the warm-up did not finish within this time.
+    #[serde(default = "default_deadline_seconds")]
+    pub deadline_seconds: u64,
+}
+
+fn default_deadline_seconds() -> u64 {
+    120
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            deadline_seconds: default_deadline_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Status {
+    Running,
+    Finished,
+    Failed(String),
+}
+
+struct Warmup {
+    started: Instant,
+    status: Status,
+}
+
+lazy_static::lazy_static! {
+    static ref WARMUPS: Mutex<BTreeMap<String, Warmup>> = Mutex::new(BTreeMap::new());
+}
+
+/// Starts the warm-up of a component, e.g. fetching keys, running migrations or filling a cache.
+///
+/// The service is not ready until the warm-up finished: it is reported as unhealthy
+/// component `warmup:<name>` to the [health] registry, so readiness checks and `READY=1`
+/// for systemd wait for it. A failed warm-up fails [wait]. Must be called within a tokio runtime.
+///
+/// Use like this: `warmup::register("migrations", async move { Ok(MIGRATOR.run(&pool).await?) })`
+pub fn register<F>(name: &str, warmup: F)
+where
+    F: Future<Output = eyre::Result<()>> + Send + 'static,
+{
+    start(name);
+
+    let name = name.to_string();
+
+    tokio::spawn(async move {
+        let result = warmup.await.map_err(|err| format!("{:#}", err));
+        finish(&name, result);
+    });
+}
+
+/// Runs the warm-up of a component in place and returns its result, for a warm-up the caller
+/// has to wait for anyway, e.g. the migrations before the database pool is used. It is reported
+/// like a warm-up started with [register].
+///
+/// Use like this: `warmup::run("migrations", migrator.run(&pool)).await?`
+pub async fn run<T, E: Display>(name: &str, warmup: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    start(name);
+
+    let result = warmup.await;
+    finish(name, result.as_ref().map(drop).map_err(ToString::to_string));
+
+    result
+}
+
+fn start(name: &str) {
+    health::set_unhealthy(&component(name), "warming up");
+
+    WARMUPS.lock().insert(
+        name.to_string(),
+        Warmup {
+            started: Instant::now(),
+            status: Status::Running,
+        },
+    );
+}
+
+fn finish(name: &str, result: Result<(), String>) {
+    let component = component(name);
+
+    let status = match result {
+        Ok(()) => {
+            tracing::info!("Warm-up of {} finished", name);
+            health::remove(&component);
+            Status::Finished
+        }
+
+        Err(err) => {
+            tracing::warn!("Warm-up of {} failed: {}", name, err);
+            health::set_unhealthy(&component, format!("warm-up failed: {}", err));
+            Status::Failed(err)
+        }
+    };
+
+    if let Some(warmup) = WARMUPS.lock().get_mut(name) {
+        warmup.status = status;
+    }
+}
+
+fn component(name: &str) -> String {
+    format!("warmup:{}", name)
+}
+
+/// Returns true if all registered warm-ups finished successfully.
+pub fn is_finished() -> bool {
+    WARMUPS
+        .lock()
+        .values()
+        .all(|warmup| matches!(warmup.status, Status::Finished))
+}
+
+/// Waits until all registered warm-ups finished. Fails as soon as a warm-up failed, or if
+/// the warm-ups did not finish within the deadline, with a report of the components that stalled.
+///
+/// Use like this: `tokio::try_join!(warmup::wait(&config.warmup), run_server(&config.http, router))?`
+pub async fn wait(config: &WarmupConfig) -> eyre::Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(config.deadline_seconds);
+
+    loop {
+        let (failed, running) = {
+            let warmups = WARMUPS.lock();
+
+            let failed: Vec<_> = warmups
+                .iter()
+                .filter_map(|(name, warmup)| match &warmup.status {
+                    Status::Failed(err) => Some(format!("{}: {}", name, err)),
+                    _ => None,
+                })
+                .collect();
+
+            let running: Vec<_> = warmups
+                .iter()
+                .filter(|(_, warmup)| matches!(warmup.status, Status::Running))
+                .map(|(name, warmup)| format!("{} (running for {:.1?})", name, warmup.started.elapsed()))
+                .collect();
+
+            (failed, running)
+        };
+
+        if !failed.is_empty() {
+            eyre::bail!("warm-up failed: {}", failed.join(", "));
+        }
+
+        if running.is_empty() {
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            eyre::bail!(
+                "warm-up did not finish within {}s, stalled: {}",
+                config.deadline_seconds,
+                running.join(", ")
+            );
+        }
+
+        // polled, like systemd::run, as health listeners can not be removed
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
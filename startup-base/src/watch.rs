@@ -0,0 +1,235 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::RwLock;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, info, warn};
+
+use crate::bus::{self, LifecycleEvent};
+
+lazy_static::lazy_static! {
+    static ref RELOAD_FAILURES: Counter<u64> = opentelemetry::global::meter("startup-base")
+        .u64_counter("file_watch.reload_failures")
+        .with_description("Number of failed reloads of watched files")
+        .init();
+}
+
+/// Watches files like certificates, keys or secrets and reloads the value derived from them
+/// when they change, so they can be rotated without a restart.
+///
+/// Changes are detected using inotify, falling back to polling if inotify is not available.
+/// The parent directories are watched instead of the files themselves, so files replaced by
+/// a rename or a symlink swap, as kubernetes does for mounted secrets, are detected too.
+/// Reloads are debounced, so a certificate and its key written one after another are
+/// loaded together. A failed reload keeps the previous value and is counted in the
+/// `file_watch.reload_failures` metric.
+///
+/// Use like this:
+/// ```ignore
+/// let certificate = FileWatch::new("tls", [&config.certificate, &config.key])
+///     .load(move || load_certificate(&config))?;
+/// ```
+pub struct FileWatch {
+    name: String,
+    paths: Vec<PathBuf>,
+    debounce: Duration,
+    poll_interval: Duration,
+}
+
+impl FileWatch {
+    /// The name identifies the value in logs and metrics.
+    pub fn new(name: impl Into<String>, paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        Self {
+            name: name.into(),
+            paths: paths.into_iter().map(Into::into).collect(),
+            debounce: Duration::from_millis(500),
+            poll_interval: Duration::from_secs(10),
+        }
+    }
+
+    /// Time without further changes before the value is reloaded. Defaults to 500ms.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Interval to check the files for changes if inotify is not available. Defaults to 10s.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Loads the value and reloads it in the background every time the files change.
+    /// Fails if the value can not be loaded initially. Must be called within a tokio runtime.
+    pub fn load<T, E, F>(self, load: F) -> Result<Watched<T>, E>
+    where
+        T: Send + Sync + 'static,
+        E: Display + 'static,
+        F: Fn() -> Result<T, E> + Send + 'static,
+    {
+        let loaded = fingerprint(&self.paths);
+
+        let watched = Watched::fixed(load()?);
+
+        let current = Arc::downgrade(&watched.current);
+        tokio::spawn(self.watch(current, loaded, load));
+
+        Ok(watched)
+    }
+
+    async fn watch<T, E, F>(self, current: Weak<RwLock<Arc<T>>>, mut loaded: u64, load: F)
+    where
+        E: Display,
+        F: Fn() -> Result<T, E>,
+    {
+        let (tx, mut events) = unbounded_channel();
+
+        // dropping the watcher stops watching, so keep it until the task ends
+        let watcher = self.watcher(tx);
+
+        loop {
+            let changed = match &watcher {
+                Some(_) => self.next_event(&mut events).await,
+                None => {
+                    tokio::time::sleep(self.poll_interval).await;
+                    true
+                }
+            };
+
+            // the watcher stopped or every copy of the value was dropped
+            let Some(current) = current.upgrade().filter(|_| changed) else {
+                debug!("Stop watching files of {}", self.name);
+                return;
+            };
+
+            if fingerprint(&self.paths) == loaded {
+                continue;
+            }
+
+            // wait for writes to multiple files to finish
+            if watcher.is_none() {
+                tokio::time::sleep(self.debounce).await;
+            }
+
+            loaded = fingerprint(&self.paths);
+
+            match load() {
+                Ok(value) => {
+                    info!("Reloaded {} from {:?}", self.name, self.paths);
+                    *current.write() = Arc::new(value);
+
+                    let name = self.name.clone();
+                    bus::publish(LifecycleEvent::Reloaded { name });
+                }
+
+                Err(err) => {
+                    warn!("Failed to reload {}, keeping the previous value: {}", self.name, err);
+
+                    let attributes = [KeyValue::new("name", self.name.clone())];
+                    RELOAD_FAILURES.add(&opentelemetry::Context::current(), 1, &attributes);
+                }
+            }
+        }
+    }
+
+    fn watcher(&self, tx: UnboundedSender<()>) -> Option<RecommendedWatcher> {
+        let directories: BTreeSet<&Path> = self
+            .paths
+            .iter()
+            .map(|path| path.parent().filter(|parent| !parent.as_os_str().is_empty()))
+            .map(|parent| parent.unwrap_or_else(|| Path::new(".")))
+            .collect();
+
+        let watcher = notify::recommended_watcher(move |_event| {
+            let _ = tx.send(());
+        });
+
+        let result = watcher.and_then(|mut watcher| {
+            for directory in directories {
+                watcher.watch(directory, RecursiveMode::NonRecursive)?;
+            }
+
+            Ok(watcher)
+        });
+
+        match result {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!(
+                    "Can not watch files of {}, polling for changes instead: {}",
+                    self.name, err
+                );
+                None
+            }
+        }
+    }
+
+    /// Waits for an event followed by `debounce` without further events.
+    async fn next_event(&self, events: &mut UnboundedReceiver<()>) -> bool {
+        if events.recv().await.is_none() {
+            return false;
+        }
+
+        loop {
+            match tokio::time::timeout(self.debounce, events.recv()).await {
+                Ok(Some(_)) => continue,
+                Ok(None) => return false,
+                Err(_) => return true,
+            }
+        }
+    }
+}
+
+/// Hashes the content of the files, as events and modification times also change
+/// for files that were touched or replaced with the same content.
+fn fingerprint(paths: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    for path in paths {
+        std::fs::read(path).ok().hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+/// A value that is replaced when the files it was loaded from change, see [FileWatch].
+/// Clones share the value.
+pub struct Watched<T> {
+    current: Arc<RwLock<Arc<T>>>,
+}
+
+impl<T> Watched<T> {
+    /// A value that never changes, e.g. for tests.
+    pub fn fixed(value: T) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(value))),
+        }
+    }
+
+    /// The current value.
+    pub fn get(&self) -> Arc<T> {
+        self.current.read().clone()
+    }
+}
+
+impl<T> Clone for Watched<T> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T: Debug> Debug for Watched<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Watched").field(&self.get()).finish()
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::{global, KeyValue};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use startup_base::clock::{self, Clock};
+
+#[cfg(feature = "redis")]
+use startup_redis::RedisPool;
+
+#[cfg(feature = "redis")]
+mod redis;
+mod warm;
+
+pub use warm::WarmConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Time after which an entry is removed from the cache.
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: u64,
+
+    /// Maximum number of entries. Only used by the in-memory cache,
+    /// least recently used entries are removed first.
+    #[serde(default = "default_max_capacity")]
+    pub max_capacity: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: default_ttl_seconds(),
+            max_capacity: default_max_capacity(),
+        }
+    }
+}
+
+fn default_ttl_seconds() -> u64 {
+    300
+}
+
+fn default_max_capacity() -> u64 {
+    10_000
+}
+
+/// A cache with a time to live for every entry, either in memory or shared by all replicas
+/// using redis. Values are serialized as json when stored in redis, so both backends can be
+/// swapped without changing the code using the cache.
+///
+/// [Cache::get_or_insert_with] computes missing values only once, concurrent calls for the
+/// same key wait for the first one to finish.
+///
+/// The metric `cache.requests` counts hits and misses per cache. Redis errors are logged and
+/// treated as misses, a cache should never break a request.
+///
+/// Use like this:
+/// ```ignore
+/// let cache: Cache<UserId, User> = Cache::memory("users", &config.cache);
+///
+/// let user = cache.get_or_try_insert_with(user_id, || repository.find_user(user_id)).await?;
+/// ```
+pub struct Cache<K, V> {
+    inner: Arc<Inner<K, V>>,
+}
+
+impl<K, V> Clone for Cache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+struct Inner<K, V> {
+    name: String,
+    backend: Backend<K, V>,
+    computing: Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>,
+    requests: Counter<u64>,
+}
+
+enum Backend<K, V> {
+    Memory(MemoryBackend<K, V>),
+
+    #[cfg(feature = "redis")]
+    Redis(redis::RedisBackend),
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: Hash + Eq + Clone + Display + Send + Sync + 'static,
+    V: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Creates a cache that holds its entries in memory.
+    pub fn memory(name: &str, config: &CacheConfig) -> Self {
+        Self::memory_with_clock(name, config, clock::system())
+    }
+
+    /// Creates a cache that holds its entries in memory and expires them using the clock,
+    /// e.g. a `ManualClock` in tests.
+    pub fn memory_with_clock(name: &str, config: &CacheConfig, clock: Arc<dyn Clock>) -> Self {
+        let ttl = Duration::from_secs(config.ttl_seconds);
+
+        let cache = moka::future::Cache::builder()
+            .name(name)
+            .max_capacity(config.max_capacity)
+            .time_to_live(ttl)
+            .build();
+
+        Self::new(name, Backend::Memory(MemoryBackend { cache, ttl, clock }))
+    }
+
+    /// Creates a cache that holds its entries in redis, using
+    /// the keys `cache:<name>:<key>`.
+    #[cfg(feature = "redis")]
+    pub fn redis(name: &str, config: &CacheConfig, pool: &RedisPool) -> Self {
+        let backend = redis::RedisBackend::new(pool, name, Duration::from_secs(config.ttl_seconds));
+        Self::new(name, Backend::Redis(backend))
+    }
+
+    fn new(name: &str, backend: Backend<K, V>) -> Self {
+        let requests = global::meter("startup-cache")
+            .u64_counter("cache.requests")
+            .with_description("Cache lookups by result")
+            .init();
+
+        let inner = Inner {
+            name: name.to_string(),
+            backend,
+            computing: Mutex::new(HashMap::new()),
+            requests,
+        };
+
+        Self { inner: Arc::new(inner) }
+    }
+
+    pub async fn get(&self, key: &K) -> Option<V> {
+        let value = self.inner.backend.get(key).await;
+
+        let result = if value.is_some() { "hit" } else { "miss" };
+        let attributes = [
+            KeyValue::new("cache", self.inner.name.clone()),
+            KeyValue::new("result", result),
+        ];
+
+        self.inner
+            .requests
+            .add(&opentelemetry::Context::current(), 1, &attributes);
+
+        value
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        self.inner.backend.insert(key, value).await
+    }
+
+    pub async fn remove(&self, key: &K) {
+        self.inner.backend.remove(key).await
+    }
+
+    /// Returns the cached value or computes and caches it.
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let Ok(value) = self
+            .get_or_try_insert_with(key, || async { Ok::<_, Infallible>(f().await) })
+            .await;
+
+        value
+    }
+
+    /// Returns the cached value or computes and caches it. Errors are not cached,
+    /// the next caller waiting for the same key tries again.
+    pub async fn get_or_try_insert_with<F, Fut, E>(&self, key: K, f: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        if let Some(value) = self.get(&key).await {
+            return Ok(value);
+        }
+
+        let computing = Computing::new(&self.inner, &key);
+        let _guard = computing.lock.lock().await;
+
+        // the value might have been computed while we were waiting
+        if let Some(value) = self.inner.backend.get(&key).await {
+            return Ok(value);
+        }
+
+        let value = f().await?;
+        self.inner.backend.insert(key.clone(), value.clone()).await;
+
+        Ok(value)
+    }
+}
+
+/// Lock held while a value is computed. Removed from the map when no one waits for it anymore.
+struct Computing<'a, K: Hash + Eq, V> {
+    inner: &'a Inner<K, V>,
+    key: &'a K,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a, K: Hash + Eq + Clone, V> Computing<'a, K, V> {
+    fn new(inner: &'a Inner<K, V>, key: &'a K) -> Self {
+        let lock = inner.computing.lock().entry(key.clone()).or_default().clone();
+        Self { inner, key, lock }
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for Computing<'_, K, V> {
+    fn drop(&mut self) {
+        let mut computing = self.inner.computing.lock();
+
+        // one reference is held by the map, the other one by us
+        if Arc::strong_count(&self.lock) == 2 {
+            computing.remove(self.key);
+        }
+    }
+}
+
+impl<K, V> Backend<K, V>
+where
+    K: Hash + Eq + Display + Send + Sync + 'static,
+    V: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        match self {
+            Backend::Memory(memory) => memory.get(key).await,
+
+            #[cfg(feature = "redis")]
+            Backend::Redis(redis) => redis.get(key).await,
+        }
+    }
+
+    async fn insert(&self, key: K, value: V) {
+        match self {
+            Backend::Memory(memory) => memory.insert(key, value).await,
+
+            #[cfg(feature = "redis")]
+            Backend::Redis(redis) => redis.insert(&key, &value).await,
+        }
+    }
+
+    async fn remove(&self, key: &K) {
+        match self {
+            Backend::Memory(memory) => memory.cache.invalidate(key).await,
+
+            #[cfg(feature = "redis")]
+            Backend::Redis(redis) => redis.remove(key).await,
+        }
+    }
+}
+
+/// Entries expire on the clock of the cache. The time to live of moka still
+/// removes them in the background, so expired entries do not pile up.
+struct MemoryBackend<K, V> {
+    cache: moka::future::Cache<K, (V, Instant)>,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl<K, V> MemoryBackend<K, V>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        let (value, expires) = self.cache.get(key).await?;
+
+        if expires <= self.clock.instant() {
+            self.cache.invalidate(key).await;
+            return None;
+        }
+
+        Some(value)
+    }
+
+    async fn insert(&self, key: K, value: V) {
+        let expires = self.clock.instant() + self.ttl;
+        self.cache.insert(key, (value, expires)).await
+    }
+}
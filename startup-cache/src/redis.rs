@@ -0,0 +1,81 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use startup_redis::redis::{AsyncCommands, RedisResult};
+use startup_redis::RedisPool;
+use tracing::warn;
+
+pub(crate) struct RedisBackend {
+    pool: RedisPool,
+    prefix: String,
+    ttl: Duration,
+}
+
+impl RedisBackend {
+    pub(crate) fn new(pool: &RedisPool, name: &str, ttl: Duration) -> Self {
+        Self {
+            pool: pool.clone(),
+            prefix: format!("cache:{}:", name),
+            ttl,
+        }
+    }
+
+    pub(crate) async fn get<K: Display, V: DeserializeOwned>(&self, key: &K) -> Option<V> {
+        let key = self.key(key);
+
+        let result: RedisResult<Option<Vec<u8>>> = async { self.pool.get().await?.get(&key).await }.await;
+
+        let payload = match result {
+            Ok(payload) => payload?,
+            Err(err) => {
+                warn!("Failed to get {} from redis: {}", key, err);
+                return None;
+            }
+        };
+
+        // entries written by an older version might not match the type anymore
+        match serde_json::from_slice(&payload) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                warn!("Ignoring cached value of {} that can not be deserialized: {}", key, err);
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn insert<K: Display, V: Serialize>(&self, key: &K, value: &V) {
+        let key = self.key(key);
+
+        let payload = match serde_json::to_vec(value) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Failed to serialize value of {}: {}", key, err);
+                return;
+            }
+        };
+
+        let ttl = self.ttl.as_millis() as u64;
+
+        let result: RedisResult<()> = async { self.pool.get().await?.pset_ex(&key, payload, ttl).await }.await;
+
+        if let Err(err) = result {
+            warn!("Failed to write {} to redis: {}", key, err);
+        }
+    }
+
+    pub(crate) async fn remove<K: Display>(&self, key: &K) {
+        let key = self.key(key);
+
+        let result: RedisResult<()> = async { self.pool.get().await?.del(&key).await }.await;
+
+        if let Err(err) = result {
+            warn!("Failed to remove {} from redis: {}", key, err);
+        }
+    }
+
+    fn key<K: Display>(&self, key: &K) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
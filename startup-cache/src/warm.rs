@@ -0,0 +1,216 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::{global, KeyValue};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use startup_base::{health, warmup};
+use tracing::{info, warn};
+
+use crate::{Cache, Inner};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmConfig {
+    /// Time between two loads after the first one.
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+
+    /// The service is not ready until the first load succeeded, see [warmup]. Otherwise the
+    /// service starts with an empty cache if the first load fails, and it is retried on refresh.
+    #[serde(default = "default_required")]
+    pub required: bool,
+
+    /// The cache is reported as unhealthy component `cache:<name>` to the [health] registry while
+    /// its last successful load is older than this, e.g. if the database is down for too long.
+    /// Checked after every load. Without it, failed loads are only logged and counted, the
+    /// loaded entries are kept.
+    #[serde(default)]
+    pub max_staleness_seconds: Option<u64>,
+}
+
+fn default_refresh_seconds() -> u64 {
+    300
+}
+
+fn default_required() -> bool {
+    true
+}
+
+impl Default for WarmConfig {
+    fn default() -> Self {
+        Self {
+            refresh_seconds: default_refresh_seconds(),
+            required: default_required(),
+            max_staleness_seconds: None,
+        }
+    }
+}
+
+type Load<K, V> = Box<dyn Fn() -> LoadFuture<K, V> + Send + Sync>;
+type LoadFuture<K, V> = std::pin::Pin<Box<dyn Future<Output = eyre::Result<Vec<(K, V)>>> + Send>>;
+
+struct Warming<K, V> {
+    name: String,
+    load: Load<K, V>,
+    max_staleness: Option<Duration>,
+    started: Instant,
+    loaded: Mutex<Option<Instant>>,
+    loads: Counter<u64>,
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: Hash + Eq + Clone + Display + Send + Sync + 'static,
+    V: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Fills the cache with the entries returned by `load`, e.g. reference data from the
+    /// database, before the service is ready, and loads them again every `refresh_seconds`.
+    /// Entries are replaced, not removed, so the ttl of the cache should be longer than the
+    /// refresh interval. Loading stops once the cache is dropped. Must be called within a tokio runtime.
+    ///
+    /// Loads are counted in the `cache.warm.loads` metric by cache and result. The time since
+    /// the last successful load is reported in the `cache.warm.age` gauge, in seconds.
+    ///
+    /// Use like this: `countries.warm(&config.warm, move || { let repository = repository.clone(); async move { repository.countries().await } })`
+    pub fn warm<F, Fut>(&self, config: &WarmConfig, load: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = eyre::Result<Vec<(K, V)>>> + Send + 'static,
+    {
+        let meter = global::meter("startup-cache");
+
+        let loads = meter
+            .u64_counter("cache.warm.loads")
+            .with_description("Loads of warmed caches by result")
+            .init();
+
+        let warming = Arc::new(Warming {
+            name: self.inner.name.clone(),
+            load: Box::new(move || Box::pin(load())),
+            max_staleness: config.max_staleness_seconds.map(Duration::from_secs),
+            started: Instant::now(),
+            loaded: Mutex::new(None),
+            loads,
+        });
+
+        let gauge = meter
+            .f64_observable_gauge("cache.warm.age")
+            .with_description("Time since the last successful load of a warmed cache")
+            .init();
+
+        let observed = Arc::downgrade(&warming);
+
+        let registered = meter.register_callback(move |cx| {
+            if let Some(warming) = observed.upgrade() {
+                let attributes = [KeyValue::new("cache", warming.name.clone())];
+                gauge.observe(cx, warming.age().as_secs_f64(), &attributes);
+            }
+        });
+
+        if let Err(err) = registered {
+            warn!("Failed to register cache age metric: {}", err);
+        }
+
+        let interval = Duration::from_secs(config.refresh_seconds.max(1));
+
+        if config.required {
+            let cache = self.clone();
+            let first = warming.clone();
+
+            warmup::register(&component(&warming.name), async move { first.load(&cache).await });
+        }
+
+        tokio::spawn(refresh(warming, Arc::downgrade(&self.inner), interval, config.required));
+    }
+}
+
+async fn refresh<K, V>(warming: Arc<Warming<K, V>>, inner: Weak<Inner<K, V>>, interval: Duration, loaded: bool)
+where
+    K: Hash + Eq + Clone + Display + Send + Sync + 'static,
+    V: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    // the first load was done by the warm-up
+    if loaded {
+        tokio::time::sleep(interval).await;
+    }
+
+    loop {
+        // stop once the cache is no longer used
+        let Some(inner) = inner.upgrade() else {
+            health::remove(&component(&warming.name));
+            return;
+        };
+
+        // errors are logged, the loaded entries are kept
+        let _ = warming.load(&Cache { inner }).await;
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+impl<K, V> Warming<K, V>
+where
+    K: Hash + Eq + Clone + Display + Send + Sync + 'static,
+    V: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    async fn load(&self, cache: &Cache<K, V>) -> eyre::Result<()> {
+        let result = (self.load)().await;
+
+        let attributes = [
+            KeyValue::new("cache", self.name.clone()),
+            KeyValue::new("result", if result.is_ok() { "ok" } else { "failed" }),
+        ];
+        self.loads.add(&opentelemetry::Context::current(), 1, &attributes);
+
+        let entries = match result {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Failed to load cache {}: {:?}", self.name, err);
+                self.check_staleness();
+                return Err(err);
+            }
+        };
+
+        let count = entries.len();
+
+        for (key, value) in entries {
+            cache.insert(key, value).await;
+        }
+
+        info!("Loaded {} entries into cache {}", count, self.name);
+
+        *self.loaded.lock() = Some(Instant::now());
+        self.check_staleness();
+
+        Ok(())
+    }
+
+    /// Time since the last successful load, or since warming started.
+    fn age(&self) -> Duration {
+        self.loaded.lock().unwrap_or(self.started).elapsed()
+    }
+
+    fn check_staleness(&self) {
+        let Some(max_staleness) = self.max_staleness else {
+            return;
+        };
+
+        let component = component(&self.name);
+        let age = self.age();
+
+        if age > max_staleness {
+            health::set_unhealthy(&component, format!("last loaded {:.0?} ago", age));
+        } else if health::status(&component).is_some() {
+            health::set_healthy(&component);
+        }
+    }
+}
+
+fn component(name: &str) -> String {
+    format!("cache:{}", name)
+}
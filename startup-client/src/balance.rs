@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::{Mutex, RwLock};
+use reqwest::header::{HeaderMap, HeaderValue, HOST};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use trust_dns_resolver::TokioAsyncResolver;
+use url::Url;
+
+use crate::ClientError;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadBalancingConfig {
+    /// Hosts whose requests are balanced across all of their addresses, like the headless
+    /// service `orders.shop.svc.cluster.local`. A host starting with `.` matches all hosts below
+    /// it. A host starting with `_`, like `_http._tcp.orders.shop.svc.cluster.local`, names SRV
+    /// records, its requests are balanced across the addresses and ports of their targets.
+    pub hosts: Vec<String>,
+
+    /// Time between two resolutions of a host.
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+
+    /// Path requested from every endpoint to check its health, like `/health`. Endpoints that do
+    /// not answer with `2xx` are ejected until they do. Without it, endpoints are only ejected
+    /// when a connection to them fails.
+    #[serde(default)]
+    pub health_check_path: Option<String>,
+
+    #[serde(default = "default_health_check_interval_seconds")]
+    pub health_check_interval_seconds: u64,
+
+    /// Time an endpoint is not used after a connection or a health check failed.
+    #[serde(default = "default_eject_seconds")]
+    pub eject_seconds: u64,
+}
+
+fn default_refresh_seconds() -> u64 {
+    30
+}
+
+fn default_health_check_interval_seconds() -> u64 {
+    10
+}
+
+fn default_eject_seconds() -> u64 {
+    30
+}
+
+/// Sends every attempt of a request to a balanced host to the next of its endpoints, see
+/// [LoadBalancingConfig]. The connection pool of the client is per endpoint, so requests
+/// are spread across all endpoints, instead of the one the first connection went to.
+pub(crate) struct LoadBalancer {
+    hosts: Vec<String>,
+    settings: Arc<Settings>,
+    pools: Mutex<HashMap<String, Arc<Pool>>>,
+}
+
+struct Settings {
+    resolver: TokioAsyncResolver,
+    client: reqwest::Client,
+    refresh: Duration,
+    health_check_path: Option<String>,
+    health_check_interval: Duration,
+    eject: Duration,
+    ejections: Counter<u64>,
+}
+
+/// The endpoints of a host and port.
+struct Pool {
+    settings: Arc<Settings>,
+    host: String,
+    port: u16,
+    endpoints: RwLock<Vec<Arc<Endpoint>>>,
+    next: AtomicUsize,
+
+    // a single resolution of a host that was not resolved before
+    resolving: tokio::sync::Mutex<()>,
+}
+
+struct Endpoint {
+    addr: SocketAddr,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+/// The endpoint an attempt was sent to.
+pub(crate) struct Routed {
+    pool: Arc<Pool>,
+    endpoint: Arc<Endpoint>,
+}
+
+impl LoadBalancer {
+    pub(crate) fn new(config: &LoadBalancingConfig, client: reqwest::Client) -> Result<Self, ClientError> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(ClientError::Dns)?;
+
+        let ejections = opentelemetry::global::meter("startup-client")
+            .u64_counter("http.client.ejections")
+            .with_description("Endpoints of balanced hosts that were ejected")
+            .init();
+
+        let settings = Settings {
+            resolver,
+            client,
+            refresh: Duration::from_secs(config.refresh_seconds.max(1)),
+            health_check_path: config.health_check_path.clone(),
+            health_check_interval: Duration::from_secs(config.health_check_interval_seconds.max(1)),
+            eject: Duration::from_secs(config.eject_seconds),
+            ejections,
+        };
+
+        Ok(Self {
+            hosts: config.hosts.iter().map(|host| host.to_ascii_lowercase()).collect(),
+            settings: Arc::new(settings),
+            pools: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Replaces the host of the url with an endpoint, if the host is balanced. The `Host` header
+    /// keeps the name of the host. Urls with `https` are not balanced, tls verifies the name.
+    pub(crate) async fn route(&self, url: &mut Url, headers: &mut HeaderMap) -> Result<Option<Routed>, ClientError> {
+        if url.scheme() != "http" {
+            return Ok(None);
+        }
+
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return Ok(None);
+        };
+
+        if !self.is_balanced(&host) {
+            return Ok(None);
+        }
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        let pool = self.pool(&host, port);
+        let endpoint = pool.pick().await?;
+
+        let authority = match url.port() {
+            _ if is_srv(&host) => srv_name(&host),
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+
+        if let Ok(value) = HeaderValue::try_from(authority) {
+            headers.insert(HOST, value);
+        }
+
+        // the url was http with a host before, so both can be set
+        let _ = url.set_ip_host(endpoint.addr.ip());
+        let _ = url.set_port(Some(endpoint.addr.port()));
+
+        Ok(Some(Routed { pool, endpoint }))
+    }
+
+    fn is_balanced(&self, host: &str) -> bool {
+        self.hosts.iter().any(|candidate| match candidate.starts_with('.') {
+            true => host.ends_with(candidate.as_str()),
+            false => host == candidate,
+        })
+    }
+
+    fn pool(&self, host: &str, port: u16) -> Arc<Pool> {
+        let mut pools = self.pools.lock();
+
+        let pool = pools.entry(format!("{}:{}", host, port)).or_insert_with(|| {
+            let pool = Arc::new(Pool {
+                settings: self.settings.clone(),
+                host: host.to_string(),
+                port,
+                endpoints: RwLock::new(Vec::new()),
+                next: AtomicUsize::new(0),
+                resolving: tokio::sync::Mutex::new(()),
+            });
+
+            tokio::spawn(maintain(Arc::downgrade(&pool), self.settings.clone()));
+
+            pool
+        });
+
+        pool.clone()
+    }
+}
+
+impl Routed {
+    /// Ejects the endpoint after a connection to it failed.
+    pub(crate) fn connect_failed(&self) {
+        self.pool.eject(&self.endpoint, "connect");
+    }
+}
+
+impl Pool {
+    /// The next available endpoint. If all endpoints are ejected, all of them are used.
+    async fn pick(&self) -> Result<Arc<Endpoint>, ClientError> {
+        if self.endpoints.read().is_empty() {
+            let _resolving = self.resolving.lock().await;
+
+            if self.endpoints.read().is_empty() {
+                self.resolve().await.map_err(|source| ClientError::Resolve {
+                    service: self.host.clone(),
+                    source,
+                })?;
+            }
+        }
+
+        let endpoints = self.endpoints.read();
+        let now = Instant::now();
+
+        let available: Vec<_> = endpoints.iter().filter(|endpoint| endpoint.is_available(now)).collect();
+        let candidates = match available.is_empty() {
+            true => endpoints.iter().collect(),
+            false => available,
+        };
+
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+        Ok(candidates[idx].clone())
+    }
+
+    /// Resolves the endpoints of the host. Endpoints that were resolved before keep their state.
+    async fn resolve(&self) -> Result<(), BoxError> {
+        let resolver = &self.settings.resolver;
+
+        let mut addrs = Vec::new();
+
+        if is_srv(&self.host) {
+            let records = resolver.srv_lookup(self.host.as_str()).await?;
+
+            // records with a higher priority value are only meant as fallback
+            let priority = records.iter().map(|record| record.priority()).min();
+
+            for record in records.iter().filter(|record| Some(record.priority()) == priority) {
+                let ips = resolver.lookup_ip(record.target().clone()).await?;
+                addrs.extend(ips.iter().map(|ip| SocketAddr::new(ip, record.port())));
+            }
+        } else {
+            let ips = resolver.lookup_ip(self.host.as_str()).await?;
+            addrs.extend(ips.iter().map(|ip| SocketAddr::new(ip, self.port)));
+        }
+
+        addrs.sort();
+        addrs.dedup();
+
+        if addrs.is_empty() {
+            return Err(format!("{} has no addresses", self.host).into());
+        }
+
+        let mut endpoints = self.endpoints.write();
+
+        let changed = endpoints.len() != addrs.len()
+            || endpoints
+                .iter()
+                .zip(&addrs)
+                .any(|(endpoint, addr)| endpoint.addr != *addr);
+
+        if changed {
+            info!("Resolved {} to {} endpoints: {:?}", self.host, addrs.len(), addrs);
+        }
+
+        *endpoints = addrs
+            .into_iter()
+            .map(|addr| {
+                endpoints
+                    .iter()
+                    .find(|endpoint| endpoint.addr == addr)
+                    .cloned()
+                    .unwrap_or_else(|| Arc::new(Endpoint::new(addr)))
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    async fn check_health(&self, path: &str) {
+        let endpoints = self.endpoints.read().clone();
+        let mut checks = tokio::task::JoinSet::new();
+
+        for endpoint in endpoints {
+            let request = self
+                .settings
+                .client
+                .get(format!("http://{}{}", endpoint.addr, path))
+                .header(HOST, srv_name(&self.host))
+                .timeout(self.settings.health_check_interval)
+                .send();
+
+            checks.spawn(async move {
+                let healthy = matches!(request.await, Ok(response) if response.status().is_success());
+                (endpoint, healthy)
+            });
+        }
+
+        while let Some(Ok((endpoint, healthy))) = checks.join_next().await {
+            match healthy {
+                true => endpoint.restore(&self.host),
+                false => self.eject(&endpoint, "health_check"),
+            }
+        }
+    }
+
+    fn eject(&self, endpoint: &Endpoint, reason: &'static str) {
+        let until = Instant::now() + self.settings.eject;
+
+        let previous = endpoint.ejected_until.lock().replace(until);
+
+        // only count an endpoint that was available before
+        if previous.is_some_and(|previous| previous > Instant::now()) {
+            return;
+        }
+
+        warn!(
+            "Ejecting endpoint {} of {} for {:?} after a failed {}",
+            endpoint.addr, self.host, self.settings.eject, reason
+        );
+
+        let attributes = [
+            KeyValue::new("net.peer.name", self.host.clone()),
+            KeyValue::new("reason", reason),
+        ];
+        self.settings
+            .ejections
+            .add(&opentelemetry::Context::current(), 1, &attributes);
+    }
+}
+
+impl Endpoint {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            ejected_until: Mutex::new(None),
+        }
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        self.ejected_until.lock().is_none_or(|until| until <= now)
+    }
+
+    fn restore(&self, host: &str) {
+        if self
+            .ejected_until
+            .lock()
+            .take()
+            .is_some_and(|until| until > Instant::now())
+        {
+            info!("Endpoint {} of {} is healthy again", self.addr, host);
+        }
+    }
+}
+
+/// Resolves the host again and checks the health of its endpoints, until the pool is dropped.
+async fn maintain(pool: Weak<Pool>, settings: Arc<Settings>) {
+    let start = tokio::time::Instant::now();
+    let mut refresh = tokio::time::interval_at(start + settings.refresh, settings.refresh);
+    let mut health_check = tokio::time::interval_at(start, settings.health_check_interval);
+
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                let Some(pool) = pool.upgrade() else {
+                    return;
+                };
+
+                // keep the known endpoints
+                if let Err(err) = pool.resolve().await {
+                    warn!("Failed to resolve {}: {}", pool.host, err);
+                }
+            }
+
+            _ = health_check.tick(), if settings.health_check_path.is_some() => {
+                let Some(pool) = pool.upgrade() else {
+                    return;
+                };
+
+                if let Some(path) = &settings.health_check_path {
+                    debug!("Checking the health of the endpoints of {}", pool.host);
+                    pool.check_health(path).await;
+                }
+            }
+        }
+    }
+}
+
+fn is_srv(host: &str) -> bool {
+    host.starts_with('_')
+}
+
+/// The name of the service of SRV records, like `orders.shop` for `_http._tcp.orders.shop`.
+fn srv_name(host: &str) -> String {
+    let mut name = host;
+
+    while let Some((label, rest)) = name.split_once('.') {
+        if !label.starts_with('_') {
+            break;
+        }
+
+        name = rest;
+    }
+
+    name.to_string()
+}
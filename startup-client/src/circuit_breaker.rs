@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Stop sending requests to a host after it failed repeatedly.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Number of consecutive failures after which the circuit opens.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// Time the circuit stays open before a single probe request is let through.
+    #[serde(default = "default_open_seconds")]
+    pub open_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            failure_threshold: default_failure_threshold(),
+            open_seconds: default_open_seconds(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_seconds() -> u64 {
+    30
+}
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probe_started: Instant },
+}
+
+/// Tracks the state of the circuit of every host the client talks to. Can be used on its own
+/// to track circuits by any other key, e.g. per endpoint.
+pub struct CircuitBreakers {
+    enabled: bool,
+    failure_threshold: u32,
+    open_duration: Duration,
+    hosts: Mutex<HashMap<String, State>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            failure_threshold: config.failure_threshold.max(1),
+            open_duration: Duration::from_secs(config.open_seconds),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns false if no request must be sent to the host right now.
+    pub fn acquire(&self, host: &str) -> bool {
+        if !self.enabled {
+            return true;
+        }
+
+        let mut hosts = self.hosts.lock();
+        let Some(state) = hosts.get_mut(host) else {
+            return true;
+        };
+
+        let now = Instant::now();
+
+        match *state {
+            State::Closed { .. } => true,
+
+            State::Open { until } if now >= until => {
+                info!("Circuit for {} is half open, sending probe request", host);
+                *state = State::HalfOpen { probe_started: now };
+                true
+            }
+
+            // let another probe through if the last one got lost
+            State::HalfOpen { probe_started } if now >= probe_started + self.open_duration => {
+                *state = State::HalfOpen { probe_started: now };
+                true
+            }
+
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Gives up the request allowed by [CircuitBreakers::acquire] without sending it, e.g.
+    /// because its token could not be fetched. A probe of a half open circuit is let through
+    /// again right away.
+    pub fn release(&self, host: &str) {
+        let mut hosts = self.hosts.lock();
+
+        if let Some(state @ State::HalfOpen { .. }) = hosts.get_mut(host) {
+            *state = State::Open { until: Instant::now() };
+        }
+    }
+
+    /// Records the outcome of a request sent to the host.
+    pub fn record(&self, host: &str, success: bool) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut hosts = self.hosts.lock();
+
+        if success {
+            let previous = hosts.insert(host.to_string(), State::Closed { failures: 0 });
+            if matches!(previous, Some(State::Open { .. } | State::HalfOpen { .. })) {
+                info!("Circuit for {} is closed again", host);
+            }
+
+            return;
+        }
+
+        let state = hosts.entry(host.to_string()).or_insert(State::Closed { failures: 0 });
+
+        let open = match state {
+            State::Closed { failures } => {
+                *failures += 1;
+                *failures >= self.failure_threshold
+            }
+
+            State::HalfOpen { .. } => true,
+            State::Open { .. } => false,
+        };
+
+        if open {
+            warn!("Circuit for {} is open for {:?}", host, self.open_duration);
+            *state = State::Open {
+                until: Instant::now() + self.open_duration,
+            };
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, AGE, CACHE_CONTROL, ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use startup_base::retry::RetryPolicy;
+use tracing::{debug, warn};
+
+use crate::{Client, ClientError};
+
+/// A json document fetched from a url, like the keys of a jwks endpoint, an OpenID Connect
+/// discovery document or a feature flag document, cached as long as the `Cache-Control` or
+/// `Expires` header of the response allows.
+///
+/// Once the document is stale, it is revalidated using its `ETag` or `Last-Modified`. Within
+/// the `stale-while-revalidate` time of the response, the stale document is returned right away
+/// and revalidated in the background. Within the `stale-if-error` time, the stale document is
+/// returned if fetching it failed. Concurrent callers share a single fetch.
+///
+/// Fetches are counted in the `http.client.document.fetches` metric by url and outcome,
+/// which is `updated`, `not_modified` or `failed`.
+///
+/// Use like this: `let keys: Arc<JwkSet> = CachedDocument::new(&client, &config.jwk_url).get().await?`
+pub struct CachedDocument<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    client: Client,
+    url: String,
+    retry: Option<RetryPolicy>,
+    default_ttl: Duration,
+    stale_while_revalidate: Duration,
+    stale_if_error: Duration,
+    entry: Mutex<Option<Entry<T>>>,
+
+    // a single fetch at a time, callers waiting for it use its result
+    fetching: tokio::sync::Mutex<()>,
+    revalidating: AtomicBool,
+    fetches: Counter<u64>,
+}
+
+struct Entry<T> {
+    value: Arc<T>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched: Instant,
+    fresh_until: Instant,
+    revalidate_until: Instant,
+    error_until: Instant,
+}
+
+impl<T> Clone for CachedDocument<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> CachedDocument<T>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn new(client: &Client, url: &str) -> Self {
+        let fetches = opentelemetry::global::meter("startup-client")
+            .u64_counter("http.client.document.fetches")
+            .with_description("Fetches of cached documents")
+            .init();
+
+        let inner = Inner {
+            client: client.clone(),
+            url: url.to_string(),
+            retry: None,
+            default_ttl: Duration::from_secs(5 * 60),
+            stale_while_revalidate: Duration::from_secs(60),
+            stale_if_error: Duration::from_secs(60 * 60),
+            entry: Mutex::new(None),
+            fetching: tokio::sync::Mutex::new(()),
+            revalidating: AtomicBool::new(false),
+            fetches,
+        };
+
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// How long the document is fresh if the response has no `max-age` or `Expires`, defaults to 5 minutes.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.inner_mut().default_ttl = ttl;
+        self
+    }
+
+    /// Overrides the retries configured for the client for fetching the document.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.inner_mut().retry = Some(retry);
+        self
+    }
+
+    /// Used if the response has no `stale-while-revalidate`, defaults to 1 minute.
+    pub fn stale_while_revalidate(mut self, duration: Duration) -> Self {
+        self.inner_mut().stale_while_revalidate = duration;
+        self
+    }
+
+    /// Used if the response has no `stale-if-error`, defaults to 1 hour.
+    pub fn stale_if_error(mut self, duration: Duration) -> Self {
+        self.inner_mut().stale_if_error = duration;
+        self
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner<T> {
+        Arc::get_mut(&mut self.inner).expect("configure the document before cloning it")
+    }
+
+    /// Returns the document, fetching it if there is no usable copy.
+    pub async fn get(&self) -> Result<Arc<T>, ClientError> {
+        let now = Instant::now();
+
+        if let Some((value, fresh)) = self.inner.cached(now, |entry| entry.revalidate_until) {
+            if !fresh {
+                self.revalidate_in_background();
+            }
+
+            return Ok(value);
+        }
+
+        self.refresh_if_stale().await
+    }
+
+    /// Fetches the document, even if it is fresh, e.g. if a jwt was signed with an unknown key.
+    /// Callers refreshing concurrently share a single fetch.
+    pub async fn refresh(&self) -> Result<Arc<T>, ClientError> {
+        let started = Instant::now();
+        let _fetching = self.inner.fetching.lock().await;
+
+        // fetched by another caller while waiting for the lock
+        if let Some(value) = self.inner.fetched_since(started) {
+            return Ok(value);
+        }
+
+        self.inner.fetch_or_stale().await
+    }
+
+    async fn refresh_if_stale(&self) -> Result<Arc<T>, ClientError> {
+        let _fetching = self.inner.fetching.lock().await;
+
+        if let Some((value, true)) = self.inner.cached(Instant::now(), |entry| entry.fresh_until) {
+            return Ok(value);
+        }
+
+        self.inner.fetch_or_stale().await
+    }
+
+    fn revalidate_in_background(&self) {
+        if self.inner.revalidating.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let document = self.clone();
+
+        tokio::spawn(async move {
+            // errors are logged, the stale document is used until it expires
+            let _ = document.refresh_if_stale().await;
+            document.inner.revalidating.store(false, Ordering::Release);
+        });
+    }
+}
+
+impl<T: DeserializeOwned> Inner<T> {
+    /// The cached value if it is usable until the deadline, and whether it is fresh.
+    fn cached(&self, now: Instant, usable_until: impl Fn(&Entry<T>) -> Instant) -> Option<(Arc<T>, bool)> {
+        let entry = self.entry.lock();
+        let entry = entry.as_ref().filter(|entry| now < usable_until(entry))?;
+
+        Some((entry.value.clone(), now < entry.fresh_until))
+    }
+
+    fn fetched_since(&self, instant: Instant) -> Option<Arc<T>> {
+        let entry = self.entry.lock();
+        let entry = entry.as_ref().filter(|entry| entry.fetched >= instant)?;
+
+        Some(entry.value.clone())
+    }
+
+    /// Fetches the document. If that fails, the stale document is used within `stale-if-error`.
+    async fn fetch_or_stale(&self) -> Result<Arc<T>, ClientError> {
+        let err = match self.fetch().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        match self.cached(Instant::now(), |entry| entry.error_until) {
+            Some((value, _)) => {
+                warn!("Failed to fetch {}, using the stale document: {:?}", self.url, err);
+                Ok(value)
+            }
+
+            None => Err(err),
+        }
+    }
+
+    async fn fetch(&self) -> Result<Arc<T>, ClientError> {
+        let result = self.send().await;
+
+        let outcome = match &result {
+            Ok((_, true)) => "updated",
+            Ok((_, false)) => "not_modified",
+            Err(_) => "failed",
+        };
+
+        let labels = [
+            KeyValue::new("url", self.url.clone()),
+            KeyValue::new("outcome", outcome),
+        ];
+        self.fetches.add(&opentelemetry::Context::current(), 1, &labels);
+
+        result.map(|(value, _)| value)
+    }
+
+    /// Sends a conditional request if the document was fetched before.
+    /// Returns the document and whether it changed.
+    async fn send(&self) -> Result<(Arc<T>, bool), ClientError> {
+        let mut request = self.client.get(&self.url).route(self.url.clone());
+
+        if let Some(retry) = &self.retry {
+            request = request.retry(retry.clone());
+        }
+
+        let previous = self
+            .entry
+            .lock()
+            .as_ref()
+            .map(|entry| (entry.value.clone(), entry.etag.clone(), entry.last_modified.clone()));
+
+        if let Some((_, etag, last_modified)) = &previous {
+            if let Some(etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+
+            if let Some(last_modified) = last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+
+        let response = request.send().await?;
+        let headers = response.headers().clone();
+
+        let (value, modified) = match (response.status(), previous) {
+            (StatusCode::NOT_MODIFIED, Some((value, ..))) => {
+                debug!("Document {} was not modified", self.url);
+                (value, false)
+            }
+
+            (StatusCode::NOT_MODIFIED, None) => {
+                return Err(ClientError::Status {
+                    url: self.url.clone(),
+                    status: StatusCode::NOT_MODIFIED,
+                    body: String::new(),
+                });
+            }
+
+            _ => {
+                let value = response.json::<T>().await.map_err(|source| ClientError::Request {
+                    url: self.url.clone(),
+                    source,
+                })?;
+
+                (Arc::new(value), true)
+            }
+        };
+
+        *self.entry.lock() = Some(self.entry(value.clone(), &headers));
+
+        Ok((value, modified))
+    }
+
+    fn entry(&self, value: Arc<T>, headers: &HeaderMap) -> Entry<T> {
+        let policy = CachePolicy::of(headers);
+
+        let ttl = match policy.no_cache {
+            true => Duration::ZERO,
+            false => policy.max_age.unwrap_or(self.default_ttl),
+        };
+
+        let now = Instant::now();
+        let fresh_until = now + ttl;
+
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+
+        Entry {
+            value,
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            fetched: now,
+            fresh_until,
+            revalidate_until: fresh_until + policy.stale_while_revalidate.unwrap_or(self.stale_while_revalidate),
+            error_until: fresh_until + policy.stale_if_error.unwrap_or(self.stale_if_error),
+        }
+    }
+}
+
+/// The caching directives of a response.
+#[derive(Debug, Default)]
+struct CachePolicy {
+    no_cache: bool,
+
+    /// Remaining freshness, the `Age` of the response already subtracted from `max-age`.
+    max_age: Option<Duration>,
+
+    stale_while_revalidate: Option<Duration>,
+    stale_if_error: Option<Duration>,
+}
+
+impl CachePolicy {
+    fn of(headers: &HeaderMap) -> Self {
+        let mut policy = CachePolicy::default();
+
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_ascii_lowercase());
+
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim().to_string(), value.trim().trim_matches('"').parse().ok()),
+                None => (directive, None),
+            };
+
+            let seconds = value.map(Duration::from_secs);
+
+            match name.as_str() {
+                "no-cache" | "no-store" => policy.no_cache = true,
+                "max-age" => policy.max_age = seconds,
+                "stale-while-revalidate" => policy.stale_while_revalidate = seconds,
+                "stale-if-error" => policy.stale_if_error = seconds,
+                _ => {}
+            }
+        }
+
+        let age = headers
+            .get(AGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+
+        policy.max_age = policy.max_age.map(|max_age| max_age.saturating_sub(age));
+
+        if policy.max_age.is_none() {
+            policy.max_age = headers
+                .get(EXPIRES)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| httpdate::parse_http_date(value).ok())
+                // an invalid date like `0` means already expired
+                .map(|expires| expires.duration_since(SystemTime::now()).unwrap_or_default())
+                .or_else(|| headers.get(EXPIRES).map(|_| Duration::ZERO));
+        }
+
+        policy
+    }
+}
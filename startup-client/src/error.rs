@@ -0,0 +1,94 @@
+use reqwest::StatusCode;
+use startup_base::retry::Failure;
+use startup_http::WebError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("failed to build http client")]
+    Build(#[source] reqwest::Error),
+
+    #[error("failed to read certificate {0:?}")]
+    Certificate(String, #[source] std::io::Error),
+
+    #[error("invalid url {0:?}")]
+    InvalidUrl(String, #[source] url::ParseError),
+
+    #[error("request to {url} failed")]
+    Request {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("request to {url} failed with status {status}: {body}")]
+    Status {
+        url: String,
+        status: StatusCode,
+        body: String,
+    },
+
+    #[error("failed to fetch access token")]
+    Token(#[source] Box<ClientError>),
+
+    #[error("access token contains characters that are not allowed in a header")]
+    InvalidToken,
+
+    #[error("circuit for {host} is open, request to {url} was not sent")]
+    CircuitOpen { host: String, url: String },
+
+    #[cfg(feature = "signing")]
+    #[error("failed to sign request")]
+    Signing(#[source] startup_http::signing::SigningError),
+
+    #[error("failed to read the dns configuration of the system")]
+    Dns(#[source] trust_dns_resolver::error::ResolveError),
+
+    #[error("failed to resolve an instance of service {service}")]
+    Resolve {
+        service: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl ClientError {
+    /// The status code of the upstream response, if the request failed because of it.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ClientError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Classifies the error for a [RetryPolicy](startup_base::retry::RetryPolicy), e.g. to retry
+    /// a request later on. Errors of the request itself, like an invalid url, are rejected.
+    pub fn failure(&self) -> Failure {
+        match self {
+            ClientError::Request { source, .. } => crate::retry::failure_of(source),
+            ClientError::Status { status, .. } => Failure::from_status(status.as_u16()).unwrap_or(Failure::Rejected),
+            ClientError::CircuitOpen { .. } | ClientError::Resolve { .. } => Failure::Unavailable,
+            ClientError::Token(err) => err.failure(),
+            _ => Failure::Rejected,
+        }
+    }
+
+    /// The status code to answer with if a request to another service failed.
+    /// Timeouts map to `504 Gateway Timeout`, an open circuit or a service without instances
+    /// maps to `503 Service Unavailable` and every other failure of the upstream service maps
+    /// to `502 Bad Gateway`.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ClientError::Request { source, .. } if source.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            ClientError::CircuitOpen { .. } | ClientError::Resolve { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ClientError::Request { .. } | ClientError::Status { .. } | ClientError::Token(_) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Converts this error into a [WebError] using [ClientError::status_code].
+    ///
+    /// Use like this: `client.get("/users").send().await.map_err(ClientError::into_web_error)?`
+    pub fn into_web_error(self) -> WebError {
+        WebError::WithStatusCode(self.status_code(), self.into())
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use reqwest::header::{AUTHORIZATION, COOKIE, HOST, PROXY_AUTHORIZATION};
+use reqwest::Request;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Number of latencies per host used to calculate the percentile.
+const WINDOW: usize = 1000;
+
+/// No backup requests are sent before this many latencies were recorded for a host.
+const MIN_SAMPLES: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HedgingConfig {
+    /// Send a backup request if a request with an idempotent method takes unusually long.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// The backup request is sent once the first request takes longer than this
+    /// percentile of the recent latencies of the host.
+    #[serde(default = "default_percentile")]
+    pub percentile: f64,
+
+    /// Never send a backup request earlier than this.
+    #[serde(default = "default_min_delay_millis")]
+    pub min_delay_millis: u64,
+
+    /// Send the backup request to this url instead of the original host,
+    /// e.g. to a replica in another zone. Only scheme, host and port are used.
+    /// Credentials like `Authorization` and `Cookie` are not sent to another host.
+    #[serde(default)]
+    pub alternate_url: Option<Url>,
+}
+
+impl Default for HedgingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            percentile: default_percentile(),
+            min_delay_millis: default_min_delay_millis(),
+            alternate_url: None,
+        }
+    }
+}
+
+fn default_percentile() -> f64 {
+    0.95
+}
+
+fn default_min_delay_millis() -> u64 {
+    10
+}
+
+/// Tracks the latencies of every host to decide when to send a backup request.
+pub(crate) struct Hedging {
+    config: HedgingConfig,
+    latencies: Mutex<HashMap<String, VecDeque<Duration>>>,
+}
+
+impl Hedging {
+    pub fn new(config: &HedgingConfig) -> Self {
+        Self {
+            config: config.clone(),
+            latencies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns how long to wait for a response before sending a backup request,
+    /// or None if no backup request should be sent.
+    pub fn delay(&self, host: &str) -> Option<Duration> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let latencies = self.latencies.lock();
+        let latencies = latencies.get(host).filter(|latencies| latencies.len() >= MIN_SAMPLES)?;
+
+        let mut sorted: Vec<_> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+
+        let percentile = self.config.percentile.clamp(0.0, 1.0);
+        let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+
+        Some(sorted[index].max(Duration::from_millis(self.config.min_delay_millis)))
+    }
+
+    pub fn record(&self, host: &str, latency: Duration) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let mut latencies = self.latencies.lock();
+        let latencies = latencies.entry(host.to_string()).or_default();
+
+        if latencies.len() >= WINDOW {
+            latencies.pop_front();
+        }
+
+        latencies.push_back(latency);
+    }
+
+    /// Returns true if backup requests are sent to the alternate url instead of being routed
+    /// like the original request.
+    pub fn has_alternate_url(&self) -> bool {
+        self.config.alternate_url.is_some()
+    }
+
+    /// Creates the backup of the given request, or None if the request can not be cloned.
+    pub fn backup(&self, request: &Request) -> Option<Request> {
+        let mut backup = request.try_clone()?;
+
+        if let Some(alternate) = &self.config.alternate_url {
+            let url = backup.url_mut();
+            let origin = url.origin();
+
+            url.set_scheme(alternate.scheme()).ok()?;
+            url.set_host(alternate.host_str()).ok()?;
+            url.set_port(alternate.port()).ok()?;
+
+            if url.origin() != origin {
+                // the credentials are meant for the original host only
+                for name in [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, HOST] {
+                    backup.headers_mut().remove(name);
+                }
+            }
+        }
+
+        Some(backup)
+    }
+}
@@ -0,0 +1,353 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use reqwest::{Certificate, Method, Proxy};
+use serde::{Deserialize, Serialize};
+use startup_base::retry::RetryPolicy;
+#[cfg(feature = "signing")]
+use startup_http::signing::{RequestSigner, RequestSigningConfig};
+use url::Url;
+
+use crate::balance::LoadBalancer;
+pub use crate::balance::LoadBalancingConfig;
+pub use crate::circuit_breaker::{CircuitBreakerConfig, CircuitBreakers};
+pub use crate::document::CachedDocument;
+pub use crate::error::ClientError;
+use crate::hedge::Hedging;
+pub use crate::hedge::HedgingConfig;
+pub use crate::request::RequestBuilder;
+pub use crate::resolve::{register_resolver, Resolver};
+pub use crate::token::{ClientCredentials, OAuth2Config, TokenProvider};
+
+mod balance;
+mod circuit_breaker;
+mod document;
+mod error;
+mod hedge;
+mod request;
+mod resolve;
+mod retry;
+mod token;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientConfig {
+    /// Relative paths passed to the [Client] are resolved against this url.
+    #[serde(default)]
+    pub base_url: Option<Url>,
+
+    /// Timeout for establishing a connection.
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+
+    /// Timeout for the complete request, including reading the response body.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// Url of a proxy to send all requests through, e.g. `http://proxy:3128`.
+    #[serde(default)]
+    pub proxy: Option<String>,
+
+    /// Path to an additional PEM encoded root certificate to trust.
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+
+    /// Accept invalid tls certificates. Never enable this in production.
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+
+    /// Value of the `User-Agent` header.
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+
+    /// Retries of failed requests with idempotent methods.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+
+    /// Per host circuit breaker.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// Backup requests for slow responses.
+    #[serde(default)]
+    pub hedging: HedgingConfig,
+
+    /// Balance requests to internal hosts across all of their addresses.
+    #[serde(default)]
+    pub load_balancing: Option<LoadBalancingConfig>,
+
+    /// Fetch access tokens using the OAuth2 client credentials grant.
+    #[serde(default)]
+    pub oauth2: Option<OAuth2Config>,
+
+    /// Hosts that receive a bearer token from the token provider.
+    /// Defaults to the host of the `base_url`.
+    #[serde(default)]
+    pub token_hosts: Vec<String>,
+
+    /// Sign every request with the key for the called service, see [RequestSigner].
+    #[cfg(feature = "signing")]
+    #[serde(default)]
+    pub signing: Option<RequestSigningConfig>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            base_url: None,
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+            timeout_seconds: default_timeout_seconds(),
+            proxy: None,
+            ca_certificate: None,
+            accept_invalid_certs: false,
+            user_agent: default_user_agent(),
+            retry: RetryPolicy::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            hedging: HedgingConfig::default(),
+            load_balancing: None,
+            oauth2: None,
+            token_hosts: Vec::new(),
+            #[cfg(feature = "signing")]
+            signing: None,
+        }
+    }
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    5
+}
+
+fn default_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_user_agent() -> String {
+    concat!("startup-client/", env!("CARGO_PKG_VERSION")).to_string()
+}
+
+/// A http client to call other services. Every request is traced as a client span,
+/// propagates the current trace context and is logged. Failed requests and responses
+/// with an error status are returned as [ClientError].
+///
+/// Requests with idempotent methods are retried on connection errors, timeouts and the status
+/// codes `429`, `502`, `503` and `504`, see [RetryPolicy]. After repeated failures, the circuit
+/// to the host opens and requests fail fast, see [CircuitBreakerConfig]. Every attempt is
+/// recorded as an event of the client span and counted in the `http.client.attempts` metric.
+///
+/// The metrics `http.client.requests` and `http.client.duration` record every request per host,
+/// method and route template, see [RequestBuilder::route].
+///
+/// If enabled, a backup request is sent for idempotent requests that take longer than usual,
+/// see [HedgingConfig]. Backup requests are counted in the `http.client.hedged` metric.
+///
+/// Requests to the configured `token_hosts` carry a bearer token from the [TokenProvider],
+/// unless they already have an `Authorization` header. If such a request is answered with
+/// `401 Unauthorized`, a fresh token is fetched and the request is sent once more.
+///
+/// Requests to the hosts of [LoadBalancingConfig] are balanced across all addresses of the host,
+/// which are resolved again periodically. Endpoints that refuse connections or fail their health
+/// check are ejected for a while.
+///
+/// With the `signing` feature and a `signing` config, every attempt is signed, see
+/// `startup_http::signing::RequestSigner`.
+///
+/// Urls with a scheme that has a registered [Resolver], e.g. `consul://orders/orders/1`, are
+/// sent to an instance of the service returned by the resolver. Metrics and the circuit
+/// breaker use the name of the service as the host.
+///
+/// Use like this: `let user: User = client.get("/users/1").send_json().await?`
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+pub(crate) struct Inner {
+    client: reqwest::Client,
+    base_url: Option<Url>,
+    retry: RetryPolicy,
+    circuit_breakers: CircuitBreakers,
+    hedging: Hedging,
+    balancer: Option<LoadBalancer>,
+    attempts: Counter<u64>,
+    requests: Counter<u64>,
+    duration: Histogram<f64>,
+    hedged: Counter<u64>,
+    token_provider: Option<Box<dyn TokenProvider>>,
+    token_hosts: Vec<String>,
+    #[cfg(feature = "signing")]
+    signer: Option<RequestSigner>,
+}
+
+impl Client {
+    /// Creates a client. If `oauth2` is configured, tokens are fetched using [ClientCredentials].
+    pub fn new(config: &ClientConfig) -> Result<Self, ClientError> {
+        Self::from_reqwest(config, Self::build(config)?)
+    }
+
+    /// Creates a client that uses the given token provider, e.g. to sign its own tokens.
+    pub fn with_token_provider(
+        config: &ClientConfig,
+        provider: impl TokenProvider + 'static,
+    ) -> Result<Self, ClientError> {
+        Self::with_reqwest(config, Self::build(config)?, Some(Box::new(provider)))
+    }
+
+    /// Creates a client that sends the requests with the given reqwest client, e.g. one shared
+    /// with other libraries. The timeouts, proxy and certificates of the config are not used.
+    pub fn from_reqwest(config: &ClientConfig, client: reqwest::Client) -> Result<Self, ClientError> {
+        let Some(oauth2) = &config.oauth2 else {
+            return Self::with_reqwest(config, client, None);
+        };
+
+        let provider = ClientCredentials::new(client.clone(), oauth2.clone());
+        Self::with_reqwest(config, client, Some(Box::new(provider)))
+    }
+
+    /// Builds the reqwest client with the timeouts, proxy and certificates of the config.
+    fn build(config: &ClientConfig) -> Result<reqwest::Client, ClientError> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .user_agent(config.user_agent.as_str())
+            .danger_accept_invalid_certs(config.accept_invalid_certs);
+
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(Proxy::all(proxy.as_str()).map_err(ClientError::Build)?);
+        }
+
+        if let Some(path) = &config.ca_certificate {
+            let display = path.display().to_string();
+            let pem = std::fs::read(path).map_err(|err| ClientError::Certificate(display, err))?;
+            builder = builder.add_root_certificate(Certificate::from_pem(&pem).map_err(ClientError::Build)?);
+        }
+
+        builder.build().map_err(ClientError::Build)
+    }
+
+    fn with_reqwest(
+        config: &ClientConfig,
+        client: reqwest::Client,
+        token_provider: Option<Box<dyn TokenProvider>>,
+    ) -> Result<Self, ClientError> {
+        let token_hosts = match &config.token_hosts {
+            hosts if hosts.is_empty() => config
+                .base_url
+                .iter()
+                .filter_map(Url::host_str)
+                .map(String::from)
+                .collect(),
+            hosts => hosts.clone(),
+        };
+
+        let meter = opentelemetry::global::meter("startup-client");
+
+        let attempts = meter
+            .u64_counter("http.client.attempts")
+            .with_description("Attempts to send a http request")
+            .init();
+
+        let requests = meter
+            .u64_counter("http.client.requests")
+            .with_description("Http requests including all retries")
+            .init();
+
+        let duration = meter
+            .f64_histogram("http.client.duration")
+            .with_description("Duration of http requests including all retries")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        let hedged = meter
+            .u64_counter("http.client.hedged")
+            .with_description("Backup requests sent for slow responses")
+            .init();
+
+        let balancer = match &config.load_balancing {
+            Some(load_balancing) => Some(LoadBalancer::new(load_balancing, client.clone())?),
+            None => None,
+        };
+
+        let inner = Inner {
+            client,
+            base_url: config.base_url.clone(),
+            retry: config.retry.clone(),
+            circuit_breakers: CircuitBreakers::new(&config.circuit_breaker),
+            hedging: Hedging::new(&config.hedging),
+            balancer,
+            attempts,
+            requests,
+            duration,
+            hedged,
+            token_provider,
+            token_hosts,
+            #[cfg(feature = "signing")]
+            signer: config
+                .signing
+                .as_ref()
+                .map(RequestSigner::new)
+                .transpose()
+                .map_err(ClientError::Signing)?,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn patch(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PATCH, path)
+    }
+
+    pub fn delete(&self, path: &str) -> RequestBuilder {
+        self.request(Method::DELETE, path)
+    }
+
+    /// Starts a request to the given path. An absolute url is used as is, everything
+    /// else is appended to the configured base url.
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = self.url(path);
+        RequestBuilder::new(self.inner.clone(), method, url)
+    }
+
+    fn url(&self, path: &str) -> Result<Url, ClientError> {
+        let url = match &self.inner.base_url {
+            Some(base_url) if !path.contains("://") => format!(
+                "{}/{}",
+                base_url.as_str().trim_end_matches('/'),
+                path.trim_start_matches('/')
+            ),
+
+            _ => path.to_string(),
+        };
+
+        Url::parse(&url).map_err(|err| ClientError::InvalidUrl(url, err))
+    }
+}
+
+impl Inner {
+    /// Returns the token provider if requests to the given url should carry a token.
+    fn token_provider_for(&self, url: &Url) -> Option<&dyn TokenProvider> {
+        let host = url.host_str()?;
+
+        if !self
+            .token_hosts
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(host))
+        {
+            return None;
+        }
+
+        self.token_provider.as_deref()
+    }
+}
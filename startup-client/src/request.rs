@@ -0,0 +1,556 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderInjector;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use reqwest::{Body, Method, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use startup_base::retry::{Failure, RetryPolicy};
+#[cfg(feature = "signing")]
+use startup_http::signing::{RequestSigner, SigningError};
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use url::Url;
+
+use crate::balance::Routed;
+use crate::resolve;
+use crate::resolve::Resolver;
+use crate::retry::{failure_of, is_idempotent, retry_after};
+use crate::{ClientError, Inner};
+
+/// A request created by a [Client](crate::Client). Call [RequestBuilder::send]
+/// or [RequestBuilder::send_json] to execute it.
+pub struct RequestBuilder {
+    client: Arc<Inner>,
+    method: Method,
+    url: String,
+    route: Option<String>,
+    retry: Option<RetryPolicy>,
+    builder: Result<reqwest::RequestBuilder, ClientError>,
+}
+
+impl RequestBuilder {
+    pub(crate) fn new(client: Arc<Inner>, method: Method, url: Result<Url, ClientError>) -> Self {
+        let (url, builder) = match url {
+            Ok(url) => (url.to_string(), Ok(client.client.request(method.clone(), url))),
+            Err(err) => (String::new(), Err(err)),
+        };
+
+        Self {
+            client,
+            method,
+            url,
+            route: None,
+            retry: None,
+            builder,
+        }
+    }
+
+    fn map(mut self, f: impl FnOnce(reqwest::RequestBuilder) -> reqwest::RequestBuilder) -> Self {
+        self.builder = self.builder.map(f);
+        self
+    }
+
+    pub fn header<K, V>(self, name: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.map(|builder| builder.header(name, value))
+    }
+
+    pub fn headers(self, headers: HeaderMap) -> Self {
+        self.map(|builder| builder.headers(headers))
+    }
+
+    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> Self {
+        self.map(|builder| builder.query(query))
+    }
+
+    pub fn json<T: Serialize + ?Sized>(self, json: &T) -> Self {
+        self.map(|builder| builder.json(json))
+    }
+
+    pub fn form<T: Serialize + ?Sized>(self, form: &T) -> Self {
+        self.map(|builder| builder.form(form))
+    }
+
+    pub fn body(self, body: impl Into<Body>) -> Self {
+        self.map(|builder| builder.body(body))
+    }
+
+    pub fn bearer_auth(self, token: impl Display) -> Self {
+        self.map(|builder| builder.bearer_auth(token))
+    }
+
+    pub fn basic_auth(self, username: impl Display, password: Option<impl Display>) -> Self {
+        self.map(|builder| builder.basic_auth(username, password))
+    }
+
+    /// Sets the route template used to label the metrics of this request, e.g. `/users/{id}`.
+    /// Without a route template, requests are labeled with the route `unknown`.
+    pub fn route(mut self, route: impl Into<String>) -> Self {
+        self.route = Some(route.into());
+        self
+    }
+
+    /// Overrides the timeout configured for the client for this request.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.map(|builder| builder.timeout(timeout))
+    }
+
+    /// Overrides the retries configured for the client for this request.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Sends the request. Responses with a status other than `2xx` are returned as
+    /// [ClientError::Status], including the response body. `304 Not Modified` is returned
+    /// as response to conditional requests, with an `If-None-Match` or `If-Modified-Since` header.
+    ///
+    /// Requests with an idempotent method are retried as configured in [RetryPolicy], see [RequestBuilder::retry].
+    /// Requests with a streaming body can not be retried.
+    pub async fn send(self) -> Result<Response, ClientError> {
+        let url = self.url;
+
+        let mut request = self.builder?.build().map_err(|source| ClientError::Request {
+            url: url.clone(),
+            source,
+        })?;
+
+        let host = match (request.url().host_str(), request.url().port_or_known_default()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (host, _) => host.unwrap_or_default().to_string(),
+        };
+
+        let route = self.route.as_deref().unwrap_or("unknown");
+
+        let span = info_span!(
+            "http_client",
+            otel.name = %format!("{} {}", self.method, host),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            http.method = %self.method,
+            http.url = %url,
+            http.route = %route,
+            http.status_code = Empty,
+        );
+
+        // propagate the trace context of the client span to the called service
+        let context = span.context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut HeaderInjector(request.headers_mut()));
+        });
+
+        let started = Instant::now();
+        let retry = self.retry.as_ref().unwrap_or(&self.client.retry);
+        let result = execute(&self.client, retry, route, &host, url, request, &span).await;
+        self.client
+            .record(&self.method, route, &host, started.elapsed(), &result);
+
+        result
+    }
+
+    /// Sends the request and deserializes the json response body.
+    pub async fn send_json<T: DeserializeOwned>(self) -> Result<T, ClientError> {
+        let url = self.url.clone();
+        let response = self.send().await?;
+
+        response
+            .json()
+            .await
+            .map_err(|source| ClientError::Request { url, source })
+    }
+}
+
+/// Sends the request, retrying it if allowed.
+async fn execute(
+    client: &Inner,
+    retry: &RetryPolicy,
+    route: &str,
+    host: &str,
+    url: String,
+    mut request: Request,
+    span: &Span,
+) -> Result<Response, ClientError> {
+    let method = &request.method().clone();
+
+    // only the caller of a conditional request expects a 304 without a body
+    let conditional =
+        request.headers().contains_key(IF_NONE_MATCH) || request.headers().contains_key(IF_MODIFIED_SINCE);
+
+    let max_attempts = if is_idempotent(method) {
+        retry.max_attempts.max(1)
+    } else {
+        1
+    };
+
+    // never replace credentials set explicitly for this request
+    let token_provider = match request.headers().contains_key(AUTHORIZATION) {
+        true => None,
+        false => client.token_provider_for(request.url()),
+    };
+
+    // the url names a service, every attempt is sent to the instance resolved for it
+    let logical = request.url().clone();
+    let resolver = resolve::resolver_for(&logical);
+
+    let started = Instant::now();
+    let mut attempt = 1;
+    let mut token_refreshed = false;
+
+    let result = loop {
+        if !client.circuit_breakers.acquire(host) {
+            client.count(method, route, host, "rejected");
+            span.record("otel.status_code", "ERROR");
+            warn!(parent: span, "Circuit for {} is open, not sending {} {}", host, method, url);
+            return Err(ClientError::CircuitOpen {
+                host: host.to_string(),
+                url,
+            });
+        }
+
+        let prepared = async {
+            let routed = route_attempt(client, resolver.as_deref(), &logical, &mut request).await?;
+
+            let token = match token_provider {
+                Some(provider) => Some(provider.token().await?),
+                None => None,
+            };
+
+            if let Some(token) = &token {
+                let mut value =
+                    HeaderValue::try_from(format!("Bearer {}", token)).map_err(|_| ClientError::InvalidToken)?;
+
+                value.set_sensitive(true);
+                request.headers_mut().insert(AUTHORIZATION, value);
+            }
+
+            // every attempt gets a fresh timestamp
+            #[cfg(feature = "signing")]
+            if let Some(signer) = &client.signer {
+                sign(signer, &mut request)?;
+            }
+
+            Ok::<_, ClientError>((routed, token))
+        };
+
+        let (routed, token) = match prepared.await {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                // the request was never sent, so it says nothing about the host
+                client.circuit_breakers.release(host);
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: span, "{} {} failed: {}", method, url, err);
+                return Err(err);
+            }
+        };
+
+        let mut next_request = if attempt < max_attempts || (token.is_some() && !token_refreshed) {
+            request.try_clone()
+        } else {
+            None
+        };
+
+        let attempt_started = Instant::now();
+        let hedge = Hedge {
+            resolver: resolver.as_deref(),
+            logical: &logical,
+        };
+
+        let result = client
+            .send_attempt(request, host, is_idempotent(method), hedge, span)
+            .await;
+        let elapsed = attempt_started.elapsed();
+
+        if let (Err(err), Some(routed)) = (&result, &routed) {
+            if err.is_connect() {
+                routed.connect_failed();
+            }
+        }
+
+        if result.is_ok() {
+            client.hedging.record(host, elapsed);
+        }
+
+        // the token might have expired early or the signing key might have been rotated
+        if let (Ok(response), Some(provider), Some(token)) = (&result, token_provider, &token) {
+            if response.status() == StatusCode::UNAUTHORIZED && !token_refreshed {
+                if let Some(next) = next_request.take() {
+                    client.count(method, route, host, "failure");
+                    warn!(parent: span, attempt, "Attempt {} was rejected with 401, fetching a new token", attempt);
+
+                    provider.invalidate(token).await;
+                    token_refreshed = true;
+
+                    request = next;
+                    continue;
+                }
+            }
+        }
+
+        let delay = match &result {
+            Ok(response) => {
+                let status = response.status();
+
+                client.circuit_breakers.record(host, !status.is_server_error());
+
+                let retried = Failure::from_status(status.as_u16()).is_some_and(|failure| retry.retries(failure));
+
+                if !retried {
+                    client.count(
+                        method,
+                        route,
+                        host,
+                        if status.is_server_error() { "failure" } else { "success" },
+                    );
+                    debug!(parent: span, attempt, http.status_code = status.as_u16(), "Attempt {} returned {} after {:?}", attempt, status, elapsed);
+                    break result;
+                }
+
+                client.count(method, route, host, "failure");
+                warn!(parent: span, attempt, http.status_code = status.as_u16(), "Attempt {} returned {} after {:?}", attempt, status, elapsed);
+
+                match retry_after(response) {
+                    // do not block the caller for longer than configured
+                    Some(delay) if delay > retry.max_backoff() => break result,
+                    Some(delay) => delay,
+                    None => retry.backoff(attempt),
+                }
+            }
+
+            Err(err) => {
+                client.circuit_breakers.record(host, false);
+                client.count(method, route, host, "failure");
+                warn!(parent: span, attempt, "Attempt {} failed after {:?}: {}", attempt, elapsed, err);
+
+                if !retry.retries(failure_of(err)) {
+                    break result;
+                }
+
+                retry.backoff(attempt)
+            }
+        };
+
+        let Some(next_request) = next_request else {
+            break result;
+        };
+
+        debug!(parent: span, "Retrying in {:?}", delay);
+        tokio::time::sleep(delay).await;
+
+        request = next_request;
+        attempt += 1;
+    };
+
+    let response = match result {
+        Ok(response) => response,
+        Err(source) => {
+            span.record("otel.status_code", "ERROR");
+            warn!(parent: span, "{} {} failed after {:?}: {}", method, url, started.elapsed(), source);
+            return Err(ClientError::Request { url, source });
+        }
+    };
+
+    let status = response.status();
+    span.record("http.status_code", status.as_u16());
+
+    let not_modified = conditional && status == StatusCode::NOT_MODIFIED;
+
+    if !status.is_success() && !not_modified {
+        span.record("otel.status_code", "ERROR");
+
+        let body = response.text().instrument(span.clone()).await.unwrap_or_default();
+        warn!(parent: span, "{} {} returned {} after {:?}", method, url, status, started.elapsed());
+
+        return Err(ClientError::Status { url, status, body });
+    }
+
+    debug!(parent: span, "{} {} returned {} after {:?}", method, url, status, started.elapsed());
+
+    Ok(response)
+}
+
+/// Sends the attempt to an instance resolved for the service of the url, and to the next
+/// endpoint of a balanced host. Every attempt is routed again, so a retry may be sent elsewhere.
+async fn route_attempt(
+    client: &Inner,
+    resolver: Option<&dyn Resolver>,
+    logical: &Url,
+    request: &mut Request,
+) -> Result<Option<Routed>, ClientError> {
+    let mut url = match resolver {
+        Some(resolver) => resolve::resolve(resolver, logical).await?,
+        None => logical.clone(),
+    };
+
+    let routed = match &client.balancer {
+        Some(balancer) => balancer.route(&mut url, request.headers_mut()).await?,
+        None => None,
+    };
+
+    *request.url_mut() = url;
+
+    Ok(routed)
+}
+
+/// Where the backup request of a hedged attempt is routed to.
+struct Hedge<'a> {
+    resolver: Option<&'a dyn Resolver>,
+    logical: &'a Url,
+}
+
+fn is_success(result: &reqwest::Result<Response>) -> bool {
+    result.as_ref().is_ok_and(|response| !response.status().is_server_error())
+}
+
+#[cfg(feature = "signing")]
+fn sign(signer: &RequestSigner, request: &mut Request) -> Result<(), ClientError> {
+    let body = match request.body() {
+        Some(body) => body
+            .as_bytes()
+            .ok_or(ClientError::Signing(SigningError::StreamingBody))?,
+        None => &[],
+    };
+
+    // the Host header hyper sends for the url
+    let url = request.url();
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (host, _) => host.unwrap_or_default().to_string(),
+    };
+
+    let mut headers = request.headers().clone();
+    signer.sign(request.method(), &host, url.path(), url.query(), body, &mut headers);
+
+    *request.headers_mut() = headers;
+
+    Ok(())
+}
+
+impl Inner {
+    /// Sends a single attempt. For idempotent requests, a backup request is sent if the
+    /// response takes unusually long. The request that answers last is cancelled.
+    async fn send_attempt(
+        &self,
+        request: Request,
+        host: &str,
+        idempotent: bool,
+        hedge: Hedge<'_>,
+        span: &Span,
+    ) -> reqwest::Result<Response> {
+        let hedged = match idempotent {
+            true => self.hedging.delay(host).zip(self.hedging.backup(&request)),
+            false => None,
+        };
+
+        let Some((delay, mut backup)) = hedged else {
+            return self.client.execute(request).instrument(span.clone()).await;
+        };
+
+        let primary = self.client.execute(request).instrument(span.clone());
+        tokio::pin!(primary);
+
+        tokio::select! {
+            result = &mut primary => return result,
+            _ = tokio::time::sleep(delay) => {},
+        }
+
+        // the primary request went to the instance picked for it, the backup request is
+        // routed on its own, so it is sent to another instance
+        let routed = match self.hedging.has_alternate_url() {
+            true => None,
+            false => match route_attempt(self, hedge.resolver, hedge.logical, &mut backup).await {
+                Ok(routed) => routed,
+                Err(_) => return primary.await,
+            },
+        };
+
+        info!(parent: span, "No response after {:?}, sending backup request to {}", delay, backup.url());
+
+        let backup = async {
+            let result = self.client.execute(backup).await;
+
+            if let (Err(err), Some(routed)) = (&result, &routed) {
+                if err.is_connect() {
+                    routed.connect_failed();
+                }
+            }
+
+            result
+        };
+
+        let backup = backup.instrument(span.clone());
+        tokio::pin!(backup);
+
+        // the first successful response wins, a failed request waits for the other one
+        let (result, winner) = tokio::select! {
+            result = &mut primary => match is_success(&result) {
+                true => (result, "primary"),
+                false => (backup.await, "backup"),
+            },
+
+            result = &mut backup => match is_success(&result) {
+                true => (result, "backup"),
+                false => (primary.await, "primary"),
+            },
+        };
+
+        let attributes = [
+            KeyValue::new("net.peer.name", host.to_string()),
+            KeyValue::new("winner", winner),
+        ];
+        self.hedged.add(&opentelemetry::Context::current(), 1, &attributes);
+
+        result
+    }
+
+    fn count(&self, method: &Method, route: &str, host: &str, result: &'static str) {
+        let attributes = [
+            KeyValue::new("http.method", method.to_string()),
+            KeyValue::new("http.route", route.to_string()),
+            KeyValue::new("net.peer.name", host.to_string()),
+            KeyValue::new("result", result),
+        ];
+
+        self.attempts.add(&opentelemetry::Context::current(), 1, &attributes);
+    }
+
+    /// Records the outcome of a request including all of its retries.
+    fn record(
+        &self,
+        method: &Method,
+        route: &str,
+        host: &str,
+        duration: Duration,
+        result: &Result<Response, ClientError>,
+    ) {
+        let (result, status) = match result {
+            Ok(response) => ("success", Some(response.status())),
+            Err(ClientError::CircuitOpen { .. }) => ("rejected", None),
+            Err(err) => ("error", err.status()),
+        };
+
+        let mut attributes = vec![
+            KeyValue::new("http.method", method.to_string()),
+            KeyValue::new("http.route", route.to_string()),
+            KeyValue::new("net.peer.name", host.to_string()),
+            KeyValue::new("result", result),
+        ];
+
+        if let Some(status) = status {
+            attributes.push(KeyValue::new("http.status_code", i64::from(status.as_u16())));
+        }
+
+        let context = opentelemetry::Context::current();
+        self.requests.add(&context, 1, &attributes);
+        self.duration.record(&context, duration.as_secs_f64(), &attributes);
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use url::Url;
+
+use crate::ClientError;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+static RESOLVERS: RwLock<Vec<(String, Arc<dyn Resolver>)>> = RwLock::new(Vec::new());
+
+/// Resolves the name of a service to the address of one of its instances, e.g. using
+/// a service registry. See [register_resolver].
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// Returns the url of an instance of the service with scheme, host and port only,
+    /// e.g. `http://10.0.0.12:8080`.
+    async fn resolve(&self, service: &str) -> Result<Url, BoxError>;
+}
+
+/// Registers a resolver for urls with the given scheme. The [Client](crate::Client) sends
+/// requests to such urls to an instance returned by the resolver instead, keeping the path
+/// and query of the url. Every attempt of a request is resolved again, so retries may be
+/// sent to another instance.
+///
+/// Use like this: `register_resolver("consul", consul.resolver())`, then
+/// `client.get("consul://orders/orders/1")`
+pub fn register_resolver(scheme: &str, resolver: impl Resolver + 'static) {
+    let mut resolvers = RESOLVERS.write();
+    resolvers.retain(|(candidate, _)| !candidate.eq_ignore_ascii_case(scheme));
+    resolvers.push((scheme.to_ascii_lowercase(), Arc::new(resolver)));
+}
+
+/// Returns the resolver registered for the scheme of the url.
+pub(crate) fn resolver_for(url: &Url) -> Option<Arc<dyn Resolver>> {
+    RESOLVERS
+        .read()
+        .iter()
+        .find(|(scheme, _)| scheme == url.scheme())
+        .map(|(_, resolver)| resolver.clone())
+}
+
+/// Replaces the scheme, host and port of the url with the ones of a resolved instance.
+pub(crate) async fn resolve(resolver: &dyn Resolver, url: &Url) -> Result<Url, ClientError> {
+    let service = url.host_str().unwrap_or_default();
+
+    let resolve_error = |source| ClientError::Resolve {
+        service: service.to_string(),
+        source,
+    };
+
+    let mut resolved = resolver.resolve(service).await.map_err(resolve_error)?;
+    resolved.set_path(url.path());
+    resolved.set_query(url.query());
+
+    Ok(resolved)
+}
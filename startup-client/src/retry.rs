@@ -0,0 +1,34 @@
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Method, Response};
+use startup_base::retry::Failure;
+
+/// Returns the delay requested by the `Retry-After` header of the response,
+/// or None if the response has no such header.
+pub(crate) fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Only requests that can safely be sent twice are retried, see RFC 9110, section 9.2.2
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Classifies an attempt that failed without a response.
+pub(crate) fn failure_of(err: &reqwest::Error) -> Failure {
+    match err.is_timeout() {
+        true => Failure::Timeout,
+        false => Failure::Connection,
+    }
+}
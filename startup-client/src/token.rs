@@ -0,0 +1,155 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use startup_base::redact::{serialize_redacted, REDACTED};
+use startup_base::retry::RetryPolicy;
+use tokio::sync::Mutex;
+use tracing::info;
+use url::Url;
+
+use crate::ClientError;
+
+/// Tokens are refreshed this long before they expire, or after half of their lifetime
+/// if they are valid for a shorter time.
+const EXPIRY_LEEWAY: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OAuth2Config {
+    /// Url of the token endpoint of the authorization server.
+    pub token_url: Url,
+
+    pub client_id: String,
+
+    #[serde(serialize_with = "serialize_redacted")]
+    pub client_secret: String,
+
+    /// Space separated list of scopes to request.
+    #[serde(default)]
+    pub scope: Option<String>,
+
+    /// Retries of failed requests to the token endpoint.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+impl fmt::Debug for OAuth2Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuth2Config")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &REDACTED)
+            .field("scope", &self.scope)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+/// Provides the bearer tokens the [Client](crate::Client) sends to internal services.
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    /// Returns a token that is valid for at least a few more seconds.
+    async fn token(&self) -> Result<String, ClientError>;
+
+    /// Called if a service rejected the token with `401 Unauthorized`.
+    /// The next call to [TokenProvider::token] should return a fresh token.
+    async fn invalidate(&self, token: &str);
+}
+
+/// Fetches tokens using the OAuth2 client credentials grant and caches them until they expire.
+pub struct ClientCredentials {
+    client: reqwest::Client,
+    config: OAuth2Config,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+impl ClientCredentials {
+    pub fn new(client: reqwest::Client, config: OAuth2Config) -> Self {
+        Self {
+            client,
+            config,
+            token: Mutex::new(None),
+        }
+    }
+
+    async fn fetch(&self) -> Result<TokenResponse, ClientError> {
+        let url = self.config.token_url.to_string();
+
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &self.config.scope {
+            form.push(("scope", scope.as_str()));
+        }
+
+        let response = self
+            .client
+            .post(self.config.token_url.clone())
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&form)
+            .send()
+            .await
+            .map_err(|source| ClientError::Request {
+                url: url.clone(),
+                source,
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(ClientError::Status { url, status, body });
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|source| ClientError::Request { url, source })
+    }
+}
+
+#[async_trait]
+impl TokenProvider for ClientCredentials {
+    async fn token(&self) -> Result<String, ClientError> {
+        // holding the lock while fetching makes concurrent requests wait for a single fetch
+        let mut token = self.token.lock().await;
+
+        if let Some((value, refresh_at)) = token.as_ref() {
+            if *refresh_at > Instant::now() {
+                return Ok(value.clone());
+            }
+        }
+
+        info!(
+            "Fetching access token for {:?} from {}",
+            self.config.client_id, self.config.token_url
+        );
+
+        let response = self
+            .config
+            .retry
+            .run("fetch an access token", ClientError::failure, || self.fetch())
+            .await
+            .map_err(|err| ClientError::Token(Box::new(err)))?;
+
+        let lifetime = Duration::from_secs(response.expires_in.unwrap_or(300));
+        let refresh_at = Instant::now() + lifetime - EXPIRY_LEEWAY.min(lifetime / 2);
+
+        *token = Some((response.access_token.clone(), refresh_at));
+
+        Ok(response.access_token)
+    }
+
+    async fn invalidate(&self, rejected: &str) {
+        let mut token = self.token.lock().await;
+
+        // another request might already have fetched a new token
+        if token.as_ref().is_some_and(|(value, _)| value == rejected) {
+            *token = None;
+        }
+    }
+}
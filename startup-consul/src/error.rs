@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use startup_client::ClientError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConsulError {
+    #[error("failed to create consul client")]
+    Client(#[source] ClientError),
+
+    #[error("failed to read secret from {path}")]
+    ReadSecret {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to register service {service_id} in consul")]
+    Register {
+        service_id: String,
+        #[source]
+        source: ClientError,
+    },
+
+    #[error("failed to deregister service {service_id} from consul")]
+    Deregister {
+        service_id: String,
+        #[source]
+        source: ClientError,
+    },
+
+    #[error("failed to look up instances of service {service} in consul")]
+    Lookup {
+        service: String,
+        #[source]
+        source: ClientError,
+    },
+
+    #[error("no healthy instance of service {service} is registered in consul")]
+    NoInstances { service: String },
+
+    #[error("consul returned the invalid address {address:?} for service {service}")]
+    InvalidAddress {
+        service: String,
+        address: String,
+        #[source]
+        source: url::ParseError,
+    },
+}
@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use startup_client::{Client, ClientConfig, RequestBuilder};
+use url::Url;
+
+pub use crate::error::ConsulError;
+pub use crate::registration::{CheckConfig, Registration, RegistrationConfig};
+pub use crate::resolver::{ConsulResolver, ResolverConfig};
+
+mod error;
+mod registration;
+mod resolver;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsulConfig {
+    /// Url of the local consul agent.
+    #[serde(default = "default_address")]
+    pub address: Url,
+
+    /// ACL token sent with every request.
+    #[serde(default)]
+    pub token: Option<String>,
+
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+
+    /// Lookups of service instances, see [ConsulResolver].
+    #[serde(default)]
+    pub resolver: ResolverConfig,
+
+    /// Client used to talk to the consul agent. The base url is always the `address`.
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+
+fn default_address() -> Url {
+    Url::parse("http://127.0.0.1:8500").unwrap()
+}
+
+/// A client for the http api of the local consul agent. Use a [Registration] to register
+/// this service instance and a [ConsulResolver] to send requests to other services.
+///
+/// Use like this:
+/// ```ignore
+/// let consul = Consul::new(&config.consul)?;
+/// startup_client::register_resolver("consul", consul.resolver());
+///
+/// Registration::new(&consul, &config.registration)
+///     .run(startup_http::shutdown_requested())
+///     .await?;
+/// ```
+#[derive(Clone)]
+pub struct Consul {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    client: Client,
+    token: Option<String>,
+    resolver: ResolverConfig,
+}
+
+impl Consul {
+    pub fn new(config: &ConsulConfig) -> Result<Self, ConsulError> {
+        let client_config = ClientConfig {
+            base_url: Some(config.address.clone()),
+            ..config.client.clone()
+        };
+
+        let token = match (&config.token, &config.token_file) {
+            (Some(token), _) => Some(token.clone()),
+            (None, Some(path)) => Some(read_secret(path)?),
+            (None, None) => None,
+        };
+
+        let inner = Inner {
+            client: Client::new(&client_config).map_err(ConsulError::Client)?,
+            token,
+            resolver: config.resolver.clone(),
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Returns a resolver for urls like `consul://orders/orders/1`, see [ConsulResolver].
+    pub fn resolver(&self) -> ConsulResolver {
+        ConsulResolver::new(self.clone(), &self.inner.resolver)
+    }
+
+    pub(crate) fn get(&self, path: &str) -> RequestBuilder {
+        self.authorize(self.inner.client.get(path))
+    }
+
+    pub(crate) fn put(&self, path: &str) -> RequestBuilder {
+        self.authorize(self.inner.client.put(path))
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.inner.token {
+            Some(token) => request.header("X-Consul-Token", token.as_str()),
+            None => request,
+        }
+    }
+}
+
+fn read_secret(path: &Path) -> Result<String, ConsulError> {
+    let value = std::fs::read_to_string(path).map_err(|source| ConsulError::ReadSecret {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(value.trim().to_string())
+}
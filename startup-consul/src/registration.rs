@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use startup_base::health;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{Consul, ConsulError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationConfig {
+    /// Name of the service, other services resolve it using `consul://{name}`.
+    pub name: String,
+
+    /// Id of this instance. Defaults to the name, the host name and the port.
+    #[serde(default)]
+    pub id: Option<String>,
+
+    /// Address other services use to reach this instance. Defaults to the address of the consul agent.
+    #[serde(default)]
+    pub address: Option<String>,
+
+    /// Port of the public http listener.
+    pub port: u16,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    #[serde(default)]
+    pub meta: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub check: CheckConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckConfig {
+    /// Url consul polls to check the health of this instance. Without a url or `admin_port`,
+    /// the instance reports the state of the [health] registry itself, see [Registration].
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Port of the admin listener serving the `startup_http::health_router`. Consul polls its
+    /// readiness endpoint `/health/ready` at the address of the instance, or at `127.0.0.1`
+    /// without an address. Ignored if `url` is set.
+    #[serde(default)]
+    pub admin_port: Option<u16>,
+
+    /// Interval of the http check.
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+
+    /// Timeout of the http check.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// The instance becomes critical if it did not report its health for this long.
+    /// Only used without an http check.
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: u64,
+
+    /// Consul removes instances that stay critical for this long, e.g. after a crash.
+    #[serde(default = "default_deregister_critical_after_seconds")]
+    pub deregister_critical_after_seconds: u64,
+}
+
+impl Default for CheckConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            admin_port: None,
+            interval_seconds: default_interval_seconds(),
+            timeout_seconds: default_timeout_seconds(),
+            ttl_seconds: default_ttl_seconds(),
+            deregister_critical_after_seconds: default_deregister_critical_after_seconds(),
+        }
+    }
+}
+
+fn default_interval_seconds() -> u64 {
+    10
+}
+
+fn default_timeout_seconds() -> u64 {
+    5
+}
+
+fn default_ttl_seconds() -> u64 {
+    15
+}
+
+fn default_deregister_critical_after_seconds() -> u64 {
+    60
+}
+
+/// Registers this service instance with the local consul agent and deregisters it on shutdown,
+/// so other services stop sending requests to it before it stops serving them.
+///
+/// With a check url or the port of the admin listener, consul polls the url to check the health
+/// of the instance, see [CheckConfig::admin_port]. Without one,
+/// the instance uses a ttl check and reports whether all components in the [health] registry
+/// are healthy, immediately on every change and repeatedly before the ttl expires.
+///
+/// Use like this:
+/// ```ignore
+/// Registration::new(&consul, &config.registration)
+///     .run(startup_http::shutdown_requested())
+///     .await?;
+/// ```
+pub struct Registration {
+    consul: Consul,
+    config: RegistrationConfig,
+}
+
+impl Registration {
+    pub fn new(consul: &Consul, config: &RegistrationConfig) -> Self {
+        Self {
+            consul: consul.clone(),
+            config: config.clone(),
+        }
+    }
+
+    /// Id of the registered instance.
+    pub fn id(&self) -> String {
+        if let Some(id) = &self.config.id {
+            return id.clone();
+        }
+
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+        format!("{}-{}-{}", self.config.name, host, self.config.port)
+    }
+
+    /// Registers the instance, keeps its health up to date until `shutdown` resolves,
+    /// e.g. `startup_http::shutdown_requested()`, and deregisters it. Fails if the instance
+    /// can not be registered.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), ConsulError> {
+        let id = self.id();
+
+        self.register(&id).await?;
+
+        info!("Registered service {} as {} in consul", self.config.name, id);
+
+        match self.check_url() {
+            Some(_) => shutdown.await,
+            None => {
+                tokio::select! {
+                    _ = shutdown => {},
+                    _ = self.report_health(&id) => {},
+                }
+            }
+        }
+
+        self.deregister(&id).await?;
+
+        info!("Deregistered service {} from consul", id);
+
+        Ok(())
+    }
+
+    /// Url of the http check, if consul should poll the instance.
+    fn check_url(&self) -> Option<String> {
+        let check = &self.config.check;
+
+        if let Some(url) = &check.url {
+            return Some(url.clone());
+        }
+
+        let port = check.admin_port?;
+
+        let host = match self.config.address.as_deref().unwrap_or("127.0.0.1") {
+            address if address.contains(':') => format!("[{}]", address),
+            address => address.to_string(),
+        };
+
+        Some(format!("http://{}:{}/health/ready", host, port))
+    }
+
+    async fn register(&self, id: &str) -> Result<(), ConsulError> {
+        let config = &self.config;
+        let check = &config.check;
+
+        let deregister_after = format!("{}s", check.deregister_critical_after_seconds);
+
+        let check = match self.check_url() {
+            Some(url) => json!({
+                "HTTP": url,
+                "Interval": format!("{}s", check.interval_seconds),
+                "Timeout": format!("{}s", check.timeout_seconds),
+                "DeregisterCriticalServiceAfter": deregister_after,
+            }),
+
+            None => json!({
+                "TTL": format!("{}s", check.ttl_seconds),
+                "DeregisterCriticalServiceAfter": deregister_after,
+            }),
+        };
+
+        let body = json!({
+            "ID": id,
+            "Name": config.name,
+            "Address": config.address,
+            "Port": config.port,
+            "Tags": config.tags,
+            "Meta": config.meta,
+            "Check": check,
+        });
+
+        self.consul
+            .put("/v1/agent/service/register")
+            .route("/v1/agent/service/register")
+            .json(&body)
+            .send()
+            .await
+            .map_err(|source| ConsulError::Register {
+                service_id: id.to_string(),
+                source,
+            })?;
+
+        Ok(())
+    }
+
+    async fn deregister(&self, id: &str) -> Result<(), ConsulError> {
+        self.consul
+            .put(&format!("/v1/agent/service/deregister/{}", id))
+            .route("/v1/agent/service/deregister/{id}")
+            .send()
+            .await
+            .map_err(|source| ConsulError::Deregister {
+                service_id: id.to_string(),
+                source,
+            })?;
+
+        Ok(())
+    }
+
+    /// Reports the health of the instance to its ttl check, forever.
+    async fn report_health(&self, id: &str) {
+        let changed = Arc::new(Notify::new());
+
+        let notify = changed.clone();
+        let _subscription = health::on_change(move || notify.notify_one());
+
+        // report well before the ttl expires, so a single failed update does no harm
+        let interval = Duration::from_secs(self.config.check.ttl_seconds.max(3) / 3);
+
+        loop {
+            if let Err(err) = self.update_check(id).await {
+                warn!("Failed to report health of {} to consul: {:?}", id, err);
+            }
+
+            tokio::select! {
+                _ = changed.notified() => {},
+                _ = tokio::time::sleep(interval) => {},
+            }
+        }
+    }
+
+    async fn update_check(&self, id: &str) -> Result<(), startup_client::ClientError> {
+        let unhealthy: Vec<_> = health::components()
+            .into_iter()
+            .filter_map(|(component, status)| match status {
+                health::HealthStatus::Healthy => None,
+                health::HealthStatus::Unhealthy(reason) => Some(format!("{}: {}", component, reason)),
+            })
+            .collect();
+
+        let body = match unhealthy.is_empty() {
+            true => json!({ "Status": "passing", "Output": "healthy" }),
+            false => json!({ "Status": "critical", "Output": unhealthy.join("\n") }),
+        };
+
+        self.consul
+            .put(&format!("/v1/agent/check/update/service:{}", id))
+            .route("/v1/agent/check/update/{id}")
+            .json(&body)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use startup_client::Resolver;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{Consul, ConsulError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolverConfig {
+    /// How long the instances of a service are cached before consul is asked again.
+    #[serde(default = "default_cache_seconds")]
+    pub cache_seconds: u64,
+
+    /// Scheme used to talk to the resolved instances.
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self {
+            cache_seconds: default_cache_seconds(),
+            scheme: default_scheme(),
+        }
+    }
+}
+
+fn default_cache_seconds() -> u64 {
+    10
+}
+
+fn default_scheme() -> String {
+    "http".to_string()
+}
+
+/// Resolves a service name to one of its healthy instances registered in consul. Requests
+/// are distributed over the instances round robin. The instances of a service are cached
+/// for [ResolverConfig::cache_seconds]. If consul can not be reached, the instances looked
+/// up before are used until it is available again.
+///
+/// Register it with the client to send requests to urls like `consul://orders/orders/1`.
+///
+/// Use like this: `startup_client::register_resolver("consul", consul.resolver())`
+#[derive(Clone)]
+pub struct ConsulResolver {
+    consul: Consul,
+    scheme: String,
+    cache_duration: Duration,
+    cache: Arc<Mutex<HashMap<String, Instances>>>,
+}
+
+/// Healthy instances of a service as of the time they were looked up.
+#[derive(Clone)]
+struct Instances {
+    urls: Arc<Vec<Url>>,
+    next: Arc<AtomicUsize>,
+    fetched: Instant,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceEntry {
+    node: NodeEntry,
+    service: ServiceInstance,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NodeEntry {
+    address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceInstance {
+    address: String,
+    port: u16,
+}
+
+impl ConsulResolver {
+    pub(crate) fn new(consul: Consul, config: &ResolverConfig) -> Self {
+        Self {
+            consul,
+            scheme: config.scheme.clone(),
+            cache_duration: Duration::from_secs(config.cache_seconds),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the urls of all healthy instances of the service, without caching.
+    pub async fn instances(&self, service: &str) -> Result<Vec<Url>, ConsulError> {
+        let entries: Vec<ServiceEntry> = self
+            .consul
+            .get(&format!("/v1/health/service/{}?passing=true", service))
+            .route("/v1/health/service/{service}")
+            .send_json()
+            .await
+            .map_err(|source| ConsulError::Lookup {
+                service: service.to_string(),
+                source,
+            })?;
+
+        entries
+            .into_iter()
+            .map(|entry| {
+                // instances registered without an address are reachable at the address of their node
+                let address = match entry.service.address {
+                    address if address.is_empty() => entry.node.address,
+                    address => address,
+                };
+
+                let host = match address.contains(':') {
+                    true => format!("[{}]", address),
+                    false => address,
+                };
+
+                let url = format!("{}://{}:{}", self.scheme, host, entry.service.port);
+
+                Url::parse(&url).map_err(|source| ConsulError::InvalidAddress {
+                    service: service.to_string(),
+                    address: url,
+                    source,
+                })
+            })
+            .collect()
+    }
+
+    async fn cached(&self, service: &str) -> Result<Instances, ConsulError> {
+        let cached = self.cache.lock().get(service).cloned();
+
+        if let Some(instances) = &cached {
+            if instances.fetched.elapsed() < self.cache_duration {
+                return Ok(instances.clone());
+            }
+        }
+
+        let urls = match self.instances(service).await {
+            Ok(urls) => urls,
+
+            Err(err) => match cached {
+                Some(mut stale) => {
+                    warn!("Using stale instances of service {}: {:?}", service, err);
+
+                    // ask consul again after the cache duration, not on every request
+                    stale.fetched = Instant::now();
+                    self.cache.lock().insert(service.to_string(), stale.clone());
+
+                    return Ok(stale);
+                }
+
+                None => return Err(err),
+            },
+        };
+
+        debug!("Resolved {} instances of service {}", urls.len(), service);
+
+        let instances = Instances {
+            urls: Arc::new(urls),
+            next: Arc::new(AtomicUsize::new(0)),
+            fetched: Instant::now(),
+        };
+
+        self.cache.lock().insert(service.to_string(), instances.clone());
+
+        Ok(instances)
+    }
+}
+
+#[async_trait]
+impl Resolver for ConsulResolver {
+    async fn resolve(&self, service: &str) -> Result<Url, Box<dyn std::error::Error + Send + Sync>> {
+        let instances = self.cached(service).await?;
+
+        if instances.urls.is_empty() {
+            return Err(Box::new(ConsulError::NoInstances {
+                service: service.to_string(),
+            }));
+        }
+
+        let index = instances.next.fetch_add(1, Ordering::Relaxed) % instances.urls.len();
+        Ok(instances.urls[index].clone())
+    }
+}
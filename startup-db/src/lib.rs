@@ -3,11 +3,21 @@ use std::str::FromStr;
 
 use futures_core::future::BoxFuture;
 use serde::{Deserialize, Serialize};
-use sqlx::migrate::Migrator;
+use sqlx::migrate::{Migrate, Migrator};
 use sqlx::postgres::PgConnectOptions;
-use sqlx::{ConnectOptions, Database, PgPool, Pool, Postgres};
+use sqlx::{ConnectOptions, Connection, Database, PgPool, Pool, Postgres, Transaction};
+use startup_base::components::Resources;
+use startup_base::retry::{Failure, RetryPolicy};
+use startup_base::shutdown::priority;
+use startup_base::warmup;
 use tracing::info;
 
+#[cfg(feature = "session")]
+pub use crate::session::PostgresSessionStore;
+
+#[cfg(feature = "session")]
+mod session;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DatabaseConfig<DB> {
     #[serde(skip)]
@@ -22,17 +32,34 @@ pub struct DatabaseConfig<DB> {
     /// Enable query logging at 'debug' level.
     #[serde(default)]
     pub query_logging: bool,
+
+    /// Retries of connecting to the database, e.g. while it is still starting up.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreflightError {
+    #[error("database check failed")]
+    Database(#[from] sqlx::Error),
+
+    #[error("migrations {0:?} are not applied yet")]
+    PendingMigrations(Vec<i64>),
+
+    #[error("migrations {0:?} were changed after they were applied")]
+    ChangedMigrations(Vec<i64>),
 }
 
 pub trait ConnectExt<DB: Database> {
     /// Connect to the database and runs the given migrations.
-    fn connect(&self, migrator: Migrator) -> BoxFuture<Result<Pool<DB>, sqlx::Error>>;
+    /// The pool is closed on shutdown, see [startup_base::on_shutdown].
+    fn connect(&self, migrator: Migrator) -> BoxFuture<'_, Result<Pool<DB>, sqlx::Error>>;
 }
 
 impl ConnectExt<Postgres> for DatabaseConfig<Postgres> {
-    fn connect(&self, migrator: Migrator) -> BoxFuture<Result<PgPool, sqlx::Error>> {
+    fn connect(&self, migrator: Migrator) -> BoxFuture<'_, Result<PgPool, sqlx::Error>> {
         Box::pin(async move {
-            let options = PgConnectOptions::from_str(&self.url.to_string())?;
+            let options = PgConnectOptions::from_str(self.url.as_str())?;
 
             // make the requested schema the default search path.
             let mut options = options.options([("search_path", &self.schema)]);
@@ -45,7 +72,13 @@ impl ConnectExt<Postgres> for DatabaseConfig<Postgres> {
             }
 
             info!("Connecting to postgres database");
-            let pool = PgPool::connect_with(options).await?;
+            startup_base::sbom::register_backend("database", "postgres");
+            let pool = self
+                .retry
+                .run("connect to the database", failure, || {
+                    PgPool::connect_with(options.clone())
+                })
+                .await?;
 
             info!("Ensure schema {:?} exists", self.schema);
             sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {:?}", self.schema))
@@ -53,9 +86,154 @@ impl ConnectExt<Postgres> for DatabaseConfig<Postgres> {
                 .await?;
 
             info!("Run database migrations");
-            migrator.run(&pool).await?;
+            warmup::run("migrations", migrator.run(&pool)).await?;
+
+            let closing = pool.clone();
+            startup_base::on_shutdown("database", priority::CONNECTIONS, move || async move {
+                info!("Closing database connections");
+                closing.close().await;
+            });
 
             Ok(pool)
         })
     }
 }
+
+impl<DB> Clone for DatabaseConfig<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            _marker: PhantomData,
+            url: self.url.clone(),
+            schema: self.schema.clone(),
+            query_logging: self.query_logging,
+            retry: self.retry.clone(),
+        }
+    }
+}
+
+impl DatabaseConfig<Postgres> {
+    /// Connects to the database as component of [Components](startup_base::components::Components),
+    /// see [ConnectExt::connect]. The pool is added to the resources.
+    ///
+    /// Use like this: `components.add("db", &[], config.db.component(MIGRATOR))`
+    pub fn component(
+        &self,
+        migrator: Migrator,
+    ) -> impl FnOnce(Resources) -> BoxFuture<'static, eyre::Result<()>> + Send + 'static {
+        let config = self.clone();
+
+        move |resources| {
+            Box::pin(async move {
+                resources.insert(config.connect(migrator).await?);
+                Ok(())
+            })
+        }
+    }
+
+    /// Connects to the database and checks that all migrations are applied, without changing
+    /// the database, e.g. as check of a [Preflight](startup_base::preflight::Preflight) that
+    /// runs after the migrations.
+    pub async fn preflight(&self, migrator: &Migrator) -> Result<(), PreflightError> {
+        let options = PgConnectOptions::from_str(self.url.as_str())?;
+        let options = options.options([("search_path", &self.schema)]);
+
+        let mut conn = self
+            .retry
+            .run("connect to the database", failure, || options.connect())
+            .await?;
+
+        let table: Option<String> = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations')::text")
+            .fetch_one(&mut conn)
+            .await?;
+
+        let applied = match table {
+            Some(_) => conn.list_applied_migrations().await.map_err(sqlx::Error::from)?,
+            None => Vec::new(),
+        };
+
+        conn.close().await?;
+
+        let mut pending = Vec::new();
+        let mut changed = Vec::new();
+
+        for migration in migrator.iter().filter(|migration| !migration.migration_type.is_down_migration()) {
+            match applied.iter().find(|applied| applied.version == migration.version) {
+                Some(applied) if applied.checksum != migration.checksum => changed.push(migration.version),
+                Some(_) => {}
+                None => pending.push(migration.version),
+            }
+        }
+
+        if !changed.is_empty() {
+            return Err(PreflightError::ChangedMigrations(changed));
+        }
+
+        if !pending.is_empty() {
+            return Err(PreflightError::PendingMigrations(pending));
+        }
+
+        Ok(())
+    }
+}
+
+/// Classifies an error of the database for a [RetryPolicy], e.g. to retry a transaction
+/// that failed because the database was restarted.
+pub fn failure(err: &sqlx::Error) -> Failure {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolClosed => Failure::Connection,
+        sqlx::Error::PoolTimedOut => Failure::Timeout,
+
+        sqlx::Error::Database(err) => match err.code().as_deref() {
+            // too_many_connections
+            Some("53300") => Failure::Throttled,
+
+            // cannot_connect_now, admin_shutdown and crash_shutdown while the database restarts
+            Some("57P01" | "57P02" | "57P03") => Failure::Unavailable,
+
+            // connection_exception
+            Some(code) if code.starts_with("08") => Failure::Connection,
+
+            _ => Failure::Rejected,
+        },
+
+        _ => Failure::Rejected,
+    }
+}
+
+/// Starts a transaction that uses the schema as search path, e.g. the schema of a tenant.
+/// The search path is reset when the transaction ends, so the connection goes back to
+/// the pool with the default schema.
+///
+/// Use like this: `let mut tx = startup_db::begin_in_schema(&pool, "tenant_acme").await?`
+pub async fn begin_in_schema(pool: &PgPool, schema: &str) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("SELECT set_config('search_path', $1, true)")
+        .bind(quote_identifier(schema))
+        .execute(&mut tx)
+        .await?;
+
+    Ok(tx)
+}
+
+/// Creates the schema if it does not exist yet and runs the migrations in it, e.g. for
+/// every tenant that has a schema of its own.
+pub async fn migrate_schema(pool: &PgPool, schema: &str, migrator: &Migrator) -> Result<(), sqlx::Error> {
+    info!("Ensure schema {:?} exists", schema);
+    sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", quote_identifier(schema)))
+        .execute(pool)
+        .await?;
+
+    info!("Run database migrations in schema {:?}", schema);
+
+    // the migrations run in a single transaction with a local search path, see begin_in_schema
+    let mut tx = begin_in_schema(pool, schema).await?;
+    migrator.run(&mut tx).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
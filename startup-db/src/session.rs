@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::{Executor, PgPool, Row};
+use startup_http::session::{SessionData, SessionError, SessionStore};
+use tracing::info;
+
+/// Table of the sessions, created by [PostgresSessionStore::install].
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS startup_sessions (
+    id TEXT PRIMARY KEY,
+    data JSONB NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS startup_sessions_expires_at ON startup_sessions (expires_at);
+"#;
+
+/// Keeps the sessions of the [SessionLayer](startup_http::session::SessionLayer) in the table
+/// `startup_sessions`, shared by all replicas, for services without redis. The data of a session
+/// is stored as json. Expired sessions are removed whenever a session is saved.
+///
+/// Use like this: `router.layer(SessionLayer::new(&config.session, PostgresSessionStore::new(&pool)))`
+pub struct PostgresSessionStore {
+    pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(pool: &PgPool) -> Self {
+        Self { pool: pool.clone() }
+    }
+
+    /// Creates the table of the sessions if it does not exist yet.
+    pub async fn install(&self) -> Result<(), sqlx::Error> {
+        info!("Ensure session table exists");
+        self.pool.execute(SCHEMA).await?;
+        Ok(())
+    }
+}
+
+fn store_error(err: sqlx::Error) -> SessionError {
+    SessionError::Store(err.into())
+}
+
+#[async_trait]
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, SessionError> {
+        let row = sqlx::query("SELECT data::text AS data FROM startup_sessions WHERE id = $1 AND expires_at > now()")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(store_error)?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let data: String = row.try_get("data").map_err(store_error)?;
+        serde_json::from_str(&data).map_err(|err| SessionError::Store(err.into()))
+    }
+
+    async fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), SessionError> {
+        let data = serde_json::to_string(data).map_err(|err| SessionError::Store(err.into()))?;
+
+        sqlx::query("DELETE FROM startup_sessions WHERE expires_at <= now()")
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+
+        sqlx::query(
+            "INSERT INTO startup_sessions (id, data, expires_at)
+            VALUES ($1, $2::jsonb, now() + make_interval(secs => $3))
+            ON CONFLICT (id) DO UPDATE SET data = $2::jsonb, expires_at = now() + make_interval(secs => $3)",
+        )
+        .bind(id)
+        .bind(data)
+        .bind(ttl.as_secs_f64())
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn touch(&self, id: &str, ttl: Duration) -> Result<(), SessionError> {
+        sqlx::query("UPDATE startup_sessions SET expires_at = now() + make_interval(secs => $2) WHERE id = $1")
+            .bind(id)
+            .bind(ttl.as_secs_f64())
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), SessionError> {
+        sqlx::query("DELETE FROM startup_sessions WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+
+        Ok(())
+    }
+}
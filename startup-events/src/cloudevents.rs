@@ -0,0 +1,315 @@
+//! Encoding of events as [CloudEvents 1.0](https://github.com/cloudevents/spec/blob/v1.0.2/cloudevents/spec.md),
+//! in structured or binary content mode over http and kafka.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use opentelemetry::global;
+use serde::{Deserialize, Serialize};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::OutboxEvent;
+
+pub const SPEC_VERSION: &str = "1.0";
+
+/// Header with the content type of the data in binary mode, for http and kafka.
+const CONTENT_TYPE: &str = "content-type";
+
+/// Content type of an event in structured mode.
+pub const STRUCTURED_CONTENT_TYPE: &str = "application/cloudevents+json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CloudEventError {
+    #[error("invalid cloud event json")]
+    Json(#[from] serde_json::Error),
+
+    #[error("unsupported spec version {0:?}")]
+    SpecVersion(String),
+
+    #[error("missing required attribute {0}")]
+    MissingAttribute(&'static str),
+
+    #[error("invalid value of attribute {0}")]
+    InvalidAttribute(String),
+
+    #[error("data with content type {0:?} is not supported, only json and text")]
+    UnsupportedData(String),
+}
+
+/// Headers as name and value, in the order they are sent.
+pub type Headers = Vec<(String, String)>;
+
+/// How the event is transferred.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentMode {
+    /// The whole event is sent as json, including its attributes.
+    #[default]
+    Structured,
+
+    /// The data is sent as payload, the attributes are sent as headers.
+    Binary,
+}
+
+/// The protocol an event in binary mode is transferred with. Decides the prefix of the headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// Headers like `ce-id`.
+    Http,
+
+    /// Headers like `ce_id`.
+    Kafka,
+}
+
+impl Binding {
+    fn prefix(self) -> &'static str {
+        match self {
+            Binding::Http => "ce-",
+            Binding::Kafka => "ce_",
+        }
+    }
+}
+
+/// An event in the CloudEvents format. The trace context is carried
+/// in the `traceparent` and `tracestate` extension attributes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    pub source: String,
+
+    #[serde(rename = "type")]
+    pub event_type: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<DateTime<Utc>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datacontenttype: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataschema: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+
+    /// Extension attributes like `traceparent`.
+    #[serde(flatten, deserialize_with = "deserialize_extensions")]
+    pub extensions: BTreeMap<String, String>,
+}
+
+impl CloudEvent {
+    pub fn new(id: impl Into<String>, source: impl Into<String>, event_type: impl Into<String>) -> Self {
+        Self {
+            specversion: SPEC_VERSION.to_string(),
+            id: id.into(),
+            source: source.into(),
+            event_type: event_type.into(),
+            subject: None,
+            time: None,
+            datacontenttype: None,
+            dataschema: None,
+            data: None,
+            extensions: BTreeMap::new(),
+        }
+    }
+
+    /// Creates the event for an event of the outbox. The idempotency key is used as id and
+    /// the aggregate id as subject, so consumers can drop duplicates by `source` and `id`.
+    pub fn from_outbox(event: &OutboxEvent, source: &str) -> Self {
+        let mut cloud_event = Self::new(&event.idempotency_key, source, &event.event_type);
+        cloud_event.subject = Some(event.aggregate_id.clone());
+        cloud_event.time = Some(event.created_at);
+        cloud_event.datacontenttype = Some("application/json".to_string());
+        cloud_event.data = Some(event.payload.clone());
+        cloud_event
+    }
+
+    /// Sets the `traceparent` and `tracestate` extension attributes to the context of the span.
+    pub fn with_trace_context(mut self, span: &Span) -> Self {
+        let mut fields = HashMap::new();
+        let context = span.context();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut fields));
+
+        for name in ["traceparent", "tracestate"] {
+            if let Some(value) = fields.remove(name) {
+                self.extensions.insert(name.to_string(), value);
+            }
+        }
+
+        self
+    }
+
+    /// Returns the trace context of the extension attributes, e.g. to use it as parent of a span.
+    pub fn trace_context(&self) -> opentelemetry::Context {
+        let fields: HashMap<String, String> = self.extensions.clone().into_iter().collect();
+        global::get_text_map_propagator(|propagator| propagator.extract(&fields))
+    }
+
+    /// Encodes the event in structured mode, see [STRUCTURED_CONTENT_TYPE].
+    pub fn to_structured(&self) -> Result<Vec<u8>, CloudEventError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// Decodes an event in structured mode.
+    pub fn from_structured(payload: &[u8]) -> Result<Self, CloudEventError> {
+        let event: CloudEvent = serde_json::from_slice(payload)?;
+
+        if event.specversion != SPEC_VERSION {
+            return Err(CloudEventError::SpecVersion(event.specversion));
+        }
+
+        Ok(event)
+    }
+
+    /// Encodes the event in binary mode, returning the headers and the payload.
+    pub fn to_binary(&self, binding: Binding) -> Result<(Headers, Vec<u8>), CloudEventError> {
+        let prefix = binding.prefix();
+
+        let mut headers = vec![
+            (format!("{}specversion", prefix), self.specversion.clone()),
+            (format!("{}id", prefix), self.id.clone()),
+            (format!("{}source", prefix), self.source.clone()),
+            (format!("{}type", prefix), self.event_type.clone()),
+        ];
+
+        let optional = [
+            ("subject", self.subject.clone()),
+            ("time", self.time.map(|time| time.to_rfc3339())),
+            ("dataschema", self.dataschema.clone()),
+        ];
+
+        for (name, value) in optional {
+            if let Some(value) = value {
+                headers.push((format!("{}{}", prefix, name), value));
+            }
+        }
+
+        for (name, value) in &self.extensions {
+            headers.push((format!("{}{}", prefix, name), value.clone()));
+        }
+
+        let content_type = self.datacontenttype.as_deref().unwrap_or("application/json");
+        headers.push((CONTENT_TYPE.to_string(), content_type.to_string()));
+
+        let payload = match &self.data {
+            None => Vec::new(),
+            Some(serde_json::Value::String(text)) if !is_json(content_type) => text.as_bytes().to_vec(),
+            Some(data) => serde_json::to_vec(data)?,
+        };
+
+        Ok((headers, payload))
+    }
+
+    /// Decodes an event in binary mode from its headers and payload.
+    /// Header names are compared case-insensitively.
+    pub fn from_binary<K, V>(
+        binding: Binding,
+        headers: impl IntoIterator<Item = (K, V)>,
+        payload: &[u8],
+    ) -> Result<Self, CloudEventError>
+    where
+        K: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        let prefix = binding.prefix();
+
+        let mut attributes = BTreeMap::new();
+        let mut content_type = None;
+
+        for (name, value) in headers {
+            let name = name.as_ref().to_ascii_lowercase();
+
+            let value = String::from_utf8(value.as_ref().to_vec())
+                .map_err(|_| CloudEventError::InvalidAttribute(name.clone()))?;
+
+            if name == CONTENT_TYPE {
+                content_type = Some(value);
+            } else if let Some(attribute) = name.strip_prefix(prefix) {
+                attributes.insert(attribute.to_string(), value);
+            }
+        }
+
+        let mut take = |name: &'static str| attributes.remove(name);
+
+        let specversion = take("specversion").ok_or(CloudEventError::MissingAttribute("specversion"))?;
+        if specversion != SPEC_VERSION {
+            return Err(CloudEventError::SpecVersion(specversion));
+        }
+
+        let id = take("id").ok_or(CloudEventError::MissingAttribute("id"))?;
+        let source = take("source").ok_or(CloudEventError::MissingAttribute("source"))?;
+        let event_type = take("type").ok_or(CloudEventError::MissingAttribute("type"))?;
+        let subject = take("subject");
+        let dataschema = take("dataschema");
+
+        let time = match take("time") {
+            Some(time) => Some(
+                DateTime::parse_from_rfc3339(&time)
+                    .map_err(|_| CloudEventError::InvalidAttribute("time".to_string()))?
+                    .with_timezone(&Utc),
+            ),
+            None => None,
+        };
+
+        let data = match (payload.is_empty(), content_type.as_deref()) {
+            (true, _) => None,
+            (false, None) => Some(serde_json::from_slice(payload)?),
+            (false, Some(content_type)) if is_json(content_type) => Some(serde_json::from_slice(payload)?),
+            (false, Some(content_type)) if content_type.starts_with("text/") => {
+                let text = std::str::from_utf8(payload)
+                    .map_err(|_| CloudEventError::UnsupportedData(content_type.to_string()))?;
+
+                Some(serde_json::Value::String(text.to_string()))
+            }
+            (false, Some(content_type)) => return Err(CloudEventError::UnsupportedData(content_type.to_string())),
+        };
+
+        Ok(Self {
+            specversion,
+            id,
+            source,
+            event_type,
+            subject,
+            time,
+            datacontenttype: content_type,
+            dataschema,
+            data,
+            extensions: attributes,
+        })
+    }
+}
+
+fn is_json(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+/// Extension attributes may be strings, numbers or booleans in json. They are kept as strings,
+/// which is how they are sent in binary mode.
+fn deserialize_extensions<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let values = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+
+    if values.contains_key("data_base64") {
+        return Err(serde::de::Error::custom(
+            "data_base64 is not supported, only json and text data",
+        ));
+    }
+
+    let extensions = values
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => (name, value),
+            value => (name, value.to_string()),
+        })
+        .collect();
+
+    Ok(extensions)
+}
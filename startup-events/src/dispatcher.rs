@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use sqlx::types::Json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{EventsConfig, OutboxEvent, Publisher};
+
+/// Publishes the events of the outbox. Every event is traced as a producer span that
+/// continues the trace that recorded the event.
+///
+/// Only the oldest event of every aggregate is published at a time, so the events of an aggregate
+/// are published in order. An event that could not be published is retried with an exponential
+/// backoff and blocks the later events of its aggregate until it was published.
+///
+/// Events are published at least once: they are removed from the outbox only after they were
+/// published, so an event can be published twice if the dispatcher crashes in between. Consumers
+/// drop such duplicates using the [OutboxEvent::idempotency_key]. Events are locked using
+/// `FOR UPDATE SKIP LOCKED` while they are published, so several dispatchers can share an outbox.
+///
+/// The metrics `events.published` and `events.duration` record every attempt per event type and result.
+///
+/// Use like this:
+/// ```ignore
+/// Dispatcher::new(&pool, &config.events, KafkaPublisher::new(&producer))
+///     .run(startup_http::shutdown_requested())
+///     .await;
+/// ```
+pub struct Dispatcher<P> {
+    pool: PgPool,
+    config: EventsConfig,
+    publisher: P,
+    published: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl<P: Publisher> Dispatcher<P> {
+    pub fn new(pool: &PgPool, config: &EventsConfig, publisher: P) -> Self {
+        let meter = global::meter("startup-events");
+
+        let published = meter
+            .u64_counter("events.published")
+            .with_description("Attempts to publish an event by result")
+            .init();
+
+        let duration = meter
+            .f64_histogram("events.duration")
+            .with_description("Duration of publishing an event")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Self {
+            pool: pool.clone(),
+            config: config.clone(),
+            publisher,
+            published,
+            duration,
+        }
+    }
+
+    /// Publishes events until `shutdown` resolves, e.g. `startup_http::shutdown_requested()`.
+    /// A batch of events that is being published is finished first.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) {
+        let poll_interval = Duration::from_millis(self.config.poll_interval_ms);
+
+        info!("Publishing events from the outbox");
+
+        tokio::pin!(shutdown);
+
+        loop {
+            let published = match self.dispatch().await {
+                Ok(published) => published,
+                Err(err) => {
+                    warn!("Failed to publish events from the outbox: {}", err);
+                    0
+                }
+            };
+
+            // continue right away while there are more events
+            let delay = match published {
+                0 => poll_interval,
+                _ => Duration::ZERO,
+            };
+
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = tokio::time::sleep(delay) => {},
+            }
+        }
+
+        info!("Stopped publishing events");
+    }
+
+    /// Publishes a batch of events and returns how many were published.
+    async fn dispatch(&self) -> Result<usize, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let events = self.take(&mut tx).await?;
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let results = join_all(
+            events
+                .iter()
+                .map(|(event, trace_context)| self.publish(event, trace_context)),
+        )
+        .await;
+
+        let mut published = 0;
+
+        for ((event, _), result) in events.iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    published += 1;
+
+                    sqlx::query("DELETE FROM startup_outbox WHERE id = $1")
+                        .bind(event.id)
+                        .execute(&mut tx)
+                        .await?;
+                }
+
+                Err(error) => {
+                    let backoff = self.backoff(event.attempts);
+
+                    sqlx::query(
+                        "UPDATE startup_outbox
+                        SET attempts = attempts + 1, last_error = $2, next_attempt_at = now() + make_interval(secs => $3)
+                        WHERE id = $1",
+                    )
+                    .bind(event.id)
+                    .bind(error)
+                    .bind(backoff.as_secs_f64())
+                    .execute(&mut tx)
+                    .await?;
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(published)
+    }
+
+    /// Locks the oldest event of every aggregate that is due.
+    async fn take(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<(OutboxEvent, HashMap<String, String>)>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT id, event_type, aggregate_id, payload, idempotency_key::text AS idempotency_key, attempts, created_at, trace_context
+            FROM startup_outbox outbox
+            WHERE next_attempt_at <= now() AND NOT EXISTS (
+                SELECT 1 FROM startup_outbox earlier
+                WHERE earlier.aggregate_id = outbox.aggregate_id AND earlier.id < outbox.id
+            )
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED",
+        )
+        .bind(self.config.batch_size.max(1) as i64)
+        .fetch_all(tx)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let event = OutboxEvent {
+                    id: row.try_get("id")?,
+                    event_type: row.try_get("event_type")?,
+                    aggregate_id: row.try_get("aggregate_id")?,
+                    payload: row.try_get("payload")?,
+                    idempotency_key: row.try_get("idempotency_key")?,
+                    attempts: row.try_get("attempts")?,
+                    created_at: row.try_get("created_at")?,
+                };
+
+                let trace_context = row.try_get::<Json<_>, _>("trace_context")?.0;
+
+                Ok((event, trace_context))
+            })
+            .collect()
+    }
+
+    async fn publish(&self, event: &OutboxEvent, trace_context: &HashMap<String, String>) -> Result<(), String> {
+        let span = info_span!(
+            "event_publish",
+            otel.name = %format!("{} publish", event.event_type),
+            otel.kind = "producer",
+            otel.status_code = Empty,
+            event.id = event.id,
+            event.type = %event.event_type,
+            event.aggregate_id = %event.aggregate_id,
+        );
+
+        // continue the trace that recorded the event
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(trace_context));
+        span.set_parent(parent);
+
+        let started = Instant::now();
+        let result = self.publisher.publish(event).instrument(span.clone()).await;
+
+        let outcome = match &result {
+            Ok(()) => "success",
+            Err(_) => "failure",
+        };
+
+        let context = opentelemetry::Context::current();
+        let attributes = [
+            KeyValue::new("type", event.event_type.clone()),
+            KeyValue::new("result", outcome),
+        ];
+
+        self.published.add(&context, 1, &attributes);
+        self.duration
+            .record(&context, started.elapsed().as_secs_f64(), &attributes);
+
+        match result {
+            Ok(()) => {
+                debug!(parent: &span, "Published event {} of type {}", event.id, event.event_type);
+                Ok(())
+            }
+
+            Err(err) => {
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: &span, "Failed to publish event {} of type {}: {}", event.id, event.event_type, err);
+                Err(err.to_string())
+            }
+        }
+    }
+
+    fn backoff(&self, attempts: i32) -> Duration {
+        let initial = Duration::from_millis(self.config.retry_backoff_ms);
+        let max = Duration::from_millis(self.config.max_retry_backoff_ms);
+
+        (initial * 2u32.pow(attempts.clamp(0, 16) as u32)).min(max)
+    }
+}
@@ -0,0 +1,12 @@
+#[derive(Debug, thiserror::Error)]
+pub enum EventError {
+    #[error("failed to serialize event of type {event_type}")]
+    Serialize {
+        event_type: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("outbox database error")]
+    Database(#[from] sqlx::Error),
+}
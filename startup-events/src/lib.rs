@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use opentelemetry::global;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Executor, PgExecutor, PgPool};
+use tracing::{info, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use crate::dispatcher::Dispatcher;
+pub use crate::error::EventError;
+#[cfg(feature = "http")]
+pub use crate::publisher::HttpPublisher;
+#[cfg(feature = "kafka")]
+pub use crate::publisher::KafkaPublisher;
+pub use crate::publisher::{OutboxEvent, PublishError, Publisher};
+
+pub mod cloudevents;
+mod dispatcher;
+mod error;
+mod publisher;
+
+/// The outbox table, created by [install]. Idempotency keys are generated
+/// by `gen_random_uuid`, which needs postgres 13 or newer.
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS startup_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    event_type TEXT NOT NULL,
+    aggregate_id TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    idempotency_key UUID NOT NULL DEFAULT gen_random_uuid(),
+    trace_context JSONB NOT NULL DEFAULT '{}',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS startup_outbox_aggregate ON startup_outbox (aggregate_id, id);
+"#;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventsConfig {
+    /// Maximum number of events published at the same time.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    /// Interval to look for new events while the outbox is empty.
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+
+    /// Delay before an event that could not be published is published again.
+    /// The delay doubles with every further attempt, up to `max_retry_backoff_ms`.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+
+    #[serde(default = "default_max_retry_backoff_ms")]
+    pub max_retry_backoff_ms: u64,
+}
+
+fn default_batch_size() -> usize {
+    100
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1_000
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    1_000
+}
+
+fn default_max_retry_backoff_ms() -> u64 {
+    60_000
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: default_batch_size(),
+            poll_interval_ms: default_poll_interval_ms(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+            max_retry_backoff_ms: default_max_retry_backoff_ms(),
+        }
+    }
+}
+
+/// A domain event. Events of the same aggregate are published in the order they were recorded.
+pub trait Event: Serialize {
+    /// Identifies the type of event, e.g. `order-created`. Publishers use it to route the event.
+    const TYPE: &'static str;
+
+    /// Identifies the entity the event belongs to, e.g. the id of the order.
+    fn aggregate_id(&self) -> String;
+}
+
+/// Creates the outbox table in the current schema if it does not exist yet.
+pub async fn install(pool: &PgPool) -> Result<(), EventError> {
+    info!("Ensure outbox table exists");
+    pool.execute(SCHEMA).await?;
+    Ok(())
+}
+
+/// Writes the event to the outbox and returns its id. Pass the transaction that changes the
+/// aggregate, so the event is published if and only if the transaction commits. The event
+/// is published by a [Dispatcher] and continues the trace of the current span.
+///
+/// Use like this: `startup_events::record(&mut tx, &OrderCreated { id, total }).await?`
+pub async fn record<'c, E: Event>(executor: impl PgExecutor<'c>, event: &E) -> Result<i64, EventError> {
+    let payload = serde_json::to_value(event).map_err(|source| EventError::Serialize {
+        event_type: E::TYPE,
+        source,
+    })?;
+
+    let mut trace_context = HashMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut trace_context));
+
+    let id = sqlx::query_scalar(
+        "INSERT INTO startup_outbox (event_type, aggregate_id, payload, trace_context)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id",
+    )
+    .bind(E::TYPE)
+    .bind(event.aggregate_id())
+    .bind(payload)
+    .bind(Json(trace_context))
+    .fetch_one(executor)
+    .await?;
+
+    Ok(id)
+}
@@ -0,0 +1,176 @@
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+
+#[cfg(any(feature = "http", feature = "kafka"))]
+use crate::cloudevents::{Binding, CloudEvent, ContentMode, Headers, STRUCTURED_CONTENT_TYPE};
+
+pub type PublishError = Box<dyn std::error::Error + Send + Sync>;
+
+/// An event taken from the outbox to be published.
+#[derive(Debug, Clone)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub event_type: String,
+    pub aggregate_id: String,
+    pub payload: serde_json::Value,
+
+    /// Stays the same if publishing the event is retried, so consumers can drop duplicates.
+    pub idempotency_key: String,
+
+    /// Previous attempts to publish the event.
+    pub attempts: i32,
+
+    /// When the event was recorded.
+    pub created_at: DateTime<Utc>,
+}
+
+/// Publishes events from the outbox to a broker or another service.
+pub trait Publisher: Send + Sync + 'static {
+    /// Publishes the event. An error leads to another attempt later.
+    fn publish<'a>(&'a self, event: &'a OutboxEvent) -> BoxFuture<'a, Result<(), PublishError>>;
+}
+
+/// Publishes events to the kafka topic named like their type. The aggregate id is used as
+/// message key, so the events of an aggregate stay in order. The idempotency key is sent in
+/// the `idempotency-key` header, unless the events are sent as cloud events.
+#[cfg(feature = "kafka")]
+pub struct KafkaPublisher {
+    producer: startup_kafka::Producer,
+    topics: std::collections::HashMap<String, String>,
+    cloudevents: Option<CloudEvents>,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaPublisher {
+    pub fn new(producer: &startup_kafka::Producer) -> Self {
+        Self {
+            producer: producer.clone(),
+            topics: std::collections::HashMap::new(),
+            cloudevents: None,
+        }
+    }
+
+    /// Publishes the events of the type to the topic instead.
+    pub fn topic(mut self, event_type: &str, topic: &str) -> Self {
+        self.topics.insert(event_type.to_string(), topic.to_string());
+        self
+    }
+
+    /// Publishes the events as cloud events with the given source, see [CloudEvent::from_outbox].
+    pub fn cloudevents(mut self, source: &str, mode: ContentMode) -> Self {
+        self.cloudevents = Some(CloudEvents::new(source, mode));
+        self
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl Publisher for KafkaPublisher {
+    fn publish<'a>(&'a self, event: &'a OutboxEvent) -> BoxFuture<'a, Result<(), PublishError>> {
+        Box::pin(async move {
+            let topic = self.topics.get(&event.event_type).unwrap_or(&event.event_type);
+            let (headers, payload) = encode(event, self.cloudevents.as_ref(), Binding::Kafka)?;
+
+            let headers: Vec<_> = headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+
+            self.producer
+                .send_with_headers(topic, Some(&event.aggregate_id), &payload, &headers)
+                .await?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Posts events as json to a path of another service. The idempotency key and the type of the
+/// event are sent in the `idempotency-key` and `event-type` headers, unless the events are sent
+/// as cloud events.
+#[cfg(feature = "http")]
+pub struct HttpPublisher {
+    client: startup_client::Client,
+    path: String,
+    cloudevents: Option<CloudEvents>,
+}
+
+#[cfg(feature = "http")]
+impl HttpPublisher {
+    pub fn new(client: &startup_client::Client, path: &str) -> Self {
+        Self {
+            client: client.clone(),
+            path: path.to_string(),
+            cloudevents: None,
+        }
+    }
+
+    /// Posts the events as cloud events with the given source, see [CloudEvent::from_outbox].
+    pub fn cloudevents(mut self, source: &str, mode: ContentMode) -> Self {
+        self.cloudevents = Some(CloudEvents::new(source, mode));
+        self
+    }
+}
+
+#[cfg(feature = "http")]
+impl Publisher for HttpPublisher {
+    fn publish<'a>(&'a self, event: &'a OutboxEvent) -> BoxFuture<'a, Result<(), PublishError>> {
+        Box::pin(async move {
+            let (headers, payload) = encode(event, self.cloudevents.as_ref(), Binding::Http)?;
+
+            let request = headers.into_iter().fold(
+                self.client.post(&self.path).route(self.path.clone()),
+                |request, (name, value)| request.header(name, value),
+            );
+
+            request.body(payload).send().await?;
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(any(feature = "http", feature = "kafka"))]
+struct CloudEvents {
+    source: String,
+    mode: ContentMode,
+}
+
+#[cfg(any(feature = "http", feature = "kafka"))]
+impl CloudEvents {
+    fn new(source: &str, mode: ContentMode) -> Self {
+        Self {
+            source: source.to_string(),
+            mode,
+        }
+    }
+}
+
+/// Encodes the event as headers and payload, as plain json or as a cloud event. The
+/// trace context of the cloud event is taken from the current span.
+#[cfg(any(feature = "http", feature = "kafka"))]
+fn encode(
+    event: &OutboxEvent,
+    cloudevents: Option<&CloudEvents>,
+    binding: Binding,
+) -> Result<(Headers, Vec<u8>), PublishError> {
+    let Some(cloudevents) = cloudevents else {
+        let headers = vec![
+            ("idempotency-key".to_string(), event.idempotency_key.clone()),
+            ("event-type".to_string(), event.event_type.clone()),
+            ("content-type".to_string(), "application/json".to_string()),
+        ];
+
+        return Ok((headers, serde_json::to_vec(&event.payload)?));
+    };
+
+    let cloud_event = CloudEvent::from_outbox(event, &cloudevents.source).with_trace_context(&tracing::Span::current());
+
+    match cloudevents.mode {
+        ContentMode::Structured => {
+            let headers = vec![("content-type".to_string(), STRUCTURED_CONTENT_TYPE.to_string())];
+            Ok((headers, cloud_event.to_structured()?))
+        }
+
+        ContentMode::Binary => Ok(cloud_event.to_binary(binding)?),
+    }
+}
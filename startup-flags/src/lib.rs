@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::{global, KeyValue};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::strategy::{Flag, Strategy};
+pub use crate::unleash::UnleashConfig;
+
+mod strategy;
+mod unleash;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlagsConfig {
+    /// Flags by name. If unleash is configured, these are used until unleash
+    /// was reached and for flags that are not known to unleash.
+    #[serde(default)]
+    pub flags: BTreeMap<String, FlagConfig>,
+
+    /// Fetch flags from an unleash server.
+    #[serde(default)]
+    pub unleash: Option<UnleashConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlagConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Percentage of users the flag is enabled for. A user always gets the same result.
+    #[serde(default = "default_rollout_percent")]
+    pub rollout_percent: u32,
+
+    /// Users the flag is enabled for regardless of the rollout.
+    #[serde(default)]
+    pub user_ids: Vec<String>,
+}
+
+fn default_rollout_percent() -> u32 {
+    100
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FlagsError {
+    #[error("failed to create unleash client")]
+    Client(#[source] startup_client::ClientError),
+}
+
+/// Who a flag is evaluated for. Gradual rollouts use the user id, or the session id if
+/// there is no user id, so the same user always gets the same result.
+#[derive(Debug, Clone, Default)]
+pub struct FlagContext {
+    pub user_id: Option<String>,
+    pub session_id: Option<String>,
+    pub properties: HashMap<String, String>,
+}
+
+impl FlagContext {
+    pub fn user(user_id: impl Into<String>) -> Self {
+        Self {
+            user_id: Some(user_id.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    pub fn property(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties.insert(name.into(), value.into());
+        self
+    }
+
+    /// Returns a field of the context by its unleash name, e.g. `userId`.
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "userId" => self.user_id.as_deref(),
+            "sessionId" => self.session_id.as_deref(),
+            name => self.properties.get(name).map(String::as_str),
+        }
+    }
+}
+
+/// Decides if a feature is enabled. Flags are taken from the config and, if configured, from
+/// an unleash server that is polled in the background, see [UnleashConfig]. Unknown flags are
+/// disabled.
+///
+/// Every evaluation is counted in the `flags.evaluations` metric per flag and result, and recorded
+/// as a `feature_flag` event of the current span, so traces show which features were enabled.
+///
+/// Use like this: `if flags.enabled("new-checkout", &FlagContext::user(&user.id)) { ... }`
+#[derive(Clone)]
+pub struct Flags {
+    inner: Arc<Inner>,
+}
+
+pub(crate) struct Inner {
+    configured: HashMap<String, Flag>,
+    fetched: RwLock<Option<HashMap<String, Flag>>>,
+    provider: &'static str,
+    evaluations: Counter<u64>,
+}
+
+impl Flags {
+    /// Creates the flags. If unleash is configured, waits for the first update of the
+    /// flags, falling back to the configured flags if unleash can not be reached.
+    pub async fn new(config: &FlagsConfig) -> Result<Self, FlagsError> {
+        let configured = config
+            .flags
+            .iter()
+            .map(|(name, flag)| (name.clone(), Flag::from_config(name, flag)))
+            .collect();
+
+        let evaluations = global::meter("startup-flags")
+            .u64_counter("flags.evaluations")
+            .with_description("Evaluations of feature flags by result")
+            .init();
+
+        let inner = Arc::new(Inner {
+            configured,
+            fetched: RwLock::new(None),
+            provider: if config.unleash.is_some() { "unleash" } else { "config" },
+            evaluations,
+        });
+
+        if let Some(unleash) = &config.unleash {
+            unleash::start(unleash, &inner).await?;
+        }
+
+        Ok(Self { inner })
+    }
+
+    /// Returns true if the flag is enabled for the context.
+    pub fn enabled(&self, name: &str, context: &FlagContext) -> bool {
+        let enabled = {
+            let fetched = self.inner.fetched.read();
+
+            let flag = fetched
+                .as_ref()
+                .and_then(|flags| flags.get(name))
+                .or_else(|| self.inner.configured.get(name));
+
+            match flag {
+                Some(flag) => flag.is_enabled(context),
+                None => false,
+            }
+        };
+
+        let variant = if enabled { "enabled" } else { "disabled" };
+
+        debug!(
+            feature_flag.key = name,
+            feature_flag.provider_name = self.inner.provider,
+            feature_flag.variant = variant,
+            "feature_flag"
+        );
+
+        let attributes = [
+            KeyValue::new("flag", name.to_string()),
+            KeyValue::new("result", variant),
+        ];
+
+        self.inner
+            .evaluations
+            .add(&opentelemetry::Context::current(), 1, &attributes);
+
+        enabled
+    }
+
+    /// Returns true if the flag is enabled without a user, e.g. for background jobs.
+    pub fn enabled_globally(&self, name: &str) -> bool {
+        self.enabled(name, &FlagContext::default())
+    }
+}
+
+impl Flag {
+    fn from_config(name: &str, config: &FlagConfig) -> Self {
+        let mut strategies = Vec::new();
+
+        if !config.user_ids.is_empty() {
+            strategies.push(Strategy::UserIds(config.user_ids.iter().cloned().collect()));
+        }
+
+        if config.rollout_percent >= 100 {
+            strategies.push(Strategy::Default);
+        } else {
+            strategies.push(Strategy::Rollout {
+                percent: config.rollout_percent,
+                group_id: name.to_string(),
+                stickiness: "default".to_string(),
+            });
+        }
+
+        Flag {
+            enabled: config.enabled,
+            strategies,
+        }
+    }
+}
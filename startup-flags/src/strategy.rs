@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+
+use rand::Rng;
+
+use crate::FlagContext;
+
+/// A flag is enabled if it is switched on and any of its strategies matches.
+pub(crate) struct Flag {
+    pub(crate) enabled: bool,
+    pub(crate) strategies: Vec<Strategy>,
+}
+
+pub(crate) enum Strategy {
+    /// Enabled for everyone.
+    Default,
+
+    UserIds(HashSet<String>),
+
+    /// Enabled for a stable percentage of the values of the stickiness field.
+    Rollout {
+        percent: u32,
+        group_id: String,
+        stickiness: String,
+    },
+
+    /// A strategy that must match and that only applies if all of its constraints are met.
+    Constrained(Vec<Constraint>, Box<Strategy>),
+
+    /// A strategy this client does not know, it never matches.
+    Unknown,
+}
+
+pub(crate) struct Constraint {
+    pub(crate) context_name: String,
+    pub(crate) values: HashSet<String>,
+
+    /// True for the `NOT_IN` operator.
+    pub(crate) negated: bool,
+}
+
+impl Flag {
+    pub(crate) fn is_enabled(&self, context: &FlagContext) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.strategies.is_empty() || self.strategies.iter().any(|strategy| strategy.matches(context))
+    }
+}
+
+impl Strategy {
+    fn matches(&self, context: &FlagContext) -> bool {
+        match self {
+            Strategy::Default => true,
+
+            Strategy::UserIds(user_ids) => context
+                .user_id
+                .as_ref()
+                .is_some_and(|user_id| user_ids.contains(user_id)),
+
+            Strategy::Rollout {
+                percent,
+                group_id,
+                stickiness,
+            } => {
+                let value = match stickiness.as_str() {
+                    "default" => context.user_id.as_deref().or(context.session_id.as_deref()),
+                    "random" => None,
+                    name => match context.get(name) {
+                        Some(value) => Some(value),
+                        // the field to stick to is missing, so the rollout does not apply
+                        None => return false,
+                    },
+                };
+
+                let bucket = match value {
+                    Some(value) => normalized_hash(group_id, value),
+                    None => rand::thread_rng().gen_range(1..=100),
+                };
+
+                *percent > 0 && bucket <= *percent
+            }
+
+            Strategy::Constrained(constraints, strategy) => {
+                constraints.iter().all(|constraint| constraint.matches(context)) && strategy.matches(context)
+            }
+
+            Strategy::Unknown => false,
+        }
+    }
+}
+
+impl Constraint {
+    fn matches(&self, context: &FlagContext) -> bool {
+        let contained = context
+            .get(&self.context_name)
+            .is_some_and(|value| self.values.contains(value));
+
+        contained != self.negated
+    }
+}
+
+/// Maps the value to a bucket from 1 to 100, the same way as the unleash clients do,
+/// so a user gets the same result from every client.
+fn normalized_hash(group_id: &str, value: &str) -> u32 {
+    murmur3_32(format!("{}:{}", group_id, value).as_bytes(), 0) % 100 + 1
+}
+
+/// The 32 bit x86 variant of MurmurHash3.
+fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut hash = seed;
+
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+
+    for chunk in chunks {
+        let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash = hash.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .enumerate()
+            .fold(0u32, |k, (idx, byte)| k | u32::from(*byte) << (8 * idx));
+
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+
+    hash
+}
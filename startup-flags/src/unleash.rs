@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use startup_client::{Client, ClientConfig};
+use tracing::{debug, info, warn};
+
+use crate::strategy::{Constraint, Flag, Strategy};
+use crate::{FlagsError, Inner};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnleashConfig {
+    /// Url of the unleash api, e.g. `https://unleash.example.com/api`.
+    pub url: url::Url,
+
+    /// A client api token.
+    pub api_token: String,
+
+    /// Name of this service, shown in unleash.
+    pub app_name: String,
+
+    #[serde(default = "default_refresh_interval_seconds")]
+    pub refresh_interval_seconds: u64,
+
+    /// Http client used to fetch the flags. The base url is set to `url`.
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+
+fn default_refresh_interval_seconds() -> u64 {
+    15
+}
+
+#[derive(Deserialize)]
+struct Features {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    name: String,
+    enabled: bool,
+
+    #[serde(default)]
+    strategies: Vec<FeatureStrategy>,
+}
+
+#[derive(Deserialize)]
+struct FeatureStrategy {
+    name: String,
+
+    #[serde(default)]
+    parameters: HashMap<String, Value>,
+
+    #[serde(default)]
+    constraints: Vec<FeatureConstraint>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FeatureConstraint {
+    context_name: String,
+    operator: String,
+
+    #[serde(default)]
+    values: Vec<String>,
+
+    #[serde(default)]
+    inverted: bool,
+}
+
+/// Fetches the flags once and keeps polling them in the background while the flags are in use.
+pub(crate) async fn start(config: &UnleashConfig, inner: &Arc<Inner>) -> Result<(), FlagsError> {
+    let mut client_config = config.client.clone();
+    client_config.base_url = Some(config.url.clone());
+
+    let client = Client::new(&client_config).map_err(FlagsError::Client)?;
+
+    let poller = Poller {
+        client,
+        api_token: config.api_token.clone(),
+        app_name: config.app_name.clone(),
+    };
+
+    match poller.fetch().await {
+        Ok(flags) => {
+            info!("Fetched {} flags from unleash", flags.len());
+            *inner.fetched.write() = Some(flags);
+        }
+
+        Err(err) => warn!(
+            "Failed to fetch flags from unleash, using the configured flags for now: {}",
+            err
+        ),
+    }
+
+    let interval = Duration::from_secs(config.refresh_interval_seconds.max(1));
+    tokio::spawn(poll(poller, Arc::downgrade(inner), interval));
+
+    Ok(())
+}
+
+async fn poll(poller: Poller, inner: Weak<Inner>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        // stop once the flags are no longer used
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+
+        match poller.fetch().await {
+            Ok(flags) => {
+                debug!("Fetched {} flags from unleash", flags.len());
+                *inner.fetched.write() = Some(flags);
+            }
+
+            // keep the last known flags
+            Err(err) => warn!("Failed to fetch flags from unleash: {}", err),
+        }
+    }
+}
+
+struct Poller {
+    client: Client,
+    api_token: String,
+    app_name: String,
+}
+
+impl Poller {
+    async fn fetch(&self) -> Result<HashMap<String, Flag>, startup_client::ClientError> {
+        let features: Features = self
+            .client
+            .get("client/features")
+            .route("client/features")
+            .header("Authorization", &self.api_token)
+            .header("UNLEASH-APPNAME", &self.app_name)
+            .send_json()
+            .await?;
+
+        let flags = features
+            .features
+            .into_iter()
+            .map(|feature| {
+                let flag = Flag {
+                    enabled: feature.enabled,
+                    strategies: feature.strategies.into_iter().map(strategy).collect(),
+                };
+
+                (feature.name, flag)
+            })
+            .collect();
+
+        Ok(flags)
+    }
+}
+
+fn strategy(feature: FeatureStrategy) -> Strategy {
+    let parameter = |name: &str| match feature.parameters.get(name) {
+        Some(Value::String(value)) => Some(value.clone()),
+        Some(Value::Number(value)) => Some(value.to_string()),
+        _ => None,
+    };
+
+    let percent = |name: &str| parameter(name).and_then(|value| value.trim().parse().ok()).unwrap_or(0);
+
+    let strategy = match feature.name.as_str() {
+        "default" => Strategy::Default,
+
+        "userWithId" => Strategy::UserIds(
+            parameter("userIds")
+                .unwrap_or_default()
+                .split(',')
+                .map(|user_id| user_id.trim().to_string())
+                .filter(|user_id| !user_id.is_empty())
+                .collect(),
+        ),
+
+        "flexibleRollout" => Strategy::Rollout {
+            percent: percent("rollout"),
+            group_id: parameter("groupId").unwrap_or_default(),
+            stickiness: parameter("stickiness").unwrap_or_else(|| "default".to_string()),
+        },
+
+        "gradualRolloutUserId" => Strategy::Rollout {
+            percent: percent("percentage"),
+            group_id: parameter("groupId").unwrap_or_default(),
+            stickiness: "userId".to_string(),
+        },
+
+        "gradualRolloutSessionId" => Strategy::Rollout {
+            percent: percent("percentage"),
+            group_id: parameter("groupId").unwrap_or_default(),
+            stickiness: "sessionId".to_string(),
+        },
+
+        "gradualRolloutRandom" => Strategy::Rollout {
+            percent: percent("percentage"),
+            group_id: String::new(),
+            stickiness: "random".to_string(),
+        },
+
+        name => {
+            debug!("Unleash strategy {} is not supported", name);
+            Strategy::Unknown
+        }
+    };
+
+    if feature.constraints.is_empty() {
+        return strategy;
+    }
+
+    let mut constraints = Vec::with_capacity(feature.constraints.len());
+
+    for constraint in feature.constraints {
+        let negated = match constraint.operator.as_str() {
+            "IN" => false,
+            "NOT_IN" => true,
+            operator => {
+                debug!("Unleash constraint operator {} is not supported", operator);
+                return Strategy::Unknown;
+            }
+        };
+
+        constraints.push(Constraint {
+            context_name: constraint.context_name,
+            values: constraint.values.into_iter().collect(),
+            negated: negated != constraint.inverted,
+        });
+    }
+
+    Strategy::Constrained(constraints, Box::new(strategy))
+}
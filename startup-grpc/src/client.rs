@@ -0,0 +1,408 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use eyre::WrapErr;
+use futures_util::future::BoxFuture;
+use http_body::Body as _;
+use hyper::body::Bytes;
+use opentelemetry::metrics::Counter;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderInjector;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tonic::body::BoxBody;
+use tonic::codegen::StdError;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Code, Status};
+use tower_service::Service;
+use tracing::field::Empty;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::trace::{split_path, status_code, Metrics, Recorder, TracedBody};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcClientConfig {
+    /// Urls of the servers, e.g. `http://orders:50051`. Requests are balanced
+    /// between all endpoints that are currently connected.
+    pub endpoints: Vec<String>,
+
+    /// Timeout for establishing a connection.
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+
+    /// Deadline of every attempt, sent to the server in the `grpc-timeout` header.
+    /// A request with its own deadline, see [tonic::Request::set_timeout], keeps it.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// Send http/2 pings to detect broken connections.
+    #[serde(default)]
+    pub http2_keepalive_interval_seconds: Option<u64>,
+
+    /// Connect using tls. Required for `https` endpoints.
+    #[serde(default)]
+    pub tls: Option<GrpcClientTlsConfig>,
+
+    /// Retries of failed requests to idempotent methods.
+    #[serde(default)]
+    pub retry: GrpcRetryConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GrpcClientTlsConfig {
+    /// Path to the PEM encoded certificate of the certificate authority. Defaults
+    /// to the root certificates of the system.
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+
+    /// Path to the PEM encoded client certificate for mutual tls.
+    #[serde(default)]
+    pub certificate: Option<PathBuf>,
+
+    /// Path to the PEM encoded private key of the client certificate.
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+
+    /// Name to verify the server certificate against. Defaults to the host of the endpoint.
+    #[serde(default)]
+    pub domain_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcRetryConfig {
+    /// Maximum number of attempts per request, including the first one. Set to `1` to disable retries.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Backoff before the first retry. The backoff doubles with every further retry.
+    #[serde(default = "default_initial_backoff_millis")]
+    pub initial_backoff_millis: u64,
+
+    /// Upper limit for the backoff.
+    #[serde(default = "default_max_backoff_millis")]
+    pub max_backoff_millis: u64,
+
+    /// Unary methods that can safely be called twice, either a single method
+    /// like `orders.v1.Orders/GetOrder` or all methods of a service like `orders.v1.Orders`.
+    #[serde(default)]
+    pub idempotent_methods: Vec<String>,
+}
+
+impl Default for GrpcRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_millis: default_initial_backoff_millis(),
+            max_backoff_millis: default_max_backoff_millis(),
+            idempotent_methods: Vec::new(),
+        }
+    }
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    5
+}
+
+fn default_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff_millis() -> u64 {
+    100
+}
+
+fn default_max_backoff_millis() -> u64 {
+    5000
+}
+
+impl GrpcRetryConfig {
+    fn is_idempotent(&self, service: &str, method: &str) -> bool {
+        self.idempotent_methods
+            .iter()
+            .any(|candidate| match candidate.split_once('/') {
+                Some((candidate_service, candidate_method)) => {
+                    candidate_service == service && candidate_method == method
+                }
+                None => candidate == service,
+            })
+    }
+
+    /// Exponential backoff before the given retry, with the upper half randomized
+    /// so that clients do not retry in lockstep.
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_millis
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(self.max_backoff_millis);
+
+        let jitter = rand::thread_rng().gen_range(0..=backoff / 2);
+        Duration::from_millis(backoff - backoff / 2 + jitter)
+    }
+}
+
+/// A channel to call grpc services, pass it to the client generated by tonic. Every request
+/// is traced as a client span and propagates the current trace context to the server.
+///
+/// Requests without a deadline get the configured `timeout_seconds`, which is sent to the
+/// server in the `grpc-timeout` header. Requests to the configured idempotent methods are
+/// retried if the server is unavailable, see [GrpcRetryConfig]. Every attempt is counted
+/// in the `rpc.client.attempts` metric.
+///
+/// The metrics `rpc.client.requests` and `rpc.client.duration` record every request per
+/// service, method and grpc status code.
+///
+/// Use like this: `let orders = OrdersClient::new(GrpcChannel::new(&config.orders)?)`
+#[derive(Clone)]
+pub struct GrpcChannel {
+    channel: Channel,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    timeout: Duration,
+    retry: GrpcRetryConfig,
+    metrics: Arc<Metrics>,
+    attempts: Counter<u64>,
+}
+
+impl GrpcChannel {
+    /// Creates the channel. Connections are established lazily with the first request.
+    /// Must be called from within the tokio runtime.
+    pub fn new(config: &GrpcClientConfig) -> eyre::Result<Self> {
+        if config.endpoints.is_empty() {
+            eyre::bail!("no grpc endpoints configured");
+        }
+
+        let tls = config
+            .tls
+            .as_ref()
+            .map(GrpcClientTlsConfig::client_tls_config)
+            .transpose()?;
+
+        let mut endpoints = Vec::with_capacity(config.endpoints.len());
+
+        for url in &config.endpoints {
+            let mut endpoint = Endpoint::from_shared(url.clone())
+                .wrap_err_with(|| format!("parse grpc endpoint {:?}", url))?
+                .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+                .timeout(Duration::from_secs(config.timeout_seconds));
+
+            if let Some(interval) = config.http2_keepalive_interval_seconds {
+                endpoint = endpoint.http2_keep_alive_interval(Duration::from_secs(interval));
+            }
+
+            if let Some(tls) = &tls {
+                endpoint = endpoint.tls_config(tls.clone())?;
+            }
+
+            endpoints.push(endpoint);
+        }
+
+        let attempts = global::meter("startup-grpc")
+            .u64_counter("rpc.client.attempts")
+            .with_description("Attempts to send a grpc request")
+            .init();
+
+        let inner = Inner {
+            timeout: Duration::from_secs(config.timeout_seconds),
+            retry: config.retry.clone(),
+            metrics: Metrics::new("client"),
+            attempts,
+        };
+
+        Ok(Self {
+            channel: Channel::balance_list(endpoints.into_iter()),
+            inner: Arc::new(inner),
+        })
+    }
+}
+
+impl GrpcClientTlsConfig {
+    fn client_tls_config(&self) -> eyre::Result<ClientTlsConfig> {
+        let mut config = ClientTlsConfig::new();
+
+        if let Some(path) = &self.ca_certificate {
+            let ca = std::fs::read(path).wrap_err_with(|| format!("read ca certificate {:?}", path))?;
+            config = config.ca_certificate(Certificate::from_pem(ca));
+        }
+
+        if let (Some(certificate), Some(key)) = (&self.certificate, &self.key) {
+            let certificate =
+                std::fs::read(certificate).wrap_err_with(|| format!("read certificate {:?}", certificate))?;
+            let key = std::fs::read(key).wrap_err_with(|| format!("read key {:?}", key))?;
+            config = config.identity(Identity::from_pem(certificate, key));
+        }
+
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name);
+        }
+
+        Ok(config)
+    }
+}
+
+impl Service<http::Request<BoxBody>> for GrpcChannel {
+    type Response = http::Response<BoxBody>;
+    type Error = StdError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.channel.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut request: http::Request<BoxBody>) -> Self::Future {
+        let (service, method) = split_path(request.uri().path());
+
+        let span = info_span!(
+            "grpc_client",
+            otel.name = %format!("{}/{}", service, method),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            rpc.system = "grpc",
+            rpc.service = %service,
+            rpc.method = %method,
+            rpc.grpc.status_code = Empty,
+        );
+
+        let context = span.context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut HeaderInjector(request.headers_mut()))
+        });
+
+        if !request.headers().contains_key("grpc-timeout") {
+            let timeout = format!("{}m", self.inner.timeout.as_millis());
+            request
+                .headers_mut()
+                .insert("grpc-timeout", timeout.parse().expect("valid header value"));
+        }
+
+        let retry = self.inner.retry.max_attempts > 1 && self.inner.retry.is_idempotent(&service, &method);
+
+        let recorder = Recorder::new(span.clone(), self.inner.metrics.clone(), service, method);
+
+        // the channel that was polled ready must be used for the first attempt
+        let channel = self.channel.clone();
+        let channel = std::mem::replace(&mut self.channel, channel);
+
+        let inner = self.inner.clone();
+
+        Box::pin(
+            async move {
+                match retry {
+                    true => inner.send_with_retries(channel, request, recorder).await,
+                    false => inner.send(channel, request, recorder).await,
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
+
+impl Inner {
+    async fn send(
+        &self,
+        mut channel: Channel,
+        request: http::Request<BoxBody>,
+        mut recorder: Recorder,
+    ) -> Result<http::Response<BoxBody>, StdError> {
+        self.record_attempt(&recorder, 1);
+
+        match channel.call(request).await {
+            Ok(response) => {
+                recorder.code = status_code(response.headers());
+                Ok(traced(response, recorder))
+            }
+
+            Err(err) => {
+                recorder.code = Some(Code::Unavailable);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Buffers the request, so it can be sent again if the server is unavailable.
+    async fn send_with_retries(
+        &self,
+        mut channel: Channel,
+        request: http::Request<BoxBody>,
+        mut recorder: Recorder,
+    ) -> Result<http::Response<BoxBody>, StdError> {
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+
+        let mut attempt = 1;
+
+        loop {
+            self.record_attempt(&recorder, attempt);
+
+            let mut request = http::Request::new(full_body(body.clone()));
+            *request.method_mut() = parts.method.clone();
+            *request.uri_mut() = parts.uri.clone();
+            *request.version_mut() = parts.version;
+            *request.headers_mut() = parts.headers.clone();
+
+            let result = channel.call(request).await;
+
+            let code = match &result {
+                Ok(response) => status_code(response.headers()),
+                Err(_) => Some(Code::Unavailable),
+            };
+
+            if code != Some(Code::Unavailable) || attempt >= self.retry.max_attempts {
+                recorder.code = code;
+                return match result {
+                    Ok(response) => Ok(traced(response, recorder)),
+                    Err(err) => Err(err.into()),
+                };
+            }
+
+            let backoff = self.retry.backoff(attempt);
+            warn!(
+                attempt,
+                "Attempt {} failed with server unavailable, retrying in {:?}", attempt, backoff
+            );
+
+            tokio::time::sleep(backoff).await;
+
+            futures_util::future::poll_fn(|cx| channel.poll_ready(cx)).await?;
+
+            attempt += 1;
+        }
+    }
+
+    fn record_attempt(&self, recorder: &Recorder, attempt: u32) {
+        debug!(
+            attempt,
+            "Sending attempt {} of {}/{}", attempt, recorder.service, recorder.method
+        );
+
+        let attributes = [
+            KeyValue::new("rpc.system", "grpc"),
+            KeyValue::new("rpc.service", recorder.service.clone()),
+            KeyValue::new("rpc.method", recorder.method.clone()),
+        ];
+
+        self.attempts.add(&opentelemetry::Context::current(), 1, &attributes);
+    }
+}
+
+/// Records the rpc once the response body was read completely or dropped.
+fn traced(response: http::Response<hyper::Body>, recorder: Recorder) -> http::Response<BoxBody> {
+    response.map(|body| {
+        let body = body.map_err(|err| Status::from_error(err.into())).boxed_unsync();
+        TracedBody::boxed(body, recorder)
+    })
+}
+
+fn full_body(bytes: Bytes) -> BoxBody {
+    http_body::Full::new(bytes)
+        .map_err(|never| match never {})
+        .boxed_unsync()
+}
@@ -0,0 +1,293 @@
+#[macro_use]
+extern crate tracing;
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use startup_base::health;
+use tokio_util::sync::CancellationToken;
+use tonic::body::BoxBody;
+use tonic::server::NamedService;
+use tonic::transport::server::Router;
+use tonic::transport::{Body, Certificate, Identity, Server, ServerTlsConfig};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tower_layer::Stack;
+use tower_service::Service;
+
+pub use crate::client::{GrpcChannel, GrpcClientConfig, GrpcClientTlsConfig, GrpcRetryConfig};
+pub use crate::trace::{GrpcTrace, GrpcTraceLayer};
+
+mod client;
+mod trace;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcConfig {
+    pub port: u16,
+    pub address: String,
+
+    /// Serve grpc using tls. Without this, plaintext http/2 is used.
+    #[serde(default)]
+    pub tls: Option<GrpcTlsConfig>,
+
+    /// Disable Nagle's algorithm on accepted connections.
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+
+    /// Send TCP keep-alive probes after a connection was idle for this long.
+    #[serde(default)]
+    pub tcp_keepalive_seconds: Option<u64>,
+
+    /// Send http/2 pings to detect broken connections.
+    #[serde(default)]
+    pub http2_keepalive_interval_seconds: Option<u64>,
+
+    /// Cancel requests that take longer than this. Clients can set
+    /// a shorter deadline using the `grpc-timeout` header.
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+
+    /// Maximum number of concurrent requests on a single connection.
+    #[serde(default)]
+    pub concurrency_limit_per_connection: Option<usize>,
+
+    /// How long to wait for open requests and streams to finish during shutdown.
+    #[serde(default = "default_drain_timeout_seconds")]
+    pub drain_timeout_seconds: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcTlsConfig {
+    /// Path to the PEM encoded certificate chain of the server.
+    pub certificate: PathBuf,
+
+    /// Path to the PEM encoded private key of the server certificate.
+    pub key: PathBuf,
+
+    /// Path to the PEM encoded certificate authority used to verify client
+    /// certificates. If set, clients must present a valid certificate.
+    #[serde(default)]
+    pub client_ca_certificate: Option<PathBuf>,
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn default_drain_timeout_seconds() -> u64 {
+    30
+}
+
+/// The layers applied to every grpc service, see [GrpcConfig::server].
+pub type GrpcLayer = Stack<GrpcTraceLayer, tower_layer::Identity>;
+
+impl GrpcConfig {
+    /// Creates a server with the connection options and tls settings of this config.
+    /// Every request is traced and measured by the [GrpcTraceLayer], and the server answers
+    /// the `grpc.health.v1.Health` protocol and the server reflection protocol.
+    ///
+    /// Use like this:
+    /// ```ignore
+    /// config.grpc.server()?
+    ///     .add_service(GreeterServer::new(greeter))
+    ///     .run()
+    ///     .await?;
+    /// ```
+    pub fn server(&self) -> eyre::Result<GrpcServer> {
+        let mut server = Server::builder()
+            .tcp_nodelay(self.tcp_nodelay)
+            .tcp_keepalive(self.tcp_keepalive_seconds.map(Duration::from_secs))
+            .http2_keepalive_interval(self.http2_keepalive_interval_seconds.map(Duration::from_secs));
+
+        if let Some(limit) = self.concurrency_limit_per_connection {
+            server = server.concurrency_limit_per_connection(limit);
+        }
+
+        if let Some(timeout) = self.timeout_seconds {
+            server = server.timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(tls) = &self.tls {
+            server = server.tls_config(tls.server_tls_config()?)?;
+        }
+
+        let (health_reporter, health_service) = tonic_health::server::health_reporter();
+
+        let router = server.layer(GrpcTraceLayer::new()).add_service(health_service);
+
+        Ok(GrpcServer {
+            addr: self.socket_addr()?,
+            drain_timeout: Duration::from_secs(self.drain_timeout_seconds),
+            router,
+            health_reporter,
+            services: Vec::new(),
+            file_descriptor_sets: Vec::new(),
+        })
+    }
+
+    fn socket_addr(&self) -> eyre::Result<SocketAddr> {
+        let ip: IpAddr = self
+            .address
+            .parse()
+            .wrap_err_with(|| format!("parse address {:?}", self.address))?;
+
+        Ok(SocketAddr::new(ip, self.port))
+    }
+}
+
+impl GrpcTlsConfig {
+    fn server_tls_config(&self) -> eyre::Result<ServerTlsConfig> {
+        let certificate =
+            std::fs::read(&self.certificate).wrap_err_with(|| format!("read certificate {:?}", self.certificate))?;
+
+        let key = std::fs::read(&self.key).wrap_err_with(|| format!("read key {:?}", self.key))?;
+
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(certificate, key));
+
+        if let Some(path) = &self.client_ca_certificate {
+            let ca = std::fs::read(path).wrap_err_with(|| format!("read client ca certificate {:?}", path))?;
+            config = config.client_ca_root(Certificate::from_pem(ca));
+        }
+
+        Ok(config)
+    }
+}
+
+/// A grpc server created by [GrpcConfig::server].
+pub struct GrpcServer {
+    addr: SocketAddr,
+    drain_timeout: Duration,
+    router: Router<GrpcLayer>,
+    health_reporter: HealthReporter,
+    services: Vec<&'static str>,
+    file_descriptor_sets: Vec<&'static [u8]>,
+}
+
+impl GrpcServer {
+    /// Adds a service. Its health is reported using the `grpc.health.v1.Health` protocol, see [GrpcServer::run].
+    pub fn add_service<S>(mut self, service: S) -> Self
+    where
+        S: Service<http::Request<Body>, Response = http::Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.services.push(S::NAME);
+        self.router = self.router.add_service(service);
+        self
+    }
+
+    /// Reports the health of the services, e.g. to mark a service as not serving while a dependency
+    /// is unavailable. Statuses set here are replaced with the status from the health registry
+    /// once it changes, so prefer reporting the health of a component using [startup_base::health].
+    pub fn health_reporter(&self) -> HealthReporter {
+        self.health_reporter.clone()
+    }
+
+    /// Registers an encoded `FileDescriptorSet` with the reflection service, so tools like
+    /// `grpcurl` can list and call the services. Write it in `build.rs` using
+    /// `tonic_build::configure().file_descriptor_set_path(..)` and pass it in using `include_bytes!`.
+    pub fn file_descriptor_set(mut self, encoded: &'static [u8]) -> Self {
+        self.file_descriptor_sets.push(encoded);
+        self
+    }
+
+    /// Serves grpc until the process receives SIGINT or SIGTERM. On shutdown, all services
+    /// are reported as not serving, and open requests and streams are given
+    /// `drain_timeout_seconds` to finish, then the shutdown hooks run, see [startup_base::on_shutdown],
+    /// before this function returns.
+    ///
+    /// The health of the services follows the shared health registry, see [startup_base::health].
+    /// The server as a whole is serving while all components are healthy. A service is serving
+    /// while the component with the name of the service is healthy, or if there is no such
+    /// component, while the server as a whole is serving.
+    pub async fn run(self) -> eyre::Result<()> {
+        let guard = startup_base::shutdown::guard();
+
+        let mut reflection = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET);
+
+        for encoded in self.file_descriptor_sets {
+            reflection = reflection.register_encoded_file_descriptor_set(encoded);
+        }
+
+        let router = self
+            .router
+            .add_service(reflection.build().wrap_err("build reflection service")?);
+
+        let stopping = CancellationToken::new();
+
+        let health = sync_health(self.health_reporter, self.services, stopping.clone());
+
+        let signal = {
+            let stopping = stopping.clone();
+
+            async move {
+                startup_base::shutdown::signal().await;
+
+                info!("Received signal, shutting down grpc server");
+                stopping.cancel();
+            }
+        };
+
+        let deadline = async {
+            stopping.cancelled().await;
+            tokio::time::sleep(self.drain_timeout).await;
+        };
+
+        info!("Serving grpc on {}", self.addr);
+
+        let serve = async { tokio::join!(router.serve_with_shutdown(self.addr, signal), health).0 };
+
+        tokio::select! {
+            result = serve => result?,
+            _ = deadline => warn!("Grpc requests still open at the end of the drain timeout"),
+        }
+
+        guard.release().await;
+
+        Ok(())
+    }
+}
+
+/// Reports the health of the services whenever the health registry changes. Once the server
+/// stops, all services are reported as not serving. Only this task updates the statuses, so a
+/// change of the registry can not mark a service as serving again during the shutdown.
+async fn sync_health(mut reporter: HealthReporter, services: Vec<&'static str>, stopping: CancellationToken) {
+    let (changed_tx, mut changed_rx) = tokio::sync::watch::channel(());
+
+    let _subscription = health::on_change(move || {
+        let _ = changed_tx.send(());
+    });
+
+    while !stopping.is_cancelled() {
+        let serving = |healthy| match healthy {
+            true => ServingStatus::Serving,
+            false => ServingStatus::NotServing,
+        };
+
+        let healthy = health::is_healthy();
+        reporter.set_service_status("", serving(healthy)).await;
+
+        for service in &services {
+            let service_healthy = health::status(service).map_or(healthy, |status| status.is_healthy());
+            reporter.set_service_status(service, serving(service_healthy)).await;
+        }
+
+        tokio::select! {
+            _ = stopping.cancelled() => break,
+            _ = changed_rx.changed() => {},
+        }
+    }
+
+    // the empty name is the health of the server as a whole
+    for service in services.into_iter().chain([""]) {
+        reporter.set_service_status(service, ServingStatus::NotServing).await;
+    }
+}
@@ -0,0 +1,288 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures_util::future::BoxFuture;
+use http_body::Body as _;
+use hyper::body::Bytes;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderExtractor;
+use pin_project::pin_project;
+use tonic::body::BoxBody;
+use tonic::{Code, Status};
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// [tower_layer::Layer] that traces every rpc as a server span, continuing the trace of the
+/// client, and records the metrics `rpc.server.requests` and `rpc.server.duration` per
+/// service, method and grpc status code. Calls of unknown services or methods are recorded
+/// with service and method `unknown`.
+///
+/// The span and the measured duration end when the response was sent completely,
+/// including all messages of a server stream.
+#[derive(Clone)]
+pub struct GrpcTraceLayer {
+    metrics: Arc<Metrics>,
+}
+
+pub(crate) struct Metrics {
+    kind: &'static str,
+    requests: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Metrics {
+    /// Creates the metrics `rpc.<kind>.requests` and `rpc.<kind>.duration`.
+    pub(crate) fn new(kind: &'static str) -> Arc<Self> {
+        let meter = global::meter("startup-grpc");
+
+        let requests = meter
+            .u64_counter(format!("rpc.{}.requests", kind))
+            .with_description("Grpc requests")
+            .init();
+
+        let duration = meter
+            .f64_histogram(format!("rpc.{}.duration", kind))
+            .with_description("Duration until the response of a grpc request was sent completely")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Arc::new(Self {
+            kind,
+            requests,
+            duration,
+        })
+    }
+}
+
+impl GrpcTraceLayer {
+    pub fn new() -> Self {
+        Self {
+            metrics: Metrics::new("server"),
+        }
+    }
+}
+
+impl Default for GrpcTraceLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> tower_layer::Layer<S> for GrpcTraceLayer {
+    type Service = GrpcTrace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcTrace {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// Service created by the [GrpcTraceLayer].
+#[derive(Clone)]
+pub struct GrpcTrace<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> tower_service::Service<http::Request<B>> for GrpcTrace<S>
+where
+    S: tower_service::Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let (service, method) = split_path(request.uri().path());
+
+        let span = info_span!(
+            "grpc_request",
+            otel.name = %format!("{}/{}", service, method),
+            otel.kind = "server",
+            otel.status_code = Empty,
+            rpc.system = "grpc",
+            rpc.service = %service,
+            rpc.method = %method,
+            rpc.grpc.status_code = Empty,
+        );
+
+        let parent =
+            global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+
+        span.set_parent(parent);
+
+        let mut recorder = Recorder::new(span.clone(), self.metrics.clone(), service, method);
+
+        let response = self.inner.call(request).instrument(span);
+
+        Box::pin(async move {
+            let response = match response.await {
+                Ok(response) => response,
+                Err(err) => {
+                    recorder.code = Some(Code::Unknown);
+                    drop(recorder);
+                    return Err(err);
+                }
+            };
+
+            // errors returned by the handler are sent without a body, the status is in the headers
+            recorder.code = status_code(response.headers());
+
+            Ok(response.map(|inner| TracedBody::boxed(inner, recorder)))
+        })
+    }
+}
+
+/// Response body that records the rpc once the body was sent or dropped.
+#[pin_project]
+pub(crate) struct TracedBody {
+    #[pin]
+    inner: BoxBody,
+    recorder: Recorder,
+}
+
+impl TracedBody {
+    pub(crate) fn boxed(inner: BoxBody, recorder: Recorder) -> BoxBody {
+        TracedBody { inner, recorder }.boxed_unsync()
+    }
+}
+
+impl http_body::Body for TracedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.project();
+
+        let result = futures_util::ready!(this.inner.poll_data(cx));
+
+        if let Some(Err(status)) = &result {
+            this.recorder.code = Some(status.code());
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let this = self.project();
+
+        let result = futures_util::ready!(this.inner.poll_trailers(cx));
+
+        match &result {
+            Ok(Some(trailers)) => this.recorder.code = status_code(trailers).or(this.recorder.code),
+            Ok(None) => {}
+            Err(status) => this.recorder.code = Some(status.code()),
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pub(crate) struct Recorder {
+    span: Span,
+    metrics: Arc<Metrics>,
+    started: Instant,
+    pub(crate) service: String,
+    pub(crate) method: String,
+    pub(crate) code: Option<Code>,
+}
+
+impl Recorder {
+    pub(crate) fn new(span: Span, metrics: Arc<Metrics>, service: String, method: String) -> Self {
+        Self {
+            span,
+            metrics,
+            started: Instant::now(),
+            service,
+            method,
+            code: None,
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // the body was dropped before the status was received, the rpc was cancelled
+        let code = self.code.unwrap_or(Code::Cancelled);
+        let elapsed = self.started.elapsed();
+
+        self.span.record("rpc.grpc.status_code", code as i32);
+
+        if is_error(self.metrics.kind, code) {
+            self.span.record("otel.status_code", "ERROR");
+        }
+
+        info!(parent: &self.span, "Finished {}/{} with {:?} in {:?}", self.service, self.method, code, elapsed);
+
+        // the server answers unknown services and methods with Unimplemented. Their names are chosen
+        // by the client, so they are not used as labels, which would add a metric series per name.
+        let (service, method) = match self.metrics.kind == "server" && code == Code::Unimplemented {
+            true => ("unknown".to_string(), "unknown".to_string()),
+            false => (self.service.clone(), self.method.clone()),
+        };
+
+        let attributes = [
+            KeyValue::new("rpc.system", "grpc"),
+            KeyValue::new("rpc.service", service),
+            KeyValue::new("rpc.method", method),
+            KeyValue::new("rpc.grpc.status_code", code as i64),
+        ];
+
+        let context = opentelemetry::Context::current();
+        self.metrics.requests.add(&context, 1, &attributes);
+        self.metrics
+            .duration
+            .record(&context, elapsed.as_secs_f64(), &attributes);
+    }
+}
+
+/// Splits the path of a grpc request, which is always `/<package>.<service>/<method>`.
+pub(crate) fn split_path(path: &str) -> (String, String) {
+    path.trim_start_matches('/')
+        .split_once('/')
+        .filter(|(service, method)| !service.is_empty() && !method.is_empty() && !method.contains('/'))
+        .map(|(service, method)| (service.to_string(), method.to_string()))
+        .unwrap_or_else(|| ("unknown".to_string(), "unknown".to_string()))
+}
+
+pub(crate) fn status_code(headers: &http::HeaderMap) -> Option<Code> {
+    let value = headers.get("grpc-status")?.to_str().ok()?.parse::<i32>().ok()?;
+    Some(Code::from_i32(value))
+}
+
+/// Following the opentelemetry conventions for grpc, a server span only fails with status
+/// codes caused by the server, while a client span fails with every code except `OK`.
+fn is_error(kind: &str, code: Code) -> bool {
+    if kind == "client" {
+        return code != Code::Ok;
+    }
+
+    matches!(
+        code,
+        Code::Unknown
+            | Code::DeadlineExceeded
+            | Code::Unimplemented
+            | Code::Internal
+            | Code::Unavailable
+            | Code::DataLoss
+    )
+}
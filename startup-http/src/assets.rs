@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{header, Request};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::MethodRouter;
+use eyre::WrapErr;
+use serde::Deserialize;
+
+use crate::serve::{serve_static_with, Caching};
+
+/// Maps logical asset names to the fingerprinted files created by a frontend build,
+/// e.g. `src/main.ts` to `assets/main-4f2a9c.js`. Reads the `manifest.json` written by Vite
+/// as well as flat manifests that map names directly to files.
+///
+/// Use like this:
+/// ```ignore
+/// let manifest = AssetManifest::load("./dist/.vite/manifest.json", "/assets")?;
+/// let router = Router::new().nest_service("/assets", serve_assets("./dist", &manifest));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AssetManifest {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    base_url: String,
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    File(String),
+
+    Vite {
+        file: String,
+
+        #[serde(default)]
+        css: Vec<String>,
+    },
+}
+
+impl Entry {
+    fn file(&self) -> &str {
+        match self {
+            Entry::File(file) => file,
+            Entry::Vite { file, .. } => file,
+        }
+    }
+
+    fn css(&self) -> &[String] {
+        match self {
+            Entry::File(_) => &[],
+            Entry::Vite { css, .. } => css,
+        }
+    }
+}
+
+impl AssetManifest {
+    /// Reads the manifest from the given file. Resolved files are prefixed with `base_url`,
+    /// the path the build directory is served at.
+    pub fn load(path: impl AsRef<Path>, base_url: impl Into<String>) -> eyre::Result<Self> {
+        let path = path.as_ref();
+
+        let content = std::fs::read(path).wrap_err_with(|| format!("read asset manifest {:?}", path))?;
+
+        Self::parse(&content, base_url).wrap_err_with(|| format!("parse asset manifest {:?}", path))
+    }
+
+    /// Parses the content of a manifest file, see [AssetManifest::load].
+    pub fn parse(content: &[u8], base_url: impl Into<String>) -> eyre::Result<Self> {
+        let entries = serde_json::from_slice(content)?;
+
+        let inner = Inner {
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            entries,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Returns the url of the fingerprinted file for a logical asset name.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        let entry = self.inner.entries.get(name)?;
+        Some(self.url(entry.file()))
+    }
+
+    /// Returns the urls of the stylesheets imported by a Vite entry point.
+    pub fn css(&self, name: &str) -> Vec<String> {
+        let Some(entry) = self.inner.entries.get(name) else {
+            return Vec::new();
+        };
+
+        entry.css().iter().map(|file| self.url(file)).collect()
+    }
+
+    fn url(&self, file: &str) -> String {
+        format!("{}/{}", self.inner.base_url, file.trim_start_matches('/'))
+    }
+
+    /// All fingerprinted files, relative to the build directory.
+    fn files(&self) -> HashSet<String> {
+        self.inner
+            .entries
+            .values()
+            .flat_map(|entry| std::iter::once(entry.file()).chain(entry.css().iter().map(String::as_str)))
+            .map(|file| format!("/{}", file.trim_start_matches('/')))
+            .collect()
+    }
+
+    /// Adds the functions `asset(name)` and `asset_css(name)` to a template environment,
+    /// so templates can reference assets by their logical name.
+    ///
+    /// Use like this: `Templates::with_setup(config, move |env| manifest.register(env))`
+    #[cfg(feature = "templates")]
+    pub fn register(&self, environment: &mut minijinja::Environment<'static>) {
+        use minijinja::{Error, ErrorKind};
+
+        let manifest = self.clone();
+        environment.add_function("asset", move |name: &str| {
+            manifest.resolve(name).ok_or_else(|| {
+                let message = format!("asset {:?} not found in manifest", name);
+                Error::new(ErrorKind::InvalidOperation, message)
+            })
+        });
+
+        let manifest = self.clone();
+        environment.add_function("asset_css", move |name: &str| manifest.css(name));
+    }
+}
+
+/// Serves the output directory of a frontend build. Fingerprinted files listed in the
+/// manifest are cached forever, all other files, like the manifest itself, need to be
+/// revalidated before each use.
+///
+/// Use like this: `.nest_service("/assets", serve_assets("./dist", &manifest))`
+pub fn serve_assets(path: impl AsRef<Path>, manifest: &AssetManifest) -> MethodRouter {
+    let immutable = Arc::new(manifest.files());
+
+    let set_cache_control = move |req: Request<Body>, next: Next<Body>| {
+        let is_fingerprinted = immutable.contains(req.uri().path());
+
+        async move {
+            let mut response: Response = next.run(req).await;
+
+            let status = response.status();
+            if is_fingerprinted && (status.is_success() || status.is_redirection()) {
+                let cache_control = Caching::Immutable.cache_control();
+                response.headers_mut().insert(header::CACHE_CONTROL, cache_control);
+            }
+
+            response
+        }
+    };
+
+    serve_static_with(path, Caching::Revalidate).layer(middleware::from_fn(set_cache_control))
+}
@@ -0,0 +1,58 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::{BoxBody, HttpBody};
+use axum::http::HeaderMap;
+
+/// A response body that keeps a guard alive until the body was sent or dropped,
+/// e.g. to track a request until its response is complete.
+#[pin_project::pin_project]
+pub(crate) struct GuardedBody<G> {
+    #[pin]
+    body: BoxBody,
+    guard: Option<G>,
+}
+
+impl<G> GuardedBody<G> {
+    pub(crate) fn new(body: BoxBody, guard: G) -> Self {
+        Self {
+            body,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl<G> HttpBody for GuardedBody<G> {
+    type Data = <BoxBody as HttpBody>::Data;
+    type Error = <BoxBody as HttpBody>::Error;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.project();
+
+        let result = futures_util::ready!(this.body.poll_data(cx));
+
+        // the trailers are not polled if there are none
+        if result.is_none() {
+            this.guard.take();
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = self.project();
+
+        let result = futures_util::ready!(this.body.poll_trailers(cx));
+        this.guard.take();
+
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,264 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, Body, BoxBody, Bytes, Full, HttpBody};
+use axum::http::{header, HeaderMap, Request, Response};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use startup_base::redact::{is_secret_key, redact_form, redact_query, REDACTED};
+use tracing::Level;
+
+/// Target of the debug events written by the [BodyLoggingLayer]. Bodies are also logged if
+/// debug logging is enabled for this target, e.g. by a layer installed at runtime using
+/// [replace_tracing_layer](startup_base::replace_tracing_layer).
+pub const BODY_LOGGING_TARGET: &str = "startup_http::body";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyLoggingConfig {
+    /// Log request and response bodies. They are logged at debug level, so they are only
+    /// written with `verbose: true` or by a layer that enables debug logging for
+    /// [BODY_LOGGING_TARGET].
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Bodies larger than this are not logged.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: u64,
+
+    /// Only bodies with a content type starting with one of these prefixes are logged.
+    #[serde(default = "default_content_types")]
+    pub content_types: Vec<String>,
+
+    /// Values of these headers are replaced by `<redacted>`.
+    #[serde(default = "default_redact_headers")]
+    pub redact_headers: Vec<String>,
+}
+
+impl Default for BodyLoggingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_body_size: default_max_body_size(),
+            content_types: default_content_types(),
+            redact_headers: default_redact_headers(),
+        }
+    }
+}
+
+fn default_max_body_size() -> u64 {
+    16 * 1024
+}
+
+fn default_content_types() -> Vec<String> {
+    ["application/json", "application/xml", "application/x-www-form-urlencoded", "text/"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_redact_headers() -> Vec<String> {
+    ["authorization", "proxy-authorization", "cookie", "set-cookie", "x-api-key"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// Logs the headers and bodies of requests and responses to debug integration issues.
+///
+/// Secrets are redacted: the headers of [BodyLoggingConfig::redact_headers], secret query
+/// parameters like `access_token`, and secret fields of json and form bodies, like `password`.
+#[derive(Clone)]
+pub struct BodyLoggingLayer {
+    config: Arc<BodyLoggingConfig>,
+}
+
+impl BodyLoggingLayer {
+    pub fn new(config: BodyLoggingConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<S> tower_layer::Layer<S> for BodyLoggingLayer {
+    type Service = BodyLogging<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BodyLogging {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// Middleware created by [BodyLoggingLayer].
+#[derive(Clone)]
+pub struct BodyLogging<S> {
+    inner: S,
+    config: Arc<BodyLoggingConfig>,
+}
+
+impl<S> tower_service::Service<Request<Body>> for BodyLogging<S>
+where
+    S: tower_service::Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let enabled = config.enabled || tracing::enabled!(target: BODY_LOGGING_TARGET, Level::DEBUG);
+            if !enabled {
+                return inner.call(req).await;
+            }
+
+            let (parts, body) = req.into_parts();
+
+            let (body, logged) = match config.buffer(&parts.headers, body).await {
+                (Buffered::Bytes(bytes), logged) => (Body::from(bytes), logged),
+                (Buffered::Body(body), logged) => (body, logged),
+            };
+
+            let uri = parts.uri.to_string();
+
+            debug!(
+                target: BODY_LOGGING_TARGET,
+                "Request {} {}\n{}\n{}",
+                parts.method,
+                redact_query(&uri).unwrap_or(uri),
+                config.format_headers(&parts.headers),
+                logged
+            );
+
+            let response = inner.call(Request::from_parts(parts, body)).await?;
+
+            let (parts, body) = response.into_parts();
+
+            let (body, logged) = match config.buffer(&parts.headers, body).await {
+                (Buffered::Bytes(bytes), logged) => (boxed(Full::new(bytes)), logged),
+                (Buffered::Body(body), logged) => (body, logged),
+            };
+
+            debug!(
+                target: BODY_LOGGING_TARGET,
+                "Response {}\n{}\n{}",
+                parts.status,
+                config.format_headers(&parts.headers),
+                logged
+            );
+
+            Ok(Response::from_parts(parts, body))
+        })
+    }
+}
+
+enum Buffered<B> {
+    Body(B),
+    Bytes(Bytes),
+}
+
+impl BodyLoggingConfig {
+    /// Reads the body into memory if it should be logged. Returns the body to pass on
+    /// and the text to log.
+    async fn buffer<B>(&self, headers: &HeaderMap, body: B) -> (Buffered<B>, String)
+    where
+        B: HttpBody<Data = Bytes>,
+        B::Error: std::fmt::Display,
+    {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        if body.is_end_stream() {
+            return (Buffered::Body(body), "<empty body>".to_string());
+        }
+
+        if !self.content_types.iter().any(|prefix| content_type.starts_with(prefix.as_str())) {
+            let logged = format!("<body with content type {:?} not logged>", content_type);
+            return (Buffered::Body(body), logged);
+        }
+
+        match body.size_hint().upper() {
+            Some(size) if size <= self.max_body_size => (),
+            _ => {
+                let logged = "<body of unknown or too large size not logged>".to_string();
+                return (Buffered::Body(body), logged);
+            }
+        }
+
+        match hyper::body::to_bytes(body).await {
+            Ok(bytes) => {
+                let logged = redact_body(content_type, &bytes);
+                (Buffered::Bytes(bytes), logged)
+            }
+
+            Err(err) => (Buffered::Bytes(Bytes::new()), format!("<failed to read body: {}>", err)),
+        }
+    }
+
+    fn format_headers(&self, headers: &HeaderMap) -> String {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let redact = self
+                    .redact_headers
+                    .iter()
+                    .any(|redacted| redacted.eq_ignore_ascii_case(name.as_str()));
+
+                if redact {
+                    format!("{}: <redacted>", name)
+                } else {
+                    format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// The body as text, with the values of secret fields of json and form bodies redacted.
+fn redact_body(content_type: &str, body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        return redact_form(&text).unwrap_or_else(|| text.into_owned());
+    }
+
+    if content_type.starts_with("application/json") {
+        if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+            redact_json(&mut value);
+            return value.to_string();
+        }
+    }
+
+    text.into_owned()
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match is_secret_key(key) && !value.is_null() {
+                    true => *value = Value::String(REDACTED.to_string()),
+                    false => redact_json(value),
+                }
+            }
+        }
+
+        Value::Array(values) => values.iter_mut().for_each(redact_json),
+
+        _ => {}
+    }
+}
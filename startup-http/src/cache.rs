@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use axum::body::{boxed, BoxBody, Bytes, Full, HttpBody};
+use axum::http::{header, HeaderMap, HeaderName, Method, Request, Response, StatusCode};
+use futures_util::future::BoxFuture;
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{header_name, ConfigError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// Time to live of a cached response, if the response does
+    /// not specify a `max-age` in its `Cache-Control` header.
+    pub ttl_seconds: u64,
+
+    /// Request headers that are part of the cache key, e.g. `Accept` or `Accept-Language`.
+    #[serde(default)]
+    pub vary: Vec<String>,
+
+    /// Maximum number of responses kept in the in-memory cache.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+
+    /// Responses with a larger body are not cached.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: u64,
+}
+
+fn default_max_entries() -> usize {
+    1000
+}
+
+fn default_max_body_size() -> u64 {
+    1024 * 1024
+}
+
+/// A response stored in a [ResponseStore].
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Storage backend of the [ResponseCacheLayer].
+#[async_trait]
+pub trait ResponseStore: Send + Sync {
+    async fn get(&self, key: &str) -> Option<CachedResponse>;
+    async fn put(&self, key: String, response: CachedResponse, ttl: Duration);
+}
+
+/// Stores responses in memory. If the store is full, the entry that expires first is evicted.
+pub struct MemoryStore {
+    max_entries: usize,
+    entries: Mutex<HashMap<String, (Instant, CachedResponse)>>,
+}
+
+impl MemoryStore {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ResponseStore for MemoryStore {
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock();
+
+        match entries.get(key) {
+            Some((expires, response)) if *expires > Instant::now() => Some(response.clone()),
+
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+
+            None => None,
+        }
+    }
+
+    async fn put(&self, key: String, response: CachedResponse, ttl: Duration) {
+        let mut entries = self.entries.lock();
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let now = Instant::now();
+            entries.retain(|_, (expires, _)| *expires > now);
+
+            if entries.len() >= self.max_entries {
+                let first_to_expire = entries
+                    .iter()
+                    .min_by_key(|(_, (expires, _))| *expires)
+                    .map(|(key, _)| key.clone());
+
+                if let Some(first_to_expire) = first_to_expire {
+                    entries.remove(&first_to_expire);
+                }
+            }
+        }
+
+        entries.insert(key, (Instant::now() + ttl, response));
+    }
+}
+
+/// Caches successful responses to `GET` and `HEAD` requests. Add it to expensive
+/// routes using `route_layer`. Responses are keyed by method, path, query and
+/// the request headers configured in [ResponseCacheConfig::vary].
+///
+/// Responses with `Cache-Control: no-store` or `private`, with a `Set-Cookie` header or
+/// with a `Vary` header naming a request header that is not in [ResponseCacheConfig::vary]
+/// are never cached. Requests with `Cache-Control: no-cache` or credentials, an
+/// `Authorization` or `Cookie` header, bypass the cache, so a response for one user is
+/// never served to another.
+///
+/// Hits and misses are counted in the `http.server.cache.requests` metric.
+#[derive(Clone)]
+pub struct ResponseCacheLayer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    name: String,
+    ttl: Duration,
+    vary: Vec<HeaderName>,
+    max_body_size: u64,
+    store: Box<dyn ResponseStore>,
+    requests: Counter<u64>,
+}
+
+impl ResponseCacheLayer {
+    /// Creates a cache layer with an in-memory store. The name is used to label the metrics.
+    pub fn new(name: impl Into<String>, config: &ResponseCacheConfig) -> Result<Self, ConfigError> {
+        Self::with_store(name, config, MemoryStore::new(config.max_entries))
+    }
+
+    /// Creates a cache layer that uses the given store.
+    pub fn with_store(
+        name: impl Into<String>,
+        config: &ResponseCacheConfig,
+        store: impl ResponseStore + 'static,
+    ) -> Result<Self, ConfigError> {
+        let vary = config
+            .vary
+            .iter()
+            .map(|name| header_name("vary", name))
+            .collect::<Result<_, _>>()?;
+
+        let requests = opentelemetry::global::meter("startup-http")
+            .u64_counter("http.server.cache.requests")
+            .with_description("Requests handled by the response cache")
+            .init();
+
+        let inner = Inner {
+            name: name.into(),
+            ttl: Duration::from_secs(config.ttl_seconds),
+            vary,
+            max_body_size: config.max_body_size,
+            store: Box::new(store),
+            requests,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+}
+
+impl<S> tower_layer::Layer<S> for ResponseCacheLayer {
+    type Service = ResponseCache<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResponseCache {
+            inner,
+            cache: self.inner.clone(),
+        }
+    }
+}
+
+/// Middleware created by [ResponseCacheLayer].
+#[derive(Clone)]
+pub struct ResponseCache<S> {
+    inner: S,
+    cache: Arc<Inner>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for ResponseCache<S>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // take the service that was driven to readiness, see tower::Service docs
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let cache = self.cache.clone();
+
+        Box::pin(async move {
+            if !is_cacheable_request(&req) {
+                return inner.call(req).await;
+            }
+
+            let key = cache.key(&req);
+
+            if let Some(cached) = cache.store.get(&key).await {
+                cache.count("hit");
+
+                let mut response = Response::new(boxed(Full::new(cached.body)));
+                *response.status_mut() = cached.status;
+                *response.headers_mut() = cached.headers;
+                return Ok(response);
+            }
+
+            cache.count("miss");
+
+            let response = inner.call(req).await?;
+
+            let Some(ttl) = cache.ttl_of(&response) else {
+                return Ok(response);
+            };
+
+            let (parts, body) = response.into_parts();
+
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(err) => {
+                    warn!("Failed to read response body: {}", err);
+                    return Ok(Response::from_parts(parts, boxed(Full::new(Bytes::new()))));
+                }
+            };
+
+            let cached = CachedResponse {
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+            };
+
+            cache.store.put(key, cached, ttl).await;
+
+            Ok(Response::from_parts(parts, boxed(Full::new(body))))
+        })
+    }
+}
+
+impl Inner {
+    fn key<B>(&self, req: &Request<B>) -> String {
+        let mut key = format!("{} {}", req.method(), req.uri());
+
+        for name in &self.vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+
+            for value in req.headers().get_all(name) {
+                let _ = write!(key, ":{}", String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+
+        key
+    }
+
+    /// Returns how long the response may be cached, or None if it must not be cached.
+    fn ttl_of(&self, response: &Response<BoxBody>) -> Option<Duration> {
+        if response.status() != StatusCode::OK {
+            return None;
+        }
+
+        // only buffer bodies of a known and limited size
+        let size = response.body().size_hint().exact()?;
+        if size > self.max_body_size {
+            return None;
+        }
+
+        let mut ttl = self.ttl;
+
+        for directive in cache_control(response.headers()) {
+            let (name, value) = directive.split_once('=').unwrap_or((&directive, ""));
+
+            match name.trim() {
+                "max-age" | "s-maxage" => {
+                    ttl = Duration::from_secs(value.trim().trim_matches('"').parse().ok()?);
+                }
+
+                // also with a list of fields, like `private="Set-Cookie"`
+                "no-store" | "private" | "no-cache" => return None,
+
+                _ => (),
+            }
+        }
+
+        // a session cookie must only be sent to the client it belongs to
+        if response.headers().contains_key(header::SET_COOKIE) {
+            return None;
+        }
+
+        // the response may only be served for requests with the same values of the headers it
+        // varies on, so all of them must be part of the key. `*` varies on everything.
+        for value in response.headers().get_all(header::VARY) {
+            let value = value.to_str().ok()?;
+
+            for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                if !self.vary.iter().any(|vary| vary.as_str().eq_ignore_ascii_case(name)) {
+                    return None;
+                }
+            }
+        }
+
+        (!ttl.is_zero()).then_some(ttl)
+    }
+
+    fn count(&self, result: &'static str) {
+        let attributes = [KeyValue::new("cache", self.name.clone()), KeyValue::new("result", result)];
+        self.requests.add(&opentelemetry::Context::current(), 1, &attributes);
+    }
+}
+
+fn is_cacheable_request<B>(req: &Request<B>) -> bool {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return false;
+    }
+
+    // the response is probably for this user only
+    if req.headers().contains_key(header::AUTHORIZATION) || req.headers().contains_key(header::COOKIE) {
+        return false;
+    }
+
+    !cache_control(req.headers()).any(|directive| directive == "no-cache" || directive == "no-store")
+}
+
+fn cache_control(headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+}
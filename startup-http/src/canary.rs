@@ -0,0 +1,443 @@
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::body::{Body, BoxBody};
+use axum::http::uri::{InvalidUri, PathAndQuery};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode, Uri};
+use axum::response::IntoResponse;
+use futures_util::future::{poll_fn, BoxFuture};
+use hyper::client::HttpConnector;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::KeyValue;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{header_name, header_value, ConfigError};
+use crate::WebError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanaryConfig {
+    /// Percentage of clients routed to the canary, from 0 to 100.
+    #[serde(default)]
+    pub percentage: f64,
+
+    /// Header that selects the variant of a request, `stable` or `canary`,
+    /// e.g. to test the canary before any client is routed to it.
+    #[serde(default = "default_header")]
+    pub header: String,
+
+    /// Cookie that keeps a client on the variant it was assigned to.
+    #[serde(default = "default_cookie")]
+    pub cookie: String,
+
+    #[serde(default = "default_cookie_max_age_seconds")]
+    pub cookie_max_age_seconds: u64,
+
+    /// Only send the cookie over https. Disable this for local development over plain http.
+    #[serde(default = "default_cookie_secure")]
+    pub cookie_secure: bool,
+
+    /// Header that identifies a client, e.g. a user or device id. Clients with this header
+    /// are assigned by its value and always get the same variant, without a cookie.
+    #[serde(default)]
+    pub sticky_header: Option<String>,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            percentage: 0.0,
+            header: default_header(),
+            cookie: default_cookie(),
+            cookie_max_age_seconds: default_cookie_max_age_seconds(),
+            cookie_secure: default_cookie_secure(),
+            sticky_header: None,
+        }
+    }
+}
+
+fn default_header() -> String {
+    "x-canary".to_string()
+}
+
+fn default_cookie() -> String {
+    "canary".to_string()
+}
+
+fn default_cookie_max_age_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_cookie_secure() -> bool {
+    true
+}
+
+/// The variant a request is routed to. Added to the extensions of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Stable,
+    Canary,
+}
+
+impl Variant {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Variant::Stable => "stable",
+            Variant::Canary => "canary",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            value if value.eq_ignore_ascii_case("stable") => Some(Variant::Stable),
+            value if value.eq_ignore_ascii_case("canary") => Some(Variant::Canary),
+            _ => None,
+        }
+    }
+}
+
+/// Routes a percentage of the clients to a canary, e.g. a new implementation of the same routes
+/// or an [Upstream] running a new version, while everyone else is handled by the wrapped service.
+///
+/// A client keeps its variant: it is assigned by the value of the
+/// [sticky header](CanaryConfig::sticky_header) if present, otherwise randomly and remembered in a
+/// cookie. The [header](CanaryConfig::header) overrides the assignment. Changing the percentage
+/// at runtime only moves clients towards the new percentage, a percentage of 0 routes every client
+/// to the stable variant, e.g. to roll back.
+///
+/// Requests are counted by variant in the `http.server.canary.requests` metric and their
+/// duration is recorded in `http.server.canary.duration`, both labeled with the name of the layer.
+///
+/// Use like this: `stable_router.layer(CanaryLayer::new("checkout", &config.canary, canary_router)?)`
+#[derive(Clone)]
+pub struct CanaryLayer<C> {
+    canary: C,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    name: String,
+    // in hundredths of a percent, to change it atomically at runtime
+    basis_points: AtomicU32,
+    header: HeaderName,
+    cookie: String,
+    // the `Set-Cookie` headers that assign the variants
+    stable_cookie: HeaderValue,
+    canary_cookie: HeaderValue,
+    sticky_header: Option<HeaderName>,
+    requests: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl<C> CanaryLayer<C> {
+    /// Creates a new layer. The name is used to label the metrics.
+    pub fn new(name: impl Into<String>, config: &CanaryConfig, canary: C) -> Result<Self, ConfigError> {
+        let meter = opentelemetry::global::meter("startup-http");
+
+        let inner = Inner {
+            name: name.into(),
+            basis_points: AtomicU32::new(basis_points(config.percentage)),
+            header: header_name("canary.header", &config.header)?,
+            cookie: config.cookie.clone(),
+            stable_cookie: set_cookie(config, Variant::Stable)?,
+            canary_cookie: set_cookie(config, Variant::Canary)?,
+            sticky_header: config
+                .sticky_header
+                .as_deref()
+                .map(|name| header_name("canary.sticky_header", name))
+                .transpose()?,
+            requests: meter
+                .u64_counter("http.server.canary.requests")
+                .with_description("Requests routed by the canary layer")
+                .init(),
+            duration: meter
+                .f64_histogram("http.server.canary.duration")
+                .with_description("Duration of requests routed by the canary layer")
+                .with_unit(Unit::new("s"))
+                .init(),
+        };
+
+        Ok(Self {
+            canary,
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Changes the percentage of clients routed to the canary, e.g. to ramp it up.
+    pub fn set_percentage(&self, percentage: f64) {
+        info!("Routing {}% of the clients to canary {}", percentage, self.inner.name);
+        self.inner
+            .basis_points
+            .store(basis_points(percentage), Ordering::Relaxed);
+    }
+}
+
+fn set_cookie(config: &CanaryConfig, variant: Variant) -> Result<HeaderValue, ConfigError> {
+    let mut cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        config.cookie,
+        variant.as_str(),
+        config.cookie_max_age_seconds
+    );
+
+    if config.cookie_secure {
+        cookie.push_str("; Secure");
+    }
+
+    header_value("canary.cookie", cookie)
+}
+
+fn basis_points(percentage: f64) -> u32 {
+    (percentage.clamp(0.0, 100.0) * 100.0).round() as u32
+}
+
+impl<S, C: Clone> tower_layer::Layer<S> for CanaryLayer<C> {
+    type Service = Canary<S, C>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Canary {
+            stable: inner,
+            canary: self.canary.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Middleware created by [CanaryLayer].
+#[derive(Clone)]
+pub struct Canary<S, C> {
+    stable: S,
+    canary: C,
+    inner: Arc<Inner>,
+}
+
+impl<S, C, B> tower_service::Service<Request<B>> for Canary<S, C>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    C: tower_service::Service<Request<B>, Response = Response<BoxBody>, Error = Infallible> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // readiness is checked on the service the request is routed to
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let (variant, assigned) = self.inner.assign(req.headers());
+
+        req.extensions_mut().insert(variant);
+
+        let inner = self.inner.clone();
+        let mut stable = self.stable.clone();
+        let mut canary = self.canary.clone();
+
+        Box::pin(async move {
+            let started = Instant::now();
+
+            let result = match variant {
+                Variant::Stable => call(&mut stable, req).await,
+                Variant::Canary => call(&mut canary, req).await,
+            };
+
+            let Ok(mut response) = result;
+
+            if assigned {
+                let cookie = match variant {
+                    Variant::Stable => inner.stable_cookie.clone(),
+                    Variant::Canary => inner.canary_cookie.clone(),
+                };
+
+                response.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+
+            inner.record(variant, response.status(), started);
+
+            Ok(response)
+        })
+    }
+}
+
+async fn call<S, B>(service: &mut S, req: Request<B>) -> Result<S::Response, S::Error>
+where
+    S: tower_service::Service<Request<B>>,
+{
+    poll_fn(|cx| service.poll_ready(cx)).await?;
+    service.call(req).await
+}
+
+impl Inner {
+    /// Returns the variant of the request and whether it was assigned just now.
+    fn assign(&self, headers: &HeaderMap) -> (Variant, bool) {
+        let requested = headers
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Variant::parse);
+
+        if let Some(variant) = requested {
+            return (variant, false);
+        }
+
+        let basis_points = self.basis_points.load(Ordering::Relaxed);
+
+        // nothing to assign, also ignores the cookies of clients after a roll back
+        match basis_points {
+            0 => return (Variant::Stable, false),
+            10_000.. => return (Variant::Canary, false),
+            _ => {}
+        }
+
+        let sticky = self
+            .sticky_header
+            .as_ref()
+            .and_then(|name| headers.get(name))
+            .map(|value| value.as_bytes());
+
+        // a hash that is the same in every instance and version of the service
+        if let Some(value) = sticky {
+            let hash = fnv1a(&[self.name.as_bytes(), &[0], value]);
+            return (variant((hash % 10_000) as u32, basis_points), false);
+        }
+
+        if let Some(variant) = self.cookie_variant(headers) {
+            return (variant, false);
+        }
+
+        let bucket = rand::thread_rng().gen_range(0..10_000);
+        (variant(bucket, basis_points), true)
+    }
+
+    fn cookie_variant(&self, headers: &HeaderMap) -> Option<Variant> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == self.cookie)
+            .and_then(|(_, value)| Variant::parse(value))
+    }
+
+    fn record(&self, variant: Variant, status: StatusCode, started: Instant) {
+        let cx = opentelemetry::Context::current();
+
+        let outcome = match status.is_server_error() {
+            true => "error",
+            false => "success",
+        };
+
+        let attributes = [
+            KeyValue::new("name", self.name.clone()),
+            KeyValue::new("variant", variant.as_str()),
+            KeyValue::new("outcome", outcome),
+        ];
+
+        self.requests.add(&cx, 1, &attributes);
+        self.duration
+            .record(&cx, started.elapsed().as_secs_f64(), &attributes[..2]);
+    }
+}
+
+/// The 64 bit FNV-1a hash of the concatenated parts.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    parts.iter().flat_map(|part| part.iter()).fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn variant(bucket: u32, basis_points: u32) -> Variant {
+    match bucket < basis_points {
+        true => Variant::Canary,
+        false => Variant::Stable,
+    }
+}
+
+/// Headers that only apply to a single connection and are not forwarded, see RFC 7230, section 6.1.
+static HOP_BY_HOP: [HeaderName; 7] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::UPGRADE,
+];
+
+/// Forwards requests to another service over plain http, e.g. a new version of the service
+/// deployed next to the current one, to use it as the canary of a [CanaryLayer].
+/// Requests that can not be forwarded are answered with `502 Bad Gateway`.
+///
+/// Use like this: `CanaryLayer::new("checkout", &config.canary, Upstream::new("http://checkout-canary:8080")?)?`
+#[derive(Clone)]
+pub struct Upstream {
+    client: hyper::Client<HttpConnector>,
+    base: Uri,
+}
+
+impl Upstream {
+    /// The path of the request is appended to the path of the url.
+    pub fn new(url: &str) -> Result<Self, InvalidUri> {
+        Ok(Self {
+            client: hyper::Client::new(),
+            base: url.parse()?,
+        })
+    }
+
+    fn uri(&self, uri: &Uri) -> Result<Uri, axum::http::Error> {
+        let base_path = self.base.path().trim_end_matches('/');
+        let path_and_query = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+
+        let mut parts = self.base.clone().into_parts();
+        parts.path_and_query = Some(format!("{}{}", base_path, path_and_query).parse()?);
+
+        Ok(Uri::from_parts(parts)?)
+    }
+}
+
+impl tower_service::Service<Request<Body>> for Upstream {
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let uri = self.uri(req.uri());
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let uri = match uri {
+                Ok(uri) => uri,
+                Err(err) => return Ok(WebError::from(err).into_response()),
+            };
+
+            *req.uri_mut() = uri;
+
+            // the client sets the host of the upstream
+            req.headers_mut().remove(header::HOST);
+
+            for name in &HOP_BY_HOP {
+                req.headers_mut().remove(name);
+            }
+
+            match client.request(req).await {
+                Ok(response) => Ok(response.into_response()),
+                Err(err) => {
+                    warn!("Failed to forward request to upstream: {}", err);
+                    let message = "Upstream is not available".to_string();
+                    Ok(WebError::Response(StatusCode::BAD_GATEWAY, message).into_response())
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::WebError;
+
+/// The certificate a client authenticated with on a tls connection, verified against the
+/// certificate authorities of [ClientAuthConfig](crate::ClientAuthConfig).
+///
+/// Rejects requests without a client certificate with `401 Unauthorized`. Use
+/// `Option<ClientCertificate>` if client certificates are optional.
+///
+/// Use like this: `async fn orders(certificate: ClientCertificate) { certificate.sans() }`
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    subject: String,
+    sans: Vec<String>,
+    der: Vec<u8>,
+}
+
+impl ClientCertificate {
+    /// Parses the DER encoded certificate of the client.
+    pub(crate) fn parse(der: &[u8]) -> Option<Self> {
+        let (_, certificate) = X509Certificate::from_der(der).ok()?;
+
+        let sans = match certificate.subject_alternative_name() {
+            Ok(Some(extension)) => extension.value.general_names.iter().filter_map(san).collect(),
+            _ => Vec::new(),
+        };
+
+        let inner = Inner {
+            subject: certificate.subject().to_string(),
+            sans,
+            der: der.to_vec(),
+        };
+
+        Some(Self { inner: Arc::new(inner) })
+    }
+
+    /// The distinguished name of the subject, like `CN=orders, O=example`.
+    pub fn subject(&self) -> &str {
+        &self.inner.subject
+    }
+
+    /// The DNS names, URIs, email addresses and IP addresses of the subject alternative names,
+    /// like `orders.internal` or `spiffe://example.org/ns/shop/sa/orders`.
+    pub fn sans(&self) -> &[String] {
+        &self.inner.sans
+    }
+
+    /// The DER encoded certificate.
+    pub fn der(&self) -> &[u8] {
+        &self.inner.der
+    }
+}
+
+fn san(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) | GeneralName::URI(name) | GeneralName::RFC822Name(name) => Some(name.to_string()),
+
+        GeneralName::IPAddress(octets) => match *octets {
+            [a, b, c, d] => Some(IpAddr::from([*a, *b, *c, *d]).to_string()),
+            octets => <[u8; 16]>::try_from(octets).ok().map(|ip| IpAddr::from(ip).to_string()),
+        },
+
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientCertificate
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Option<ClientCertificate>>()
+            .cloned()
+            .flatten()
+            .ok_or_else(|| WebError::Response(StatusCode::UNAUTHORIZED, "client certificate required".to_string()))
+    }
+}
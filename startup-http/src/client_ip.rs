@@ -0,0 +1,136 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{header, Extensions, HeaderMap, StatusCode};
+use axum::Extension;
+use ipnet::IpNet;
+
+use crate::WebError;
+
+/// The list of proxies that are trusted to report the address of the client
+/// in the `Forwarded`, `X-Forwarded-For` or `X-Real-Ip` header.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Arc<Vec<IpNet>>,
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>) -> Self {
+        Self {
+            networks: Arc::new(networks),
+        }
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// The address of the client that sent the request.
+///
+/// If the request was received from a trusted proxy, the address is taken from
+/// the `Forwarded`, `X-Forwarded-For` or `X-Real-Ip` header. Otherwise the address
+/// of the peer is used. Requests received on a unix socket are always
+/// considered to come from a trusted proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        resolve(&parts.headers, &parts.extensions).map(ClientIp).ok_or_else(|| {
+            let message = "Address of client is not available".to_string();
+            WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message)
+        })
+    }
+}
+
+/// Resolves the address of the client like [ClientIp], for middleware that has the whole request.
+pub(crate) fn resolve(headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    let trusted = extensions.get::<TrustedProxies>().cloned().unwrap_or_default();
+
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    match peer {
+        Some(peer) if !trusted.contains(&peer) => Some(peer),
+        _ => resolve_forwarded(headers, &trusted).or(peer),
+    }
+}
+
+/// Resolves the client address from the forwarding headers of a request that was
+/// received from a trusted proxy. Walks the chain of proxies from the right and
+/// returns the first address that is not a trusted proxy.
+fn resolve_forwarded(headers: &HeaderMap, trusted: &TrustedProxies) -> Option<IpAddr> {
+    let mut chain = forwarded_for(headers, header::FORWARDED.as_str(), parse_forwarded_element);
+
+    if chain.is_empty() {
+        chain = forwarded_for(headers, "x-forwarded-for", parse_node);
+    }
+
+    if chain.is_empty() {
+        chain = forwarded_for(headers, "x-real-ip", parse_node);
+    }
+
+    let mut client = None;
+
+    for node in chain.into_iter().rev() {
+        // an obfuscated or unparsable address ends the chain we can trust
+        let ip = node?;
+
+        client = Some(ip);
+
+        if !trusted.contains(&ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+fn forwarded_for(headers: &HeaderMap, name: &str, parse: fn(&str) -> Option<IpAddr>) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(parse)
+        .collect()
+}
+
+/// Parses the `for` parameter of an element of the `Forwarded` header, see RFC 7239.
+fn parse_forwarded_element(element: &str) -> Option<IpAddr> {
+    element
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+        .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+}
+
+/// Parses a node like `192.0.2.43`, `192.0.2.43:47011` or `[2001:db8:cafe::17]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+
+    node.split_once(':')?.0.parse().ok()
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, BoxBody, Full, HttpBody};
+use axum::http::{header, HeaderName, Method, Request, Response, StatusCode};
+use axum::response::IntoResponse;
+use futures_util::future::{BoxFuture, Shared};
+use futures_util::FutureExt;
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::CachedResponse;
+use crate::error::{header_name, ConfigError};
+use crate::WebError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoalesceConfig {
+    /// Request headers that are part of the key, e.g. `Accept` or `Accept-Language`.
+    /// `Authorization` and `Cookie` are always part of the key.
+    #[serde(default)]
+    pub vary: Vec<String>,
+
+    /// Responses with a larger body are not shared, waiting requests
+    /// are handled on their own instead.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: u64,
+}
+
+fn default_max_body_size() -> u64 {
+    1024 * 1024
+}
+
+type Execution = Shared<BoxFuture<'static, Option<CachedResponse>>>;
+
+/// Deduplicates concurrent identical `GET` requests. While a request is handled, identical
+/// requests wait for it and receive a copy of its response instead of calling the handler
+/// again. Add it to expensive routes using `route_layer`, e.g. in front of a [ResponseCacheLayer]
+/// to protect the handler when a popular entry expires. Requests are identical if they share
+/// path, query and the request headers configured in [CoalesceConfig::vary].
+///
+/// Responses without a known body size, like streams, are never shared.
+///
+/// Executed and coalesced requests are counted in the `http.server.coalesce.requests` metric.
+///
+/// [ResponseCacheLayer]: crate::cache::ResponseCacheLayer
+#[derive(Clone)]
+pub struct CoalesceLayer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    name: String,
+    vary: Vec<HeaderName>,
+    max_body_size: u64,
+    in_flight: Mutex<HashMap<String, Execution>>,
+    requests: Counter<u64>,
+}
+
+impl CoalesceLayer {
+    /// Creates a new layer. The name is used to label the metrics.
+    pub fn new(name: impl Into<String>, config: &CoalesceConfig) -> Result<Self, ConfigError> {
+        let mut vary = vec![header::AUTHORIZATION, header::COOKIE];
+
+        for name in &config.vary {
+            let name = header_name("coalesce.vary", name)?;
+            if !vary.contains(&name) {
+                vary.push(name);
+            }
+        }
+
+        let requests = opentelemetry::global::meter("startup-http")
+            .u64_counter("http.server.coalesce.requests")
+            .with_description("Requests handled by the request coalescing layer")
+            .init();
+
+        let inner = Inner {
+            name: name.into(),
+            vary,
+            max_body_size: config.max_body_size,
+            in_flight: Mutex::new(HashMap::new()),
+            requests,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+}
+
+impl<S> tower_layer::Layer<S> for CoalesceLayer {
+    type Service = Coalesce<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Coalesce {
+            inner,
+            coalesce: self.inner.clone(),
+        }
+    }
+}
+
+/// Middleware created by [CoalesceLayer].
+#[derive(Clone)]
+pub struct Coalesce<S> {
+    inner: S,
+    coalesce: Arc<Inner>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for Coalesce<S>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // take the service that was driven to readiness, see tower::Service docs
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let coalesce = self.coalesce.clone();
+
+        Box::pin(async move {
+            if req.method() != Method::GET {
+                return inner.call(req).await;
+            }
+
+            let key = coalesce.key(&req);
+
+            let running = coalesce.in_flight.lock().get(&key).cloned();
+
+            if let Some(execution) = running {
+                coalesce.count("coalesced");
+
+                return match execution.await {
+                    Some(shared) => Ok(into_response(shared)),
+
+                    // the response could not be shared, handle the request on our own
+                    None => inner.call(req).await,
+                };
+            }
+
+            coalesce.count("executed");
+
+            // receives the response if it can not be shared
+            let unshared = Arc::new(Mutex::new(None));
+
+            let execution = coalesce
+                .clone()
+                .execute(key.clone(), inner.call(req), unshared.clone())
+                .boxed()
+                .shared();
+
+            coalesce.in_flight.lock().insert(key, execution.clone());
+
+            match execution.await {
+                Some(shared) => Ok(into_response(shared)),
+                None => Ok(unshared.lock().take().expect("unshared response")),
+            }
+        })
+    }
+}
+
+impl Inner {
+    fn key<B>(&self, req: &Request<B>) -> String {
+        let mut key = req.uri().to_string();
+
+        for name in &self.vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+
+            for value in req.headers().get_all(name) {
+                let _ = write!(key, ":{}", String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+
+        key
+    }
+
+    /// Runs the request and buffers the response so it can be handed to every waiting request.
+    async fn execute(
+        self: Arc<Self>,
+        key: String,
+        response: impl Future<Output = Result<Response<BoxBody>, Infallible>>,
+        unshared: Arc<Mutex<Option<Response<BoxBody>>>>,
+    ) -> Option<CachedResponse> {
+        let Ok(response) = response.await;
+
+        // requests arriving from now on start a new execution
+        self.in_flight.lock().remove(&key);
+
+        // only buffer bodies of a known and limited size
+        let size = response.body().size_hint().exact();
+        if size.is_none_or(|size| size > self.max_body_size) {
+            *unshared.lock() = Some(response);
+            return None;
+        }
+
+        let (parts, body) = response.into_parts();
+
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(err) => {
+                // the status and headers were meant for the complete body, fail every waiting request
+                warn!("Failed to read response body: {}", err);
+                return Some(bad_gateway().await);
+            }
+        };
+
+        Some(CachedResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        })
+    }
+
+    fn count(&self, result: &'static str) {
+        let attributes = [KeyValue::new("coalesce", self.name.clone()), KeyValue::new("result", result)];
+        self.requests.add(&opentelemetry::Context::current(), 1, &attributes);
+    }
+}
+
+async fn bad_gateway() -> CachedResponse {
+    let message = "Failed to read the response of the handler".to_string();
+    let (parts, body) = WebError::Response(StatusCode::BAD_GATEWAY, message).into_response().into_parts();
+
+    CachedResponse {
+        status: parts.status,
+        headers: parts.headers,
+        body: hyper::body::to_bytes(body).await.unwrap_or_default(),
+    }
+}
+
+fn into_response(shared: CachedResponse) -> Response<BoxBody> {
+    let mut response = Response::new(boxed(Full::new(shared.body)));
+    *response.status_mut() = shared.status;
+    *response.headers_mut() = shared.headers;
+    response
+}
@@ -0,0 +1,178 @@
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, Empty};
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use axum::response::{IntoResponse, IntoResponseParts, ResponseParts};
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::serve::etag_matches;
+use crate::WebError;
+
+/// Headers a `304 Not Modified` response keeps from the response it replaces, see RFC 9110, section 15.4.5
+const NOT_MODIFIED_HEADERS: [header::HeaderName; 7] = [
+    header::ETAG,
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::EXPIRES,
+    header::LAST_MODIFIED,
+    header::VARY,
+];
+
+/// An entity tag identifying one version of a response, sent to clients in the `ETag` header.
+/// Clients send it back in the `If-None-Match` header of their next request, which the
+/// [NotModifiedLayer] answers with `304 Not Modified` if the tag still matches.
+///
+/// Use like this: `async fn status() -> impl IntoResponse { (ETag::weak(&version.to_be_bytes()), Json(status)) }`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag(HeaderValue);
+
+impl ETag {
+    /// A strong tag derived from the content of the response. Use it for responses that
+    /// are byte-for-byte identical as long as the tag does not change.
+    pub fn strong(content: impl AsRef<[u8]>) -> Self {
+        Self(HeaderValue::from_str(&format!("\"{}\"", digest(content.as_ref()))).unwrap())
+    }
+
+    /// A weak tag derived from what the response is computed from, like the version of an entity.
+    /// Use it for responses that are equivalent, but may differ in their bytes, e.g. the order of
+    /// the fields of a `HashMap`.
+    pub fn weak(content: impl AsRef<[u8]>) -> Self {
+        Self(HeaderValue::from_str(&format!("W/\"{}\"", digest(content.as_ref()))).unwrap())
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.0.as_bytes().starts_with(b"W/")
+    }
+
+    pub fn header_value(&self) -> &HeaderValue {
+        &self.0
+    }
+
+    /// Checks if the `If-None-Match` header of a request matches this tag, so the
+    /// client already has this version of the response.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .any(|if_none_match| etag_matches(if_none_match, &self.0))
+    }
+}
+
+impl IntoResponseParts for ETag {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.headers_mut().insert(header::ETAG, self.0);
+        Ok(res)
+    }
+}
+
+/// Like [Json](crate::Json), but sends a strong [ETag] computed from the serialized body.
+/// Together with the [NotModifiedLayer], clients polling for a response that did not change
+/// get a `304 Not Modified` without a body.
+///
+/// Serialize maps with a stable order, like a `BTreeMap`, so the same value always gets the same tag.
+///
+/// Use like this: `async fn orders() -> TaggedJson<Vec<Order>> { TaggedJson(repository.orders().await) }`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TaggedJson<T>(pub T);
+
+impl<T: Serialize> IntoResponse for TaggedJson<T> {
+    fn into_response(self) -> axum::response::Response {
+        let body = match serde_json::to_vec(&self.0) {
+            Ok(body) => body,
+            Err(err) => return WebError::from(err).into_response(),
+        };
+
+        let content_type = HeaderValue::from_static("application/json");
+        ([(header::CONTENT_TYPE, content_type)], ETag::strong(&body), body).into_response()
+    }
+}
+
+/// Answers `GET` and `HEAD` requests with `304 Not Modified` and an empty body, if the `ETag`
+/// of the successful response matches the `If-None-Match` header of the request. Handlers only
+/// need to send an [ETag], e.g. using [TaggedJson], the comparison is done by this layer.
+///
+/// Use like this: `router.layer(NotModifiedLayer)`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NotModifiedLayer;
+
+impl<S> tower_layer::Layer<S> for NotModifiedLayer {
+    type Service = NotModified<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        NotModified { inner }
+    }
+}
+
+/// Middleware created by [NotModifiedLayer].
+#[derive(Debug, Clone)]
+pub struct NotModified<S> {
+    inner: S,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for NotModified<S>
+where
+    S: tower_service::Service<Request<B>>,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+{
+    type Response = axum::response::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let conditional = req.method() == Method::GET || req.method() == Method::HEAD;
+        let if_none_match = req
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .cloned()
+            .filter(|_| conditional);
+
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let response = response.await?.into_response();
+
+            let Some(if_none_match) = if_none_match else {
+                return Ok(response);
+            };
+
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+
+            match response.headers().get(header::ETAG) {
+                Some(etag) if etag_matches(&if_none_match, etag) => Ok(not_modified(response.headers())),
+                _ => Ok(response),
+            }
+        })
+    }
+}
+
+/// Answers with `304 Not Modified` and the headers of the response it replaces that a 304 keeps,
+/// the same for every conditional request.
+pub(crate) fn not_modified(headers: &HeaderMap) -> axum::response::Response {
+    let mut response = Response::new(boxed(Empty::new()));
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+
+    for name in NOT_MODIFIED_HEADERS {
+        for value in headers.get_all(&name) {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+
+    response
+}
+
+fn digest(content: &[u8]) -> String {
+    let hash = Sha256::digest(content);
+    hash[..16].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,16 @@
+use axum::routing::get;
+use axum::Router;
+
+use crate::Json;
+
+/// Admin endpoint `GET /admin/config` listing every config value and where it came from,
+/// like the embedded config, a config file or an environment variable, with secrets redacted.
+/// See [config_provenance](startup_base::config_provenance).
+///
+/// Use like this: `admin.merge(startup_http::config_router())`
+pub fn config_router() -> Router {
+    Router::new().route(
+        "/admin/config",
+        get(|| async { Json(startup_base::config_provenance()) }),
+    )
+}
@@ -0,0 +1,263 @@
+use std::convert::Infallible;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, Request};
+use opentelemetry::trace::TraceContextExt;
+use parking_lot::RwLock;
+use serde_json::Value;
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+/// What is known about the request being handled: its request id, trace id and deadline,
+/// and once resolved by the respective layers or extractors, its tenant, the authenticated
+/// principal and the locale. It is created once per request and cheap to clone, so handlers
+/// and the code they call do not need to extract and parse the same data again.
+///
+/// The context is added by the [RequestContextLayer], or created by the first extractor that
+/// needs it. Code without access to the request uses [RequestContext::current].
+///
+/// Use like this: `async fn orders(context: RequestContext) -> Json<Vec<Order>> { repository.orders(&context).await }`
+#[derive(Clone)]
+pub struct RequestContext {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    request_id: Option<String>,
+    trace_id: Option<String>,
+    deadline: Option<Instant>,
+    resolved: RwLock<Resolved>,
+}
+
+#[derive(Default)]
+struct Resolved {
+    tenant: Option<Arc<str>>,
+    principal: Option<Principal>,
+    locale: Option<Arc<str>>,
+}
+
+/// The authenticated subject of a request and the verified claims of its token.
+///
+/// The kind names the layer or extractor that authenticated the request, e.g. `jwt`. Code that
+/// expects a principal of a certain kind must check it, as another layer may have set it.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    kind: &'static str,
+    subject: Option<Arc<str>>,
+    claims: Arc<Value>,
+}
+
+impl Principal {
+    pub fn new(kind: &'static str, subject: Option<&str>, claims: Value) -> Self {
+        Self {
+            kind,
+            subject: subject.map(Into::into),
+            claims: Arc::new(claims),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    pub fn claims(&self) -> &Value {
+        &self.claims
+    }
+}
+
+impl RequestContext {
+    /// Creates the context of a request from its headers and the current trace.
+    pub fn new(headers: &HeaderMap, deadline: Option<Instant>) -> Self {
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let context = opentelemetry::Context::current();
+        let span_context = context.span().span_context().clone();
+        let trace_id = span_context.is_valid().then(|| span_context.trace_id().to_string());
+
+        let inner = Inner {
+            request_id,
+            trace_id,
+            deadline,
+            resolved: RwLock::new(Resolved::default()),
+        };
+
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// The context of the request, created and added to the request if it does not have one yet.
+    pub fn of(parts: &mut Parts) -> Self {
+        if let Some(context) = parts.extensions.get::<RequestContext>() {
+            return context.clone();
+        }
+
+        let context = RequestContext::new(&parts.headers, None);
+        parts.extensions.insert(context.clone());
+        context
+    }
+
+    /// The context of the request handled by the current task, if it runs for one.
+    pub fn current() -> Option<RequestContext> {
+        CURRENT.try_with(RequestContext::clone).ok()
+    }
+
+    /// Runs the future with this context as [RequestContext::current].
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    /// The `x-request-id` header of the request, see [LayersConfig::request_id](crate::LayersConfig).
+    pub fn request_id(&self) -> Option<&str> {
+        self.inner.request_id.as_deref()
+    }
+
+    /// Id of the trace of the request, as hex string.
+    pub fn trace_id(&self) -> Option<&str> {
+        self.inner.trace_id.as_deref()
+    }
+
+    /// Time by which the request should be answered, e.g. to limit the time of queries.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// Time left until the deadline, zero once it passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.inner
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn tenant(&self) -> Option<Arc<str>> {
+        self.inner.resolved.read().tenant.clone()
+    }
+
+    pub fn principal(&self) -> Option<Principal> {
+        self.inner.resolved.read().principal.clone()
+    }
+
+    pub fn locale(&self) -> Option<Arc<str>> {
+        self.inner.resolved.read().locale.clone()
+    }
+
+    /// Sets the tenant, once resolved by the layer or extractor responsible for it.
+    pub fn set_tenant(&self, tenant: &str) {
+        self.inner.resolved.write().tenant = Some(tenant.into());
+    }
+
+    /// Sets the principal, once authenticated by the layer or extractor responsible for it.
+    pub fn set_principal(&self, principal: Principal) {
+        self.inner.resolved.write().principal = Some(principal);
+    }
+
+    /// Sets the locale as language tag, once resolved by the layer or extractor responsible for it.
+    pub fn set_locale(&self, locale: &str) {
+        self.inner.resolved.write().locale = Some(locale.into());
+    }
+}
+
+impl Debug for RequestContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let resolved = self.inner.resolved.read();
+
+        f.debug_struct("RequestContext")
+            .field("request_id", &self.inner.request_id)
+            .field("trace_id", &self.inner.trace_id)
+            .field("deadline", &self.inner.deadline)
+            .field("tenant", &resolved.tenant)
+            .field("subject", &resolved.principal.as_ref().and_then(Principal::subject))
+            .field("locale", &resolved.locale)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestContext
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(RequestContext::of(parts))
+    }
+}
+
+/// Creates the [RequestContext] of every request and runs the request with it as
+/// [RequestContext::current]. Add it inside the tracing layers, so the context knows the
+/// trace id, as done by [standard_layers](crate::standard_layers).
+///
+/// Use like this: `router.layer(RequestContextLayer::new().with_timeout(Duration::from_secs(30)))`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestContextLayer {
+    timeout: Option<Duration>,
+}
+
+impl RequestContextLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the deadline of requests to the time they were received plus the timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+impl<S> tower_layer::Layer<S> for RequestContextLayer {
+    type Service = RequestContextService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestContextService {
+            inner,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// Middleware created by [RequestContextLayer].
+#[derive(Debug, Clone)]
+pub struct RequestContextService<S> {
+    inner: S,
+    timeout: Option<Duration>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for RequestContextService<S>
+where
+    S: tower_service::Service<Request<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures_util::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // created here and not in the future, as the trace of the request is only current during call
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let context = RequestContext::new(req.headers(), deadline);
+
+        req.extensions_mut().insert(context.clone());
+
+        Box::pin(context.scope(self.inner.call(req)))
+    }
+}
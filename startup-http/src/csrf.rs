@@ -0,0 +1,249 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::async_trait;
+use axum::body::Body;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use startup_base::signature::constant_time_eq;
+
+use crate::session::Session;
+use crate::{is_path_prefix, WebError};
+
+/// Key of the token in the [Session].
+const SESSION_KEY: &str = "csrf_token";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsrfConfig {
+    /// Header with the token, for requests sent by scripts.
+    #[serde(default = "default_header")]
+    pub header: String,
+
+    /// Field with the token in `application/x-www-form-urlencoded` forms.
+    #[serde(default = "default_form_field")]
+    pub form_field: String,
+
+    /// Forms with a larger body are rejected.
+    #[serde(default = "default_max_form_size")]
+    pub max_form_size: usize,
+
+    /// Path prefixes that are not protected, e.g. `/api` for an api authenticated by
+    /// bearer tokens instead of the session cookie.
+    #[serde(default)]
+    pub exempt: Vec<String>,
+}
+
+fn default_header() -> String {
+    "x-csrf-token".to_string()
+}
+
+fn default_form_field() -> String {
+    "_csrf".to_string()
+}
+
+fn default_max_form_size() -> usize {
+    1024 * 1024
+}
+
+impl Default for CsrfConfig {
+    fn default() -> Self {
+        Self {
+            header: default_header(),
+            form_field: default_form_field(),
+            max_form_size: default_max_form_size(),
+            exempt: Vec::new(),
+        }
+    }
+}
+
+/// The csrf token of the session, created on first use. Put it into the forms of a page
+/// as hidden field, or into a `meta` tag for scripts to send it in the header.
+///
+/// Use like this: `async fn page(token: CsrfToken) -> Html<String> { render("page.html", token.value()) }`
+#[derive(Debug, Clone)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    pub fn value(&self) -> &str {
+        &self.0
+    }
+
+    /// Replaces the token of the session with a new one, e.g. after a login.
+    pub fn renew(session: &Session) -> Result<CsrfToken, serde_json::Error> {
+        let token = format!("{:032x}", rand::random::<u128>());
+        session.insert(SESSION_KEY, &token)?;
+        Ok(CsrfToken(token))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state).await?;
+
+        match session.get::<String>(SESSION_KEY) {
+            Some(token) => Ok(CsrfToken(token)),
+            None => Ok(CsrfToken::renew(&session)?),
+        }
+    }
+}
+
+/// Protects the session of a browser against cross-site request forgery using the
+/// synchronizer token pattern: requests with a method other than `GET`, `HEAD`, `OPTIONS`
+/// and `TRACE` must send the [CsrfToken] of their session in the header or in the form
+/// field of the config. Other requests are rejected with `403 Forbidden`.
+///
+/// Add it inside the [SessionLayer](crate::session::SessionLayer), it needs the session of
+/// the request. Paths in `exempt`, or added with [CsrfLayer::exempt], are not protected.
+///
+/// Use like this: `router.layer(CsrfLayer::new(&config.csrf).exempt("/webhooks")).layer(session_layer)`
+#[derive(Clone)]
+pub struct CsrfLayer {
+    config: Arc<CsrfConfig>,
+}
+
+impl CsrfLayer {
+    pub fn new(config: &CsrfConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+        }
+    }
+
+    /// Does not protect the path and the paths below it.
+    pub fn exempt(mut self, prefix: &str) -> Self {
+        Arc::make_mut(&mut self.config).exempt.push(prefix.to_string());
+        self
+    }
+}
+
+impl<S> tower_layer::Layer<S> for CsrfLayer {
+    type Service = CsrfService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CsrfService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// Middleware created by [CsrfLayer].
+#[derive(Clone)]
+pub struct CsrfService<S> {
+    inner: S,
+    config: Arc<CsrfConfig>,
+}
+
+impl<S> tower_service::Service<Request<Body>> for CsrfService<S>
+where
+    S: tower_service::Service<Request<Body>> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // the inner service was polled ready, keep it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let path = req.uri().path();
+            let exempt = config.exempt.iter().any(|prefix| is_path_prefix(prefix, path));
+
+            if is_safe(req.method()) || exempt {
+                return inner.call(req).await.map(IntoResponse::into_response);
+            }
+
+            let Some(session) = req.extensions().get::<Session>().cloned() else {
+                let message = "csrf protection requires the SessionLayer".to_string();
+                return Ok(WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message).into_response());
+            };
+
+            let expected = session.get::<String>(SESSION_KEY);
+
+            let (req, token) = match header_token(req.headers(), &config.header) {
+                Some(token) => (req, Some(token)),
+                None => match form_token(req, &config).await {
+                    Ok(result) => result,
+                    Err(err) => return Ok(err.into_response()),
+                },
+            };
+
+            let valid = match (expected, token) {
+                (Some(expected), Some(token)) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+                _ => false,
+            };
+
+            if !valid {
+                debug!(
+                    "Rejected {} {} without a valid csrf token",
+                    req.method(),
+                    req.uri().path()
+                );
+                let message = "missing or invalid csrf token".to_string();
+                return Ok(WebError::Response(StatusCode::FORBIDDEN, message).into_response());
+            }
+
+            inner.call(req).await.map(IntoResponse::into_response)
+        })
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE)
+}
+
+fn header_token(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get(name)?.to_str().ok().map(str::to_string)
+}
+
+/// Reads the token from the body of a form. The body is put back into the request for the handler.
+async fn form_token(req: Request<Body>, config: &CsrfConfig) -> Result<(Request<Body>, Option<String>), WebError> {
+    let is_form = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+    if !is_form {
+        return Ok((req, None));
+    }
+
+    let (parts, body) = req.into_parts();
+
+    let body = match hyper::body::to_bytes(http_body::Limited::new(body, config.max_form_size)).await {
+        Ok(body) => body,
+        Err(err) if err.is::<http_body::LengthLimitError>() => {
+            let message = format!("form is larger than {} bytes", config.max_form_size);
+            return Err(WebError::Response(StatusCode::PAYLOAD_TOO_LARGE, message));
+        }
+        Err(err) => {
+            let message = format!("Failed to read the request body: {}", err);
+            return Err(WebError::Response(StatusCode::BAD_REQUEST, message));
+        }
+    };
+
+    let token = form_urlencoded::parse(&body)
+        .find(|(name, _)| *name == config.form_field)
+        .map(|(_, value)| value.into_owned());
+
+    Ok((Request::from_parts(parts, Body::from(body)), token))
+}
@@ -0,0 +1,180 @@
+use std::io::Read;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures_util::future::BoxFuture;
+
+use crate::WebError;
+
+/// Decompresses request bodies sent with `Content-Encoding: gzip` or `deflate`, so handlers
+/// and extractors see the plain body. Requests with any other encoding are rejected with
+/// `415 Unsupported Media Type`.
+///
+/// Compressed bodies are read into memory and decompressed up to `limit` bytes on a blocking
+/// thread, so the runtime keeps serving other requests. Bodies that are larger, either compressed
+/// or after decompression, are rejected with `413 Payload Too Large` before more memory is used,
+/// which protects against zip bombs.
+///
+/// Use like this: `router.layer(InflateBodyLayer::new(2 * 1024 * 1024))`
+#[derive(Debug, Clone, Copy)]
+pub struct InflateBodyLayer {
+    limit: usize,
+}
+
+impl InflateBodyLayer {
+    pub fn new(limit: usize) -> Self {
+        Self { limit }
+    }
+}
+
+impl<S> tower_layer::Layer<S> for InflateBodyLayer {
+    type Service = InflateBody<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InflateBody {
+            inner,
+            limit: self.limit,
+        }
+    }
+}
+
+/// Middleware created by [InflateBodyLayer].
+#[derive(Debug, Clone)]
+pub struct InflateBody<S> {
+    inner: S,
+    limit: usize,
+}
+
+impl<S> tower_service::Service<Request<Body>> for InflateBody<S>
+where
+    S: tower_service::Service<Request<Body>> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // the inner service was polled ready, keep it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let limit = self.limit;
+
+        Box::pin(async move {
+            let Some(encoding) = Encoding::of(req.headers()) else {
+                return inner.call(req).await.map(IntoResponse::into_response);
+            };
+
+            let (mut parts, body) = req.into_parts();
+
+            let body = match decompress(encoding, body, limit).await {
+                Ok(body) => body,
+                Err(err) => return Ok(err.into_response()),
+            };
+
+            parts.headers.remove(header::CONTENT_ENCODING);
+            parts
+                .headers
+                .insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+
+            let response = inner.call(Request::from_parts(parts, Body::from(body))).await;
+            response.map(IntoResponse::into_response)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Gzip,
+    Deflate,
+    Unsupported,
+}
+
+impl Encoding {
+    /// The encoding of the body, none if it is not encoded.
+    fn of(headers: &HeaderMap) -> Option<Encoding> {
+        let value = headers.get(header::CONTENT_ENCODING)?;
+
+        let encoding = match value.to_str().map(|value| value.trim().to_ascii_lowercase()) {
+            Ok(value) if value.is_empty() || value == "identity" => return None,
+            Ok(value) if value == "gzip" || value == "x-gzip" => Encoding::Gzip,
+            Ok(value) if value == "deflate" => Encoding::Deflate,
+            _ => Encoding::Unsupported,
+        };
+
+        Some(encoding)
+    }
+}
+
+async fn decompress(encoding: Encoding, body: Body, limit: usize) -> Result<Vec<u8>, WebError> {
+    if let Encoding::Unsupported = encoding {
+        let message = "Content encoding of the request is not supported, use gzip or deflate".to_string();
+        return Err(WebError::Response(StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+    }
+
+    let compressed = match hyper::body::to_bytes(http_body::Limited::new(body, limit)).await {
+        Ok(compressed) => compressed,
+        Err(err) if err.is::<http_body::LengthLimitError>() => return Err(too_large(limit)),
+        Err(err) => {
+            let message = format!("Failed to read the request body: {}", err);
+            return Err(WebError::Response(StatusCode::BAD_REQUEST, message));
+        }
+    };
+
+    // inflating up to the limit takes a while, so it must not block the runtime
+    let inflated = tokio::task::spawn_blocking(move || inflate(encoding, &compressed, limit)).await;
+
+    let decompressed = match inflated {
+        Ok(Ok(decompressed)) => decompressed,
+
+        Ok(Err(err)) => {
+            debug!("Failed to decompress {:?} request body: {}", encoding, err);
+            let message = format!("Failed to decompress the request body: {}", err);
+            return Err(WebError::Response(StatusCode::BAD_REQUEST, message));
+        }
+
+        Err(err) => {
+            let message = format!("Failed to decompress the request body: {}", err);
+            return Err(WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message));
+        }
+    };
+
+    if decompressed.len() > limit {
+        debug!(
+            "Rejecting {:?} request body larger than {} bytes after decompression",
+            encoding, limit
+        );
+        return Err(too_large(limit));
+    }
+
+    Ok(decompressed)
+}
+
+/// Decompresses the body, reading one byte more than the limit to tell a body of exactly the limit
+/// from a larger one.
+fn inflate(encoding: Encoding, compressed: &[u8], limit: usize) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    let take = limit as u64 + 1;
+
+    match encoding {
+        Encoding::Gzip => GzDecoder::new(compressed).take(take).read_to_end(&mut decompressed)?,
+        Encoding::Deflate => ZlibDecoder::new(compressed).take(take).read_to_end(&mut decompressed)?,
+        Encoding::Unsupported => unreachable!(),
+    };
+
+    Ok(decompressed)
+}
+
+fn too_large(limit: usize) -> WebError {
+    let message = format!("Request body is larger than {} bytes", limit);
+    WebError::Response(StatusCode::PAYLOAD_TOO_LARGE, message)
+}
@@ -0,0 +1,177 @@
+use std::borrow::Cow;
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::body::{boxed, Bytes, Full};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, MethodRouter};
+use percent_encoding::percent_decode_str;
+use tower_http::set_header::SetResponseHeaderLayer;
+
+#[doc(hidden)]
+pub use rust_embed;
+pub use rust_embed::RustEmbed;
+
+use crate::conditional::not_modified;
+use crate::serve::{byte_range, etag_matches, if_range_matches, ByteRange, Caching};
+
+/// Embeds the given directory into the binary and serves its files as static files.
+/// The directory is resolved relative to the `Cargo.toml` of the calling crate.
+///
+/// Use like this: `.nest_service("/public", serve_embedded!("files/pub"))`
+///
+/// Files are served with the same caching, compression and range behaviour as [serve_static](crate::serve_static).
+/// In debug builds, files are read from disk on every request.
+///
+/// If `startup-http` is renamed in the `Cargo.toml` of the calling crate, pass the path of its
+/// `rust_embed` re-export: `serve_embedded!("files/pub", crate_path = "http::embed::rust_embed")`
+#[macro_export]
+macro_rules! serve_embedded {
+    ( $folder:literal, crate_path = $crate_path:literal ) => {
+        $crate::serve_embedded!($folder, $crate::Caching::Immutable, crate_path = $crate_path)
+    };
+
+    ( $folder:literal, $caching:expr, crate_path = $crate_path:literal ) => {{
+        #[derive($crate::embed::RustEmbed)]
+        #[folder = $folder]
+        #[crate_path = $crate_path]
+        struct Assets;
+
+        $crate::embed::serve_embedded_with::<Assets>($caching)
+    }};
+
+    ( $folder:literal ) => {
+        $crate::serve_embedded!($folder, $crate::Caching::Immutable)
+    };
+
+    ( $folder:literal, $caching:expr ) => {
+        $crate::serve_embedded!($folder, $caching, crate_path = "startup_http::embed::rust_embed")
+    };
+}
+
+/// Serves the files of the given [RustEmbed] type using the given caching strategy.
+pub fn serve_embedded_with<E: RustEmbed + Send + Sync + 'static>(caching: Caching) -> MethodRouter {
+    let add_cache_control = SetResponseHeaderLayer::overriding(header::CACHE_CONTROL, caching.cache_control());
+
+    get(serve_embedded_file::<E>).layer(add_cache_control)
+}
+
+async fn serve_embedded_file<E: RustEmbed>(uri: Uri, headers: HeaderMap) -> Response {
+    let Ok(path) = percent_decode_str(uri.path()).decode_utf8() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let mut path = path.trim_start_matches('/').to_owned();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    }
+
+    // prefer a precompressed variant of the file, same as ServeDir::precompressed_gzip
+    let gzip_file = E::get(&format!("{}.gz", path));
+    let has_gzip_variant = gzip_file.is_some();
+
+    let (file, encoding) = match gzip_file.filter(|_| accepts_gzip(&headers)) {
+        Some(file) => (file, Some("gzip")),
+        None => match E::get(&path) {
+            Some(file) => (file, None),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+    };
+
+    let hash = file.metadata.sha256_hash();
+    let etag = HeaderValue::from_str(&format!("\"{}\"", hex(&hash[..16]))).unwrap();
+
+    let last_modified = file
+        .metadata
+        .last_modified()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+    let mut response_headers = HeaderMap::new();
+
+    if let Some(last_modified) = last_modified {
+        let value = HeaderValue::from_str(&httpdate::fmt_http_date(last_modified)).unwrap();
+        response_headers.insert(header::LAST_MODIFIED, value);
+    }
+
+    // kept by a 304 as well
+    response_headers.insert(header::ETAG, etag.clone());
+
+    if has_gzip_variant {
+        response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(if_none_match, &etag) {
+            return not_modified(&response_headers);
+        }
+    } else if let Some(last_modified) = last_modified {
+        let if_modified_since = headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok());
+
+        if if_modified_since.is_some_and(|since| since >= last_modified) {
+            return not_modified(&response_headers);
+        }
+    }
+
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(mime.as_ref()).unwrap());
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    if let Some(encoding) = encoding {
+        response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
+
+    let body = match file.data {
+        Cow::Borrowed(data) => Bytes::from_static(data),
+        Cow::Owned(data) => Bytes::from(data),
+    };
+
+    let range = headers.get(header::RANGE).filter(|_| {
+        let last_modified = response_headers.get(header::LAST_MODIFIED);
+        match headers.get(header::IF_RANGE) {
+            Some(if_range) => if_range_matches(if_range, &etag, last_modified),
+            None => true,
+        }
+    });
+
+    let size = body.len() as u64;
+
+    match byte_range(range, size) {
+        ByteRange::Full => (response_headers, boxed(Full::new(body))).into_response(),
+
+        ByteRange::Partial(range) => {
+            let content_range = format!("bytes {}-{}/{}", range.start(), range.end(), size);
+            response_headers.insert(header::CONTENT_RANGE, HeaderValue::from_str(&content_range).unwrap());
+
+            let body = body.slice(*range.start() as usize..=*range.end() as usize);
+            (StatusCode::PARTIAL_CONTENT, response_headers, boxed(Full::new(body))).into_response()
+        }
+
+        ByteRange::Unsatisfiable => {
+            let content_range = format!("bytes */{}", size);
+            response_headers.insert(header::CONTENT_RANGE, HeaderValue::from_str(&content_range).unwrap());
+
+            (StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response()
+        }
+    }
+}
+
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let rejected = parts.any(|param| param == "q=0" || param == "q=0.0" || param == "q=0.00");
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+        })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,12 +1,23 @@
 use std::fmt::{Debug, Write};
 
-use axum::http::StatusCode;
+use axum::http::{HeaderName, HeaderValue, StatusCode};
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::Json;
 use eyre::Report;
+use opentelemetry::metrics::Counter;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::KeyValue;
+use opentelemetry_semantic_conventions::trace::{EXCEPTION_MESSAGE, EXCEPTION_STACKTRACE};
 use serde::Serialize;
 
+lazy_static::lazy_static! {
+    static ref ERRORS: Counter<u64> = opentelemetry::global::meter("startup-http")
+        .u64_counter("http.server.errors")
+        .with_description("Errors answered with their status code, like a failing database query")
+        .init();
+}
+
 pub trait WebErrorExt<T> {
     fn with_status_code(self, code: StatusCode) -> Result<T, WebError>;
 }
@@ -15,6 +26,14 @@ pub trait WebErrorExt<T> {
 pub enum WebError {
     Response(StatusCode, String),
     WithStatusCode(StatusCode, Report),
+
+    /// The request contained a value that could not be deserialized.
+    /// `field` is the path to the value, if known.
+    InvalidField {
+        status: StatusCode,
+        field: Option<String>,
+        message: String,
+    },
 }
 
 impl<T: Into<Report>> From<T> for WebError {
@@ -29,16 +48,30 @@ impl<T, E: Into<Report>> WebErrorExt<T> for Result<T, E> {
     }
 }
 
-impl IntoResponse for WebError {
-    fn into_response(self) -> Response {
+impl WebError {
+    /// Converts the error into the status code and body of its response.
+    pub(crate) fn into_error_response(self) -> (StatusCode, ErrorResponse) {
         match self {
             WebError::Response(status, message) => {
                 let response = ErrorResponse {
                     status: status.as_u16(),
                     message,
+                    field: None,
+                    trace_id: None,
                 };
 
-                (status, Json(response)).into_response()
+                (status, response)
+            }
+
+            WebError::InvalidField { status, field, message } => {
+                let response = ErrorResponse {
+                    status: status.as_u16(),
+                    message,
+                    field,
+                    trace_id: None,
+                };
+
+                (status, response)
             }
 
             WebError::WithStatusCode(status, err) => {
@@ -52,19 +85,93 @@ impl IntoResponse for WebError {
 
                 info!("{}", message);
 
+                let trace_id = record_error(status, &err);
+
                 let response = ErrorResponse {
                     status: status.as_u16(),
                     message,
+                    field: None,
+                    trace_id,
                 };
 
-                (status, Json(response)).into_response()
+                (status, response)
             }
         }
     }
 }
 
+/// Adds the error chain as exception event to the span of the request and counts the error
+/// by status code. Returns the id of the trace, so clients can report it.
+fn record_error(status: StatusCode, err: &Report) -> Option<String> {
+    let attributes = [KeyValue::new("status", i64::from(status.as_u16()))];
+    ERRORS.add(&opentelemetry::Context::current(), 1, &attributes);
+
+    let context = opentelemetry::Context::current();
+    let span = context.span();
+
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return None;
+    }
+
+    let attributes = vec![
+        EXCEPTION_MESSAGE.string(format!("{:#}", err)),
+        EXCEPTION_STACKTRACE.string(format!("{:?}", err)),
+    ];
+
+    span.add_event("exception", attributes);
+
+    Some(span_context.trace_id().to_string())
+}
+
+impl IntoResponse for WebError {
+    fn into_response(self) -> Response {
+        let (status, response) = self.into_error_response();
+        (status, Json(response)).into_response()
+    }
+}
+
+/// The json body of every error response.
 #[derive(Serialize)]
-struct ErrorResponse {
-    status: u16,
-    message: String,
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    /// The http status code of the response.
+    pub status: u16,
+
+    /// A human readable description of the error.
+    pub message: String,
+
+    /// The path to the request value that could not be deserialized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+
+    /// The id of the trace of the request, to find it in the logs and traces.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+/// Returned when a layer is created from a config that can not be used.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("{field}: invalid header name {value:?}")]
+    InvalidHeaderName { field: &'static str, value: String },
+
+    #[error("{field}: invalid header value {value:?}")]
+    InvalidHeaderValue { field: &'static str, value: String },
+
+    #[error("{field}: {message}")]
+    Invalid { field: &'static str, message: String },
+}
+
+/// Parses a header name of the config, like `cache.vary`.
+pub(crate) fn header_name(field: &'static str, value: &str) -> Result<HeaderName, ConfigError> {
+    HeaderName::try_from(value).map_err(|_| ConfigError::InvalidHeaderName {
+        field,
+        value: value.to_string(),
+    })
+}
+
+/// Creates a header value from the config, like `versioning.link`.
+pub(crate) fn header_value(field: &'static str, value: String) -> Result<HeaderValue, ConfigError> {
+    HeaderValue::try_from(value.as_str()).map_err(|_| ConfigError::InvalidHeaderValue { field, value })
 }
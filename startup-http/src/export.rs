@@ -0,0 +1,293 @@
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::{Bytes, StreamBody};
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use futures_util::Stream;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::KeyValue;
+use serde::Serialize;
+use tracing::Span;
+
+/// Rows are sent in chunks of about this size, or earlier if the next row is not available yet.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Streams the rows to the client as a json array, without collecting them in memory.
+///
+/// The request span stays open until the last row was sent or the client disconnected, and
+/// records the number of rows sent as `export.rows`. Rows are only read from the stream as
+/// fast as the client receives them. If reading a row fails, the response is aborted, so
+/// the client sees an incomplete response instead of a truncated but valid document.
+///
+/// Use like this: `async fn orders(db: Db) -> impl IntoResponse { json_array(sqlx::query_as::<_, Order>(SQL).fetch(db)) }`
+pub fn json_array<S, T, E>(rows: S) -> Export<S>
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Display,
+{
+    Export::new(rows, Encoder::JsonArray { first: true }, "application/json")
+}
+
+/// Same as [json_array], but writes every row as a json document on its own line.
+pub fn ndjson<S, T, E>(rows: S) -> Export<S>
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Display,
+{
+    Export::new(rows, Encoder::Ndjson, "application/x-ndjson")
+}
+
+/// Same as [json_array], but writes the rows as CSV. The header is taken from the field names
+/// of the first row, so rows must be structs or maps without nested values.
+#[cfg(feature = "csv")]
+pub fn csv<S, T, E>(rows: S) -> Export<S>
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Display,
+{
+    Export::new(rows, Encoder::Csv { first: true }, "text/csv; charset=utf-8")
+}
+
+/// Turns an iterator into a stream of rows for [json_array], [ndjson] and [csv]. The iterator
+/// is advanced while the rows are sent, so it can produce its values lazily.
+///
+/// Use like this: `json_array(rows((0..1_000_000).map(|id| Row { id })))`
+pub fn rows<I: IntoIterator>(rows: I) -> impl Stream<Item = Result<I::Item, Infallible>> {
+    futures_util::stream::iter(rows.into_iter().map(Ok))
+}
+
+/// A response streaming rows, see [json_array].
+pub struct Export<S> {
+    stream: ExportStream<S>,
+    content_type: &'static str,
+    filename: Option<String>,
+}
+
+impl<S> Export<S> {
+    fn new(rows: S, encoder: Encoder, content_type: &'static str) -> Self {
+        let stream = ExportStream {
+            inner: rows,
+            encoder,
+            buffer: Vec::new(),
+            started: false,
+            count: 0,
+            state: State::Streaming,
+            span: Span::current(),
+            otel_context: opentelemetry::Context::current(),
+        };
+
+        Self {
+            stream,
+            content_type,
+            filename: None,
+        }
+    }
+
+    /// Asks browsers to download the response as a file with this name.
+    pub fn attachment(mut self, filename: impl Into<String>) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+}
+
+impl<S, T, E> IntoResponse for Export<S>
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Display,
+{
+    fn into_response(self) -> Response {
+        let mut response = StreamBody::new(self.stream).into_response();
+
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(self.content_type));
+
+        if let Some(filename) = self.filename {
+            let filename = filename.replace(['"', '\\'], "_");
+
+            if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename)) {
+                headers.insert(header::CONTENT_DISPOSITION, value);
+            }
+        }
+
+        response
+    }
+}
+
+enum Encoder {
+    JsonArray {
+        first: bool,
+    },
+
+    Ndjson,
+
+    #[cfg(feature = "csv")]
+    Csv {
+        first: bool,
+    },
+}
+
+impl Encoder {
+    fn start(&mut self, buffer: &mut Vec<u8>) {
+        if let Encoder::JsonArray { .. } = self {
+            buffer.push(b'[');
+        }
+    }
+
+    fn row<T: Serialize>(&mut self, buffer: &mut Vec<u8>, row: &T) -> Result<(), String> {
+        match self {
+            Encoder::JsonArray { first } => {
+                if !std::mem::take(first) {
+                    buffer.push(b',');
+                }
+
+                serde_json::to_writer(buffer, row).map_err(|err| err.to_string())
+            }
+
+            Encoder::Ndjson => {
+                serde_json::to_writer(&mut *buffer, row).map_err(|err| err.to_string())?;
+                buffer.push(b'\n');
+                Ok(())
+            }
+
+            #[cfg(feature = "csv")]
+            Encoder::Csv { first } => {
+                // the header is written with the first row
+                let mut writer = ::csv::WriterBuilder::new()
+                    .has_headers(std::mem::take(first))
+                    .buffer_capacity(1024)
+                    .from_writer(buffer);
+
+                writer.serialize(row).map_err(|err| err.to_string())?;
+                writer.flush().map_err(|err| err.to_string())
+            }
+        }
+    }
+
+    fn finish(&mut self, buffer: &mut Vec<u8>) {
+        if let Encoder::JsonArray { .. } = self {
+            buffer.push(b']');
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Streaming,
+    Finished,
+    Failed,
+}
+
+/// Body of an [Export].
+#[pin_project::pin_project(PinnedDrop)]
+pub struct ExportStream<S> {
+    #[pin]
+    inner: S,
+    encoder: Encoder,
+    buffer: Vec<u8>,
+    started: bool,
+    count: u64,
+    state: State,
+    span: Span,
+    otel_context: opentelemetry::Context,
+}
+
+impl<S, T, E> Stream for ExportStream<S>
+where
+    S: Stream<Item = Result<T, E>>,
+    T: Serialize,
+    E: Display,
+{
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        if *this.state != State::Streaming {
+            return Poll::Ready(None);
+        }
+
+        if !std::mem::replace(this.started, true) {
+            this.encoder.start(this.buffer);
+        }
+
+        let count = *this.count;
+
+        loop {
+            let row = match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(row))) => row,
+
+                Poll::Ready(Some(Err(err))) => {
+                    let _entered = this.span.enter();
+                    warn!(
+                        "Aborting export after {} rows, reading a row failed: {}",
+                        this.count, err
+                    );
+
+                    *this.state = State::Failed;
+                    return Poll::Ready(Some(Err(io::Error::other(err.to_string()))));
+                }
+
+                Poll::Ready(None) => {
+                    *this.state = State::Finished;
+                    this.encoder.finish(this.buffer);
+                    break;
+                }
+
+                // send what we have while waiting for the next row
+                Poll::Pending if this.buffer.is_empty() => return Poll::Pending,
+                Poll::Pending => break,
+            };
+
+            if let Err(err) = this.encoder.row(this.buffer, &row) {
+                let _entered = this.span.enter();
+                warn!(
+                    "Aborting export after {} rows, a row failed to serialize: {}",
+                    this.count, err
+                );
+
+                *this.state = State::Failed;
+                return Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::InvalidData, err))));
+            }
+
+            *this.count += 1;
+
+            if this.buffer.len() >= CHUNK_SIZE {
+                break;
+            }
+        }
+
+        if *this.count != count {
+            let otel_span = this.otel_context.span();
+            otel_span.set_attribute(KeyValue::new("export.rows", *this.count as i64));
+        }
+
+        Poll::Ready(Some(Ok(Bytes::from(std::mem::take(this.buffer)))))
+    }
+}
+
+#[pin_project::pinned_drop]
+impl<S> PinnedDrop for ExportStream<S> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+
+        let _entered = this.span.enter();
+
+        match this.state {
+            State::Finished => debug!("Export finished after {} rows", this.count),
+            State::Failed => {}
+            State::Streaming => {
+                // the request span ends once the last reference to it is dropped
+                this.otel_context.span().add_event("export.disconnected", Vec::new());
+                debug!("Client disconnected from export after {} rows", this.count);
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::async_trait;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_path_to_error::Segment;
+
+use crate::WebError;
+
+/// Like [axum::Json], but rejects invalid requests with a [WebError]
+/// that names the field that failed to deserialize.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for Json<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json_content_type(req.headers()) {
+            let message = "Expected request with `Content-Type: application/json`".to_string();
+            return Err(WebError::Response(StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+        }
+
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|err| WebError::Response(err.status(), err.body_text()))?;
+
+        deserialize_json(&bytes).map(Json)
+    }
+}
+
+/// Deserializes a json body, naming the field that failed to deserialize.
+pub(crate) fn deserialize_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WebError> {
+    let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+
+    serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let field = field_of(err.path());
+        let err = err.into_inner();
+
+        // the body is valid json but does not match the expected type
+        let status = if err.is_data() {
+            StatusCode::UNPROCESSABLE_ENTITY
+        } else {
+            StatusCode::BAD_REQUEST
+        };
+
+        WebError::InvalidField {
+            status,
+            field,
+            message: format!("Failed to deserialize the JSON body: {}", err),
+        }
+    })
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Like [axum::extract::Query], but rejects invalid requests with a [WebError]
+/// that names the parameter that failed to deserialize.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+
+        match serde_path_to_error::deserialize(deserializer) {
+            Ok(value) => Ok(Query(value)),
+            Err(err) => Err(WebError::InvalidField {
+                status: StatusCode::BAD_REQUEST,
+                field: field_of(err.path()),
+                message: format!("Failed to deserialize query string: {}", err.inner()),
+            }),
+        }
+    }
+}
+
+impl<T> Deref for Query<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Formats the path to the failed field, or None if the path is not known,
+/// e.g. because deserialization failed at the top level.
+fn field_of(path: &serde_path_to_error::Path) -> Option<String> {
+    if path.iter().all(|segment| matches!(segment, Segment::Unknown)) {
+        return None;
+    }
+
+    Some(path.to_string())
+}
+
+pub(crate) fn is_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+
+    essence.eq_ignore_ascii_case("application/json")
+        || essence.starts_with("application/") && essence.ends_with("+json")
+}
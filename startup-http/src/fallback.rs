@@ -0,0 +1,77 @@
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+
+use axum::body::{BoxBody, HttpBody};
+use axum::http::{header, Request, Response, StatusCode};
+use axum::response::IntoResponse;
+use futures_util::future::BoxFuture;
+
+use crate::WebError;
+
+/// Renders the empty `404 Not Found` and `405 Method Not Allowed` responses of axum in the
+/// standard error format. Responses with a body, e.g. from a custom fallback, are not changed.
+/// The `Allow` header of a `405 Method Not Allowed` response is kept.
+///
+/// This layer is added automatically by [crate::run_server].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ErrorFallbackLayer;
+
+impl<S> tower_layer::Layer<S> for ErrorFallbackLayer {
+    type Service = ErrorFallback<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ErrorFallback { inner }
+    }
+}
+
+/// Middleware created by [ErrorFallbackLayer].
+#[derive(Debug, Clone)]
+pub struct ErrorFallback<S> {
+    inner: S,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for ErrorFallback<S>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let response = response.await?;
+
+            let is_empty = response.body().size_hint().exact() == Some(0)
+                && !response.headers().contains_key(header::CONTENT_TYPE);
+
+            if !is_empty {
+                return Ok(response);
+            }
+
+            let message = match response.status() {
+                StatusCode::NOT_FOUND => format!("No route found for {} {}", method, path),
+                StatusCode::METHOD_NOT_ALLOWED => format!("Method {} is not allowed for {}", method, path),
+                _ => return Ok(response),
+            };
+
+            let (mut parts, _) = response.into_parts();
+            parts.headers.remove(header::CONTENT_LENGTH);
+
+            let (error_parts, body) = WebError::Response(parts.status, message).into_response().into_parts();
+            parts.headers.extend(error_parts.headers);
+
+            Ok(Response::from_parts(parts, body))
+        })
+    }
+}
@@ -0,0 +1,99 @@
+use async_graphql::extensions::Tracing;
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{ErrorExtensions, ObjectType, Schema, SchemaBuilder, SubscriptionType};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::body::Body;
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+
+use crate::WebError;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GraphQLConfig {
+    /// Serve the GraphiQL playground at `{path}/playground`. It lets anyone who can reach the
+    /// service explore the schema and run queries from the browser, so only enable it where
+    /// that is fine, like in a local or test environment.
+    #[serde(default)]
+    pub playground: bool,
+}
+
+type GraphQLRejection = <GraphQLRequest as FromRequest<(), Body>>::Rejection;
+
+/// Starts building a schema that records a tracing span for every resolver. The spans
+/// are children of the span of the http request.
+///
+/// Use like this: `let schema = graphql::schema(Query, EmptyMutation, EmptySubscription).finish()`
+pub fn schema<Q, M, S>(query: Q, mutation: M, subscription: S) -> SchemaBuilder<Q, M, S>
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static,
+{
+    Schema::build(query, mutation, subscription).extension(Tracing)
+}
+
+/// Serves the schema at the given path using `GET` and `POST`, and the GraphiQL
+/// playground at `{path}/playground` if enabled in the config.
+///
+/// Use like this: `.merge(graphql_router("/graphql", schema, &config.graphql))`
+pub fn graphql_router<Q, M, S>(path: &str, schema: Schema<Q, M, S>, config: &GraphQLConfig) -> Router
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static,
+{
+    graphql_router_with::<(), Q, M, S>(path, schema, config)
+}
+
+/// Same as [graphql_router], but extracts `C` from every request and adds it to the
+/// GraphQL context. Requests are rejected if `C` can not be extracted.
+///
+/// Use like this to make the claims of the caller available to resolvers:
+/// `graphql_router_with::<Jwt<Claims>, _, _, _>("/graphql", schema, &config.graphql)`, then
+/// `ctx.data::<Jwt<Claims>>()` in a resolver. Use `Option<Jwt<Claims>>` to allow anonymous requests.
+pub fn graphql_router_with<C, Q, M, S>(path: &str, schema: Schema<Q, M, S>, config: &GraphQLConfig) -> Router
+where
+    C: FromRequestParts<()> + Send + Sync + 'static,
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static,
+{
+    let execute = move |context: C, request: Result<GraphQLRequest, GraphQLRejection>| async move {
+        let request = request.map_err(|err| WebError::Response(StatusCode::BAD_REQUEST, err.0.to_string()))?;
+        let response = schema.execute(request.into_inner().data(context)).await;
+        Ok::<GraphQLResponse, WebError>(response.into())
+    };
+
+    let router = Router::new().route(path, get(execute.clone()).post(execute));
+
+    if !config.playground {
+        return router;
+    }
+
+    let playground = Html(GraphiQLSource::build().endpoint(path).finish());
+    router.route(&format!("{}/playground", path), get(move || async move { playground }))
+}
+
+/// Renders the error like an [ErrorResponse](crate::ErrorResponse), with the
+/// status code, field and trace id in the extensions of the GraphQL error.
+impl From<WebError> for async_graphql::Error {
+    fn from(err: WebError) -> Self {
+        let (status, response) = err.into_error_response();
+
+        async_graphql::Error::new(response.message).extend_with(|_, extensions| {
+            extensions.set("status", status.as_u16());
+
+            if let Some(field) = response.field {
+                extensions.set("field", field);
+            }
+
+            if let Some(trace_id) = response.trace_id {
+                extensions.set("traceId", trace_id);
+            }
+        })
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use serde::Serialize;
+use startup_base::health::{self, HealthStatus};
+
+use crate::{is_shutting_down, Json};
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+
+    /// The status of every component, `healthy` or the reason it is not.
+    components: BTreeMap<String, String>,
+}
+
+/// Endpoints for the liveness and readiness probes of an orchestrator like kubernetes.
+///
+/// `GET /health/live` always answers `200 OK` while the server runs. `GET /health/ready` answers
+/// `200 OK` while all components of the [health] registry are healthy, which includes the
+/// warm-ups registered with [startup_base::warmup::register], and `503 Service Unavailable`
+/// otherwise or once the server is shutting down. The readiness lists the status of every component.
+///
+/// Use like this: `router.merge(startup_http::health_router())`
+pub fn health_router() -> Router {
+    Router::new()
+        .route("/health/live", get(|| async { StatusCode::OK }))
+        .route("/health/ready", get(ready))
+}
+
+async fn ready() -> impl IntoResponse {
+    let readiness = readiness();
+
+    let status = match readiness.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, Json(readiness))
+}
+
+fn readiness() -> Readiness {
+    let components: BTreeMap<_, _> = health::components()
+        .into_iter()
+        .map(|(name, status)| match status {
+            HealthStatus::Healthy => (name, "healthy".to_string()),
+            HealthStatus::Unhealthy(reason) => (name, reason),
+        })
+        .collect();
+
+    let ready = health::is_healthy() && !is_shutting_down();
+
+    Readiness { ready, components }
+}
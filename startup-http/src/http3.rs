@@ -0,0 +1,292 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, Version};
+use axum::Router;
+use eyre::eyre;
+use hyper::body::{Buf, Bytes, HttpBody};
+use hyper::Body;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::rustls::crypto::ring;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer};
+use quinn::rustls::server::{ClientHello, ResolvesServerCert};
+use quinn::rustls::sign::CertifiedKey;
+use quinn::rustls::ServerConfig;
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use startup_base::watch::{FileWatch, Watched};
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_service::Service;
+
+use crate::tls::invalid_data;
+use crate::{shutdown, HttpConfig, TlsConfig};
+
+type RequestResolver = h3::server::RequestResolver<h3_quinn::Connection, Bytes>;
+
+/// Error code of a connection closed without an error, see RFC 9114, section 8.1
+const H3_NO_ERROR: u32 = 0x100;
+
+/// Experimental HTTP/3 support. Requires [TlsConfig], the certificate is shared with the
+/// http listener. Responses of the http listener advertise HTTP/3 in the `Alt-Svc` header,
+/// so clients switch to HTTP/3 for the following requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Http3Config {
+    /// UDP port to serve HTTP/3 on. Defaults to the port of the http listener.
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// How long clients may remember that HTTP/3 is available.
+    #[serde(default = "default_alt_svc_max_age_seconds")]
+    pub alt_svc_max_age_seconds: u64,
+}
+
+fn default_alt_svc_max_age_seconds() -> u64 {
+    86400
+}
+
+impl Http3Config {
+    fn port(&self, config: &HttpConfig) -> u16 {
+        self.port.unwrap_or(config.port)
+    }
+
+    /// Adds the `Alt-Svc` header advertising HTTP/3 to the responses of the http listener.
+    pub(crate) fn alt_svc(&self, config: &HttpConfig) -> SetResponseHeaderLayer<HeaderValue> {
+        let value = format!("h3=\":{}\"; ma={}", self.port(config), self.alt_svc_max_age_seconds);
+        SetResponseHeaderLayer::if_not_present(header::ALT_SVC, HeaderValue::from_str(&value).unwrap())
+    }
+}
+
+/// The HTTP/3 endpoint, closed with all of its connections when dropped.
+pub(crate) struct Http3Endpoint(quinn::Endpoint);
+
+impl Drop for Http3Endpoint {
+    fn drop(&mut self) {
+        self.0.close(H3_NO_ERROR.into(), b"shutdown");
+    }
+}
+
+/// Serves the router with HTTP/3 on the UDP port. Connections are told to go away once the
+/// server begins to shut down, and are tracked like long-lived connections, so open requests
+/// are given the drain timeout to finish.
+pub(crate) fn serve(config: &HttpConfig, http3: &Http3Config, router: Router) -> eyre::Result<Http3Endpoint> {
+    let Some(tls) = &config.tls else {
+        return Err(eyre!("http3 requires tls to be configured"));
+    };
+
+    // clients would be able to bypass the client certificate check of the http listener
+    if tls.client_auth.is_some() {
+        return Err(eyre!("http3 does not support client certificate authentication"));
+    }
+
+    let ip: IpAddr = config.address.parse()?;
+    let addr = SocketAddr::new(ip, http3.port(config));
+
+    let crypto = QuicServerConfig::try_from(server_config(tls)?)?;
+    let endpoint = quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), addr)?;
+
+    let handshake_timeout = Duration::from_secs(tls.handshake_timeout_seconds);
+    tokio::spawn(accept(endpoint.clone(), router, handshake_timeout));
+
+    info!("Serving http3 on udp port {}", addr.port());
+
+    Ok(Http3Endpoint(endpoint))
+}
+
+/// Loads the certificate of the [TlsConfig], which is reloaded when one of the files changes.
+fn server_config(tls: &TlsConfig) -> eyre::Result<ServerConfig> {
+    let config = tls.clone();
+
+    let certificate =
+        FileWatch::new("http3-certificate", [&tls.certificate, &tls.key]).load(move || load_certificate(&config))?;
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(&[&quinn::rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(WatchedCertificate(certificate)));
+
+    server_config.alpn_protocols = vec![b"h3".to_vec()];
+
+    Ok(server_config)
+}
+
+fn load_certificate(tls: &TlsConfig) -> io::Result<CertifiedKey> {
+    let (certificates, key) = tls.read_pem()?;
+
+    let key = match key {
+        Item::RSAKey(key) => PrivateKeyDer::Pkcs1(PrivatePkcs1KeyDer::from(key)),
+        Item::PKCS8Key(key) => PrivateKeyDer::Pkcs8(key.into()),
+        Item::ECKey(key) => PrivateKeyDer::Sec1(key.into()),
+        _ => unreachable!("read_pem only returns private keys"),
+    };
+
+    let key =
+        ring::sign::any_supported_type(&key).map_err(|err| invalid_data(format!("invalid private key: {}", err)))?;
+
+    let certificates = certificates.into_iter().map(CertificateDer::from).collect();
+    Ok(CertifiedKey::new(certificates, key))
+}
+
+#[derive(Debug)]
+struct WatchedCertificate(Watched<CertifiedKey>);
+
+impl ResolvesServerCert for WatchedCertificate {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.get())
+    }
+}
+
+async fn accept(endpoint: quinn::Endpoint, router: Router, handshake_timeout: Duration) {
+    loop {
+        let incoming = tokio::select! {
+            _ = shutdown::shutdown_requested() => return,
+
+            incoming = endpoint.accept() => match incoming {
+                Some(incoming) => incoming,
+                // the endpoint was closed
+                None => return,
+            },
+        };
+
+        let router = router.clone();
+
+        tokio::spawn(shutdown::track_connection(async move {
+            let remote = incoming.remote_address();
+
+            if let Err(err) = serve_connection(incoming, router, handshake_timeout).await {
+                debug!("Http3 connection from {} failed: {}", remote, err);
+            }
+        }));
+    }
+}
+
+async fn serve_connection(incoming: quinn::Incoming, router: Router, handshake_timeout: Duration) -> eyre::Result<()> {
+    let remote = incoming.remote_address();
+
+    let connection = tokio::time::timeout(handshake_timeout, incoming)
+        .await
+        .map_err(|_| eyre!("handshake timed out"))??;
+
+    let mut connection = h3::server::Connection::new(h3_quinn::Connection::new(connection)).await?;
+
+    let mut closing = false;
+
+    loop {
+        let resolver = tokio::select! {
+            _ = shutdown::shutdown_requested(), if !closing => {
+                // the client sends new requests on a new connection, open requests are finished
+                closing = true;
+                connection.shutdown(0).await?;
+                continue;
+            }
+
+            resolver = connection.accept() => resolver?,
+        };
+
+        let Some(resolver) = resolver else {
+            return Ok(());
+        };
+
+        let router = router.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = serve_request(resolver, router, remote).await {
+                debug!("Http3 request from {} failed: {}", remote, err);
+            }
+        });
+    }
+}
+
+async fn serve_request(resolver: RequestResolver, mut router: Router, remote: SocketAddr) -> eyre::Result<()> {
+    let (request, stream) = resolver.resolve_request().await?;
+    let (mut send, mut recv) = stream.split();
+
+    let (parts, ()) = request.into_parts();
+
+    let (mut body_tx, body) = Body::channel();
+
+    // forwards the request body until the handler drops it
+    tokio::spawn(async move {
+        loop {
+            match recv.recv_data().await {
+                Ok(Some(mut data)) => {
+                    if body_tx.send_data(data.copy_to_bytes(data.remaining())).await.is_err() {
+                        return;
+                    }
+                }
+
+                Ok(None) => return,
+
+                Err(err) => {
+                    debug!("Failed to receive http3 request body: {}", err);
+                    body_tx.abort();
+                    return;
+                }
+            }
+        }
+    });
+
+    let mut request = Request::builder()
+        .method(parts.method.as_str())
+        .uri(parts.uri.to_string())
+        .version(Version::HTTP_3);
+
+    for (name, value) in &parts.headers {
+        request = request.header(name.as_str(), value.as_bytes());
+    }
+
+    let mut request = request.body(body)?;
+    request.extensions_mut().insert(ConnectInfo(remote));
+
+    let head_request = request.method() == Method::HEAD;
+
+    let response = match router.call(request).await {
+        Ok(response) => response,
+        Err(err) => match err {},
+    };
+
+    let (parts, mut body) = response.into_parts();
+
+    let mut response = http1::Response::builder().status(parts.status.as_u16());
+    *response.headers_mut().unwrap() = convert_headers(&parts.headers)?;
+
+    send.send_response(response.body(())?).await?;
+
+    if !head_request {
+        while let Some(data) = body.data().await {
+            send.send_data(data?).await?;
+        }
+
+        if let Some(trailers) = body.trailers().await? {
+            send.send_trailers(convert_headers(&trailers)?).await?;
+        }
+    }
+
+    send.finish().await?;
+
+    Ok(())
+}
+
+/// Converts the headers of a response, without the headers that are specific to a HTTP/1.1
+/// connection and not allowed in HTTP/3, see RFC 9114, section 4.2
+fn convert_headers(headers: &HeaderMap) -> eyre::Result<http1::HeaderMap> {
+    let mut converted = http1::HeaderMap::with_capacity(headers.len());
+
+    for (name, value) in headers {
+        if matches!(
+            name.as_str(),
+            "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+        ) {
+            continue;
+        }
+
+        converted.append(
+            http1::HeaderName::from_bytes(name.as_str().as_bytes())?,
+            http1::HeaderValue::from_bytes(value.as_bytes())?,
+        );
+    }
+
+    Ok(converted)
+}
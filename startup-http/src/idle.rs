@@ -0,0 +1,234 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use axum::body::{boxed, BoxBody};
+use axum::http::{Request, Response, StatusCode};
+use futures_util::future::BoxFuture;
+use hyper::server::accept::Accept;
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use crate::body::GuardedBody;
+
+/// Accepts connections that are closed once they had no request in flight for the idle timeout.
+pub(crate) struct IdleAccept<A> {
+    accept: A,
+    timeout: Option<Duration>,
+}
+
+impl<A> IdleAccept<A> {
+    /// Connections are never closed if `timeout` is None.
+    pub(crate) fn new(accept: A, timeout: Option<Duration>) -> Self {
+        Self { accept, timeout }
+    }
+}
+
+impl<A: Accept + Unpin> Accept for IdleAccept<A> {
+    type Conn = IdleConnection<A::Conn>;
+    type Error = A::Error;
+
+    fn poll_accept(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let timeout = self.timeout;
+
+        Pin::new(&mut self.accept)
+            .poll_accept(cx)
+            .map_ok(|io| IdleConnection::new(io, timeout))
+    }
+}
+
+/// The requests in flight on a connection and when the last one finished.
+#[derive(Clone)]
+struct Idle {
+    in_flight: Arc<AtomicUsize>,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    since: Instant,
+
+    /// Wakes the connection to start the timer once the last request finished.
+    waker: Option<Waker>,
+}
+
+impl Idle {
+    fn start(&self) -> Active {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Active(self.clone())
+    }
+
+    fn touch(&self) {
+        self.state.lock().since = Instant::now();
+    }
+}
+
+/// A request in flight, finished when dropped together with the response body.
+struct Active(Idle);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.since = Instant::now();
+
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// The io of a connection. Reading ends like a closed connection once there were no requests
+/// in flight and no bytes received for the idle timeout, so hyper closes it gracefully.
+#[pin_project::pin_project]
+pub(crate) struct IdleConnection<IO> {
+    #[pin]
+    io: IO,
+    idle: Idle,
+    timeout: Option<Duration>,
+    timer: Pin<Box<Sleep>>,
+}
+
+impl<IO> IdleConnection<IO> {
+    fn new(io: IO, timeout: Option<Duration>) -> Self {
+        let now = Instant::now();
+
+        Self {
+            io,
+            idle: Idle {
+                in_flight: Arc::new(AtomicUsize::new(0)),
+                state: Arc::new(Mutex::new(State {
+                    since: now,
+                    waker: None,
+                })),
+            },
+            timeout,
+            timer: Box::pin(tokio::time::sleep_until(now)),
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &IO {
+        &self.io
+    }
+
+    /// Tracks the requests of this connection handled by the service.
+    pub(crate) fn service<S>(&self, inner: S) -> IdleService<S> {
+        IdleService {
+            inner,
+            idle: self.idle.clone(),
+        }
+    }
+}
+
+impl<IO: AsyncRead> AsyncRead for IdleConnection<IO> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+
+        let filled = buf.filled().len();
+
+        if let Poll::Ready(result) = this.io.poll_read(cx, buf) {
+            if buf.filled().len() > filled {
+                this.idle.touch();
+            }
+
+            return Poll::Ready(result);
+        }
+
+        let Some(timeout) = *this.timeout else {
+            return Poll::Pending;
+        };
+
+        let deadline = {
+            let mut state = this.idle.state.lock();
+
+            if this.idle.in_flight.load(Ordering::SeqCst) > 0 {
+                state.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            state.since + timeout
+        };
+
+        if this.timer.deadline() != deadline {
+            this.timer.as_mut().reset(deadline);
+        }
+
+        match this.timer.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                debug!("Closing connection that was idle for {:?}", timeout);
+                Poll::Ready(Ok(()))
+            }
+
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<IO: AsyncWrite> AsyncWrite for IdleConnection<IO> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.project().io.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().io.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().io.poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().io.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+}
+
+/// Keeps the connection from being closed as idle while a request is handled
+/// and its response body is sent.
+#[derive(Clone)]
+pub(crate) struct IdleService<S> {
+    inner: S,
+    idle: Idle,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for IdleService<S>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let active = self.idle.start();
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let response = response.await?;
+
+            // the io is handed to the upgraded protocol, like a websocket, which handles idle connections itself
+            if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+                active.0.in_flight.fetch_add(1, Ordering::SeqCst);
+            }
+
+            Ok(response.map(|body| boxed(GuardedBody::new(body, active))))
+        })
+    }
+}
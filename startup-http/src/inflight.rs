@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, BoxBody};
+use axum::extract::MatchedPath;
+use axum::http::{Request, Response};
+use futures_util::future::BoxFuture;
+use opentelemetry::metrics::{Counter, Histogram, Unit, UpDownCounter};
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+
+use crate::body::GuardedBody;
+use crate::shutdown;
+
+lazy_static::lazy_static! {
+    static ref ACTIVE: UpDownCounter<i64> = opentelemetry::global::meter("startup-http")
+        .i64_up_down_counter("http.server.active_requests")
+        .with_description("Requests currently handled, until their response body was sent")
+        .init();
+
+    static ref DRAIN_REQUESTS: Counter<u64> = opentelemetry::global::meter("startup-http")
+        .u64_counter("http.server.drain.requests")
+        .with_description("Requests finished during shutdown, or aborted at the end of the drain timeout")
+        .init();
+
+    static ref DRAIN_DURATION: Histogram<f64> = opentelemetry::global::meter("startup-http")
+        .f64_histogram("http.server.drain.duration")
+        .with_description("Time from the shutdown signal until all requests were finished or aborted")
+        .with_unit(Unit::new("s"))
+        .init();
+}
+
+#[derive(Default)]
+struct Requests {
+    in_flight: u64,
+    drained: u64,
+}
+
+/// Tracks the requests handled by a server, by route, to report how many requests were
+/// drained during shutdown and how many were still in flight at the end of the drain timeout.
+#[derive(Clone, Default)]
+pub(crate) struct InFlight {
+    routes: Arc<Mutex<BTreeMap<String, Requests>>>,
+    finished: Arc<AtomicBool>,
+}
+
+impl InFlight {
+    /// Logs and records the outcome of the drain, `aborted` if the drain timeout was reached.
+    /// Must be called before the requests still in flight are dropped.
+    pub(crate) fn finish(&self, aborted: bool) {
+        if self.finished.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let context = opentelemetry::Context::current();
+
+        let mut drained_total = 0;
+        let mut aborted_total = 0;
+        let mut aborted_routes = Vec::new();
+
+        for (route, requests) in self.routes.lock().iter() {
+            drained_total += requests.drained;
+
+            let attributes = [
+                KeyValue::new("route", route.clone()),
+                KeyValue::new("result", "drained"),
+            ];
+            DRAIN_REQUESTS.add(&context, requests.drained, &attributes);
+
+            if aborted && requests.in_flight > 0 {
+                let attributes = [
+                    KeyValue::new("route", route.clone()),
+                    KeyValue::new("result", "aborted"),
+                ];
+                DRAIN_REQUESTS.add(&context, requests.in_flight, &attributes);
+
+                aborted_total += requests.in_flight;
+                aborted_routes.push(format!("{} ({})", route, requests.in_flight));
+            }
+        }
+
+        let elapsed = shutdown::requested_at().map(|requested_at| requested_at.elapsed());
+
+        if let Some(elapsed) = elapsed {
+            DRAIN_DURATION.record(&context, elapsed.as_secs_f64(), &[]);
+        }
+
+        if aborted_routes.is_empty() {
+            info!(
+                "Drained {} requests in {:.1?}",
+                drained_total,
+                elapsed.unwrap_or_default()
+            );
+        } else {
+            warn!(
+                "Drained {} requests, aborted {} requests at the end of the drain timeout: {}",
+                drained_total,
+                aborted_total,
+                aborted_routes.join(", ")
+            );
+        }
+    }
+
+    fn start(&self, route: String) -> Tracked {
+        self.routes.lock().entry(route.clone()).or_default().in_flight += 1;
+
+        let attributes = [KeyValue::new("route", route.clone())];
+        ACTIVE.add(&opentelemetry::Context::current(), 1, &attributes);
+
+        Tracked {
+            in_flight: self.clone(),
+            route,
+        }
+    }
+}
+
+/// A request in flight, finished when dropped with the response body.
+struct Tracked {
+    in_flight: InFlight,
+    route: String,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Some(requests) = self.in_flight.routes.lock().get_mut(&self.route) {
+            requests.in_flight -= 1;
+
+            // requests dropped after the drain finished were aborted
+            if shutdown::is_shutting_down() && !self.in_flight.finished.load(Ordering::SeqCst) {
+                requests.drained += 1;
+            }
+        }
+
+        let attributes = [KeyValue::new("route", self.route.clone())];
+        ACTIVE.add(&opentelemetry::Context::current(), -1, &attributes);
+    }
+}
+
+impl<S> tower_layer::Layer<S> for InFlight {
+    type Service = InFlightService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InFlightService {
+            inner,
+            in_flight: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct InFlightService<S> {
+    inner: S,
+    in_flight: InFlight,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for InFlightService<S>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // use the route template instead of the path to keep the number of series low
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or_else(|| "unmatched".to_string(), |path| path.as_str().to_string());
+
+        let tracked = self.in_flight.start(route);
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            // the request is in flight until the response body was sent, e.g. a download
+            let response = response.await?;
+            Ok(response.map(|body| boxed(GuardedBody::new(body, tracked))))
+        })
+    }
+}
@@ -0,0 +1,419 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, MatchedPath};
+use axum::http::{header, HeaderName, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use opentelemetry::metrics::{Histogram, Unit};
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
+use tower_http::request_id::{MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer};
+
+use crate::error::{header_name, header_value, ConfigError};
+use crate::slo::SloTracker;
+use crate::{
+    tracing_layer, HttpConfig, InflateBodyLayer, NotModifiedLayer, RequestContextLayer, SloConfig, WebError,
+    ZipkinTraceLayer,
+};
+
+static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Switches and limits of the layers added by [standard_layers].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayersConfig {
+    /// Give every request an `x-request-id` header, unless it already has one,
+    /// and send it back with the response.
+    #[serde(default = "default_enabled")]
+    pub request_id: bool,
+
+    /// Trace requests, see [ZipkinTraceLayer] and [tracing_layer].
+    #[serde(default = "default_enabled")]
+    pub tracing: bool,
+
+    /// Create the [RequestContext] of every request, see [RequestContextLayer].
+    #[serde(default = "default_enabled")]
+    pub request_context: bool,
+
+    /// Record the duration of requests in the `http.server.duration` metric,
+    /// labeled with method, route and status, and track the `slos`.
+    #[serde(default = "default_enabled")]
+    pub metrics: bool,
+
+    /// Service level objectives of groups of routes, tracked by the metrics layer.
+    #[serde(default)]
+    pub slos: Vec<SloConfig>,
+
+    /// Answer requests with a panicking handler with `500 Internal Server Error`.
+    #[serde(default = "default_enabled")]
+    pub catch_panic: bool,
+
+    /// Answer requests that did not finish in time with `503 Service Unavailable`.
+    /// Only the response headers must be sent in time, streamed bodies are not limited.
+    #[serde(default = "default_enabled")]
+    pub timeout: bool,
+
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// Limit the size of request bodies read by extractors like [Json](crate::Json),
+    /// larger bodies are rejected with `413 Payload Too Large`.
+    #[serde(default = "default_enabled")]
+    pub body_limit: bool,
+
+    #[serde(default = "default_body_limit_bytes")]
+    pub body_limit_bytes: usize,
+
+    /// Decompress request bodies sent with `Content-Encoding: gzip` or `deflate`, see
+    /// [InflateBodyLayer]. Bodies larger than `body_limit_bytes` after decompression
+    /// are rejected with `413 Payload Too Large`, even if the body limit is disabled.
+    #[serde(default)]
+    pub decompression: bool,
+
+    /// Answer `GET` requests with `304 Not Modified` if the `ETag` of the response matches
+    /// the `If-None-Match` header of the request, see [NotModifiedLayer].
+    #[serde(default = "default_enabled")]
+    pub not_modified: bool,
+
+    /// Compress responses with gzip or deflate if the client accepts it.
+    /// Images and server-sent events are not compressed.
+    #[serde(default = "default_enabled")]
+    pub compression: bool,
+
+    /// Answer cross-origin requests of browsers. Disabled unless configured.
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins allowed to send requests, like `https://example.com`, or `*` for any origin.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// Request headers allowed in addition to the safelisted ones, or `*` for any header.
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+
+    /// Allow requests with cookies. Can not be combined with `*` for origins or headers.
+    #[serde(default)]
+    pub allow_credentials: bool,
+
+    /// How long browsers may cache the answer to a preflight request.
+    #[serde(default = "default_max_age_seconds")]
+    pub max_age_seconds: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_body_limit_bytes() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"].map(String::from).to_vec()
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    ["content-type", "authorization"].map(String::from).to_vec()
+}
+
+fn default_max_age_seconds() -> u64 {
+    3600
+}
+
+impl Default for LayersConfig {
+    fn default() -> Self {
+        Self {
+            request_id: default_enabled(),
+            tracing: default_enabled(),
+            request_context: default_enabled(),
+            metrics: default_enabled(),
+            slos: Vec::new(),
+            catch_panic: default_enabled(),
+            timeout: default_enabled(),
+            timeout_seconds: default_timeout_seconds(),
+            body_limit: default_enabled(),
+            body_limit_bytes: default_body_limit_bytes(),
+            decompression: false,
+            not_modified: default_enabled(),
+            compression: default_enabled(),
+            cors: None,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: default_allowed_methods(),
+            allowed_headers: default_allowed_headers(),
+            allow_credentials: false,
+            max_age_seconds: default_max_age_seconds(),
+        }
+    }
+}
+
+/// The recommended middleware of a service, configured by the `layers` of the [HttpConfig].
+///
+/// The layers are applied in this order, from the outermost to the innermost: request id,
+/// tracing, request context, metrics, panic catcher, CORS, compression, timeout,
+/// conditional requests, request decompression and body limit.
+/// So every request is traced with its request id, the [RequestContext](crate::RequestContext)
+/// knows both ids, and panics, timeouts and rejected bodies are traced and counted with the
+/// status they are answered with.
+///
+/// Fails if a layer can not be created from the config, like CORS with an invalid origin
+/// or a service level objective that is not between 0 and 1.
+///
+/// Use like this: `run_server(&config.http, standard_layers(&config.http)?.apply(router)).await`
+pub fn standard_layers(config: &HttpConfig) -> Result<StandardLayers, ConfigError> {
+    let cors = config.layers.cors.as_ref().map(cors_layer).transpose()?;
+    let slos = match config.layers.metrics {
+        true => Some(Arc::new(SloTracker::new(&config.layers.slos)?)),
+        false => None,
+    };
+
+    Ok(StandardLayers {
+        config: config.layers.clone(),
+        cors,
+        slos,
+    })
+}
+
+/// Created by [standard_layers].
+#[derive(Debug, Clone)]
+pub struct StandardLayers {
+    config: LayersConfig,
+    cors: Option<CorsLayer>,
+    slos: Option<Arc<SloTracker>>,
+}
+
+impl StandardLayers {
+    /// Adds the enabled layers to all routes of the router. Routes added to the router
+    /// afterwards do not get the layers.
+    pub fn apply<S>(self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let StandardLayers { config, cors, slos } = self;
+
+        // the layer added last is the outermost one
+        let mut router = router;
+
+        if config.body_limit {
+            router = router.layer(DefaultBodyLimit::max(config.body_limit_bytes));
+        }
+
+        if config.decompression {
+            router = router.layer(InflateBodyLayer::new(config.body_limit_bytes));
+        }
+
+        if config.not_modified {
+            router = router.layer(NotModifiedLayer);
+        }
+
+        if config.timeout {
+            let duration = Duration::from_secs(config.timeout_seconds);
+            router = router.layer(middleware::from_fn(move |req, next| timeout(duration, req, next)));
+        }
+
+        if config.compression {
+            let predicate = DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream"));
+            router = router.layer(CompressionLayer::new().compress_when(predicate));
+        }
+
+        if let Some(cors) = cors {
+            router = router.layer(cors);
+        }
+
+        if config.catch_panic {
+            router = router.layer(CatchPanicLayer::custom(panic_response));
+        }
+
+        // created by standard_layers if metrics are enabled
+        if let Some(slos) = slos {
+            let duration = opentelemetry::global::meter("startup-http")
+                .f64_histogram("http.server.duration")
+                .with_description("Duration of handling requests until the response headers were sent")
+                .with_unit(Unit::new("s"))
+                .init();
+
+            router = router.layer(middleware::from_fn(move |req, next| {
+                record_duration(duration.clone(), slos.clone(), req, next)
+            }));
+        }
+
+        if config.request_context {
+            let mut layer = RequestContextLayer::new();
+
+            if config.timeout {
+                layer = layer.with_timeout(Duration::from_secs(config.timeout_seconds));
+            }
+
+            router = router.layer(layer);
+        }
+
+        if config.request_id {
+            router = router.layer(PropagateRequestIdLayer::new(X_REQUEST_ID.clone()));
+        }
+
+        if config.tracing {
+            router = router.layer(tracing_layer()).layer(ZipkinTraceLayer::new());
+        }
+
+        if config.request_id {
+            router = router.layer(SetRequestIdLayer::new(X_REQUEST_ID.clone(), MakeRandomRequestId));
+        }
+
+        router
+    }
+}
+
+async fn timeout(duration: Duration, req: Request<Body>, next: Next<Body>) -> Response {
+    match tokio::time::timeout(duration, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            let message = format!("Request did not finish within {:?}", duration);
+            WebError::Response(StatusCode::SERVICE_UNAVAILABLE, message).into_response()
+        }
+    }
+}
+
+async fn record_duration(
+    duration: Histogram<f64>,
+    slos: Arc<SloTracker>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let started = Instant::now();
+
+    let method = req.method().to_string();
+
+    // use the route template instead of the path to keep the number of series low
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+
+    let response = next.run(req).await;
+    let elapsed = started.elapsed();
+
+    if let Some(route) = &route {
+        slos.record(route, response.status(), elapsed);
+    }
+
+    let mut attributes = vec![
+        KeyValue::new("method", method),
+        KeyValue::new("status", i64::from(response.status().as_u16())),
+    ];
+
+    if let Some(route) = route {
+        attributes.push(KeyValue::new("route", route));
+    }
+
+    duration.record(&opentelemetry::Context::current(), elapsed.as_secs_f64(), &attributes);
+
+    response
+}
+
+fn panic_response(err: Box<dyn Any + Send + 'static>) -> Response {
+    let message = err
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| err.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+
+    error!("Handler panicked: {}", message);
+
+    let message = "Internal server error".to_string();
+    WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+}
+
+fn cors_layer(config: &CorsConfig) -> Result<CorsLayer, ConfigError> {
+    let any_origin = config.allowed_origins.iter().any(|origin| origin == "*");
+    let any_header = config.allowed_headers.iter().any(|header| header == "*");
+
+    // browsers reject credentials for any origin, and tower-http panics on it
+    if config.allow_credentials && (any_origin || any_header) {
+        return Err(ConfigError::Invalid {
+            field: "layers.cors.allow_credentials",
+            message: "can not be combined with `*` for origins or headers".to_string(),
+        });
+    }
+
+    let origins = match any_origin {
+        true => AllowOrigin::any(),
+        false => {
+            let origins = config
+                .allowed_origins
+                .iter()
+                .map(|origin| header_value("layers.cors.allowed_origins", origin.clone()))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            AllowOrigin::list(origins)
+        }
+    };
+
+    let methods = config
+        .allowed_methods
+        .iter()
+        .map(|method| {
+            Method::from_bytes(method.as_bytes()).map_err(|_| ConfigError::Invalid {
+                field: "layers.cors.allowed_methods",
+                message: format!("not a http method: {:?}", method),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let headers = match any_header {
+        true => AllowHeaders::any(),
+        false => {
+            let headers = config
+                .allowed_headers
+                .iter()
+                .map(|header| header_name("layers.cors.allowed_headers", header))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            AllowHeaders::list(headers)
+        }
+    };
+
+    let layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(config.allow_credentials)
+        .expose_headers([X_REQUEST_ID.clone(), header::RETRY_AFTER])
+        .max_age(Duration::from_secs(config.max_age_seconds));
+
+    Ok(layer)
+}
+
+/// Creates random request ids, like `4bf92f3577b34da6a3ce929d0e0e4736`.
+#[derive(Debug, Clone, Copy)]
+struct MakeRandomRequestId;
+
+impl MakeRequestId for MakeRandomRequestId {
+    fn make_request_id<B>(&mut self, _request: &Request<B>) -> Option<RequestId> {
+        let id = format!("{:032x}", rand::random::<u128>());
+        HeaderValue::try_from(id).ok().map(RequestId::new)
+    }
+}
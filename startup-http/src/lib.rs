@@ -1,27 +1,212 @@
 #[macro_use]
 extern crate tracing;
 
-use std::net::{AddrParseError, IpAddr, SocketAddr, ToSocketAddrs};
+use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
 use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer};
 use tracing::Level;
 
-pub use error::{WebError, WebErrorExt};
-pub use serve::serve_static;
+pub use assets::{serve_assets, AssetManifest};
+pub use body_logging::{BodyLoggingConfig, BodyLoggingLayer, BODY_LOGGING_TARGET};
+#[cfg(feature = "tls")]
+pub use client_certificate::ClientCertificate;
+pub use client_ip::{ClientIp, TrustedProxies};
+pub use conditional::{ETag, NotModified, NotModifiedLayer, TaggedJson};
+pub use config::config_router;
+pub use context::{Principal, RequestContext, RequestContextLayer, RequestContextService};
+pub use decompress::{InflateBody, InflateBodyLayer};
+pub use error::{ConfigError, ErrorResponse, WebError, WebErrorExt};
+#[cfg(feature = "csv")]
+pub use export::csv;
+pub use export::{json_array, ndjson, rows, Export, ExportStream};
+pub use extract::{Json, Query};
+pub use fallback::{ErrorFallback, ErrorFallbackLayer};
+pub use health::health_router;
+#[cfg(feature = "http3")]
+pub use http3::Http3Config;
+pub use layers::{standard_layers, CorsConfig, LayersConfig, StandardLayers};
+pub use maintenance::{Maintenance, MaintenanceConfig, MaintenanceMode};
+pub use negotiate::{Format, Negotiate, Negotiated, SupportedFormats};
+pub use priority::{Priority, PriorityConfig, PriorityLayer, PriorityRoute, PriorityService};
+pub use sbom::sbom_router;
+pub use serve::{serve_static, serve_static_with, Caching};
+pub use server::{component, run_server, Listener, Serving};
+pub use slo::SloConfig;
+pub use shutdown::{is_shutting_down, shutdown_requested, track_connection};
+pub use sse::{sse, sse_with_keep_alive, EventStream};
+#[cfg(feature = "tls")]
+pub use tls::{ClientAuthConfig, TlsConfig};
+pub use versioning::{ApiVersionConfig, ApiVersions, VersioningConfig};
 
 pub use crate::trace::ZipkinMakeSpan;
 pub use crate::trace::{Layer as ZipkinTraceLayer};
 
+mod assets;
+mod body;
+mod body_logging;
+pub mod cache;
+pub mod canary;
+#[cfg(feature = "tls")]
+mod client_certificate;
+mod client_ip;
+mod conditional;
+mod config;
+mod context;
+pub mod csrf;
+mod decompress;
+pub mod coalesce;
+#[cfg(feature = "embed")]
+pub mod embed;
 mod error;
+mod export;
+mod extract;
+mod fallback;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+mod health;
+#[cfg(feature = "http3")]
+mod http3;
+mod idle;
+mod inflight;
+mod layers;
+mod maintenance;
+mod negotiate;
+#[cfg(feature = "resumable")]
+pub mod resumable;
+#[cfg(feature = "openapi")]
+pub mod openapi;
+mod priority;
+mod reload;
+mod sbom;
 mod serve;
+mod server;
+pub mod session;
+mod shutdown;
+#[cfg(feature = "signing")]
+pub mod signing;
+mod slo;
+mod sse;
+#[cfg(feature = "templates")]
+pub mod templates;
+#[cfg(feature = "tls")]
+mod tls;
 mod trace;
+#[cfg(feature = "multipart")]
+pub mod upload;
+mod versioning;
+#[cfg(feature = "webhooks")]
+pub mod webhook;
+#[cfg(feature = "ws")]
+pub mod ws;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HttpConfig {
     pub port: u16,
     pub address: String,
+
+    /// Listen on this unix domain socket instead of address and port.
+    #[serde(default)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Octal file permissions of the unix socket.
+    #[serde(default = "default_unix_socket_mode")]
+    pub unix_socket_mode: String,
+
+    /// Use the socket passed in by systemd socket activation (`LISTEN_FDS`) if available.
+    #[serde(default)]
+    pub socket_activation: bool,
+
+    /// Set `SO_REUSEPORT` on the tcp socket, so a new instance of the service can listen on the
+    /// same port while this one still drains its connections. Note that linux resets the connections
+    /// still waiting to be accepted by an instance when it stops listening.
+    #[serde(default)]
+    pub reuse_port: bool,
+
+    /// Hand the listening socket over to a new process of the binary on SIGUSR2, for deployments
+    /// without an orchestrator. See [run_server].
+    #[serde(default)]
+    pub reload: bool,
+
+    /// Serve https with this certificate instead of plain http.
+    #[cfg(feature = "tls")]
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Also serve HTTP/3 on a UDP port, using the certificate of `tls`.
+    #[cfg(feature = "http3")]
+    #[serde(default)]
+    pub http3: Option<Http3Config>,
+
+    /// Networks of reverse proxies that are trusted to report the address of the client.
+    /// See [ClientIp].
+    #[serde(default)]
+    pub trusted_proxies: Vec<ipnet::IpNet>,
+
+    /// Maximum number of connections waiting to be accepted.
+    #[serde(default = "default_backlog")]
+    pub backlog: i32,
+
+    /// Disable Nagle's algorithm on accepted connections.
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+
+    /// Send TCP keep-alive probes after a connection was idle for this long.
+    #[serde(default)]
+    pub tcp_keepalive_seconds: Option<u64>,
+
+    /// Keep HTTP/1.1 connections open between requests, see `idle_timeout_seconds`.
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: bool,
+
+    /// Close connections without a request in flight after this long. Behind a load balancer,
+    /// this should be longer than its idle timeout, so the load balancer closes idle connections
+    /// first and never sends a request on a connection that is being closed. Set to `null` to
+    /// keep idle connections open until the client closes them.
+    #[serde(default = "default_idle_timeout_seconds")]
+    pub idle_timeout_seconds: Option<u64>,
+
+    /// Close connections that do not send the complete request headers in time.
+    #[serde(default = "default_header_read_timeout_seconds")]
+    pub header_read_timeout_seconds: u64,
+
+    /// How long to wait for open requests and long-lived connections to finish during shutdown.
+    #[serde(default = "default_drain_timeout_seconds")]
+    pub drain_timeout_seconds: u64,
+
+    /// Layers added by [standard_layers].
+    #[serde(default)]
+    pub layers: LayersConfig,
+}
+
+fn default_unix_socket_mode() -> String {
+    "660".into()
+}
+
+fn default_backlog() -> i32 {
+    1024
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn default_keep_alive() -> bool {
+    true
+}
+
+fn default_idle_timeout_seconds() -> Option<u64> {
+    Some(75)
+}
+
+fn default_header_read_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_drain_timeout_seconds() -> u64 {
+    30
 }
 
 impl TryFrom<HttpConfig> for SocketAddr {
@@ -39,3 +224,10 @@ pub fn tracing_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, Z
         .on_request(DefaultOnRequest::new().level(Level::INFO))
         .on_response(DefaultOnResponse::new().level(Level::INFO))
 }
+
+/// Checks if the path is the prefix or below it, e.g. `/api/orders` is below `/api`,
+/// but `/apiary` is not.
+pub fn is_path_prefix(prefix: &str, path: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'))
+}
@@ -0,0 +1,155 @@
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::BoxBody;
+use axum::http::{header, HeaderValue, Request, Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use futures_util::future::{ready, Either, Ready};
+use serde::{Deserialize, Serialize};
+
+use crate::{ErrorResponse, Json};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    /// Start the service in maintenance mode.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Value of the `Retry-After` header sent during maintenance.
+    #[serde(default = "default_retry_after_seconds")]
+    pub retry_after_seconds: u64,
+
+    /// Json body sent during maintenance. Defaults to an [ErrorResponse].
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retry_after_seconds: default_retry_after_seconds(),
+            body: None,
+        }
+    }
+}
+
+fn default_retry_after_seconds() -> u64 {
+    300
+}
+
+/// A switch to put the service into maintenance mode at runtime. Add it as a layer to the
+/// public router only, so that health and admin endpoints stay available. While in
+/// maintenance mode, every request to the public router is answered with
+/// `503 Service Unavailable`.
+///
+/// Use like this: `public.layer(maintenance.clone()).merge(maintenance.admin_router())`
+#[derive(Clone)]
+pub struct MaintenanceMode {
+    enabled: Arc<AtomicBool>,
+    retry_after: HeaderValue,
+    body: Arc<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MaintenanceState {
+    enabled: bool,
+}
+
+impl MaintenanceMode {
+    pub fn new(config: &MaintenanceConfig) -> Self {
+        let body = config.body.clone().unwrap_or_else(|| {
+            let response = ErrorResponse {
+                status: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+                message: "Service is down for maintenance".to_string(),
+                field: None,
+                trace_id: None,
+            };
+
+            serde_json::to_value(response).expect("serialize error response")
+        });
+
+        Self {
+            enabled: Arc::new(AtomicBool::new(config.enabled)),
+            retry_after: HeaderValue::from(config.retry_after_seconds),
+            body: Arc::new(body),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            info!("Maintenance mode is now {}", if enabled { "enabled" } else { "disabled" });
+        }
+    }
+
+    /// Admin endpoint to query and switch the maintenance mode using
+    /// `GET` and `PUT` on `/admin/maintenance` with a body like `{"enabled": true}`.
+    pub fn admin_router(&self) -> Router {
+        let get_state = {
+            let this = self.clone();
+            move || async move { Json(MaintenanceState { enabled: this.is_enabled() }) }
+        };
+
+        let put_state = {
+            let this = self.clone();
+            move |Json(state): Json<MaintenanceState>| async move {
+                this.set_enabled(state.enabled);
+                Json(state)
+            }
+        };
+
+        Router::new().route("/admin/maintenance", get(get_state).put(put_state))
+    }
+
+    fn response(&self) -> Response<BoxBody> {
+        let headers = [(header::RETRY_AFTER, self.retry_after.clone())];
+        (StatusCode::SERVICE_UNAVAILABLE, headers, Json(self.body.as_ref().clone())).into_response()
+    }
+}
+
+impl<S> tower_layer::Layer<S> for MaintenanceMode {
+    type Service = Maintenance<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Maintenance {
+            inner,
+            mode: self.clone(),
+        }
+    }
+}
+
+/// Middleware created by using [MaintenanceMode] as a layer.
+#[derive(Clone)]
+pub struct Maintenance<S> {
+    inner: S,
+    mode: MaintenanceMode,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for Maintenance<S>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>, Error = Infallible>,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if self.mode.is_enabled() {
+            return Either::Right(ready(Ok(self.mode.response())));
+        }
+
+        Either::Left(self.inner.call(req))
+    }
+}
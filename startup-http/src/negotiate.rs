@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use serde::Serialize;
+
+use crate::WebError;
+
+/// A serialization format that can be negotiated using the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Format {
+    /// All formats that are compiled in, in order of preference.
+    pub const ALL: &'static [Format] = &[
+        Format::Json,
+        #[cfg(feature = "msgpack")]
+        Format::MessagePack,
+        #[cfg(feature = "cbor")]
+        Format::Cbor,
+    ];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => "application/msgpack",
+
+            #[cfg(feature = "cbor")]
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    fn matches(self, media_type: &str) -> bool {
+        match self {
+            Format::Json => media_type == "application/json",
+
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => matches!(
+                media_type,
+                "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack"
+            ),
+
+            #[cfg(feature = "cbor")]
+            Format::Cbor => media_type == "application/cbor",
+        }
+    }
+
+    fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>, WebError> {
+        match self {
+            Format::Json => Ok(serde_json::to_vec(value)?),
+
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+
+            #[cfg(feature = "cbor")]
+            Format::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::ser::into_writer(value, &mut buffer)?;
+                Ok(buffer)
+            }
+        }
+    }
+}
+
+/// The formats a service is willing to produce, in order of preference.
+/// Add it as a layer to restrict the formats, defaults to [Format::ALL].
+#[derive(Debug, Clone)]
+pub struct SupportedFormats(Arc<Vec<Format>>);
+
+impl SupportedFormats {
+    pub fn new(formats: Vec<Format>) -> Self {
+        assert!(!formats.is_empty(), "at least one format must be supported");
+        Self(Arc::new(formats))
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+}
+
+impl Default for SupportedFormats {
+    fn default() -> Self {
+        Self::new(Format::ALL.to_vec())
+    }
+}
+
+/// Extracts the best [Format] for the response from the `Accept` header of the request.
+/// Rejects the request with `406 Not Acceptable` if none of the supported formats is accepted.
+///
+/// Use like this: `async fn handler(negotiate: Negotiate) -> Negotiated<T> { negotiate.respond(value) }`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiate(pub Format);
+
+impl Negotiate {
+    pub fn respond<T: Serialize>(self, value: T) -> Negotiated<T> {
+        Negotiated { format: self.0, value }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Negotiate
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let supported = parts.extensions.get::<SupportedFormats>().cloned().unwrap_or_default();
+
+        negotiate(&parts.headers, &supported.0).map(Negotiate).ok_or_else(|| {
+            let accepted: Vec<_> = supported.0.iter().map(|format| format.content_type()).collect();
+            let message = format!("Can only produce one of: {}", accepted.join(", "));
+            WebError::Response(StatusCode::NOT_ACCEPTABLE, message)
+        })
+    }
+}
+
+/// A response that is serialized in the format negotiated by [Negotiate].
+#[derive(Debug, Clone)]
+pub struct Negotiated<T> {
+    format: Format,
+    value: T,
+}
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        match self.format.serialize(&self.value) {
+            Ok(body) => {
+                let content_type = HeaderValue::from_static(self.format.content_type());
+
+                let headers = [
+                    (header::CONTENT_TYPE, content_type),
+                    (header::VARY, HeaderValue::from_static("accept")),
+                ];
+
+                (headers, body).into_response()
+            }
+
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// Selects the supported format with the highest quality in the `Accept` header.
+/// Without an `Accept` header, the first supported format is used.
+fn negotiate(headers: &HeaderMap, supported: &[Format]) -> Option<Format> {
+    let mut accepted: Vec<(String, f32)> = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_media_range)
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    if accepted.is_empty() {
+        return supported.first().copied();
+    }
+
+    // stable sort, so media ranges with the same quality keep the clients order
+    accepted.sort_by(|(_, lhs), (_, rhs)| rhs.total_cmp(lhs));
+
+    accepted.into_iter().find_map(|(media_range, _)| {
+        supported.iter().copied().find(|format| match media_range.as_str() {
+            "*/*" => true,
+            "application/*" => true,
+            media_type => format.matches(media_type),
+        })
+    })
+}
+
+fn parse_media_range(value: &str) -> Option<(String, f32)> {
+    let mut parts = value.split(';').map(str::trim);
+
+    let media_range = parts.next().filter(|media_range| !media_range.is_empty())?;
+
+    let quality = parts
+        .filter_map(|param| param.strip_prefix("q="))
+        .find_map(|quality| quality.parse().ok())
+        .unwrap_or(1.0);
+
+    Some((media_range.to_ascii_lowercase(), quality))
+}
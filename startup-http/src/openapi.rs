@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use axum::http::header;
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use utoipa::openapi::{Components, OpenApi};
+use utoipa::ToSchema;
+
+pub use utoipa;
+
+use crate::ErrorResponse;
+
+/// Serves the given OpenAPI document below `path` as `openapi.json`, together with
+/// a Swagger UI at `swagger-ui` and a RapiDoc page at `rapidoc`.
+///
+/// The schema of [ErrorResponse] is added to the components of the document,
+/// so handlers can reference it in their responses.
+///
+/// Use like this: `.merge(openapi_router("/docs", ApiDoc::openapi()))`
+pub fn openapi_router(path: &str, mut openapi: OpenApi) -> Router {
+    let (name, schema) = ErrorResponse::schema();
+
+    openapi
+        .components
+        .get_or_insert_with(Components::new)
+        .schemas
+        .insert(name.to_string(), schema);
+
+    let json: Arc<str> = openapi.to_json().expect("serialize openapi document").into();
+
+    let base = path.trim_end_matches('/');
+
+    // the pages reference the document relative to their own path, so the
+    // router keeps working when it is nested.
+    let swagger_ui = Html(SWAGGER_UI.replace("{url}", "openapi.json"));
+    let rapidoc = Html(RAPIDOC.replace("{url}", "openapi.json"));
+
+    Router::new()
+        .route(
+            &format!("{}/openapi.json", base),
+            get(move || async move { ([(header::CONTENT_TYPE, "application/json")], json.to_string()) }),
+        )
+        .route(&format!("{}/swagger-ui", base), get(move || async move { swagger_ui }))
+        .route(&format!("{}/rapidoc", base), get(move || async move { rapidoc }))
+}
+
+const SWAGGER_UI: &str = r##"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>API documentation</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>window.ui = SwaggerUIBundle({ url: "{url}", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+const RAPIDOC: &str = r#"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>API documentation</title>
+  <script type="module" src="https://unpkg.com/rapidoc@9/dist/rapidoc-min.js"></script>
+</head>
+<body>
+  <rapi-doc spec-url="{url}" render-style="read" show-header="false"></rapi-doc>
+</body>
+</html>
+"#;
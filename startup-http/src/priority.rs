@@ -0,0 +1,532 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::http::{header, HeaderName, HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::error::{header_name, ConfigError};
+use crate::WebError;
+
+lazy_static::lazy_static! {
+    static ref QUEUE_DURATION: Histogram<f64> = opentelemetry::global::meter("startup-http")
+        .f64_histogram("http.server.queue.duration")
+        .with_description("Time requests waited for a slot of the priority layer")
+        .with_unit(Unit::new("s"))
+        .init();
+
+    static ref QUEUE_REJECTED: Counter<u64> = opentelemetry::global::meter("startup-http")
+        .u64_counter("http.server.queue.rejected")
+        .with_description("Requests rejected by the priority layer, because the queue was full or they waited too long")
+        .init();
+}
+
+/// Priority of a request, see [PriorityLayer].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Bulk requests of background consumers, like exports or synchronizations.
+    Batch,
+
+    /// Requests of users waiting for the response.
+    Interactive,
+
+    /// Health and admin requests. Never limited or queued.
+    Critical,
+}
+
+impl Priority {
+    fn as_str(self) -> &'static str {
+        match self {
+            Priority::Batch => "batch",
+            Priority::Interactive => "interactive",
+            Priority::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriorityConfig {
+    /// Requests of priority batch and interactive handled at the same time.
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+
+    /// Fraction of `max_concurrency` that batch requests may use, so interactive
+    /// requests find a free slot without waiting for a batch request to finish.
+    #[serde(default = "default_batch_share")]
+    pub batch_share: f64,
+
+    /// Requests waiting for a slot. Further requests are answered with `503 Service Unavailable`.
+    #[serde(default = "default_max_queue")]
+    pub max_queue: usize,
+
+    /// Requests waiting longer for a slot are answered with `503 Service Unavailable`.
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+
+    /// Priority of requests by the prefix of their path. The longest matching prefix applies.
+    #[serde(default = "default_routes")]
+    pub routes: Vec<PriorityRoute>,
+
+    /// Priority of requests not matching any route.
+    #[serde(default = "default_priority")]
+    pub default: Priority,
+
+    /// Header clients use to lower the priority of their requests, like `X-Priority: batch`.
+    /// Clients can not raise the priority given by the route.
+    #[serde(default = "default_header")]
+    pub header: Option<String>,
+
+    /// Header identifying the client, like `X-Client-Id`. Waiting requests of the same priority
+    /// take turns by client, so a single client can not fill the queue for everyone else.
+    /// Defaults to the address of the client, see [ClientIp](crate::ClientIp).
+    #[serde(default)]
+    pub fairness_header: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriorityRoute {
+    pub prefix: String,
+    pub priority: Priority,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: default_max_concurrency(),
+            batch_share: default_batch_share(),
+            max_queue: default_max_queue(),
+            queue_timeout_ms: default_queue_timeout_ms(),
+            routes: default_routes(),
+            default: default_priority(),
+            header: default_header(),
+            fairness_header: None,
+        }
+    }
+}
+
+fn default_max_concurrency() -> usize {
+    64
+}
+
+fn default_batch_share() -> f64 {
+    0.5
+}
+
+fn default_max_queue() -> usize {
+    256
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_routes() -> Vec<PriorityRoute> {
+    ["/health", "/admin"]
+        .into_iter()
+        .map(|prefix| PriorityRoute {
+            prefix: prefix.to_string(),
+            priority: Priority::Critical,
+        })
+        .collect()
+}
+
+fn default_priority() -> Priority {
+    Priority::Interactive
+}
+
+fn default_header() -> Option<String> {
+    Some("x-priority".to_string())
+}
+
+/// Limits the number of requests handled at the same time. Requests over the limit wait
+/// for a slot, interactive requests before batch requests, which may only use a share of
+/// the slots. This way background bulk consumers can not starve interactive users during
+/// a load spike. Critical requests, like health checks, are never limited.
+///
+/// Requests that find the queue full or wait longer than `queue_timeout_ms` are answered with
+/// `503 Service Unavailable`. The wait is recorded in the `http.server.queue.duration` metric,
+/// rejected requests are counted in `http.server.queue.rejected`, both labeled with the priority.
+///
+/// Use like this: `router.layer(PriorityLayer::new(&config.priority)?)`
+#[derive(Clone)]
+pub struct PriorityLayer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    routes: Vec<PriorityRoute>,
+    default: Priority,
+    header: Option<HeaderName>,
+    fairness_header: Option<HeaderName>,
+    queue_timeout: Duration,
+    scheduler: Arc<Scheduler>,
+}
+
+impl PriorityLayer {
+    pub fn new(config: &PriorityConfig) -> Result<Self, ConfigError> {
+        let mut routes = config.routes.clone();
+
+        // longest prefix first
+        routes.sort_by_key(|route| std::cmp::Reverse(route.prefix.len()));
+
+        let max_concurrency = config.max_concurrency.max(1);
+        let max_batch = ((max_concurrency as f64 * config.batch_share).floor() as usize).clamp(1, max_concurrency);
+
+        let scheduler = Scheduler {
+            max_concurrency,
+            max_batch,
+            max_queue: config.max_queue,
+            state: Mutex::new(State::default()),
+        };
+
+        let inner = Inner {
+            routes,
+            default: config.default,
+            header: config
+                .header
+                .as_deref()
+                .map(|name| header_name("priority.header", name))
+                .transpose()?,
+            fairness_header: config
+                .fairness_header
+                .as_deref()
+                .map(|name| header_name("priority.fairness_header", name))
+                .transpose()?,
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            scheduler: Arc::new(scheduler),
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+}
+
+impl Inner {
+    fn classify<B>(&self, req: &Request<B>) -> Priority {
+        let path = req.uri().path();
+
+        let priority = self
+            .routes
+            .iter()
+            .find(|route| crate::is_path_prefix(&route.prefix, path))
+            .map_or(self.default, |route| route.priority);
+
+        let requested = self
+            .header
+            .as_ref()
+            .and_then(|header| req.headers().get(header))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| match value.trim().to_ascii_lowercase().as_str() {
+                "batch" => Some(Priority::Batch),
+                "interactive" => Some(Priority::Interactive),
+                _ => None,
+            });
+
+        match requested {
+            Some(requested) => requested.min(priority),
+            None => priority,
+        }
+    }
+
+    fn fairness_key<B>(&self, req: &Request<B>) -> String {
+        let key = self
+            .fairness_header
+            .as_ref()
+            .and_then(|header| req.headers().get(header))
+            .and_then(|value| value.to_str().ok());
+
+        match key {
+            Some(key) => key.to_string(),
+            // the address of the client behind trusted proxies, not the one of the proxy
+            None => crate::client_ip::resolve(req.headers(), req.extensions())
+                .map(|ip| ip.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl<S> tower_layer::Layer<S> for PriorityLayer {
+    type Service = PriorityService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PriorityService {
+            inner,
+            priority: self.inner.clone(),
+        }
+    }
+}
+
+/// Middleware created by [PriorityLayer].
+#[derive(Clone)]
+pub struct PriorityService<S> {
+    inner: S,
+    priority: Arc<Inner>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for PriorityService<S>
+where
+    S: tower_service::Service<Request<B>> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // the inner service was polled ready, keep it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let this = self.priority.clone();
+
+        Box::pin(async move {
+            let priority = this.classify(&req);
+
+            if priority == Priority::Critical {
+                return Ok(inner.call(req).await?.into_response());
+            }
+
+            let labels = [KeyValue::new("priority", priority.as_str())];
+            let started = Instant::now();
+
+            let acquired = this
+                .scheduler
+                .acquire(priority, this.fairness_key(&req), this.queue_timeout)
+                .await;
+
+            let cx = opentelemetry::Context::current();
+            QUEUE_DURATION.record(&cx, started.elapsed().as_secs_f64(), &labels);
+
+            let _permit = match acquired {
+                Ok(permit) => permit,
+
+                Err(reason) => {
+                    let [label] = labels;
+                    QUEUE_REJECTED.add(&cx, 1, &[label, KeyValue::new("reason", reason)]);
+
+                    debug!("Rejected request of priority {}, {}", priority.as_str(), reason);
+                    return Ok(unavailable(reason));
+                }
+            };
+
+            Ok(inner.call(req).await?.into_response())
+        })
+    }
+}
+
+fn unavailable(reason: &str) -> Response {
+    let message = format!("Service is overloaded, {}", reason);
+    let mut response = WebError::Response(StatusCode::SERVICE_UNAVAILABLE, message).into_response();
+
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+
+    response
+}
+
+/// Hands out slots to waiting requests by priority, and by client within a priority.
+struct Scheduler {
+    max_concurrency: usize,
+    max_batch: usize,
+    max_queue: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    running_batch: usize,
+    queued: usize,
+    interactive: Queue,
+    batch: Queue,
+}
+
+/// Waiting requests, taking turns by client.
+#[derive(Default)]
+struct Queue {
+    turns: VecDeque<String>,
+    waiting: HashMap<String, VecDeque<oneshot::Sender<Permit>>>,
+}
+
+impl Queue {
+    fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    fn push(&mut self, key: String, waiter: oneshot::Sender<Permit>) {
+        let waiting = self.waiting.entry(key.clone()).or_default();
+        if waiting.is_empty() {
+            self.turns.push_back(key);
+        }
+
+        waiting.push_back(waiter);
+    }
+
+    /// The next waiter of the client whose turn it is.
+    fn pop(&mut self) -> Option<oneshot::Sender<Permit>> {
+        let key = self.turns.pop_front()?;
+        let waiting = self.waiting.get_mut(&key)?;
+        let waiter = waiting.pop_front();
+
+        if waiting.is_empty() {
+            self.waiting.remove(&key);
+        } else {
+            self.turns.push_back(key);
+        }
+
+        waiter
+    }
+
+    /// Removes the requests that stopped waiting, and returns their number.
+    fn remove_closed(&mut self) -> usize {
+        let mut removed = 0;
+
+        self.waiting.retain(|_, waiting| {
+            let len = waiting.len();
+            waiting.retain(|waiter| !waiter.is_closed());
+            removed += len - waiting.len();
+
+            !waiting.is_empty()
+        });
+
+        let waiting = &self.waiting;
+        self.turns.retain(|key| waiting.contains_key(key));
+
+        removed
+    }
+}
+
+impl State {
+    fn queue(&mut self, priority: Priority) -> &mut Queue {
+        match priority {
+            Priority::Batch => &mut self.batch,
+            _ => &mut self.interactive,
+        }
+    }
+
+    fn start(&mut self, priority: Priority) {
+        self.running += 1;
+        if priority == Priority::Batch {
+            self.running_batch += 1;
+        }
+    }
+
+    fn finish(&mut self, priority: Priority) {
+        self.running -= 1;
+        if priority == Priority::Batch {
+            self.running_batch -= 1;
+        }
+    }
+}
+
+impl Scheduler {
+    fn can_start(&self, state: &State, priority: Priority) -> bool {
+        match priority {
+            Priority::Batch => state.running < self.max_concurrency && state.running_batch < self.max_batch,
+            _ => state.running < self.max_concurrency,
+        }
+    }
+
+    /// Waits for a slot. Fails with the reason if the queue is full or the timeout elapsed.
+    async fn acquire(
+        self: &Arc<Self>,
+        priority: Priority,
+        key: String,
+        timeout: Duration,
+    ) -> Result<Permit, &'static str> {
+        let waiting = {
+            let mut state = self.state.lock();
+
+            // requests of a higher priority are served first
+            let queued_before = match priority {
+                Priority::Batch => !state.interactive.is_empty() || !state.batch.is_empty(),
+                _ => !state.interactive.is_empty(),
+            };
+
+            if !queued_before && self.can_start(&state, priority) {
+                state.start(priority);
+                return Ok(Permit::new(self, priority));
+            }
+
+            if state.queued >= self.max_queue {
+                let removed = state.interactive.remove_closed() + state.batch.remove_closed();
+                state.queued -= removed;
+
+                if state.queued >= self.max_queue {
+                    return Err("queue is full");
+                }
+            }
+
+            let (tx, rx) = oneshot::channel();
+            state.queue(priority).push(key, tx);
+            state.queued += 1;
+
+            rx
+        };
+
+        match tokio::time::timeout(timeout, waiting).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) | Err(_) => Err("waited too long"),
+        }
+    }
+
+    /// Frees the slot of a finished request and hands out slots to waiting requests.
+    fn release(self: &Arc<Self>, priority: Priority) {
+        let mut state = self.state.lock();
+        state.finish(priority);
+
+        for priority in [Priority::Interactive, Priority::Batch] {
+            while self.can_start(&state, priority) {
+                let Some(waiter) = state.queue(priority).pop() else {
+                    break;
+                };
+
+                state.queued -= 1;
+                state.start(priority);
+
+                // the request stopped waiting, e.g. because the client went away
+                if let Err(mut permit) = waiter.send(Permit::new(self, priority)) {
+                    permit.scheduler = None;
+                    state.finish(priority);
+                }
+            }
+        }
+    }
+}
+
+/// A slot of the [Scheduler], freed when dropped.
+struct Permit {
+    scheduler: Option<Arc<Scheduler>>,
+    priority: Priority,
+}
+
+impl Permit {
+    fn new(scheduler: &Arc<Scheduler>, priority: Priority) -> Self {
+        Self {
+            scheduler: Some(scheduler.clone()),
+            priority,
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release(self.priority);
+        }
+    }
+}
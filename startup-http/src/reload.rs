@@ -0,0 +1,184 @@
+use std::io;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use eyre::eyre;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::shutdown;
+
+/// Variable with the file descriptor on which the new process reports that it accepts connections.
+const READY_FD_VAR: &str = "STARTUP_RELOAD_READY_FD";
+
+/// File descriptors of the listener and the ready socket in the new process.
+const LISTENER_FD: RawFd = 3;
+const READY_FD: RawFd = 4;
+
+/// Time the new process gets to start accepting connections before it is killed.
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Set once the previous process was notified, the fd is closed from then on.
+static NOTIFIED: AtomicBool = AtomicBool::new(false);
+
+/// Starts a new process of the binary on SIGUSR2 and passes it the listener, using the
+/// `LISTEN_FDS` protocol of systemd. Once the new process accepts connections, this process
+/// sends itself SIGTERM, so all components shut down gracefully as if the service was stopped.
+/// If the new process fails to start, this process keeps serving.
+///
+/// Returns when the server begins to shut down.
+pub(crate) async fn run(listener: OwnedFd) {
+    let mut reload = match signal(SignalKind::user_defined2()) {
+        Ok(reload) => reload,
+        Err(err) => {
+            warn!("Failed to install SIGUSR2 handler, reload is disabled: {}", err);
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = reload.recv() => {},
+            _ = shutdown::shutdown_requested() => return,
+        }
+
+        info!("Received SIGUSR2, handing the listener over to a new process");
+
+        match handover(&listener).await {
+            Ok(pid) => {
+                info!("Process {} accepts connections, shutting down", pid);
+
+                // Safety: sends a signal to this process, which is handled by tokio.
+                unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+
+                return;
+            }
+
+            Err(err) => error!("Reload failed, continuing to serve: {:#}", err),
+        }
+    }
+}
+
+/// Starts the new process and waits until it accepts connections. Returns its pid.
+async fn handover(listener: &OwnedFd) -> eyre::Result<u32> {
+    let (ready, child_ready) = UnixStream::pair()?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+
+    command
+        .args(std::env::args_os().skip(1))
+        .env("LISTEN_FDS", "1")
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDNAMES")
+        .env(READY_FD_VAR, READY_FD.to_string());
+
+    let listener = listener.as_raw_fd();
+    let child_ready_fd = child_ready.as_raw_fd();
+
+    // Safety: only async-signal-safe functions are called between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            // move both out of the way first, they might already use the target numbers
+            let listener = cvt(libc::fcntl(listener, libc::F_DUPFD_CLOEXEC, READY_FD + 1))?;
+            let ready = cvt(libc::fcntl(child_ready_fd, libc::F_DUPFD_CLOEXEC, READY_FD + 1))?;
+
+            // the duplicates are inherited, dup2 clears their close-on-exec flag
+            cvt(libc::dup2(listener, LISTENER_FD))?;
+            cvt(libc::dup2(ready, READY_FD))?;
+
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn()?;
+    let pid = child.id();
+
+    // only the new process holds the other end now, it closes once the process exits
+    drop(child_ready);
+
+    ready.set_nonblocking(true)?;
+    let ready = tokio::net::UnixStream::from_std(ready)?;
+
+    match tokio::time::timeout(READY_TIMEOUT, wait_ready(&ready)).await {
+        Ok(Ok(true)) => Ok(pid),
+
+        Ok(Ok(false)) => {
+            let status = tokio::task::spawn_blocking(move || child.wait()).await??;
+            Err(eyre!("process {} exited before accepting connections: {}", pid, status))
+        }
+
+        Ok(Err(err)) => {
+            kill(child);
+            Err(err.into())
+        }
+
+        Err(_) => {
+            kill(child);
+            Err(eyre!(
+                "process {} did not accept connections within {:?}",
+                pid,
+                READY_TIMEOUT
+            ))
+        }
+    }
+}
+
+/// Returns true once the new process reported that it is ready, false if it exited.
+async fn wait_ready(ready: &tokio::net::UnixStream) -> io::Result<bool> {
+    let mut buf = [0; 1];
+
+    loop {
+        ready.readable().await?;
+
+        match ready.try_read(&mut buf) {
+            Ok(len) => return Ok(len > 0),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn kill(mut child: std::process::Child) {
+    let _ = child.kill();
+
+    // reap the process in the background
+    tokio::task::spawn_blocking(move || child.wait());
+}
+
+fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        result => Ok(result),
+    }
+}
+
+/// Reports to the process that started this one during a reload that the server accepts
+/// connections, so it can shut down. Does nothing if this process was not started by a reload.
+///
+/// The variable is left in the environment, changing it while the runtime runs other threads is
+/// not safe. A new process started by a reload gets the variable set by [handover].
+pub(crate) fn notify_ready() {
+    let Ok(fd) = std::env::var(READY_FD_VAR) else {
+        return;
+    };
+
+    if NOTIFIED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let Ok(fd) = fd.parse::<RawFd>() else {
+        warn!("Ignoring invalid {}={}", READY_FD_VAR, fd);
+        return;
+    };
+
+    // Safety: the fd was passed in by the previous process, see handover.
+    let mut stream = unsafe { UnixStream::from_raw_fd(fd) };
+
+    if let Err(err) = stream.write_all(b"1") {
+        warn!("Failed to notify the previous process about the reload: {}", err);
+    }
+}
@@ -0,0 +1,826 @@
+//! Resumable uploads using the core protocol of [tus](https://tus.io/protocols/resumable-upload)
+//! with the creation, termination and expiration extensions, so existing clients like
+//! `tus-js-client` or `uppy` can be used.
+//!
+//! The client creates an upload with its length using `POST`, then sends the content in one
+//! or more `PATCH` requests. If a request is interrupted, the client asks for the offset of
+//! the upload using `HEAD` and continues from there. Once complete, the upload is passed to
+//! the handler, e.g. to move it to a storage backend, and then removed from the [UploadStore].
+
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use futures_util::{StreamExt, TryStreamExt};
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::WebError;
+
+const TUS_VERSION: &str = "1.0.0";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+static TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+static TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+static TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+static TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+static UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+static UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+static UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+static UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumableUploadConfig {
+    /// Path of the upload endpoints, e.g. `/api/uploads`.
+    pub base_path: String,
+
+    /// Uploads larger than this are rejected with `413 Payload Too Large`.
+    #[serde(default = "default_max_size")]
+    pub max_size: u64,
+
+    /// Incomplete uploads are removed after this time.
+    #[serde(default = "default_expiration_seconds")]
+    pub expiration_seconds: u64,
+}
+
+fn default_max_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_expiration_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+/// The state of an upload, as kept by the [UploadStore].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub id: String,
+
+    /// Length of the complete upload in bytes.
+    pub length: u64,
+
+    /// Number of bytes received so far.
+    pub offset: u64,
+
+    /// Metadata sent by the client when creating the upload, like `filename` or `filetype`.
+    pub metadata: BTreeMap<String, String>,
+
+    pub created_at: SystemTime,
+}
+
+impl Upload {
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+/// Chunks of the content of an upload.
+pub type ChunkStream = BoxStream<'static, io::Result<Bytes>>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("upload not found")]
+    NotFound,
+
+    #[error("upload is at offset {0}")]
+    OffsetMismatch(u64),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Keeps the content and the offset of uploads, so the client can resume an upload with
+/// another replica after a restart. Implemented by [FileUploadStore].
+#[async_trait]
+pub trait UploadStore: Send + Sync + 'static {
+    async fn create(&self, upload: &Upload) -> Result<(), UploadError>;
+
+    async fn get(&self, id: &str) -> Result<Option<Upload>, UploadError>;
+
+    /// Appends the chunk, if the upload is still at the offset. Bytes received before the chunk
+    /// failed must be kept, so the client can continue from there. Returns the new offset.
+    async fn append(&self, id: &str, offset: u64, chunk: ChunkStream) -> Result<u64, UploadError>;
+
+    /// Reads the content of the upload, once it is complete.
+    async fn read(&self, id: &str) -> Result<ChunkStream, UploadError>;
+
+    async fn delete(&self, id: &str) -> Result<(), UploadError>;
+
+    /// Deletes the uploads created before the time and returns their number.
+    async fn delete_expired(&self, created_before: SystemTime) -> Result<usize, UploadError>;
+}
+
+/// Keeps uploads as files in a directory, which should be on a volume shared by all replicas.
+/// The content of an upload is kept in a file named after its id, the state in a json file
+/// next to it. The offset is the size of the content, so it survives a crash during a request.
+pub struct FileUploadStore {
+    directory: PathBuf,
+}
+
+impl FileUploadStore {
+    /// Creates the directory if it does not exist.
+    pub fn new(directory: impl Into<PathBuf>) -> io::Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+
+        Ok(Self { directory })
+    }
+
+    /// Path of the content of the upload. Ids are generated by [ResumableUploads],
+    /// anything else is not found, so a client can not escape the directory.
+    fn content_path(&self, id: &str) -> Result<PathBuf, UploadError> {
+        if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(UploadError::NotFound);
+        }
+
+        Ok(self.directory.join(id))
+    }
+
+    fn state_path(&self, id: &str) -> Result<PathBuf, UploadError> {
+        Ok(self.content_path(id)?.with_extension("json"))
+    }
+
+    async fn read_state(path: &Path) -> Result<Option<Upload>, UploadError> {
+        match tokio::fs::read(path).await {
+            Ok(state) => Ok(Some(serde_json::from_slice(&state).map_err(io::Error::from)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl UploadStore for FileUploadStore {
+    async fn create(&self, upload: &Upload) -> Result<(), UploadError> {
+        File::create(self.content_path(&upload.id)?).await?;
+
+        let state = serde_json::to_vec(upload).map_err(io::Error::from)?;
+        tokio::fs::write(self.state_path(&upload.id)?, state).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Upload>, UploadError> {
+        let Some(mut upload) = Self::read_state(&self.state_path(id)?).await? else {
+            return Ok(None);
+        };
+
+        upload.offset = match tokio::fs::metadata(self.content_path(id)?).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Some(upload))
+    }
+
+    async fn append(&self, id: &str, offset: u64, mut chunk: ChunkStream) -> Result<u64, UploadError> {
+        let mut file = match OpenOptions::new().append(true).open(self.content_path(id)?).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(UploadError::NotFound),
+            Err(err) => return Err(err.into()),
+        };
+
+        let current = file.metadata().await?.len();
+        if current != offset {
+            return Err(UploadError::OffsetMismatch(current));
+        }
+
+        let mut written = offset;
+
+        let result = async {
+            while let Some(bytes) = chunk.try_next().await? {
+                file.write_all(&bytes).await?;
+                written += bytes.len() as u64;
+            }
+
+            io::Result::Ok(())
+        }
+        .await;
+
+        // keep what was received, even if the client went away
+        file.flush().await?;
+        result?;
+
+        Ok(written)
+    }
+
+    async fn read(&self, id: &str) -> Result<ChunkStream, UploadError> {
+        let file = match File::open(self.content_path(id)?).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(UploadError::NotFound),
+            Err(err) => return Err(err.into()),
+        };
+
+        let chunks = futures_util::stream::try_unfold(file, |mut file| async move {
+            let mut buffer = vec![0; 64 * 1024];
+
+            match file.read(&mut buffer).await? {
+                0 => Ok(None),
+                len => {
+                    buffer.truncate(len);
+                    Ok(Some((Bytes::from(buffer), file)))
+                }
+            }
+        });
+
+        Ok(chunks.boxed())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), UploadError> {
+        for path in [self.state_path(id)?, self.content_path(id)?] {
+            match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete_expired(&self, created_before: SystemTime) -> Result<usize, UploadError> {
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        let mut deleted = 0;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+
+            let Some(upload) = Self::read_state(&path).await? else {
+                continue;
+            };
+
+            if upload.created_at < created_before {
+                self.delete(&upload.id).await?;
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// A complete upload, passed to the handler of [ResumableUploads].
+pub struct CompletedUpload {
+    upload: Upload,
+    store: Arc<dyn UploadStore>,
+}
+
+impl CompletedUpload {
+    pub fn id(&self) -> &str {
+        &self.upload.id
+    }
+
+    pub fn length(&self) -> u64 {
+        self.upload.length
+    }
+
+    /// Metadata sent by the client, like `filename` or `filetype`.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.upload.metadata
+    }
+
+    /// The content of the upload, e.g. to pass it on to a storage backend.
+    pub async fn stream(&self) -> Result<ChunkStream, UploadError> {
+        self.store.read(&self.upload.id).await
+    }
+
+    /// Copies the content of the upload into a new file at the given path.
+    /// The file is removed if the copy fails.
+    pub async fn save_to(&self, path: impl AsRef<Path>) -> Result<u64, UploadError> {
+        let path = path.as_ref();
+        let mut file = File::create(path).await?;
+
+        let result = async {
+            let mut chunks = self.stream().await?;
+            let mut written = 0;
+
+            while let Some(bytes) = chunks.try_next().await? {
+                file.write_all(&bytes).await?;
+                written += bytes.len() as u64;
+            }
+
+            file.flush().await?;
+            Ok(written)
+        }
+        .await;
+
+        if result.is_err() {
+            drop(file);
+            let _ = tokio::fs::remove_file(path).await;
+        }
+
+        result
+    }
+}
+
+type Handler = dyn Fn(CompletedUpload) -> BoxFuture<'static, Result<(), WebError>> + Send + Sync;
+
+/// Endpoints for resumable uploads, see the [module](self) documentation.
+///
+/// Bytes received are counted in the `http.server.uploads.bytes` metric, created, completed,
+/// failed and expired uploads in `http.server.uploads`, both labeled with the base path.
+///
+/// Use like this:
+/// ```ignore
+/// let store = FileUploadStore::new("/data/uploads")?;
+///
+/// let uploads = ResumableUploads::new(&config.uploads, store, |upload: CompletedUpload| async move {
+///     upload.save_to(Path::new("/data/files").join(upload.id())).await?;
+///     Ok(())
+/// });
+///
+/// let router = router.merge(uploads.router());
+/// ```
+#[derive(Clone)]
+pub struct ResumableUploads {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    base_path: String,
+    max_size: u64,
+    expiration: Duration,
+    store: Arc<dyn UploadStore>,
+    handler: Box<Handler>,
+
+    // uploads receiving a chunk in this replica
+    locked: Mutex<HashSet<String>>,
+
+    bytes: Counter<u64>,
+    uploads: Counter<u64>,
+}
+
+impl ResumableUploads {
+    /// The handler is called once an upload is complete. If it fails, the upload is kept until
+    /// it expires, and the client can retry the completion with an empty `PATCH` request.
+    ///
+    /// Expired uploads are removed in the background. Must be called within a tokio runtime.
+    pub fn new<F, Fut>(config: &ResumableUploadConfig, store: impl UploadStore, handler: F) -> Self
+    where
+        F: Fn(CompletedUpload) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), WebError>> + Send + 'static,
+    {
+        let meter = opentelemetry::global::meter("startup-http");
+
+        let inner = Inner {
+            base_path: config.base_path.trim_end_matches('/').to_string(),
+            max_size: config.max_size,
+            expiration: Duration::from_secs(config.expiration_seconds),
+            store: Arc::new(store),
+            handler: Box::new(move |upload| Box::pin(handler(upload))),
+            locked: Mutex::new(HashSet::new()),
+
+            bytes: meter
+                .u64_counter("http.server.uploads.bytes")
+                .with_description("Bytes received by resumable uploads")
+                .init(),
+
+            uploads: meter
+                .u64_counter("http.server.uploads")
+                .with_description("Resumable uploads by event, like created or completed")
+                .init(),
+        };
+
+        let uploads = Self { inner: Arc::new(inner) };
+        tokio::spawn(uploads.clone().expire());
+
+        uploads
+    }
+
+    /// Endpoints `POST` and `OPTIONS` on the base path, `HEAD`, `PATCH` and `DELETE` below it.
+    pub fn router(&self) -> Router {
+        let create = {
+            let this = self.clone();
+            move |headers: HeaderMap| async move { with_tus(this.create(&headers).await.into_response()) }
+        };
+
+        let options = {
+            let this = self.clone();
+            move || async move { with_tus(this.options()) }
+        };
+
+        let head = {
+            let this = self.clone();
+            move |extract::Path(id): extract::Path<String>| async move { with_tus(this.head(&id).await.into_response()) }
+        };
+
+        let patch = {
+            let this = self.clone();
+            move |extract::Path(id): extract::Path<String>,
+                  headers: HeaderMap,
+                  extract::RawBody(body): extract::RawBody| async move {
+                with_tus(this.patch(&id, &headers, body).await.into_response())
+            }
+        };
+
+        let delete = {
+            let this = self.clone();
+            move |extract::Path(id): extract::Path<String>| async move { with_tus(this.delete(&id).await.into_response()) }
+        };
+
+        let base_path = match self.inner.base_path.as_str() {
+            "" => "/",
+            base_path => base_path,
+        };
+
+        Router::new().route(base_path, post(create).options(options)).route(
+            &format!("{}/:id", self.inner.base_path),
+            axum::routing::head(head).patch(patch).delete(delete),
+        )
+    }
+
+    fn options(&self) -> Response {
+        let headers = [
+            (TUS_VERSION_HEADER.clone(), HeaderValue::from_static(TUS_VERSION)),
+            (
+                TUS_EXTENSION.clone(),
+                HeaderValue::from_static("creation,termination,expiration"),
+            ),
+            (TUS_MAX_SIZE.clone(), HeaderValue::from(self.inner.max_size)),
+        ];
+
+        (StatusCode::NO_CONTENT, headers).into_response()
+    }
+
+    async fn create(&self, headers: &HeaderMap) -> Result<Response, WebError> {
+        check_version(headers)?;
+
+        let length = parse_header(headers, &UPLOAD_LENGTH)?;
+
+        if length > self.inner.max_size {
+            let message = format!("Uploads are limited to {} bytes", self.inner.max_size);
+            return Err(WebError::Response(StatusCode::PAYLOAD_TOO_LARGE, message));
+        }
+
+        let metadata = match headers.get(&UPLOAD_METADATA) {
+            Some(value) => parse_metadata(value)?,
+            None => BTreeMap::new(),
+        };
+
+        let upload = Upload {
+            id: format!("{:032x}", rand::random::<u128>()),
+            length,
+            offset: 0,
+            metadata,
+            created_at: SystemTime::now(),
+        };
+
+        self.inner.store.create(&upload).await.map_err(into_web_error)?;
+        self.record("created");
+
+        debug!("Created upload {} of {} bytes", upload.id, upload.length);
+
+        let location = format!("{}/{}", self.inner.base_path, upload.id);
+        let expires = self.expires(&upload);
+
+        // an empty upload is complete right away
+        if upload.is_complete() {
+            self.complete(upload).await?;
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::LOCATION, HeaderValue::from_str(&location)?);
+        insert_expires(&mut headers, expires);
+
+        Ok((StatusCode::CREATED, headers).into_response())
+    }
+
+    async fn head(&self, id: &str) -> Result<Response, WebError> {
+        let upload = self.get(id).await?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(UPLOAD_OFFSET.clone(), HeaderValue::from(upload.offset));
+        headers.insert(UPLOAD_LENGTH.clone(), HeaderValue::from(upload.length));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+        insert_expires(&mut headers, self.expires(&upload));
+
+        if !upload.metadata.is_empty() {
+            if let Ok(metadata) = HeaderValue::from_str(&format_metadata(&upload.metadata)) {
+                headers.insert(UPLOAD_METADATA.clone(), metadata);
+            }
+        }
+
+        Ok((StatusCode::OK, headers).into_response())
+    }
+
+    async fn patch(&self, id: &str, headers: &HeaderMap, body: Body) -> Result<Response, WebError> {
+        check_version(headers)?;
+
+        let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+        if content_type != Some(OFFSET_CONTENT_TYPE) {
+            let message = format!("Expected request with `Content-Type: {}`", OFFSET_CONTENT_TYPE);
+            return Err(WebError::Response(StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+        }
+
+        let offset = parse_header(headers, &UPLOAD_OFFSET)?;
+
+        let Some(_lock) = Lock::acquire(&self.inner, id) else {
+            let message = "Upload is receiving another chunk".to_string();
+            return Err(WebError::Response(StatusCode::CONFLICT, message));
+        };
+
+        let upload = self.get(id).await?;
+
+        if offset != upload.offset {
+            let message = format!("Upload is at offset {}", upload.offset);
+            return Err(WebError::Response(StatusCode::CONFLICT, message));
+        }
+
+        let remaining = upload.length - upload.offset;
+
+        let content_length: Option<u64> = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+
+        if content_length.is_some_and(|content_length| content_length > remaining) {
+            let message = format!("Chunk exceeds the remaining {} bytes of the upload", remaining);
+            return Err(WebError::Response(StatusCode::PAYLOAD_TOO_LARGE, message));
+        }
+
+        let chunk = limit(body, remaining);
+        let appended = self.inner.store.append(id, offset, chunk).await;
+
+        let new_offset = match appended {
+            Ok(new_offset) => new_offset,
+
+            Err(err) => {
+                // the bytes received before the chunk failed are kept
+                if let Ok(Some(upload)) = self.inner.store.get(id).await {
+                    self.inner.bytes.add(
+                        &opentelemetry::Context::current(),
+                        upload.offset.saturating_sub(offset),
+                        &self.labels(),
+                    );
+
+                    // the client sent more than the remaining bytes, which completed the upload
+                    if upload.is_complete() {
+                        self.complete(upload).await?;
+                    }
+                }
+
+                return Err(into_web_error(err));
+            }
+        };
+
+        self.inner
+            .bytes
+            .add(&opentelemetry::Context::current(), new_offset - offset, &self.labels());
+
+        debug!("Upload {} at {} of {} bytes", id, new_offset, upload.length);
+
+        let upload = Upload {
+            offset: new_offset,
+            ..upload
+        };
+
+        if upload.is_complete() {
+            self.complete(upload).await?;
+        }
+
+        let headers = [(UPLOAD_OFFSET.clone(), HeaderValue::from(new_offset))];
+        Ok((StatusCode::NO_CONTENT, headers).into_response())
+    }
+
+    async fn delete(&self, id: &str) -> Result<Response, WebError> {
+        self.get(id).await?;
+        self.inner.store.delete(id).await.map_err(into_web_error)?;
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    /// Gets the upload, answers with `404 Not Found` if it does not exist or expired.
+    async fn get(&self, id: &str) -> Result<Upload, WebError> {
+        let not_found = || WebError::Response(StatusCode::NOT_FOUND, "Upload not found".to_string());
+
+        let upload = match self.inner.store.get(id).await {
+            Ok(Some(upload)) => upload,
+            Ok(None) | Err(UploadError::NotFound) => return Err(not_found()),
+            Err(err) => return Err(into_web_error(err)),
+        };
+
+        if self.expires(&upload) < SystemTime::now() {
+            return Err(not_found());
+        }
+
+        Ok(upload)
+    }
+
+    fn expires(&self, upload: &Upload) -> SystemTime {
+        upload.created_at + self.inner.expiration
+    }
+
+    /// Passes the upload to the handler and removes it once the handler succeeded.
+    async fn complete(&self, upload: Upload) -> Result<(), WebError> {
+        let id = upload.id.clone();
+
+        let completed = CompletedUpload {
+            upload,
+            store: self.inner.store.clone(),
+        };
+
+        if let Err(err) = (self.inner.handler)(completed).await {
+            self.record("failed");
+            return Err(err);
+        }
+
+        self.record("completed");
+        debug!("Completed upload {}", id);
+
+        if let Err(err) = self.inner.store.delete(&id).await {
+            warn!("Failed to delete completed upload {}: {}", id, err);
+        }
+
+        Ok(())
+    }
+
+    /// Removes expired uploads until the server shuts down.
+    async fn expire(self) {
+        let mut interval = tokio::time::interval((self.inner.expiration / 4).max(Duration::from_secs(60)));
+
+        loop {
+            tokio::select! {
+                _ = crate::shutdown_requested() => return,
+                _ = interval.tick() => {}
+            }
+
+            let created_before = SystemTime::now() - self.inner.expiration;
+
+            match self.inner.store.delete_expired(created_before).await {
+                Ok(0) => {}
+
+                Ok(deleted) => {
+                    debug!("Removed {} expired uploads", deleted);
+                    self.inner.uploads.add(
+                        &opentelemetry::Context::current(),
+                        deleted as u64,
+                        &self.labels_with("expired"),
+                    );
+                }
+
+                Err(err) => warn!("Failed to remove expired uploads: {}", err),
+            }
+        }
+    }
+
+    fn record(&self, event: &'static str) {
+        self.inner
+            .uploads
+            .add(&opentelemetry::Context::current(), 1, &self.labels_with(event));
+    }
+
+    fn labels(&self) -> [KeyValue; 1] {
+        [KeyValue::new("path", self.inner.base_path.clone())]
+    }
+
+    fn labels_with(&self, event: &'static str) -> [KeyValue; 2] {
+        let [path] = self.labels();
+        [path, KeyValue::new("event", event)]
+    }
+}
+
+/// Marks an upload as receiving a chunk until dropped.
+struct Lock<'a> {
+    inner: &'a Inner,
+    id: String,
+}
+
+impl<'a> Lock<'a> {
+    fn acquire(inner: &'a Inner, id: &str) -> Option<Self> {
+        inner.locked.lock().insert(id.to_string()).then(|| Lock {
+            inner,
+            id: id.to_string(),
+        })
+    }
+}
+
+impl Drop for Lock<'_> {
+    fn drop(&mut self) {
+        self.inner.locked.lock().remove(&self.id);
+    }
+}
+
+/// Streams the body, failing once it exceeds the remaining bytes of the upload.
+/// The bytes up to the limit are still appended.
+fn limit(body: Body, remaining: u64) -> ChunkStream {
+    futures_util::stream::try_unfold((body, remaining, false), |(mut body, remaining, exceeded)| async move {
+        if exceeded {
+            let message = "chunk exceeds the remaining bytes of the upload";
+            return Err(io::Error::new(io::ErrorKind::FileTooLarge, message));
+        }
+
+        let Some(mut bytes) = body.try_next().await.map_err(io::Error::other)? else {
+            return Ok(None);
+        };
+
+        let exceeded = bytes.len() as u64 > remaining;
+        if exceeded {
+            bytes.truncate(remaining as usize);
+        }
+
+        Ok(Some((bytes.clone(), (body, remaining - bytes.len() as u64, exceeded))))
+    })
+    .boxed()
+}
+
+fn insert_expires(headers: &mut HeaderMap, expires: SystemTime) {
+    if let Ok(expires) = HeaderValue::from_str(&httpdate::fmt_http_date(expires)) {
+        headers.insert(UPLOAD_EXPIRES.clone(), expires);
+    }
+}
+
+fn with_tus(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(TUS_RESUMABLE.clone(), HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+/// Requests must be sent for the version of the protocol that is supported.
+fn check_version(headers: &HeaderMap) -> Result<(), WebError> {
+    match headers.get(&TUS_RESUMABLE) {
+        Some(version) if version == TUS_VERSION => Ok(()),
+        _ => {
+            let message = format!("Expected request with `Tus-Resumable: {}`", TUS_VERSION);
+            Err(WebError::Response(StatusCode::PRECONDITION_FAILED, message))
+        }
+    }
+}
+
+fn parse_header(headers: &HeaderMap, name: &HeaderName) -> Result<u64, WebError> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            let message = format!("Expected request with a numeric `{}` header", name);
+            WebError::Response(StatusCode::BAD_REQUEST, message)
+        })
+}
+
+/// Parses the comma separated pairs of key and base64 encoded value. The value may be omitted.
+fn parse_metadata(value: &HeaderValue) -> Result<BTreeMap<String, String>, WebError> {
+    let invalid = || WebError::Response(StatusCode::BAD_REQUEST, "Invalid `Upload-Metadata` header".to_string());
+
+    let value = value.to_str().map_err(|_| invalid())?;
+
+    let mut metadata = BTreeMap::new();
+
+    for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+
+        let value = BASE64.decode(value.trim()).map_err(|_| invalid())?;
+        let value = String::from_utf8(value).map_err(|_| invalid())?;
+
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
+fn format_metadata(metadata: &BTreeMap<String, String>) -> String {
+    metadata
+        .iter()
+        .map(|(key, value)| match value.is_empty() {
+            true => key.clone(),
+            false => format!("{} {}", key, BASE64.encode(value)),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn into_web_error(err: UploadError) -> WebError {
+    match err {
+        UploadError::NotFound => WebError::Response(StatusCode::NOT_FOUND, "Upload not found".to_string()),
+        UploadError::OffsetMismatch(offset) => {
+            WebError::Response(StatusCode::CONFLICT, format!("Upload is at offset {}", offset))
+        }
+
+        UploadError::Io(err) if err.kind() == io::ErrorKind::FileTooLarge => {
+            WebError::Response(StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+        }
+
+        UploadError::Io(err) => err.into(),
+    }
+}
@@ -0,0 +1,13 @@
+use axum::routing::get;
+use axum::Router;
+
+use crate::Json;
+
+/// Admin endpoint `GET /admin/sbom` listing the crates compiled into the service with their
+/// versions and features, and the backends in use, like the tracing exporter or the database.
+/// See [sbom](startup_base::sbom).
+///
+/// Use like this: `admin.merge(startup_http::sbom_router())`
+pub fn sbom_router() -> Router {
+    Router::new().route("/admin/sbom", get(|| async { Json(startup_base::sbom::report()) }))
+}
@@ -1,9 +1,42 @@
+use std::future::Future;
+#[cfg(feature = "embed")]
+use std::ops::RangeInclusive;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::UNIX_EPOCH;
+
+use axum::body::{boxed, BoxBody};
 use axum::error_handling::HandleErrorLayer;
-use axum::http::{header, HeaderValue, StatusCode};
+use axum::http::{header, HeaderValue, Request, Response, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::{get_service, MethodRouter};
+use futures_util::future::poll_fn;
 use tower_http::services::ServeDir;
 use tower_http::set_header::SetResponseHeaderLayer;
+use tower_service::Service;
+
+use crate::conditional::not_modified;
+
+/// How clients are allowed to cache files served by [serve_static_with].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Caching {
+    /// Cache files forever. Only use this for files that never change their content,
+    /// e.g. files with a content hash in their name.
+    Immutable,
+
+    /// Clients may store files, but need to revalidate them using
+    /// `If-None-Match` or `If-Modified-Since` before each use.
+    Revalidate,
+}
+
+impl Caching {
+    pub(crate) fn cache_control(self) -> HeaderValue {
+        match self {
+            Caching::Immutable => HeaderValue::from_static("public, max-age=604800, immutable"),
+            Caching::Revalidate => HeaderValue::from_static("public, no-cache"),
+        }
+    }
+}
 
 /// Serves files from the given directory as static files.
 /// This method will aso configure caching headers to cache the files forever.
@@ -11,12 +44,26 @@ use tower_http::set_header::SetResponseHeaderLayer;
 /// Use like this: `.nest("/public", serve_static("./files/pub"))`
 ///
 pub fn serve_static(path: impl AsRef<std::path::Path>) -> MethodRouter {
-    let add_cache_control = SetResponseHeaderLayer::overriding(
-        header::CACHE_CONTROL,
-        HeaderValue::from_static("public, max-age=604800, immutable"),
-    );
+    serve_static_with(path, Caching::Immutable)
+}
 
-    get_service(ServeDir::new(path).precompressed_gzip())
+/// Serves files from the given directory as static files using the given caching strategy.
+///
+/// Every file is served with an `ETag` and a `Last-Modified` header. Conditional
+/// requests using `If-None-Match` or `If-Modified-Since` are answered with
+/// `304 Not Modified` if the file did not change.
+///
+/// Single byte ranges requested using `Range` are answered with `206 Partial Content`,
+/// so that downloads can be resumed and media files can be streamed. If the request
+/// contains an `If-Range` header that does not match the file, the full file is sent.
+pub fn serve_static_with(path: impl AsRef<std::path::Path>, caching: Caching) -> MethodRouter {
+    let add_cache_control = SetResponseHeaderLayer::overriding(header::CACHE_CONTROL, caching.cache_control());
+
+    let serve_dir = Conditional {
+        inner: ServeDir::new(path).precompressed_gzip(),
+    };
+
+    get_service(serve_dir)
         .layer(add_cache_control)
         .layer(HandleErrorLayer::new(io_error_to_response))
 }
@@ -27,3 +74,205 @@ async fn io_error_to_response(error: std::io::Error) -> impl IntoResponse {
         format!("Failed to serve static file: {}", error),
     )
 }
+
+/// Adds an `ETag` header to the responses of the inner service,
+/// answers `If-None-Match` and `If-Modified-Since` requests with
+/// `304 Not Modified` and handles `If-Range` requests.
+#[derive(Clone)]
+struct Conditional<S> {
+    inner: S,
+}
+
+type BoxedFuture<R, E> = Pin<Box<dyn Future<Output = Result<R, E>> + Send>>;
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Conditional<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Default + Send + 'static,
+    ResBody: http_body::Body<Data = axum::body::Bytes> + Send + 'static,
+    ResBody::Error: Into<axum::BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxedFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+
+        // evaluated here instead of by the inner service, so the 304 carries the entity tag.
+        // If-None-Match takes precedence over If-Modified-Since, see RFC 9110, section 13.1.3
+        let if_modified_since = req
+            .headers_mut()
+            .remove(header::IF_MODIFIED_SINCE)
+            .filter(|_| if_none_match.is_none());
+
+        // keep what we need to request the full file again, if If-Range does not match
+        let if_range = req.headers().get(header::IF_RANGE).cloned().map(|if_range| {
+            let mut full = Request::new(ReqBody::default());
+            *full.method_mut() = req.method().clone();
+            *full.uri_mut() = req.uri().clone();
+            *full.version_mut() = req.version();
+            *full.headers_mut() = req.headers().clone();
+            full.headers_mut().remove(header::RANGE);
+            full.headers_mut().remove(header::IF_RANGE);
+
+            (if_range, full, self.inner.clone())
+        });
+
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response = response.await?.map(boxed);
+
+            let Some(etag) = entity_tag(&response) else {
+                return Ok(response);
+            };
+
+            if let Some((if_range, full, mut inner)) = if_range {
+                let last_modified = response.headers().get(header::LAST_MODIFIED);
+
+                if response.status() == StatusCode::PARTIAL_CONTENT && !if_range_matches(&if_range, &etag, last_modified) {
+                    poll_fn(|cx| inner.poll_ready(cx)).await?;
+                    response = inner.call(full).await?.map(boxed);
+                }
+            }
+
+            response.headers_mut().insert(header::ETAG, etag.clone());
+
+            if let Some(if_none_match) = if_none_match {
+                if etag_matches(&if_none_match, &etag) {
+                    return Ok(not_modified(response.headers()));
+                }
+            }
+
+            if let Some(if_modified_since) = if_modified_since {
+                if response.status() == StatusCode::OK && !modified_since(&response, &if_modified_since) {
+                    return Ok(not_modified(response.headers()));
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+/// Returns true if the file was modified after the date of If-Modified-Since. Files without a
+/// valid date count as modified.
+fn modified_since(response: &Response<BoxBody>, if_modified_since: &HeaderValue) -> bool {
+    let parse = |value: &HeaderValue| httpdate::parse_http_date(value.to_str().ok()?).ok();
+
+    let last_modified = response.headers().get(header::LAST_MODIFIED).and_then(parse);
+
+    match (last_modified, parse(if_modified_since)) {
+        (Some(last_modified), Some(if_modified_since)) => last_modified > if_modified_since,
+        _ => true,
+    }
+}
+
+/// Derives a strong entity tag from the modification time and the size
+/// of the served file, the same way nginx does it.
+fn entity_tag(response: &Response<BoxBody>) -> Option<HeaderValue> {
+    let headers = response.headers();
+
+    let size: u64 = match response.status() {
+        StatusCode::OK => headers.get(header::CONTENT_LENGTH)?.to_str().ok()?.parse().ok()?,
+
+        // a partial response has the size of the full file in its Content-Range header
+        StatusCode::PARTIAL_CONTENT => {
+            let content_range = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+            content_range.rsplit_once('/')?.1.parse().ok()?
+        }
+
+        _ => return None,
+    };
+
+    let last_modified = headers.get(header::LAST_MODIFIED)?.to_str().ok()?;
+    let last_modified = httpdate::parse_http_date(last_modified).ok()?;
+    let last_modified = last_modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+    HeaderValue::from_str(&format!("\"{:x}-{:x}\"", last_modified, size)).ok()
+}
+
+/// Checks if the value of an `If-None-Match` header matches the given entity tag.
+/// This uses the weak comparison as required by RFC 9110, section 13.1.2
+pub(crate) fn etag_matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+
+    let etag = weak_tag(etag.to_str().unwrap_or_default());
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || weak_tag(candidate) == etag)
+}
+
+/// Checks if the value of an `If-Range` header matches the current representation. This
+/// uses the strong comparison for entity tags and an exact match for dates, see RFC 9110, section 13.1.5
+pub(crate) fn if_range_matches(if_range: &HeaderValue, etag: &HeaderValue, last_modified: Option<&HeaderValue>) -> bool {
+    let Ok(if_range) = if_range.to_str() else {
+        return false;
+    };
+
+    if if_range.starts_with('"') {
+        return if_range == etag.to_str().unwrap_or_default();
+    }
+
+    last_modified.is_some_and(|last_modified| last_modified.as_bytes() == if_range.as_bytes())
+}
+
+/// The part of a file to send in response to a request.
+#[cfg(feature = "embed")]
+pub(crate) enum ByteRange {
+    Full,
+    Partial(RangeInclusive<u64>),
+    Unsatisfiable,
+}
+
+/// Evaluates the `Range` header of a request for a file of the given size. Only single
+/// byte ranges are supported, other ranges are ignored and the full file is sent.
+#[cfg(feature = "embed")]
+pub(crate) fn byte_range(range: Option<&HeaderValue>, size: u64) -> ByteRange {
+    let Some(range) = range.and_then(|range| range.to_str().ok()) else {
+        return ByteRange::Full;
+    };
+
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // suffix range with the last n bytes of the file
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 || size == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+
+            size.saturating_sub(suffix)..=size - 1
+        }
+
+        (Ok(start), _) if end.is_empty() => start..=size.saturating_sub(1),
+        (Ok(start), Ok(end)) if start <= end => start..=end.min(size.saturating_sub(1)),
+        _ => return ByteRange::Full,
+    };
+
+    if *range.start() >= size {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial(range)
+}
+
+fn weak_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
@@ -0,0 +1,388 @@
+use std::convert::Infallible;
+use std::fs::Permissions;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsFd, BorrowedFd, FromRawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use futures_util::future::BoxFuture;
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::server::Builder;
+use hyper::service::make_service_fn;
+use socket2::{Domain, SockAddr, Socket, Type};
+use startup_base::components::Resources;
+use tokio::task::JoinHandle;
+use tower_layer::Layer;
+
+use crate::idle::{IdleAccept, IdleConnection};
+use crate::inflight::InFlight;
+use crate::{reload, shutdown, ErrorFallbackLayer, HttpConfig, TrustedProxies};
+
+/// The first file descriptor passed by systemd, see `sd_listen_fds(3)`.
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Set once the socket passed in by systemd was taken.
+static LISTEN_FDS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// A listener as configured by [HttpConfig].
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl AsFd for Listener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Listener::Tcp(listener) => listener.as_fd(),
+            Listener::Unix(listener) => listener.as_fd(),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Creates the listener configured by this config. A socket passed in by systemd
+    /// takes precedence over a unix socket, which takes precedence over address and port.
+    /// With `reload`, the socket passed in by the previous process takes precedence as well.
+    pub fn listen(&self) -> io::Result<Listener> {
+        if self.socket_activation || self.reload {
+            if let Some(listener) = listen_fds()? {
+                return Ok(listener);
+            }
+
+            if self.socket_activation {
+                warn!("Socket activation is enabled, but no socket was passed in");
+            }
+        }
+
+        if let Some(path) = self.unix_socket.as_ref() {
+            return listen_unix(path, &self.unix_socket_mode, self.backlog);
+        }
+
+        let ip: IpAddr = self
+            .address
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        info!("Listening on {}:{}", ip, self.port);
+        Ok(Listener::Tcp(listen_tcp(SocketAddr::new(ip, self.port), self.backlog, self.reuse_port)?))
+    }
+
+    /// Applies the connection options that are independent of the kind of listener.
+    fn configure<I>(&self, builder: Builder<I>) -> Builder<I> {
+        builder
+            .http1_keepalive(self.keep_alive)
+            .http1_header_read_timeout(Duration::from_secs(self.header_read_timeout_seconds))
+    }
+
+    fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_seconds.map(Duration::from_secs)
+    }
+}
+
+/// Binds the listener configured in `config` and serves the router on it until
+/// the process receives SIGINT or SIGTERM. Open requests and long-lived connections
+/// are given `drain_timeout_seconds` to finish, then the shutdown hooks run,
+/// see [startup_base::on_shutdown], before this function returns.
+///
+/// Unknown routes and unsupported methods are answered in the standard error format,
+/// see [ErrorFallbackLayer].
+///
+/// Requests in flight are counted per route in the `http.server.active_requests` metric. During
+/// shutdown, the requests that finished and those aborted at the end of the drain timeout are
+/// logged and counted per route in `http.server.drain.requests`, and the time it took in
+/// `http.server.drain.duration`, to tune the termination grace period of the service.
+///
+/// With the `http3` feature and [Http3Config](crate::Http3Config), the router is also served
+/// with HTTP/3, which is advertised to clients in the `Alt-Svc` header of the responses.
+///
+/// With `reload`, SIGUSR2 starts a new process of the binary with the same arguments and
+/// passes it the listening socket. Once the new process serves on it, this process shuts down
+/// as if it received SIGTERM and drains its connections, while the new process accepts the new
+/// ones. If the new process fails to start, this process keeps serving. The HTTP/3 endpoint
+/// is not passed on, reloading a server with HTTP/3 fails.
+pub async fn run_server(config: &HttpConfig, router: Router) -> eyre::Result<()> {
+    let guard = startup_base::shutdown::guard();
+
+    let in_flight = InFlight::default();
+
+    let router = router
+        .layer(in_flight.clone())
+        .layer(ErrorFallbackLayer)
+        .layer(TrustedProxies::new(config.trusted_proxies.clone()).into_layer());
+
+    // closes the endpoint when the server returns
+    #[cfg(feature = "http3")]
+    let (router, _http3) = match &config.http3 {
+        Some(http3) => (
+            router.clone().layer(http3.alt_svc(config)),
+            Some(crate::http3::serve(config, http3, router)?),
+        ),
+        None => (router, None),
+    };
+
+    let deadline = shutdown::deadline(Duration::from_secs(config.drain_timeout_seconds));
+    tokio::pin!(deadline);
+
+    let listener = config.listen()?;
+
+    if config.reload {
+        tokio::spawn(reload::run(listener.as_fd().try_clone_to_owned()?));
+    }
+
+    // the listener accepts connections from here on, a previous process can shut down
+    reload::notify_ready();
+
+    match listener {
+        Listener::Tcp(listener) => {
+            listener.set_nonblocking(true)?;
+
+            #[cfg(feature = "tls")]
+            if let Some(tls) = &config.tls {
+                let make_service = make_service_fn(move |connection: &IdleConnection<crate::tls::TlsConnection>| {
+                    let service = connection.service(connection.get_ref().service(router.clone()));
+                    async move { Ok::<_, Infallible>(service) }
+                });
+
+                let accept = IdleAccept::new(crate::tls::accept(config, tls, listener)?, config.idle_timeout());
+
+                let server = config
+                    .configure(axum::Server::builder(accept))
+                    .serve(make_service)
+                    .with_graceful_shutdown(shutdown::signal());
+
+                serve_until(server, &mut deadline, &in_flight).await?;
+                shutdown::drain(deadline).await;
+                guard.release().await;
+
+                return Ok(());
+            }
+
+            let mut incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?;
+            incoming.set_nodelay(config.tcp_nodelay);
+            incoming.set_keepalive(config.tcp_keepalive_seconds.map(Duration::from_secs));
+
+            let make_service = make_service_fn(move |connection: &IdleConnection<AddrStream>| {
+                let remote = connection.get_ref().remote_addr();
+                let service = connection.service(Extension(ConnectInfo(remote)).layer(router.clone()));
+                async move { Ok::<_, Infallible>(service) }
+            });
+
+            let server = config
+                .configure(axum::Server::builder(IdleAccept::new(incoming, config.idle_timeout())))
+                .serve(make_service)
+                .with_graceful_shutdown(shutdown::signal());
+
+            serve_until(server, &mut deadline, &in_flight).await?;
+        }
+
+        Listener::Unix(listener) => {
+            listener.set_nonblocking(true)?;
+
+            let listener = tokio::net::UnixListener::from_std(listener)?;
+
+            let make_service = make_service_fn(move |connection: &IdleConnection<tokio::net::UnixStream>| {
+                let service = connection.service(router.clone());
+                async move { Ok::<_, Infallible>(service) }
+            });
+
+            let server = config
+                .configure(axum::Server::builder(IdleAccept::new(UnixAccept(listener), config.idle_timeout())))
+                .serve(make_service)
+                .with_graceful_shutdown(shutdown::signal());
+
+            serve_until(server, &mut deadline, &in_flight).await?;
+        }
+    }
+
+    shutdown::drain(deadline).await;
+    guard.release().await;
+
+    Ok(())
+}
+
+/// Serves the router as component of [Components](startup_base::components::Components),
+/// see [run_server]. The router is built from the resources of the components the server
+/// depends on. The server runs in the background, wait for it using the [Serving] resource.
+///
+/// Use like this: `components.add("http", &["db"], startup_http::component(&config.http, router))`
+pub fn component<F>(
+    config: &HttpConfig,
+    router: F,
+) -> impl FnOnce(Resources) -> BoxFuture<'static, eyre::Result<()>> + Send + 'static
+where
+    F: FnOnce(&Resources) -> eyre::Result<Router> + Send + 'static,
+{
+    let config = config.clone();
+
+    move |resources| {
+        Box::pin(async move {
+            let router = router(&resources)?;
+            let task = tokio::spawn(async move { run_server(&config, router).await });
+
+            resources.insert(Serving {
+                task: Arc::new(parking_lot::Mutex::new(Some(task))),
+            });
+
+            Ok(())
+        })
+    }
+}
+
+/// The server started by [component].
+#[derive(Clone)]
+pub struct Serving {
+    task: Arc<parking_lot::Mutex<Option<JoinHandle<eyre::Result<()>>>>>,
+}
+
+impl Serving {
+    /// Waits until the server stopped, see [run_server]. Returns immediately if someone else
+    /// is already waiting.
+    pub async fn wait(&self) -> eyre::Result<()> {
+        let task = self.task.lock().take();
+
+        match task {
+            Some(task) => task.await?,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs the server until all open requests are finished or the deadline is reached.
+async fn serve_until(
+    server: impl Future<Output = hyper::Result<()>>,
+    deadline: impl Future<Output = ()>,
+    in_flight: &InFlight,
+) -> eyre::Result<()> {
+    // the open requests are dropped with the server, after the drain was recorded
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result?;
+            in_flight.finish(false);
+        },
+
+        _ = deadline => in_flight.finish(true),
+    }
+
+    Ok(())
+}
+
+fn listen_tcp(addr: SocketAddr, backlog: i32, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+
+    // same as the standard library, allows restarting while old connections are in TIME_WAIT
+    socket.set_reuse_address(true)?;
+
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+
+    Ok(socket.into())
+}
+
+fn listen_unix(path: &Path, mode: &str, backlog: i32) -> io::Result<Listener> {
+    let mode = u32::from_str_radix(mode, 8).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    // remove a stale socket left behind by a previous instance
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    info!("Listening on unix socket {:?}", path);
+
+    // bind in a directory only we can access and move the socket into place once it has its
+    // permissions, so nobody can connect while the socket still has the default permissions
+    let file_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    let private_dir = path.with_file_name(format!(".{}.{}", file_name.to_string_lossy(), std::process::id()));
+
+    std::fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+
+    let result = bind_unix(&private_dir.join("socket"), path, mode, backlog);
+
+    // the directory is empty once the socket was moved
+    let _ = std::fs::remove_dir_all(&private_dir);
+
+    Ok(Listener::Unix(result?.into()))
+}
+
+fn bind_unix(private_path: &Path, path: &Path, mode: u32, backlog: i32) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    socket.bind(&SockAddr::unix(private_path)?)?;
+
+    std::fs::set_permissions(private_path, Permissions::from_mode(mode))?;
+    std::fs::rename(private_path, path)?;
+
+    socket.listen(backlog)?;
+
+    Ok(socket)
+}
+
+/// Takes the socket passed in by systemd using the `LISTEN_FDS` protocol, once per process.
+///
+/// The variables are left in the environment, changing it while other threads might read it is
+/// unsound. Child processes ignore them, as `LISTEN_PID` names this process.
+fn listen_fds() -> io::Result<Option<Listener>> {
+    let Ok(fds) = std::env::var("LISTEN_FDS") else {
+        return Ok(None);
+    };
+
+    // the sockets are meant for a different process
+    if let Ok(pid) = std::env::var("LISTEN_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return Ok(None);
+        }
+    }
+
+    // the socket has a single owner, a second listener gets none
+    if LISTEN_FDS_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    if fds != "1" {
+        let message = format!("expected exactly one socket from systemd, got LISTEN_FDS={}", fds);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    // Safety: systemd passes the socket as the first file descriptor after stdio.
+    let socket = unsafe { Socket::from_raw_fd(SD_LISTEN_FDS_START) };
+
+    let listener = if socket.local_addr()?.is_unix() {
+        info!("Listening on unix socket passed in by systemd");
+        Listener::Unix(socket.into())
+    } else {
+        info!("Listening on socket passed in by systemd");
+        Listener::Tcp(socket.into())
+    };
+
+    Ok(Some(listener))
+}
+
+struct UnixAccept(tokio::net::UnixListener);
+
+impl Accept for UnixAccept {
+    type Conn = tokio::net::UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let (stream, _) = futures_util::ready!(self.0.poll_accept(cx))?;
+        Poll::Ready(Some(Ok(stream)))
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::WebError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionConfig {
+    /// Name of the cookie with the session id.
+    #[serde(default = "default_cookie")]
+    pub cookie: String,
+
+    /// Sessions expire after this time without a request.
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: u64,
+
+    #[serde(default)]
+    pub same_site: SameSite,
+
+    /// Only send the cookie over https. Disable this for local development over plain http.
+    #[serde(default = "default_secure")]
+    pub secure: bool,
+
+    /// Domain of the cookie, e.g. `example.com` to share the session with its subdomains.
+    /// Defaults to the host of the request.
+    #[serde(default)]
+    pub domain: Option<String>,
+
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+/// The `SameSite` attribute of the session cookie, see
+/// <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie#samesitesamesite-value>.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    /// Only send the cookie with requests from the site itself.
+    Strict,
+
+    /// Also send the cookie when navigating to the site from another site.
+    #[default]
+    Lax,
+
+    /// Send the cookie with all requests, requires `secure`.
+    None,
+}
+
+fn default_cookie() -> String {
+    "session".to_string()
+}
+
+fn default_ttl_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_secure() -> bool {
+    true
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cookie: default_cookie(),
+            ttl_seconds: default_ttl_seconds(),
+            same_site: SameSite::default(),
+            secure: default_secure(),
+            domain: None,
+            path: default_path(),
+        }
+    }
+}
+
+/// The values of a session by their key.
+pub type SessionData = BTreeMap<String, Value>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("session store failed")]
+    Store(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Keeps the data of the sessions, shared by all replicas of a service. Implemented
+/// by [MemorySessionStore], by the `RedisSessionStore` of `startup-redis` and by the
+/// `PostgresSessionStore` of `startup-db`.
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, SessionError>;
+
+    /// Stores the data, the session expires after the ttl.
+    async fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), SessionError>;
+
+    /// Extends the session that was used without changing its data.
+    async fn touch(&self, id: &str, ttl: Duration) -> Result<(), SessionError>;
+
+    async fn delete(&self, id: &str) -> Result<(), SessionError>;
+}
+
+/// Keeps the sessions in memory, e.g. for tests or a service with a single replica.
+/// Expired sessions are removed whenever a session is saved.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, (Instant, SessionData)>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, SessionError> {
+        let sessions = self.sessions.lock();
+
+        match sessions.get(id) {
+            Some((expires, data)) if *expires > Instant::now() => Ok(Some(data.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    async fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), SessionError> {
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|_, (expires, _)| *expires > now);
+        sessions.insert(id.to_string(), (now + ttl, data.clone()));
+
+        Ok(())
+    }
+
+    async fn touch(&self, id: &str, ttl: Duration) -> Result<(), SessionError> {
+        if let Some((expires, _)) = self.sessions.lock().get_mut(id) {
+            *expires = Instant::now() + ttl;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), SessionError> {
+        self.sessions.lock().remove(id);
+        Ok(())
+    }
+}
+
+/// The session of the browser sending the request, loaded by the [SessionLayer]. Changes
+/// are saved once the handler returned. A new session is only stored once a value is inserted.
+///
+/// Call [Session::rotate] after a login or any other change of privileges, so a session id
+/// that was planted in the browser before (session fixation) is worthless.
+///
+/// Use like this: `async fn cart(session: Session) { let items: Vec<Item> = session.get("cart").unwrap_or_default(); }`
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    /// Id of the stored session, None for a new session.
+    id: Option<String>,
+    data: SessionData,
+    changed: bool,
+    rotate: bool,
+    destroy: bool,
+}
+
+impl Session {
+    fn new(id: Option<String>, data: SessionData) -> Self {
+        let state = State {
+            id,
+            data,
+            changed: false,
+            rotate: false,
+            destroy: false,
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Returns the value, or None if there is no value or it can not be deserialized.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let state = self.state.lock();
+        serde_json::from_value(state.data.get(key)?.clone()).ok()
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) -> Result<(), serde_json::Error> {
+        let value = serde_json::to_value(value)?;
+
+        let mut state = self.state.lock();
+        state.data.insert(key.to_string(), value);
+        state.changed = true;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.state.lock();
+
+        if state.data.remove(key).is_some() {
+            state.changed = true;
+        }
+    }
+
+    /// Moves the session to a new id, keeping its data. The old id is deleted.
+    pub fn rotate(&self) {
+        self.state.lock().rotate = true;
+    }
+
+    /// Deletes the session and its cookie, e.g. on logout.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock();
+        state.data.clear();
+        state.destroy = true;
+    }
+
+    /// True if the session was stored before the request.
+    pub fn is_stored(&self) -> bool {
+        self.state.lock().id.is_some()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Session
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Session>().cloned().ok_or_else(|| {
+            WebError::Response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "session requires the SessionLayer".to_string(),
+            )
+        })
+    }
+}
+
+/// Loads the [Session] of the request from the store, using the id in the session cookie,
+/// and saves it after the response was created. Requests with an unknown or expired id get
+/// a new session, so a client can not pick its own id. If the session can not be loaded, requests
+/// are answered with `503 Service Unavailable`. If it can not be saved, the failure is logged and
+/// the response of the handler is sent without updating the cookie.
+///
+/// Sessions expire after `ttl_seconds` without a request, every request extends the session.
+/// The cookie is `HttpOnly`. With [SessionLayer::with_encryption], the id in the cookie is encrypted.
+///
+/// Use like this: `router.layer(SessionLayer::new(&config.session, RedisSessionStore::new(&pool)))`
+#[derive(Clone)]
+pub struct SessionLayer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: SessionConfig,
+    store: Arc<dyn SessionStore>,
+
+    #[cfg(feature = "encryption")]
+    encryption: Option<startup_base::encryption::Encryption>,
+}
+
+impl SessionLayer {
+    pub fn new(config: &SessionConfig, store: impl SessionStore) -> Self {
+        if config.same_site == SameSite::None && !config.secure {
+            warn!("Browsers ignore session cookies with SameSite=None that are not secure");
+        }
+
+        let inner = Inner {
+            config: config.clone(),
+            store: Arc::new(store),
+
+            #[cfg(feature = "encryption")]
+            encryption: None,
+        };
+
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Encrypts the session id in the cookie, so a cookie that was not issued by the service
+    /// is rejected without asking the store. Every response sets the cookie encrypted with the
+    /// current key, so a key can be removed once `ttl_seconds` passed after the rotation.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, encryption: startup_base::encryption::Encryption) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("configure the layer before cloning it")
+            .encryption = Some(encryption);
+
+        self
+    }
+}
+
+impl<S> tower_layer::Layer<S> for SessionLayer {
+    type Service = SessionService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionService {
+            inner,
+            sessions: self.inner.clone(),
+        }
+    }
+}
+
+/// Middleware created by [SessionLayer].
+#[derive(Clone)]
+pub struct SessionService<S> {
+    inner: S,
+    sessions: Arc<Inner>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for SessionService<S>
+where
+    S: tower_service::Service<Request<B>> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // the inner service was polled ready, keep it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let sessions = self.sessions.clone();
+
+        Box::pin(async move {
+            let session = match sessions.load(req.headers()).await {
+                Ok(session) => session,
+                Err(err) => {
+                    warn!("Failed to load session: {:?}", err);
+                    return Ok(unavailable());
+                }
+            };
+
+            req.extensions_mut().insert(session.clone());
+
+            let mut response = inner.call(req).await?.into_response();
+
+            match sessions.save(&session).await {
+                Ok(Some(cookie)) => {
+                    response.headers_mut().append(header::SET_COOKIE, cookie);
+                }
+
+                Ok(None) => {}
+
+                // the handler already ran, so its response is sent anyway
+                Err(err) => warn!("Failed to save session: {:?}", err),
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+fn new_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+fn unavailable() -> Response {
+    WebError::Response(StatusCode::SERVICE_UNAVAILABLE, "session store unavailable".to_string()).into_response()
+}
+
+impl Inner {
+    async fn load(&self, headers: &HeaderMap) -> Result<Session, SessionError> {
+        let id = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == self.config.cookie)
+            .and_then(|(_, value)| self.decode_id(value));
+
+        if let Some(id) = id {
+            if let Some(data) = self.store.load(&id).await? {
+                return Ok(Session::new(Some(id), data));
+            }
+        }
+
+        Ok(Session::new(None, SessionData::new()))
+    }
+
+    /// Saves the changes of the session and returns the cookie to set, if any.
+    async fn save(&self, session: &Session) -> Result<Option<HeaderValue>, SessionError> {
+        let (id, data, changed, rotate, destroy) = {
+            let state = session.state.lock();
+            (
+                state.id.clone(),
+                state.data.clone(),
+                state.changed,
+                state.rotate,
+                state.destroy,
+            )
+        };
+
+        let ttl = Duration::from_secs(self.config.ttl_seconds);
+
+        if destroy {
+            let Some(id) = id else {
+                return Ok(None);
+            };
+
+            self.store.delete(&id).await?;
+            return Ok(self.cookie("", 0));
+        }
+
+        let id = match id {
+            Some(id) if rotate => {
+                // store the data under the new id first, so the session is not lost if saving fails
+                let rotated = new_id();
+                self.store.save(&rotated, &data, ttl).await?;
+
+                if let Err(err) = self.store.delete(&id).await {
+                    warn!("Failed to delete the rotated session: {:?}", err);
+                }
+
+                return Ok(self.cookie(&rotated, self.config.ttl_seconds));
+            }
+
+            Some(id) if !changed => {
+                self.store.touch(&id, ttl).await?;
+                return Ok(self.cookie(&id, self.config.ttl_seconds));
+            }
+
+            id => id,
+        };
+
+        // a new session is only stored with data
+        if !changed {
+            return Ok(None);
+        }
+
+        let id = id.unwrap_or_else(new_id);
+        self.store.save(&id, &data, ttl).await?;
+
+        Ok(self.cookie(&id, self.config.ttl_seconds))
+    }
+
+    /// The session id in the value of the cookie, None if it can not be decrypted.
+    fn decode_id(&self, value: &str) -> Option<String> {
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            let id = encryption.decrypt_str(value, self.config.cookie.as_bytes()).ok()?;
+            return String::from_utf8(id).ok();
+        }
+
+        Some(value.to_string())
+    }
+
+    fn encode_id(&self, id: &str) -> String {
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            return encryption.encrypt_to_string(id.as_bytes(), self.config.cookie.as_bytes());
+        }
+
+        id.to_string()
+    }
+
+    fn cookie(&self, id: &str, max_age: u64) -> Option<HeaderValue> {
+        let config = &self.config;
+
+        // the cookie of a destroyed session is removed with an empty value
+        let value = match id {
+            "" => String::new(),
+            id => self.encode_id(id),
+        };
+
+        let same_site = match config.same_site {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        };
+
+        let mut cookie = format!(
+            "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite={}",
+            config.cookie, value, config.path, max_age, same_site
+        );
+
+        if let Some(domain) = &config.domain {
+            cookie.push_str("; Domain=");
+            cookie.push_str(domain);
+        }
+
+        if config.secure {
+            cookie.push_str("; Secure");
+        }
+
+        HeaderValue::try_from(cookie).ok()
+    }
+}
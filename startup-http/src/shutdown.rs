@@ -0,0 +1,68 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::task_tracker::TrackedFuture;
+use tokio_util::task::TaskTracker;
+
+lazy_static::lazy_static! {
+    static ref SHUTDOWN: CancellationToken = CancellationToken::new();
+    static ref CONNECTIONS: TaskTracker = TaskTracker::new();
+    static ref REQUESTED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+/// Resolves once the server begins to shut down. Long-lived connections
+/// like websockets should wait for this and close themselves.
+pub async fn shutdown_requested() {
+    SHUTDOWN.cancelled().await
+}
+
+/// Returns true if the server is shutting down.
+pub fn is_shutting_down() -> bool {
+    SHUTDOWN.is_cancelled()
+}
+
+/// Tracks a long-lived connection that is not handled by the http server itself,
+/// so that the shutdown waits for it to finish.
+pub fn track_connection<F: Future>(connection: F) -> TrackedFuture<F> {
+    CONNECTIONS.track_future(connection)
+}
+
+/// Waits for SIGINT or SIGTERM and notifies all long-lived connections
+/// and streaming responses about the shutdown.
+pub(crate) async fn signal() {
+    startup_base::shutdown::signal().await;
+
+    info!("Received signal, shutting down");
+
+    REQUESTED_AT.lock().get_or_insert_with(Instant::now);
+    SHUTDOWN.cancel();
+}
+
+/// Time the server began to shut down.
+pub(crate) fn requested_at() -> Option<Instant> {
+    *REQUESTED_AT.lock()
+}
+
+/// Resolves `timeout` after the server began to shut down.
+pub(crate) async fn deadline(timeout: Duration) {
+    SHUTDOWN.cancelled().await;
+    tokio::time::sleep(timeout).await;
+}
+
+/// Waits until the long-lived connections are closed or the deadline is reached.
+pub(crate) async fn drain(deadline: impl Future<Output = ()>) {
+    CONNECTIONS.close();
+
+    if CONNECTIONS.is_empty() {
+        return;
+    }
+
+    info!("Waiting for {} connections to close", CONNECTIONS.len());
+
+    tokio::select! {
+        _ = CONNECTIONS.wait() => {},
+        _ = deadline => warn!("{} connections still open at the end of the drain timeout", CONNECTIONS.len()),
+    }
+}
@@ -0,0 +1,393 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::body::Body;
+use axum::extract::OriginalUri;
+use axum::http::header::{CONTENT_TYPE, HOST};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use startup_base::signature::{hmac, verify_hmac, SignatureAlgorithm};
+
+use crate::{Principal, RequestContext, WebError};
+
+/// Header with the id of the key that signed the request.
+pub const KEY_HEADER: &str = "x-signature-key";
+
+/// Header with the unix time the request was signed at.
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+
+/// Header with the hex encoded HMAC-SHA256 of the canonical request.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Kind of the [Principal] of a request verified by the [SignatureLayer].
+pub const PRINCIPAL_KIND: &str = "signature";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    /// Identifies the key, and the calling service, in the requests it signs.
+    pub id: String,
+
+    pub secret: String,
+}
+
+/// Keys shared between services to sign and verify their requests, see [RequestSigner].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestSigningConfig {
+    /// Requests signed with any of the keys are accepted. To rotate a key, add the new key to the
+    /// receiving service, switch the calling service to it with `signing_key`, then remove the old key.
+    pub keys: Vec<SigningKey>,
+
+    /// Id of the key that signs outgoing requests. Defaults to the last key.
+    #[serde(default)]
+    pub signing_key: Option<String>,
+
+    /// Maximum difference between the timestamp of a request and now.
+    #[serde(default = "default_tolerance_seconds")]
+    pub tolerance_seconds: u64,
+
+    /// Requests with a larger body are rejected, the body is read to verify its hash.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+}
+
+fn default_tolerance_seconds() -> u64 {
+    300
+}
+
+fn default_max_body_size() -> usize {
+    10 * 1024 * 1024
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SigningError {
+    #[error("no signing key configured")]
+    NoKeys,
+
+    #[error("signing key {0:?} is not configured")]
+    UnknownKey(String),
+
+    #[error("signing key {0:?} is configured twice")]
+    DuplicateKey(String),
+
+    #[error("requests with a streaming body can not be signed")]
+    StreamingBody,
+}
+
+/// Signs requests between internal services with a shared key, as a lighter alternative to
+/// OAuth2 tokens that needs no token endpoint and adds no round trip.
+///
+/// The signature is the HMAC-SHA256 of the canonical request, the lines
+///
+/// ```text
+/// {method}
+/// {host, lowercase and with the port unless it is the default port}
+/// {path}
+/// {query, its parameters sorted}
+/// {content type, empty without a body}
+/// {timestamp}
+/// {key id}
+/// {hex encoded SHA-256 of the body}
+/// ```
+///
+/// sent in the headers [KEY_HEADER], [TIMESTAMP_HEADER] and [SIGNATURE_HEADER]. A signed request
+/// can be replayed within the tolerance, so only sign requests to services reached over a
+/// trusted network or tls.
+///
+/// Requests are signed by the `startup-client` if configured, and verified by the [SignatureLayer].
+#[derive(Clone)]
+pub struct RequestSigner {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    keys: Vec<SigningKey>,
+    signing_key: usize,
+    tolerance_seconds: u64,
+    max_body_size: usize,
+}
+
+impl RequestSigner {
+    pub fn new(config: &RequestSigningConfig) -> Result<Self, SigningError> {
+        for (idx, key) in config.keys.iter().enumerate() {
+            if config.keys[..idx].iter().any(|other| other.id == key.id) {
+                return Err(SigningError::DuplicateKey(key.id.clone()));
+            }
+        }
+
+        let signing_key = match &config.signing_key {
+            Some(id) => config
+                .keys
+                .iter()
+                .position(|key| key.id == *id)
+                .ok_or_else(|| SigningError::UnknownKey(id.clone()))?,
+
+            None => config.keys.len().checked_sub(1).ok_or(SigningError::NoKeys)?,
+        };
+
+        let inner = Inner {
+            keys: config.keys.clone(),
+            signing_key,
+            tolerance_seconds: config.tolerance_seconds,
+            max_body_size: config.max_body_size,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Adds the signature headers for the request to its headers. `host` is the host of the url with
+    /// the port unless it is the default port, like the `Host` header, and `query` is its raw query.
+    pub fn sign(
+        &self,
+        method: &Method,
+        host: &str,
+        path: &str,
+        query: Option<&str>,
+        body: &[u8],
+        headers: &mut HeaderMap,
+    ) {
+        let key = &self.inner.keys[self.inner.signing_key];
+        let timestamp = now().to_string();
+
+        let request = CanonicalRequest {
+            method,
+            host,
+            path,
+            query,
+            content_type: content_type(headers),
+            body,
+        };
+
+        let canonical = request.canonical(&timestamp, &key.id);
+        let message = [canonical.as_bytes()];
+        let signature = hex::encode(hmac(SignatureAlgorithm::Sha256, key.secret.as_bytes(), &message));
+
+        for (name, value) in [(KEY_HEADER, &key.id), (TIMESTAMP_HEADER, &timestamp)] {
+            if let Ok(value) = HeaderValue::try_from(value.as_str()) {
+                headers.insert(name, value);
+            }
+        }
+
+        if let Ok(mut value) = HeaderValue::try_from(signature) {
+            value.set_sensitive(true);
+            headers.insert(SIGNATURE_HEADER, value);
+        }
+    }
+
+    /// Verifies the signature of the request and returns the id of the key that signed it.
+    /// `host` is the value of the `Host` header, or the authority of the uri for http/2.
+    pub fn verify(
+        &self,
+        method: &Method,
+        host: &str,
+        path: &str,
+        query: Option<&str>,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<&str, WebError> {
+        let key_id = header(headers, KEY_HEADER)?;
+        let timestamp = header(headers, TIMESTAMP_HEADER)?;
+
+        let signature =
+            hex::decode(header(headers, SIGNATURE_HEADER)?).map_err(|_| unauthorized("Invalid request signature"))?;
+
+        let signed_at: u64 = timestamp
+            .parse()
+            .map_err(|_| unauthorized("Invalid signature timestamp"))?;
+
+        if now().abs_diff(signed_at) > self.inner.tolerance_seconds {
+            return Err(unauthorized("Signature timestamp is outside of the tolerance"));
+        }
+
+        let key = self
+            .inner
+            .keys
+            .iter()
+            .find(|key| key.id == key_id)
+            .ok_or_else(|| unauthorized("Unknown signature key"))?;
+
+        let request = CanonicalRequest {
+            method,
+            host,
+            path,
+            query,
+            content_type: content_type(headers),
+            body,
+        };
+
+        let canonical = request.canonical(timestamp, key_id);
+        let message = [canonical.as_bytes()];
+
+        // compares in constant time, so the signature can not be guessed byte by byte
+        if !verify_hmac(SignatureAlgorithm::Sha256, key.secret.as_bytes(), &message, &signature) {
+            return Err(unauthorized("Invalid request signature"));
+        }
+
+        Ok(&key.id)
+    }
+}
+
+/// The parts of a request that are signed.
+struct CanonicalRequest<'a> {
+    method: &'a Method,
+    host: &'a str,
+    path: &'a str,
+    query: Option<&'a str>,
+    content_type: &'a str,
+    body: &'a [u8],
+}
+
+impl CanonicalRequest<'_> {
+    fn canonical(&self, timestamp: &str, key_id: &str) -> String {
+        let mut parameters: Vec<&str> = self
+            .query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|parameter| !parameter.is_empty())
+            .collect();
+
+        parameters.sort_unstable();
+
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            self.method,
+            self.host.to_ascii_lowercase(),
+            self.path,
+            parameters.join("&"),
+            self.content_type,
+            timestamp,
+            key_id,
+            hex::encode(Sha256::digest(self.body))
+        )
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, WebError> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| unauthorized(&format!("Missing signature header {:?}", name)))
+}
+
+fn unauthorized(message: &str) -> WebError {
+    WebError::Response(StatusCode::UNAUTHORIZED, message.to_string())
+}
+
+/// Rejects requests without a valid signature of the [RequestSigner] with `401 Unauthorized`.
+/// The id of the key becomes the subject of the [Principal] of the [RequestContext], so give every
+/// calling service its own key. The body is read to verify its hash and passed on to the handler.
+///
+/// Use like this: `internal_routes.layer(SignatureLayer::new(RequestSigner::new(&config.signing)?))`
+#[derive(Clone)]
+pub struct SignatureLayer {
+    signer: RequestSigner,
+}
+
+impl SignatureLayer {
+    pub fn new(signer: RequestSigner) -> Self {
+        Self { signer }
+    }
+}
+
+impl<S> tower_layer::Layer<S> for SignatureLayer {
+    type Service = SignatureService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SignatureService {
+            inner,
+            signer: self.signer.clone(),
+        }
+    }
+}
+
+/// Middleware created by [SignatureLayer].
+#[derive(Clone)]
+pub struct SignatureService<S> {
+    inner: S,
+    signer: RequestSigner,
+}
+
+impl<S> tower_service::Service<Request<Body>> for SignatureService<S>
+where
+    S: tower_service::Service<Request<Body>> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // the inner service was polled ready, keep it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let signer = self.signer.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+
+            let max_body_size = signer.inner.max_body_size;
+            let body = match hyper::body::to_bytes(http_body::Limited::new(body, max_body_size)).await {
+                Ok(body) => body,
+                Err(err) if err.is::<http_body::LengthLimitError>() => {
+                    let message = format!("signed body is larger than {} bytes", max_body_size);
+                    return Ok(WebError::Response(StatusCode::PAYLOAD_TOO_LARGE, message).into_response());
+                }
+                Err(err) => {
+                    let message = format!("Failed to read the request body: {}", err);
+                    return Ok(WebError::Response(StatusCode::BAD_REQUEST, message).into_response());
+                }
+            };
+
+            // the uri of a nested router lacks the prefix of the route, but the signature covers it
+            let uri = match parts.extensions.get::<OriginalUri>() {
+                Some(OriginalUri(uri)) => uri.clone(),
+                None => parts.uri.clone(),
+            };
+
+            let host = match parts.headers.get(HOST) {
+                Some(host) => host.to_str().unwrap_or_default(),
+                None => uri.authority().map(|authority| authority.as_str()).unwrap_or_default(),
+            };
+
+            let verified = signer.verify(&parts.method, host, uri.path(), uri.query(), &parts.headers, &body);
+
+            let key_id = match verified {
+                Ok(key_id) => key_id,
+                Err(err) => {
+                    debug!("Rejected {} {} without a valid signature", parts.method, uri.path());
+                    return Ok(err.into_response());
+                }
+            };
+
+            let principal = Principal::new(PRINCIPAL_KIND, Some(key_id), json!({ "key_id": key_id }));
+            RequestContext::of(&mut parts).set_principal(principal);
+
+            let req = Request::from_parts(parts, Body::from(body));
+            inner.call(req).await.map(IntoResponse::into_response)
+        })
+    }
+}
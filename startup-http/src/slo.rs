@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::http::StatusCode;
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ConfigError;
+
+/// Windows of the burn rate gauge, those of the multi-window alerts in the Google SRE workbook.
+const WINDOWS: [(&str, u64); 7] = [
+    ("5m", 5),
+    ("30m", 30),
+    ("1h", 60),
+    ("2h", 2 * 60),
+    ("6h", 6 * 60),
+    ("1d", 24 * 60),
+    ("3d", 3 * 24 * 60),
+];
+
+/// A service level objective of a group of routes, tracked by the metrics layer of
+/// [standard_layers](crate::standard_layers).
+///
+/// A request is good if it was answered without a server error and, with `latency_ms`,
+/// within that time. Requests are counted in `http.server.slo.requests`, labeled with the
+/// slo and whether they were `good`.
+///
+/// The burn rate, the ratio of bad requests divided by the ratio the objective allows, is
+/// reported in the `http.server.slo.burn_rate` gauge, labeled with the slo and the window:
+/// `5m`, `30m`, `1h`, `2h`, `6h`, `1d` and `3d`. Alert if both windows of a pair exceed the threshold
+/// of the pair, like 14.4 for `1h` and `5m`. The gauge covers the requests of the instance since
+/// it started, take the maximum over all instances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloConfig {
+    pub name: String,
+
+    /// Route templates as in the router, like `/orders/:id`. A template ending
+    /// in `*` matches all routes that start with it.
+    pub routes: Vec<String>,
+
+    /// Ratio of good requests, like `0.999`.
+    pub objective: f64,
+
+    /// Requests that take longer until the response headers are sent are bad.
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+}
+
+impl SloConfig {
+    fn matches(&self, route: &str) -> bool {
+        self.routes.iter().any(|template| match template.strip_suffix('*') {
+            Some(prefix) => route.starts_with(prefix),
+            None => route == template,
+        })
+    }
+}
+
+/// Records the requests of the slos, see [SloConfig].
+pub(crate) struct SloTracker {
+    slos: Arc<Vec<Slo>>,
+    requests: Counter<u64>,
+    started: Instant,
+}
+
+impl fmt::Debug for SloTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let configs: Vec<&SloConfig> = self.slos.iter().map(|slo| &slo.config).collect();
+        f.debug_struct("SloTracker").field("slos", &configs).finish()
+    }
+}
+
+struct Slo {
+    config: SloConfig,
+    latency: Option<Duration>,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+/// Requests within a minute since the tracker was created.
+struct Bucket {
+    minute: u64,
+    total: u64,
+    bad: u64,
+}
+
+impl SloTracker {
+    pub(crate) fn new(configs: &[SloConfig]) -> Result<Self, ConfigError> {
+        let slos = configs
+            .iter()
+            .map(|config| {
+                if !(config.objective > 0.0 && config.objective < 1.0) {
+                    return Err(ConfigError::Invalid {
+                        field: "layers.slos.objective",
+                        message: format!(
+                            "objective of slo {:?} must be between 0 and 1, got {}",
+                            config.name, config.objective
+                        ),
+                    });
+                }
+
+                Ok(Slo {
+                    config: config.clone(),
+                    latency: config.latency_ms.map(Duration::from_millis),
+                    buckets: Mutex::new(VecDeque::new()),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let slos = Arc::new(slos);
+        let started = Instant::now();
+
+        let meter = opentelemetry::global::meter("startup-http");
+
+        let requests = meter
+            .u64_counter("http.server.slo.requests")
+            .with_description("Requests of the routes of a service level objective")
+            .init();
+
+        let gauge = meter
+            .f64_observable_gauge("http.server.slo.burn_rate")
+            .with_description("Ratio of bad requests divided by the ratio allowed by the objective")
+            .init();
+
+        let observed = slos.clone();
+
+        let registered = meter.register_callback(move |cx| {
+            let now = minute(started);
+
+            for slo in observed.iter() {
+                for (window, minutes) in WINDOWS {
+                    let attributes = [
+                        KeyValue::new("slo", slo.config.name.clone()),
+                        KeyValue::new("window", window),
+                    ];
+
+                    gauge.observe(cx, slo.burn_rate(now, minutes), &attributes);
+                }
+            }
+        });
+
+        if let Err(err) = registered {
+            warn!("Failed to register slo burn rate metric: {}", err);
+        }
+
+        Ok(Self {
+            slos,
+            requests,
+            started,
+        })
+    }
+
+    /// Records a request to the route with the status and the time until the response headers were sent.
+    pub(crate) fn record(&self, route: &str, status: StatusCode, elapsed: Duration) {
+        let now = minute(self.started);
+
+        for slo in self.slos.iter().filter(|slo| slo.config.matches(route)) {
+            let good = !status.is_server_error() && slo.latency.is_none_or(|latency| elapsed <= latency);
+
+            slo.add(now, good);
+
+            let attributes = [
+                KeyValue::new("slo", slo.config.name.clone()),
+                KeyValue::new("good", good),
+            ];
+
+            self.requests.add(&opentelemetry::Context::current(), 1, &attributes);
+        }
+    }
+}
+
+impl Slo {
+    fn add(&self, now: u64, good: bool) {
+        let mut buckets = self.buckets.lock();
+
+        if buckets.back().is_none_or(|bucket| bucket.minute != now) {
+            buckets.push_back(Bucket {
+                minute: now,
+                total: 0,
+                bad: 0,
+            });
+        }
+
+        // only the longest window is kept
+        let (_, longest) = WINDOWS[WINDOWS.len() - 1];
+        while buckets.front().is_some_and(|bucket| bucket.minute + longest <= now) {
+            buckets.pop_front();
+        }
+
+        let bucket = buckets.back_mut().expect("bucket was added");
+        bucket.total += 1;
+        bucket.bad += u64::from(!good);
+    }
+
+    /// The burn rate of the last minutes, including the current one.
+    fn burn_rate(&self, now: u64, minutes: u64) -> f64 {
+        let (total, bad) = self
+            .buckets
+            .lock()
+            .iter()
+            .rev()
+            .take_while(|bucket| bucket.minute + minutes > now)
+            .fold((0, 0), |(total, bad), bucket| (total + bucket.total, bad + bucket.bad));
+
+        if total == 0 {
+            return 0.0;
+        }
+
+        (bad as f64 / total as f64) / (1.0 - self.config.objective)
+    }
+}
+
+fn minute(started: Instant) -> u64 {
+    started.elapsed().as_secs() / 60
+}
@@ -0,0 +1,115 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::Stream;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::KeyValue;
+use serde::Serialize;
+use tracing::Span;
+
+/// Streams the given events to the client as server-sent events, serialized as json.
+/// A keep-alive comment is sent every 15 seconds while no event is available.
+///
+/// The request span stays open until the stream ends or the client disconnects,
+/// and records the number of events sent as `sse.events`. The stream ends
+/// when the server shuts down.
+///
+/// Use like this: `async fn handler() -> impl IntoResponse { sse(events) }`
+pub fn sse<S, T>(events: S) -> Sse<EventStream<S>>
+where
+    S: Stream<Item = T> + Send + 'static,
+    T: Serialize,
+{
+    sse_with_keep_alive(events, Duration::from_secs(15))
+}
+
+/// Same as [sse], but sends keep-alive comments using the given interval.
+pub fn sse_with_keep_alive<S, T>(events: S, keep_alive: Duration) -> Sse<EventStream<S>>
+where
+    S: Stream<Item = T> + Send + 'static,
+    T: Serialize,
+{
+    let stream = EventStream {
+        inner: events,
+        shutdown: Box::pin(crate::shutdown_requested()),
+        count: 0,
+        finished: false,
+        span: Span::current(),
+        otel_context: opentelemetry::Context::current(),
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(keep_alive))
+}
+
+/// Stream of server-sent events returned by [sse].
+#[pin_project::pin_project(PinnedDrop)]
+pub struct EventStream<S> {
+    #[pin]
+    inner: S,
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send>>,
+    count: u64,
+    finished: bool,
+    span: Span,
+    otel_context: opentelemetry::Context,
+}
+
+impl<S, T> Stream for EventStream<S>
+where
+    S: Stream<Item = T>,
+    T: Serialize,
+{
+    type Item = Result<Event, Infallible>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        if this.shutdown.as_mut().poll(cx).is_ready() {
+            *this.finished = true;
+            return Poll::Ready(None);
+        }
+
+        loop {
+            let Some(value) = futures_util::ready!(this.inner.as_mut().poll_next(cx)) else {
+                *this.finished = true;
+                return Poll::Ready(None);
+            };
+
+            match Event::default().json_data(value) {
+                Ok(event) => {
+                    *this.count += 1;
+
+                    let otel_span = this.otel_context.span();
+                    otel_span.set_attribute(KeyValue::new("sse.events", *this.count as i64));
+
+                    return Poll::Ready(Some(Ok(event)));
+                }
+
+                Err(err) => {
+                    let _entered = this.span.enter();
+                    warn!("Skipping server-sent event that failed to serialize: {}", err);
+                }
+            }
+        }
+    }
+}
+
+#[pin_project::pinned_drop]
+impl<S> PinnedDrop for EventStream<S> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+
+        let _entered = this.span.enter();
+
+        if *this.finished {
+            debug!("Event stream finished after {} events", this.count);
+        } else {
+            // the request span ends once the last reference to it is dropped
+            this.otel_context.span().add_event("sse.disconnected", Vec::new());
+            debug!("Client disconnected from event stream after {} events", this.count);
+        }
+    }
+}
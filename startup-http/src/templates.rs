@@ -0,0 +1,149 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use minijinja::{path_loader, Environment};
+use serde::{Deserialize, Serialize};
+
+use crate::WebError;
+
+#[doc(hidden)]
+pub use minijinja;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateConfig {
+    /// Directory to load the templates from.
+    #[serde(default = "default_directory")]
+    pub directory: PathBuf,
+
+    /// Read templates from disk on every render, so changes show up without a restart.
+    /// Enabled by default in debug builds.
+    #[serde(default = "default_reload")]
+    pub reload: bool,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_directory(),
+            reload: default_reload(),
+        }
+    }
+}
+
+fn default_directory() -> PathBuf {
+    PathBuf::from("templates")
+}
+
+fn default_reload() -> bool {
+    cfg!(debug_assertions)
+}
+
+type Setup = dyn Fn(&mut Environment<'static>) + Send + Sync;
+
+/// Renders the minijinja templates of a directory. Add it as a layer and extract it
+/// in handlers to render html responses.
+///
+/// Use like this: `async fn index(templates: Templates) -> Html<Page> { templates.html("index.html", page) }`
+#[derive(Clone)]
+pub struct Templates {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: TemplateConfig,
+    setup: Box<Setup>,
+    environment: Environment<'static>,
+}
+
+impl Templates {
+    pub fn new(config: TemplateConfig) -> Self {
+        Self::with_setup(config, |_| {})
+    }
+
+    /// Calls `setup` on every environment that is created, e.g. to add filters,
+    /// functions or globals to the environment.
+    pub fn with_setup(
+        config: TemplateConfig,
+        setup: impl Fn(&mut Environment<'static>) + Send + Sync + 'static,
+    ) -> Self {
+        let environment = environment(&config, &setup);
+
+        let inner = Inner {
+            config,
+            setup: Box::new(setup),
+            environment,
+        };
+
+        Self { inner: Arc::new(inner) }
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+
+    /// Renders the template with the given name.
+    pub fn render<T: Serialize>(&self, name: &str, context: T) -> Result<String, WebError> {
+        let environment = match self.inner.config.reload {
+            true => Cow::Owned(environment(&self.inner.config, &self.inner.setup)),
+            false => Cow::Borrowed(&self.inner.environment),
+        };
+
+        Ok(environment.get_template(name)?.render(context)?)
+    }
+
+    /// Returns a response that renders the template with the given name.
+    pub fn html<T: Serialize>(&self, name: impl Into<String>, context: T) -> Html<T> {
+        Html {
+            templates: self.clone(),
+            name: name.into(),
+            context,
+        }
+    }
+}
+
+/// Templates are loaded lazily, the loader caches them in the environment.
+fn environment(config: &TemplateConfig, setup: &Setup) -> Environment<'static> {
+    let mut environment = Environment::new();
+    environment.set_loader(path_loader(&config.directory));
+    setup(&mut environment);
+    environment
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Templates
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Templates>().cloned().ok_or_else(|| {
+            let message = "Templates are not configured, add Templates::into_layer() to the router";
+            WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message.into())
+        })
+    }
+}
+
+/// A html response rendered by [Templates::html]. Rendering errors are
+/// returned as [WebError].
+pub struct Html<T> {
+    templates: Templates,
+    name: String,
+    context: T,
+}
+
+impl<T: Serialize> IntoResponse for Html<T> {
+    fn into_response(self) -> Response {
+        match self.templates.render(&self.name, self.context) {
+            Ok(body) => axum::response::Html(body).into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::middleware::AddExtension;
+use axum::{Extension, Router};
+use hyper::server::accept::Accept;
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
+use startup_base::watch::{FileWatch, Watched};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, NoClientAuth, ResolvesServerCert,
+    UnparsedCertRevocationList,
+};
+use tokio_rustls::rustls::sign::{any_supported_type, CertifiedKey};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::{ClientCertificate, HttpConfig};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate chain, starting with the certificate of the server.
+    pub certificate: PathBuf,
+
+    /// Path to the PEM encoded private key of the certificate.
+    pub key: PathBuf,
+
+    /// Close connections that do not complete the handshake in time.
+    #[serde(default = "default_handshake_timeout_seconds")]
+    pub handshake_timeout_seconds: u64,
+
+    /// Authenticate clients with certificates, for internal services where mutual tls
+    /// replaces bearer tokens. See [ClientCertificate].
+    #[serde(default)]
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+fn default_handshake_timeout_seconds() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientAuthConfig {
+    /// Path to the PEM encoded certificates of the authorities that issue client certificates.
+    pub ca: PathBuf,
+
+    /// Reject clients without a certificate during the handshake. If false, clients may connect
+    /// without a certificate, and handlers decide using `Option<ClientCertificate>`.
+    #[serde(default = "default_required")]
+    pub required: bool,
+
+    /// Paths to PEM or DER encoded certificate revocation lists of the authorities.
+    /// Reloaded like the certificate authorities when they change.
+    #[serde(default)]
+    pub crls: Vec<PathBuf>,
+
+    /// Accept only client certificates with one of these subject alternative names,
+    /// like `orders.internal` or `spiffe://example.org/ns/shop/sa/orders`. Empty accepts
+    /// all certificates issued by the authorities.
+    #[serde(default)]
+    pub allowed_sans: Vec<String>,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+impl ClientAuthConfig {
+    fn read_roots(&self) -> io::Result<RootCertStore> {
+        let certificates = rustls_pemfile::certs(&mut read(&self.ca)?.as_slice())?;
+
+        let mut roots = RootCertStore::empty();
+        let (valid, _) = roots.add_parsable_certificates(&certificates);
+
+        if valid == 0 {
+            return Err(invalid_data(format!("no valid certificate found in {:?}", self.ca)));
+        }
+
+        Ok(roots)
+    }
+
+    fn read_crls(&self) -> io::Result<Vec<UnparsedCertRevocationList>> {
+        let mut crls = Vec::new();
+
+        for path in &self.crls {
+            let content = read(path)?;
+
+            match rustls_pemfile::crls(&mut content.as_slice())? {
+                pem if pem.is_empty() => crls.push(UnparsedCertRevocationList(content)),
+                pem => crls.extend(pem.into_iter().map(UnparsedCertRevocationList)),
+            }
+        }
+
+        Ok(crls)
+    }
+
+    /// Checks the subject alternative names of the certificate against `allowed_sans`.
+    fn allows(&self, certificate: &ClientCertificate) -> bool {
+        self.allowed_sans.is_empty() || certificate.sans().iter().any(|san| self.allowed_sans.contains(san))
+    }
+}
+
+impl TlsConfig {
+    /// Loads the certificate, which is reloaded when one of the files changes,
+    /// so a renewed certificate is used for new connections without a restart.
+    /// The certificate authorities and revocation lists of [ClientAuthConfig] are
+    /// reloaded the same way.
+    fn acceptor(&self) -> io::Result<Watched<TlsAcceptor>> {
+        let config = self.clone();
+
+        let certificate = FileWatch::new("tls-certificate", [&self.certificate, &self.key])
+            .load(move || config.load_certificate())?;
+
+        let resolver = Arc::new(WatchedCertificate(certificate));
+
+        let Some(client_auth) = self.client_auth.clone() else {
+            return Ok(Watched::fixed(server_config(resolver, None)?));
+        };
+
+        let paths = std::iter::once(&client_auth.ca).chain(&client_auth.crls);
+
+        FileWatch::new("tls-client-auth", paths).load(move || server_config(resolver.clone(), Some(&client_auth)))
+    }
+
+    fn load_certificate(&self) -> io::Result<CertifiedKey> {
+        let (certificates, key) = self.read_pem()?;
+
+        let key = match key {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => PrivateKey(key),
+            _ => unreachable!("read_pem only returns private keys"),
+        };
+
+        let key = any_supported_type(&key).map_err(|err| invalid_data(format!("invalid private key: {}", err)))?;
+
+        let certificates = certificates.into_iter().map(Certificate).collect();
+        Ok(CertifiedKey::new(certificates, key))
+    }
+
+    /// Reads the DER encoded certificate chain and the first private key from the PEM files.
+    pub(crate) fn read_pem(&self) -> io::Result<(Vec<Vec<u8>>, Item)> {
+        let certificates = rustls_pemfile::certs(&mut read(&self.certificate)?.as_slice())?;
+        if certificates.is_empty() {
+            return Err(invalid_data(format!("no certificate found in {:?}", self.certificate)));
+        }
+
+        let key = rustls_pemfile::read_all(&mut read(&self.key)?.as_slice())?
+            .into_iter()
+            .find(|item| matches!(item, Item::RSAKey(_) | Item::PKCS8Key(_) | Item::ECKey(_)))
+            .ok_or_else(|| invalid_data(format!("no private key found in {:?}", self.key)))?;
+
+        Ok((certificates, key))
+    }
+}
+
+fn server_config(resolver: Arc<WatchedCertificate>, client_auth: Option<&ClientAuthConfig>) -> io::Result<TlsAcceptor> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+
+    let builder = match client_auth {
+        None => builder.with_client_cert_verifier(NoClientAuth::boxed()),
+
+        Some(client_auth) => {
+            let roots = client_auth.read_roots()?;
+            let crls = client_auth.read_crls()?;
+
+            let invalid_crl = |err| invalid_data(format!("invalid certificate revocation list: {:?}", err));
+
+            let verifier = match client_auth.required {
+                true => AllowAnyAuthenticatedClient::new(roots).with_crls(crls).map_err(invalid_crl)?.boxed(),
+                false => AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+                    .with_crls(crls)
+                    .map_err(invalid_crl)?
+                    .boxed(),
+            };
+
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+
+    let mut server_config = builder.with_cert_resolver(resolver);
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|err| io::Error::new(err.kind(), format!("failed to read {:?}: {}", path, err)))
+}
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct WatchedCertificate(Watched<CertifiedKey>);
+
+impl ResolvesServerCert for WatchedCertificate {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.get())
+    }
+}
+
+/// Accepts tls connections on the listener. Handshakes run in their own tasks,
+/// so a slow client does not hold up the connections of other clients.
+pub(crate) fn accept(config: &HttpConfig, tls: &TlsConfig, listener: std::net::TcpListener) -> io::Result<TlsAccept> {
+    let acceptor = tls.acceptor()?;
+    let client_auth = tls.client_auth.clone().map(Arc::new);
+    let listener = TcpListener::from_std(listener)?;
+
+    let handshake_timeout = Duration::from_secs(tls.handshake_timeout_seconds);
+    let tcp_nodelay = config.tcp_nodelay;
+    let tcp_keepalive = config.tcp_keepalive_seconds.map(Duration::from_secs);
+
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote) = tokio::select! {
+                // the server stopped accepting connections
+                _ = tx.closed() => return,
+
+                result = listener.accept() => match result {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        // e.g. too many open files, try again after other connections closed
+                        warn!("Failed to accept connection: {}", err);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+            };
+
+            if let Err(err) = configure(&stream, tcp_nodelay, tcp_keepalive) {
+                debug!("Failed to configure connection from {}: {}", remote, err);
+            }
+
+            let acceptor = acceptor.get();
+            let client_auth = client_auth.clone();
+            let tx = tx.clone();
+
+            tokio::spawn(async move {
+                match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let der = stream
+                            .get_ref()
+                            .1
+                            .peer_certificates()
+                            .and_then(|certificates| certificates.first())
+                            .map(|certificate| certificate.0.clone());
+
+                        let client_certificate = der.as_deref().and_then(ClientCertificate::parse);
+
+                        // the subject alternative names of a certificate that cannot be parsed are unknown
+                        if let (Some(client_auth), Some(_), None) = (&client_auth, &der, &client_certificate) {
+                            if !client_auth.allowed_sans.is_empty() {
+                                debug!("Rejected client certificate of {} that could not be parsed", remote);
+                                return;
+                            }
+                        }
+
+                        if let (Some(client_auth), Some(certificate)) = (&client_auth, &client_certificate) {
+                            if !client_auth.allows(certificate) {
+                                debug!(
+                                    "Rejected client certificate of {} with subject alternative names {:?}",
+                                    remote,
+                                    certificate.sans()
+                                );
+
+                                return;
+                            }
+                        }
+
+                        let connection = TlsConnection {
+                            stream,
+                            remote,
+                            client_certificate,
+                        };
+
+                        let _ = tx.send(connection).await;
+                    }
+
+                    Ok(Err(err)) => debug!("Tls handshake with {} failed: {}", remote, err),
+                    Err(_) => debug!("Tls handshake with {} timed out", remote),
+                }
+            });
+        }
+    });
+
+    info!("Serving https");
+
+    Ok(TlsAccept(rx))
+}
+
+fn configure(stream: &TcpStream, tcp_nodelay: bool, tcp_keepalive: Option<Duration>) -> io::Result<()> {
+    stream.set_nodelay(tcp_nodelay)?;
+
+    if let Some(time) = tcp_keepalive {
+        SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+    }
+
+    Ok(())
+}
+
+pub(crate) struct TlsAccept(mpsc::Receiver<TlsConnection>);
+
+impl Accept for TlsAccept {
+    type Conn = TlsConnection;
+    type Error = io::Error;
+
+    fn poll_accept(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0.poll_recv(cx).map(|connection| connection.map(Ok))
+    }
+}
+
+/// A connection that completed the tls handshake.
+pub struct TlsConnection {
+    stream: TlsStream<TcpStream>,
+    remote: SocketAddr,
+    client_certificate: Option<ClientCertificate>,
+}
+
+type ConnectionService = AddExtension<AddExtension<Router, ConnectInfo<SocketAddr>>, Option<ClientCertificate>>;
+
+impl TlsConnection {
+    /// The router serving the requests of this connection, with the address
+    /// and the [ClientCertificate] of the client.
+    pub(crate) fn service(&self, router: Router) -> ConnectionService {
+        use tower_layer::Layer;
+
+        let service = Extension(ConnectInfo(self.remote)).layer(router);
+        Extension(self.client_certificate.clone()).layer(service)
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+}
@@ -2,6 +2,7 @@ use std::{borrow::Cow, error::Error as StdError, future::Future, pin::Pin, task:
 use std::fmt::Debug;
 
 use axum::body::BoxBody;
+use axum::extract::MatchedPath;
 use axum::http::Request;
 use futures_util::FutureExt as f_FutureExt;
 use hyper::{header, HeaderMap, Method, Response, Version};
@@ -11,7 +12,7 @@ use opentelemetry::trace::{FutureExt, OrderMap, Status};
 use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer};
 use opentelemetry_http::HeaderExtractor;
 use opentelemetry_semantic_conventions::trace::{
-    HTTP_FLAVOR, HTTP_METHOD, HTTP_STATUS_CODE, HTTP_TARGET, HTTP_URL, HTTP_USER_AGENT,
+    HTTP_FLAVOR, HTTP_METHOD, HTTP_ROUTE, HTTP_STATUS_CODE, HTTP_TARGET, HTTP_URL, HTTP_USER_AGENT,
 };
 use tower_http::trace::{DefaultMakeSpan, MakeSpan};
 use tracing::{Level, Span};
@@ -30,6 +31,12 @@ impl ZipkinMakeSpan {
     }
 }
 
+impl Default for ZipkinMakeSpan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<B> MakeSpan<B> for ZipkinMakeSpan {
     fn make_span(&mut self, request: &Request<B>) -> Span {
         let span = self.delegate.make_span(request);
@@ -49,6 +56,9 @@ impl<B> MakeSpan<B> for ZipkinMakeSpan {
 
 /// [`Layer`] that adds high level [opentelemetry propagation] to a [`Service`].
 ///
+/// Spans are named after the matched route template, e.g. `/orders/:id`, which requires adding
+/// the layer using `Router::layer`. The raw path is recorded in the `http.target` attribute.
+///
 /// [`Layer`]: tower_layer::Layer
 /// [opentelemetry propagation]: https://opentelemetry.io/docs/java/manual_instrumentation/#context-propagation
 /// [`Service`]: tower_service::Service
@@ -122,10 +132,23 @@ impl<B, S> tower_service::Service<Request<B>> for Service<S>
 
         let uri = req.uri();
 
-        let mut builder = tracer.span_builder(uri.path().to_string()).with_kind(SpanKind::Server);
+        // use the route template instead of the path to keep the number of span names low
+        let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_owned());
+
+        let name = match &route {
+            Some(route) => route.clone(),
+            None => format!("HTTP {}", http_method_str(req.method())),
+        };
 
-        let mut attributes = OrderMap::<Key, Value>::with_capacity(11);
+        let mut builder = tracer.span_builder(name).with_kind(SpanKind::Server);
+
+        let mut attributes = OrderMap::<Key, Value>::with_capacity(12);
         attributes.insert(HTTP_METHOD, Value::String(http_method_str(req.method()).into()));
+
+        if let Some(route) = route {
+            attributes.insert(HTTP_ROUTE, Value::String(route.into()));
+        }
+
         attributes.insert(HTTP_FLAVOR, Value::String(http_flavor(req.version()).into()));
         attributes.insert(HTTP_URL, Value::String(uri.to_string().into()));
 
@@ -136,7 +159,7 @@ impl<B, S> tower_service::Service<Request<B>> for Service<S>
             attributes.insert(HTTP_USER_AGENT, Value::String(user_agent.to_owned().into()));
         }
 
-        builder.attributes = Some(attributes.into());
+        builder.attributes = Some(attributes);
 
         let span = tracer.build_with_context(builder, &parent_context);
         let cx = Context::current_with_span(span);
@@ -228,9 +251,9 @@ impl HttpBody for EndSpanBody {
         let this = self.project();
 
         let result = futures_util::ready!(this.body.poll_data(cx));
-        if result.is_some() {
-            // the caller is not expected to call `poll_trailers` if this function
-            // does not return None. So we need to end the span here.
+        if result.is_none() {
+            // the caller is not expected to call `poll_trailers` after the body
+            // has ended. So we need to end the span here.
             this.span_context.span().end();
         }
 
@@ -0,0 +1,219 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::{BodyStream, FromRequest};
+use axum::http::{header, Request, StatusCode};
+use axum::{BoxError, Extension};
+use futures_util::{Stream, TryStreamExt};
+use multer::{Constraints, Field, Multipart, SizeLimit};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::WebError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadConfig {
+    /// Requests with a field larger than this are rejected with `413 Payload Too Large`.
+    #[serde(default = "default_max_field_size")]
+    pub max_field_size: u64,
+
+    /// Requests with a multipart body larger than this are rejected with `413 Payload Too Large`.
+    #[serde(default = "default_max_total_size")]
+    pub max_total_size: u64,
+
+    /// Only files with a content type starting with one of these prefixes are accepted,
+    /// others are rejected with `415 Unsupported Media Type`. Accepts all files if empty.
+    #[serde(default)]
+    pub content_types: Vec<String>,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_field_size: default_max_field_size(),
+            max_total_size: default_max_total_size(),
+            content_types: Vec::new(),
+        }
+    }
+}
+
+fn default_max_field_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_total_size() -> u64 {
+    50 * 1024 * 1024
+}
+
+impl UploadConfig {
+    /// Use like this: `.route("/upload", post(upload).layer(config.into_layer()))`
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    fn accepts(&self, content_type: Option<&str>) -> bool {
+        if self.content_types.is_empty() {
+            return true;
+        }
+
+        let Some(content_type) = content_type.map(str::to_ascii_lowercase) else {
+            return false;
+        };
+
+        self.content_types
+            .iter()
+            .any(|prefix| content_type.starts_with(&prefix.to_ascii_lowercase()))
+    }
+}
+
+/// Extracts a `multipart/form-data` request body and streams its fields without
+/// buffering the whole request. The limits are taken from the [UploadConfig]
+/// added as a layer, or the defaults if there is none.
+///
+/// Use like this:
+/// ```ignore
+/// async fn upload(mut upload: MultipartUpload) -> Result<(), WebError> {
+///     while let Some(field) = upload.next_field().await? {
+///         field.save_to("/tmp/upload").await?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct MultipartUpload {
+    multipart: Multipart<'static>,
+    config: Arc<UploadConfig>,
+}
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for MultipartUpload
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Into<Bytes>,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let boundary = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|content_type| multer::parse_boundary(content_type).ok())
+            .ok_or_else(|| {
+                let message = "Expected request with `Content-Type: multipart/form-data`".to_string();
+                WebError::Response(StatusCode::UNSUPPORTED_MEDIA_TYPE, message)
+            })?;
+
+        let config = req.extensions().get::<Arc<UploadConfig>>().cloned().unwrap_or_default();
+
+        let constraints = Constraints::new().size_limit(
+            SizeLimit::new()
+                .per_field(config.max_field_size)
+                .whole_stream(config.max_total_size),
+        );
+
+        let Ok(stream) = BodyStream::from_request(req, state).await;
+        let multipart = Multipart::with_constraints(stream, boundary, constraints);
+
+        Ok(MultipartUpload { multipart, config })
+    }
+}
+
+impl MultipartUpload {
+    /// Returns the next field of the request. Files with a content type that is
+    /// not accepted by the [UploadConfig] are rejected with `415 Unsupported Media Type`.
+    pub async fn next_field(&mut self) -> Result<Option<UploadField>, WebError> {
+        let Some(field) = self.multipart.next_field().await.map_err(into_web_error)? else {
+            return Ok(None);
+        };
+
+        if field.file_name().is_some() && !self.config.accepts(field.content_type().map(|mime| mime.as_ref())) {
+            let content_type = field.content_type().map(|mime| mime.to_string()).unwrap_or_default();
+            let message = format!("Files of type {:?} are not accepted", content_type);
+            return Err(WebError::Response(StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+        }
+
+        Ok(Some(UploadField(field)))
+    }
+}
+
+/// A single field of a [MultipartUpload].
+pub struct UploadField(Field<'static>);
+
+impl UploadField {
+    pub fn name(&self) -> Option<&str> {
+        self.0.name()
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.0.file_name()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.0.content_type().map(|mime| mime.as_ref())
+    }
+
+    /// Returns the next chunk of the field, or None if the field is complete.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, WebError> {
+        self.0.chunk().await.map_err(into_web_error)
+    }
+
+    /// Reads the whole field into memory.
+    pub async fn bytes(self) -> Result<Bytes, WebError> {
+        self.0.bytes().await.map_err(into_web_error)
+    }
+
+    pub async fn text(self) -> Result<String, WebError> {
+        self.0.text().await.map_err(into_web_error)
+    }
+
+    /// Returns the content of the field as a stream of chunks, e.g. to pass it on to a storage backend.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes, WebError>> + Send + 'static {
+        self.0.map_err(into_web_error)
+    }
+
+    /// Streams the content of the field into the given writer and returns the number of bytes written.
+    pub async fn copy_to<W: AsyncWrite + Unpin>(mut self, writer: &mut W) -> Result<u64, WebError> {
+        let mut written = 0;
+
+        while let Some(chunk) = self.chunk().await? {
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+
+        writer.flush().await?;
+
+        Ok(written)
+    }
+
+    /// Streams the content of the field into a new file at the given path.
+    /// The file is removed if the upload fails.
+    pub async fn save_to(self, path: impl AsRef<Path>) -> Result<u64, WebError> {
+        let path = path.as_ref();
+        let mut file = File::create(path).await?;
+
+        let result = self.copy_to(&mut file).await;
+        if result.is_err() {
+            drop(file);
+            let _ = tokio::fs::remove_file(path).await;
+        }
+
+        result
+    }
+}
+
+fn into_web_error(err: multer::Error) -> WebError {
+    let status = match err {
+        multer::Error::FieldSizeExceeded { .. } | multer::Error::StreamSizeExceeded { .. } => {
+            StatusCode::PAYLOAD_TOO_LARGE
+        }
+
+        _ => StatusCode::BAD_REQUEST,
+    };
+
+    WebError::Response(status, err.to_string())
+}
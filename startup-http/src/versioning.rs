@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode};
+use axum::response::IntoResponse;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_service::Service;
+
+use crate::error::{header_value, ConfigError};
+use crate::WebError;
+
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VersioningConfig {
+    /// Deprecation settings by version name, e.g. `v1`.
+    #[serde(default)]
+    pub versions: HashMap<String, ApiVersionConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiVersionConfig {
+    /// Responses of a deprecated version carry a `Deprecation` header.
+    #[serde(default)]
+    pub deprecated: bool,
+
+    /// Date after which the version will be removed, as http date,
+    /// e.g. `Wed, 01 Jan 2025 00:00:00 GMT`. Sent as `Sunset` header, implies `deprecated`.
+    #[serde(default)]
+    pub sunset: Option<String>,
+
+    /// Link to the migration guide, sent as `Link` header with `rel="deprecation"`.
+    #[serde(default)]
+    pub link: Option<String>,
+}
+
+impl ApiVersionConfig {
+    fn headers(&self, version: &str) -> Result<Vec<(HeaderName, HeaderValue)>, ConfigError> {
+        let mut headers = Vec::new();
+
+        if self.deprecated || self.sunset.is_some() {
+            headers.push((DEPRECATION.clone(), HeaderValue::from_static("true")));
+        }
+
+        if let Some(sunset) = &self.sunset {
+            let sunset = httpdate::parse_http_date(sunset).map_err(|_| ConfigError::Invalid {
+                field: "versioning.sunset",
+                message: format!("sunset of {} must be a http date, got {:?}", version, sunset),
+            })?;
+
+            headers.push((SUNSET.clone(), header_value("versioning.sunset", httpdate::fmt_http_date(sunset))?));
+        }
+
+        if let Some(link) = &self.link {
+            let link = header_value("versioning.link", format!("<{}>; rel=\"deprecation\"", link))?;
+            headers.push((header::LINK, link));
+        }
+
+        Ok(headers)
+    }
+}
+
+/// Mounts multiple versions of an api. Responses of versions that are deprecated
+/// in the [VersioningConfig] carry `Deprecation`, `Sunset` and `Link` headers.
+///
+/// Use like this: `ApiVersions::new(config)?.version("v1", v1::router()).version("v2", v2::router()).into_router()`
+pub struct ApiVersions {
+    headers: HashMap<String, Vec<(HeaderName, HeaderValue)>>,
+    versions: Vec<(String, Router)>,
+}
+
+impl ApiVersions {
+    /// Fails if the headers of a version can not be created from the config.
+    pub fn new(config: VersioningConfig) -> Result<Self, ConfigError> {
+        let headers = config
+            .versions
+            .iter()
+            .map(|(name, version)| Ok((name.clone(), version.headers(name)?)))
+            .collect::<Result<_, ConfigError>>()?;
+
+        Ok(Self {
+            headers,
+            versions: Vec::new(),
+        })
+    }
+
+    /// Adds a version. Versions must be added from oldest to newest.
+    pub fn version(mut self, name: impl Into<String>, router: Router) -> Self {
+        let name = name.into();
+
+        let headers = self.headers.get(&name).cloned().unwrap_or_default();
+
+        let router = headers.into_iter().fold(router, |router, (name, value)| {
+            router.layer(SetResponseHeaderLayer::overriding(name, value))
+        });
+
+        self.versions.push((name, router));
+        self
+    }
+
+    /// Mounts every version below a path prefix with its name, e.g. `/v1/users`.
+    pub fn into_router(self) -> Router {
+        self.versions
+            .into_iter()
+            .fold(Router::new(), |router, (name, version)| {
+                router.nest(&format!("/{}", name), version)
+            })
+    }
+
+/// Selects the version using the `version` parameter of the `Accept` header, e.g.
+    /// `Accept: application/json; version=v2`, or a vendor media type like
+    /// `Accept: application/vnd.example.v2+json`. Requests without a version are
+    /// served by the newest version, requests for an unknown version are rejected
+    /// with `406 Not Acceptable`.
+    pub fn into_accept_router(self) -> Router {
+        assert!(!self.versions.is_empty(), "at least one version must be added");
+
+        let versions = self.versions;
+
+        Router::new().fallback(move |req: Request<Body>| {
+            let index = match requested_version(req.headers()) {
+                Some(requested) => versions.iter().position(|(name, _)| same_version(name, &requested)),
+                None => Some(versions.len() - 1),
+            };
+
+            let router = index.map(|index| versions[index].1.clone());
+
+            let available: Vec<_> = versions.iter().map(|(name, _)| name.clone()).collect();
+
+            async move {
+                let Some(mut router) = router else {
+                    let message = format!("Unknown api version, available versions: {}", available.join(", "));
+                    return WebError::Response(StatusCode::NOT_ACCEPTABLE, message).into_response();
+                };
+
+                let Ok(mut response) = router.call(req).await;
+                response
+                    .headers_mut()
+                    .append(header::VARY, HeaderValue::from_static("accept"));
+
+                response
+            }
+        })
+    }
+}
+
+/// Returns the version requested in the `Accept` header, if any.
+fn requested_version(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|media_range| {
+            let mut parts = media_range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default();
+
+            let parameter = parts.find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("version")
+                    .then(|| value.trim().trim_matches('"'))
+            });
+
+            // application/vnd.example.v2+json
+            let vendor = || {
+                let subtype = media_type.split_once('/')?.1.strip_prefix("vnd.")?;
+                let subtype = subtype.split_once('+').map_or(subtype, |(subtype, _)| subtype);
+                subtype.rsplit_once('.').map(|(_, version)| version)
+            };
+
+            parameter.or_else(vendor).map(str::to_owned)
+        })
+}
+
+/// Compares version names, ignoring a leading `v`, so `2` and `v2` are the same version.
+fn same_version(name: &str, requested: &str) -> bool {
+    let normalize = |version: &str| {
+        let version = version.trim();
+        version.strip_prefix(['v', 'V']).unwrap_or(version).to_ascii_lowercase()
+    };
+
+    normalize(name) == normalize(requested)
+}
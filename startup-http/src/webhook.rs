@@ -0,0 +1,302 @@
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::async_trait;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::FromRequest;
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::{BoxError, Extension};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use startup_base::redact::REDACTED;
+use startup_base::signature::{verify_hmac, webhook_key_or_raw};
+use startup_base::watch::{FileWatch, Watched};
+
+use crate::extract::{deserialize_json, is_json_content_type};
+use crate::WebError;
+
+pub use startup_base::signature::SignatureAlgorithm;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureEncoding {
+    Hex,
+    Base64,
+}
+
+/// How the signatures of incoming webhooks are verified. The defaults match the Standard
+/// Webhooks scheme used by `startup-webhooks`: the base64 encoded HMAC-SHA256 of
+/// `{id}.{timestamp}.{body}` in the header `webhook-signature`, prefixed with `v1,`.
+///
+/// For a sender like GitHub, that signs only the body, configure `signature_header:
+/// x-hub-signature-256`, `prefix: "sha256="`, `encoding: hex` and no id or timestamp header.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Active secrets. A signature made with any of them is accepted, so a secret can be
+    /// rotated by adding the new secret and removing the old one once the sender switched.
+    /// The key of a Standard Webhooks secret like `whsec_<base64 key>` is decoded, other
+    /// secrets are used as they are.
+    #[serde(default, serialize_with = "startup_base::redact::serialize_redacted")]
+    pub secrets: Vec<String>,
+
+    /// File with further secrets, one per line, that is reloaded when it changes.
+    /// It is read by [WebhookConfig::watch_secrets].
+    #[serde(default)]
+    pub secrets_file: Option<PathBuf>,
+
+    #[serde(skip)]
+    file_secrets: Option<Watched<Vec<String>>>,
+
+    /// Header with the signatures, multiple signatures are separated by spaces.
+    #[serde(default = "default_signature_header")]
+    pub signature_header: String,
+
+    /// Prefix of every signature in the header, e.g. the version.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+
+    /// Header with the id of the message, which is part of the signed content if set.
+    #[serde(default = "default_id_header")]
+    pub id_header: Option<String>,
+
+    /// Header with the unix time the message was signed at, which is part of the signed
+    /// content if set. Messages signed too long ago are rejected as replays.
+    #[serde(default = "default_timestamp_header")]
+    pub timestamp_header: Option<String>,
+
+    #[serde(default = "default_algorithm")]
+    pub algorithm: SignatureAlgorithm,
+
+    #[serde(default = "default_encoding")]
+    pub encoding: SignatureEncoding,
+
+    /// Maximum difference between the timestamp of a message and now.
+    #[serde(default = "default_tolerance_seconds")]
+    pub tolerance_seconds: u64,
+}
+
+impl Debug for WebhookConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("secrets", &REDACTED)
+            .field("secrets_file", &self.secrets_file)
+            .field("signature_header", &self.signature_header)
+            .field("prefix", &self.prefix)
+            .field("id_header", &self.id_header)
+            .field("timestamp_header", &self.timestamp_header)
+            .field("algorithm", &self.algorithm)
+            .field("encoding", &self.encoding)
+            .field("tolerance_seconds", &self.tolerance_seconds)
+            .finish()
+    }
+}
+
+fn default_signature_header() -> String {
+    "webhook-signature".to_string()
+}
+
+fn default_prefix() -> String {
+    "v1,".to_string()
+}
+
+fn default_id_header() -> Option<String> {
+    Some("webhook-id".to_string())
+}
+
+fn default_timestamp_header() -> Option<String> {
+    Some("webhook-timestamp".to_string())
+}
+
+fn default_algorithm() -> SignatureAlgorithm {
+    SignatureAlgorithm::Sha256
+}
+
+fn default_encoding() -> SignatureEncoding {
+    SignatureEncoding::Base64
+}
+
+fn default_tolerance_seconds() -> u64 {
+    300
+}
+
+impl WebhookConfig {
+    /// Loads the secrets of `secrets_file` and reloads them when the file changes, so
+    /// secrets mounted from a secret store can be rotated without a restart.
+    ///
+    /// Use like this: `post(payment).layer(config.watch_secrets()?.into_layer())`
+    pub fn watch_secrets(mut self) -> std::io::Result<Self> {
+        if let Some(path) = self.secrets_file.clone() {
+            let watch = FileWatch::new("webhook-secrets", [&path]);
+            self.file_secrets = Some(watch.load(move || read_secrets(&path))?);
+        }
+
+        Ok(self)
+    }
+
+    /// Use like this: `.route("/webhooks/payments", post(payment).layer(config.into_layer()))`
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    /// Verifies the signature of the body, see [SignedBody].
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), WebError> {
+        let id = match &self.id_header {
+            Some(name) => Some(header(headers, name)?),
+            None => None,
+        };
+
+        let timestamp = match &self.timestamp_header {
+            Some(name) => Some(header(headers, name)?),
+            None => None,
+        };
+
+        if let Some(timestamp) = timestamp {
+            let timestamp: u64 = timestamp
+                .parse()
+                .map_err(|_| unauthorized("Invalid webhook timestamp"))?;
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            if now.abs_diff(timestamp) > self.tolerance_seconds {
+                return Err(unauthorized("Webhook timestamp is outside of the tolerance"));
+            }
+        }
+
+        // the signed content is the body, preceded by the id and timestamp if configured
+        let mut message: Vec<&[u8]> = Vec::new();
+        for value in id.iter().chain(timestamp.iter()) {
+            message.extend([value.as_bytes(), b"."]);
+        }
+
+        message.push(body);
+
+        let signatures = header(headers, &self.signature_header)?;
+
+        let file_secrets = self.file_secrets.as_ref().map(Watched::get).unwrap_or_default();
+        let keys: Vec<Vec<u8>> = self
+            .secrets
+            .iter()
+            .chain(file_secrets.iter())
+            .map(|secret| webhook_key_or_raw(secret))
+            .collect();
+
+        let valid = signatures
+            .split_whitespace()
+            .filter_map(|signature| signature.strip_prefix(self.prefix.as_str()))
+            .filter_map(|signature| self.decode(signature))
+            .any(|signature| {
+                keys.iter()
+                    .any(|key| verify_hmac(self.algorithm, key, &message, &signature))
+            });
+
+        match valid {
+            true => Ok(()),
+            false => Err(unauthorized("Invalid webhook signature")),
+        }
+    }
+
+    fn decode(&self, signature: &str) -> Option<Vec<u8>> {
+        match self.encoding {
+            SignatureEncoding::Hex => hex::decode(signature).ok(),
+            SignatureEncoding::Base64 => STANDARD.decode(signature).ok(),
+        }
+    }
+}
+
+fn read_secrets(path: &Path) -> std::io::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)?;
+
+    let secrets = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect();
+
+    Ok(secrets)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, WebError> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| unauthorized(&format!("Missing webhook header {:?}", name)))
+}
+
+fn unauthorized(message: &str) -> WebError {
+    WebError::Response(StatusCode::UNAUTHORIZED, message.to_string())
+}
+
+/// The body of a webhook request with a verified signature. The signature is verified using
+/// the [WebhookConfig] added as a layer, requests with a missing or invalid signature are
+/// rejected with `401 Unauthorized`.
+///
+/// Use like this: `async fn payment(SignedBody(body): SignedBody) -> StatusCode { .. }`
+#[derive(Debug, Clone)]
+pub struct SignedBody(pub Bytes);
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for SignedBody
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(config) = req.extensions().get::<Arc<WebhookConfig>>().cloned() else {
+            let message = "WebhookConfig layer is missing".to_string();
+            return Err(WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message));
+        };
+
+        let headers = req.headers().clone();
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|err| WebError::Response(err.status(), err.body_text()))?;
+
+        config.verify(&headers, &body)?;
+
+        Ok(SignedBody(body))
+    }
+}
+
+/// Like [SignedBody], but deserializes the verified body like [Json](crate::Json).
+///
+/// Use like this: `async fn payment(SignedJson(event): SignedJson<PaymentEvent>) -> StatusCode { .. }`
+#[derive(Debug, Clone)]
+pub struct SignedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for SignedJson<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = is_json_content_type(req.headers());
+
+        let SignedBody(body) = SignedBody::from_request(req, state).await?;
+
+        if !is_json {
+            let message = "Expected request with `Content-Type: application/json`".to_string();
+            return Err(WebError::Response(StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+        }
+
+        deserialize_json(&body).map(SignedJson)
+    }
+}
@@ -0,0 +1,88 @@
+use std::future::Future;
+
+use axum::async_trait;
+use axum::extract::ws;
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::response::Response;
+use opentelemetry::trace::{FutureExt, SpanKind, TraceContextExt, Tracer};
+use tracing::{Instrument, Span};
+
+pub use axum::extract::ws::{CloseFrame, Message, WebSocket};
+
+pub use self::hub::{Hub, HubClient, HubConfig, SlowClient};
+use crate::shutdown;
+
+mod hub;
+
+/// Like [axum::extract::ws::WebSocketUpgrade], but runs the connection in its own span
+/// that is a child of the span of the upgrade request.
+///
+/// The connection is tracked by the server, which waits for it to close during a
+/// graceful shutdown. Use [crate::shutdown_requested] in the connection handler to
+/// close the connection once the shutdown begins.
+///
+/// To authenticate the connection, add the `Jwt` extractor of `startup-jwt` to the
+/// handler. As browsers cannot set headers on websocket requests, it also accepts
+/// the token in an `access_token` query parameter.
+pub struct WebSocketUpgrade {
+    inner: ws::WebSocketUpgrade,
+    path: String,
+    span: Span,
+    otel_context: opentelemetry::Context,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for WebSocketUpgrade
+where
+    S: Send + Sync,
+{
+    type Rejection = WebSocketUpgradeRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let inner = ws::WebSocketUpgrade::from_request_parts(parts, state).await?;
+
+        Ok(Self {
+            inner,
+            path: parts.uri.path().to_string(),
+            span: Span::current(),
+            otel_context: opentelemetry::Context::current(),
+        })
+    }
+}
+
+impl WebSocketUpgrade {
+    /// Configures the underlying upgrade, e.g. to set the protocols or message size limits.
+    pub fn configure(mut self, configure: impl FnOnce(ws::WebSocketUpgrade) -> ws::WebSocketUpgrade) -> Self {
+        self.inner = configure(self.inner);
+        self
+    }
+
+    /// Finalize the upgrade and run the callback with the established connection.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let tracer = opentelemetry::global::tracer("startup-http");
+
+        let otel_span = tracer
+            .span_builder(format!("websocket {}", self.path))
+            .with_kind(SpanKind::Server)
+            .start_with_context(&tracer, &self.otel_context);
+
+        let otel_context = self.otel_context.with_span(otel_span);
+
+        let span = info_span!(parent: &self.span, "websocket", path = %self.path);
+
+        self.inner.on_upgrade(move |socket| {
+            let connection = callback(socket).with_context(otel_context.clone()).instrument(span);
+
+            shutdown::track_connection(async move {
+                connection.await;
+                otel_context.span().end();
+            })
+        })
+    }
+}
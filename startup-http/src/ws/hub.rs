@@ -0,0 +1,485 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::Extension;
+use opentelemetry::metrics::{Counter, UpDownCounter};
+use opentelemetry::KeyValue;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::{shutdown, WebError};
+
+/// Close code sent to clients that did not keep up with their messages, "Try Again Later".
+const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+/// Close code sent to clients when the server shuts down, "Going Away".
+const CLOSE_GOING_AWAY: u16 = 1001;
+
+/// How the hub handles a client whose queue of outgoing messages is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowClient {
+    /// Drop the message, the client misses it.
+    Drop,
+
+    /// Close the connection, so the client reconnects and loads the current state.
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HubConfig {
+    /// Number of messages queued for a client before it counts as slow.
+    #[serde(default = "default_buffer")]
+    pub buffer: usize,
+
+    #[serde(default = "default_slow_client")]
+    pub slow_client: SlowClient,
+
+    /// Maximum number of topics a client can subscribe to.
+    #[serde(default = "default_max_subscriptions")]
+    pub max_subscriptions: usize,
+
+    /// Kinds of topics the metrics are labeled with, e.g. `order` for the topics `order:42` and
+    /// `order:43`. Clients choose the topics, so other topics share the label `other`.
+    #[serde(default)]
+    pub metric_topics: Vec<String>,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        Self {
+            buffer: default_buffer(),
+            slow_client: default_slow_client(),
+            max_subscriptions: default_max_subscriptions(),
+            metric_topics: Vec::new(),
+        }
+    }
+}
+
+fn default_buffer() -> usize {
+    64
+}
+
+fn default_slow_client() -> SlowClient {
+    SlowClient::Disconnect
+}
+
+fn default_max_subscriptions() -> usize {
+    100
+}
+
+/// A client connected to the [Hub].
+#[derive(Debug, Clone)]
+pub struct HubClient {
+    pub id: u64,
+
+    /// The authenticated user of the connection, a user can have multiple connections.
+    pub user: String,
+}
+
+type Authorize = dyn Fn(&HubClient, &str) -> bool + Send + Sync;
+
+/// Tracks the websocket clients of this instance and the topics they subscribed to, and
+/// broadcasts messages to the subscribers of a topic, e.g. for live updates in a frontend.
+///
+/// Clients subscribe by sending `{"type": "subscribe", "topic": "order:42"}` and unsubscribe
+/// by sending `{"type": "unsubscribe", "topic": "order:42"}`. Both are answered with a message
+/// of type `subscribed`, `unsubscribed` or `error`. Published messages are sent to the client
+/// as `{"type": "message", "topic": "order:42", "data": ...}`.
+///
+/// Every client has a queue of outgoing messages of [HubConfig::buffer] messages. If a client
+/// does not keep up, further messages are dropped or the client is disconnected, see [SlowClient].
+///
+/// The hub only reaches the clients connected to this instance. With multiple replicas,
+/// publish the message on every replica, e.g. by consuming it from a broadcast topic.
+///
+/// Clients, subscriptions and messages are counted in the `ws.hub.clients`,
+/// `ws.hub.subscriptions` and `ws.hub.messages` metrics. The metrics are labeled with the
+/// topic up to the first `:` if listed in [HubConfig::metric_topics], and `other` if not.
+///
+/// Use like this:
+/// ```ignore
+/// let hub = Hub::new(&config.hub, |client, topic| topic == format!("user:{}", client.user));
+///
+/// async fn live(upgrade: WebSocketUpgrade, hub: Hub, Jwt(claims): Jwt<Claims>) -> Response {
+///     upgrade.on_upgrade(move |socket| async move { hub.serve(socket, claims.user_name).await })
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Hub {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: HubConfig,
+    authorize: Box<Authorize>,
+    next_id: AtomicU64,
+    state: RwLock<State>,
+    clients: UpDownCounter<i64>,
+    subscriptions: UpDownCounter<i64>,
+    messages: Counter<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    clients: HashMap<u64, Client>,
+    topics: HashMap<String, HashSet<u64>>,
+}
+
+struct Client {
+    info: HubClient,
+    tx: mpsc::Sender<Message>,
+    topics: HashSet<String>,
+}
+
+/// A message sent by a client.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Request {
+    Subscribe { topic: String },
+    Unsubscribe { topic: String },
+}
+
+/// A message sent to a client.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Event<'a, T> {
+    Message { topic: &'a str, data: T },
+    Subscribed { topic: &'a str },
+    Unsubscribed { topic: &'a str },
+    Error { message: &'a str },
+}
+
+/// An event without data, sent in reply to a message of the client.
+type Reply<'a> = Event<'a, ()>;
+
+impl Hub {
+    /// Only lets clients subscribe to a topic if `authorize` returns true,
+    /// e.g. to restrict the topic `user:42` to the user 42.
+    pub fn new(config: &HubConfig, authorize: impl Fn(&HubClient, &str) -> bool + Send + Sync + 'static) -> Self {
+        let meter = opentelemetry::global::meter("startup-http");
+
+        let inner = Inner {
+            config: config.clone(),
+            authorize: Box::new(authorize),
+            next_id: AtomicU64::new(1),
+            state: RwLock::new(State::default()),
+            clients: meter
+                .i64_up_down_counter("ws.hub.clients")
+                .with_description("Websocket clients connected to the hub")
+                .init(),
+            subscriptions: meter
+                .i64_up_down_counter("ws.hub.subscriptions")
+                .with_description("Subscriptions of websocket clients to topics")
+                .init(),
+            messages: meter
+                .u64_counter("ws.hub.messages")
+                .with_description("Messages published to websocket clients")
+                .init(),
+        };
+
+        Self { inner: Arc::new(inner) }
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+
+    /// Runs the connection of a client until it is closed by either side. The connection is
+    /// closed when the server shuts down.
+    pub async fn serve(&self, mut socket: WebSocket, user: impl Into<String>) {
+        let (tx, mut rx) = mpsc::channel(self.inner.config.buffer.max(1));
+
+        let client = self.register(user.into(), tx);
+
+        let close = loop {
+            tokio::select! {
+                message = rx.recv() => match message {
+                    Some(message) => {
+                        if socket.send(message).await.is_err() {
+                            break None;
+                        }
+                    }
+
+                    // the hub disconnected the client, as it did not keep up
+                    None => break Some((CLOSE_TRY_AGAIN_LATER, "Client does not keep up with its messages")),
+                },
+
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = self.handle(&client, &text);
+
+                        if socket.send(reply).await.is_err() {
+                            break None;
+                        }
+                    }
+
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+
+                    // pings are answered by the websocket itself
+                    Some(Ok(_)) => {}
+                },
+
+                _ = shutdown::shutdown_requested() => break Some((CLOSE_GOING_AWAY, "Server is shutting down")),
+            }
+        };
+
+        self.unregister(client.id);
+
+        if let Some((code, reason)) = close {
+            let frame = CloseFrame {
+                code,
+                reason: Cow::Borrowed(reason),
+            };
+
+            let _ = socket.send(Message::Close(Some(frame))).await;
+        }
+    }
+
+    /// Sends the message to every client subscribed to the topic.
+    /// Returns the number of clients the message was queued for.
+    pub fn publish<T: Serialize>(&self, topic: &str, data: &T) -> Result<usize, serde_json::Error> {
+        let message = serde_json::to_string(&Event::Message { topic, data })?;
+
+        let targets: Vec<_> = {
+            let state = self.inner.state.read();
+
+            let Some(subscribers) = state.topics.get(topic) else {
+                return Ok(0);
+            };
+
+            subscribers
+                .iter()
+                .filter_map(|id| state.clients.get(id))
+                .map(|client| (client.info.id, client.tx.clone()))
+                .collect()
+        };
+
+        Ok(self.deliver(topic, targets, message))
+    }
+
+    /// Sends the message to every connection of the user, whether they subscribed
+    /// to the topic or not. Returns the number of connections the message was queued for.
+    pub fn send_to_user<T: Serialize>(&self, user: &str, topic: &str, data: &T) -> Result<usize, serde_json::Error> {
+        let message = serde_json::to_string(&Event::Message { topic, data })?;
+
+        let targets = self
+            .inner
+            .state
+            .read()
+            .clients
+            .values()
+            .filter(|client| client.info.user == user)
+            .map(|client| (client.info.id, client.tx.clone()))
+            .collect();
+
+        Ok(self.deliver(topic, targets, message))
+    }
+
+    /// The connected clients.
+    pub fn clients(&self) -> Vec<HubClient> {
+        let state = self.inner.state.read();
+        state.clients.values().map(|client| client.info.clone()).collect()
+    }
+
+    fn deliver(&self, topic: &str, targets: Vec<(u64, mpsc::Sender<Message>)>, message: String) -> usize {
+        let mut delivered = 0;
+        let mut dropped = 0;
+
+        for (id, tx) in targets {
+            match tx.try_send(Message::Text(message.clone())) {
+                Ok(()) => delivered += 1,
+
+                Err(TrySendError::Full(_)) => {
+                    dropped += 1;
+
+                    if self.inner.config.slow_client == SlowClient::Disconnect {
+                        debug!("Disconnecting websocket client {}, it does not keep up", id);
+                        self.unregister(id);
+                    }
+                }
+
+                // the client disconnected in the meantime
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+
+        let cx = opentelemetry::Context::current();
+        let label = self.topic_label(topic);
+
+        if delivered > 0 {
+            let attributes = [label.clone(), KeyValue::new("outcome", "delivered")];
+            self.inner.messages.add(&cx, delivered as u64, &attributes);
+        }
+
+        if dropped > 0 {
+            let attributes = [label, KeyValue::new("outcome", "dropped")];
+            self.inner.messages.add(&cx, dropped as u64, &attributes);
+        }
+
+        delivered
+    }
+
+    fn register(&self, user: String, tx: mpsc::Sender<Message>) -> HubClient {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let info = HubClient { id, user };
+
+        let client = Client {
+            info: info.clone(),
+            tx,
+            topics: HashSet::new(),
+        };
+
+        self.inner.state.write().clients.insert(id, client);
+
+        let cx = opentelemetry::Context::current();
+        self.inner.clients.add(&cx, 1, &[]);
+
+        debug!("Websocket client {} of user {:?} connected", id, info.user);
+
+        info
+    }
+
+    /// Removes the client and its subscriptions. Dropping the sender of the client
+    /// ends its connection once the queued messages are sent.
+    fn unregister(&self, id: u64) {
+        let Some(client) = self.inner.state.write().remove_client(id) else {
+            return;
+        };
+
+        let cx = opentelemetry::Context::current();
+        self.inner.clients.add(&cx, -1, &[]);
+
+        for topic in &client.topics {
+            let attributes = [self.topic_label(topic)];
+            self.inner.subscriptions.add(&cx, -1, &attributes);
+        }
+
+        debug!("Websocket client {} disconnected", id);
+    }
+
+    /// Handles a message of the client and returns the reply.
+    fn handle(&self, client: &HubClient, text: &str) -> Message {
+        let reply = match serde_json::from_str(text) {
+            Ok(Request::Subscribe { topic }) => match self.subscribe(client, &topic) {
+                Ok(()) => serde_json::to_string(&Reply::Subscribed { topic: &topic }),
+                Err(message) => serde_json::to_string(&Reply::Error { message }),
+            },
+
+            Ok(Request::Unsubscribe { topic }) => {
+                self.unsubscribe(client, &topic);
+                serde_json::to_string(&Reply::Unsubscribed { topic: &topic })
+            }
+
+            Err(_) => {
+                let message = "Expected a message of type subscribe or unsubscribe with a topic";
+                serde_json::to_string(&Reply::Error { message })
+            }
+        };
+
+        Message::Text(reply.expect("serialize websocket event"))
+    }
+
+    fn subscribe(&self, client: &HubClient, topic: &str) -> Result<(), &'static str> {
+        if !(self.inner.authorize)(client, topic) {
+            return Err("Not allowed to subscribe to the topic");
+        }
+
+        let mut state = self.inner.state.write();
+
+        let Some(entry) = state.clients.get_mut(&client.id) else {
+            return Err("Client is disconnected");
+        };
+
+        if entry.topics.contains(topic) {
+            return Ok(());
+        }
+
+        if entry.topics.len() >= self.inner.config.max_subscriptions {
+            return Err("Too many subscriptions");
+        }
+
+        entry.topics.insert(topic.to_string());
+        state.topics.entry(topic.to_string()).or_default().insert(client.id);
+
+        let attributes = [self.topic_label(topic)];
+        self.inner
+            .subscriptions
+            .add(&opentelemetry::Context::current(), 1, &attributes);
+
+        Ok(())
+    }
+
+    fn unsubscribe(&self, client: &HubClient, topic: &str) {
+        let mut state = self.inner.state.write();
+
+        let removed = state
+            .clients
+            .get_mut(&client.id)
+            .is_some_and(|entry| entry.topics.remove(topic));
+
+        if removed {
+            state.remove_subscriber(topic, client.id);
+
+            let attributes = [self.topic_label(topic)];
+            self.inner
+                .subscriptions
+                .add(&opentelemetry::Context::current(), -1, &attributes);
+        }
+    }
+
+    fn topic_label(&self, topic: &str) -> KeyValue {
+        let kind = topic.split(':').next().unwrap_or(topic);
+
+        match self.inner.config.metric_topics.iter().any(|known| known == kind) {
+            true => KeyValue::new("topic", kind.to_string()),
+            false => KeyValue::new("topic", "other"),
+        }
+    }
+}
+
+impl State {
+    fn remove_client(&mut self, id: u64) -> Option<Client> {
+        let client = self.clients.remove(&id)?;
+
+        for topic in &client.topics {
+            self.remove_subscriber(topic, id);
+        }
+
+        Some(client)
+    }
+
+    fn remove_subscriber(&mut self, topic: &str, id: u64) {
+        if let Some(subscribers) = self.topics.get_mut(topic) {
+            subscribers.remove(&id);
+
+            if subscribers.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Hub
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Hub>().cloned().ok_or_else(|| {
+            let message = "Hub is not configured, add Hub::into_layer() to the router";
+            WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message.into())
+        })
+    }
+}
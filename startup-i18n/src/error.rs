@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum I18nError {
+    #[error("failed to read translations from {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("invalid locale {0:?}")]
+    InvalidLocale(String),
+
+    #[error("failed to parse translations in {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+
+    #[error("no translations found for the default locale {0}")]
+    MissingDefault(String),
+}
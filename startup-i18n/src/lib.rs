@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::Extension;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::FluentResource;
+use serde::{Deserialize, Serialize, Serializer};
+use startup_http::{RequestContext, WebError};
+use startup_jwt::Jwt;
+use tracing::{debug, warn};
+
+pub use fluent_bundle::{FluentArgs, FluentValue};
+pub use unic_langid::LanguageIdentifier;
+
+pub use crate::error::I18nError;
+
+mod error;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct I18nConfig {
+    /// Directory with a subdirectory of Fluent `.ftl` files per locale,
+    /// e.g. `locales/en/main.ftl` and `locales/de/main.ftl`.
+    #[serde(default = "default_directory")]
+    pub directory: PathBuf,
+
+    /// Locale used if a request asks for no supported locale, and for messages
+    /// missing in the requested locale.
+    #[serde(default = "default_locale")]
+    pub default_locale: String,
+
+    /// Claim of the bearer token with the locale of the user. It takes precedence
+    /// over the `Accept-Language` header.
+    #[serde(default = "default_claim")]
+    pub claim: String,
+}
+
+impl Default for I18nConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_directory(),
+            default_locale: default_locale(),
+            claim: default_claim(),
+        }
+    }
+}
+
+fn default_directory() -> PathBuf {
+    PathBuf::from("locales")
+}
+
+fn default_locale() -> String {
+    "en".to_string()
+}
+
+fn default_claim() -> String {
+    "locale".to_string()
+}
+
+/// The message bundles of all supported locales, loaded once at startup. Add it as a layer
+/// and use the [Locale] extractor to translate messages in handlers.
+///
+/// Use like this: `router.layer(Translations::load(&config.i18n)?.into_layer())`
+#[derive(Clone)]
+pub struct Translations {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    default: LanguageIdentifier,
+    claim: String,
+    bundles: BTreeMap<LanguageIdentifier, FluentBundle<FluentResource>>,
+}
+
+impl Translations {
+    pub fn load(config: &I18nConfig) -> Result<Self, I18nError> {
+        let default = parse_locale(&config.default_locale)?;
+
+        let mut bundles = BTreeMap::new();
+
+        for entry in read_dir(&config.directory)? {
+            if !entry.is_dir() {
+                continue;
+            }
+
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            let locale = parse_locale(&name)?;
+
+            let bundle = load_bundle(&entry, locale.clone())?;
+            bundles.insert(locale, bundle);
+        }
+
+        if !bundles.contains_key(&default) {
+            return Err(I18nError::MissingDefault(default.to_string()));
+        }
+
+        debug!(
+            "Loaded translations for locales {:?}",
+            bundles.keys().collect::<Vec<_>>()
+        );
+
+        let inner = Inner {
+            default,
+            claim: config.claim.clone(),
+            bundles,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+
+    /// The supported locales.
+    pub fn locales(&self) -> impl Iterator<Item = &LanguageIdentifier> {
+        self.inner.bundles.keys()
+    }
+
+    /// The supported locale that matches the requested locale best, falling back to the
+    /// default locale. Use it to translate outside of requests, e.g. in mails or jobs.
+    pub fn locale(&self, requested: &str) -> Locale {
+        let id = parse_locale(requested)
+            .ok()
+            .and_then(|requested| self.negotiate(&requested))
+            .unwrap_or_else(|| self.inner.default.clone());
+
+        Locale {
+            id,
+            translations: self.clone(),
+        }
+    }
+
+    /// Translates the message with the given id. Messages missing in the locale are taken
+    /// from the default locale, unknown messages are replaced by their id.
+    pub fn translate(&self, locale: &LanguageIdentifier, id: &str, args: Option<&FluentArgs>) -> String {
+        let bundles = [locale, &self.inner.default]
+            .into_iter()
+            .filter_map(|locale| self.inner.bundles.get(locale));
+
+        for bundle in bundles {
+            let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+                continue;
+            };
+
+            let mut errors = Vec::new();
+            let text = bundle.format_pattern(pattern, args, &mut errors);
+
+            if !errors.is_empty() {
+                warn!("Failed to format message {:?} for {}: {:?}", id, locale, errors);
+            }
+
+            return text.into_owned();
+        }
+
+        warn!("Message {:?} is missing for {}", id, locale);
+        id.to_string()
+    }
+
+    /// Exact matches win over matches of the language only, so `de-AT` selects `de-AT`
+    /// over `de`, and `de-CH` still selects `de` or `de-DE`.
+    fn negotiate(&self, requested: &LanguageIdentifier) -> Option<LanguageIdentifier> {
+        let locales = || self.inner.bundles.keys();
+
+        locales()
+            .find(|locale| *locale == requested)
+            .or_else(|| locales().find(|locale| locale.matches(requested, true, true)))
+            .or_else(|| locales().find(|locale| locale.language == requested.language))
+            .cloned()
+    }
+
+    /// Makes the function `t` available in templates, e.g. `{{ t("greeting", name=user.name) }}`.
+    /// It translates into the locale in the `locale` variable of the template context.
+    ///
+    /// Use like this: `Templates::with_setup(config, move |env| translations.register(env))`
+    #[cfg(feature = "templates")]
+    pub fn register(&self, environment: &mut minijinja::Environment<'static>) {
+        use minijinja::value::{Kwargs, ValueKind};
+        use minijinja::State;
+
+        let translations = self.clone();
+
+        environment.add_function("t", move |state: &State, id: &str, kwargs: Kwargs| {
+            let locale = state
+                .lookup("locale")
+                .map(|locale| translations.locale(&locale.to_string()))
+                .unwrap_or_else(|| translations.locale(""));
+
+            let mut args = FluentArgs::new();
+            for name in kwargs.args() {
+                let value: minijinja::Value = kwargs.get(name)?;
+
+                match value.kind() {
+                    ValueKind::Number => args.set(name, f64::try_from(value)?),
+                    _ => args.set(name, value.to_string()),
+                }
+            }
+
+            Ok(locale.translate(id, Some(&args)))
+        });
+    }
+
+    /// The locale of the request: the locale claim of the bearer token if present, then
+    /// the preferred locales of the `Accept-Language` header, then the default locale.
+    async fn request_locale(&self, parts: &mut Parts) -> LanguageIdentifier {
+        // a request without a valid token has no locale claim
+        if let Ok(Jwt(claims)) = Jwt::<serde_json::Value>::from_request_parts(parts, &()).await {
+            let claimed = claims
+                .get(&self.inner.claim)
+                .and_then(|locale| locale.as_str())
+                .and_then(|locale| parse_locale(locale).ok())
+                .and_then(|locale| self.negotiate(&locale));
+
+            if let Some(locale) = claimed {
+                return locale;
+            }
+        }
+
+        accept_language(&parts.headers)
+            .iter()
+            .find_map(|locale| self.negotiate(locale))
+            .unwrap_or_else(|| self.inner.default.clone())
+    }
+}
+
+fn read_dir(directory: &Path) -> Result<Vec<PathBuf>, I18nError> {
+    let read_error = |source| I18nError::Read {
+        path: directory.to_path_buf(),
+        source,
+    };
+
+    let mut paths = std::fs::read_dir(directory)
+        .map_err(read_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_error)?;
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn load_bundle(directory: &Path, locale: LanguageIdentifier) -> Result<FluentBundle<FluentResource>, I18nError> {
+    let mut bundle = FluentBundle::new_concurrent(vec![locale]);
+
+    // unicode isolation marks around placeables would end up in json responses
+    bundle.set_use_isolating(false);
+
+    for path in read_dir(directory)? {
+        if path.extension() != Some(OsStr::new("ftl")) {
+            continue;
+        }
+
+        let source = std::fs::read_to_string(&path).map_err(|source| I18nError::Read {
+            path: path.clone(),
+            source,
+        })?;
+
+        let resource = FluentResource::try_new(source).map_err(|(_, errors)| I18nError::Parse {
+            path: path.clone(),
+            message: format!("{:?}", errors),
+        })?;
+
+        bundle.add_resource(resource).map_err(|errors| I18nError::Parse {
+            path: path.clone(),
+            message: format!("{:?}", errors),
+        })?;
+    }
+
+    Ok(bundle)
+}
+
+fn parse_locale(locale: &str) -> Result<LanguageIdentifier, I18nError> {
+    // tokens often carry posix style locales like `de_DE`
+    locale
+        .replace('_', "-")
+        .parse()
+        .map_err(|_| I18nError::InvalidLocale(locale.to_string()))
+}
+
+/// The locales of the `Accept-Language` header, most preferred first.
+fn accept_language(headers: &HeaderMap) -> Vec<LanguageIdentifier> {
+    let Some(value) = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Vec::new();
+    };
+
+    let mut ranges: Vec<(f32, LanguageIdentifier)> = value
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let tag = params.next()?.trim();
+
+            let quality = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.parse().ok()?,
+                None => 1.0,
+            };
+
+            if tag == "*" || quality <= 0.0 {
+                return None;
+            }
+
+            Some((quality, tag.parse().ok()?))
+        })
+        .collect();
+
+    // the sort is stable, ranges of the same quality keep the order of the header
+    ranges.sort_by(|(lhs, _), (rhs, _)| rhs.total_cmp(lhs));
+
+    ranges.into_iter().map(|(_, locale)| locale).collect()
+}
+
+/// The locale of a request, with the translations to format messages in it. Resolved from
+/// the locale claim of the bearer token or the `Accept-Language` header, see [I18nConfig].
+///
+/// Use like this: `async fn order(locale: Locale) -> Result<Json<Order>, WebError> { Err(locale.error(StatusCode::NOT_FOUND, "order-not-found", None)) }`
+#[derive(Clone)]
+pub struct Locale {
+    id: LanguageIdentifier,
+    translations: Translations,
+}
+
+impl Locale {
+    pub fn id(&self) -> &LanguageIdentifier {
+        &self.id
+    }
+
+    /// Translates the message with the given id, see [Translations::translate].
+    pub fn translate(&self, id: &str, args: Option<&FluentArgs>) -> String {
+        self.translations.translate(&self.id, id, args)
+    }
+
+    /// Translates the message with the given id without arguments.
+    pub fn text(&self, id: &str) -> String {
+        self.translate(id, None)
+    }
+
+    /// An error response with the translated message as its message.
+    pub fn error(&self, status: StatusCode, id: &str, args: Option<&FluentArgs>) -> WebError {
+        WebError::Response(status, self.translate(id, args))
+    }
+}
+
+impl Debug for Locale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Locale").field(&self.id.to_string()).finish()
+    }
+}
+
+impl Display for Locale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.id, f)
+    }
+}
+
+/// Serializes as the language tag, e.g. to pass it as `locale` in a template context.
+impl Serialize for Locale {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.id)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Locale
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(translations) = parts.extensions.get::<Translations>().cloned() else {
+            let message = "Translations are not configured, add Translations::into_layer() to the router";
+            return Err(WebError::Response(StatusCode::INTERNAL_SERVER_ERROR, message.into()));
+        };
+
+        // the locale is resolved once per request
+        let context = RequestContext::of(parts);
+        let resolved = context.locale().and_then(|locale| parse_locale(&locale).ok());
+
+        let id = match resolved {
+            Some(id) => id,
+            None => {
+                let id = translations.request_locale(parts).await;
+                context.set_locale(&id.to_string());
+                id
+            }
+        };
+
+        Ok(Locale { id, translations })
+    }
+}
@@ -0,0 +1,12 @@
+#[derive(Debug, thiserror::Error)]
+pub enum JobError {
+    #[error("failed to serialize job of kind {kind}")]
+    Serialize {
+        kind: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("job queue database error")]
+    Database(#[from] sqlx::Error),
+}
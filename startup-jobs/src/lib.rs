@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use opentelemetry::global;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Executor, PgExecutor, PgPool};
+use tracing::field::Empty;
+use tracing::{info, info_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use crate::error::JobError;
+pub use crate::saga::{start_saga, Saga};
+pub use crate::worker::Worker;
+
+mod error;
+mod saga;
+mod worker;
+
+/// Tables of the queue, created by [install].
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS startup_jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    locked_until TIMESTAMPTZ,
+    last_error TEXT,
+    trace_context JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS startup_jobs_due ON startup_jobs (kind, priority DESC, run_at);
+
+CREATE TABLE IF NOT EXISTS startup_dead_jobs (
+    id BIGINT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    priority INTEGER NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_error TEXT,
+    trace_context JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    failed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+"#;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobsConfig {
+    /// Number of jobs a worker runs at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// Interval to look for due jobs while the queue is empty.
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+
+    /// A job that runs longer than this fails and is retried.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// Delay before the first retry of a failed job. The delay doubles
+    /// with every further attempt, up to `max_retry_backoff_seconds`.
+    #[serde(default = "default_retry_backoff_seconds")]
+    pub retry_backoff_seconds: u64,
+
+    #[serde(default = "default_max_retry_backoff_seconds")]
+    pub max_retry_backoff_seconds: u64,
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1_000
+}
+
+fn default_timeout_seconds() -> u64 {
+    300
+}
+
+fn default_retry_backoff_seconds() -> u64 {
+    10
+}
+
+fn default_max_retry_backoff_seconds() -> u64 {
+    3_600
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: default_concurrency(),
+            poll_interval_ms: default_poll_interval_ms(),
+            timeout_seconds: default_timeout_seconds(),
+            retry_backoff_seconds: default_retry_backoff_seconds(),
+            max_retry_backoff_seconds: default_max_retry_backoff_seconds(),
+        }
+    }
+}
+
+/// A job that can be put into the queue. The payload is stored as json.
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Identifies the type of job in the queue and selects its handler.
+    const KIND: &'static str;
+}
+
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    /// Due jobs with a higher priority run first.
+    pub priority: i32,
+
+    /// Run the job at this time instead of right away.
+    pub run_at: Option<DateTime<Utc>>,
+
+    /// A job that failed this often is moved to the dead jobs.
+    pub max_attempts: i32,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            run_at: None,
+            max_attempts: 10,
+        }
+    }
+}
+
+/// Creates the tables of the queue in the current schema if they do not exist yet.
+pub async fn install(pool: &PgPool) -> Result<(), JobError> {
+    info!("Ensure job queue tables exist");
+    pool.execute(SCHEMA).await?;
+    Ok(())
+}
+
+/// Puts a job into the queue, see [enqueue_with].
+pub async fn enqueue<'c, J: Job>(executor: impl PgExecutor<'c>, job: &J) -> Result<i64, JobError> {
+    enqueue_with(executor, job, &JobOptions::default()).await
+}
+
+/// Puts a job into the queue and returns its id. Pass a transaction to enqueue the job
+/// only if the transaction commits. The job continues the trace of the current span.
+///
+/// Use like this: `startup_jobs::enqueue(&mut tx, &SendMail { to }).await?`
+pub async fn enqueue_with<'c, J: Job>(
+    executor: impl PgExecutor<'c>,
+    job: &J,
+    options: &JobOptions,
+) -> Result<i64, JobError> {
+    let payload = serde_json::to_value(job).map_err(|source| JobError::Serialize { kind: J::KIND, source })?;
+    enqueue_payload(executor, J::KIND, payload, options).await
+}
+
+async fn enqueue_payload<'c>(
+    executor: impl PgExecutor<'c>,
+    kind: &str,
+    payload: serde_json::Value,
+    options: &JobOptions,
+) -> Result<i64, JobError> {
+    let span = info_span!(
+        "job_enqueue",
+        otel.name = %format!("{} enqueue", kind),
+        otel.kind = "producer",
+        otel.status_code = Empty,
+        job.kind = kind,
+        job.id = Empty,
+    );
+
+    // propagate the trace context of the producer span to the worker
+    let mut trace_context = HashMap::new();
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut trace_context));
+
+    let result = sqlx::query_scalar(
+        "INSERT INTO startup_jobs (kind, payload, priority, run_at, max_attempts, trace_context)
+        VALUES ($1, $2, $3, COALESCE($4, now()), $5, $6)
+        RETURNING id",
+    )
+    .bind(kind)
+    .bind(payload)
+    .bind(options.priority)
+    .bind(options.run_at)
+    .bind(options.max_attempts.max(1))
+    .bind(Json(trace_context))
+    .fetch_one(executor)
+    .instrument(span.clone())
+    .await;
+
+    match result {
+        Ok(id) => {
+            span.record("job.id", id);
+            Ok(id)
+        }
+
+        Err(err) => {
+            span.record("otel.status_code", "ERROR");
+            Err(err.into())
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use opentelemetry::metrics::Counter;
+use opentelemetry::{global, KeyValue};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+use startup_base::retry::{Failure, RetryPolicy};
+use tracing::field::Empty;
+use tracing::{info, info_span, warn, Instrument, Span};
+
+use crate::worker::{Outcome, Progress};
+use crate::{JobError, JobOptions};
+
+type Action<C> = Arc<dyn Fn(C) -> BoxFuture<'static, Result<C, String>> + Send + Sync>;
+
+type Compensation<C> = Arc<dyn Fn(C) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+struct Step<C> {
+    name: &'static str,
+    action: Action<C>,
+    compensation: Option<Compensation<C>>,
+}
+
+/// The progress of a saga, stored as the payload of its job.
+#[derive(Serialize, Deserialize)]
+struct State<C> {
+    context: C,
+
+    /// Number of steps that completed and were not compensated yet.
+    completed: usize,
+
+    /// Why the saga failed, set once it compensates the completed steps.
+    #[serde(default)]
+    failure: Option<String>,
+}
+
+/// An operation of several steps that can not share a transaction, like reserving stock,
+/// charging the customer and shipping an order. Every step declares an action that undoes it.
+/// If a step fails, the steps that completed before are compensated in reverse order, so the
+/// saga either completes or is undone as a whole.
+///
+/// Sagas run as jobs of the queue, see [start_saga] and [Worker::saga](crate::Worker::saga).
+/// The context is passed from step to step and stored after every step, so a saga continues
+/// where it stopped if its worker crashed or the job timed out. A step that was interrupted runs
+/// again, so steps and compensations must be idempotent, e.g. by using an id of the context as
+/// idempotency key.
+///
+/// Failed compensations are retried as configured with [Saga::with_retry]. Their errors count as
+/// [Failure::Rejected], which is retried by default. If a compensation still fails, the job fails
+/// and continues compensating when it runs again. Once the job failed `max_attempts` times, it is
+/// moved to `startup_dead_jobs` with the state of the saga, to be resolved by hand.
+///
+/// Every step and compensation is traced as a span of the job. The metric `sagas.runs` counts
+/// the runs of a saga by result, `completed`, `compensated` or `failed`.
+///
+/// Use like this:
+/// ```ignore
+/// let saga = Saga::new("place-order")
+///     .step("reserve", |order: Order| async move { stock.reserve(&order).await.map(|_| order) },
+///         |order| async move { stock.release(&order).await })
+///     .step("charge", |order| async move { payments.charge(&order).await.map(|_| order) },
+///         |order| async move { payments.refund(&order).await })
+///     .step_without_compensation("confirm", |order| async move { mailer.confirm(&order).await.map(|_| order) });
+///
+/// Worker::new(&pool, &config.jobs).saga(saga).run(shutdown).await;
+///
+/// startup_jobs::start_saga(&mut tx, "place-order", &order).await?;
+/// ```
+pub struct Saga<C> {
+    name: &'static str,
+    steps: Vec<Step<C>>,
+    retry: RetryPolicy,
+    runs: Counter<u64>,
+}
+
+impl<C> Saga<C>
+where
+    C: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    /// Creates a saga without steps. The name identifies the saga in the queue.
+    pub fn new(name: &'static str) -> Self {
+        let runs = global::meter("startup-jobs")
+            .u64_counter("sagas.runs")
+            .with_description("Runs of sagas by result, including retries")
+            .init();
+
+        let retry = RetryPolicy {
+            max_attempts: 5,
+            initial_backoff_millis: 1_000,
+            max_backoff_millis: 30_000,
+            retry_on: vec![
+                Failure::Connection,
+                Failure::Timeout,
+                Failure::Throttled,
+                Failure::Unavailable,
+                Failure::Rejected,
+            ],
+            ..RetryPolicy::default()
+        };
+
+        Self {
+            name,
+            steps: Vec::new(),
+            retry,
+            runs,
+        }
+    }
+
+    /// Adds a step that runs the action with the context and passes the context it returns on to
+    /// the next step. The compensation undoes the action with the context the action returned.
+    pub fn step<A, AF, K, KF, E, KE>(mut self, name: &'static str, action: A, compensation: K) -> Self
+    where
+        A: Fn(C) -> AF + Send + Sync + 'static,
+        AF: Future<Output = Result<C, E>> + Send + 'static,
+        K: Fn(C) -> KF + Send + Sync + 'static,
+        KF: Future<Output = Result<(), KE>> + Send + 'static,
+        E: Display,
+        KE: Display,
+    {
+        let compensation: Compensation<C> = Arc::new(move |context| {
+            compensation(context)
+                .map(|result| result.map_err(|err| err.to_string()))
+                .boxed()
+        });
+
+        self.steps.push(Step {
+            name,
+            action: boxed_action(action),
+            compensation: Some(compensation),
+        });
+
+        self
+    }
+
+    /// Adds a step that does not need to be undone, like sending a notification as last step.
+    pub fn step_without_compensation<A, AF, E>(mut self, name: &'static str, action: A) -> Self
+    where
+        A: Fn(C) -> AF + Send + Sync + 'static,
+        AF: Future<Output = Result<C, E>> + Send + 'static,
+        E: Display,
+    {
+        self.steps.push(Step {
+            name,
+            action: boxed_action(action),
+            compensation: None,
+        });
+
+        self
+    }
+
+    /// Retries of failed compensations, by default 5 attempts starting with a backoff of a second.
+    /// Without `rejected` in `retry_on`, a failed compensation fails the job right away.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Runs the saga from the state in the payload of its job.
+    pub(crate) async fn run(&self, progress: Progress, payload: serde_json::Value) -> Outcome {
+        let mut state: State<C> = match serde_json::from_value(payload) {
+            Ok(state) => state,
+            Err(err) => return Outcome::Invalid(err.to_string()),
+        };
+
+        if state.completed > self.steps.len() {
+            let error = format!("saga {} has no step {}", self.name, state.completed);
+            return Outcome::Invalid(error);
+        }
+
+        if state.failure.is_none() {
+            while let Some(step) = self.steps.get(state.completed) {
+                let span = self.span(step, "execute");
+
+                match (step.action)(state.context.clone()).instrument(span.clone()).await {
+                    Ok(context) => {
+                        state.context = context;
+                        state.completed += 1;
+                    }
+
+                    Err(err) => {
+                        span.record("otel.status_code", "ERROR");
+
+                        warn!(
+                            parent: &span,
+                            "Step {} of saga {} failed, compensating {} completed steps: {}",
+                            step.name,
+                            self.name,
+                            state.completed,
+                            err
+                        );
+
+                        state.failure = Some(format!("step {} failed: {}", step.name, err));
+                    }
+                }
+
+                if let Err(err) = self.save(&progress, &state).await {
+                    return err;
+                }
+
+                if state.failure.is_some() {
+                    break;
+                }
+            }
+
+            if state.failure.is_none() {
+                info!("Saga {} of job {} completed", self.name, progress.job_id());
+                self.record("completed");
+                return Outcome::Done;
+            }
+        }
+
+        while let Some(step) = state.completed.checked_sub(1).map(|idx| &self.steps[idx]) {
+            if let Some(compensation) = &step.compensation {
+                let span = self.span(step, "compensate");
+
+                let compensated = self
+                    .compensate(step, compensation, &state.context)
+                    .instrument(span.clone())
+                    .await;
+
+                if let Err(err) = compensated {
+                    span.record("otel.status_code", "ERROR");
+                    self.record("failed");
+                    return Outcome::Failed(format!("failed to compensate step {}: {}", step.name, err));
+                }
+            }
+
+            state.completed -= 1;
+
+            if let Err(err) = self.save(&progress, &state).await {
+                return err;
+            }
+        }
+
+        let failure = state.failure.unwrap_or_default();
+        warn!(
+            "Saga {} of job {} was compensated after {}",
+            self.name,
+            progress.job_id(),
+            failure
+        );
+
+        self.record("compensated");
+        Outcome::Done
+    }
+
+    async fn compensate(&self, step: &Step<C>, compensation: &Compensation<C>, context: &C) -> Result<(), String> {
+        let name = format!("compensate step {} of saga {}", step.name, self.name);
+
+        // compensations do not classify their errors
+        self.retry
+            .run(&name, |_| Failure::Rejected, || compensation(context.clone()))
+            .await
+    }
+
+    async fn save(&self, progress: &Progress, state: &State<C>) -> Result<(), Outcome> {
+        let payload = serde_json::to_value(state).map_err(|err| {
+            self.record("failed");
+            Outcome::Failed(format!("failed to serialize the state of the saga: {}", err))
+        })?;
+
+        progress.save(&payload).await.map_err(|err| {
+            self.record("failed");
+            Outcome::Failed(format!("failed to save the progress of the saga: {}", err))
+        })
+    }
+
+    fn span(&self, step: &Step<C>, phase: &'static str) -> Span {
+        info_span!(
+            "saga_step",
+            otel.name = %format!("{} {} {}", self.name, phase, step.name),
+            otel.status_code = Empty,
+            saga.name = self.name,
+            saga.step = step.name,
+            saga.phase = phase,
+        )
+    }
+
+    fn record(&self, result: &'static str) {
+        let attributes = [KeyValue::new("saga", self.name), KeyValue::new("result", result)];
+        self.runs.add(&opentelemetry::Context::current(), 1, &attributes);
+    }
+}
+
+fn boxed_action<C, A, AF, E>(action: A) -> Action<C>
+where
+    A: Fn(C) -> AF + Send + Sync + 'static,
+    AF: Future<Output = Result<C, E>> + Send + 'static,
+    E: Display,
+{
+    Arc::new(move |context| {
+        action(context)
+            .map(|result| result.map_err(|err| err.to_string()))
+            .boxed()
+    })
+}
+
+/// Starts the saga of the given name with the context, see [Saga]. Pass a transaction to start
+/// the saga only if the transaction commits.
+///
+/// Use like this: `startup_jobs::start_saga(&mut tx, "place-order", &order).await?`
+pub async fn start_saga<'c, C: Serialize>(
+    executor: impl PgExecutor<'c>,
+    name: &'static str,
+    context: &C,
+) -> Result<i64, JobError> {
+    let state = State {
+        context,
+        completed: 0,
+        failure: None,
+    };
+
+    let payload = serde_json::to_value(&state).map_err(|source| JobError::Serialize { kind: name, source })?;
+    crate::enqueue_payload(executor, name, payload, &JobOptions::default()).await
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::types::Json;
+use sqlx::{PgPool, Row};
+use tokio::sync::Semaphore;
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{Job, JobsConfig, Saga};
+
+pub(crate) enum Outcome {
+    Done,
+    Failed(String),
+    Invalid(String),
+}
+
+type Handler = Arc<dyn Fn(Progress, serde_json::Value) -> BoxFuture<'static, Outcome> + Send + Sync>;
+
+/// Stores the progress of a running job in its payload, so the job continues where it stopped
+/// if it runs again.
+pub(crate) struct Progress {
+    pool: PgPool,
+    id: i64,
+}
+
+impl Progress {
+    pub(crate) fn job_id(&self) -> i64 {
+        self.id
+    }
+
+    pub(crate) async fn save(&self, payload: &serde_json::Value) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE startup_jobs SET payload = $2 WHERE id = $1")
+            .bind(self.id)
+            .bind(payload)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A job claimed by this worker.
+struct Claimed {
+    id: i64,
+    kind: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    max_attempts: i32,
+    trace_context: HashMap<String, String>,
+}
+
+/// Runs jobs from the queue with the handlers registered for their kind. Due jobs are claimed
+/// using `FOR UPDATE SKIP LOCKED`, so any number of workers can share a queue. Every job is traced
+/// as a consumer span that continues the trace that enqueued the job.
+///
+/// Jobs run at least once. A claimed job is locked for [JobsConfig::timeout_seconds], so a job of a
+/// crashed worker runs again once its lock expired. A job that fails, panics or times out is retried
+/// with an exponential backoff until it failed `max_attempts` times, then it is moved to the
+/// `startup_dead_jobs` table. Jobs with a payload that can not be deserialized are moved there right away.
+///
+/// The metrics `jobs.runs` and `jobs.duration` record every run per kind and result.
+///
+/// Use like this:
+/// ```ignore
+/// Worker::new(&pool, &config.jobs)
+///     .handle(|job: SendMail| async move { mailer.send(job.to).await })
+///     .run(startup_http::shutdown_requested())
+///     .await;
+/// ```
+pub struct Worker {
+    pool: PgPool,
+    config: JobsConfig,
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl Worker {
+    pub fn new(pool: &PgPool, config: &JobsConfig) -> Self {
+        Self {
+            pool: pool.clone(),
+            config: config.clone(),
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Handles the jobs of kind [Job::KIND].
+    pub fn handle<J, F, Fut, E>(mut self, handler: F) -> Self
+    where
+        J: Job,
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let handler = Arc::new(handler);
+
+        let handler: Handler = Arc::new(move |_progress, payload| {
+            let handler = handler.clone();
+
+            async move {
+                let job = match serde_json::from_value::<J>(payload) {
+                    Ok(job) => job,
+                    Err(err) => return Outcome::Invalid(err.to_string()),
+                };
+
+                match handler(job).await {
+                    Ok(()) => Outcome::Done,
+                    Err(err) => Outcome::Failed(err.to_string()),
+                }
+            }
+            .boxed()
+        });
+
+        self.handlers.insert(J::KIND, handler);
+        self
+    }
+
+    /// Runs the sagas started with [start_saga](crate::start_saga) under the name of the saga.
+    pub fn saga<C>(mut self, saga: Saga<C>) -> Self
+    where
+        C: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        let kind = saga.name();
+        let saga = Arc::new(saga);
+
+        let handler: Handler = Arc::new(move |progress, payload| {
+            let saga = saga.clone();
+            async move { saga.run(progress, payload).await }.boxed()
+        });
+
+        self.handlers.insert(kind, handler);
+        self
+    }
+
+    /// Runs jobs until `shutdown` resolves, e.g. `startup_http::shutdown_requested()`.
+    /// Waits for running jobs to finish before returning.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) {
+        let kinds: Vec<String> = self.handlers.keys().map(|kind| kind.to_string()).collect();
+        let concurrency = self.config.concurrency.max(1);
+        let poll_interval = Duration::from_millis(self.config.poll_interval_ms);
+
+        info!("Running jobs of kinds {:?}", kinds);
+
+        let shared = Arc::new(Shared::new(self.pool, &self.config, self.handlers));
+        let permits = Arc::new(Semaphore::new(concurrency));
+
+        tokio::pin!(shutdown);
+
+        loop {
+            // wait for a free slot before claiming jobs
+            let permit = tokio::select! {
+                _ = &mut shutdown => break,
+                permit = permits.clone().acquire_owned() => permit.expect("semaphore is never closed"),
+            };
+
+            let limit = permits.available_permits() + 1;
+
+            let jobs = match shared.claim(&kinds, limit).await {
+                Ok(jobs) => jobs,
+                Err(err) => {
+                    warn!("Failed to claim jobs: {}", err);
+                    Vec::new()
+                }
+            };
+
+            if jobs.is_empty() {
+                drop(permit);
+
+                tokio::select! {
+                    _ = &mut shutdown => break,
+                    _ = tokio::time::sleep(poll_interval) => continue,
+                }
+            }
+
+            let mut permit = Some(permit);
+
+            for job in jobs {
+                let permit = match permit.take() {
+                    Some(permit) => permit,
+                    None => permits
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed"),
+                };
+
+                let shared = shared.clone();
+
+                tokio::spawn(async move {
+                    shared.run(job).await;
+                    drop(permit);
+                });
+            }
+        }
+
+        info!("Stopping job worker, waiting for running jobs to finish");
+
+        let _ = permits.acquire_many(concurrency as u32).await;
+    }
+}
+
+/// State shared by all running jobs.
+struct Shared {
+    pool: PgPool,
+    handlers: HashMap<&'static str, Handler>,
+    timeout: Duration,
+    retry_backoff: Duration,
+    max_retry_backoff: Duration,
+    runs: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Shared {
+    fn new(pool: PgPool, config: &JobsConfig, handlers: HashMap<&'static str, Handler>) -> Self {
+        let meter = global::meter("startup-jobs");
+
+        let runs = meter
+            .u64_counter("jobs.runs")
+            .with_description("Runs of jobs by result, including retries")
+            .init();
+
+        let duration = meter
+            .f64_histogram("jobs.duration")
+            .with_description("Duration of running a job")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Self {
+            pool,
+            handlers,
+            timeout: Duration::from_secs(config.timeout_seconds),
+            retry_backoff: Duration::from_secs(config.retry_backoff_seconds),
+            max_retry_backoff: Duration::from_secs(config.max_retry_backoff_seconds),
+            runs,
+            duration,
+        }
+    }
+
+    /// Locks up to `limit` due jobs for this worker, highest priority first.
+    async fn claim(&self, kinds: &[String], limit: usize) -> Result<Vec<Claimed>, sqlx::Error> {
+        // keep the lock a little longer than the timeout, so the job can record its failure
+        let lock = self.timeout + Duration::from_secs(30);
+
+        let rows = sqlx::query(
+            "UPDATE startup_jobs
+            SET attempts = attempts + 1, locked_until = now() + make_interval(secs => $3)
+            WHERE id IN (
+                SELECT id FROM startup_jobs
+                WHERE kind = ANY($1) AND run_at <= now() AND (locked_until IS NULL OR locked_until < now())
+                ORDER BY priority DESC, run_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, kind, payload, attempts, max_attempts, trace_context",
+        )
+        .bind(kinds)
+        .bind(limit as i64)
+        .bind(lock.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Claimed {
+                    id: row.try_get("id")?,
+                    kind: row.try_get("kind")?,
+                    payload: row.try_get("payload")?,
+                    attempts: row.try_get("attempts")?,
+                    max_attempts: row.try_get("max_attempts")?,
+                    trace_context: row.try_get::<Json<_>, _>("trace_context")?.0,
+                })
+            })
+            .collect()
+    }
+
+    async fn run(&self, job: Claimed) {
+        let span = info_span!(
+            "job",
+            otel.name = %format!("{} process", job.kind),
+            otel.kind = "consumer",
+            otel.status_code = Empty,
+            job.id = job.id,
+            job.kind = %job.kind,
+            job.attempt = job.attempts,
+        );
+
+        // continue the trace that enqueued the job
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&job.trace_context));
+        span.set_parent(parent);
+
+        let Some(handler) = self.handlers.get(job.kind.as_str()) else {
+            return;
+        };
+
+        let started = Instant::now();
+
+        let progress = Progress {
+            pool: self.pool.clone(),
+            id: job.id,
+        };
+
+        let running = AssertUnwindSafe(handler(progress, job.payload.clone())).catch_unwind();
+
+        let outcome = match tokio::time::timeout(self.timeout, running)
+            .instrument(span.clone())
+            .await
+        {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(_)) => Outcome::Failed("job panicked".to_string()),
+            Err(_) => Outcome::Failed(format!("job timed out after {:?}", self.timeout)),
+        };
+
+        let result = match &outcome {
+            Outcome::Done => "success",
+            Outcome::Failed(_) => "failure",
+            Outcome::Invalid(_) => "invalid",
+        };
+
+        self.record(&job.kind, started.elapsed(), result);
+
+        if let Err(err) = self.finish(&job, outcome, &span).await {
+            warn!(parent: &span, "Failed to update job {}, it will run again: {}", job.id, err);
+        }
+    }
+
+    async fn finish(&self, job: &Claimed, outcome: Outcome, span: &Span) -> Result<(), sqlx::Error> {
+        let error = match outcome {
+            Outcome::Done => {
+                debug!(parent: span, "Job {} of kind {} is done", job.id, job.kind);
+
+                sqlx::query("DELETE FROM startup_jobs WHERE id = $1")
+                    .bind(job.id)
+                    .execute(&self.pool)
+                    .await?;
+
+                return Ok(());
+            }
+
+            Outcome::Invalid(error) => {
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: span, "Job {} of kind {} is invalid: {}", job.id, job.kind, error);
+                return self.bury(job, &error).await;
+            }
+
+            Outcome::Failed(error) => error,
+        };
+
+        span.record("otel.status_code", "ERROR");
+
+        if job.attempts >= job.max_attempts {
+            warn!(parent: span, "Job {} of kind {} failed {} times, giving up: {}", job.id, job.kind, job.attempts, error);
+            return self.bury(job, &error).await;
+        }
+
+        let exponent = (job.attempts - 1).clamp(0, 16) as u32;
+        let backoff = (self.retry_backoff * 2u32.pow(exponent)).min(self.max_retry_backoff);
+
+        warn!(parent: span, "Job {} of kind {} failed, retrying in {:?}: {}", job.id, job.kind, backoff, error);
+
+        sqlx::query(
+            "UPDATE startup_jobs
+            SET run_at = now() + make_interval(secs => $2), locked_until = NULL, last_error = $3
+            WHERE id = $1",
+        )
+        .bind(job.id)
+        .bind(backoff.as_secs_f64())
+        .bind(&error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves the job to the dead jobs table.
+    async fn bury(&self, job: &Claimed, error: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "WITH dead AS (DELETE FROM startup_jobs WHERE id = $1 RETURNING *)
+            INSERT INTO startup_dead_jobs (id, kind, payload, priority, attempts, last_error, trace_context, created_at)
+            SELECT id, kind, payload, priority, attempts, $2, trace_context, created_at FROM dead",
+        )
+        .bind(job.id)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    fn record(&self, kind: &str, elapsed: Duration, result: &'static str) {
+        let context = opentelemetry::Context::current();
+
+        let attributes = [KeyValue::new("kind", kind.to_string()), KeyValue::new("result", result)];
+
+        self.runs.add(&context, 1, &attributes);
+        self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+    }
+}
@@ -1,3 +1,6 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use axum::{
     async_trait,
     extract::{Extension, TypedHeader},
@@ -7,32 +10,132 @@ use headers::{Authorization, authorization::Bearer};
 use http::StatusCode;
 use http::request::Parts;
 use jsonwebtoken::jwk::JwkSet;
-use reqwest::Client;
+use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
-use tracing::{debug, error, warn};
+use serde_json::Value;
+use startup_base::clock::{self, Clock};
+use startup_base::warmup;
+use startup_base::watch::{FileWatch, Watched};
+use startup_client::{CachedDocument, Client};
+use startup_http::{Principal, RequestContext};
+use tracing::{debug, error, info, warn};
 
 use crate::{Error, JwtConfig};
 
+/// Minimum time between fetching the keys again for tokens signed with an unknown key.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub struct JwtAuth {
     validate_expiry_time: bool,
-    jwk_set: JwkSet,
+    jwk_set: Keys,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Clone)]
+enum Keys {
+    /// Read from a file, or fixed.
+    Watched(Watched<JwkSet>),
+
+    /// Fetched from `jwk_url` and refreshed as its `Cache-Control` header allows,
+    /// or if a token was signed with an unknown key.
+    Fetched {
+        document: CachedDocument<JwkSet>,
+        refreshed: Arc<Mutex<Option<Instant>>>,
+    },
+}
+
+impl Keys {
+    async fn get(&self) -> Result<Arc<JwkSet>, Error> {
+        match self {
+            Keys::Watched(keys) => Ok(keys.get()),
+            Keys::Fetched { document, .. } => Ok(document.get().await?),
+        }
+    }
+
+    /// Fetches the keys again, e.g. after they were rotated. At most once per [REFRESH_INTERVAL],
+    /// so tokens with made up key ids do not cause a fetch each. Returns None if not fetched.
+    async fn refresh(&self) -> Result<Option<Arc<JwkSet>>, Error> {
+        let Keys::Fetched { document, refreshed } = self else {
+            return Ok(None);
+        };
+
+        {
+            let mut refreshed = refreshed.lock();
+            if refreshed.is_some_and(|refreshed| refreshed.elapsed() < REFRESH_INTERVAL) {
+                return Ok(None);
+            }
+
+            *refreshed = Some(Instant::now());
+        }
+
+        Ok(Some(document.refresh().await?))
+    }
 }
 
 impl JwtAuth {
     pub async fn new(config: &JwtConfig) -> Result<Self, Error> {
-        Self::new_with_client(config, Client::new()).await
+        Self::with_client(config, Client::new(&config.client_config())?).await
     }
 
+    /// Fetches the keys using the reqwest client, retrying as configured in `jwks_retry`.
+    #[deprecated(note = "use JwtAuth::with_client with a startup_client::Client")]
     pub async fn new_with_client(config: &JwtConfig, client: reqwest::Client) -> Result<Self, Error> {
-        let jwk_set = crate::request_jwk_set(&config.jwk_url, &client).await?;
+        Self::with_client(config, Client::from_reqwest(&config.client_config(), client)?).await
+    }
+
+    /// Fetches the keys from `jwk_url` using the client, retrying as configured in `jwks_retry`.
+    /// The keys are cached as long as the response allows, see [CachedDocument]. The service
+    /// is not ready until the keys were fetched, see [startup_base::warmup].
+    pub async fn with_client(config: &JwtConfig, client: Client) -> Result<Self, Error> {
+        let jwk_set = match &config.jwk_file {
+            Some(path) => {
+                info!("Loading JwkSet from {:?}", path);
+                let jwk_file = path.clone();
+                Keys::Watched(FileWatch::new("jwks", [path]).load(move || crate::read_jwk_set(&jwk_file))?)
+            }
+
+            None => {
+                info!("Loading JwkSet from {:?}", config.jwk_url);
+                let document = CachedDocument::new(&client, &config.jwk_url).retry(config.jwks_retry.clone());
+
+                // the service is not ready until the keys were fetched
+                let fetching = document.clone();
+                warmup::register("jwks", async move {
+                    fetching.get().await?;
+                    Ok::<_, eyre::Report>(())
+                });
+
+                Keys::Fetched {
+                    document,
+                    refreshed: Arc::default(),
+                }
+            }
+        };
+
         let validate_expiry_time = config.validate_expiry_time;
         Ok(Self {
             validate_expiry_time,
             jwk_set,
+            clock: clock::system(),
         })
     }
 
+    /// Creates an instance that validates tokens using the given keys, e.g. in tests.
+    pub fn with_jwk_set(jwk_set: JwkSet, validate_expiry_time: bool) -> Self {
+        Self {
+            validate_expiry_time,
+            jwk_set: Keys::Watched(Watched::fixed(jwk_set)),
+            clock: clock::system(),
+        }
+    }
+
+    /// Validates the expiry of tokens using the clock, e.g. a `ManualClock` in tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn into_layer(self) -> Extension<Self> {
         Extension(self)
     }
@@ -40,6 +143,9 @@ impl JwtAuth {
 
 pub struct Jwt<C: DeserializeOwned>(pub C);
 
+/// Kind of the [Principal] of a request authenticated by [Jwt].
+pub const PRINCIPAL_KIND: &str = "jwt";
+
 #[async_trait]
 impl<S, C> FromRequestParts<S> for Jwt<C>
 where
@@ -50,24 +156,90 @@ where
 
     #[tracing::instrument(name = "parse-jwt", skip_all)]
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let TypedHeader(Authorization(bearer)) = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
-            .await
-            .map_err(|_err| {
+        // the token is verified once per request, later extractions use the verified claims
+        let context = RequestContext::of(parts);
+
+        // a principal of another kind, e.g. of a signed request, holds no verified token
+        if let Some(principal) = context
+            .principal()
+            .filter(|principal| principal.kind() == PRINCIPAL_KIND)
+        {
+            return from_principal(&principal);
+        }
+
+        let token = match TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state).await {
+            Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+
+            Err(_err) => websocket_access_token(parts).ok_or_else(|| {
                 debug!("No 'Authorization' header found");
                 StatusCode::UNAUTHORIZED
-            })?;
+            })?,
+        };
 
         let Extension(auth) = Extension::<JwtAuth>::from_request_parts(parts, state).await.map_err(|_err| {
             error!("No 'JwtAuth' found on request. Did you add the layer?");
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-        match crate::decode::<C>(&auth.jwk_set, bearer.token(), auth.validate_expiry_time) {
-            Ok(claims) => Ok(Jwt(claims)),
+        let jwk_set = auth.jwk_set.get().await.map_err(|err| {
+            error!("Failed to fetch the keys to validate the token: {:?}", err);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?;
+
+        let mut result = crate::decode::<Value>(&jwk_set, &token, auth.validate_expiry_time, auth.clock.now());
+
+        // the keys might have been rotated since they were fetched
+        if matches!(result, Err(Error::KeyNotFound(_))) {
+            match auth.jwk_set.refresh().await {
+                Ok(Some(jwk_set)) => {
+                    result = crate::decode::<Value>(&jwk_set, &token, auth.validate_expiry_time, auth.clock.now());
+                }
+
+                Ok(None) => {}
+                Err(err) => warn!("Failed to fetch the keys again: {:?}", err),
+            }
+        }
+
+        let claims = match result {
+            Ok(claims) => claims,
             Err(err) => {
                 warn!("Token is invalid: {:?}", err);
-                Err(StatusCode::UNAUTHORIZED)
+                return Err(StatusCode::UNAUTHORIZED);
             }
+        };
+
+        let subject = claims.get("sub").and_then(Value::as_str).map(str::to_string);
+        let principal = Principal::new(PRINCIPAL_KIND, subject.as_deref(), claims);
+
+        if context.principal().is_none() {
+            context.set_principal(principal.clone());
         }
+
+        from_principal(&principal)
     }
 }
+
+fn from_principal<C: DeserializeOwned>(principal: &Principal) -> Result<Jwt<C>, StatusCode> {
+    match C::deserialize(principal.claims()) {
+        Ok(claims) => Ok(Jwt(claims)),
+        Err(err) => {
+            warn!("Token is invalid: {:?}", err);
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// Browsers can not set an `Authorization` header on websocket requests, so the token
+/// is accepted in the `access_token` query parameter of an upgrade request, see RFC 6750, section 2.3
+fn websocket_access_token(parts: &Parts) -> Option<String> {
+    let upgrade = parts.headers.get(http::header::UPGRADE)?.to_str().ok()?;
+    if !upgrade.eq_ignore_ascii_case("websocket") {
+        return None;
+    }
+
+    let query = parts.uri.query()?;
+
+    form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "access_token")
+        .map(|(_, value)| value.into_owned())
+}
@@ -1,24 +1,92 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::future::BoxFuture;
 use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::{DecodingKey, Validation};
-use reqwest::Client;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use startup_base::components::Resources;
+use startup_base::retry::RetryPolicy;
+use startup_client::{Client, ClientConfig};
 
-pub use crate::http::{Jwt, JwtAuth};
+pub use crate::http::{Jwt, JwtAuth, PRINCIPAL_KIND};
+pub use crate::policy::{OpaConfig, Policy, PolicyConfig, PolicyEngine, PolicyLayer, PolicyService, AUDIT_TARGET};
 
 mod http;
+mod policy;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JwtConfig {
+    #[serde(default)]
     pub jwk_url: String,
+
+    /// Read the keys from this file instead of `jwk_url`. The keys are reloaded
+    /// when the file changes.
+    #[serde(default)]
+    pub jwk_file: Option<PathBuf>,
+
     pub validate_expiry_time: bool,
+
+    /// Retries of fetching the keys from `jwk_url`.
+    #[serde(default)]
+    pub jwks_retry: RetryPolicy,
+}
+
+impl JwtConfig {
+    /// Creates the [JwtAuth] as component of [Components](startup_base::components::Components),
+    /// and adds it to the resources.
+    ///
+    /// Use like this: `components.add("jwt", &[], config.jwt.component())`
+    pub fn component(&self) -> impl FnOnce(Resources) -> BoxFuture<'static, eyre::Result<()>> + Send + 'static {
+        let config = self.clone();
+
+        move |resources| {
+            Box::pin(async move {
+                resources.insert(JwtAuth::new(&config).await?);
+                Ok(())
+            })
+        }
+    }
+
+    /// Fetches the keys from `jwk_url`, or reads them from `jwk_file`, like [JwtAuth::new],
+    /// e.g. as check of a [Preflight](startup_base::preflight::Preflight).
+    pub async fn preflight(&self) -> Result<(), Error> {
+        match &self.jwk_file {
+            Some(path) => read_jwk_set(path)?,
+            None => Client::new(&self.client_config())?.get(&self.jwk_url).send_json::<JwkSet>().await?,
+        };
+
+        Ok(())
+    }
+
+    /// Config of the client that fetches the keys.
+    pub(crate) fn client_config(&self) -> ClientConfig {
+        ClientConfig {
+            retry: self.jwks_retry.clone(),
+            ..ClientConfig::default()
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("failed to fetch 'jwks.json'")]
+    Fetch(#[from] startup_client::ClientError),
+
+    /// Not returned anymore, fetching the keys fails with [Error::Fetch].
+    #[deprecated(note = "fetching the keys fails with Error::Fetch")]
     #[error("failed to fetch 'jwks.json'")]
     Http(#[from] reqwest::Error),
 
+    #[error("failed to read keys from {0:?}")]
+    ReadJwkFile(PathBuf, #[source] std::io::Error),
+
+    #[error("failed to parse keys from {0:?}")]
+    ParseJwkFile(PathBuf, #[source] serde_json::Error),
+
     #[error("failed to decode jwt header")]
     DecodeHeader(#[source] jsonwebtoken::errors::Error),
 
@@ -41,13 +109,18 @@ pub enum Error {
     DecodeJwt(#[source] jsonwebtoken::errors::Error),
 }
 
-pub(crate) async fn request_jwk_set(url: &str, client: &Client) -> Result<JwkSet, Error> {
-    tracing::info!("Loading JwkSet from {:?}", url);
-    let response = client.get(url).send().await?;
-    Ok(response.json().await?)
+pub(crate) fn read_jwk_set(path: &Path) -> Result<JwkSet, Error> {
+    let content = std::fs::read(path).map_err(|err| Error::ReadJwkFile(path.to_path_buf(), err))?;
+    serde_json::from_slice(&content).map_err(|err| Error::ParseJwkFile(path.to_path_buf(), err))
 }
 
-pub(crate) fn decode<C: DeserializeOwned>(keys: &JwkSet, token: &str, validate_exp: bool) -> Result<C, Error> {
+/// Decodes and validates the token. The expiry is validated against `now`, the time of the clock of [JwtAuth].
+pub(crate) fn decode<C: DeserializeOwned>(
+    keys: &JwkSet,
+    token: &str,
+    validate_exp: bool,
+    now: SystemTime,
+) -> Result<C, Error> {
     // TODO maybe cache decoding keys
     let header = jsonwebtoken::decode_header(token).map_err(Error::DecodeHeader)?;
     let kid = header.kid.ok_or(Error::NoKeyInHeader)?;
@@ -56,14 +129,35 @@ pub(crate) fn decode<C: DeserializeOwned>(keys: &JwkSet, token: &str, validate_e
     let algorithm = key.common.algorithm.ok_or(Error::KeyHasNoAlgorithm)?;
 
     let mut validation = Validation::new(algorithm);
-    validation.validate_exp = validate_exp;
+
+    // jsonwebtoken uses the system time, the expiry is validated below instead
+    validation.validate_exp = false;
 
     let decoding_key = convert_to_decoding_key(key)?;
 
     // decode token
-    let data = jsonwebtoken::decode(token, &decoding_key, &validation).map_err(Error::DecodeJwt)?;
+    let data = jsonwebtoken::decode::<Value>(token, &decoding_key, &validation).map_err(Error::DecodeJwt)?;
+
+    if validate_exp {
+        validate_expiry(&data.claims, now, validation.leeway)?;
+    }
+
+    serde_json::from_value(data.claims).map_err(|err| Error::DecodeJwt(err.into()))
+}
+
+fn validate_expiry(claims: &Value, now: SystemTime, leeway: u64) -> Result<(), Error> {
+    let exp = claims
+        .get("exp")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| Error::DecodeJwt(ErrorKind::MissingRequiredClaim("exp".to_string()).into()))?;
+
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    if exp < now.saturating_sub(leeway) {
+        return Err(Error::DecodeJwt(ErrorKind::ExpiredSignature.into()));
+    }
 
-    Ok(data.claims)
+    Ok(())
 }
 
 fn convert_to_decoding_key(key: &Jwk) -> Result<DecodingKey, Error> {
@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::body::BoxBody;
+use axum::extract::{FromRequestParts, MatchedPath};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use http::request::Parts;
+use http::{Request, StatusCode};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::Jwt;
+
+/// Target of the audit events of authorization decisions, e.g. to route them to an audit log.
+pub const AUDIT_TARGET: &str = "startup_jwt::audit";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyConfig {
+    /// Scopes granted by a role, e.g. `admin: [orders:read, orders:write]`.
+    #[serde(default)]
+    pub roles: BTreeMap<String, Vec<String>>,
+
+    /// Claim with the roles of the subject, a list of strings.
+    #[serde(default = "default_roles_claim")]
+    pub roles_claim: String,
+
+    /// Claim with the scopes of the token, a list or a space separated string.
+    #[serde(default = "default_scopes_claim")]
+    pub scopes_claim: String,
+
+    /// Claim identifying the subject in audit events.
+    #[serde(default = "default_subject_claim")]
+    pub subject_claim: String,
+
+    /// Ask an Open Policy Agent for every request that passed the declared policy.
+    #[serde(default)]
+    pub opa: Option<OpaConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpaConfig {
+    /// Url of the rule to evaluate, like `http://localhost:8181/v1/data/http/authz/allow`.
+    /// The rule is queried with the request and the claims as input and must return a boolean.
+    pub url: String,
+
+    #[serde(default = "default_opa_timeout_millis")]
+    pub timeout_millis: u64,
+}
+
+fn default_roles_claim() -> String {
+    "roles".to_string()
+}
+
+fn default_scopes_claim() -> String {
+    "scope".to_string()
+}
+
+fn default_subject_claim() -> String {
+    "sub".to_string()
+}
+
+fn default_opa_timeout_millis() -> u64 {
+    500
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            roles: BTreeMap::new(),
+            roles_claim: default_roles_claim(),
+            scopes_claim: default_scopes_claim(),
+            subject_claim: default_subject_claim(),
+            opa: None,
+        }
+    }
+}
+
+/// The scopes and roles a route requires. All scopes are required, and if roles are given,
+/// any one of them. Scopes are granted by the token itself or by the roles of the subject.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Policy {
+    scopes: Vec<String>,
+    roles: Vec<String>,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.push(scope.into());
+        self
+    }
+
+    /// Requires any of the roles.
+    pub fn any_role<I: IntoIterator<Item = impl Into<String>>>(mut self, roles: I) -> Self {
+        self.roles.extend(roles.into_iter().map(Into::into));
+        self
+    }
+}
+
+/// Evaluates the [Policy] of routes using the claims of the bearer token, validated by
+/// the [JwtAuth](crate::JwtAuth) layer.
+///
+/// Requests without a valid token are answered with `401 Unauthorized`, requests that do not
+/// satisfy the policy with `403 Forbidden`. If an Open Policy Agent is configured, it is asked
+/// for requests that satisfy the policy, and requests are answered with
+/// `503 Service Unavailable` while it can not be reached.
+///
+/// Every decision is logged as an audit event with the target [AUDIT_TARGET].
+///
+/// Use like this:
+/// ```ignore
+/// let policies = PolicyEngine::new(&config.policy);
+///
+/// Router::new()
+///     .route("/orders", post(create_order).route_layer(policies.require(Policy::new().scope("orders:write"))))
+///     .layer(jwt_auth.into_layer())
+/// ```
+#[derive(Clone)]
+pub struct PolicyEngine {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: PolicyConfig,
+    client: Client,
+}
+
+impl PolicyEngine {
+    pub fn new(config: &PolicyConfig) -> Self {
+        Self::new_with_client(config, Client::new())
+    }
+
+    pub fn new_with_client(config: &PolicyConfig, client: Client) -> Self {
+        let inner = Inner {
+            config: config.clone(),
+            client,
+        };
+
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Creates a layer that enforces the policy. Add it as a route layer, so the
+    /// route of a request is known to the policy agent and in audit events.
+    pub fn require(&self, policy: Policy) -> PolicyLayer {
+        PolicyLayer {
+            engine: self.clone(),
+            policy: Arc::new(policy),
+        }
+    }
+
+    async fn authorize(&self, policy: &Policy, parts: &mut Parts) -> Result<(), StatusCode> {
+        let route = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map_or_else(|| parts.uri.path().to_string(), |path| path.as_str().to_string());
+
+        let claims = match Jwt::<Value>::from_request_parts(parts, &()).await {
+            Ok(Jwt(claims)) => claims,
+            Err(status) => {
+                audit(&parts.method, &route, None, "deny", "no valid token");
+                return Err(status);
+            }
+        };
+
+        let config = &self.inner.config;
+        let subject = claims.get(&config.subject_claim).and_then(Value::as_str);
+
+        let roles = strings(claims.get(&config.roles_claim));
+
+        let mut scopes = strings(claims.get(&config.scopes_claim));
+        for role in &roles {
+            scopes.extend(config.roles.get(role).into_iter().flatten().cloned());
+        }
+
+        if let Some(scope) = policy.scopes.iter().find(|scope| !scopes.contains(*scope)) {
+            let reason = format!("missing scope {}", scope);
+            audit(&parts.method, &route, subject, "deny", &reason);
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        if !policy.roles.is_empty() && !policy.roles.iter().any(|role| roles.contains(role)) {
+            let reason = format!("missing any role of {:?}", policy.roles);
+            audit(&parts.method, &route, subject, "deny", &reason);
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        if let Some(opa) = &config.opa {
+            let input = serde_json::json!({
+                "method": parts.method.as_str(),
+                "path": parts.uri.path(),
+                "route": route,
+                "claims": claims,
+                "policy": policy,
+            });
+
+            match self.ask_opa(opa, input).await {
+                Ok(true) => {}
+
+                Ok(false) => {
+                    audit(&parts.method, &route, subject, "deny", "denied by policy agent");
+                    return Err(StatusCode::FORBIDDEN);
+                }
+
+                Err(err) => {
+                    warn!("Failed to query policy agent: {}", err);
+                    audit(&parts.method, &route, subject, "deny", "policy agent not available");
+                    return Err(StatusCode::SERVICE_UNAVAILABLE);
+                }
+            }
+        }
+
+        audit(&parts.method, &route, subject, "allow", "policy satisfied");
+
+        Ok(())
+    }
+
+    async fn ask_opa(&self, opa: &OpaConfig, input: Value) -> Result<bool, reqwest::Error> {
+        #[derive(Deserialize)]
+        struct Decision {
+            // an undefined rule has no result
+            #[serde(default)]
+            result: Option<bool>,
+        }
+
+        let decision: Decision = self
+            .inner
+            .client
+            .post(&opa.url)
+            .timeout(Duration::from_millis(opa.timeout_millis))
+            .json(&serde_json::json!({ "input": input }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(decision.result == Some(true))
+    }
+}
+
+/// A list of strings or a space separated string, like the `scope` claim of OAuth 2.0.
+fn strings(value: Option<&Value>) -> BTreeSet<String> {
+    match value {
+        Some(Value::String(value)) => value.split_whitespace().map(str::to_string).collect(),
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+fn audit(method: &http::Method, route: &str, subject: Option<&str>, decision: &str, reason: &str) {
+    info!(
+        target: AUDIT_TARGET,
+        method = %method,
+        route,
+        subject,
+        decision,
+        reason,
+        "Authorization decision: {} {} {} for {}",
+        decision,
+        method,
+        route,
+        subject.unwrap_or("anonymous"),
+    );
+}
+
+/// Layer created by [PolicyEngine::require].
+#[derive(Clone)]
+pub struct PolicyLayer {
+    engine: PolicyEngine,
+    policy: Arc<Policy>,
+}
+
+impl<S> tower_layer::Layer<S> for PolicyLayer {
+    type Service = PolicyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PolicyService {
+            inner,
+            engine: self.engine.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+/// Middleware created by [PolicyLayer].
+#[derive(Clone)]
+pub struct PolicyService<S> {
+    inner: S,
+    engine: PolicyEngine,
+    policy: Arc<Policy>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for PolicyService<S>
+where
+    S: tower_service::Service<Request<B>, Response = Response<BoxBody>, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // take the service that was driven to readiness, see tower::Service docs
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let engine = self.engine.clone();
+        let policy = self.policy.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+
+            if let Err(status) = engine.authorize(&policy, &mut parts).await {
+                return Ok(status.into_response());
+            }
+
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
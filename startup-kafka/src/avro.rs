@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use apache_avro::{from_avro_datum, from_value, to_avro_datum, to_value, AvroSchema, Schema};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use startup_client::{Client, ClientConfig, ClientError};
+use url::Url;
+
+#[doc(hidden)]
+pub use apache_avro;
+
+/// First byte of every message in the confluent wire format.
+const MAGIC_BYTE: u8 = 0;
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaRegistryConfig {
+    /// Url of the schema registry, e.g. `http://schema-registry:8081`.
+    pub url: Url,
+
+    /// Username for basic authentication.
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Register unknown schemas when encoding. If disabled, schemas
+    /// must already be registered for the subject.
+    #[serde(default = "default_auto_register")]
+    pub auto_register: bool,
+
+    /// Timeouts and retries of requests to the registry.
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+
+fn default_auto_register() -> bool {
+    true
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AvroError {
+    #[error("request to schema registry failed")]
+    Registry(#[source] ClientError),
+
+    #[error("schema {0} from registry is invalid")]
+    InvalidSchema(u32, #[source] apache_avro::Error),
+
+    #[error("failed to encode value")]
+    Encode(#[source] apache_avro::Error),
+
+    #[error("failed to decode value")]
+    Decode(#[source] apache_avro::Error),
+
+    #[error("message is not in the confluent wire format")]
+    WireFormat,
+}
+
+impl AvroError {
+    /// Returns true if the error is caused by the schema registry and
+    /// the same message might be handled successfully later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, AvroError::Registry(_))
+    }
+}
+
+/// Encodes and decodes avro messages in the confluent wire format: a zero byte, the
+/// id of the writer schema as a big endian `u32` and the avro encoded value. Schemas
+/// are looked up in the schema registry and cached forever, they never change.
+///
+/// Schemas are registered under the subject `<topic>-value`, see [subject].
+///
+/// Use like this:
+/// ```ignore
+/// #[derive(Serialize, Deserialize, AvroSchema)]
+/// struct OrderCreated { id: String }
+///
+/// let payload = avro.encode_typed("orders", &OrderCreated { id }).await?;
+/// let event: OrderCreated = avro.decode_typed(&payload).await?;
+/// ```
+#[derive(Clone)]
+pub struct AvroCodec {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    client: Client,
+    basic_auth: Option<(String, Option<String>)>,
+    auto_register: bool,
+    schemas: Mutex<HashMap<u32, Arc<Schema>>>,
+    ids: Mutex<HashMap<(String, String), u32>>,
+}
+
+#[derive(Serialize)]
+struct SchemaRequest<'a> {
+    schema: &'a str,
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+}
+
+#[derive(Deserialize)]
+struct IdResponse {
+    id: u32,
+}
+
+impl AvroCodec {
+    pub fn new(config: &SchemaRegistryConfig) -> Result<Self, AvroError> {
+        let client_config = ClientConfig {
+            base_url: Some(config.url.clone()),
+            ..config.client.clone()
+        };
+
+        let inner = Inner {
+            client: Client::new(&client_config).map_err(AvroError::Registry)?,
+            basic_auth: config
+                .username
+                .clone()
+                .map(|username| (username, config.password.clone())),
+            auto_register: config.auto_register,
+            schemas: Mutex::new(HashMap::new()),
+            ids: Mutex::new(HashMap::new()),
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Encodes the value with the given schema. The schema is registered
+    /// for the topic, if `auto_register` is enabled.
+    pub async fn encode<T: Serialize>(&self, topic: &str, schema: &Schema, value: &T) -> Result<Vec<u8>, AvroError> {
+        let id = self.schema_id(&subject(topic), schema).await?;
+
+        let value = to_value(value)
+            .and_then(|value| value.resolve(schema))
+            .map_err(AvroError::Encode)?;
+
+        let datum = to_avro_datum(schema, value).map_err(AvroError::Encode)?;
+
+        let mut payload = Vec::with_capacity(datum.len() + 5);
+        payload.push(MAGIC_BYTE);
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&datum);
+
+        Ok(payload)
+    }
+
+    /// Encodes the value using the schema derived from its type.
+    pub async fn encode_typed<T: Serialize + AvroSchema>(&self, topic: &str, value: &T) -> Result<Vec<u8>, AvroError> {
+        self.encode(topic, &T::get_schema(), value).await
+    }
+
+    /// Decodes a message using the writer schema from the registry. If a reader schema is
+    /// given, the value is converted to it using the avro schema resolution rules.
+    pub async fn decode<T: DeserializeOwned>(&self, payload: &[u8], reader: Option<&Schema>) -> Result<T, AvroError> {
+        let (id, mut datum) = match payload {
+            [MAGIC_BYTE, a, b, c, d, datum @ ..] => (u32::from_be_bytes([*a, *b, *c, *d]), datum),
+            _ => return Err(AvroError::WireFormat),
+        };
+
+        let writer = self.schema(id).await?;
+
+        let value = from_avro_datum(&writer, &mut datum, reader).map_err(AvroError::Decode)?;
+
+        from_value(&value).map_err(AvroError::Decode)
+    }
+
+    /// Decodes a message into a type, using the schema derived from the type as reader schema.
+    pub async fn decode_typed<T: DeserializeOwned + AvroSchema>(&self, payload: &[u8]) -> Result<T, AvroError> {
+        self.decode(payload, Some(&T::get_schema())).await
+    }
+
+    /// Fetches the schema with the given id from the registry.
+    pub async fn schema(&self, id: u32) -> Result<Arc<Schema>, AvroError> {
+        if let Some(schema) = self.inner.schemas.lock().get(&id) {
+            return Ok(schema.clone());
+        }
+
+        let response: SchemaResponse = self
+            .request(self.inner.client.get(&format!("/schemas/ids/{}", id)))
+            .route("/schemas/ids/{id}")
+            .send_json()
+            .await
+            .map_err(AvroError::Registry)?;
+
+        let schema = Schema::parse_str(&response.schema).map_err(|err| AvroError::InvalidSchema(id, err))?;
+        let schema = Arc::new(schema);
+
+        self.inner.schemas.lock().insert(id, schema.clone());
+
+        Ok(schema)
+    }
+
+    /// Returns the id of the schema for the subject, registering it if `auto_register` is enabled.
+    pub async fn schema_id(&self, subject: &str, schema: &Schema) -> Result<u32, AvroError> {
+        // keep logical types and defaults, the canonical form drops them
+        let schema_json = serde_json::to_string(schema).expect("schema serializes to json");
+
+        let key = (subject.to_string(), schema_json);
+        if let Some(id) = self.inner.ids.lock().get(&key) {
+            return Ok(*id);
+        }
+
+        let (path, route) = match self.inner.auto_register {
+            true => (
+                format!("/subjects/{}/versions", subject),
+                "/subjects/{subject}/versions",
+            ),
+            false => (format!("/subjects/{}", subject), "/subjects/{subject}"),
+        };
+
+        let response: IdResponse = self
+            .request(self.inner.client.post(&path))
+            .route(route)
+            .json(&SchemaRequest { schema: &key.1 })
+            .send_json()
+            .await
+            .map_err(AvroError::Registry)?;
+
+        self.inner.ids.lock().insert(key, response.id);
+        self.inner.schemas.lock().insert(response.id, Arc::new(schema.clone()));
+
+        Ok(response.id)
+    }
+
+    fn request(&self, request: startup_client::RequestBuilder) -> startup_client::RequestBuilder {
+        let request = request.header("accept", CONTENT_TYPE);
+
+        match &self.inner.basic_auth {
+            Some((username, password)) => request.basic_auth(username, password.as_ref()),
+            None => request,
+        }
+    }
+}
+
+/// The subject the value schemas of a topic are registered under.
+pub fn subject(topic: &str) -> String {
+    format!("{}-value", topic)
+}
@@ -0,0 +1,917 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use futures_util::stream::FuturesUnordered;
+use futures_util::{FutureExt, StreamExt};
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::{global, KeyValue};
+use parking_lot::Mutex;
+use rdkafka::client::ClientContext;
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::consumer::{CommitMode, Consumer as _, ConsumerContext, Rebalance, StreamConsumer};
+use rdkafka::message::{Headers, OwnedHeaders, OwnedMessage};
+use rdkafka::{Message as _, Statistics, TopicPartitionList};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use startup_base::shutdown::priority;
+use startup_base::warmup;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::sync::mpsc::OwnedPermit;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::field::Empty;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+#[cfg(feature = "avro")]
+use crate::avro::AvroCodec;
+use crate::context::LoggingContext;
+use crate::dead_letter::{DeadLetterConfig, DeadLetters, Failure};
+use crate::{KafkaConfig, KafkaError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsumerConfig {
+    /// Consumer group to join. Partitions are distributed between all members of the group.
+    pub group_id: String,
+
+    /// Where to start if the group has no committed offset for a partition.
+    #[serde(default)]
+    pub auto_offset_reset: OffsetReset,
+
+    /// Number of messages of a single partition that are handled at the same time.
+    /// With a value larger than one, messages of a partition are no longer handled in order.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// Members that do not send a heartbeat in time are removed from the group.
+    #[serde(default = "default_session_timeout_ms")]
+    pub session_timeout_ms: u64,
+
+    /// Interval to commit the offsets of handled messages.
+    #[serde(default = "default_commit_interval_ms")]
+    pub commit_interval_ms: u64,
+
+    /// Initial backoff before a failed message is handled again. The backoff doubles
+    /// after every failure, up to `max_retry_backoff_ms`.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+
+    #[serde(default = "default_max_retry_backoff_ms")]
+    pub max_retry_backoff_ms: u64,
+
+    /// Attempts to handle a failed message before it is logged as an error and skipped. If a
+    /// dead-letter topic is configured, the message is published there after its `max_attempts`.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Interval to update the `kafka.consumer.lag` metric.
+    #[serde(default = "default_lag_interval_ms")]
+    pub lag_interval_ms: u64,
+
+    /// Publish messages that can not be handled to a dead-letter topic instead
+    /// of retrying them forever.
+    #[serde(default)]
+    pub dead_letter: Option<DeadLetterConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OffsetReset {
+    /// Start with the oldest message that is still available.
+    #[default]
+    Earliest,
+
+    /// Only consume messages produced after joining the group.
+    Latest,
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+fn default_session_timeout_ms() -> u64 {
+    45_000
+}
+
+fn default_commit_interval_ms() -> u64 {
+    5_000
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_retry_backoff_ms() -> u64 {
+    30_000
+}
+
+fn default_max_attempts() -> u32 {
+    10
+}
+
+fn default_lag_interval_ms() -> u64 {
+    10_000
+}
+
+/// A consumed message with its deserialized payload.
+#[derive(Debug, Clone)]
+pub struct Message<T> {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+
+    /// Milliseconds since the unix epoch, if the message has a timestamp.
+    pub timestamp: Option<i64>,
+
+    /// Headers of the message in the order they were sent.
+    pub headers: Vec<(String, Vec<u8>)>,
+
+    pub payload: T,
+}
+
+impl<T> Message<T> {
+    fn new(message: &OwnedMessage, payload: T) -> Self {
+        Self {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+            key: message.key().map(<[u8]>::to_vec),
+            timestamp: message.timestamp().to_millis(),
+            headers: message
+                .headers()
+                .map(|headers| {
+                    headers
+                        .iter()
+                        .map(|header| (header.key.to_string(), header.value.unwrap_or_default().to_vec()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            payload,
+        }
+    }
+
+    fn with_payload<U>(self, payload: U) -> Message<U> {
+        Message {
+            topic: self.topic,
+            partition: self.partition,
+            offset: self.offset,
+            key: self.key,
+            timestamp: self.timestamp,
+            headers: self.headers,
+            payload,
+        }
+    }
+}
+
+enum Outcome {
+    Handled,
+    Failed(String),
+    Invalid(String),
+}
+
+type Handler = Arc<dyn Fn(&OwnedMessage) -> BoxFuture<'static, Outcome> + Send + Sync>;
+
+/// Consumes messages of a consumer group and passes them to the handler registered for
+/// their topic. Every message is traced as a consumer span that continues the trace of
+/// the producer.
+///
+/// Offsets are committed once a message was handled successfully, so every message is handled
+/// at least once. If a handler fails or panics, the message is handled again with an exponential
+/// backoff, see [ConsumerConfig::retry_backoff_ms], and skipped after
+/// [ConsumerConfig::max_attempts]. Messages that can not be deserialized are logged and skipped.
+///
+/// If a [DeadLetterConfig] is given, messages that failed `max_attempts` times or can not be
+/// deserialized are published to the dead-letter topic together with headers describing the
+/// error, and the consumer continues with the next message. Published messages are counted
+/// in the `kafka.consumer.dead_letters` metric.
+///
+/// Each partition is handled by its own task, with up to [ConsumerConfig::concurrency] messages
+/// at the same time. A partition whose task is busy is paused, so the other partitions are still
+/// consumed while a message is retried.
+///
+/// If partitions are revoked during a rebalance or the consumer shuts down, no new messages of
+/// these partitions are started, but running handlers are allowed to finish. Offsets of messages
+/// that finish after their partition was revoked are not stored, the new owner handles them again.
+///
+/// The metrics `kafka.consumer.messages` and `kafka.consumer.duration` record every handled
+/// message per topic, `kafka.consumer.lag` records the lag of every assigned partition.
+///
+/// Use like this:
+/// ```ignore
+/// Consumer::new(&config.kafka, &config.consumer)
+///     .handle("orders", |order: Message<Order>| async move { process(order.payload).await })
+///     .run(startup_http::shutdown_requested())
+///     .await?;
+/// ```
+pub struct Consumer {
+    kafka: KafkaConfig,
+    config: ConsumerConfig,
+    handlers: HashMap<String, Handler>,
+}
+
+impl Consumer {
+    pub fn new(kafka: &KafkaConfig, config: &ConsumerConfig) -> Self {
+        Self {
+            kafka: kafka.clone(),
+            config: config.clone(),
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Handles the messages of a topic, deserializing their json payload.
+    pub fn handle<T, F, Fut, E>(self, topic: impl Into<String>, handler: F) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let decode = |payload: Vec<u8>| {
+            let result = serde_json::from_slice(&payload).map_err(|err| Outcome::Invalid(err.to_string()));
+            futures_util::future::ready(result).boxed()
+        };
+
+        self.handle_with(topic, decode, handler)
+    }
+
+    /// Handles the messages of a topic with their raw payload.
+    pub fn handle_raw<F, Fut, E>(self, topic: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Message<Vec<u8>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        self.handle_with(topic, |payload| futures_util::future::ok(payload).boxed(), handler)
+    }
+
+    /// Handles the messages of a topic, decoding their avro payload using the schema registry.
+    /// The schema derived from the type is used as reader schema. If the registry can not be
+    /// reached, the message is retried like a failed message.
+    #[cfg(feature = "avro")]
+    pub fn handle_avro<T, F, Fut, E>(self, topic: impl Into<String>, avro: &AvroCodec, handler: F) -> Self
+    where
+        T: DeserializeOwned + apache_avro::AvroSchema + Send + 'static,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let avro = avro.clone();
+
+        let decode = move |payload: Vec<u8>| {
+            let avro = avro.clone();
+
+            async move {
+                avro.decode_typed(&payload).await.map_err(|err| {
+                    let message = error_chain(&err);
+                    match err.is_retryable() {
+                        true => Outcome::Failed(message),
+                        false => Outcome::Invalid(message),
+                    }
+                })
+            }
+            .boxed()
+        };
+
+        self.handle_with(topic, decode, handler)
+    }
+
+    fn handle_with<T, D, F, Fut, E>(mut self, topic: impl Into<String>, decode: D, handler: F) -> Self
+    where
+        T: Send + 'static,
+        D: Fn(Vec<u8>) -> BoxFuture<'static, Result<T, Outcome>> + Send + Sync + 'static,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let handler = Arc::new(handler);
+
+        let handler: Handler = Arc::new(move |message: &OwnedMessage| {
+            let decoded = decode(message.payload().unwrap_or_default().to_vec());
+            let message = Message::new(message, ());
+            let handler = handler.clone();
+
+            async move {
+                let payload = match decoded.await {
+                    Ok(payload) => payload,
+                    Err(outcome) => return outcome,
+                };
+
+                match handler(message.with_payload(payload)).await {
+                    Ok(()) => Outcome::Handled,
+                    Err(err) => Outcome::Failed(err.to_string()),
+                }
+            }
+            .boxed()
+        });
+
+        self.handlers.insert(topic.into(), handler);
+        self
+    }
+
+    /// Consumes messages until `shutdown` resolves, e.g. `startup_http::shutdown_requested()`,
+    /// or the shutdown hooks run, see [startup_base::on_shutdown]. Waits for the handlers of
+    /// running messages to finish and commits their offsets before leaving the consumer group.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), KafkaError> {
+        let hook_stop = CancellationToken::new();
+        let stopped = CancellationToken::new();
+
+        // the hook waits until the offsets are committed, also if the consumer stopped before
+        let _stopped = stopped.clone().drop_guard();
+
+        let name = format!("kafka consumer {}", self.config.group_id);
+        let cancel = hook_stop.clone();
+
+        startup_base::on_shutdown(name, priority::CONSUMERS, move || async move {
+            cancel.cancel();
+            stopped.cancelled().await;
+        });
+
+        let shutdown = async {
+            tokio::select! {
+                _ = shutdown => {},
+                _ = hook_stop.cancelled() => {},
+            }
+        };
+
+        let (revoked_tx, mut revoked_rx) = mpsc::unbounded_channel();
+
+        let context = RunnerContext {
+            revoked: revoked_tx,
+            lag: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        let lag = context.lag.clone();
+
+        let consumer: StreamConsumer<RunnerContext> = self
+            .kafka
+            .consumer_config(&self.config)
+            .create_with_context(context)
+            .map_err(KafkaError::Client)?;
+
+        let consumer = Arc::new(consumer);
+
+        let topics: Vec<_> = self.handlers.keys().cloned().collect();
+        let topic_refs: Vec<_> = topics.iter().map(String::as_str).collect();
+
+        consumer
+            .subscribe(&topic_refs)
+            .map_err(|err| KafkaError::Subscribe(topics.clone(), err))?;
+
+        info!("Consuming topics {:?} in group {}", topics, self.config.group_id);
+
+        // the service is not ready until the brokers know the topics
+        let metadata = (consumer.clone(), topics.clone());
+        warmup::register(&format!("kafka:{}", self.config.group_id), async move {
+            let (consumer, topics) = metadata;
+            tokio::task::spawn_blocking(move || topic_metadata(&consumer, &topics)).await?
+        });
+
+        let dead_letters = match &self.config.dead_letter {
+            Some(config) => Some(DeadLetters::new(&self.kafka, config, &self.config.group_id)?),
+            None => None,
+        };
+
+        let shared = Arc::new(Shared::new(&self.config, lag, dead_letters));
+
+        let stop = CancellationToken::new();
+        let mut workers: HashMap<(String, i32), Worker> = HashMap::new();
+        let mut draining: Vec<JoinHandle<()>> = Vec::new();
+        let mut next_worker = 0;
+
+        // paused partitions waiting for their worker to take the next message
+        let mut waiting = FuturesUnordered::new();
+
+        // the partition whose worker stopped unexpectedly
+        let mut failed = None;
+
+        tokio::pin!(shutdown);
+
+        loop {
+            let message = tokio::select! {
+                biased;
+
+                _ = &mut shutdown => break,
+
+                Some(revoked) = revoked_rx.recv() => {
+                    for partition in revoked {
+                        if let Some(worker) = workers.remove(&partition) {
+                            debug!("Partition {} {} was revoked", partition.0, partition.1);
+                            worker.revoked.cancel();
+                            worker.stop.cancel();
+                            draining.push(worker.task);
+                        }
+                    }
+
+                    continue;
+                }
+
+                Some((partition, id, permit)) = waiting.next() => {
+                    // the partition might have been revoked and assigned again in the meantime
+                    let Some(worker) = workers.get_mut(&partition).filter(|worker| worker.id == id) else {
+                        continue;
+                    };
+
+                    let Ok(permit) = permit else {
+                        failed = Some(partition);
+                        break;
+                    };
+
+                    if let Some(reserve) = worker.unblock(permit, &partition) {
+                        waiting.push(reserve);
+                    } else {
+                        pause(&consumer, &partition, false);
+                    }
+
+                    continue;
+                }
+
+                message = consumer.recv() => message,
+            };
+
+            let message = match message {
+                Ok(message) => message.detach(),
+                Err(err) => {
+                    warn!("Failed to receive message: {}", err);
+                    continue;
+                }
+            };
+
+            let Some(handler) = self.handlers.get(message.topic()) else {
+                continue;
+            };
+
+            let partition = (message.topic().to_string(), message.partition());
+
+            let worker = workers.entry(partition.clone()).or_insert_with(|| {
+                next_worker += 1;
+                Worker::spawn(
+                    next_worker,
+                    consumer.clone(),
+                    handler.clone(),
+                    shared.clone(),
+                    stop.child_token(),
+                )
+            });
+
+            // messages fetched before the partition was paused are kept in order
+            if !worker.pending.is_empty() {
+                worker.pending.push_back(message);
+                continue;
+            }
+
+            match worker.sender.try_send(message) {
+                Ok(()) => {}
+
+                // the worker has too many pending messages, e.g. because it retries a failed one
+                Err(TrySendError::Full(message)) => {
+                    worker.pending.push_back(message);
+                    waiting.push(worker.reserve(&partition));
+                    pause(&consumer, &partition, true);
+                }
+
+                Err(TrySendError::Closed(_)) => {
+                    failed = Some(partition);
+                    break;
+                }
+            }
+        }
+
+        match &failed {
+            Some((topic, partition)) => error!(
+                "Task handling partition {} {} stopped, stopping consumer",
+                topic, partition
+            ),
+            None => info!("Stopping consumer, waiting for running handlers to finish"),
+        }
+
+        stop.cancel();
+
+        let tasks = workers.into_values().map(|worker| worker.task).chain(draining);
+
+        for result in futures_util::future::join_all(tasks).await {
+            if let Err(err) = result {
+                error!("Task handling a partition failed: {}", err);
+            }
+        }
+
+        if let Err(err) = consumer.commit_consumer_state(CommitMode::Sync) {
+            debug!("Failed to commit offsets: {}", err);
+        }
+
+        consumer.unsubscribe();
+
+        match failed {
+            Some((topic, partition)) => Err(KafkaError::Partition { topic, partition }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// State shared by the tasks of all partitions.
+struct Shared {
+    group_id: String,
+    concurrency: usize,
+    retry_backoff: Duration,
+    max_retry_backoff: Duration,
+    max_attempts: u32,
+    messages: Counter<u64>,
+    duration: Histogram<f64>,
+    dead_letters: Option<DeadLetters>,
+}
+
+impl Shared {
+    fn new(
+        config: &ConsumerConfig,
+        lag: Arc<Mutex<HashMap<(String, i32), i64>>>,
+        dead_letters: Option<DeadLetters>,
+    ) -> Self {
+        let meter = global::meter("startup-kafka");
+
+        let messages = meter
+            .u64_counter("kafka.consumer.messages")
+            .with_description("Messages handled by the consumer, including retries")
+            .init();
+
+        let duration = meter
+            .f64_histogram("kafka.consumer.duration")
+            .with_description("Duration of handling a message")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        let gauge = meter
+            .i64_observable_gauge("kafka.consumer.lag")
+            .with_description("Messages of an assigned partition that were not consumed yet")
+            .init();
+
+        let group_id = config.group_id.clone();
+
+        let registered = meter.register_callback(move |cx| {
+            for ((topic, partition), lag) in lag.lock().iter() {
+                let attributes = [
+                    KeyValue::new("group", group_id.clone()),
+                    KeyValue::new("topic", topic.clone()),
+                    KeyValue::new("partition", i64::from(*partition)),
+                ];
+
+                gauge.observe(cx, *lag, &attributes);
+            }
+        });
+
+        if let Err(err) = registered {
+            warn!("Failed to register consumer lag metric: {}", err);
+        }
+
+        Self {
+            group_id: config.group_id.clone(),
+            concurrency: config.concurrency.max(1),
+            retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+            max_retry_backoff: Duration::from_millis(config.max_retry_backoff_ms),
+            max_attempts: config.max_attempts.max(1),
+            messages,
+            duration,
+            dead_letters,
+        }
+    }
+
+    fn record(&self, topic: &str, elapsed: Duration, result: &'static str) {
+        let context = opentelemetry::Context::current();
+
+        let attributes = [
+            KeyValue::new("group", self.group_id.clone()),
+            KeyValue::new("topic", topic.to_string()),
+            KeyValue::new("result", result),
+        ];
+
+        self.messages.add(&context, 1, &attributes);
+        self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+    }
+}
+
+/// The task handling the messages of a single partition.
+struct Worker {
+    /// Tells apart the workers of a partition that was revoked and assigned again.
+    id: u64,
+    sender: mpsc::Sender<OwnedMessage>,
+
+    /// Messages received while the partition is paused because the worker is busy.
+    pending: VecDeque<OwnedMessage>,
+    stop: CancellationToken,
+    revoked: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+/// Resolves with the partition and id of a worker once it can take the next message.
+type Reserve = BoxFuture<'static, ((String, i32), u64, Result<OwnedPermit<OwnedMessage>, SendError<()>>)>;
+
+impl Worker {
+    fn spawn(
+        id: u64,
+        consumer: Arc<StreamConsumer<RunnerContext>>,
+        handler: Handler,
+        shared: Arc<Shared>,
+        stop: CancellationToken,
+    ) -> Self {
+        // keep a few messages ready, so handlers do not wait for the next message
+        let (sender, receiver) = mpsc::channel(shared.concurrency * 2);
+
+        let revoked = CancellationToken::new();
+        let task = tokio::spawn(handle_partition(
+            consumer,
+            handler,
+            shared,
+            receiver,
+            stop.clone(),
+            revoked.clone(),
+        ));
+
+        Self {
+            id,
+            sender,
+            pending: VecDeque::new(),
+            stop,
+            revoked,
+            task,
+        }
+    }
+
+    /// Waits until the worker can take the next message.
+    fn reserve(&self, partition: &(String, i32)) -> Reserve {
+        let partition = partition.clone();
+        let sender = self.sender.clone();
+        let id = self.id;
+
+        async move { (partition, id, sender.reserve_owned().await) }.boxed()
+    }
+
+    /// Passes pending messages to the worker. Returns the future to wait for if not all of them fit.
+    fn unblock(&mut self, permit: OwnedPermit<OwnedMessage>, partition: &(String, i32)) -> Option<Reserve> {
+        if let Some(message) = self.pending.pop_front() {
+            permit.send(message);
+        }
+
+        while let Some(message) = self.pending.pop_front() {
+            if let Err(err) = self.sender.try_send(message) {
+                let (TrySendError::Full(message) | TrySendError::Closed(message)) = err;
+                self.pending.push_front(message);
+                return Some(self.reserve(partition));
+            }
+        }
+
+        None
+    }
+}
+
+/// Pauses or resumes fetching the messages of a partition.
+fn pause(consumer: &StreamConsumer<RunnerContext>, (topic, partition): &(String, i32), pause: bool) {
+    let mut partitions = TopicPartitionList::new();
+    partitions.add_partition(topic, *partition);
+
+    let result = match pause {
+        true => consumer.pause(&partitions),
+        false => consumer.resume(&partitions),
+    };
+
+    match result {
+        Ok(()) if pause => debug!("Paused partition {} {} until its handlers catch up", topic, partition),
+        Ok(()) => debug!("Resumed partition {} {}", topic, partition),
+        Err(err) => warn!("Failed to pause or resume partition {} {}: {}", topic, partition, err),
+    }
+}
+
+/// Fetches the metadata of the topics, failing if a topic does not exist. Blocks until the brokers answered.
+fn topic_metadata(consumer: &StreamConsumer<RunnerContext>, topics: &[String]) -> eyre::Result<()> {
+    for topic in topics {
+        let metadata = consumer.fetch_metadata(Some(topic), Duration::from_secs(10))?;
+
+        if let Some(err) = metadata.topics().iter().find_map(|topic| topic.error()) {
+            eyre::bail!("topic {} is not available: {:?}", topic, err);
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_partition(
+    consumer: Arc<StreamConsumer<RunnerContext>>,
+    handler: Handler,
+    shared: Arc<Shared>,
+    mut receiver: mpsc::Receiver<OwnedMessage>,
+    stop: CancellationToken,
+    revoked: CancellationToken,
+) {
+    let messages = futures_util::stream::poll_fn(|cx| receiver.poll_recv(cx)).take_until(stop.cancelled());
+
+    // results are returned in the order of the messages, so offsets are only
+    // stored once all previous messages of the partition were handled
+    let results = messages
+        .map(|message| handle_message(&handler, &shared, message, &stop))
+        .buffered(shared.concurrency);
+
+    tokio::pin!(results);
+
+    while let Some(result) = results.next().await {
+        let Some((topic, partition, offset)) = result else {
+            // stopped while retrying a failed message, the next owner of the partition handles it again
+            break;
+        };
+
+        // the partition belongs to another member of the group now
+        if revoked.is_cancelled() {
+            continue;
+        }
+
+        if let Err(err) = consumer.store_offset(&topic, partition, offset) {
+            debug!("Failed to store offset {} of {} {}: {}", offset, topic, partition, err);
+        }
+    }
+}
+
+/// Handles a message until it succeeds or was published to the dead-letter topic. Returns
+/// the position of the message or None if the consumer stopped before the message was handled.
+async fn handle_message(
+    handler: &Handler,
+    shared: &Shared,
+    message: OwnedMessage,
+    stop: &CancellationToken,
+) -> Option<(String, i32, i64)> {
+    let topic = message.topic().to_string();
+    let partition = message.partition();
+    let offset = message.offset();
+
+    let span = info_span!(
+        "kafka_consumer",
+        otel.name = %format!("{} process", topic),
+        otel.kind = "consumer",
+        otel.status_code = Empty,
+        messaging.system = "kafka",
+        messaging.source = %topic,
+        messaging.kafka.partition = partition,
+        messaging.kafka.offset = offset,
+    );
+
+    // continue the trace of the producer
+    if let Some(headers) = message.headers() {
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+        span.set_parent(parent);
+    }
+
+    let mut backoff = shared.retry_backoff;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+
+        let started = Instant::now();
+        let outcome = AssertUnwindSafe(handler(&message))
+            .catch_unwind()
+            .instrument(span.clone())
+            .await
+            .unwrap_or_else(|_| Outcome::Failed("handler panicked".to_string()));
+
+        match outcome {
+            Outcome::Handled => {
+                shared.record(&topic, started.elapsed(), "success");
+                return Some((topic, partition, offset));
+            }
+
+            Outcome::Invalid(err) => {
+                shared.record(&topic, started.elapsed(), "invalid");
+                span.record("otel.status_code", "ERROR");
+
+                if let Some(dead_letters) = &shared.dead_letters {
+                    let failure = Failure {
+                        reason: "invalid",
+                        error: &err,
+                        attempts,
+                    };
+
+                    let published = dead_letters.publish(&message, failure, backoff, stop, &span).await;
+                    return published.then_some((topic, partition, offset));
+                }
+
+                warn!(parent: &span, "Skipping invalid message of {} {} at offset {}: {}", topic, partition, offset, err);
+                return Some((topic, partition, offset));
+            }
+
+            Outcome::Failed(err) => {
+                shared.record(&topic, started.elapsed(), "failure");
+                span.record("otel.status_code", "ERROR");
+
+                match &shared.dead_letters {
+                    Some(dead_letters) if attempts >= dead_letters.max_attempts => {
+                        let failure = Failure {
+                            reason: "failure",
+                            error: &err,
+                            attempts,
+                        };
+
+                        let published = dead_letters.publish(&message, failure, backoff, stop, &span).await;
+                        return published.then_some((topic, partition, offset));
+                    }
+
+                    None if attempts >= shared.max_attempts => {
+                        error!(parent: &span, "Skipping message of {} {} at offset {} after {} failed attempts: {}", topic, partition, offset, attempts, err);
+                        return Some((topic, partition, offset));
+                    }
+
+                    _ => {}
+                }
+
+                warn!(parent: &span, "Failed to handle message of {} {} at offset {}, retrying in {:?}: {}", topic, partition, offset, backoff, err);
+            }
+        }
+
+        tokio::select! {
+            _ = stop.cancelled() => return None,
+            _ = tokio::time::sleep(backoff) => {},
+        }
+
+        backoff = (backoff * 2).min(shared.max_retry_backoff);
+    }
+}
+
+/// Tracks rebalances and the lag of assigned partitions.
+struct RunnerContext {
+    revoked: mpsc::UnboundedSender<Vec<(String, i32)>>,
+    lag: Arc<Mutex<HashMap<(String, i32), i64>>>,
+}
+
+impl ClientContext for RunnerContext {
+    fn log(&self, level: RDKafkaLogLevel, facility: &str, message: &str) {
+        LoggingContext.log(level, facility, message)
+    }
+
+    fn error(&self, error: rdkafka::error::KafkaError, reason: &str) {
+        LoggingContext.error(error, reason)
+    }
+
+    fn stats(&self, statistics: Statistics) {
+        let mut lag = self.lag.lock();
+
+        for (name, topic) in statistics.topics {
+            // the internal partition -1 and partitions that are not assigned report a lag of -1
+            for partition in topic.partitions.values() {
+                if partition.partition >= 0 && partition.consumer_lag >= 0 {
+                    lag.insert((name.clone(), partition.partition), partition.consumer_lag);
+                }
+            }
+        }
+    }
+}
+
+impl ConsumerContext for RunnerContext {
+    fn pre_rebalance(&self, rebalance: &Rebalance<'_>) {
+        let Rebalance::Revoke(partitions) = rebalance else {
+            return;
+        };
+
+        let revoked: Vec<_> = partitions
+            .elements()
+            .iter()
+            .map(|element| (element.topic().to_string(), element.partition()))
+            .collect();
+
+        let mut lag = self.lag.lock();
+        for partition in &revoked {
+            lag.remove(partition);
+        }
+
+        let _ = self.revoked.send(revoked);
+    }
+}
+
+struct HeaderExtractor<'a>(&'a OwnedHeaders);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|header| header.key == key)
+            .and_then(|header| std::str::from_utf8(header.value?).ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|header| header.key).collect()
+    }
+}
+
+/// Formats an error with all its causes, e.g. `failed to decode value: invalid utf-8`.
+#[cfg(feature = "avro")]
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+
+    message
+}
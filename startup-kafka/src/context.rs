@@ -0,0 +1,25 @@
+use rdkafka::client::ClientContext;
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::error::KafkaError;
+use tracing::{debug, error, info, warn};
+
+/// Forwards the logs and errors of librdkafka to tracing.
+pub(crate) struct LoggingContext;
+
+impl ClientContext for LoggingContext {
+    fn log(&self, level: RDKafkaLogLevel, facility: &str, message: &str) {
+        match level {
+            RDKafkaLogLevel::Emerg | RDKafkaLogLevel::Alert | RDKafkaLogLevel::Critical | RDKafkaLogLevel::Error => {
+                error!(facility, "librdkafka: {}", message)
+            }
+
+            RDKafkaLogLevel::Warning => warn!(facility, "librdkafka: {}", message),
+            RDKafkaLogLevel::Notice | RDKafkaLogLevel::Info => info!(facility, "librdkafka: {}", message),
+            RDKafkaLogLevel::Debug => debug!(facility, "librdkafka: {}", message),
+        }
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        warn!("Kafka client error: {}: {}", error, reason);
+    }
+}
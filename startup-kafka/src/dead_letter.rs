@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::{global, KeyValue};
+use rdkafka::message::{Header, Headers, OwnedHeaders, OwnedMessage};
+use rdkafka::Message as _;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{warn, Instrument, Span};
+
+use crate::{KafkaConfig, KafkaError, Producer};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterConfig {
+    /// Topic to publish failed messages to. `{topic}` is replaced
+    /// with the topic the message was consumed from.
+    #[serde(default = "default_topic")]
+    pub topic: String,
+
+    /// Attempts to handle a message before it is published to the dead-letter topic.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            topic: default_topic(),
+            max_attempts: default_max_attempts(),
+        }
+    }
+}
+
+fn default_topic() -> String {
+    "{topic}.dlq".to_string()
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+/// Publishes messages that could not be handled to a dead-letter topic.
+pub(crate) struct DeadLetters {
+    producer: Producer,
+    topic: String,
+    pub(crate) max_attempts: u32,
+    group_id: String,
+    published: Counter<u64>,
+}
+
+/// Describes why a message was published to the dead-letter topic.
+pub(crate) struct Failure<'a> {
+    pub(crate) reason: &'static str,
+    pub(crate) error: &'a str,
+    pub(crate) attempts: u32,
+}
+
+impl DeadLetters {
+    pub(crate) fn new(kafka: &KafkaConfig, config: &DeadLetterConfig, group_id: &str) -> Result<Self, KafkaError> {
+        let published = global::meter("startup-kafka")
+            .u64_counter("kafka.consumer.dead_letters")
+            .with_description("Messages published to a dead-letter topic")
+            .init();
+
+        Ok(Self {
+            producer: Producer::new(kafka)?,
+            topic: config.topic.clone(),
+            max_attempts: config.max_attempts.max(1),
+            group_id: group_id.to_string(),
+            published,
+        })
+    }
+
+    /// Publishes the original message with headers describing the failure. Publishing is retried
+    /// until it succeeds. Returns false if the consumer stopped before the message was published.
+    pub(crate) async fn publish(
+        &self,
+        message: &OwnedMessage,
+        failure: Failure<'_>,
+        backoff: Duration,
+        stop: &CancellationToken,
+        span: &Span,
+    ) -> bool {
+        let topic = self.topic.replace("{topic}", message.topic());
+        let headers = headers(message, &self.group_id, &failure);
+
+        loop {
+            let result = self
+                .producer
+                .send_record(
+                    &topic,
+                    message.key(),
+                    message.payload().unwrap_or_default(),
+                    headers.clone(),
+                )
+                .instrument(span.clone())
+                .await;
+
+            match result {
+                Ok(_) => {
+                    let attributes = [
+                        KeyValue::new("group", self.group_id.clone()),
+                        KeyValue::new("topic", message.topic().to_string()),
+                        KeyValue::new("reason", failure.reason),
+                    ];
+
+                    self.published.add(&opentelemetry::Context::current(), 1, &attributes);
+
+                    warn!(parent: span, "Published message of {} {} at offset {} to {} after {} attempts: {}", message.topic(), message.partition(), message.offset(), topic, failure.attempts, failure.error);
+                    return true;
+                }
+
+                Err(err) => {
+                    warn!(parent: span, "Failed to publish message to {}, retrying in {:?}: {}", topic, backoff, err);
+                }
+            }
+
+            tokio::select! {
+                _ = stop.cancelled() => return false,
+                _ = tokio::time::sleep(backoff) => {},
+            }
+        }
+    }
+}
+
+/// Copies the headers of the original message, except for the trace context
+/// that is replaced by the producer, and adds the details of the failure.
+fn headers(message: &OwnedMessage, group_id: &str, failure: &Failure) -> OwnedHeaders {
+    let trace_fields: Vec<String> =
+        global::get_text_map_propagator(|propagator| propagator.fields().map(String::from).collect());
+
+    let mut headers = OwnedHeaders::new();
+
+    if let Some(original) = message.headers() {
+        for header in original.iter() {
+            if !trace_fields.iter().any(|field| field == header.key) {
+                headers = headers.insert(header);
+            }
+        }
+    }
+
+    let details = [
+        ("dlq.topic", message.topic().to_string()),
+        ("dlq.partition", message.partition().to_string()),
+        ("dlq.offset", message.offset().to_string()),
+        ("dlq.group", group_id.to_string()),
+        ("dlq.reason", failure.reason.to_string()),
+        ("dlq.error", failure.error.to_string()),
+        ("dlq.attempts", failure.attempts.to_string()),
+    ];
+
+    for (key, value) in details {
+        headers = headers.insert(Header {
+            key,
+            value: Some(&value),
+        });
+    }
+
+    headers
+}
@@ -0,0 +1,39 @@
+#[derive(Debug, thiserror::Error)]
+pub enum KafkaError {
+    #[error("failed to create kafka client")]
+    Client(#[source] rdkafka::error::KafkaError),
+
+    #[error("failed to fetch the metadata of the cluster")]
+    Metadata(#[source] rdkafka::error::KafkaError),
+
+    #[error("failed to subscribe to topics {0:?}")]
+    Subscribe(Vec<String>, #[source] rdkafka::error::KafkaError),
+
+    #[error("failed to serialize message for topic {topic}")]
+    Serialize {
+        topic: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[cfg(feature = "avro")]
+    #[error("failed to encode avro message for topic {topic}")]
+    Avro {
+        topic: String,
+        #[source]
+        source: crate::avro::AvroError,
+    },
+
+    #[error("failed to send message to topic {topic}")]
+    Send {
+        topic: String,
+        #[source]
+        source: rdkafka::error::KafkaError,
+    },
+
+    #[error("the task handling partition {partition} of {topic} stopped unexpectedly")]
+    Partition { topic: String, partition: i32 },
+
+    #[error("failed to flush queued messages")]
+    Flush(#[source] rdkafka::error::KafkaError),
+}
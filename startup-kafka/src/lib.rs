@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use rdkafka::consumer::{BaseConsumer, Consumer as _};
+use serde::{Deserialize, Serialize};
+use startup_base::components::Resources;
+use startup_base::retry::RetryPolicy;
+use tracing::warn;
+
+pub use crate::consumer::{Consumer, ConsumerConfig, Message, OffsetReset};
+pub use crate::dead_letter::DeadLetterConfig;
+pub use crate::error::KafkaError;
+pub use crate::producer::{Delivery, Producer};
+
+#[cfg(feature = "avro")]
+pub mod avro;
+mod consumer;
+mod context;
+mod dead_letter;
+mod error;
+mod producer;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KafkaConfig {
+    /// Addresses of the bootstrap brokers, e.g. `kafka-1:9092`.
+    pub brokers: Vec<String>,
+
+    /// Identifies this service in the logs and quotas of the brokers.
+    /// Defaults to the name of the service, see [startup_base::service_name].
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+
+    /// Connect to the brokers using tls.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Authenticate using SASL.
+    #[serde(default)]
+    pub sasl: Option<SaslConfig>,
+
+    /// Compression of produced message batches.
+    #[serde(default)]
+    pub compression: Compression,
+
+    /// Acknowledgements the leader needs to receive before a message counts as delivered.
+    #[serde(default)]
+    pub acks: Acks,
+
+    /// Retries of sending a message, done by librdkafka. librdkafka decides which errors are
+    /// retried and randomizes the backoff by itself, so `retry_on` and `jitter` do not apply.
+    #[serde(default = "default_retry")]
+    pub retry: RetryPolicy,
+
+    /// Replaced by `retry.max_attempts`, which counts the first attempt too.
+    /// Still used as `max_attempts - 1` if set.
+    #[deprecated(note = "use retry.max_attempts")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+
+    /// Replaced by `retry.initial_backoff_millis`. Still used instead of it if set.
+    #[deprecated(note = "use retry.initial_backoff_millis")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_backoff_ms: Option<u64>,
+
+    /// Time to wait for a batch to fill up before it is sent.
+    #[serde(default = "default_linger_ms")]
+    pub linger_ms: u64,
+
+    /// Maximum time a message may take to be delivered, including all retries.
+    #[serde(default = "default_message_timeout_ms")]
+    pub message_timeout_ms: u64,
+
+    /// Maximum number of messages waiting to be sent. If the queue is full,
+    /// sending waits for up to `queue_timeout_ms` for space to free up.
+    #[serde(default = "default_queue_max_messages")]
+    pub queue_max_messages: u32,
+
+    /// How long sending waits for space in a full queue before it fails.
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+
+    /// Additional librdkafka properties, see
+    /// <https://github.com/confluentinc/librdkafka/blob/master/CONFIGURATION.md>.
+    /// These take precedence over all other settings.
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate of the certificate authority. Defaults
+    /// to the root certificates of the system.
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+
+    /// Path to the PEM encoded client certificate for mutual tls.
+    #[serde(default)]
+    pub certificate: Option<PathBuf>,
+
+    /// Path to the PEM encoded private key of the client certificate.
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaslConfig {
+    /// One of `PLAIN`, `SCRAM-SHA-256` or `SCRAM-SHA-512`.
+    #[serde(default = "default_sasl_mechanism")]
+    pub mechanism: String,
+
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Snappy,
+    #[default]
+    Lz4,
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Acks {
+    /// Do not wait for the leader, messages might get lost.
+    None,
+
+    /// Wait for the leader to write the message.
+    Leader,
+
+    /// Wait for all in-sync replicas to write the message.
+    #[default]
+    All,
+}
+
+fn default_client_id() -> String {
+    startup_base::service_name()
+}
+
+fn default_retry() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 11,
+        initial_backoff_millis: 100,
+        max_backoff_millis: 1_000,
+        ..RetryPolicy::default()
+    }
+}
+
+/// Largest backoff librdkafka accepts for `retry.backoff.ms` and `retry.backoff.max.ms`.
+const MAX_BACKOFF_MILLIS: u64 = 300_000;
+
+fn default_linger_ms() -> u64 {
+    5
+}
+
+fn default_message_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_queue_max_messages() -> u32 {
+    100_000
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_sasl_mechanism() -> String {
+    "PLAIN".to_string()
+}
+
+impl KafkaConfig {
+    /// Creates the [Producer] as component of [Components](startup_base::components::Components),
+    /// once the brokers answered with the metadata of the cluster, and adds it to the resources.
+    ///
+    /// Use like this: `components.add("kafka", &[], config.kafka.component())`
+    pub fn component(&self) -> impl FnOnce(Resources) -> BoxFuture<'static, eyre::Result<()>> + Send + 'static {
+        let config = self.clone();
+
+        move |resources| {
+            Box::pin(async move {
+                config.preflight().await?;
+                resources.insert(Producer::new(&config)?);
+                Ok(())
+            })
+        }
+    }
+
+    /// Creates the librdkafka configuration for connecting to the brokers.
+    pub fn client_config(&self) -> rdkafka::ClientConfig {
+        startup_base::sbom::register_backend("kafka", "librdkafka");
+
+        let mut config = rdkafka::ClientConfig::new();
+
+        config
+            .set("bootstrap.servers", self.brokers.join(","))
+            .set("client.id", &self.client_id);
+
+        let protocol = match (&self.tls, &self.sasl) {
+            (None, None) => "plaintext",
+            (Some(_), None) => "ssl",
+            (None, Some(_)) => "sasl_plaintext",
+            (Some(_), Some(_)) => "sasl_ssl",
+        };
+
+        config.set("security.protocol", protocol);
+
+        if let Some(tls) = &self.tls {
+            let paths = [
+                ("ssl.ca.location", &tls.ca_certificate),
+                ("ssl.certificate.location", &tls.certificate),
+                ("ssl.key.location", &tls.key),
+            ];
+
+            for (key, path) in paths {
+                if let Some(path) = path {
+                    config.set(key, path.display().to_string());
+                }
+            }
+        }
+
+        if let Some(sasl) = &self.sasl {
+            config
+                .set("sasl.mechanism", &sasl.mechanism)
+                .set("sasl.username", &sasl.username)
+                .set("sasl.password", &sasl.password);
+        }
+
+        self.apply_properties(&mut config);
+
+        config
+    }
+
+    /// Creates the librdkafka configuration for a producer.
+    pub fn producer_config(&self) -> rdkafka::ClientConfig {
+        let mut config = self.client_config();
+
+        let compression = match self.compression {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Snappy => "snappy",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        };
+
+        let acks = match self.acks {
+            Acks::None => "0",
+            Acks::Leader => "1",
+            Acks::All => "all",
+        };
+
+        let retry = self.retry();
+        let retries = retry.max_attempts.saturating_sub(1);
+
+        // librdkafka rejects backoffs above five minutes and a maximum below the initial backoff
+        let initial_backoff = retry.initial_backoff_millis.min(MAX_BACKOFF_MILLIS);
+        let max_backoff = retry.max_backoff_millis.clamp(initial_backoff, MAX_BACKOFF_MILLIS);
+
+        config
+            .set("compression.type", compression)
+            .set("acks", acks)
+            // retries must not reorder or duplicate messages
+            .set("enable.idempotence", (self.acks == Acks::All).to_string())
+            .set("message.send.max.retries", retries.to_string())
+            .set("retry.backoff.ms", initial_backoff.to_string())
+            .set("retry.backoff.max.ms", max_backoff.to_string())
+            .set("linger.ms", self.linger_ms.to_string())
+            .set("message.timeout.ms", self.message_timeout_ms.to_string())
+            .set("queue.buffering.max.messages", self.queue_max_messages.to_string());
+
+        self.apply_properties(&mut config);
+
+        config
+    }
+
+    /// The retry policy with the deprecated `retries` and `retry_backoff_ms` applied.
+    #[allow(deprecated)]
+    fn retry(&self) -> RetryPolicy {
+        let mut retry = self.retry.clone();
+
+        if let Some(retries) = self.retries {
+            warn!("kafka.retries is deprecated, use kafka.retry.max_attempts");
+            retry.max_attempts = retries.saturating_add(1);
+        }
+
+        if let Some(backoff) = self.retry_backoff_ms {
+            warn!("kafka.retry_backoff_ms is deprecated, use kafka.retry.initial_backoff_millis");
+            retry.initial_backoff_millis = backoff;
+        }
+
+        retry
+    }
+
+    /// Creates the librdkafka configuration for a consumer of the given group.
+    pub fn consumer_config(&self, consumer: &ConsumerConfig) -> rdkafka::ClientConfig {
+        let mut config = self.client_config();
+
+        let auto_offset_reset = match consumer.auto_offset_reset {
+            OffsetReset::Earliest => "earliest",
+            OffsetReset::Latest => "latest",
+        };
+
+        config
+            .set("group.id", &consumer.group_id)
+            .set("auto.offset.reset", auto_offset_reset)
+            .set("session.timeout.ms", consumer.session_timeout_ms.to_string())
+            // offsets are stored once a message was handled and committed in the background
+            .set("enable.auto.commit", "true")
+            .set("enable.auto.offset.store", "false")
+            .set("auto.commit.interval.ms", consumer.commit_interval_ms.to_string())
+            .set("statistics.interval.ms", consumer.lag_interval_ms.to_string());
+
+        self.apply_properties(&mut config);
+
+        config
+    }
+
+    /// Fetches the metadata of the cluster from the brokers, e.g. as check of a
+    /// [Preflight](startup_base::preflight::Preflight).
+    pub async fn preflight(&self) -> Result<(), KafkaError> {
+        let consumer: BaseConsumer = self.client_config().create().map_err(KafkaError::Client)?;
+
+        // fetching the metadata blocks until the brokers answered
+        let metadata = tokio::task::spawn_blocking(move || consumer.fetch_metadata(None, Duration::from_secs(10)))
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+
+        metadata.map_err(KafkaError::Metadata)?;
+
+        Ok(())
+    }
+
+    fn apply_properties(&self, config: &mut rdkafka::ClientConfig) {
+        for (key, value) in &self.properties {
+            config.set(key, value);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::propagation::Injector;
+use opentelemetry::{global, KeyValue};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer as _};
+use serde::Serialize;
+use tracing::field::Empty;
+use tracing::{debug, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+#[cfg(feature = "avro")]
+use crate::avro::AvroCodec;
+use crate::context::LoggingContext;
+use crate::{KafkaConfig, KafkaError};
+
+/// Where a message was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// Sends messages to kafka. Every message is traced as a producer span and carries
+/// the trace context in its headers, so consumers can continue the trace.
+///
+/// Failed sends are retried by librdkafka as configured in [KafkaConfig::retry]. If too many
+/// messages are waiting to be sent, sending waits for up to [KafkaConfig::queue_timeout_ms],
+/// slowing down the caller instead of buffering without limit.
+///
+/// The metrics `kafka.producer.messages` and `kafka.producer.duration` record every message
+/// per topic, including the time until the brokers acknowledged it.
+///
+/// Use like this: `producer.send_json("orders", Some(&order.id), &order).await?`
+#[derive(Clone)]
+pub struct Producer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    producer: FutureProducer<LoggingContext>,
+    queue_timeout: Duration,
+    messages: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Producer {
+    pub fn new(config: &KafkaConfig) -> Result<Self, KafkaError> {
+        let producer = config
+            .producer_config()
+            .create_with_context(LoggingContext)
+            .map_err(KafkaError::Client)?;
+
+        let meter = global::meter("startup-kafka");
+
+        let messages = meter
+            .u64_counter("kafka.producer.messages")
+            .with_description("Messages sent to kafka")
+            .init();
+
+        let duration = meter
+            .f64_histogram("kafka.producer.duration")
+            .with_description("Duration until a message was acknowledged by kafka")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        let inner = Inner {
+            producer,
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            messages,
+            duration,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Sends a message and waits until it was acknowledged by the brokers.
+    pub async fn send(&self, topic: &str, key: Option<&str>, payload: &[u8]) -> Result<Delivery, KafkaError> {
+        self.send_record(topic, key.map(str::as_bytes), payload, OwnedHeaders::new())
+            .await
+    }
+
+    /// Sends a message with additional headers, e.g. an idempotency key for the consumers.
+    pub async fn send_with_headers(
+        &self,
+        topic: &str,
+        key: Option<&str>,
+        payload: &[u8],
+        headers: &[(&str, &str)],
+    ) -> Result<Delivery, KafkaError> {
+        let headers = headers.iter().fold(OwnedHeaders::new(), |headers, (key, value)| {
+            headers.insert(Header {
+                key,
+                value: Some(*value),
+            })
+        });
+
+        self.send_record(topic, key.map(str::as_bytes), payload, headers).await
+    }
+
+    /// Sends a message with additional headers.
+    pub(crate) async fn send_record(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        payload: &[u8],
+        headers: OwnedHeaders,
+    ) -> Result<Delivery, KafkaError> {
+        let span = info_span!(
+            "kafka_producer",
+            otel.name = %format!("{} send", topic),
+            otel.kind = "producer",
+            otel.status_code = Empty,
+            messaging.system = "kafka",
+            messaging.destination = %topic,
+            messaging.kafka.partition = Empty,
+            messaging.kafka.offset = Empty,
+        );
+
+        // propagate the trace context of the producer span to the consumers
+        let mut headers = HeaderInjector(headers);
+        let context = span.context();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut headers));
+
+        let mut record = FutureRecord::to(topic).payload(payload).headers(headers.0);
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+
+        let started = Instant::now();
+
+        let result = self
+            .inner
+            .producer
+            .send(record, self.inner.queue_timeout)
+            .instrument(span.clone())
+            .await;
+
+        let result = match result {
+            Ok((partition, offset)) => {
+                span.record("messaging.kafka.partition", partition);
+                span.record("messaging.kafka.offset", offset);
+                debug!(parent: &span, "Sent message to {} partition {} at offset {}", topic, partition, offset);
+                Ok(Delivery { partition, offset })
+            }
+
+            Err((source, _)) => {
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: &span, "Failed to send message to {}: {}", topic, source);
+                Err(KafkaError::Send {
+                    topic: topic.to_string(),
+                    source,
+                })
+            }
+        };
+
+        self.inner.record(topic, started.elapsed(), result.is_ok());
+
+        result
+    }
+
+    /// Serializes the value as json and sends it.
+    pub async fn send_json<T: Serialize>(
+        &self,
+        topic: &str,
+        key: Option<&str>,
+        value: &T,
+    ) -> Result<Delivery, KafkaError> {
+        let payload = serde_json::to_vec(value).map_err(|source| KafkaError::Serialize {
+            topic: topic.to_string(),
+            source,
+        })?;
+
+        self.send(topic, key, &payload).await
+    }
+
+    /// Encodes the value as avro, registering its schema if needed, and sends it.
+    #[cfg(feature = "avro")]
+    pub async fn send_avro<T: Serialize + apache_avro::AvroSchema>(
+        &self,
+        topic: &str,
+        key: Option<&str>,
+        value: &T,
+        avro: &AvroCodec,
+    ) -> Result<Delivery, KafkaError> {
+        let payload = avro
+            .encode_typed(topic, value)
+            .await
+            .map_err(|source| KafkaError::Avro {
+                topic: topic.to_string(),
+                source,
+            })?;
+
+        self.send(topic, key, &payload).await
+    }
+
+    /// Waits until all queued messages are sent. Call this before shutting down.
+    pub fn flush(&self, timeout: Duration) -> Result<(), KafkaError> {
+        self.inner.producer.flush(timeout).map_err(KafkaError::Flush)
+    }
+}
+
+impl Inner {
+    fn record(&self, topic: &str, elapsed: Duration, success: bool) {
+        let context = opentelemetry::Context::current();
+
+        let result = if success { "success" } else { "failure" };
+        let attributes = [
+            KeyValue::new("topic", topic.to_string()),
+            KeyValue::new("result", result),
+        ];
+
+        self.messages.add(&context, 1, &attributes);
+        self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+    }
+}
+
+struct HeaderInjector(OwnedHeaders);
+
+impl Injector for HeaderInjector {
+    fn set(&mut self, key: &str, value: String) {
+        let headers = std::mem::take(&mut self.0);
+        self.0 = headers.insert(Header {
+            key,
+            value: Some(&value),
+        });
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("invalid mail address {address:?}")]
+    Address {
+        address: String,
+        #[source]
+        source: lettre::address::AddressError,
+    },
+
+    #[error("failed to build mail")]
+    Message(#[from] lettre::error::Error),
+
+    #[error("failed to render mail template")]
+    Template(#[from] minijinja::Error),
+
+    #[error("mail template {0} needs a subject and a text or html body")]
+    IncompleteTemplate(String),
+
+    #[error("mail has neither a text nor a html body")]
+    EmptyBody,
+
+    #[error("invalid mail configuration: {0}")]
+    Config(&'static str),
+
+    #[error("invalid dkim private key")]
+    DkimKey(#[source] lettre::message::dkim::DkimSigningKeyError),
+
+    #[error("failed to read secret from {path}")]
+    ReadSecret {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to send mail via smtp")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+
+    #[cfg(feature = "http")]
+    #[error("failed to send mail via http")]
+    Http(#[from] startup_client::ClientError),
+}
+
+impl MailError {
+    /// Returns true if sending the mail again might succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MailError::Smtp(err) => !err.is_permanent() && !err.is_client(),
+
+            #[cfg(feature = "http")]
+            MailError::Http(err) => match err.status() {
+                Some(status) => status.is_server_error() || status.as_u16() == 429,
+                None => !matches!(err, startup_client::ClientError::InvalidUrl(..)),
+            },
+
+            _ => false,
+        }
+    }
+}
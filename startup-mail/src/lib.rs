@@ -0,0 +1,361 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use lettre::message::dkim::{DkimSigningAlgorithm, DkimSigningKey};
+use lettre::message::Mailbox;
+use minijinja::{path_loader, Environment, ErrorKind};
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde::{Deserialize, Serialize};
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+pub use crate::error::MailError;
+pub use crate::message::Mail;
+#[cfg(feature = "http")]
+pub use crate::transport::HttpConfig;
+pub use crate::transport::{SmtpConfig, SmtpTls};
+
+use crate::message::mailbox;
+use crate::transport::{Outgoing, Transport};
+
+mod error;
+mod message;
+mod transport;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// Sender of mails that do not set one, e.g. `Example <noreply@example.com>`.
+    pub from: String,
+
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+
+    /// Send mails using a http api instead of smtp.
+    #[cfg(feature = "http")]
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+
+    /// Log mails instead of sending them. Enabled by default in debug builds.
+    #[serde(default = "default_sandbox")]
+    pub sandbox: bool,
+
+    /// Directory to load the mail templates from, see [Mailer::render].
+    #[serde(default = "default_templates")]
+    pub templates: PathBuf,
+
+    /// Sign mails sent via smtp.
+    #[serde(default)]
+    pub dkim: Option<DkimConfig>,
+
+    /// Attempts to send a mail, including the first one. Only transient
+    /// errors are retried, with an exponential backoff.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DkimConfig {
+    /// The signing domain, e.g. `example.com`.
+    pub domain: String,
+
+    /// Selector of the public key in dns, published at `{selector}._domainkey.{domain}`.
+    pub selector: String,
+
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+
+    /// A PKCS1 pem encoded rsa key or a base64 encoded ed25519 key.
+    #[serde(default)]
+    pub private_key: Option<String>,
+
+    /// Read the private key from a file, e.g. a mounted secret.
+    #[serde(default)]
+    pub private_key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimAlgorithm {
+    #[default]
+    Rsa,
+    Ed25519,
+}
+
+fn default_sandbox() -> bool {
+    cfg!(debug_assertions)
+}
+
+fn default_templates() -> PathBuf {
+    PathBuf::from("templates/mail")
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    1_000
+}
+
+/// Sends mails via smtp or a http api. Every mail is traced as a client span.
+///
+/// In sandbox mode, mails are built and logged but not sent. The metrics `mail.deliveries`
+/// and `mail.duration` record every mail per transport, template and result.
+///
+/// Use like this: `mailer.send_template("welcome", "jane@example.com", json!({ "name": "Jane" })).await?`
+#[derive(Clone)]
+pub struct Mailer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    from: Mailbox,
+    transport: Transport,
+    dkim: Option<lettre::message::dkim::DkimConfig>,
+    templates: Environment<'static>,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    deliveries: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Mailer {
+    pub fn new(config: &MailConfig) -> Result<Self, MailError> {
+        let transport = transport(config)?;
+
+        let dkim = config.dkim.as_ref().map(signer).transpose()?;
+
+        let mut templates = Environment::new();
+        templates.set_loader(path_loader(&config.templates));
+
+        let meter = global::meter("startup-mail");
+
+        let deliveries = meter
+            .u64_counter("mail.deliveries")
+            .with_description("Mails by transport, template and delivery result")
+            .init();
+
+        let duration = meter
+            .f64_histogram("mail.duration")
+            .with_description("Duration of sending a mail, including retries")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        let inner = Inner {
+            from: mailbox(&config.from)?,
+            transport,
+            dkim,
+            templates,
+            max_attempts: config.max_attempts.max(1),
+            retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+            deliveries,
+            duration,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Renders a mail from the templates `{name}.subject.txt`, `{name}.txt` and `{name}.html`
+    /// in the template directory. The subject is required, the text or the html template
+    /// may be missing.
+    pub fn render<T: Serialize>(&self, name: &str, to: impl Into<String>, context: T) -> Result<Mail, MailError> {
+        let context = minijinja::Value::from_serialize(&context);
+
+        let subject = self.render_template(&format!("{}.subject.txt", name), &context)?;
+        let text = self.render_template(&format!("{}.txt", name), &context)?;
+        let html = self.render_template(&format!("{}.html", name), &context)?;
+
+        let Some(subject) = subject else {
+            return Err(MailError::IncompleteTemplate(name.to_string()));
+        };
+
+        if text.is_none() && html.is_none() {
+            return Err(MailError::IncompleteTemplate(name.to_string()));
+        }
+
+        Ok(Mail {
+            subject: subject.trim().to_string(),
+            text,
+            html,
+            template: Some(name.to_string()),
+            ..Mail::new(to)
+        })
+    }
+
+    fn render_template(&self, name: &str, context: &minijinja::Value) -> Result<Option<String>, MailError> {
+        let template = match self.inner.templates.get_template(name) {
+            Ok(template) => template,
+            Err(err) if err.kind() == ErrorKind::TemplateNotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Some(template.render(context)?))
+    }
+
+    /// Renders the mail using [Mailer::render] and sends it.
+    pub async fn send_template<T: Serialize>(
+        &self,
+        name: &str,
+        to: impl Into<String>,
+        context: T,
+    ) -> Result<(), MailError> {
+        let mail = self.render(name, to, context)?;
+        self.send(mail).await
+    }
+
+    /// Sends the mail. Transient errors are retried as configured.
+    pub async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        let from = match &mail.from {
+            Some(address) => mailbox(address)?,
+            None => self.inner.from.clone(),
+        };
+
+        let mut message = mail.to_message(from.clone())?;
+
+        if let Some(dkim) = &self.inner.dkim {
+            message.sign(dkim);
+        }
+
+        let outgoing = Outgoing { mail, from, message };
+
+        let transport = self.inner.transport.name();
+        let template = outgoing.mail.template.as_deref().unwrap_or("none");
+
+        let span = info_span!(
+            "mail",
+            otel.name = "mail send",
+            otel.kind = "client",
+            otel.status_code = Empty,
+            mail.transport = transport,
+            mail.template = template,
+            mail.recipients = outgoing.mail.to.len() + outgoing.mail.cc.len() + outgoing.mail.bcc.len(),
+            mail.attempts = Empty,
+        );
+
+        let started = Instant::now();
+        let result = self.deliver(&outgoing).instrument(span.clone()).await;
+
+        let outcome = match &result {
+            Ok(_) if matches!(self.inner.transport, Transport::Sandbox) => "sandbox",
+            Ok(_) => "sent",
+            Err(err) => {
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: &span, "Failed to send mail {:?} to {:?}: {:?}", outgoing.mail.subject, outgoing.mail.to, err);
+                "failed"
+            }
+        };
+
+        let context = opentelemetry::Context::current();
+        let attributes = [
+            KeyValue::new("transport", transport),
+            KeyValue::new("template", template.to_string()),
+            KeyValue::new("result", outcome),
+        ];
+
+        self.inner.deliveries.add(&context, 1, &attributes);
+        self.inner
+            .duration
+            .record(&context, started.elapsed().as_secs_f64(), &attributes);
+
+        result
+    }
+
+    async fn deliver(&self, outgoing: &Outgoing) -> Result<(), MailError> {
+        if let Transport::Sandbox = self.inner.transport {
+            let mail = &outgoing.mail;
+            info!(
+                "Sandbox mode, not sending mail {:?} from {} to {:?}",
+                mail.subject, outgoing.from, mail.to
+            );
+
+            debug!("Mail body:\n{}", String::from_utf8_lossy(&outgoing.message.formatted()));
+            return Ok(());
+        }
+
+        let mut attempt = 1;
+
+        loop {
+            let result = self.inner.transport.send(outgoing).await;
+
+            match result {
+                Err(err) if err.is_transient() && attempt < self.inner.max_attempts => {
+                    let backoff = self.inner.retry_backoff * 2u32.saturating_pow(attempt - 1);
+                    warn!(
+                        "Attempt {} to send mail failed, retrying in {:?}: {:?}",
+                        attempt, backoff, err
+                    );
+
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+
+                result => {
+                    tracing::Span::current().record("mail.attempts", attempt);
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+fn transport(config: &MailConfig) -> Result<Transport, MailError> {
+    if config.sandbox {
+        return Ok(Transport::Sandbox);
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(http) = &config.http {
+        if config.smtp.is_some() {
+            return Err(MailError::Config("configure either smtp or http, not both"));
+        }
+
+        return Transport::http(http);
+    }
+
+    match &config.smtp {
+        Some(smtp) => Transport::smtp(smtp),
+        None => Err(MailError::Config("no smtp or http transport configured")),
+    }
+}
+
+fn signer(config: &DkimConfig) -> Result<lettre::message::dkim::DkimConfig, MailError> {
+    let Some(private_key) = secret(&config.private_key, &config.private_key_file)? else {
+        return Err(MailError::Config("dkim needs a private_key or private_key_file"));
+    };
+
+    let algorithm = match config.algorithm {
+        DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+    };
+
+    let key = DkimSigningKey::new(private_key.trim(), algorithm).map_err(MailError::DkimKey)?;
+
+    Ok(lettre::message::dkim::DkimConfig::default_config(
+        config.selector.clone(),
+        config.domain.clone(),
+        key,
+    ))
+}
+
+/// Returns the value, or the trimmed content of the file if only a file is given.
+pub(crate) fn secret(value: &Option<String>, file: &Option<PathBuf>) -> Result<Option<String>, MailError> {
+    match (value, file) {
+        (Some(value), _) => Ok(Some(value.clone())),
+        (None, Some(path)) => read_secret(path).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+fn read_secret(path: &Path) -> Result<String, MailError> {
+    let value = std::fs::read_to_string(path).map_err(|source| MailError::ReadSecret {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(value.trim().to_string())
+}
@@ -0,0 +1,114 @@
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use lettre::Message;
+
+use crate::MailError;
+
+/// A mail to send. Addresses may contain a display name, e.g. `Jane Doe <jane@example.com>`.
+///
+/// Use like this: `Mail::new("jane@example.com").subject("Welcome").text("Hello Jane")`
+#[derive(Debug, Clone, Default)]
+pub struct Mail {
+    /// Sender of the mail. Defaults to the configured sender.
+    pub from: Option<String>,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub bcc: Vec<String>,
+    pub reply_to: Option<String>,
+    pub subject: String,
+    pub text: Option<String>,
+    pub html: Option<String>,
+
+    /// Name of the template the mail was rendered from, used in traces and metrics.
+    pub(crate) template: Option<String>,
+}
+
+impl Mail {
+    pub fn new(to: impl Into<String>) -> Self {
+        Self {
+            to: vec![to.into()],
+            ..Self::default()
+        }
+    }
+
+    pub fn from(mut self, from: impl Into<String>) -> Self {
+        self.from = Some(from.into());
+        self
+    }
+
+    pub fn to(mut self, to: impl Into<String>) -> Self {
+        self.to.push(to.into());
+        self
+    }
+
+    pub fn cc(mut self, cc: impl Into<String>) -> Self {
+        self.cc.push(cc.into());
+        self
+    }
+
+    pub fn bcc(mut self, bcc: impl Into<String>) -> Self {
+        self.bcc.push(bcc.into());
+        self
+    }
+
+    pub fn reply_to(mut self, reply_to: impl Into<String>) -> Self {
+        self.reply_to = Some(reply_to.into());
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = subject.into();
+        self
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn html(mut self, html: impl Into<String>) -> Self {
+        self.html = Some(html.into());
+        self
+    }
+
+    /// Builds the mime message sent by `from`.
+    pub(crate) fn to_message(&self, from: Mailbox) -> Result<Message, MailError> {
+        let mut builder = Message::builder().from(from).subject(self.subject.as_str());
+
+        for address in &self.to {
+            builder = builder.to(mailbox(address)?);
+        }
+
+        for address in &self.cc {
+            builder = builder.cc(mailbox(address)?);
+        }
+
+        for address in &self.bcc {
+            builder = builder.bcc(mailbox(address)?);
+        }
+
+        if let Some(address) = &self.reply_to {
+            builder = builder.reply_to(mailbox(address)?);
+        }
+
+        let message = match (&self.text, &self.html) {
+            (Some(text), Some(html)) => {
+                builder.multipart(MultiPart::alternative_plain_html(text.clone(), html.clone()))?
+            }
+
+            (Some(text), None) => builder.singlepart(SinglePart::plain(text.clone()))?,
+
+            (None, Some(html)) => builder.singlepart(SinglePart::html(html.clone()))?,
+
+            (None, None) => return Err(MailError::EmptyBody),
+        };
+
+        Ok(message)
+    }
+}
+
+pub(crate) fn mailbox(address: &str) -> Result<Mailbox, MailError> {
+    address.parse().map_err(|source| MailError::Address {
+        address: address.to_string(),
+        source,
+    })
+}
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+
+use crate::{secret, Mail, MailError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+
+    /// Defaults to `465` for `tls`, `587` for `starttls` and `25` otherwise.
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    #[serde(default)]
+    pub tls: SmtpTls,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Read the password from a file, e.g. a mounted secret.
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade the connection to tls, the connection fails if the server does not support it.
+    #[default]
+    StartTls,
+
+    /// Connect using tls right away.
+    Tls,
+
+    /// Send mails unencrypted, e.g. to a local relay or a test server like mailpit.
+    None,
+}
+
+fn default_timeout_seconds() -> u64 {
+    30
+}
+
+/// Sends mails to a http api that accepts the json format of the SendGrid v3 mail send api.
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Url of the endpoint, e.g. `https://api.sendgrid.com/v3/mail/send`.
+    pub url: url::Url,
+
+    #[serde(default)]
+    pub api_key: Option<String>,
+
+    /// Read the api key from a file, e.g. a mounted secret.
+    #[serde(default)]
+    pub api_key_file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub client: startup_client::ClientConfig,
+}
+
+/// A mail ready to send, with the sender resolved.
+pub(crate) struct Outgoing {
+    pub(crate) mail: Mail,
+    pub(crate) from: Mailbox,
+    pub(crate) message: Message,
+}
+
+pub(crate) enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+
+    #[cfg(feature = "http")]
+    Http(http::HttpTransport),
+
+    /// Logs mails instead of sending them.
+    Sandbox,
+}
+
+impl Transport {
+    pub(crate) fn smtp(config: &SmtpConfig) -> Result<Self, MailError> {
+        let builder = match config.tls {
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+
+        let port = config.port.unwrap_or(match config.tls {
+            SmtpTls::StartTls => 587,
+            SmtpTls::Tls => 465,
+            SmtpTls::None => 25,
+        });
+
+        let mut builder = builder
+            .port(port)
+            .timeout(Some(Duration::from_secs(config.timeout_seconds)));
+
+        if let Some(username) = &config.username {
+            let password = secret(&config.password, &config.password_file)?.unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        Ok(Transport::Smtp(builder.build()))
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn http(config: &HttpConfig) -> Result<Self, MailError> {
+        Ok(Transport::Http(http::HttpTransport::new(config)?))
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Transport::Smtp(_) => "smtp",
+            #[cfg(feature = "http")]
+            Transport::Http(_) => "http",
+            Transport::Sandbox => "sandbox",
+        }
+    }
+
+    /// Sends the mail. The smtp transport sends the signed mime message, the http transport
+    /// sends the parts of the mail, the provider builds and signs the message itself.
+    pub(crate) async fn send(&self, outgoing: &Outgoing) -> Result<(), MailError> {
+        match self {
+            Transport::Smtp(transport) => {
+                let message = &outgoing.message;
+                transport.send_raw(message.envelope(), &message.formatted()).await?;
+
+                Ok(())
+            }
+
+            #[cfg(feature = "http")]
+            Transport::Http(transport) => transport.send(outgoing).await,
+
+            Transport::Sandbox => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+mod http {
+    use lettre::message::Mailbox;
+    use serde::Serialize;
+    use startup_client::Client;
+
+    use crate::message::mailbox;
+    use crate::{secret, MailError};
+
+    use super::{HttpConfig, Outgoing};
+
+    pub(crate) struct HttpTransport {
+        client: Client,
+        url: String,
+        api_key: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct SendRequest<'a> {
+        personalizations: [Personalization<'a>; 1],
+        from: Address<'a>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reply_to: Option<Address<'a>>,
+
+        subject: &'a str,
+        content: Vec<Content<'a>>,
+    }
+
+    #[derive(Serialize)]
+    struct Personalization<'a> {
+        to: Vec<Address<'a>>,
+
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        cc: Vec<Address<'a>>,
+
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        bcc: Vec<Address<'a>>,
+    }
+
+    #[derive(Serialize)]
+    struct Address<'a> {
+        email: String,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<&'a str>,
+    }
+
+    #[derive(Serialize)]
+    struct Content<'a> {
+        #[serde(rename = "type")]
+        content_type: &'static str,
+        value: &'a str,
+    }
+
+    impl HttpTransport {
+        pub(crate) fn new(config: &HttpConfig) -> Result<Self, MailError> {
+            Ok(Self {
+                client: Client::new(&config.client)?,
+                url: config.url.to_string(),
+                api_key: secret(&config.api_key, &config.api_key_file)?,
+            })
+        }
+
+        pub(crate) async fn send(&self, outgoing: &Outgoing) -> Result<(), MailError> {
+            let mail = &outgoing.mail;
+
+            let to = mailboxes(&mail.to)?;
+            let cc = mailboxes(&mail.cc)?;
+            let bcc = mailboxes(&mail.bcc)?;
+            let reply_to = mail.reply_to.as_deref().map(mailbox).transpose()?;
+
+            let body = SendRequest {
+                personalizations: [Personalization {
+                    to: to.iter().map(address).collect(),
+                    cc: cc.iter().map(address).collect(),
+                    bcc: bcc.iter().map(address).collect(),
+                }],
+                from: address(&outgoing.from),
+                reply_to: reply_to.as_ref().map(address),
+                subject: &mail.subject,
+                content: mail
+                    .text
+                    .iter()
+                    .map(|text| Content {
+                        content_type: "text/plain",
+                        value: text,
+                    })
+                    .chain(mail.html.iter().map(|html| Content {
+                        content_type: "text/html",
+                        value: html,
+                    }))
+                    .collect(),
+            };
+
+            let mut request = self.client.post(&self.url).route("mail/send").json(&body);
+
+            if let Some(api_key) = &self.api_key {
+                request = request.bearer_auth(api_key);
+            }
+
+            request.send().await?;
+
+            Ok(())
+        }
+    }
+
+    fn mailboxes(addresses: &[String]) -> Result<Vec<Mailbox>, MailError> {
+        addresses.iter().map(|address| mailbox(address)).collect()
+    }
+
+    fn address(mailbox: &Mailbox) -> Address<'_> {
+        Address {
+            email: mailbox.email.to_string(),
+            name: mailbox.name.as_deref(),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::future::Future;
+use std::process::ExitCode;
+use std::time::Instant;
+
+use opentelemetry::KeyValue;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use startup_base::DefaultConfig;
+
+use crate::MonitoringConfig;
+
+/// Exit code of a job that failed or panicked.
+const EXIT_FAILED: u8 = 1;
+
+/// Exit code of a job with an invalid config, `EX_CONFIG` of sysexits.
+const EXIT_CONFIG: u8 = 78;
+
+/// Exit code of a job that was stopped by SIGINT or SIGTERM, as the shell reports it.
+const EXIT_INTERRUPTED: u8 = 128 + 15;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Succeeded,
+    Failed,
+    Interrupted,
+    InvalidConfig,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Succeeded => "succeeded",
+            Status::Failed => "failed",
+            Status::Interrupted => "interrupted",
+            Status::InvalidConfig => "invalid_config",
+        }
+    }
+}
+
+/// Printed to stdout as a single line of json once the job finished.
+#[derive(Serialize)]
+struct Summary {
+    job: String,
+    status: Status,
+    exit_code: u8,
+    duration_seconds: f64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+}
+
+/// Runs a short-lived job, like a Kubernetes cron job or a worker that processes a batch,
+/// instead of a service. No server is started.
+///
+/// The config is read like in [startup_base::init], which also sets up logging. If the config has
+/// a `monitoring` section, tracing and metrics are set up from it, see [MonitoringConfig]. Jobs
+/// can not be scraped, so metrics should be pushed using `metrics.otlp` and the `otlp` feature.
+///
+/// Once the job returned, the [shutdown hooks](startup_base::on_shutdown) run, which export the
+/// remaining spans and metrics. A summary with the status, the duration, the error or the result
+/// of the job is printed to stdout as json, and the run is counted in the `job.runs` metric.
+///
+/// Returns the exit code for `main`: `0` if the job succeeded, `1` if it failed or panicked,
+/// `78` if the config is invalid and `143` if the job was stopped by SIGINT or SIGTERM. A stopped
+/// job is dropped at its next await point.
+///
+/// Use like this:
+/// ```ignore
+/// #[tokio::main]
+/// async fn main() -> ExitCode {
+///     startup_monitoring::run_job!("config.yaml", |config: Config| async move { cleanup(config).await })
+/// }
+/// ```
+pub async fn run_job<'a, C, F, Fut, T>(name: &str, config: impl Into<DefaultConfig<'a>>, job: F) -> ExitCode
+where
+    C: Default + Serialize + DeserializeOwned,
+    F: FnOnce(C) -> Fut,
+    Fut: Future<Output = eyre::Result<T>> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    let started = Instant::now();
+
+    let (status, error, result) = match setup::<C>(name, config.into()) {
+        Ok(config) => run(job(config)).await,
+        Err(err) => (Status::InvalidConfig, Some(format!("{:#}", err)), None),
+    };
+
+    let exit_code = match status {
+        Status::Succeeded => 0,
+        Status::Failed => EXIT_FAILED,
+        Status::Interrupted => EXIT_INTERRUPTED,
+        Status::InvalidConfig => EXIT_CONFIG,
+    };
+
+    let summary = Summary {
+        job: name.to_string(),
+        status,
+        exit_code,
+        duration_seconds: started.elapsed().as_secs_f64(),
+        error,
+        result,
+    };
+
+    match &summary.error {
+        None => tracing::info!("Job {} finished after {:.3}s", name, summary.duration_seconds),
+        Some(err) => tracing::error!("Job {} failed after {:.3}s: {}", name, summary.duration_seconds, err),
+    }
+
+    let attributes = [
+        KeyValue::new("job", name.to_string()),
+        KeyValue::new("status", status.as_str()),
+    ];
+
+    opentelemetry::global::meter("startup-monitoring")
+        .u64_counter("job.runs")
+        .with_description("Runs of short-lived jobs by status")
+        .init()
+        .add(&opentelemetry::Context::current(), 1, &attributes);
+
+    // export the remaining spans and metrics
+    startup_base::shutdown::run_hooks().await;
+
+    match serde_json::to_string(&summary) {
+        Ok(summary) => println!("{}", summary),
+        Err(err) => tracing::warn!("Failed to serialize job summary: {}", err),
+    }
+
+    ExitCode::from(exit_code)
+}
+
+/// Reads the config and sets up logging, tracing and metrics.
+fn setup<C>(name: &str, config: DefaultConfig) -> eyre::Result<C>
+where
+    C: Default + Serialize + DeserializeOwned,
+{
+    let app_config = startup_base::init::<C>(name, config)?;
+
+    let figment = startup_base::figment(config);
+
+    if figment.contains("monitoring") {
+        let monitoring: MonitoringConfig = figment.extract_inner("monitoring")?;
+        monitoring.setup()?;
+    }
+
+    Ok(app_config)
+}
+
+async fn run<Fut, T>(job: Fut) -> (Status, Option<String>, Option<serde_json::Value>)
+where
+    Fut: Future<Output = eyre::Result<T>> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    // a panic of the job fails the job instead of the process
+    let mut handle = tokio::spawn(job);
+
+    let outcome = tokio::select! {
+        outcome = &mut handle => outcome,
+
+        _ = interrupted() => {
+            handle.abort();
+            return (Status::Interrupted, Some("stopped by signal".to_string()), None);
+        }
+    };
+
+    match outcome {
+        Ok(Ok(result)) => {
+            let result = serde_json::to_value(result).ok().filter(|result| !result.is_null());
+            (Status::Succeeded, None, result)
+        }
+
+        Ok(Err(err)) => (Status::Failed, Some(format!("{:#}", err)), None),
+        Err(err) if err.is_panic() => (Status::Failed, Some("job panicked".to_string()), None),
+        Err(err) => (Status::Failed, Some(err.to_string()), None),
+    }
+}
+
+async fn interrupted() {
+    startup_base::shutdown::signal().await;
+    tracing::warn!("Received signal, stopping the job");
+}
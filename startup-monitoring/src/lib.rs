@@ -1,20 +1,52 @@
 use eyre::Result;
 use opentelemetry::sdk::trace;
 use serde::{Deserialize, Serialize};
+use startup_base::shutdown::priority;
+
+use crate::metrics::MetricsConfig;
 
 mod idgenerator;
+mod job;
+pub mod metrics;
+#[cfg(feature = "otlp")]
+mod otlp;
+
+pub use job::run_job;
+
+#[doc(hidden)]
+pub use startup_base;
+
+/// Runs a short-lived job with the config of the file, see [run_job].
+#[macro_export]
+macro_rules! run_job {
+    ( $name:expr, $job:expr ) => {
+        $crate::run_job(
+            env!("CARGO_PKG_NAME"),
+            $crate::startup_base::DefaultConfig::from_path($name, include_str!($name)),
+            $job,
+        )
+    };
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MonitoringConfig {
     pub zipkin: Option<String>,
+
+    /// Name of the service in traces and pushed metrics.
     pub zipkin_service_name: String,
+
+    #[serde(default)]
+    pub metrics: MetricsConfig,
     // statsd: HostPort,
 }
 
 impl MonitoringConfig {
     pub fn setup(&self) -> Result<()> {
+        self.metrics.setup(&self.zipkin_service_name)?;
+
         if let Some(zipkin) = self.zipkin.as_ref() {
             tracing::info!("Setup zipkin tracing to {}", zipkin);
+            startup_base::sbom::register_backend("tracing", "zipkin");
 
             opentelemetry::global::set_text_map_propagator(opentelemetry_zipkin::Propagator::new());
 
@@ -31,10 +63,18 @@ impl MonitoringConfig {
             // inject layer into registry
             let layer = tracing_opentelemetry::layer().with_tracer(tracer);
             startup_base::replace_tracing_layer(Some(Box::new(layer)))?;
+
+            // export the spans that are still buffered
+            startup_base::on_shutdown("tracing", priority::TELEMETRY, || async {
+                let flushed = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+
+                if let Err(err) = flushed {
+                    tracing::warn!("Failed to flush spans: {}", err);
+                }
+            });
         }
 
         Ok(())
     }
 }
 
-
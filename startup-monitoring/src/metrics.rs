@@ -0,0 +1,412 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use opentelemetry::sdk::export::metrics::aggregation::{
+    constant_temporality_selector, cumulative_temporality_selector, Histogram, LastValue, Sum, Temporality,
+};
+use opentelemetry::sdk::export::metrics::{AggregatorSelector, InstrumentationLibraryReader};
+use opentelemetry::sdk::metrics::aggregators::{
+    self, Aggregator, HistogramAggregator, LastValueAggregator, SumAggregator,
+};
+use opentelemetry::sdk::metrics::controllers::{self, BasicController};
+use opentelemetry::sdk::metrics::processors;
+use opentelemetry::sdk::metrics::sdk_api::{Descriptor, InstrumentKind};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Content type of the metrics rendered by [render].
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+lazy_static::lazy_static! {
+    static ref CONTROLLER: Mutex<Option<BasicController>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Collect the metrics recorded by all components, to serve them using [render].
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Upper bounds of the buckets of histograms without configured buckets, in seconds.
+    /// The default buckets are dense between 100ms and 1s, where most latency objectives are.
+    #[serde(default = "default_buckets")]
+    pub default_buckets: Vec<f64>,
+
+    /// Upper bounds of the buckets of histograms by metric family, the name of a metric or
+    /// a prefix of its name like `http.server`. The longest matching family wins.
+    #[serde(default)]
+    pub buckets: BTreeMap<String, Vec<f64>>,
+
+    /// Push the metrics to an OpenTelemetry collector, for services that can not be scraped,
+    /// like batch jobs and short-lived workers. Requires `enabled`.
+    #[serde(default)]
+    pub otlp: Option<OtlpMetricsConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtlpMetricsConfig {
+    /// OTLP/gRPC endpoint of the collector, like `http://otel-collector:4317`.
+    pub endpoint: String,
+
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// Time between two pushes. The last push happens on shutdown.
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+
+    /// With delta temporality, [render] is not available, every collection only sees what was
+    /// recorded since the previous one.
+    #[serde(default)]
+    pub temporality: OtlpTemporality,
+}
+
+fn default_interval_seconds() -> u64 {
+    60
+}
+
+fn default_timeout_seconds() -> u64 {
+    10
+}
+
+/// Whether pushed counters and histograms contain everything since the start of the service,
+/// or only what was recorded since the last push, as some backends require.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OtlpTemporality {
+    #[default]
+    Cumulative,
+    Delta,
+}
+
+impl From<OtlpTemporality> for Temporality {
+    fn from(temporality: OtlpTemporality) -> Self {
+        match temporality {
+            OtlpTemporality::Cumulative => Temporality::Cumulative,
+            OtlpTemporality::Delta => Temporality::Delta,
+        }
+    }
+}
+
+fn default_buckets() -> Vec<f64> {
+    vec![
+        0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.15, 0.2, 0.25, 0.3, 0.4, 0.5, 0.75, 1.0, 1.5, 2.5, 5.0, 10.0,
+    ]
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_buckets: default_buckets(),
+            buckets: BTreeMap::new(),
+            otlp: None,
+        }
+    }
+}
+
+impl MetricsConfig {
+    /// Installs the global meter provider. Instruments created before are not collected,
+    /// so call this before the other components are set up. With `otlp`, the metrics are
+    /// pushed to the collector, labeled with the name of the service.
+    #[cfg_attr(not(feature = "otlp"), allow(unused_variables))]
+    pub fn setup(&self, service_name: &str) -> Result<()> {
+        if !self.enabled {
+            if self.otlp.is_some() {
+                return Err(eyre!("pushing metrics with metrics.otlp requires metrics.enabled"));
+            }
+
+            return Ok(());
+        }
+
+        let selector = BucketSelector::new(self)?;
+
+        let temporality = match &self.otlp {
+            Some(otlp) => otlp.temporality.into(),
+            None => Temporality::Cumulative,
+        };
+
+        // cumulative values include instruments that were not updated since the last collection
+        let processor = processors::factory(selector, constant_temporality_selector(temporality))
+            .with_memory(temporality == Temporality::Cumulative);
+
+        let builder = controllers::basic(processor);
+
+        let controller = match &self.otlp {
+            #[cfg(feature = "otlp")]
+            Some(otlp) => crate::otlp::push(builder, otlp, service_name)?,
+
+            #[cfg(not(feature = "otlp"))]
+            Some(_) => return Err(eyre!("pushing metrics with metrics.otlp requires the otlp feature")),
+
+            None => {
+                tracing::info!("Collecting metrics");
+                startup_base::sbom::register_backend("metrics", "openmetrics");
+
+                // collect on every scrape
+                builder.with_collect_period(Duration::ZERO).build()
+            }
+        };
+
+        opentelemetry::global::set_meter_provider(controller.clone());
+
+        // with delta temporality, every collection only sees what was recorded since the previous one
+        if temporality != Temporality::Delta {
+            *CONTROLLER.lock() = Some(controller);
+        }
+
+        Ok(())
+    }
+}
+
+/// Picks the histogram buckets of every metric from the [MetricsConfig].
+#[derive(Debug)]
+struct BucketSelector {
+    default: Arc<[f64]>,
+
+    // ordered by length, longest first
+    families: Vec<(String, Arc<[f64]>)>,
+}
+
+impl BucketSelector {
+    fn new(config: &MetricsConfig) -> Result<Self> {
+        let mut families = Vec::new();
+
+        for (family, buckets) in &config.buckets {
+            families.push((family.clone(), validate(family, buckets)?));
+        }
+
+        families.sort_by_key(|(family, _)| std::cmp::Reverse(family.len()));
+
+        Ok(Self {
+            default: validate("default", &config.default_buckets)?,
+            families,
+        })
+    }
+
+    fn buckets(&self, name: &str) -> &[f64] {
+        let family = self.families.iter().find(|(family, _)| {
+            name.strip_prefix(family.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        });
+
+        family.map_or(&self.default, |(_, buckets)| buckets)
+    }
+}
+
+fn validate(family: &str, buckets: &[f64]) -> Result<Arc<[f64]>> {
+    if buckets.is_empty() || buckets.iter().any(|bound| !bound.is_finite()) {
+        return Err(eyre!("buckets of {:?} must be a list of finite numbers", family));
+    }
+
+    if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(eyre!("buckets of {:?} must be in increasing order", family));
+    }
+
+    Ok(buckets.into())
+}
+
+impl AggregatorSelector for BucketSelector {
+    fn aggregator_for(&self, descriptor: &Descriptor) -> Option<Arc<dyn Aggregator + Send + Sync>> {
+        match descriptor.instrument_kind() {
+            InstrumentKind::GaugeObserver => Some(Arc::new(aggregators::last_value())),
+            InstrumentKind::Histogram => Some(Arc::new(aggregators::histogram(self.buckets(descriptor.name())))),
+            _ => Some(Arc::new(aggregators::sum())),
+        }
+    }
+}
+
+/// A metric family in the OpenMetrics text format.
+struct Family {
+    kind: &'static str,
+    help: Option<String>,
+    samples: String,
+}
+
+/// Renders the current value of all metrics in the OpenMetrics text format, see [CONTENT_TYPE].
+/// Metric names are converted to OpenMetrics names, e.g. `http.server.duration` in seconds becomes
+/// `http_server_duration_seconds`. When the metrics are pushed, the values of the last push are rendered.
+///
+/// Use like this: `.route("/metrics", get(|| async { ([(CONTENT_TYPE, metrics::CONTENT_TYPE)], metrics::render().unwrap_or_default()) }))`
+pub fn render() -> Result<String> {
+    let Some(controller) = CONTROLLER.lock().clone() else {
+        return Err(eyre!("metrics are not enabled or pushed with delta temporality"));
+    };
+
+    // when pushing, the controller collects on its own
+    if !controller.is_running() {
+        controller.collect(&opentelemetry::Context::current())?;
+    }
+
+    let mut families = BTreeMap::<String, Family>::new();
+
+    controller.try_for_each(&mut |_library, reader| {
+        reader.try_for_each(&cumulative_temporality_selector(), &mut |record| {
+            let descriptor = record.descriptor();
+            let number_kind = descriptor.number_kind();
+
+            let Some(aggregator) = record.aggregator() else {
+                return Ok(());
+            };
+
+            let name = family_name(descriptor);
+            let labels: Vec<(String, String)> = record
+                .attributes()
+                .iter()
+                .map(|(key, value)| (sanitize(key.as_str()), value.as_str().into_owned()))
+                .collect();
+
+            let aggregator = aggregator.as_any();
+
+            let (kind, samples) = if let Some(histogram) = aggregator.downcast_ref::<HistogramAggregator>() {
+                let buckets = histogram.histogram()?;
+
+                let mut samples = String::new();
+                let mut count = 0.0;
+
+                for (index, bucket_count) in buckets.counts().iter().enumerate() {
+                    count += bucket_count;
+
+                    let le = match buckets.boundaries().get(index) {
+                        Some(bound) => format_number(*bound),
+                        None => "+Inf".to_string(),
+                    };
+
+                    let labels = with_label(&labels, "le", le);
+                    sample(&mut samples, &name, "_bucket", &labels, format_number(count));
+                }
+
+                let sum = histogram.sum()?.to_f64(number_kind);
+                sample(&mut samples, &name, "_sum", &labels, format_number(sum));
+                sample(&mut samples, &name, "_count", &labels, format_number(count));
+
+                ("histogram", samples)
+            } else if let Some(sum) = aggregator.downcast_ref::<SumAggregator>() {
+                let value = format_number(sum.sum()?.to_f64(number_kind));
+                let mut samples = String::new();
+
+                if descriptor.instrument_kind().monotonic() {
+                    sample(&mut samples, &name, "_total", &labels, value);
+                    ("counter", samples)
+                } else {
+                    sample(&mut samples, &name, "", &labels, value);
+                    ("gauge", samples)
+                }
+            } else if let Some(last_value) = aggregator.downcast_ref::<LastValueAggregator>() {
+                let (value, _) = last_value.last_value()?;
+                let mut samples = String::new();
+                sample(
+                    &mut samples,
+                    &name,
+                    "",
+                    &labels,
+                    format_number(value.to_f64(number_kind)),
+                );
+
+                ("gauge", samples)
+            } else {
+                return Ok(());
+            };
+
+            let family = families.entry(name).or_insert_with(|| Family {
+                kind,
+                help: descriptor.description().cloned(),
+                samples: String::new(),
+            });
+
+            family.samples.push_str(&samples);
+
+            Ok(())
+        })
+    })?;
+
+    let mut text = String::new();
+
+    for (name, family) in families {
+        let _ = writeln!(text, "# TYPE {} {}", name, family.kind);
+
+        if let Some(help) = family.help {
+            let _ = writeln!(text, "# HELP {} {}", name, escape(&help));
+        }
+
+        text.push_str(&family.samples);
+    }
+
+    text.push_str("# EOF\n");
+
+    Ok(text)
+}
+
+/// The OpenMetrics name of a metric, with its unit as suffix.
+fn family_name(descriptor: &Descriptor) -> String {
+    let name = sanitize(descriptor.name());
+
+    let unit = match descriptor.unit() {
+        Some("s") => "seconds",
+        Some("ms") => "milliseconds",
+        Some("By") => "bytes",
+        _ => return name,
+    };
+
+    if name.ends_with(unit) {
+        name
+    } else {
+        format!("{}_{}", name, unit)
+    }
+}
+
+/// Replaces all characters not allowed in OpenMetrics names with underscores.
+fn sanitize(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+        .collect();
+
+    if sanitized.starts_with(|ch: char| ch.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
+
+fn with_label(labels: &[(String, String)], name: &str, value: String) -> Vec<(String, String)> {
+    let mut labels = labels.to_vec();
+    labels.push((name.to_string(), value));
+    labels
+}
+
+fn sample(text: &mut String, name: &str, suffix: &str, labels: &[(String, String)], value: String) {
+    text.push_str(name);
+    text.push_str(suffix);
+
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect();
+
+        let _ = write!(text, "{{{}}}", labels.join(","));
+    }
+
+    let _ = writeln!(text, " {}", value);
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_number(value: f64) -> String {
+    if value.is_infinite() {
+        return if value > 0.0 { "+Inf" } else { "-Inf" }.to_string();
+    }
+
+    // integers without fraction, like the counts of histograms, are written as integers
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{}", value)
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use eyre::Result;
+use opentelemetry::sdk::export::metrics::aggregation::constant_temporality_selector;
+use opentelemetry::sdk::metrics::controllers::{BasicController, BasicControllerBuilder};
+use opentelemetry::sdk::Resource;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::{ExportConfig, MetricsExporter};
+use startup_base::shutdown::priority;
+
+use crate::metrics::OtlpMetricsConfig;
+
+/// Time the exporter gets to send the last push on shutdown. It sends in the background,
+/// without a way to wait for it.
+const FLUSH_DELAY: Duration = Duration::from_millis(500);
+
+/// Builds a controller that pushes the metrics to the collector over OTLP/gRPC every interval
+/// and once more on shutdown, labeled with the name of the service.
+pub(crate) fn push(
+    builder: BasicControllerBuilder,
+    config: &OtlpMetricsConfig,
+    service_name: &str,
+) -> Result<BasicController> {
+    let export_config = ExportConfig {
+        endpoint: config.endpoint.clone(),
+        timeout: Duration::from_secs(config.timeout_seconds),
+        ..ExportConfig::default()
+    };
+
+    let temporality = constant_temporality_selector(config.temporality.into());
+
+    // the default tonic config, without metadata and tls
+    let exporter = MetricsExporter::new(export_config, Default::default(), Box::new(temporality))?;
+
+    let controller = builder
+        .with_collect_period(Duration::from_secs(config.interval_seconds.max(1)))
+        .with_exporter(exporter)
+        .with_resource(Resource::new([KeyValue::new("service.name", service_name.to_string())]))
+        .build();
+
+    controller.start(&Context::current(), opentelemetry::runtime::Tokio)?;
+
+    let stopping = controller.clone();
+    startup_base::on_shutdown("metrics", priority::TELEMETRY, move || async move {
+        // blocks until the last values were handed to the exporter
+        let stopped = tokio::task::spawn_blocking(move || stopping.stop(&Context::current())).await;
+
+        match stopped {
+            Ok(Ok(())) => tokio::time::sleep(FLUSH_DELAY).await,
+            Ok(Err(err)) => tracing::warn!("Failed to push metrics: {}", err),
+            Err(err) => tracing::warn!("Failed to push metrics: {}", err),
+        }
+    });
+
+    tracing::info!(
+        "Pushing metrics to {} every {}s",
+        config.endpoint,
+        config.interval_seconds
+    );
+    startup_base::sbom::register_backend("metrics", "otlp");
+
+    Ok(controller)
+}
@@ -0,0 +1,376 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use async_nats::jetstream;
+use async_nats::jetstream::consumer::pull;
+use async_nats::jetstream::AckKind;
+use futures_util::StreamExt;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use startup_base::messaging::{self, Outcome};
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::handler::{self, Handler, HeaderExtractor, Message};
+use crate::{Nats, NatsError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsumerConfig {
+    /// Stream to consume, it must exist already.
+    pub stream: String,
+
+    /// Name of the durable consumer. All instances of a service share the consumer,
+    /// so every message is handled by one of them.
+    pub durable_name: String,
+
+    #[serde(default)]
+    pub ack_policy: AckPolicy,
+
+    /// Time a handler has to acknowledge a message before it is delivered again. Running
+    /// handlers extend this time, so only messages of crashed instances are redelivered.
+    #[serde(default = "default_ack_wait_seconds")]
+    pub ack_wait_seconds: u64,
+
+    /// Deliveries of a message before jetstream gives up on it, `-1` for no limit.
+    #[serde(default = "default_max_deliver")]
+    pub max_deliver: i64,
+
+    /// Number of messages that are handled at the same time. With a value larger than
+    /// one, messages are no longer handled in order. Always one with [AckPolicy::All].
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// Delay before a failed message is delivered again. The delay doubles with every
+    /// delivery, up to `max_retry_backoff_ms`.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+
+    #[serde(default = "default_max_retry_backoff_ms")]
+    pub max_retry_backoff_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AckPolicy {
+    /// Every message is acknowledged once it was handled, failed messages are delivered again.
+    #[default]
+    Explicit,
+
+    /// Acknowledging a message also acknowledges all previous messages.
+    All,
+
+    /// Messages are never acknowledged, so every message is delivered at most once.
+    None,
+}
+
+fn default_ack_wait_seconds() -> u64 {
+    30
+}
+
+fn default_max_deliver() -> i64 {
+    10
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_retry_backoff_ms() -> u64 {
+    30_000
+}
+
+/// Consumes the messages of a jetstream stream using a durable pull consumer and passes them
+/// to the handler registered for their subject. Every message is traced as a consumer span
+/// that continues the trace of the producer.
+///
+/// A message is acknowledged once its handler succeeded. If a handler fails, the message is
+/// delivered again with an exponential backoff, until jetstream gives up after
+/// [ConsumerConfig::max_deliver] deliveries. Messages that can not be deserialized are
+/// terminated and never delivered again.
+///
+/// On shutdown, no new messages are fetched, but running handlers are allowed to finish.
+/// Fetched messages that were not handled yet are delivered again after the ack wait.
+///
+/// The metrics `nats.consumer.messages` and `nats.consumer.duration` record every handled
+/// message per stream, subject pattern and result.
+///
+/// Use like this:
+/// ```ignore
+/// Consumer::new(&nats, &config.consumer)
+///     .handle("orders.created", |order: Message<Order>| async move { process(order.payload).await })
+///     .run(startup_http::shutdown_requested())
+///     .await?;
+/// ```
+pub struct Consumer {
+    nats: Nats,
+    config: ConsumerConfig,
+    handlers: Vec<(String, Handler)>,
+}
+
+impl Consumer {
+    pub fn new(nats: &Nats, config: &ConsumerConfig) -> Self {
+        Self {
+            nats: nats.clone(),
+            config: config.clone(),
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Handles the messages of a subject, deserializing their json payload. The subject
+    /// may contain wildcards, e.g. `orders.*`.
+    pub fn handle<T, F, Fut, E>(mut self, subject: impl Into<String>, handler: F) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        self.handlers.push((subject.into(), messaging::json(handler)));
+        self
+    }
+
+    /// Handles the messages of a subject with their raw payload.
+    pub fn handle_raw<F, Fut, E>(mut self, subject: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Message<Vec<u8>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        self.handlers.push((subject.into(), messaging::raw(handler)));
+        self
+    }
+
+    /// Consumes messages until `shutdown` resolves, e.g. `startup_http::shutdown_requested()`.
+    /// Waits for the handlers of running messages to finish and acknowledges them.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), NatsError> {
+        let config = &self.config;
+
+        let consumer_error = |source: Box<dyn std::error::Error + Send + Sync>| NatsError::Consumer {
+            stream: config.stream.clone(),
+            consumer: config.durable_name.clone(),
+            source,
+        };
+
+        let ack_policy = match config.ack_policy {
+            AckPolicy::Explicit => jetstream::consumer::AckPolicy::Explicit,
+            AckPolicy::All => jetstream::consumer::AckPolicy::All,
+            AckPolicy::None => jetstream::consumer::AckPolicy::None,
+        };
+
+        // acknowledging a later message would acknowledge earlier messages that are still running
+        let concurrency = match config.ack_policy {
+            AckPolicy::All => 1,
+            _ => config.concurrency.max(1),
+        };
+
+        let consumer_config = pull::Config {
+            durable_name: Some(config.durable_name.clone()),
+            ack_policy,
+            ack_wait: Duration::from_secs(config.ack_wait_seconds),
+            max_deliver: config.max_deliver,
+            filter_subjects: self.handlers.iter().map(|(subject, _)| subject.clone()).collect(),
+            ..Default::default()
+        };
+
+        let stream = self
+            .nats
+            .jetstream()
+            .get_stream(&config.stream)
+            .await
+            .map_err(|err| consumer_error(Box::new(err)))?;
+
+        let consumer = stream
+            .create_consumer(consumer_config)
+            .await
+            .map_err(|err| consumer_error(Box::new(err)))?;
+
+        let messages = consumer.messages().await.map_err(|err| consumer_error(Box::new(err)))?;
+
+        info!(
+            "Consuming stream {} as {} with subjects {:?}",
+            config.stream,
+            config.durable_name,
+            self.handlers.iter().map(|(subject, _)| subject).collect::<Vec<_>>()
+        );
+
+        let shared = Shared::new(config);
+
+        let results = messages
+            .take_until(shutdown)
+            .filter_map(|message| async move {
+                match message {
+                    Ok(message) => Some(message),
+                    Err(err) => {
+                        warn!("Failed to receive message: {}", err);
+                        None
+                    }
+                }
+            })
+            .map(|message| handle_message(&self.handlers, &shared, message))
+            .buffer_unordered(concurrency);
+
+        tokio::pin!(results);
+        while results.next().await.is_some() {}
+
+        info!("Stopped consuming stream {}", config.stream);
+
+        Ok(())
+    }
+}
+
+/// State shared by all messages.
+struct Shared {
+    stream: String,
+    ack_policy: AckPolicy,
+    ack_wait: Duration,
+    retry_backoff_ms: u64,
+    max_retry_backoff_ms: u64,
+    messages: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Shared {
+    fn new(config: &ConsumerConfig) -> Self {
+        let meter = global::meter("startup-nats");
+
+        let messages = meter
+            .u64_counter("nats.consumer.messages")
+            .with_description("Messages handled by the consumer, including redeliveries")
+            .init();
+
+        let duration = meter
+            .f64_histogram("nats.consumer.duration")
+            .with_description("Duration of handling a message")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Self {
+            stream: config.stream.clone(),
+            ack_policy: config.ack_policy,
+            ack_wait: Duration::from_secs(config.ack_wait_seconds.max(1)),
+            retry_backoff_ms: config.retry_backoff_ms,
+            max_retry_backoff_ms: config.max_retry_backoff_ms,
+            messages,
+            duration,
+        }
+    }
+
+    fn record(&self, pattern: &str, elapsed: Duration, result: &'static str) {
+        let context = opentelemetry::Context::current();
+
+        let attributes = [
+            KeyValue::new("stream", self.stream.clone()),
+            KeyValue::new("subject", pattern.to_string()),
+            KeyValue::new("result", result),
+        ];
+
+        self.messages.add(&context, 1, &attributes);
+        self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+    }
+
+    /// Backoff before the next delivery of a message that failed `delivered` times.
+    fn backoff(&self, delivered: i64) -> Duration {
+        messaging::retry_backoff(self.retry_backoff_ms, self.max_retry_backoff_ms, delivered.clamp(1, 16) as u32)
+    }
+}
+
+async fn handle_message(handlers: &[(String, Handler)], shared: &Shared, message: jetstream::Message) {
+    let subject = message.subject.to_string();
+
+    let Some((pattern, handler)) = handlers.iter().find(|(pattern, _)| handler::matches(pattern, &subject)) else {
+        // the consumer only receives subjects with a handler, unless its filter was changed elsewhere.
+        // terminate the message, otherwise it is delivered again until max_deliver is reached.
+        warn!("Skipping message {} without a handler", subject);
+
+        if shared.ack_policy != AckPolicy::None {
+            if let Err(err) = message.ack_with(AckKind::Term).await {
+                warn!("Failed to terminate message {}: {}", subject, err);
+            }
+        }
+
+        return;
+    };
+
+    let (stream_sequence, delivered) = match message.info() {
+        Ok(info) => (Some(info.stream_sequence), info.delivered),
+        Err(_) => (None, 1),
+    };
+
+    let span = info_span!(
+        "nats_consumer",
+        otel.name = %format!("{} process", pattern),
+        otel.kind = "consumer",
+        otel.status_code = Empty,
+        messaging.system = "nats",
+        messaging.source = %subject,
+        messaging.nats.stream = %shared.stream,
+        messaging.nats.sequence = stream_sequence,
+        messaging.nats.delivered = delivered,
+    );
+
+    // continue the trace of the producer
+    if let Some(headers) = &message.headers {
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+        span.set_parent(parent);
+    }
+
+    let mut received = Message::new(&message);
+    received.stream_sequence = stream_sequence;
+    received.delivered = Some(delivered);
+
+    let started = Instant::now();
+    let handled = handler(received, &message.payload).instrument(span.clone());
+    tokio::pin!(handled);
+
+    // tell jetstream the message is still in progress, so it is not delivered again meanwhile
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut handled => break outcome,
+
+            _ = tokio::time::sleep(shared.ack_wait / 2), if shared.ack_policy != AckPolicy::None => {
+                if let Err(err) = message.ack_with(AckKind::Progress).await {
+                    debug!(parent: &span, "Failed to extend the ack wait of message {}: {}", subject, err);
+                }
+            }
+        }
+    };
+
+    let elapsed = started.elapsed();
+
+    let (result, ack) = match outcome {
+        Outcome::Handled => ("success", AckKind::Ack),
+
+        Outcome::Failed(err) => {
+            span.record("otel.status_code", "ERROR");
+
+            let backoff = shared.backoff(delivered);
+            warn!(parent: &span, "Failed to handle message {} (delivery {}), retrying in {:?}: {}", subject, delivered, backoff, err);
+
+            ("failure", AckKind::Nak(Some(backoff)))
+        }
+
+        Outcome::Invalid(err) => {
+            span.record("otel.status_code", "ERROR");
+            warn!(parent: &span, "Skipping invalid message {}: {}", subject, err);
+
+            ("invalid", AckKind::Term)
+        }
+    };
+
+    shared.record(pattern, elapsed, result);
+
+    if shared.ack_policy == AckPolicy::None {
+        return;
+    }
+
+    if let Err(err) = message.ack_with(ack).await {
+        warn!(parent: &span, "Failed to acknowledge message {}: {}", subject, err);
+    }
+}
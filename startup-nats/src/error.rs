@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NatsError {
+    #[error("failed to connect to nats")]
+    Connect(#[source] async_nats::ConnectError),
+
+    #[error("failed to read nats credentials from {path}")]
+    Credentials {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to serialize message for subject {subject}")]
+    Serialize {
+        subject: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("failed to publish message to subject {subject}")]
+    Publish {
+        subject: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("failed to flush published messages")]
+    Flush(#[source] async_nats::client::FlushError),
+
+    #[error("failed to subscribe to subject {subject}")]
+    Subscribe {
+        subject: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("failed to create consumer {consumer} on stream {stream}")]
+    Consumer {
+        stream: String,
+        consumer: String,
+        #[source]
+        source: BoxError,
+    },
+}
@@ -0,0 +1,99 @@
+use async_nats::HeaderMap;
+use opentelemetry::propagation::{Extractor, Injector};
+use startup_base::messaging::{self, is_trace_header, Envelope};
+
+/// A received message with its deserialized payload.
+#[derive(Debug, Clone)]
+pub struct Message<T> {
+    pub subject: String,
+
+    /// Subject to send a reply to, for messages sent as a request.
+    pub reply: Option<String>,
+
+    /// Headers of the message, without the trace context.
+    pub headers: Vec<(String, String)>,
+
+    /// Sequence of the message in its stream, for messages consumed from jetstream.
+    pub stream_sequence: Option<u64>,
+
+    /// How often the message was delivered, including this delivery, for messages
+    /// consumed from jetstream.
+    pub delivered: Option<i64>,
+
+    pub payload: T,
+}
+
+impl Message<()> {
+    pub(crate) fn new(message: &async_nats::Message) -> Self {
+        let headers = message
+            .headers
+            .iter()
+            .flat_map(|headers| headers.iter())
+            .filter(|(name, _)| !is_trace_header(name.as_ref()))
+            .flat_map(|(name, values)| values.iter().map(move |value| (name.to_string(), value.to_string())))
+            .collect();
+
+        Self {
+            subject: message.subject.to_string(),
+            reply: message.reply.as_ref().map(ToString::to_string),
+            headers,
+            stream_sequence: None,
+            delivered: None,
+            payload: (),
+        }
+    }
+}
+
+impl Envelope for Message<()> {
+    type Message<T> = Message<T>;
+
+    fn with_payload<U>(self, payload: U) -> Message<U> {
+        Message {
+            subject: self.subject,
+            reply: self.reply,
+            headers: self.headers,
+            stream_sequence: self.stream_sequence,
+            delivered: self.delivered,
+            payload,
+        }
+    }
+}
+
+pub(crate) type Handler = messaging::Handler<Message<()>>;
+
+/// Returns true if the subject matches the pattern, which may contain the
+/// wildcards `*` for a single token and `>` for all remaining tokens.
+pub(crate) fn matches(pattern: &str, subject: &str) -> bool {
+    let mut subject_tokens = subject.split('.');
+
+    for token in pattern.split('.') {
+        match (token, subject_tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => continue,
+            (token, Some(subject_token)) if token == subject_token => continue,
+            _ => return false,
+        }
+    }
+
+    subject_tokens.next().is_none()
+}
+
+pub(crate) struct HeaderInjector<'a>(pub(crate) &'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key, value.as_str());
+    }
+}
+
+pub(crate) struct HeaderExtractor<'a>(pub(crate) &'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|value| value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name.as_ref()).collect()
+    }
+}
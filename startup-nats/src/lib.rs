@@ -0,0 +1,380 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_nats::jetstream;
+use async_nats::{ConnectOptions, Event, HeaderMap};
+use bytes::Bytes;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde::{Deserialize, Serialize};
+use startup_base::health;
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use crate::consumer::{AckPolicy, Consumer, ConsumerConfig};
+pub use crate::error::NatsError;
+pub use crate::handler::Message;
+pub use crate::subscriber::Subscriber;
+
+use crate::handler::HeaderInjector;
+
+mod consumer;
+mod error;
+mod handler;
+mod subscriber;
+
+#[doc(hidden)]
+pub use async_nats;
+
+/// Name of the connection in the health registry.
+const HEALTH_COMPONENT: &str = "nats";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NatsConfig {
+    /// Addresses of the servers, e.g. `nats://nats-1:4222`.
+    pub servers: Vec<String>,
+
+    /// Name of the connection, shown in the monitoring of the server.
+    /// Defaults to the name of the service, see [startup_base::service_name].
+    #[serde(default = "default_name")]
+    pub name: String,
+
+    /// Path to a `.creds` file with the user jwt and nkey seed.
+    #[serde(default)]
+    pub credentials_file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub token: Option<String>,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Connect to the servers using tls.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+
+    /// Maximum delay between two reconnect attempts. The delay grows exponentially up to this value.
+    #[serde(default = "default_max_reconnect_delay_ms")]
+    pub max_reconnect_delay_ms: u64,
+
+    /// Reconnect attempts before the connection is closed for good. Reconnects forever if not set.
+    #[serde(default)]
+    pub max_reconnects: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate of the certificate authority. Defaults
+    /// to the root certificates of the system.
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+
+    /// Path to the PEM encoded client certificate for mutual tls.
+    #[serde(default)]
+    pub certificate: Option<PathBuf>,
+
+    /// Path to the PEM encoded private key of the client certificate.
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+}
+
+fn default_name() -> String {
+    startup_base::service_name()
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    5
+}
+
+fn default_max_reconnect_delay_ms() -> u64 {
+    5_000
+}
+
+/// Where a message was stored by jetstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ack {
+    pub stream: String,
+    pub sequence: u64,
+
+    /// True if the stream already contained a message with the same message id.
+    pub duplicate: bool,
+}
+
+/// A connection to nats. The connection reconnects in the background if it is lost,
+/// and reports its state to the [health] registry as `nats`.
+///
+/// Every published message is traced as a producer span and carries the trace context
+/// in its headers, so [Consumer] and [Subscriber] can continue the trace.
+///
+/// The metrics `nats.producer.messages` and `nats.producer.duration` record every published
+/// message per kind (`core` or `jetstream`) and result.
+///
+/// Use like this: `nats.publish_json("orders.created", &order).await?`
+#[derive(Clone)]
+pub struct Nats {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    client: async_nats::Client,
+    jetstream: jetstream::Context,
+    messages: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Nats {
+    pub async fn connect(config: &NatsConfig) -> Result<Self, NatsError> {
+        let mut options = match &config.credentials_file {
+            Some(path) => {
+                ConnectOptions::with_credentials_file(path)
+                    .await
+                    .map_err(|source| NatsError::Credentials {
+                        path: path.clone(),
+                        source,
+                    })?
+            }
+
+            None => ConnectOptions::new(),
+        };
+
+        if let Some(token) = &config.token {
+            options = options.token(token.clone());
+        }
+
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options = options.user_and_password(username.clone(), password.clone());
+        }
+
+        if let Some(tls) = &config.tls {
+            options = options.require_tls(true);
+
+            if let Some(ca_certificate) = &tls.ca_certificate {
+                options = options.add_root_certificates(ca_certificate.clone());
+            }
+
+            if let (Some(certificate), Some(key)) = (&tls.certificate, &tls.key) {
+                options = options.add_client_certificate(certificate.clone(), key.clone());
+            }
+        }
+
+        let max_reconnect_delay = Duration::from_millis(config.max_reconnect_delay_ms);
+
+        let options = options
+            .name(&config.name)
+            .connection_timeout(Duration::from_secs(config.connect_timeout_seconds))
+            .max_reconnects(config.max_reconnects)
+            .reconnect_delay_callback(move |attempts| reconnect_delay(attempts, max_reconnect_delay))
+            .event_callback(|event| async move { on_event(event) });
+
+        let client = options
+            .connect(config.servers.join(","))
+            .await
+            .map_err(NatsError::Connect)?;
+
+        info!("Connected to nats at {:?}", config.servers);
+        startup_base::sbom::register_backend("nats", "async-nats");
+        health::set_healthy(HEALTH_COMPONENT);
+
+        let meter = global::meter("startup-nats");
+
+        let messages = meter
+            .u64_counter("nats.producer.messages")
+            .with_description("Messages published to nats")
+            .init();
+
+        let duration = meter
+            .f64_histogram("nats.producer.duration")
+            .with_description("Duration of publishing a message, until jetstream acknowledged it")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        let inner = Inner {
+            jetstream: jetstream::new(client.clone()),
+            client,
+            messages,
+            duration,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// The underlying client, e.g. to send requests.
+    pub fn client(&self) -> &async_nats::Client {
+        &self.inner.client
+    }
+
+    /// The jetstream context, e.g. to manage streams or use key value stores.
+    pub fn jetstream(&self) -> &jetstream::Context {
+        &self.inner.jetstream
+    }
+
+    /// Publishes a message without waiting for it to be received by anyone.
+    pub async fn publish(&self, subject: &str, payload: impl Into<Bytes>) -> Result<(), NatsError> {
+        let span = producer_span(subject, "core");
+        let headers = trace_headers(&span);
+
+        let started = Instant::now();
+
+        let result = self
+            .inner
+            .client
+            .publish_with_headers(subject.to_string(), headers, payload.into())
+            .instrument(span.clone())
+            .await
+            .map_err(|err| NatsError::Publish {
+                subject: subject.to_string(),
+                source: Box::new(err),
+            });
+
+        self.record("core", started, &span, &result);
+        result
+    }
+
+    /// Serializes the value as json and publishes it, see [Nats::publish].
+    pub async fn publish_json<T: Serialize>(&self, subject: &str, value: &T) -> Result<(), NatsError> {
+        self.publish(subject, serialize(subject, value)?).await
+    }
+
+    /// Publishes a message to a jetstream stream and waits until the stream stored it.
+    /// Messages with a `message_id` are deduplicated by the stream.
+    pub async fn publish_stream(
+        &self,
+        subject: &str,
+        message_id: Option<&str>,
+        payload: impl Into<Bytes>,
+    ) -> Result<Ack, NatsError> {
+        let span = producer_span(subject, "jetstream");
+        let headers = trace_headers(&span);
+
+        let mut publish = jetstream::context::Publish::build()
+            .payload(payload.into())
+            .headers(headers);
+
+        if let Some(message_id) = message_id {
+            publish = publish.message_id(message_id);
+        }
+
+        let started = Instant::now();
+
+        let result = async {
+            let ack = self
+                .inner
+                .jetstream
+                .send_publish(subject.to_string(), publish)
+                .await?
+                .await?;
+
+            Ok(Ack {
+                stream: ack.stream,
+                sequence: ack.sequence,
+                duplicate: ack.duplicate,
+            })
+        }
+        .instrument(span.clone())
+        .await
+        .map_err(|err: jetstream::context::PublishError| NatsError::Publish {
+            subject: subject.to_string(),
+            source: Box::new(err),
+        });
+
+        if let Ok(ack) = &result {
+            debug!(parent: &span, "Stored message in stream {} at sequence {}", ack.stream, ack.sequence);
+        }
+
+        self.record("jetstream", started, &span, &result);
+        result
+    }
+
+    /// Serializes the value as json and publishes it to a stream, see [Nats::publish_stream].
+    pub async fn publish_stream_json<T: Serialize>(
+        &self,
+        subject: &str,
+        message_id: Option<&str>,
+        value: &T,
+    ) -> Result<Ack, NatsError> {
+        self.publish_stream(subject, message_id, serialize(subject, value)?)
+            .await
+    }
+
+    /// Flushes published messages to the server, e.g. before the service exits.
+    pub async fn flush(&self) -> Result<(), NatsError> {
+        self.inner.client.flush().await.map_err(NatsError::Flush)
+    }
+
+    fn record<T>(&self, kind: &'static str, started: Instant, span: &Span, result: &Result<T, NatsError>) {
+        let outcome = match result {
+            Ok(_) => "success",
+            Err(err) => {
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: span, "{:?}", err);
+                "failure"
+            }
+        };
+
+        let context = opentelemetry::Context::current();
+        let attributes = [KeyValue::new("kind", kind), KeyValue::new("result", outcome)];
+
+        self.inner.messages.add(&context, 1, &attributes);
+        self.inner
+            .duration
+            .record(&context, started.elapsed().as_secs_f64(), &attributes);
+    }
+}
+
+fn producer_span(subject: &str, kind: &'static str) -> Span {
+    info_span!(
+        "nats_producer",
+        otel.name = %format!("{} publish", subject),
+        otel.kind = "producer",
+        otel.status_code = Empty,
+        messaging.system = "nats",
+        messaging.destination = %subject,
+        messaging.nats.kind = kind,
+    )
+}
+
+/// Returns headers carrying the trace context of the producer span.
+fn trace_headers(span: &Span) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = span.context();
+
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers));
+    });
+
+    headers
+}
+
+fn serialize<T: Serialize>(subject: &str, value: &T) -> Result<Vec<u8>, NatsError> {
+    serde_json::to_vec(value).map_err(|source| NatsError::Serialize {
+        subject: subject.to_string(),
+        source,
+    })
+}
+
+fn reconnect_delay(attempts: usize, max_delay: Duration) -> Duration {
+    if attempts <= 1 {
+        return Duration::ZERO;
+    }
+
+    let exponent = (attempts - 1).min(16) as u32;
+    (Duration::from_millis(100) * 2u32.pow(exponent)).min(max_delay)
+}
+
+fn on_event(event: Event) {
+    match event {
+        Event::Connected => health::set_healthy(HEALTH_COMPONENT),
+        Event::Disconnected => health::set_unhealthy(HEALTH_COMPONENT, "disconnected from nats, reconnecting"),
+        Event::Closed => health::set_unhealthy(HEALTH_COMPONENT, "connection to nats closed"),
+        Event::Draining => info!("Draining nats connection"),
+        event => warn!("Nats connection: {}", event),
+    }
+}
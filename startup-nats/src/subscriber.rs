@@ -0,0 +1,236 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde::de::DeserializeOwned;
+use startup_base::messaging::{self, Outcome};
+use tokio_util::sync::CancellationToken;
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::handler::{Handler, HeaderExtractor, Message};
+use crate::{Nats, NatsError};
+
+/// Subscribes to core nats subjects and passes the messages to the handler registered
+/// for their subject. Every message is traced as a consumer span that continues the
+/// trace of the producer.
+///
+/// Core nats delivers every message at most once, failed messages are logged and dropped.
+/// Use a [Consumer](crate::Consumer) if messages must not get lost. The messages of a subject
+/// are handled one after another, in the order they were received.
+///
+/// With a queue group, every message is received by only one member of the group, e.g. by
+/// one instance of the service.
+///
+/// On shutdown, the subscriptions are drained: no new messages are received, but the messages
+/// that were already received are still handled.
+///
+/// The metrics `nats.subscriber.messages` and `nats.subscriber.duration` record every handled
+/// message per subject pattern and result.
+///
+/// Use like this:
+/// ```ignore
+/// Subscriber::new(&nats)
+///     .queue_group("order-service")
+///     .handle("prices.*", |price: Message<Price>| async move { update(price.payload).await })
+///     .run(startup_http::shutdown_requested())
+///     .await?;
+/// ```
+pub struct Subscriber {
+    nats: Nats,
+    queue_group: Option<String>,
+    handlers: Vec<(String, Handler)>,
+}
+
+impl Subscriber {
+    pub fn new(nats: &Nats) -> Self {
+        Self {
+            nats: nats.clone(),
+            queue_group: None,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Joins the queue group with all subscriptions.
+    pub fn queue_group(mut self, queue_group: impl Into<String>) -> Self {
+        self.queue_group = Some(queue_group.into());
+        self
+    }
+
+    /// Handles the messages of a subject, deserializing their json payload. The subject
+    /// may contain wildcards, e.g. `prices.*`.
+    pub fn handle<T, F, Fut, E>(mut self, subject: impl Into<String>, handler: F) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        self.handlers.push((subject.into(), messaging::json(handler)));
+        self
+    }
+
+    /// Handles the messages of a subject with their raw payload.
+    pub fn handle_raw<F, Fut, E>(mut self, subject: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Message<Vec<u8>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        self.handlers.push((subject.into(), messaging::raw(handler)));
+        self
+    }
+
+    /// Handles messages until `shutdown` resolves, e.g. `startup_http::shutdown_requested()`,
+    /// then drains the subscriptions.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), NatsError> {
+        let client = self.nats.client();
+        let shared = Arc::new(Shared::new());
+        let stop = CancellationToken::new();
+
+        let mut tasks = Vec::with_capacity(self.handlers.len());
+
+        for (subject, handler) in self.handlers {
+            let subscribed = match &self.queue_group {
+                Some(queue_group) => client.queue_subscribe(subject.clone(), queue_group.clone()).await,
+                None => client.subscribe(subject.clone()).await,
+            };
+
+            let subscription = subscribed.map_err(|err| NatsError::Subscribe {
+                subject: subject.clone(),
+                source: Box::new(err),
+            })?;
+
+            info!("Subscribed to {}", subject);
+
+            tasks.push(tokio::spawn(consume(
+                subject,
+                subscription,
+                handler,
+                shared.clone(),
+                stop.clone(),
+            )));
+        }
+
+        shutdown.await;
+
+        info!("Draining nats subscriptions");
+
+        stop.cancel();
+        futures_util::future::join_all(tasks).await;
+
+        Ok(())
+    }
+}
+
+async fn consume(
+    pattern: String,
+    mut subscription: async_nats::Subscriber,
+    handler: Handler,
+    shared: Arc<Shared>,
+    stop: CancellationToken,
+) {
+    loop {
+        let message = tokio::select! {
+            _ = stop.cancelled() => break,
+            message = subscription.next() => message,
+        };
+
+        match message {
+            Some(message) => handle_message(&pattern, &handler, &shared, message).await,
+            None => return,
+        }
+    }
+
+    // the server stops sending messages, the stream ends after the buffered ones
+    if let Err(err) = subscription.drain().await {
+        debug!("Failed to drain subscription {}: {}", pattern, err);
+        return;
+    }
+
+    while let Some(message) = subscription.next().await {
+        handle_message(&pattern, &handler, &shared, message).await;
+    }
+
+    debug!("Drained subscription {}", pattern);
+}
+
+async fn handle_message(pattern: &str, handler: &Handler, shared: &Shared, message: async_nats::Message) {
+    let span = info_span!(
+        "nats_subscriber",
+        otel.name = %format!("{} process", pattern),
+        otel.kind = "consumer",
+        otel.status_code = Empty,
+        messaging.system = "nats",
+        messaging.source = %message.subject,
+    );
+
+    // continue the trace of the producer
+    if let Some(headers) = &message.headers {
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+        span.set_parent(parent);
+    }
+
+    let started = Instant::now();
+    let outcome = handler(Message::new(&message), &message.payload)
+        .instrument(span.clone())
+        .await;
+
+    let result = match outcome {
+        Outcome::Handled => "success",
+
+        Outcome::Failed(err) => {
+            span.record("otel.status_code", "ERROR");
+            warn!(parent: &span, "Failed to handle message {}: {}", message.subject, err);
+            "failure"
+        }
+
+        Outcome::Invalid(err) => {
+            span.record("otel.status_code", "ERROR");
+            warn!(parent: &span, "Skipping invalid message {}: {}", message.subject, err);
+            "invalid"
+        }
+    };
+
+    shared.record(pattern, started.elapsed(), result);
+}
+
+struct Shared {
+    messages: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Shared {
+    fn new() -> Self {
+        let meter = global::meter("startup-nats");
+
+        let messages = meter
+            .u64_counter("nats.subscriber.messages")
+            .with_description("Messages handled by core nats subscriptions")
+            .init();
+
+        let duration = meter
+            .f64_histogram("nats.subscriber.duration")
+            .with_description("Duration of handling a message")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Self { messages, duration }
+    }
+
+    fn record(&self, pattern: &str, elapsed: Duration, result: &'static str) {
+        let context = opentelemetry::Context::current();
+
+        let attributes = [
+            KeyValue::new("subject", pattern.to_string()),
+            KeyValue::new("result", result),
+        ];
+
+        self.messages.add(&context, 1, &attributes);
+        self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+    }
+}
@@ -0,0 +1,12 @@
+#[derive(Debug, thiserror::Error)]
+pub enum OperationError {
+    #[error("failed to serialize result of operation {id}")]
+    Serialize {
+        id: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("operation database error")]
+    Database(#[from] sqlx::Error),
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use opentelemetry::metrics::{Histogram, Unit};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{global, KeyValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Executor, PgPool};
+use tracing::field::Empty;
+use tracing::{info, info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use crate::error::OperationError;
+pub use crate::store::{DatabaseOperations, OperationStore};
+
+mod error;
+mod store;
+
+/// The table of the operations, created by [install]. Ids are generated
+/// by `gen_random_uuid`, which needs postgres 13 or newer.
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS startup_operations (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    kind TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'running',
+    progress DOUBLE PRECISION,
+    message TEXT,
+    result JSONB,
+    error TEXT,
+    trace_context JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+"#;
+
+/// Creates the table of the operations in the current schema if it does not exist yet.
+pub async fn install(pool: &PgPool) -> Result<(), OperationError> {
+    info!("Ensure operation table exists");
+    pool.execute(SCHEMA).await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl OperationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationStatus::Running => "running",
+            OperationStatus::Succeeded => "succeeded",
+            OperationStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "running" => Some(OperationStatus::Running),
+            "succeeded" => Some(OperationStatus::Succeeded),
+            "failed" => Some(OperationStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// The state of an operation, as reported by the status endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct Operation {
+    pub id: String,
+    pub kind: String,
+    pub status: OperationStatus,
+
+    /// Fraction of the work that is done, between 0 and 1, if the operation reports it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// The result of a succeeded operation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+
+    /// The error of a failed operation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Context of the trace that started the operation, see [span].
+    #[serde(skip)]
+    pub trace_context: HashMap<String, String>,
+
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Runs long-running operations in the background. The handler that starts an operation answers
+/// with `202 Accepted`, the id of the operation and its status endpoint in the `Location` header.
+/// Clients poll the status endpoint, see [Operations::router], for the progress and the result.
+///
+/// The work of an operation is traced in its own trace, linked to the trace of the request that
+/// started it, see [span]. The metric `operations.duration` records every operation per kind and result.
+///
+/// Operations run in the process that started them. An operation of a process that
+/// stopped before the operation finished is reported as running forever, so operations
+/// that must survive a restart should run as a job that reports through [Operations::progress].
+///
+/// Use like this:
+/// ```ignore
+/// async fn export(Extension(operations): Extension<Operations>) -> Result<Accepted, HttpError> {
+///     let accepted = operations
+///         .start("export", |progress| async move { exporter.run(progress).await })
+///         .await?;
+///
+///     Ok(accepted)
+/// }
+///
+/// router.merge(operations.router())
+/// ```
+#[derive(Clone)]
+pub struct Operations {
+    store: Arc<dyn OperationStore>,
+    base_path: String,
+    duration: Histogram<f64>,
+}
+
+impl Operations {
+    pub fn new(store: impl OperationStore) -> Self {
+        let duration = global::meter("startup-operations")
+            .f64_histogram("operations.duration")
+            .with_description("Duration of long-running operations")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Self {
+            store: Arc::new(store),
+            base_path: "/operations".to_string(),
+            duration,
+        }
+    }
+
+    /// Path of the status endpoint, defaults to `/operations`.
+    pub fn with_base_path(mut self, base_path: impl Into<String>) -> Self {
+        self.base_path = base_path.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Creates a running operation to report on from elsewhere, e.g. a job,
+    /// using [Operations::progress]. Keeps the context of the current trace.
+    pub async fn create(&self, kind: &str) -> Result<Operation, OperationError> {
+        let mut trace_context = HashMap::new();
+        let context = Span::current().context();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut trace_context));
+
+        self.store.create(kind, trace_context).await
+    }
+
+    /// Creates an operation and runs it in the background. The operation succeeds with the
+    /// result of the future, or fails with its error. Answer the request with the returned [Accepted].
+    pub async fn start<F, Fut, T, E>(&self, kind: &str, run: F) -> Result<Accepted, OperationError>
+    where
+        F: FnOnce(Progress) -> Fut,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize,
+        E: Display,
+    {
+        let operation = self.create(kind).await?;
+
+        let span = span(&operation);
+        let progress = self.progress(&operation.id);
+        let running = AssertUnwindSafe(run(progress.clone())).catch_unwind();
+
+        let duration = self.duration.clone();
+        let kind = operation.kind.clone();
+
+        tokio::spawn(
+            async move {
+                let started = Instant::now();
+
+                let outcome = match running.await {
+                    Ok(Ok(result)) => {
+                        serde_json::to_value(result).map_err(|err| format!("failed to serialize result: {}", err))
+                    }
+
+                    Ok(Err(err)) => Err(err.to_string()),
+                    Err(_) => Err("operation panicked".to_string()),
+                };
+
+                let result = match &outcome {
+                    Ok(_) => "success",
+                    Err(err) => {
+                        Span::current().record("otel.status_code", "ERROR");
+                        warn!("Operation {} of kind {} failed: {}", progress.id, kind, err);
+                        "failure"
+                    }
+                };
+
+                let attributes = [KeyValue::new("kind", kind), KeyValue::new("result", result)];
+                let elapsed = started.elapsed().as_secs_f64();
+                duration.record(&opentelemetry::Context::current(), elapsed, &attributes);
+
+                if let Err(err) = progress.store.finish(&progress.id, outcome).await {
+                    warn!("Failed to record the outcome of operation {}: {}", progress.id, err);
+                }
+            }
+            .instrument(span),
+        );
+
+        Ok(self.accepted(operation))
+    }
+
+    /// Reports the progress and the outcome of the operation with the id.
+    pub fn progress(&self, id: &str) -> Progress {
+        Progress {
+            id: id.to_string(),
+            store: self.store.clone(),
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Operation>, OperationError> {
+        self.store.get(id).await
+    }
+
+    /// The response to the request that started the operation.
+    pub fn accepted(&self, operation: Operation) -> Accepted {
+        Accepted {
+            location: self.location(&operation.id),
+            operation,
+        }
+    }
+
+    fn location(&self, id: &str) -> String {
+        format!("{}/{}", self.base_path, id)
+    }
+
+    /// Status endpoint `GET /operations/{id}`, answers with the [Operation] or `404 Not Found`.
+    pub fn router(&self) -> Router {
+        let get_operation = {
+            let this = self.clone();
+            move |Path(id): Path<String>| async move { this.status(&id).await }
+        };
+
+        Router::new().route(&format!("{}/:id", self.base_path), get(get_operation))
+    }
+
+    async fn status(&self, id: &str) -> Response {
+        match self.store.get(id).await {
+            Ok(Some(operation)) => Json(operation).into_response(),
+            Ok(None) => StatusCode::NOT_FOUND.into_response(),
+            Err(err) => {
+                warn!("Failed to query operation {}: {}", id, err);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// Answers the request that started an operation with `202 Accepted`, the [Operation]
+/// as body and the status endpoint in the `Location` header.
+#[derive(Debug, Clone)]
+pub struct Accepted {
+    pub operation: Operation,
+    pub location: String,
+}
+
+impl IntoResponse for Accepted {
+    fn into_response(self) -> Response {
+        let headers = [(header::LOCATION, self.location)];
+        (StatusCode::ACCEPTED, headers, Json(self.operation)).into_response()
+    }
+}
+
+/// Reports the progress and the outcome of an operation, see [Operations::progress].
+#[derive(Clone)]
+pub struct Progress {
+    id: String,
+    store: Arc<dyn OperationStore>,
+}
+
+impl Progress {
+    /// Id of the operation.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Records the fraction of the work that is done, between 0 and 1. A failure to record
+    /// the progress is logged and does not fail the operation.
+    pub async fn report(&self, progress: f64, message: &str) {
+        if let Err(err) = self.store.report(&self.id, progress, Some(message)).await {
+            warn!("Failed to record the progress of operation {}: {}", self.id, err);
+        }
+    }
+
+    /// Finishes the operation with the result. Done by [Operations::start] for the operations it runs.
+    pub async fn succeed<T: Serialize + ?Sized>(&self, result: &T) -> Result<(), OperationError> {
+        let result = serde_json::to_value(result).map_err(|source| OperationError::Serialize {
+            id: self.id.clone(),
+            source,
+        })?;
+
+        self.store.finish(&self.id, Ok(result)).await
+    }
+
+    /// Finishes the operation with the error. Done by [Operations::start] for the operations it runs.
+    pub async fn fail(&self, error: impl Display) -> Result<(), OperationError> {
+        self.store.finish(&self.id, Err(error.to_string())).await
+    }
+}
+
+/// A span for the work of the operation, in a trace of its own that is linked to the trace of the
+/// request that started the operation. Use it for operations that run elsewhere, e.g. in a job.
+///
+/// Use like this: `export(progress).instrument(startup_operations::span(&operation)).await`
+pub fn span(operation: &Operation) -> Span {
+    let span = info_span!(
+        "operation",
+        otel.name = %format!("{} run", operation.kind),
+        otel.kind = "internal",
+        otel.status_code = Empty,
+        operation.id = %operation.id,
+        operation.kind = %operation.kind,
+    );
+
+    // a new trace, the request that started the operation finishes long before it
+    span.set_parent(opentelemetry::Context::new());
+
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&operation.trace_context));
+    let span_context = context.span().span_context().clone();
+
+    if span_context.is_valid() {
+        span.add_link(span_context);
+    }
+
+    span
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde_json::Value;
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::{PgPool, Row};
+
+use crate::{Operation, OperationError, OperationStatus};
+
+/// Stores the state of the operations, so the status endpoint can be served by every replica.
+/// Implemented by [DatabaseOperations].
+#[async_trait]
+pub trait OperationStore: Send + Sync + 'static {
+    /// Creates a running operation of the kind. The trace context is kept
+    /// to link the work of the operation to the request that started it.
+    async fn create(&self, kind: &str, trace_context: HashMap<String, String>) -> Result<Operation, OperationError>;
+
+    async fn get(&self, id: &str) -> Result<Option<Operation>, OperationError>;
+
+    /// Records the progress of a running operation, a fraction between 0 and 1.
+    async fn report(&self, id: &str, progress: f64, message: Option<&str>) -> Result<(), OperationError>;
+
+    /// Finishes the operation with its result or an error.
+    async fn finish(&self, id: &str, outcome: Result<Value, String>) -> Result<(), OperationError>;
+}
+
+/// Operations stored in the `startup_operations` table, see [install](crate::install).
+#[derive(Clone)]
+pub struct DatabaseOperations {
+    pool: PgPool,
+}
+
+impl DatabaseOperations {
+    pub fn new(pool: &PgPool) -> Self {
+        Self { pool: pool.clone() }
+    }
+}
+
+const COLUMNS: &str = "id, kind, status, progress, message, result, error, trace_context, created_at, updated_at";
+
+#[async_trait]
+impl OperationStore for DatabaseOperations {
+    async fn create(&self, kind: &str, trace_context: HashMap<String, String>) -> Result<Operation, OperationError> {
+        let query = format!(
+            "INSERT INTO startup_operations (kind, trace_context) VALUES ($1, $2) RETURNING {}",
+            COLUMNS
+        );
+
+        let row = sqlx::query(&query)
+            .bind(kind)
+            .bind(Json(trace_context))
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(operation(row)?)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Operation>, OperationError> {
+        let query = format!("SELECT {} FROM startup_operations WHERE id = $1", COLUMNS);
+
+        let row = sqlx::query(&query).bind(id).fetch_optional(&self.pool).await?;
+
+        Ok(row.map(operation).transpose()?)
+    }
+
+    async fn report(&self, id: &str, progress: f64, message: Option<&str>) -> Result<(), OperationError> {
+        sqlx::query(
+            "UPDATE startup_operations
+            SET progress = $2, message = COALESCE($3, message), updated_at = now()
+            WHERE id = $1 AND status = 'running'",
+        )
+        .bind(id)
+        .bind(progress.clamp(0.0, 1.0))
+        .bind(message)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn finish(&self, id: &str, outcome: Result<Value, String>) -> Result<(), OperationError> {
+        let (status, result, error) = match outcome {
+            Ok(result) => (OperationStatus::Succeeded, Some(result), None),
+            Err(error) => (OperationStatus::Failed, None, Some(error)),
+        };
+
+        sqlx::query(
+            "UPDATE startup_operations
+            SET status = $2, result = $3, error = $4, updated_at = now(),
+                progress = CASE WHEN $2 = 'succeeded' THEN 1 ELSE progress END
+            WHERE id = $1 AND status = 'running'",
+        )
+        .bind(id)
+        .bind(status.as_str())
+        .bind(result)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn operation(row: PgRow) -> Result<Operation, sqlx::Error> {
+    let status: String = row.try_get("status")?;
+
+    let status = OperationStatus::parse(&status).ok_or_else(|| sqlx::Error::ColumnDecode {
+        index: "status".to_string(),
+        source: format!("unknown operation status {:?}", status).into(),
+    })?;
+
+    Ok(Operation {
+        id: row.try_get("id")?,
+        kind: row.try_get("kind")?,
+        status,
+        progress: row.try_get("progress")?,
+        message: row.try_get("message")?,
+        result: row.try_get("result")?,
+        error: row.try_get("error")?,
+        trace_context: row.try_get::<Json<_>, _>("trace_context")?.0,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use redis::aio::{ConnectionLike, ConnectionManager, MultiplexedConnection};
+use redis::cluster_async::ClusterConnection;
+use redis::{Arg, Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult, Value};
+use tracing::field::Empty;
+use tracing::{info_span, warn, Instrument, Span};
+
+use crate::pool::SentinelSlot;
+
+/// A connection taken from a [crate::RedisPool]. Use it with [redis::AsyncCommands]
+/// or [redis::Cmd::query_async]. Every command is traced as a client span.
+///
+/// Connections are cheap to clone and can be used concurrently, commands are
+/// multiplexed over the underlying connection of the pool.
+#[derive(Clone)]
+pub struct RedisConnection {
+    backend: Backend,
+}
+
+#[derive(Clone)]
+pub(crate) enum Backend {
+    /// Reconnects automatically if the connection breaks.
+    Standalone(ConnectionManager),
+
+    /// Follows redirects and reconnects to the nodes of the cluster on its own.
+    Cluster(ClusterConnection),
+
+    /// Connection to the current master. The slot is reset if the connection
+    /// breaks or the master was demoted, so the next connection asks the
+    /// sentinels for the new master.
+    Sentinel(MultiplexedConnection, Arc<SentinelSlot>),
+}
+
+impl RedisConnection {
+    pub(crate) fn new(backend: Backend) -> Self {
+        Self { backend }
+    }
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let span = command_span(&command_name(cmd));
+
+        Box::pin(
+            async move {
+                let result = self.backend.send(cmd).await;
+                record_result(&result);
+                result
+            }
+            .instrument(span),
+        )
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let span = command_span("PIPELINE");
+
+        Box::pin(
+            async move {
+                let result = self.backend.send_pipeline(pipeline, offset, count).await;
+                record_result(&result);
+                result
+            }
+            .instrument(span),
+        )
+    }
+
+    fn get_db(&self) -> i64 {
+        self.backend.get_db()
+    }
+}
+
+impl Backend {
+    pub(crate) async fn send(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        let result = match self {
+            Backend::Standalone(connection) => connection.req_packed_command(cmd).await,
+            Backend::Cluster(connection) => connection.req_packed_command(cmd).await,
+            Backend::Sentinel(connection, _) => connection.req_packed_command(cmd).await,
+        };
+
+        self.check(result)
+    }
+
+    async fn send_pipeline(&mut self, pipeline: &Pipeline, offset: usize, count: usize) -> RedisResult<Vec<Value>> {
+        let result = match self {
+            Backend::Standalone(connection) => connection.req_packed_commands(pipeline, offset, count).await,
+            Backend::Cluster(connection) => connection.req_packed_commands(pipeline, offset, count).await,
+            Backend::Sentinel(connection, _) => connection.req_packed_commands(pipeline, offset, count).await,
+        };
+
+        self.check(result)
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Backend::Standalone(connection) => connection.get_db(),
+            Backend::Cluster(connection) => connection.get_db(),
+            Backend::Sentinel(connection, _) => connection.get_db(),
+        }
+    }
+
+    /// Resets the sentinel slot if the error shows that we need a new connection.
+    fn check<T>(&self, result: RedisResult<T>) -> RedisResult<T> {
+        if let (Backend::Sentinel(_, slot), Err(err)) = (self, &result) {
+            if needs_reconnect(err) {
+                warn!(
+                    "Connection to redis master failed, reconnecting using sentinel: {}",
+                    err
+                );
+                slot.reset();
+            }
+        }
+
+        result
+    }
+}
+
+fn needs_reconnect(err: &RedisError) -> bool {
+    // after a failover, the old master still accepts connections as a replica
+    err.is_unrecoverable_error() || err.is_connection_dropped() || err.kind() == ErrorKind::ReadOnly
+}
+
+fn command_span(command: &str) -> Span {
+    info_span!(
+        "redis",
+        otel.name = %command,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        db.system = "redis",
+        db.operation = %command,
+    )
+}
+
+fn record_result<T>(result: &RedisResult<T>) {
+    if let Err(err) = result {
+        let span = Span::current();
+        span.record("otel.status_code", "ERROR");
+        warn!("Redis command failed: {}", err);
+    }
+}
+
+/// Name of the command, without its arguments. Keys and values are never recorded.
+fn command_name(cmd: &Cmd) -> String {
+    match cmd.args_iter().next() {
+        Some(Arg::Simple(name)) => String::from_utf8_lossy(name).to_uppercase(),
+        _ => "UNKNOWN".to_string(),
+    }
+}
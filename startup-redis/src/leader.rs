@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use startup_base::bus::{self, LifecycleEvent};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{DistributedLock, LockConfig, RedisPool};
+
+/// Elects one replica of a service as the leader, e.g. to run jobs that must not run on
+/// every replica. The leader holds a [DistributedLock] for as long as it lives. The other
+/// replicas try to take the lock regularly, so a new leader is elected after the lease of
+/// a crashed leader expired.
+///
+/// Use like this: `if leadership.is_leader() { send_reminders().await? }`
+#[derive(Clone)]
+pub struct Leadership {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    leader: Arc<AtomicBool>,
+    election: JoinHandle<()>,
+}
+
+impl Leadership {
+    /// Starts to take part in the election of the leader with the given name.
+    /// Stops when the last clone is dropped, giving up the leadership.
+    pub fn start(pool: &RedisPool, name: &str, config: &LockConfig) -> Self {
+        let lock = DistributedLock::new(pool, &format!("leader:{}", name), config);
+
+        // trying to take the lock more often than the lease is renewed does not help
+        let interval = Duration::from_millis(config.lease_ms / 3).max(Duration::from_millis(config.retry_interval_ms));
+
+        let leader = Arc::new(AtomicBool::new(false));
+        let election = tokio::spawn(elect(lock, name.to_string(), interval, leader.clone()));
+
+        Self {
+            inner: Arc::new(Inner { leader, election }),
+        }
+    }
+
+    /// Returns true while this replica is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.inner.leader.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.election.abort();
+    }
+}
+
+async fn elect(lock: DistributedLock, name: String, interval: Duration, leader: Arc<AtomicBool>) {
+    loop {
+        match lock.try_acquire().await {
+            Ok(Some(guard)) => {
+                info!("Became leader of {}", name);
+                leader.store(true, Ordering::Relaxed);
+                bus::publish(LifecycleEvent::LeadershipGained { name: name.clone() });
+
+                guard.lost().await;
+
+                warn!("Lost leadership of {}", name);
+                leader.store(false, Ordering::Relaxed);
+                bus::publish(LifecycleEvent::LeadershipLost { name: name.clone() });
+            }
+
+            Ok(None) => {}
+
+            Err(err) => warn!("Failed to take part in the election of the leader of {}: {}", name, err),
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use redis::aio::ConnectionManagerConfig;
+use redis::cluster::ClusterClientBuilder;
+use redis::sentinel::{SentinelClient, SentinelNodeConnectionInfo, SentinelServerType};
+use redis::{
+    AsyncConnectionConfig, ConnectionAddr, ConnectionInfo, ErrorKind, IntoConnectionInfo, RedisError, TlsMode,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+pub use crate::connection::RedisConnection;
+pub use crate::leader::Leadership;
+pub use crate::lock::{DistributedLock, LockConfig, LockGuard};
+#[cfg(feature = "http")]
+pub use crate::policy::{RateLimitLayer, RateLimitPolicy, RateLimitPolicyConfig, RateLimitService};
+pub use crate::pool::RedisPool;
+pub use crate::rate_limit::{RateLimitConfig, RateLimitDecision, RateLimiter};
+#[cfg(feature = "http")]
+pub use crate::session::RedisSessionStore;
+
+#[doc(hidden)]
+pub use redis;
+
+mod connection;
+mod leader;
+mod lock;
+#[cfg(feature = "http")]
+mod policy;
+mod pool;
+mod rate_limit;
+#[cfg(feature = "http")]
+mod session;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedisConfig {
+    /// Connection url, e.g. `redis://:password@localhost:6379/0`. Use `rediss://` to connect
+    /// using tls. In sentinel mode, the credentials, database and tls setting of this url
+    /// are used to connect to the master.
+    pub url: String,
+
+    #[serde(default)]
+    pub mode: RedisMode,
+
+    /// In cluster mode, the urls of the nodes used to discover the cluster. Defaults to `url`.
+    /// In sentinel mode, the urls of the sentinels.
+    #[serde(default)]
+    pub nodes: Vec<String>,
+
+    /// Name of the master monitored by the sentinels. Required in sentinel mode.
+    #[serde(default)]
+    pub master_name: Option<String>,
+
+    /// Accept invalid tls certificates. Never enable this in production.
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+
+    /// Number of connections. Every connection is multiplexed and
+    /// can be used by many tasks at the same time.
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+
+    /// Timeout for establishing a connection.
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+
+    /// Timeout for the response to a command.
+    #[serde(default = "default_response_timeout_ms")]
+    pub response_timeout_ms: u64,
+
+    /// Interval to check the connections using `PING`.
+    #[serde(default = "default_health_check_interval_seconds")]
+    pub health_check_interval_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisMode {
+    /// A single redis server.
+    #[default]
+    Standalone,
+
+    /// A redis cluster. Keys are distributed between the nodes of the cluster.
+    Cluster,
+
+    /// A master found using redis sentinel. After a failover, the new master is used.
+    Sentinel,
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+fn default_connect_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_response_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_health_check_interval_seconds() -> u64 {
+    30
+}
+
+impl RedisConfig {
+    /// Connects to redis. Every command sent using the pool is traced as a client span.
+    ///
+    /// Use like this: `let value: Option<String> = pool.get().await?.get("key").await?`
+    pub async fn connect(&self) -> Result<RedisPool, RedisError> {
+        let connect_timeout = Duration::from_millis(self.connect_timeout_ms);
+        let response_timeout = Duration::from_millis(self.response_timeout_ms);
+        let pool_size = self.pool_size.max(1);
+
+        let mut slots = Vec::with_capacity(pool_size);
+
+        startup_base::sbom::register_backend("redis", format!("{:?}", self.mode).to_lowercase());
+
+        match self.mode {
+            RedisMode::Standalone => {
+                info!("Connecting to redis");
+
+                let client = redis::Client::open(self.connection_info(&self.url)?)?;
+
+                let config = ConnectionManagerConfig::new()
+                    .set_connection_timeout(connect_timeout)
+                    .set_response_timeout(response_timeout);
+
+                for _ in 0..pool_size {
+                    let manager = client.get_connection_manager_with_config(config.clone()).await?;
+                    slots.push(pool::Slot::Standalone(manager.into()));
+                }
+            }
+
+            RedisMode::Cluster => {
+                info!("Connecting to redis cluster");
+
+                let nodes = match self.nodes.is_empty() {
+                    true => vec![self.connection_info(&self.url)?],
+                    false => self.nodes_info()?,
+                };
+
+                let client = ClusterClientBuilder::new(nodes)
+                    .connection_timeout(connect_timeout)
+                    .response_timeout(response_timeout)
+                    .build()?;
+
+                for _ in 0..pool_size {
+                    slots.push(pool::Slot::Cluster(client.get_async_connection().await?));
+                }
+            }
+
+            RedisMode::Sentinel => {
+                let Some(master_name) = &self.master_name else {
+                    return Err(RedisError::from((
+                        ErrorKind::InvalidClientConfig,
+                        "master_name is required in sentinel mode",
+                    )));
+                };
+
+                info!("Connecting to redis master {:?} using sentinel", master_name);
+
+                let master = self.connection_info(&self.url)?;
+
+                let tls_mode = match master.addr {
+                    ConnectionAddr::TcpTls { insecure: true, .. } => Some(TlsMode::Insecure),
+                    ConnectionAddr::TcpTls { insecure: false, .. } => Some(TlsMode::Secure),
+                    _ => None,
+                };
+
+                let node_connection_info = SentinelNodeConnectionInfo {
+                    tls_mode,
+                    redis_connection_info: Some(master.redis),
+                };
+
+                let config = AsyncConnectionConfig::new()
+                    .set_connection_timeout(connect_timeout)
+                    .set_response_timeout(response_timeout);
+
+                for _ in 0..pool_size {
+                    let client = SentinelClient::build(
+                        self.nodes_info()?,
+                        master_name.clone(),
+                        Some(node_connection_info.clone()),
+                        SentinelServerType::Master,
+                    )?;
+
+                    let slot = pool::SentinelSlot::new(client, config.clone());
+                    slot.connection().await?;
+
+                    slots.push(pool::Slot::Sentinel(slot.into()));
+                }
+            }
+        }
+
+        let health_check_interval = Duration::from_secs(self.health_check_interval_seconds);
+        Ok(RedisPool::new(slots, health_check_interval))
+    }
+
+    /// Connects to redis with a single connection and sends a `PING`, e.g. as check of a
+    /// [Preflight](startup_base::preflight::Preflight).
+    pub async fn preflight(&self) -> Result<(), RedisError> {
+        let config = RedisConfig {
+            pool_size: 1,
+            ..self.clone()
+        };
+
+        config.connect().await?.ping().await
+    }
+
+    fn connection_info(&self, url: &str) -> Result<ConnectionInfo, RedisError> {
+        let mut info = url.into_connection_info()?;
+
+        if let ConnectionAddr::TcpTls { insecure, .. } = &mut info.addr {
+            *insecure |= self.accept_invalid_certs;
+        }
+
+        Ok(info)
+    }
+
+    fn nodes_info(&self) -> Result<Vec<ConnectionInfo>, RedisError> {
+        self.nodes.iter().map(|url| self.connection_info(url)).collect()
+    }
+}
@@ -0,0 +1,260 @@
+use std::time::{Duration, Instant};
+
+use redis::{RedisResult, Script};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::RedisPool;
+
+lazy_static::lazy_static! {
+    /// Takes the lock if it is free and increments the fencing token.
+    static ref ACQUIRE: Script = Script::new(r"
+        if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+            return redis.call('INCR', KEYS[2])
+        end
+        return false
+    ");
+
+    /// Extends the lease, but only if we still own the lock.
+    static ref RENEW: Script = Script::new(r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+        end
+        return 0
+    ");
+
+    /// Deletes the lock, but only if we still own it.
+    static ref RELEASE: Script = Script::new(r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+    ");
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockConfig {
+    /// Time until the lock expires if its owner stops renewing it, e.g. because the process died.
+    #[serde(default = "default_lease_ms")]
+    pub lease_ms: u64,
+
+    /// Interval to check if the lock became free while waiting for it.
+    #[serde(default = "default_retry_interval_ms")]
+    pub retry_interval_ms: u64,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            lease_ms: default_lease_ms(),
+            retry_interval_ms: default_retry_interval_ms(),
+        }
+    }
+}
+
+fn default_lease_ms() -> u64 {
+    30_000
+}
+
+fn default_retry_interval_ms() -> u64 {
+    100
+}
+
+/// A lock shared by all replicas of a service. The lock is held for a lease that is renewed
+/// in the background while the [LockGuard] is alive, so a crashed owner can not block the
+/// lock forever.
+///
+/// A lease can still be lost, e.g. after a long gc pause or a network partition. Every
+/// acquisition returns a fencing token that is larger than all tokens before. Pass it to the
+/// systems you modify while holding the lock, so they can reject writes of a stale owner.
+///
+/// Use like this:
+/// ```ignore
+/// let lock = DistributedLock::new(&pool, "billing-run", &LockConfig::default());
+///
+/// let guard = lock.acquire().await?;
+/// run_billing(guard.fencing_token()).await?;
+/// guard.release().await?;
+/// ```
+#[derive(Clone)]
+pub struct DistributedLock {
+    pool: RedisPool,
+    key: String,
+    fencing_key: String,
+    lease: Duration,
+    retry_interval: Duration,
+}
+
+impl DistributedLock {
+    pub fn new(pool: &RedisPool, name: &str, config: &LockConfig) -> Self {
+        // the hash tag keeps both keys in the same slot in cluster mode
+        Self {
+            pool: pool.clone(),
+            key: format!("lock:{{{}}}", name),
+            fencing_key: format!("lock:{{{}}}:fencing", name),
+            lease: Duration::from_millis(config.lease_ms.max(1)),
+            retry_interval: Duration::from_millis(config.retry_interval_ms.max(1)),
+        }
+    }
+
+    /// Takes the lock if it is free, returns `None` if it is held by someone else.
+    pub async fn try_acquire(&self) -> RedisResult<Option<LockGuard>> {
+        let owner = format!("{:032x}", rand::random::<u128>());
+
+        let token: Option<u64> = ACQUIRE
+            .key(&self.key)
+            .key(&self.fencing_key)
+            .arg(&owner)
+            .arg(self.lease.as_millis() as u64)
+            .invoke_async(&mut self.pool.get().await?)
+            .await?;
+
+        let Some(token) = token else {
+            return Ok(None);
+        };
+
+        debug!("Acquired lock {} with fencing token {}", self.key, token);
+
+        let lost = CancellationToken::new();
+        let renewal = tokio::spawn(renew(self.clone(), owner.clone(), lost.clone()));
+
+        Ok(Some(LockGuard {
+            lock: self.clone(),
+            owner,
+            token,
+            lost,
+            renewal,
+            released: false,
+        }))
+    }
+
+    /// Waits until the lock is free and takes it. Wrap the call in
+    /// [tokio::time::timeout] to limit the time to wait.
+    pub async fn acquire(&self) -> RedisResult<LockGuard> {
+        loop {
+            if let Some(guard) = self.try_acquire().await? {
+                return Ok(guard);
+            }
+
+            tokio::time::sleep(self.retry_interval).await;
+        }
+    }
+
+    async fn release(&self, owner: &str) -> RedisResult<()> {
+        let _: u64 = RELEASE
+            .key(&self.key)
+            .arg(owner)
+            .invoke_async(&mut self.pool.get().await?)
+            .await?;
+
+        debug!("Released lock {}", self.key);
+
+        Ok(())
+    }
+}
+
+/// Renews the lease until the guard is dropped. The lock is lost if someone else owns it
+/// or if renewing failed for longer than the lease.
+async fn renew(lock: DistributedLock, owner: String, lost: CancellationToken) {
+    let mut renewed = Instant::now();
+
+    loop {
+        tokio::time::sleep(lock.lease / 3).await;
+
+        let result: RedisResult<u64> = async {
+            RENEW
+                .key(&lock.key)
+                .arg(&owner)
+                .arg(lock.lease.as_millis() as u64)
+                .invoke_async(&mut lock.pool.get().await?)
+                .await
+        }
+        .await;
+
+        match result {
+            Ok(1) => renewed = Instant::now(),
+
+            Ok(_) => {
+                warn!("Lost lock {}, it is owned by someone else", lock.key);
+                break;
+            }
+
+            Err(err) if renewed.elapsed() >= lock.lease => {
+                warn!("Lost lock {}, renewing the lease failed: {}", lock.key, err);
+                break;
+            }
+
+            Err(err) => warn!("Failed to renew lease of lock {}, retrying: {}", lock.key, err),
+        }
+    }
+
+    lost.cancel();
+}
+
+/// Holds a [DistributedLock] until it is released or dropped. Dropping the
+/// guard releases the lock in the background.
+pub struct LockGuard {
+    lock: DistributedLock,
+    owner: String,
+    token: u64,
+    lost: CancellationToken,
+    renewal: JoinHandle<()>,
+    released: bool,
+}
+
+impl LockGuard {
+    /// Increases with every acquisition of the lock.
+    pub fn fencing_token(&self) -> u64 {
+        self.token
+    }
+
+    /// Returns true if the lease could not be renewed. Stop working on the protected resource.
+    pub fn is_lost(&self) -> bool {
+        self.lost.is_cancelled()
+    }
+
+    /// Completes when the lease could not be renewed, e.g. to cancel the work using `select!`.
+    pub async fn lost(&self) {
+        self.lost.cancelled().await
+    }
+
+    /// Releases the lock, so others can take it immediately.
+    pub async fn release(mut self) -> RedisResult<()> {
+        self.released = true;
+        self.renewal.abort();
+        self.lock.release(&self.owner).await
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.renewal.abort();
+
+        if self.released || self.lost.is_cancelled() {
+            return;
+        }
+
+        // dropped outside of a runtime, e.g. after it shut down
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            debug!(
+                "Lock {} expires after its lease, it was dropped outside of a runtime",
+                self.lock.key
+            );
+            return;
+        };
+
+        let lock = self.lock.clone();
+        let owner = std::mem::take(&mut self.owner);
+
+        handle.spawn(async move {
+            if let Err(err) = lock.release(&owner).await {
+                warn!(
+                    "Failed to release lock {}, it expires after its lease: {}",
+                    lock.key, err
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use redis::RedisResult;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use startup_http::{is_path_prefix, WebError};
+use startup_jwt::Jwt;
+use tracing::{debug, warn};
+
+use crate::{RateLimitConfig, RateLimitDecision, RateLimiter, RedisPool};
+
+static RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+static RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+static RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitPolicyConfig {
+    /// Groups of routes by name, as prefixes of the request path,
+    /// e.g. `search: [/api/search, /api/suggest]`.
+    #[serde(default)]
+    pub route_groups: BTreeMap<String, Vec<String>>,
+
+    /// Limits of individual principals, e.g. a customer with a contract for more requests.
+    /// The first matching override applies instead of the policies.
+    #[serde(default)]
+    pub overrides: Vec<RateLimitPolicy>,
+
+    /// The first matching policy applies.
+    #[serde(default)]
+    pub policies: Vec<RateLimitPolicy>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitPolicy {
+    /// Name of the policy, part of the redis keys of its limits.
+    pub name: String,
+
+    /// Claims of the token the policy applies to, e.g. `scope: api:premium` or `site: de`.
+    /// All claims must match. A claim matches if it equals the value, or contains it if it
+    /// is a list or a space separated string like `scope`. Empty applies to all tokens.
+    #[serde(default)]
+    pub claims: BTreeMap<String, Value>,
+
+    /// Route groups the policy applies to. Empty applies to all routes.
+    #[serde(default)]
+    pub route_groups: Vec<String>,
+
+    /// Claim identifying who is limited, e.g. `customerNumber` for a limit per customer.
+    /// Tokens without this claim are not limited by the policy.
+    #[serde(default = "default_key_claim")]
+    pub key_claim: String,
+
+    /// Requests allowed within a long window, e.g. 1000 per hour.
+    pub sustained: RateLimitConfig,
+
+    /// Requests allowed within a short window, e.g. 20 per second, so the sustained
+    /// limit can not be used up in a single spike.
+    #[serde(default)]
+    pub burst: Option<RateLimitConfig>,
+}
+
+fn default_key_claim() -> String {
+    "customerNumber".to_string()
+}
+
+/// Enforces the rate limit policies of a [RateLimitPolicyConfig] using the claims of the bearer
+/// token, validated by the [JwtAuth](startup_jwt::JwtAuth) layer. The limits are shared by all
+/// replicas, see [RateLimiter].
+///
+/// Requests over the limit are answered with `429 Too Many Requests` and a `Retry-After` header,
+/// allowed requests get the `RateLimit-Limit` and `RateLimit-Remaining` headers of the sustained
+/// limit. Requests without a valid token are passed on, so the authorization layers can answer
+/// them. If redis can not be reached, requests are allowed.
+///
+/// Use like this: `router.layer(RateLimitLayer::new(&pool, &config.rate_limits)?).layer(jwt_auth.into_layer())`
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    policies: Arc<Policies>,
+}
+
+struct Policies {
+    route_groups: BTreeMap<String, Vec<String>>,
+
+    // overrides first, then the policies
+    limits: Vec<Limits>,
+}
+
+struct Limits {
+    policy: RateLimitPolicy,
+    sustained: RateLimiter,
+    burst: Option<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    /// Fails if a policy has a limit of zero.
+    pub fn new(pool: &RedisPool, config: &RateLimitPolicyConfig) -> RedisResult<Self> {
+        let limits = config
+            .overrides
+            .iter()
+            .chain(&config.policies)
+            .map(|policy| {
+                for group in &policy.route_groups {
+                    if !config.route_groups.contains_key(group) {
+                        warn!(
+                            "Rate limit policy {} refers to unknown route group {}",
+                            policy.name, group
+                        );
+                    }
+                }
+
+                Ok(Limits {
+                    policy: policy.clone(),
+                    sustained: RateLimiter::new(pool, &format!("{}:sustained", policy.name), &policy.sustained)?,
+                    burst: policy
+                        .burst
+                        .as_ref()
+                        .map(|burst| RateLimiter::new(pool, &format!("{}:burst", policy.name), burst))
+                        .transpose()?,
+                })
+            })
+            .collect::<RedisResult<_>>()?;
+
+        let policies = Policies {
+            route_groups: config.route_groups.clone(),
+            limits,
+        };
+
+        Ok(Self {
+            policies: Arc::new(policies),
+        })
+    }
+}
+
+impl Policies {
+    /// Checks the limits of the first policy that applies to the request.
+    async fn check(&self, parts: &mut Parts) -> Option<(&str, RateLimitDecision)> {
+        let Ok(Jwt(claims)) = Jwt::<Value>::from_request_parts(parts, &()).await else {
+            return None;
+        };
+
+        let path = parts.uri.path();
+
+        let groups: Vec<&str> = self
+            .route_groups
+            .iter()
+            .filter(|(_, prefixes)| prefixes.iter().any(|prefix| is_path_prefix(prefix, path)))
+            .map(|(group, _)| group.as_str())
+            .collect();
+
+        let (limits, key) = self.limits.iter().find_map(|limits| {
+            let policy = &limits.policy;
+
+            if !policy.route_groups.is_empty()
+                && !policy.route_groups.iter().any(|group| groups.contains(&group.as_str()))
+            {
+                return None;
+            }
+
+            if !policy
+                .claims
+                .iter()
+                .all(|(claim, value)| claim_matches(claims.get(claim), value))
+            {
+                return None;
+            }
+
+            Some((limits, key(claims.get(&policy.key_claim))?))
+        })?;
+
+        match limits.check(&key).await {
+            Ok(decision) => Some((&limits.policy.name, decision)),
+            Err(err) => {
+                warn!("Failed to check rate limit of policy {}: {}", limits.policy.name, err);
+                None
+            }
+        }
+    }
+}
+
+impl Limits {
+    /// Checks the burst limit first, so requests denied by it do not count against the sustained limit.
+    async fn check(&self, key: &str) -> redis::RedisResult<RateLimitDecision> {
+        if let Some(burst) = &self.burst {
+            let decision = burst.check(key).await?;
+            if !decision.allowed {
+                return Ok(decision);
+            }
+        }
+
+        self.sustained.check(key).await
+    }
+}
+
+/// Checks if the claim equals the value, or contains it if the claim is a list or a space separated string.
+fn claim_matches(claim: Option<&Value>, value: &Value) -> bool {
+    let Some(value) = key(Some(value)) else {
+        return false;
+    };
+
+    match claim {
+        Some(Value::String(claim)) => claim == &value || claim.split_whitespace().any(|claim| claim == value),
+        Some(Value::Array(claims)) => claims.iter().any(|claim| key(Some(claim)).as_ref() == Some(&value)),
+        claim => key(claim).as_ref() == Some(&value),
+    }
+}
+
+/// The value of a claim as used in the keys of the limits.
+fn key(claim: Option<&Value>) -> Option<String> {
+    match claim? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+impl<S> tower_layer::Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            policies: self.policies.clone(),
+        }
+    }
+}
+
+/// Middleware created by [RateLimitLayer].
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    policies: Arc<Policies>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for RateLimitService<S>
+where
+    S: tower_service::Service<Request<B>> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // the inner service was polled ready, keep it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let policies = self.policies.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+
+            let Some((policy, decision)) = policies.check(&mut parts).await else {
+                return Ok(inner.call(Request::from_parts(parts, body)).await?.into_response());
+            };
+
+            if !decision.allowed {
+                debug!("Rate limit of policy {} exceeded", policy);
+                return Ok(too_many_requests(policy, &decision));
+            }
+
+            let mut response = inner.call(Request::from_parts(parts, body)).await?.into_response();
+            insert_limit(response.headers_mut(), &decision);
+
+            Ok(response)
+        })
+    }
+}
+
+fn too_many_requests(policy: &str, decision: &RateLimitDecision) -> Response {
+    let message = format!("rate limit of policy {} exceeded", policy);
+    let mut response = WebError::Response(StatusCode::TOO_MANY_REQUESTS, message).into_response();
+
+    // whole seconds, rounded up so the client does not retry too early
+    let retry_after = HeaderValue::from(decision.retry_after.as_millis().div_ceil(1000) as u64);
+
+    let headers = response.headers_mut();
+    insert_limit(headers, decision);
+    headers.insert(header::RETRY_AFTER, retry_after.clone());
+    headers.insert(RATELIMIT_RESET.clone(), retry_after);
+
+    response
+}
+
+fn insert_limit(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    headers.insert(RATELIMIT_LIMIT.clone(), HeaderValue::from(decision.limit));
+    headers.insert(RATELIMIT_REMAINING.clone(), HeaderValue::from(decision.remaining));
+}
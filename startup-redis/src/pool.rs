@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use redis::aio::{ConnectionManager, MultiplexedConnection};
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::SentinelClient;
+use redis::{AsyncConnectionConfig, RedisResult};
+use tracing::{debug, info, warn};
+
+use crate::connection::{Backend, RedisConnection};
+
+/// A fixed number of multiplexed connections to redis, handed out round-robin.
+/// The connections are checked using `PING` in the background.
+///
+/// Cloning the pool is cheap, all clones share the same connections.
+#[derive(Clone)]
+pub struct RedisPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    slots: Vec<Slot>,
+    next: AtomicUsize,
+}
+
+pub(crate) enum Slot {
+    Standalone(Box<ConnectionManager>),
+    Cluster(ClusterConnection),
+    Sentinel(Arc<SentinelSlot>),
+}
+
+impl RedisPool {
+    pub(crate) fn new(slots: Vec<Slot>, health_check_interval: Duration) -> Self {
+        let inner = Arc::new(Inner {
+            slots,
+            next: AtomicUsize::new(0),
+        });
+
+        tokio::spawn(health_check(Arc::downgrade(&inner), health_check_interval));
+
+        Self { inner }
+    }
+
+    /// Returns the next connection of the pool.
+    pub async fn get(&self) -> RedisResult<RedisConnection> {
+        let idx = self.inner.next.fetch_add(1, Ordering::Relaxed) % self.inner.slots.len();
+        let backend = self.inner.slots[idx].backend().await?;
+        Ok(RedisConnection::new(backend))
+    }
+
+    /// Sends a `PING` using every connection of the pool, e.g. for a readiness check.
+    pub async fn ping(&self) -> RedisResult<()> {
+        for slot in &self.inner.slots {
+            slot.ping().await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Slot {
+    async fn backend(&self) -> RedisResult<Backend> {
+        let backend = match self {
+            Slot::Standalone(connection) => Backend::Standalone(connection.as_ref().clone()),
+            Slot::Cluster(connection) => Backend::Cluster(connection.clone()),
+            Slot::Sentinel(slot) => Backend::Sentinel(slot.connection().await?, slot.clone()),
+        };
+
+        Ok(backend)
+    }
+
+    /// Pings without tracing, health checks should not show up as spans.
+    async fn ping(&self) -> RedisResult<()> {
+        self.backend().await?.send(&redis::cmd("PING")).await?;
+        Ok(())
+    }
+}
+
+async fn health_check(pool: Weak<Inner>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // the first tick completes immediately, the connections were just established
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        // stop checking as soon as the pool was dropped
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+
+        for (idx, slot) in pool.slots.iter().enumerate() {
+            match slot.ping().await {
+                Ok(()) => debug!("Redis connection {} is healthy", idx),
+                Err(err) => warn!("Health check of redis connection {} failed: {}", idx, err),
+            }
+        }
+    }
+}
+
+/// Holds the connection to the master found using the sentinels.
+/// After the connection was reset, the next call reconnects.
+pub(crate) struct SentinelSlot {
+    client: tokio::sync::Mutex<SentinelClient>,
+    config: AsyncConnectionConfig,
+    connection: Mutex<Option<MultiplexedConnection>>,
+}
+
+impl SentinelSlot {
+    pub(crate) fn new(client: SentinelClient, config: AsyncConnectionConfig) -> Self {
+        Self {
+            client: tokio::sync::Mutex::new(client),
+            config,
+            connection: Mutex::new(None),
+        }
+    }
+
+    pub(crate) async fn connection(&self) -> RedisResult<MultiplexedConnection> {
+        if let Some(connection) = self.connection.lock().clone() {
+            return Ok(connection);
+        }
+
+        let mut client = self.client.lock().await;
+
+        // another task might have reconnected while we were waiting for the client
+        if let Some(connection) = self.connection.lock().clone() {
+            return Ok(connection);
+        }
+
+        info!("Connecting to redis master using sentinel");
+        let connection = client.get_async_connection_with_config(&self.config).await?;
+
+        *self.connection.lock() = Some(connection.clone());
+
+        Ok(connection)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.connection.lock().take();
+    }
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use redis::{ErrorKind, RedisError, RedisResult, Script};
+use serde::{Deserialize, Serialize};
+use startup_base::clock::Clock;
+
+use crate::RedisPool;
+
+lazy_static::lazy_static! {
+    /// Sliding window log: every request in the window is a member of a sorted set, scored by
+    /// the time of the request in microseconds. The time of the redis server is used, so the
+    /// clocks of the replicas do not matter, unless the time is passed in by a [Clock].
+    static ref CHECK: Script = Script::new(r"
+        redis.replicate_commands()
+
+        local now = tonumber(ARGV[4])
+        if not now then
+            local time = redis.call('TIME')
+            now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+        end
+        local window = tonumber(ARGV[1])
+        local limit = tonumber(ARGV[2])
+
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+
+        local count = redis.call('ZCARD', KEYS[1])
+        if count < limit then
+            redis.call('ZADD', KEYS[1], now, ARGV[3])
+            redis.call('PEXPIRE', KEYS[1], math.ceil(window / 1000))
+            return {1, limit - count - 1, 0}
+        end
+
+        local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+        return {0, 0, tonumber(oldest[2]) + window - now}
+    ");
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Requests allowed per key within the window, at least one.
+    pub limit: u64,
+
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: u64,
+}
+
+fn default_window_seconds() -> u64 {
+    60
+}
+
+/// Result of [RateLimiter::check], with everything needed for
+/// the `RateLimit-*` and `Retry-After` response headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u64,
+
+    /// Requests still allowed within the current window.
+    pub remaining: u64,
+
+    /// Time until the next request is allowed. Zero if the request was allowed.
+    pub retry_after: Duration,
+}
+
+/// Limits the requests per key within a sliding window, shared by all replicas of a service.
+/// Unlike a fixed window, a client can not send twice the limit around the window boundary.
+///
+/// Every allowed request is stored until it leaves the window, so keep the limits moderate.
+///
+/// Use like this:
+/// ```ignore
+/// let limiter = RateLimiter::new(&pool, "login", &RateLimitConfig { limit: 10, window_seconds: 60 })?;
+///
+/// let decision = limiter.check(&client_ip.to_string()).await?;
+/// if !decision.allowed {
+///     return Err(StatusCode::TOO_MANY_REQUESTS);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RateLimiter {
+    pool: RedisPool,
+    prefix: String,
+    limit: u64,
+    window: Duration,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl RateLimiter {
+    /// Fails if the limit is zero, which would deny every request.
+    pub fn new(pool: &RedisPool, name: &str, config: &RateLimitConfig) -> RedisResult<Self> {
+        if config.limit == 0 {
+            return Err(RedisError::from((
+                ErrorKind::InvalidClientConfig,
+                "limit of rate limiter must be at least 1",
+                name.to_string(),
+            )));
+        }
+
+        Ok(Self {
+            pool: pool.clone(),
+            prefix: format!("ratelimit:{}:", name),
+            limit: config.limit,
+            window: Duration::from_secs(config.window_seconds.max(1)),
+            clock: None,
+        })
+    }
+
+    /// Uses the time of the clock instead of the time of the redis server, e.g. a `ManualClock`
+    /// in tests. All replicas sharing a limit should use the same kind of clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Counts a request for the key, if it is allowed. Denied requests are not counted.
+    pub async fn check(&self, key: &str) -> RedisResult<RateLimitDecision> {
+        let request_id = format!("{:032x}", rand::random::<u128>());
+
+        let mut invocation = CHECK.key(format!("{}{}", self.prefix, key));
+
+        invocation
+            .arg(self.window.as_micros() as u64)
+            .arg(self.limit)
+            .arg(request_id);
+
+        if let Some(clock) = &self.clock {
+            let now = clock.now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            invocation.arg(now.as_micros() as u64);
+        }
+
+        let (allowed, remaining, retry_after_micros): (bool, u64, u64) =
+            invocation.invoke_async(&mut self.pool.get().await?).await?;
+
+        Ok(RateLimitDecision {
+            allowed,
+            limit: self.limit,
+            remaining,
+            retry_after: Duration::from_micros(retry_after_micros),
+        })
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use redis::RedisError;
+use startup_http::session::{SessionData, SessionError, SessionStore};
+
+use crate::RedisPool;
+
+/// Keeps the sessions of the [SessionLayer](startup_http::session::SessionLayer) in redis,
+/// shared by all replicas. The data of a session is stored as json, redis removes it once
+/// the session expired.
+///
+/// Use like this: `router.layer(SessionLayer::new(&config.session, RedisSessionStore::new(&pool)))`
+pub struct RedisSessionStore {
+    pool: RedisPool,
+    prefix: String,
+}
+
+impl RedisSessionStore {
+    pub fn new(pool: &RedisPool) -> Self {
+        Self {
+            pool: pool.clone(),
+            prefix: "session:".to_string(),
+        }
+    }
+
+    /// Prefix of the redis keys of the sessions, defaults to `session:`.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{}", self.prefix, id)
+    }
+}
+
+fn store_error(err: RedisError) -> SessionError {
+    SessionError::Store(err.into())
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, SessionError> {
+        let mut conn = self.pool.get().await.map_err(store_error)?;
+
+        let data: Option<String> = redis::cmd("GET")
+            .arg(self.key(id))
+            .query_async(&mut conn)
+            .await
+            .map_err(store_error)?;
+
+        let Some(data) = data else {
+            return Ok(None);
+        };
+
+        serde_json::from_str(&data).map_err(|err| SessionError::Store(err.into()))
+    }
+
+    async fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), SessionError> {
+        let data = serde_json::to_string(data).map_err(|err| SessionError::Store(err.into()))?;
+
+        let mut conn = self.pool.get().await.map_err(store_error)?;
+
+        redis::cmd("SET")
+            .arg(self.key(id))
+            .arg(data)
+            .arg("EX")
+            .arg(ttl.as_secs().max(1))
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(store_error)
+    }
+
+    async fn touch(&self, id: &str, ttl: Duration) -> Result<(), SessionError> {
+        let mut conn = self.pool.get().await.map_err(store_error)?;
+
+        redis::cmd("EXPIRE")
+            .arg(self.key(id))
+            .arg(ttl.as_secs().max(1))
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(store_error)
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), SessionError> {
+        let mut conn = self.pool.get().await.map_err(store_error)?;
+
+        redis::cmd("DEL")
+            .arg(self.key(id))
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(store_error)
+    }
+}
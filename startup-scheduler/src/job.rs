@@ -0,0 +1,175 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use rand::Rng;
+use startup_base::clock::Clock;
+use tokio_util::sync::CancellationToken;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use crate::{JobFn, LeaderElection};
+
+pub(crate) enum Schedule {
+    Cron(Box<cron::Schedule>),
+    Interval(Duration),
+}
+
+impl Schedule {
+    /// Time until the next run, given the start of the previous run.
+    fn delay(&self, previous: Instant, clock: &dyn Clock) -> Duration {
+        match self {
+            Schedule::Cron(schedule) => {
+                let now = DateTime::<Utc>::from(clock.now());
+
+                schedule
+                    .after(&now)
+                    .next()
+                    .and_then(|next| (next - now).to_std().ok())
+                    .unwrap_or(Duration::MAX)
+            }
+
+            Schedule::Interval(interval) => (previous + *interval).saturating_duration_since(clock.instant()),
+        }
+    }
+}
+
+pub(crate) struct Job {
+    pub(crate) name: String,
+    pub(crate) f: JobFn,
+    pub(crate) schedule: Schedule,
+    pub(crate) jitter: Duration,
+    pub(crate) leader: Option<Arc<dyn LeaderElection>>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl Job {
+    /// Runs the job on its schedule until the scheduler stops. A running job is not interrupted.
+    pub(crate) async fn schedule(self, metrics: Arc<Metrics>, stop: CancellationToken) {
+        let mut previous = self.clock.instant();
+
+        loop {
+            let jitter = match self.jitter.is_zero() {
+                true => Duration::ZERO,
+                false => rand::thread_rng().gen_range(Duration::ZERO..=self.jitter),
+            };
+
+            let delay = self
+                .schedule
+                .delay(previous, self.clock.as_ref())
+                .saturating_add(jitter);
+            debug!("Next run of job {} in {:?}", self.name, delay);
+
+            tokio::select! {
+                _ = stop.cancelled() => return,
+                _ = self.clock.sleep(delay) => {},
+            }
+
+            previous = self.clock.instant();
+
+            if let Some(leader) = &self.leader {
+                if !leader.is_leader() {
+                    debug!("Skipping job {}, this replica is not the leader", self.name);
+                    metrics.record(&self.name, "skipped", None);
+                    continue;
+                }
+            }
+
+            self.run(&metrics).await;
+        }
+    }
+
+    async fn run(&self, metrics: &Metrics) {
+        let span = info_span!(
+            parent: None,
+            "scheduled_job",
+            otel.name = %self.name,
+            otel.kind = "internal",
+            otel.status_code = Empty,
+            job.name = %self.name,
+        );
+
+        info!(parent: &span, "Running job {}", self.name);
+
+        let started = Instant::now();
+
+        let result = AssertUnwindSafe((self.f)().instrument(span.clone()))
+            .catch_unwind()
+            .await;
+
+        let elapsed = started.elapsed();
+
+        let result = match result {
+            Ok(Ok(())) => {
+                info!(parent: &span, "Job {} finished after {:?}", self.name, elapsed);
+                "success"
+            }
+
+            Ok(Err(err)) => {
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: &span, "Job {} failed after {:?}: {:?}", self.name, elapsed, err);
+                "failure"
+            }
+
+            Err(panic) => {
+                span.record("otel.status_code", "ERROR");
+                error!(parent: &span, "Job {} panicked after {:?}: {}", self.name, elapsed, panic_message(&panic));
+                "failure"
+            }
+        };
+
+        metrics.record(&self.name, result, Some(elapsed));
+    }
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return message;
+    }
+
+    match panic.downcast_ref::<String>() {
+        Some(message) => message,
+        None => "unknown panic",
+    }
+}
+
+pub(crate) struct Metrics {
+    runs: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Arc<Self> {
+        let meter = global::meter("startup-scheduler");
+
+        let runs = meter
+            .u64_counter("scheduler.runs")
+            .with_description("Scheduled runs of jobs by result")
+            .init();
+
+        let duration = meter
+            .f64_histogram("scheduler.duration")
+            .with_description("Duration of job runs")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Arc::new(Self { runs, duration })
+    }
+
+    fn record(&self, job: &str, result: &'static str, elapsed: Option<Duration>) {
+        let context = opentelemetry::Context::current();
+
+        let attributes = [KeyValue::new("job", job.to_string()), KeyValue::new("result", result)];
+
+        self.runs.add(&context, 1, &attributes);
+
+        if let Some(elapsed) = elapsed {
+            self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use eyre::WrapErr;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use startup_base::clock::{self, Clock};
+use tokio_util::sync::CancellationToken;
+
+use crate::job::{Job, Metrics, Schedule};
+
+mod job;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchedulerConfig {
+    /// Schedules of the jobs by name.
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobConfig {
+    /// Cron expression including seconds, e.g. `0 */15 * * * *` for every 15 minutes. Times are in UTC.
+    #[serde(default)]
+    pub cron: Option<String>,
+
+    /// Run the job in a fixed interval instead, measured from the start of the previous run.
+    #[serde(default)]
+    pub interval_seconds: Option<u64>,
+
+    /// Delay every run by a random time up to this, so that
+    /// the replicas of a service do not all start at the same time.
+    #[serde(default)]
+    pub jitter_seconds: u64,
+
+    /// Run the job only on the leader, see [Scheduler::leader_election].
+    #[serde(default)]
+    pub leader_only: bool,
+
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Decides if this replica is the leader and runs the jobs marked as `leader_only`.
+pub trait LeaderElection: Send + Sync + 'static {
+    fn is_leader(&self) -> bool;
+}
+
+#[cfg(feature = "redis")]
+impl LeaderElection for startup_redis::Leadership {
+    fn is_leader(&self) -> bool {
+        startup_redis::Leadership::is_leader(self)
+    }
+}
+
+type JobFn = Arc<dyn Fn() -> BoxFuture<'static, eyre::Result<()>> + Send + Sync>;
+
+/// Runs jobs on the schedules from the [SchedulerConfig]. Every run is traced as its own trace,
+/// and the metrics `scheduler.runs` and `scheduler.duration` record every run per job and result.
+/// Failed runs and panics are logged and the job runs again at its next scheduled time.
+///
+/// A job never runs concurrently with itself. If a run takes longer than the schedule, the
+/// scheduled times that passed in the meantime are skipped.
+///
+/// Use like this:
+/// ```ignore
+/// Scheduler::new(&config.scheduler)
+///     .job("cleanup", move || cleanup(pool.clone()))
+///     .run(async { tokio::signal::ctrl_c().await.unwrap_or_default() })
+///     .await?;
+/// ```
+pub struct Scheduler {
+    config: SchedulerConfig,
+    jobs: Vec<(String, JobFn)>,
+    leader: Option<Arc<dyn LeaderElection>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Scheduler {
+    pub fn new(config: &SchedulerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            jobs: Vec::new(),
+            leader: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Registers a job. Its schedule is taken from the config entry with the same name.
+    pub fn job<F, Fut>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        let f: JobFn = Arc::new(move || Box::pin(f()));
+        self.jobs.push((name.to_string(), f));
+        self
+    }
+
+    /// Sets the leader election used by jobs marked as `leader_only`, e.g. a
+    /// `startup_redis::Leadership` with the `redis` feature enabled.
+    pub fn leader_election(mut self, leader: impl LeaderElection) -> Self {
+        self.leader = Some(Arc::new(leader));
+        self
+    }
+
+    /// Schedules the jobs using the clock, e.g. a `ManualClock` in tests.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Runs the jobs until `shutdown` resolves, e.g. on SIGTERM.
+    /// Waits for running jobs to finish before returning. Fails without running any
+    /// job if a schedule is missing or invalid.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> eyre::Result<()> {
+        for name in self.config.jobs.keys() {
+            if !self.jobs.iter().any(|(job, _)| job == name) {
+                warn!("Job {} is configured, but was never registered", name);
+            }
+        }
+
+        let mut jobs = Vec::with_capacity(self.jobs.len());
+
+        for (name, f) in self.jobs {
+            let config = self
+                .config
+                .jobs
+                .get(&name)
+                .ok_or_else(|| eyre::eyre!("no schedule configured for job {}", name))?;
+
+            if !config.enabled {
+                info!("Job {} is disabled", name);
+                continue;
+            }
+
+            let leader = match config.leader_only {
+                true => Some(
+                    self.leader
+                        .clone()
+                        .ok_or_else(|| eyre::eyre!("job {} is leader only, but no leader election is set", name))?,
+                ),
+                false => None,
+            };
+
+            let schedule = match (&config.cron, config.interval_seconds) {
+                (Some(cron), None) => Schedule::Cron(Box::new(
+                    cron::Schedule::from_str(cron)
+                        .wrap_err_with(|| format!("parse cron expression of job {}", name))?,
+                )),
+
+                (None, Some(seconds)) => Schedule::Interval(Duration::from_secs(seconds.max(1))),
+
+                _ => eyre::bail!("job {} needs either a cron expression or an interval", name),
+            };
+
+            jobs.push(Job {
+                name,
+                f,
+                schedule,
+                jitter: Duration::from_secs(config.jitter_seconds),
+                leader,
+                clock: self.clock.clone(),
+            });
+        }
+
+        let metrics = Metrics::new();
+        let stop = CancellationToken::new();
+
+        let tasks: Vec<_> = jobs
+            .into_iter()
+            .map(|job| tokio::spawn(job.schedule(metrics.clone(), stop.clone())))
+            .collect();
+
+        shutdown.await;
+
+        info!("Stopping scheduler, waiting for running jobs to finish");
+        stop.cancel();
+
+        for task in tasks {
+            task.await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[macro_use]
+extern crate tracing;
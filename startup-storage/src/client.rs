@@ -0,0 +1,380 @@
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::config::timeout::TimeoutConfig;
+use aws_sdk_s3::config::{Credentials, Region};
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::field::Empty;
+use tracing::{debug, info_span, warn, Instrument};
+
+use crate::{StorageConfig, StorageError};
+
+/// An object read from the bucket.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub body: Bytes,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+}
+
+/// A client for a bucket of an S3 compatible object storage. Every operation is traced as a
+/// client span. Requests are retried on transient errors, see [StorageConfig::max_attempts].
+///
+/// The metrics `storage.requests` and `storage.duration` record every operation per bucket,
+/// operation and result.
+///
+/// Use like this: `let invoice: Invoice = storage.get_json("invoices/1.json").await?`
+#[derive(Clone)]
+pub struct Storage {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    requests: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Storage {
+    pub fn new(config: &StorageConfig) -> Result<Self, StorageError> {
+        let credentials = match &config.credentials {
+            Some(credentials) => {
+                let (access_key_id, secret_access_key) = credentials.resolve()?;
+                Credentials::new(access_key_id, secret_access_key, None, None, "startup-storage")
+            }
+
+            None => Credentials::new(
+                env("AWS_ACCESS_KEY_ID")?,
+                env("AWS_SECRET_ACCESS_KEY")?,
+                std::env::var("AWS_SESSION_TOKEN").ok(),
+                None,
+                "startup-storage",
+            ),
+        };
+
+        let timeouts = TimeoutConfig::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+            .operation_timeout(Duration::from_secs(config.timeout_seconds))
+            .build();
+
+        let mut builder = aws_sdk_s3::config::Builder::new()
+            .region(Region::new(config.region.clone()))
+            .credentials_provider(credentials)
+            .force_path_style(config.path_style)
+            .retry_config(RetryConfig::standard().with_max_attempts(config.max_attempts.max(1)))
+            .timeout_config(timeouts);
+
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.endpoint_url(endpoint.as_str().trim_end_matches('/'));
+        }
+
+        let meter = global::meter("startup-storage");
+
+        let requests = meter
+            .u64_counter("storage.requests")
+            .with_description("Operations on the object storage by result")
+            .init();
+
+        let duration = meter
+            .f64_histogram("storage.duration")
+            .with_description("Duration of operations on the object storage, including retries")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        let inner = Inner {
+            client: aws_sdk_s3::Client::from_conf(builder.build()),
+            bucket: config.bucket.clone(),
+            requests,
+            duration,
+        };
+
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Reads the complete object. Returns [StorageError::NotFound] if there is no such object.
+    pub async fn get(&self, key: &str) -> Result<Object, StorageError> {
+        self.traced("GetObject", key, async {
+            let output = self
+                .inner
+                .client
+                .get_object()
+                .bucket(&self.inner.bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|err| match err.as_service_error() {
+                    Some(service_error) if service_error.is_no_such_key() => {
+                        StorageError::NotFound { key: key.to_string() }
+                    }
+                    _ => StorageError::request("GetObject", key, err),
+                })?;
+
+            let content_type = output.content_type;
+            let etag = output.e_tag;
+
+            let body = output
+                .body
+                .collect()
+                .await
+                .map_err(|err| StorageError::request("GetObject", key, err))?
+                .into_bytes();
+
+            Ok(Object {
+                body,
+                content_type,
+                etag,
+            })
+        })
+        .await
+    }
+
+    /// Reads the object and deserializes it from json.
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<T, StorageError> {
+        let object = self.get(key).await?;
+
+        serde_json::from_slice(&object.body).map_err(|source| StorageError::Json {
+            key: key.to_string(),
+            source,
+        })
+    }
+
+    /// Reads the object as a stream of chunks, without keeping it in memory.
+    pub async fn stream(&self, key: &str) -> Result<BoxStream<'static, Result<Bytes, StorageError>>, StorageError> {
+        let body = self
+            .traced("GetObject", key, async {
+                let output = self
+                    .inner
+                    .client
+                    .get_object()
+                    .bucket(&self.inner.bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map_err(|err| match err.as_service_error() {
+                        Some(service_error) if service_error.is_no_such_key() => {
+                            StorageError::NotFound { key: key.to_string() }
+                        }
+                        _ => StorageError::request("GetObject", key, err),
+                    })?;
+
+                Ok(output.body)
+            })
+            .await?;
+
+        let key = key.to_string();
+
+        let chunks = futures_util::stream::unfold(body, move |mut body| {
+            let key = key.clone();
+
+            async move {
+                let chunk = body.next().await?;
+                Some((chunk.map_err(|err| StorageError::request("GetObject", &key, err)), body))
+            }
+        });
+
+        Ok(chunks.boxed())
+    }
+
+    /// Writes the object, replacing an existing object with the same key.
+    pub async fn put(&self, key: &str, body: impl Into<Bytes>, content_type: Option<&str>) -> Result<(), StorageError> {
+        self.put_body(key, ByteStream::from(body.into()), content_type).await
+    }
+
+    /// Serializes the value as json and writes it.
+    pub async fn put_json<T: Serialize>(&self, key: &str, value: &T) -> Result<(), StorageError> {
+        let body = serde_json::to_vec(value).map_err(|source| StorageError::Json {
+            key: key.to_string(),
+            source,
+        })?;
+
+        self.put(key, body, Some("application/json")).await
+    }
+
+    /// Writes the content of a local file, streaming it from disk.
+    pub async fn put_file(&self, key: &str, path: &Path, content_type: Option<&str>) -> Result<(), StorageError> {
+        let body = ByteStream::from_path(path)
+            .await
+            .map_err(|err| StorageError::request("PutObject", key, err))?;
+
+        self.put_body(key, body, content_type).await
+    }
+
+    async fn put_body(&self, key: &str, body: ByteStream, content_type: Option<&str>) -> Result<(), StorageError> {
+        self.traced("PutObject", key, async {
+            self.inner
+                .client
+                .put_object()
+                .bucket(&self.inner.bucket)
+                .key(key)
+                .body(body)
+                .set_content_type(content_type.map(str::to_string))
+                .send()
+                .await
+                .map_err(|err| StorageError::request("PutObject", key, err))?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns true if the object exists.
+    pub async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        self.traced("HeadObject", key, async {
+            let result = self
+                .inner
+                .client
+                .head_object()
+                .bucket(&self.inner.bucket)
+                .key(key)
+                .send()
+                .await;
+
+            match result {
+                Ok(_) => Ok(true),
+                Err(err) if err.as_service_error().is_some_and(|err| err.is_not_found()) => Ok(false),
+                Err(err) => Err(StorageError::request("HeadObject", key, err)),
+            }
+        })
+        .await
+    }
+
+    /// Deletes the object. Deleting an object that does not exist is not an error.
+    pub async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.traced("DeleteObject", key, async {
+            self.inner
+                .client
+                .delete_object()
+                .bucket(&self.inner.bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|err| StorageError::request("DeleteObject", key, err))?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns a url to download the object without credentials, valid for the given time.
+    pub async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, StorageError> {
+        let presigning = PresigningConfig::expires_in(expires_in)
+            .map_err(|err| StorageError::request("PresignGetObject", key, err))?;
+
+        let request = self
+            .inner
+            .client
+            .get_object()
+            .bucket(&self.inner.bucket)
+            .key(key)
+            .presigned(presigning)
+            .await
+            .map_err(|err| StorageError::request("PresignGetObject", key, err))?;
+
+        Ok(request.uri().to_string())
+    }
+
+    /// Returns a url to upload the object with a `PUT` request without credentials, valid for
+    /// the given time. If a content type is given, the upload must use the same content type.
+    pub async fn presign_put(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        content_type: Option<&str>,
+    ) -> Result<String, StorageError> {
+        let presigning = PresigningConfig::expires_in(expires_in)
+            .map_err(|err| StorageError::request("PresignPutObject", key, err))?;
+
+        let request = self
+            .inner
+            .client
+            .put_object()
+            .bucket(&self.inner.bucket)
+            .key(key)
+            .set_content_type(content_type.map(str::to_string))
+            .presigned(presigning)
+            .await
+            .map_err(|err| StorageError::request("PresignPutObject", key, err))?;
+
+        Ok(request.uri().to_string())
+    }
+
+    /// Runs the operation in a client span and records its metrics.
+    async fn traced<T>(
+        &self,
+        operation: &'static str,
+        key: &str,
+        f: impl Future<Output = Result<T, StorageError>>,
+    ) -> Result<T, StorageError> {
+        let span = info_span!(
+            "storage",
+            otel.name = %format!("S3.{}", operation),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            rpc.system = "aws-api",
+            rpc.service = "S3",
+            rpc.method = operation,
+            aws.s3.bucket = %self.inner.bucket,
+            aws.s3.key = %key,
+        );
+
+        let started = Instant::now();
+        let result = f.instrument(span.clone()).await;
+        let elapsed = started.elapsed();
+
+        let outcome = match &result {
+            Ok(_) => "success",
+
+            // a missing object is an answer, not a failed request
+            Err(StorageError::NotFound { .. }) => "not_found",
+
+            Err(err) => {
+                span.record("otel.status_code", "ERROR");
+                warn!(parent: &span, "{} of {} failed after {:?}: {}", operation, key, elapsed, error_chain(err));
+                "failure"
+            }
+        };
+
+        debug!(parent: &span, "{} of {} finished after {:?}", operation, key, elapsed);
+
+        let context = opentelemetry::Context::current();
+        let attributes = [
+            KeyValue::new("bucket", self.inner.bucket.clone()),
+            KeyValue::new("operation", operation),
+            KeyValue::new("result", outcome),
+        ];
+
+        self.inner.requests.add(&context, 1, &attributes);
+        self.inner.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+
+        result
+    }
+}
+
+fn env(name: &'static str) -> Result<String, StorageError> {
+    std::env::var(name).map_err(|_| StorageError::Credentials(name))
+}
+
+/// Formats an error with all its causes, as the sdk errors hide the details in their sources.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+
+    message
+}
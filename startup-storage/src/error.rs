@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("object {key} not found")]
+    NotFound { key: String },
+
+    #[error("{operation} of object {key} failed")]
+    Request {
+        operation: &'static str,
+        key: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("failed to (de)serialize object {key} as json")]
+    Json {
+        key: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("no {0} configured for object storage")]
+    Credentials(&'static str),
+
+    #[error("failed to read secret from {path}")]
+    ReadSecret {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl StorageError {
+    pub(crate) fn request(
+        operation: &'static str,
+        key: &str,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        StorageError::Request {
+            operation,
+            key: key.to_string(),
+            source: Box::new(source),
+        }
+    }
+}
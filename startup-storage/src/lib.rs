@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+pub use crate::client::{Object, Storage};
+pub use crate::error::StorageError;
+
+mod client;
+mod error;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Url of an S3 compatible service like MinIO. Defaults to AWS S3.
+    #[serde(default)]
+    pub endpoint: Option<url::Url>,
+
+    #[serde(default = "default_region")]
+    pub region: String,
+
+    pub bucket: String,
+
+    /// Address the bucket as part of the path instead of the host name, as MinIO needs it.
+    #[serde(default)]
+    pub path_style: bool,
+
+    /// Credentials, see [StorageCredentials]. If not set, the `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment variables are used.
+    #[serde(default)]
+    pub credentials: Option<StorageCredentials>,
+
+    /// Attempts of a request, including the first one. Failed requests are
+    /// retried with an exponential backoff if the error is transient.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+
+    /// Timeout for a complete operation, including all retries. Does not apply to
+    /// reading the body of a streamed object.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+/// Credentials are either given directly or read from files, e.g. mounted secrets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageCredentials {
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+
+    #[serde(default)]
+    pub access_key_id_file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+
+    #[serde(default)]
+    pub secret_access_key_file: Option<PathBuf>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    5
+}
+
+fn default_timeout_seconds() -> u64 {
+    60
+}
+
+impl StorageCredentials {
+    /// Returns the access key id and secret access key.
+    fn resolve(&self) -> Result<(String, String), StorageError> {
+        let access_key_id = secret("access_key_id", &self.access_key_id, &self.access_key_id_file)?;
+        let secret_access_key = secret(
+            "secret_access_key",
+            &self.secret_access_key,
+            &self.secret_access_key_file,
+        )?;
+        Ok((access_key_id, secret_access_key))
+    }
+}
+
+fn secret(name: &'static str, value: &Option<String>, file: &Option<PathBuf>) -> Result<String, StorageError> {
+    match (value, file) {
+        (Some(value), _) => Ok(value.clone()),
+        (None, Some(path)) => read_secret(path),
+        (None, None) => Err(StorageError::Credentials(name)),
+    }
+}
+
+fn read_secret(path: &Path) -> Result<String, StorageError> {
+    let value = std::fs::read_to_string(path).map_err(|source| StorageError::ReadSecret {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(value.trim().to_string())
+}
@@ -0,0 +1,175 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::uri::Authority;
+use axum::http::{header, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use startup_http::{RequestContext, WebError};
+use startup_jwt::Jwt;
+use tracing::debug;
+
+use crate::{Tenant, TenantConfig, TenantSource};
+
+/// Resolves the tenant of every request from the configured sources. The tenant is added
+/// to the request for the [Tenant] extractor and to its [RequestContext], set as the tenant
+/// of the task handling the request for [Tenant::current] and recorded on the request span.
+///
+/// Add it inside the tracing layer and, for the [TenantSource::Jwt] source, inside
+/// the [JwtAuth](startup_jwt::JwtAuth) layer.
+///
+/// Use like this: `router.layer(TenantLayer::new(&config.tenant)).layer(jwt_auth.into_layer())`
+#[derive(Clone)]
+pub struct TenantLayer {
+    config: Arc<TenantConfig>,
+}
+
+impl TenantLayer {
+    pub fn new(config: &TenantConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+        }
+    }
+}
+
+impl<S> tower_layer::Layer<S> for TenantLayer {
+    type Service = TenantService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TenantService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TenantService<S> {
+    inner: S,
+    config: Arc<TenantConfig>,
+}
+
+impl<S, B> tower_service::Service<Request<B>> for TenantService<S>
+where
+    S: tower_service::Service<Request<B>> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // the inner service was polled ready, keep it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+
+            let tenant = match resolve(&config, &mut parts).await {
+                Ok(tenant) => tenant,
+                Err(err) => return Ok(err.into_response()),
+            };
+
+            let Some(tenant) = tenant else {
+                return inner
+                    .call(Request::from_parts(parts, body))
+                    .await
+                    .map(IntoResponse::into_response);
+            };
+
+            parts.extensions.insert(tenant.clone());
+            RequestContext::of(&mut parts).set_tenant(tenant.id());
+
+            let response = tenant.scope(inner.call(Request::from_parts(parts, body))).await;
+            response.map(IntoResponse::into_response)
+        })
+    }
+}
+
+async fn resolve(config: &TenantConfig, parts: &mut Parts) -> Result<Option<Tenant>, WebError> {
+    let mut id = None;
+
+    for source in &config.sources {
+        id = match source {
+            TenantSource::Jwt => from_jwt(config, parts).await,
+            TenantSource::Header => from_header(config, parts),
+            TenantSource::Host => from_host(config, parts),
+        };
+
+        if id.is_some() {
+            break;
+        }
+    }
+
+    let Some(id) = id else {
+        if config.required {
+            let message = "Tenant of the request is missing".to_string();
+            return Err(WebError::Response(StatusCode::BAD_REQUEST, message));
+        }
+
+        return Ok(None);
+    };
+
+    let tenant = Tenant::new(&id).filter(|_| config.tenants.is_empty() || config.tenants.contains(&id));
+
+    match tenant {
+        Some(tenant) => Ok(Some(tenant)),
+        None => {
+            debug!("Rejecting request for unknown tenant {:?}", id);
+            let message = "Unknown tenant".to_string();
+            Err(WebError::Response(StatusCode::FORBIDDEN, message))
+        }
+    }
+}
+
+async fn from_jwt(config: &TenantConfig, parts: &mut Parts) -> Option<String> {
+    // a request without a valid token has no tenant, rejecting it is up to the handler
+    let Jwt(claims) = Jwt::<serde_json::Value>::from_request_parts(parts, &()).await.ok()?;
+
+    match claims.get(&config.claim)? {
+        serde_json::Value::String(id) => Some(id.clone()),
+        serde_json::Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+fn from_header(config: &TenantConfig, parts: &Parts) -> Option<String> {
+    let value = parts.headers.get(&config.header)?.to_str().ok()?.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn from_host(config: &TenantConfig, parts: &Parts) -> Option<String> {
+    // http/2 requests carry the host in the uri instead of the header
+    let authority = match parts.headers.get(header::HOST) {
+        Some(host) => host.to_str().ok()?.parse::<Authority>().ok()?,
+        None => parts.uri.authority()?.clone(),
+    };
+
+    let host = authority.host().to_ascii_lowercase();
+
+    if let Some(tenant) = config.hosts.get(&host) {
+        return Some(tenant.clone());
+    }
+
+    // the host of ipv6 addresses is in brackets
+    let address = host.trim_start_matches('[').trim_end_matches(']');
+    if address.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+
+    // only a subdomain names a tenant, not the domain itself
+    let labels: Vec<&str> = host.split('.').collect();
+    (labels.len() > 2).then(|| labels[0].to_string())
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+use startup_http::WebError;
+
+pub use crate::layer::{TenantLayer, TenantService};
+
+mod layer;
+
+tokio::task_local! {
+    static CURRENT: Tenant;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TenantSource {
+    /// A claim of the bearer token, validated by the [JwtAuth](startup_jwt::JwtAuth) layer.
+    Jwt,
+
+    /// A request header.
+    Header,
+
+    /// The host name of the request.
+    Host,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantConfig {
+    /// Sources of the tenant of a request, the first source that names a tenant wins.
+    #[serde(default = "default_sources")]
+    pub sources: Vec<TenantSource>,
+
+    /// Claim of the bearer token that names the tenant.
+    #[serde(default = "default_claim")]
+    pub claim: String,
+
+    /// Header that names the tenant. Only use it behind a gateway that sets the header.
+    #[serde(default = "default_header")]
+    pub header: String,
+
+    /// Tenants by host name. Hosts not listed here name the tenant with their first label,
+    /// e.g. `acme` for `acme.example.com`. IP addresses never name a tenant.
+    #[serde(default)]
+    pub hosts: HashMap<String, String>,
+
+    /// Accepted tenants, every tenant is accepted if empty. Requests for other tenants
+    /// are rejected with `403 Forbidden`.
+    #[serde(default)]
+    pub tenants: Vec<String>,
+
+    /// Reject requests without a tenant with `400 Bad Request`. Handlers can still
+    /// require a tenant using the [Tenant] extractor.
+    #[serde(default)]
+    pub required: bool,
+}
+
+impl Default for TenantConfig {
+    fn default() -> Self {
+        Self {
+            sources: default_sources(),
+            claim: default_claim(),
+            header: default_header(),
+            hosts: HashMap::new(),
+            tenants: Vec::new(),
+            required: false,
+        }
+    }
+}
+
+fn default_sources() -> Vec<TenantSource> {
+    vec![TenantSource::Jwt]
+}
+
+fn default_claim() -> String {
+    "site".to_string()
+}
+
+fn default_header() -> String {
+    "x-tenant".to_string()
+}
+
+/// The tenant a request or task runs for. It is resolved by the [TenantLayer] and available
+/// to everything the request runs, using the extractor or [Tenant::current].
+///
+/// Ids consist of up to 56 ascii letters, digits, `-` and `_`, so they are safe to use in
+/// schema names and metric labels.
+///
+/// Use like this: `async fn orders(tenant: Tenant) -> Json<Vec<Order>> { .. }`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tenant(Arc<str>);
+
+impl Tenant {
+    /// Returns None if the id contains other characters than allowed.
+    pub fn new(id: &str) -> Option<Self> {
+        let valid = !id.is_empty()
+            // the schema name must fit into the 63 bytes of a postgres identifier
+            && id.len() <= 63 - "tenant_".len()
+            && id
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+
+        valid.then(|| Tenant(id.into()))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.0
+    }
+
+    /// The tenant of the current task, if it runs for one.
+    pub fn current() -> Option<Tenant> {
+        CURRENT.try_with(Tenant::clone).ok()
+    }
+
+    /// Runs the future for the tenant, e.g. a message consumed for a tenant. The tenant
+    /// is recorded on the current span.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        self.record_on_span();
+        CURRENT.scope(self, future).await
+    }
+
+    /// Label for metrics recorded for the tenant.
+    ///
+    /// Use like this: `counter.add(&cx, 1, &[tenant.key_value()])`
+    pub fn key_value(&self) -> KeyValue {
+        KeyValue::new("tenant", self.0.to_string())
+    }
+
+    /// Name of the database schema of the tenant, `tenant_` followed by the id. Every tenant
+    /// has a schema of its own, the name is quoted as it may contain `-` and uppercase letters.
+    pub fn schema(&self) -> String {
+        format!("tenant_{}", self.0)
+    }
+
+    /// Starts a transaction in the schema of the tenant, see [startup_db::begin_in_schema].
+    #[cfg(feature = "db")]
+    pub async fn begin(&self, pool: &sqlx::PgPool) -> Result<sqlx::Transaction<'static, sqlx::Postgres>, sqlx::Error> {
+        startup_db::begin_in_schema(pool, &self.schema()).await
+    }
+
+    /// Records the tenant as an attribute of the current opentelemetry span.
+    fn record_on_span(&self) {
+        let context = opentelemetry::Context::current();
+        context.span().set_attribute(self.key_value());
+    }
+}
+
+impl Display for Tenant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tenant
+where
+    S: Send + Sync,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Tenant>().cloned().ok_or_else(|| {
+            let message = "Tenant of the request is missing".to_string();
+            WebError::Response(StatusCode::BAD_REQUEST, message)
+        })
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::Serialize;
+use serde_json::{json, Value};
+use startup_jwt::JwtAuth;
+
+/// Key id of the test key in the header of every token.
+const KEY_ID: &str = "startup-testing";
+
+/// A private key that is only used in tests. Never trust it outside of tests.
+const PRIVATE_KEY: &str = include_str!("test_key.pem");
+
+/// Modulus of the public part of the test key, base64url encoded.
+const PUBLIC_MODULUS: &str = "1aDd0W6giCHXNhygmyLWvE2LlbQblsJNczzZPp_CdKTl9yP1B82bFALL3e18tssYXl1hznK_a7PjLOoHjm8YP6UnJqWuoa0hCimp_HIF5K0wbEkDKPAEO372KbcfXxNkStwxx1R9cFDBQ856xmWN-SmS7UZZVLD-NBAUuA_i243AIGGkNFigSOI6GPlK595z6vtgQ9_BnZP1N4bmI8W1zvBdW-5ex0PqheFMp6ui5257NLxwub3NHg3ugW_ooxIsTk9Pkn16xdWYLMtjL_Rt0GjevPcQ4A1VbabtyIBUhGce5eEW4ryRk9xMGOHuQUJB2hn7c5ab_2RmAtPrvJu_ew";
+
+/// Tokens expire this long after they were signed, unless the claims contain an `exp`.
+const TOKEN_LIFETIME_SECONDS: u64 = 3600;
+
+/// Signs tokens with a test key and provides a [JwtAuth] that accepts them, so the
+/// [Jwt](startup_jwt::Jwt) extractor works in tests without an identity provider.
+///
+/// Use like this:
+/// ```ignore
+/// let auth = TestAuth::new();
+/// let client = TestClient::new(router.layer(auth.jwt_auth().into_layer()));
+/// client.get("/me").bearer(auth.token(&json!({ "user_name": "alice" }))).send().await;
+/// ```
+#[derive(Clone)]
+pub struct TestAuth {
+    key: EncodingKey,
+}
+
+impl TestAuth {
+    pub fn new() -> Self {
+        let key = EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).expect("invalid test key");
+        Self { key }
+    }
+
+    /// The public test key as a key set, as an identity provider serves it in its `jwks.json`.
+    pub fn jwk_set(&self) -> JwkSet {
+        let jwks = json!({
+            "keys": [{
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": KEY_ID,
+                "n": PUBLIC_MODULUS,
+                "e": "AQAB",
+            }]
+        });
+
+        serde_json::from_value(jwks).expect("invalid test key set")
+    }
+
+    /// Accepts the tokens signed by [TestAuth::token], including their expiry time.
+    pub fn jwt_auth(&self) -> JwtAuth {
+        JwtAuth::with_jwk_set(self.jwk_set(), true)
+    }
+
+    /// Signs a token with the claims, which must serialize to a json object. An `exp`
+    /// claim is added if missing, as tokens without one are rejected.
+    pub fn token(&self, claims: &impl Serialize) -> String {
+        let mut claims = serde_json::to_value(claims).expect("failed to serialize claims");
+
+        let Value::Object(object) = &mut claims else {
+            panic!("claims must be a json object, got {}", claims);
+        };
+
+        if !object.contains_key("exp") {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            object.insert("exp".into(), json!(now.as_secs() + TOKEN_LIFETIME_SECONDS));
+        }
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(KEY_ID.to_string());
+
+        jsonwebtoken::encode(&header, &claims, &self.key).expect("failed to sign token")
+    }
+}
+
+impl Default for TestAuth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::subscriber::DefaultGuard;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
+
+/// An event logged while a [TracingCapture] was active.
+#[derive(Debug, Clone)]
+pub struct CapturedEvent {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+
+    /// Fields of the event other than the message.
+    pub fields: BTreeMap<String, String>,
+
+    /// Names of the spans the event was logged in, the outermost span first.
+    pub spans: Vec<String>,
+}
+
+/// Captures the events logged by the current thread until it is dropped, so tests can
+/// assert on logs. With `#[tokio::test]`, tasks spawned by the test run on the same thread
+/// and are captured as well, unless the multi threaded runtime is used.
+///
+/// Use like this:
+/// ```ignore
+/// let logs = TracingCapture::start();
+/// client.get("/orders/unknown").send().await;
+/// logs.assert_logged(Level::WARN, "Order unknown not found");
+/// ```
+pub struct TracingCapture {
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+    _guard: DefaultGuard,
+}
+
+impl TracingCapture {
+    pub fn start() -> Self {
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let layer = CaptureLayer { events: events.clone() };
+        let guard = tracing::subscriber::set_default(Registry::default().with(layer));
+
+        Self { events, _guard: guard }
+    }
+
+    /// All events captured so far.
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.events.lock().clone()
+    }
+
+    /// Forgets the events captured so far.
+    pub fn clear(&self) {
+        self.events.lock().clear();
+    }
+
+    /// Returns true if an event with the level was captured that contains the text in its message.
+    pub fn contains(&self, level: Level, text: &str) -> bool {
+        self.events
+            .lock()
+            .iter()
+            .any(|event| event.level == level && event.message.contains(text))
+    }
+
+    #[track_caller]
+    pub fn assert_logged(&self, level: Level, text: &str) {
+        assert!(
+            self.contains(level, text),
+            "no {} event containing {:?} was logged, captured events: {:#?}",
+            level,
+            text,
+            self.messages()
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_not_logged(&self, level: Level, text: &str) {
+        assert!(
+            !self.contains(level, text),
+            "a {} event containing {:?} was logged, captured events: {:#?}",
+            level,
+            text,
+            self.messages()
+        );
+    }
+
+    fn messages(&self) -> Vec<String> {
+        self.events
+            .lock()
+            .iter()
+            .map(|event| format!("{} {}: {}", event.level, event.target, event.message))
+            .collect()
+    }
+}
+
+struct CaptureLayer {
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+}
+
+impl<S> Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| scope.from_root().map(|span| span.name().to_string()).collect())
+            .unwrap_or_default();
+
+        let metadata = event.metadata();
+
+        self.events.lock().push(CapturedEvent {
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+            spans,
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            name => {
+                self.fields.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            name => {
+                self.fields.insert(name.to_string(), format!("{:?}", value));
+            }
+        }
+    }
+}
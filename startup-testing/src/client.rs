@@ -0,0 +1,212 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use axum::Router;
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use startup_http::{ErrorFallbackLayer, TrustedProxies};
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+use url::Url;
+
+/// Address of the client as seen by the router, e.g. in the [ClientIp](startup_http::ClientIp) extractor.
+const CLIENT_ADDRESS: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 40000);
+
+/// Sends requests to a router without opening a socket. The router is wrapped in the same
+/// layers `startup_http::run_server` adds, so unknown routes are answered in the standard
+/// error format.
+///
+/// Use like this: `TestClient::new(router).get("/orders/1").send().await.assert_status(StatusCode::OK)`
+#[derive(Clone)]
+pub struct TestClient {
+    router: Router,
+}
+
+impl TestClient {
+    pub fn new(router: Router) -> Self {
+        Self {
+            router: layered(router),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> TestRequest {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn patch(&self, path: &str) -> TestRequest {
+        self.request(Method::PATCH, path)
+    }
+
+    pub fn delete(&self, path: &str) -> TestRequest {
+        self.request(Method::DELETE, path)
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> TestRequest {
+        TestRequest {
+            router: self.router.clone(),
+            method,
+            path: path.to_string(),
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+        }
+    }
+}
+
+/// Adds the layers `startup_http::run_server` adds to every router.
+fn layered(router: Router) -> Router {
+    router
+        .layer(ErrorFallbackLayer)
+        .layer(TrustedProxies::new(Vec::new()).into_layer())
+}
+
+/// A request created by a [TestClient]. Invalid headers or bodies panic, as they are
+/// a mistake in the test.
+pub struct TestRequest {
+    router: Router,
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestRequest {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        let value = HeaderValue::from_str(value).expect("invalid header value");
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sends the token in the `Authorization` header, see [TestAuth::token](crate::TestAuth::token).
+    pub fn bearer(mut self, token: impl AsRef<str>) -> Self {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token.as_ref())).expect("invalid token");
+        self.headers.insert(AUTHORIZATION, value);
+        self
+    }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        self.body = serde_json::to_vec(value)
+            .expect("failed to serialize request body")
+            .into();
+        self.headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub async fn send(self) -> TestResponse {
+        let mut request = Request::builder()
+            .method(self.method)
+            .uri(self.path)
+            .body(Body::from(self.body))
+            .expect("invalid request");
+
+        *request.headers_mut() = self.headers;
+        request.extensions_mut().insert(ConnectInfo(CLIENT_ADDRESS));
+
+        let response = match self.router.oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        };
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("failed to read response body");
+
+        TestResponse { status, headers, body }
+    }
+}
+
+/// A response received by a [TestClient], with the complete body.
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Deserializes the json body, panics if it does not match.
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|err| panic!("failed to deserialize response body {:?}: {}", self.text(), err))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// Panics with the body of the response if the status does not match.
+    #[track_caller]
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(self.status, status, "unexpected status, response body: {}", self.text());
+        self
+    }
+}
+
+/// Serves a router on a random local port, for clients that need a real socket, e.g. a
+/// `startup_client::Client` or a websocket client. The server stops when this is dropped.
+pub struct TestServer {
+    address: SocketAddr,
+    server: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start(router: Router) -> Self {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind test server");
+        let address = listener.local_addr().expect("test server has no address");
+        listener.set_nonblocking(true).expect("failed to configure test server");
+
+        let router = layered(router);
+
+        let server = axum::Server::from_tcp(listener)
+            .expect("failed to start test server")
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+
+        let server = tokio::spawn(async move {
+            if let Err(err) = server.await {
+                tracing::warn!("Test server failed: {}", err);
+            }
+        });
+
+        Self { address, server }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Url of the path on the server, e.g. `http://127.0.0.1:41234/orders/1`.
+    pub fn url(&self, path: &str) -> Url {
+        let url = format!("http://{}/{}", self.address, path.trim_start_matches('/'));
+        Url::parse(&url).expect("invalid path")
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
@@ -0,0 +1,38 @@
+use figment::providers::{Format, Serialized, Yaml};
+use figment::Figment;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Loads the config of a service the same way `startup_base::init` does, but with
+/// overrides for the test instead of the `APP_` environment variables, so tests do
+/// not depend on the environment they run in.
+///
+/// Use like this: `TestConfig::new(include_str!("../config.yaml")).set("http.port", 0).extract()?`
+#[derive(Clone)]
+pub struct TestConfig {
+    figment: Figment,
+}
+
+impl TestConfig {
+    /// Starts with the yaml config of the service.
+    pub fn new(yaml: &str) -> Self {
+        Self {
+            figment: Figment::new().merge(Yaml::string(yaml)),
+        }
+    }
+
+    /// Overrides the value at the dotted path, e.g. `database.url`. The value may be
+    /// a complete config struct, e.g. the config of a fixture.
+    pub fn set(mut self, path: &str, value: impl Serialize) -> Self {
+        self.figment = self.figment.merge(Serialized::default(path, value));
+        self
+    }
+
+    /// Builds the config, starting with its default values.
+    #[allow(clippy::result_large_err)]
+    pub fn extract<C: Default + Serialize + DeserializeOwned>(&self) -> Result<C, figment::Error> {
+        Figment::from(Serialized::defaults(C::default()))
+            .merge(self.figment.clone())
+            .extract()
+    }
+}
@@ -0,0 +1,141 @@
+//! Containers for the infrastructure a service needs, started using testcontainers. Every
+//! fixture provides the config of the matching startup crate to connect to its container.
+//! The container is removed when the fixture is dropped, so keep it for the whole test.
+
+pub use testcontainers_modules::testcontainers::TestcontainersError;
+
+#[cfg(feature = "postgres")]
+pub use self::postgres::PostgresFixture;
+
+#[cfg(feature = "redis")]
+pub use self::redis::RedisFixture;
+
+#[cfg(feature = "kafka")]
+pub use self::kafka::KafkaFixture;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::marker::PhantomData;
+
+    use testcontainers_modules::postgres::Postgres;
+    use testcontainers_modules::testcontainers::runners::AsyncRunner;
+    use testcontainers_modules::testcontainers::{ContainerAsync, TestcontainersError};
+    use url::Url;
+
+    /// A postgres database with the user, password and database `postgres`.
+    ///
+    /// Use like this: `TestConfig::new(yaml).set("database", postgres.config::<Postgres>())`
+    pub struct PostgresFixture {
+        _container: ContainerAsync<Postgres>,
+        url: Url,
+    }
+
+    impl PostgresFixture {
+        pub async fn start() -> Result<Self, TestcontainersError> {
+            let container = Postgres::default().start().await?;
+
+            let host = container.get_host().await?;
+            let port = container.get_host_port_ipv4(5432).await?;
+
+            let url = format!("postgres://postgres:postgres@{}:{}/postgres", host, port);
+            let url = Url::parse(&url).expect("invalid postgres url");
+
+            Ok(Self {
+                _container: container,
+                url,
+            })
+        }
+
+        pub fn url(&self) -> &Url {
+            &self.url
+        }
+
+        /// Config to connect to the database using the `public` schema.
+        pub fn config<DB>(&self) -> startup_db::DatabaseConfig<DB> {
+            startup_db::DatabaseConfig {
+                _marker: PhantomData,
+                url: self.url.clone(),
+                schema: "public".to_string(),
+                query_logging: false,
+                retry: Default::default(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis {
+    use serde_json::json;
+    use startup_redis::RedisConfig;
+    use testcontainers_modules::redis::{Redis, REDIS_PORT};
+    use testcontainers_modules::testcontainers::runners::AsyncRunner;
+    use testcontainers_modules::testcontainers::{ContainerAsync, TestcontainersError};
+
+    /// A standalone redis server without authentication.
+    pub struct RedisFixture {
+        _container: ContainerAsync<Redis>,
+        url: String,
+    }
+
+    impl RedisFixture {
+        pub async fn start() -> Result<Self, TestcontainersError> {
+            let container = Redis::default().start().await?;
+
+            let host = container.get_host().await?;
+            let port = container.get_host_port_ipv4(REDIS_PORT).await?;
+
+            Ok(Self {
+                _container: container,
+                url: format!("redis://{}:{}/0", host, port),
+            })
+        }
+
+        pub fn url(&self) -> &str {
+            &self.url
+        }
+
+        /// Config to connect to the server with the default settings.
+        pub fn config(&self) -> RedisConfig {
+            serde_json::from_value(json!({ "url": self.url })).expect("invalid redis config")
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use serde_json::json;
+    use startup_kafka::KafkaConfig;
+    use testcontainers_modules::kafka::{Kafka, KAFKA_PORT};
+    use testcontainers_modules::testcontainers::runners::AsyncRunner;
+    use testcontainers_modules::testcontainers::{ContainerAsync, TestcontainersError};
+
+    /// A single kafka broker that creates topics on first use.
+    pub struct KafkaFixture {
+        _container: ContainerAsync<Kafka>,
+        broker: String,
+    }
+
+    impl KafkaFixture {
+        pub async fn start() -> Result<Self, TestcontainersError> {
+            let container = Kafka::default().start().await?;
+
+            let host = container.get_host().await?;
+            let port = container.get_host_port_ipv4(KAFKA_PORT).await?;
+
+            Ok(Self {
+                _container: container,
+                broker: format!("{}:{}", host, port),
+            })
+        }
+
+        /// Address of the broker, e.g. `localhost:49153`.
+        pub fn broker(&self) -> &str {
+            &self.broker
+        }
+
+        /// Config to connect to the broker with the default settings.
+        pub fn config(&self) -> KafkaConfig {
+            serde_json::from_value(json!({ "brokers": [self.broker] })).expect("invalid kafka config")
+        }
+    }
+}
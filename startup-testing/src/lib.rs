@@ -0,0 +1,48 @@
+//! Helpers for integration tests of services built with the startup crates.
+//!
+//! * [TestConfig] loads the config of the service with overrides for the test.
+//! * [TestClient] sends requests to the router of the service without opening a socket,
+//!   [TestServer] serves it on a local port for clients that need one.
+//! * [MockServer] stubs the services the service calls, its url can be passed to a
+//!   `startup_client::Client`.
+//! * [TestAuth] signs tokens that are accepted by a [JwtAuth](startup_jwt::JwtAuth).
+//! * [TracingCapture] records the logs of a test, so tests can assert on them.
+//! * With the `postgres`, `redis` and `kafka` features, the [fixtures] start containers
+//!   using testcontainers and provide the matching config of the startup crates.
+//!
+//! Use like this:
+//! ```ignore
+//! #[tokio::test]
+//! async fn returns_the_order() {
+//!     let postgres = PostgresFixture::start().await.unwrap();
+//!
+//!     let config: Config = TestConfig::new(include_str!("../config.yaml"))
+//!         .set("database", postgres.config::<Postgres>())
+//!         .extract()
+//!         .unwrap();
+//!
+//!     let auth = TestAuth::new();
+//!     let router = app(&config).await.layer(auth.jwt_auth().into_layer());
+//!
+//!     TestClient::new(router)
+//!         .get("/orders/1")
+//!         .bearer(auth.token(&json!({ "sub": "customer-1" })))
+//!         .send()
+//!         .await
+//!         .assert_status(StatusCode::OK);
+//! }
+//! ```
+
+pub use crate::auth::TestAuth;
+pub use crate::capture::{CapturedEvent, TracingCapture};
+pub use crate::client::{TestClient, TestRequest, TestResponse, TestServer};
+pub use crate::config::TestConfig;
+pub use crate::mock::{Mock, MockHandle, MockResponse, MockServer, RecordedRequest};
+
+mod auth;
+mod capture;
+mod client;
+mod config;
+#[cfg(any(feature = "postgres", feature = "redis", feature = "kafka"))]
+pub mod fixtures;
+mod mock;
@@ -0,0 +1,453 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use startup_client::ClientConfig;
+use url::Url;
+
+use crate::TestServer;
+
+/// A http server that answers requests with canned responses, to stub the services a
+/// service calls in tests. Requests are answered by the most recently registered [Mock]
+/// that matches them, requests without a matching mock are answered with `404 Not Found`.
+/// All requests are recorded, so tests can assert on them.
+///
+/// Pass [MockServer::client_config] to the code under test to send its requests here
+/// instead of to the real service.
+///
+/// Use like this:
+/// ```ignore
+/// let server = MockServer::start().await;
+/// let users = server.mock(Mock::get("/users/{id}").respond(MockResponse::json(&user)));
+///
+/// let client = Client::new(&server.client_config())?;
+/// client.get("/users/1").send().await?;
+///
+/// users.assert_calls(1);
+/// ```
+pub struct MockServer {
+    server: TestServer,
+    state: Arc<ServerState>,
+}
+
+#[derive(Default)]
+struct ServerState {
+    mocks: Mutex<Vec<Arc<MockState>>>,
+    requests: Mutex<Vec<RecordedRequest>>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let state = Arc::new(ServerState::default());
+
+        let router = Router::new().fallback(answer).with_state(state.clone());
+
+        Self {
+            server: TestServer::start(router).await,
+            state,
+        }
+    }
+
+    /// Base url of the server, e.g. `http://127.0.0.1:41234/`.
+    pub fn url(&self) -> Url {
+        self.server.url("/")
+    }
+
+    /// Default client config with the url of this server as the base url, so relative
+    /// paths are sent here. Requests are not retried, so every request is recorded once.
+    pub fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig {
+            base_url: Some(self.url()),
+            ..Default::default()
+        };
+
+        config.retry.max_attempts = 1;
+        config
+    }
+
+    /// Registers a mock. The returned handle records the requests answered by it.
+    pub fn mock(&self, mock: Mock) -> MockHandle {
+        let state = Arc::new(MockState {
+            mock,
+            calls: AtomicUsize::new(0),
+            requests: Mutex::new(Vec::new()),
+        });
+
+        self.state.mocks.lock().push(state.clone());
+
+        MockHandle { state }
+    }
+
+    /// Removes all mocks and forgets the recorded requests.
+    pub fn reset(&self) {
+        self.state.mocks.lock().clear();
+        self.state.requests.lock().clear();
+    }
+
+    /// All requests received so far, including the ones no mock matched.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().clone()
+    }
+
+    /// Panics if a request was received that no mock matched.
+    #[track_caller]
+    pub fn assert_all_matched(&self) {
+        let unmatched: Vec<_> = self
+            .state
+            .requests
+            .lock()
+            .iter()
+            .filter(|request| !request.matched)
+            .map(|request| format!("{} {}", request.method, request.path))
+            .collect();
+
+        assert!(
+            unmatched.is_empty(),
+            "requests without a matching mock: {:#?}",
+            unmatched
+        );
+    }
+}
+
+async fn answer(State(state): State<Arc<ServerState>>, request: Request<Body>) -> Response {
+    let (parts, body) = request.into_parts();
+
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+
+    let mut recorded = RecordedRequest {
+        method: parts.method,
+        path: parts.uri.path().to_string(),
+        query: parts.uri.query().map(String::from),
+        headers: parts.headers,
+        body,
+        matched: false,
+    };
+
+    // later mocks take precedence, so a test can override the mocks of its setup. The call
+    // is counted while the mocks are locked, so concurrent requests respect `times`.
+    let mock = state
+        .mocks
+        .lock()
+        .iter()
+        .rev()
+        .find(|mock| mock.accepts(&recorded))
+        .map(|mock| {
+            mock.calls.fetch_add(1, Ordering::Relaxed);
+            mock.clone()
+        });
+
+    recorded.matched = mock.is_some();
+    state.requests.lock().push(recorded.clone());
+
+    let Some(mock) = mock else {
+        let body = json!({
+            "error": "no mock matches the request",
+            "method": recorded.method.as_str(),
+            "path": recorded.path,
+        });
+
+        return (StatusCode::NOT_FOUND, axum::Json(body)).into_response();
+    };
+
+    mock.requests.lock().push(recorded);
+
+    let response = &mock.mock.response;
+
+    if !response.delay.is_zero() {
+        tokio::time::sleep(response.delay).await;
+    }
+
+    (response.status, response.headers.clone(), response.body.clone()).into_response()
+}
+
+/// Describes the requests a mock answers and the response it answers them with.
+#[derive(Debug, Clone)]
+pub struct Mock {
+    method: Option<Method>,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(HeaderName, String)>,
+    body: Option<Value>,
+    times: Option<usize>,
+    response: MockResponse,
+}
+
+impl Mock {
+    /// Matches requests with the method and path. Segments of the path written like
+    /// `{id}` match any value, e.g. `/users/{id}` matches `/users/1`. The mock answers
+    /// with `200 OK` and an empty body, unless a response is set.
+    pub fn new(method: Method, path: &str) -> Self {
+        Self {
+            method: Some(method),
+            ..Self::any(path)
+        }
+    }
+
+    /// Matches requests with the path and any method.
+    pub fn any(path: &str) -> Self {
+        Self {
+            method: None,
+            path: path.to_string(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: None,
+            times: None,
+            response: MockResponse::status(StatusCode::OK),
+        }
+    }
+
+    pub fn get(path: &str) -> Self {
+        Self::new(Method::GET, path)
+    }
+
+    pub fn post(path: &str) -> Self {
+        Self::new(Method::POST, path)
+    }
+
+    pub fn put(path: &str) -> Self {
+        Self::new(Method::PUT, path)
+    }
+
+    pub fn patch(path: &str) -> Self {
+        Self::new(Method::PATCH, path)
+    }
+
+    pub fn delete(path: &str) -> Self {
+        Self::new(Method::DELETE, path)
+    }
+
+    /// Only matches requests with this query parameter.
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Only matches requests with this header.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    /// Only matches requests with a json body equal to the value.
+    pub fn json_body<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        self.body = Some(serde_json::to_value(value).expect("failed to serialize expected body"));
+        self
+    }
+
+    /// Answers at most this many requests, e.g. to fail only the first attempt of a request.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    pub fn respond(mut self, response: MockResponse) -> Self {
+        self.response = response;
+        self
+    }
+}
+
+/// A canned response of a [Mock].
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    delay: Duration,
+}
+
+impl MockResponse {
+    /// A response with the status and an empty body.
+    pub fn status(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// A `200 OK` response with the value as json body.
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Self {
+        let body = serde_json::to_vec(value).expect("failed to serialize response body");
+
+        Self::status(StatusCode::OK)
+            .header(CONTENT_TYPE.as_str(), "application/json")
+            .body(body)
+    }
+
+    /// A `200 OK` response with the text as body.
+    pub fn text(text: &str) -> Self {
+        Self::status(StatusCode::OK)
+            .header(CONTENT_TYPE.as_str(), "text/plain; charset=utf-8")
+            .body(text.to_string())
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        let value = HeaderValue::from_str(value).expect("invalid header value");
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Waits before answering, e.g. to test timeouts or hedged requests.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+struct MockState {
+    mock: Mock,
+    calls: AtomicUsize,
+    requests: Mutex<Vec<RecordedRequest>>,
+}
+
+impl MockState {
+    fn accepts(&self, request: &RecordedRequest) -> bool {
+        let mock = &self.mock;
+
+        if mock
+            .times
+            .is_some_and(|times| self.calls.load(Ordering::Relaxed) >= times)
+        {
+            return false;
+        }
+
+        if mock.method.as_ref().is_some_and(|method| method != request.method) {
+            return false;
+        }
+
+        if !path_matches(&mock.path, &request.path) {
+            return false;
+        }
+
+        let query = request.query_pairs();
+        if !mock.query.iter().all(|expected| query.contains(expected)) {
+            return false;
+        }
+
+        let headers_match = mock.headers.iter().all(|(name, value)| {
+            request
+                .headers
+                .get_all(name)
+                .iter()
+                .any(|candidate| candidate.as_bytes() == value.as_bytes())
+        });
+
+        if !headers_match {
+            return false;
+        }
+
+        match &mock.body {
+            Some(expected) => serde_json::from_slice::<Value>(&request.body).is_ok_and(|body| &body == expected),
+            None => true,
+        }
+    }
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut segments = path.trim_matches('/').split('/');
+
+    for expected in pattern.trim_matches('/').split('/') {
+        match segments.next() {
+            Some(_) if expected.starts_with('{') && expected.ends_with('}') => continue,
+            Some(segment) if segment == expected => continue,
+            _ => return false,
+        }
+    }
+
+    segments.next().is_none()
+}
+
+/// Records the requests answered by a registered [Mock].
+#[derive(Clone)]
+pub struct MockHandle {
+    state: Arc<MockState>,
+}
+
+impl MockHandle {
+    /// Number of requests the mock answered.
+    pub fn calls(&self) -> usize {
+        self.state.calls.load(Ordering::Relaxed)
+    }
+
+    /// The requests the mock answered, in the order they were received.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().clone()
+    }
+
+    #[track_caller]
+    pub fn assert_calls(&self, calls: usize) {
+        assert_eq!(
+            self.calls(),
+            calls,
+            "unexpected number of calls to mock for {:?} {}",
+            self.state.mock.method,
+            self.state.mock.path
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_called(&self) {
+        assert!(
+            self.calls() > 0,
+            "mock for {:?} {} was never called",
+            self.state.mock.method,
+            self.state.mock.path
+        );
+    }
+}
+
+/// A request received by a [MockServer].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+
+    /// True if a mock answered the request.
+    pub matched: bool,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// Decoded query parameters of the request.
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        let query = self.query.as_deref().unwrap_or_default();
+        url::form_urlencoded::parse(query.as_bytes()).into_owned().collect()
+    }
+
+    /// Deserializes the json body, panics if it does not match.
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body).unwrap_or_else(|err| {
+            let body = String::from_utf8_lossy(&self.body);
+            panic!("failed to deserialize request body {:?}: {}", body, err)
+        })
+    }
+}
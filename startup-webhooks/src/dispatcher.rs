@@ -0,0 +1,401 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::{global, KeyValue};
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use startup_base::retry::{Failure, RetryPolicy};
+use startup_client::{CircuitBreakers, Client, ClientError};
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+
+use crate::{sign, SubscriptionStore, WebhookError, WebhooksConfig, WEBHOOK_ID, WEBHOOK_SIGNATURE, WEBHOOK_TIMESTAMP};
+
+/// A delivery claimed by this dispatcher.
+struct Claimed {
+    id: i64,
+    event_id: String,
+    event_type: String,
+    subscription_id: String,
+    url: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    created_at: DateTime<Utc>,
+}
+
+enum Outcome {
+    Delivered,
+    Failed(String, Failure),
+
+    /// The circuit of the endpoint is open, nothing was sent.
+    Deferred,
+
+    /// The subscription no longer exists.
+    Cancelled,
+}
+
+/// Sends the stored deliveries to the endpoints of their subscriptions. Due deliveries are claimed
+/// using `FOR UPDATE SKIP LOCKED`, so any number of dispatchers can share the tables.
+///
+/// Every delivery is a `POST` of the json envelope `{"id", "type", "timestamp", "data"}` with the
+/// headers `webhook-id`, `webhook-timestamp` and `webhook-signature`, see [sign](crate::sign).
+/// A delivery that is not answered with `2xx` is retried with an exponential backoff as
+/// configured in [WebhooksConfig::retry]. Every attempt is recorded in `startup_webhook_attempts`.
+///
+/// After repeated failures, the circuit of an endpoint opens and its deliveries are postponed
+/// until the circuit lets a probe through, so a broken endpoint does not use up the attempts
+/// of its deliveries.
+///
+/// The metrics `webhooks.deliveries` and `webhooks.attempts` count deliveries by outcome and
+/// attempts by result, `webhooks.duration` records the duration of every attempt.
+///
+/// Use like this:
+/// ```ignore
+/// Dispatcher::new(&pool, &config.webhooks, config.webhooks.subscriptions.clone())?
+///     .run(startup_http::shutdown_requested())
+///     .await;
+/// ```
+pub struct Dispatcher {
+    pool: PgPool,
+    client: Client,
+    store: Arc<dyn SubscriptionStore>,
+    circuit_breakers: CircuitBreakers,
+    concurrency: usize,
+    poll_interval: Duration,
+    lock: Duration,
+    retry: RetryPolicy,
+    open_duration: Duration,
+    deliveries: Counter<u64>,
+    attempts: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl Dispatcher {
+    /// Fails if a subscription of the config is invalid, see [Subscription::validate](crate::Subscription::validate).
+    pub fn new(pool: &PgPool, config: &WebhooksConfig, store: impl SubscriptionStore) -> Result<Self, WebhookError> {
+        for subscription in &config.subscriptions {
+            subscription.validate()?;
+        }
+
+        let mut client_config = config.client.clone();
+        client_config.retry.max_attempts = 1;
+        client_config.circuit_breaker.enabled = false;
+
+        let meter = global::meter("startup-webhooks");
+
+        let deliveries = meter
+            .u64_counter("webhooks.deliveries")
+            .with_description("Webhook deliveries by outcome, including retries")
+            .init();
+
+        let attempts = meter
+            .u64_counter("webhooks.attempts")
+            .with_description("Attempts to deliver a webhook by result")
+            .init();
+
+        let duration = meter
+            .f64_histogram("webhooks.duration")
+            .with_description("Duration of an attempt to deliver a webhook")
+            .with_unit(Unit::new("s"))
+            .init();
+
+        Ok(Self {
+            pool: pool.clone(),
+            client: Client::new(&client_config)?,
+            store: Arc::new(store),
+            circuit_breakers: CircuitBreakers::new(&config.circuit_breaker),
+            concurrency: config.concurrency.max(1),
+            poll_interval: Duration::from_millis(config.poll_interval_ms),
+            // keep the lock a little longer than a request may take, so the failure can be recorded
+            lock: Duration::from_secs(config.client.timeout_seconds + 30),
+            retry: config.retry.clone(),
+            open_duration: Duration::from_secs(config.circuit_breaker.open_seconds),
+            deliveries,
+            attempts,
+            duration,
+        })
+    }
+
+    /// Sends deliveries until `shutdown` resolves, e.g. `startup_http::shutdown_requested()`.
+    /// Waits for running deliveries to finish before returning.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) {
+        info!("Running webhook dispatcher");
+
+        tokio::pin!(shutdown);
+
+        loop {
+            let claimed = tokio::select! {
+                biased;
+
+                _ = &mut shutdown => break,
+                claimed = self.claim() => claimed,
+            };
+
+            let deliveries = match claimed {
+                Ok(deliveries) => deliveries,
+                Err(err) => {
+                    warn!("Failed to claim webhook deliveries: {}", err);
+                    Vec::new()
+                }
+            };
+
+            if deliveries.is_empty() {
+                tokio::select! {
+                    _ = &mut shutdown => break,
+                    _ = tokio::time::sleep(self.poll_interval) => continue,
+                }
+            }
+
+            join_all(deliveries.into_iter().map(|delivery| self.deliver(delivery))).await;
+        }
+
+        info!("Stopped webhook dispatcher");
+    }
+
+    /// Locks up to `concurrency` due deliveries for this dispatcher, oldest first.
+    async fn claim(&self) -> Result<Vec<Claimed>, sqlx::Error> {
+        let rows = sqlx::query(
+            "UPDATE startup_webhook_deliveries
+            SET locked_until = now() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id FROM startup_webhook_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= now()
+                    AND (locked_until IS NULL OR locked_until < now())
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, event_id, event_type, subscription_id, url, payload, attempts, created_at",
+        )
+        .bind(self.concurrency as i64)
+        .bind(self.lock.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Claimed {
+                    id: row.try_get("id")?,
+                    event_id: row.try_get("event_id")?,
+                    event_type: row.try_get("event_type")?,
+                    subscription_id: row.try_get("subscription_id")?,
+                    url: row.try_get("url")?,
+                    payload: row.try_get("payload")?,
+                    attempts: row.try_get("attempts")?,
+                    created_at: row.try_get("created_at")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn deliver(&self, delivery: Claimed) {
+        let span = info_span!(
+            "webhook_delivery",
+            otel.name = %format!("{} deliver", delivery.event_type),
+            otel.kind = "producer",
+            otel.status_code = Empty,
+            webhook.delivery_id = delivery.id,
+            webhook.event_id = %delivery.event_id,
+            webhook.subscription = %delivery.subscription_id,
+            webhook.attempt = delivery.attempts + 1,
+        );
+
+        let outcome = self.attempt(&delivery).instrument(span.clone()).await;
+
+        if let Err(err) = self.finish(&delivery, outcome, &span).await {
+            warn!(parent: &span, "Failed to update webhook delivery {}, it will be sent again: {}", delivery.id, err);
+        }
+    }
+
+    async fn attempt(&self, delivery: &Claimed) -> Outcome {
+        let subscription = match self.store.subscription(&delivery.subscription_id).await {
+            Ok(Some(subscription)) => subscription,
+            Ok(None) => return Outcome::Cancelled,
+            Err(err) => {
+                let error = format!("failed to load subscription: {}", err);
+                return Outcome::Failed(error, Failure::Unavailable);
+            }
+        };
+
+        if !self.circuit_breakers.acquire(&delivery.url) {
+            return Outcome::Deferred;
+        }
+
+        let timestamp = Utc::now().timestamp();
+
+        let body = json!({
+            "id": delivery.event_id,
+            "type": delivery.event_type,
+            "timestamp": delivery.created_at.to_rfc3339(),
+            "data": delivery.payload,
+        });
+
+        let body = serde_json::to_vec(&body).expect("json values always serialize");
+
+        // the secrets of the subscription were changed to invalid ones, e.g. in the database
+        let signature = match sign(&subscription.secrets, &delivery.event_id, timestamp, &body) {
+            Ok(signature) => signature,
+            Err(err) => return Outcome::Failed(err.to_string(), Failure::Rejected),
+        };
+
+        let started = Instant::now();
+
+        let result = self
+            .client
+            .post(&delivery.url)
+            .route("webhook")
+            .header("content-type", "application/json")
+            .header(WEBHOOK_ID, &delivery.event_id)
+            .header(WEBHOOK_TIMESTAMP, timestamp.to_string())
+            .header(WEBHOOK_SIGNATURE, signature)
+            .body(body)
+            .send()
+            .await;
+
+        let elapsed = started.elapsed();
+
+        let (status_code, error) = match &result {
+            Ok(response) => (Some(response.status().as_u16() as i32), None),
+            Err(err) => (err.status().map(|status| status.as_u16() as i32), Some(err.to_string())),
+        };
+
+        // only failures of the endpoint itself open the circuit, not rejected payloads
+        let healthy = match &result {
+            Ok(_) => true,
+            Err(ClientError::Status { status, .. }) => status.is_client_error() && status.as_u16() != 429,
+            Err(_) => false,
+        };
+
+        self.circuit_breakers.record(&delivery.url, healthy);
+
+        let result_label = if error.is_none() { "success" } else { "failure" };
+        let context = opentelemetry::Context::current();
+        let attributes = [KeyValue::new("result", result_label)];
+        self.attempts.add(&context, 1, &attributes);
+        self.duration.record(&context, elapsed.as_secs_f64(), &attributes);
+
+        let recorded = sqlx::query(
+            "INSERT INTO startup_webhook_attempts (delivery_id, attempt, status_code, error, duration_ms)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(delivery.id)
+        .bind(delivery.attempts + 1)
+        .bind(status_code)
+        .bind(&error)
+        .bind(elapsed.as_millis() as i64)
+        .execute(&self.pool)
+        .await;
+
+        if let Err(err) = recorded {
+            warn!("Failed to record attempt of webhook delivery {}: {}", delivery.id, err);
+        }
+
+        match result {
+            Ok(_) => Outcome::Delivered,
+            Err(err) => Outcome::Failed(err.to_string(), err.failure()),
+        }
+    }
+
+    async fn finish(&self, delivery: &Claimed, outcome: Outcome, span: &Span) -> Result<(), sqlx::Error> {
+        let result = match outcome {
+            Outcome::Delivered => {
+                debug!(parent: span, "Delivered event {} to {}", delivery.event_id, delivery.url);
+                self.close(delivery, "delivered", true).await?;
+                "delivered"
+            }
+
+            Outcome::Cancelled => {
+                info!(
+                    parent: span,
+                    "Subscription {} no longer exists, cancelling delivery {}", delivery.subscription_id, delivery.id
+                );
+
+                self.close(delivery, "cancelled", false).await?;
+                "cancelled"
+            }
+
+            Outcome::Deferred => {
+                debug!(parent: span, "Circuit for {} is open, postponing delivery {}", delivery.url, delivery.id);
+                self.reschedule(delivery, self.open_duration, false).await?;
+                "deferred"
+            }
+
+            Outcome::Failed(error, failure) => {
+                span.record("otel.status_code", "ERROR");
+
+                let attempts = delivery.attempts + 1;
+
+                if !self.retry.should_retry(attempts as u32, failure) {
+                    warn!(
+                        parent: span,
+                        "Delivery {} of event {} to {} failed {} times, giving up: {}",
+                        delivery.id,
+                        delivery.event_id,
+                        delivery.url,
+                        attempts,
+                        error
+                    );
+
+                    self.close(delivery, "failed", true).await?;
+                    "failed"
+                } else {
+                    let backoff = self.retry.backoff(attempts as u32);
+
+                    warn!(
+                        parent: span,
+                        "Delivery {} of event {} to {} failed, retrying in {:?}: {}",
+                        delivery.id,
+                        delivery.event_id,
+                        delivery.url,
+                        backoff,
+                        error
+                    );
+
+                    self.reschedule(delivery, backoff, true).await?;
+                    "retry"
+                }
+            }
+        };
+
+        let attributes = [KeyValue::new("outcome", result)];
+        self.deliveries.add(&opentelemetry::Context::current(), 1, &attributes);
+
+        Ok(())
+    }
+
+    /// Finishes the delivery with the given status.
+    async fn close(&self, delivery: &Claimed, status: &str, attempted: bool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE startup_webhook_deliveries
+            SET status = $2, attempts = attempts + $3, locked_until = NULL, finished_at = now()
+            WHERE id = $1",
+        )
+        .bind(delivery.id)
+        .bind(status)
+        .bind(attempted as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sends the delivery again after the delay.
+    async fn reschedule(&self, delivery: &Claimed, delay: Duration, attempted: bool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE startup_webhook_deliveries
+            SET next_attempt_at = now() + make_interval(secs => $2), attempts = attempts + $3, locked_until = NULL
+            WHERE id = $1",
+        )
+        .bind(delivery.id)
+        .bind(delay.as_secs_f64())
+        .bind(attempted as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("failed to serialize payload of event {event_type}")]
+    Serialize {
+        event_type: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("webhook database error")]
+    Database(#[from] sqlx::Error),
+
+    #[error("failed to create webhook client")]
+    Client(#[from] startup_client::ClientError),
+
+    #[error("invalid webhook signature")]
+    InvalidSignature,
+
+    #[error("webhook secret is not a Standard Webhooks secret like whsec_<base64 key>")]
+    InvalidSecret,
+
+    #[error("no webhook secret to sign the payload with")]
+    NoSecrets,
+
+    #[error("webhook subscription {id} is invalid")]
+    InvalidSubscription {
+        id: String,
+        #[source]
+        source: Box<WebhookError>,
+    },
+}
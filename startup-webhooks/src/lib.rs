@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgExecutor, PgPool};
+use startup_base::retry::RetryPolicy;
+use startup_client::{CircuitBreakerConfig, ClientConfig};
+use tracing::{debug, info};
+
+pub use crate::dispatcher::Dispatcher;
+pub use crate::error::WebhookError;
+pub use crate::signature::{sign, verify, WEBHOOK_ID, WEBHOOK_SIGNATURE, WEBHOOK_TIMESTAMP};
+pub use crate::subscription::{DatabaseSubscriptions, Subscription, SubscriptionStore};
+
+mod dispatcher;
+mod error;
+mod signature;
+mod subscription;
+
+/// Tables of the subscriptions, deliveries and attempts, created by [install].
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS startup_webhook_subscriptions (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    events TEXT[] NOT NULL,
+    secrets TEXT[] NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS startup_webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    subscription_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    locked_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS startup_webhook_deliveries_due
+    ON startup_webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+
+CREATE TABLE IF NOT EXISTS startup_webhook_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    delivery_id BIGINT NOT NULL REFERENCES startup_webhook_deliveries (id) ON DELETE CASCADE,
+    attempt INTEGER NOT NULL,
+    status_code INTEGER,
+    error TEXT,
+    duration_ms BIGINT NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS startup_webhook_attempts_delivery ON startup_webhook_attempts (delivery_id);
+"#;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhooksConfig {
+    /// Subscriptions from the config, use [DatabaseSubscriptions] to manage them at runtime.
+    #[serde(default)]
+    pub subscriptions: Vec<Subscription>,
+
+    /// Number of deliveries sent at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// Interval to look for due deliveries while there are none.
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+
+    /// Retries of failed deliveries, by default up to 10 attempts starting with a backoff of
+    /// 30 seconds. Deliveries that are answered with e.g. `400` or `500` are rejected and fail
+    /// right away, unless `rejected` is added to `retry_on`.
+    #[serde(default = "default_retry")]
+    pub retry: RetryPolicy,
+
+    /// Per endpoint circuit breaker. Deliveries to an endpoint with an open circuit
+    /// are postponed without counting as an attempt.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// Client to send the deliveries with. Retries and the per host circuit breaker of the
+    /// client are disabled, deliveries are retried by the [Dispatcher].
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+
+fn default_concurrency() -> usize {
+    8
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1_000
+}
+
+fn default_retry() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 10,
+        initial_backoff_millis: 30_000,
+        max_backoff_millis: 6 * 3_600_000,
+        ..RetryPolicy::default()
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            subscriptions: Vec::new(),
+            concurrency: default_concurrency(),
+            poll_interval_ms: default_poll_interval_ms(),
+            retry: default_retry(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            client: ClientConfig::default(),
+        }
+    }
+}
+
+/// An attempt to deliver an event, recorded for auditing.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DeliveryAttempt {
+    pub delivery_id: i64,
+    pub attempt: i32,
+
+    /// Status code of the response, if the endpoint answered.
+    pub status_code: Option<i32>,
+
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Creates the tables of the webhooks in the current schema if they do not exist yet.
+pub async fn install(pool: &PgPool) -> Result<(), WebhookError> {
+    info!("Ensure webhook tables exist");
+    pool.execute(SCHEMA).await?;
+    Ok(())
+}
+
+/// The recorded attempts of a delivery, the first attempt first.
+pub async fn attempts<'c>(
+    executor: impl PgExecutor<'c>,
+    delivery_id: i64,
+) -> Result<Vec<DeliveryAttempt>, WebhookError> {
+    let attempts = sqlx::query_as(
+        "SELECT delivery_id, attempt, status_code, error, duration_ms, attempted_at
+        FROM startup_webhook_attempts WHERE delivery_id = $1 ORDER BY attempt",
+    )
+    .bind(delivery_id)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(attempts)
+}
+
+/// Publishes events to the subscribed endpoints. Publishing stores a delivery per subscription,
+/// the [Dispatcher] sends them.
+///
+/// Use like this: `webhooks.publish(&mut tx, "order.created", &order).await?`
+#[derive(Clone)]
+pub struct Webhooks {
+    store: Arc<dyn SubscriptionStore>,
+}
+
+impl Webhooks {
+    pub fn new(store: impl SubscriptionStore) -> Self {
+        Self { store: Arc::new(store) }
+    }
+
+    /// Stores a delivery of the event for every subscription that accepts its type and returns
+    /// the id of the event. Pass a transaction to deliver the event only if the transaction commits.
+    pub async fn publish<'c, T: Serialize + ?Sized>(
+        &self,
+        executor: impl PgExecutor<'c>,
+        event_type: &str,
+        payload: &T,
+    ) -> Result<String, WebhookError> {
+        let payload = serde_json::to_value(payload).map_err(|source| WebhookError::Serialize {
+            event_type: event_type.to_string(),
+            source,
+        })?;
+
+        let subscriptions: Vec<Subscription> = self
+            .store
+            .subscriptions()
+            .await?
+            .into_iter()
+            .filter(|subscription| subscription.accepts(event_type))
+            .collect();
+
+        let event_id = format!("evt_{:032x}", rand::thread_rng().gen::<u128>());
+
+        if subscriptions.is_empty() {
+            debug!("No subscriptions for event {} of type {}", event_id, event_type);
+            return Ok(event_id);
+        }
+
+        let ids: Vec<&str> = subscriptions
+            .iter()
+            .map(|subscription| subscription.id.as_str())
+            .collect();
+        let urls: Vec<&str> = subscriptions
+            .iter()
+            .map(|subscription| subscription.url.as_str())
+            .collect();
+
+        sqlx::query(
+            "INSERT INTO startup_webhook_deliveries (event_id, event_type, subscription_id, url, payload)
+            SELECT $1, $2, subscription_id, url, $5 FROM UNNEST($3::TEXT[], $4::TEXT[]) AS s (subscription_id, url)",
+        )
+        .bind(&event_id)
+        .bind(event_type)
+        .bind(&ids)
+        .bind(&urls)
+        .bind(payload)
+        .execute(executor)
+        .await?;
+
+        debug!(
+            "Publishing event {} of type {} to {} subscriptions",
+            event_id,
+            event_type,
+            subscriptions.len()
+        );
+
+        Ok(event_id)
+    }
+}
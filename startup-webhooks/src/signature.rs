@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use startup_base::signature::{self, webhook_key};
+
+use crate::WebhookError;
+
+/// Header with the id of the event, the same for every attempt to deliver it.
+pub const WEBHOOK_ID: &str = "webhook-id";
+
+/// Header with the unix time the payload was signed at.
+pub const WEBHOOK_TIMESTAMP: &str = "webhook-timestamp";
+
+/// Header with the signatures of the payload.
+pub const WEBHOOK_SIGNATURE: &str = "webhook-signature";
+
+/// Signs the payload with every secret of the subscription, following the Standard Webhooks
+/// scheme: the signature is the base64 encoded HMAC-SHA256 of `{id}.{timestamp}.{body}` and
+/// the header lists one `v1,<signature>` per secret, separated by spaces. Secrets look like
+/// `whsec_<base64 key>`, the decoded key is used for the HMAC.
+///
+/// To rotate a secret, add the new secret to the subscription and remove the old one once the
+/// receiver verifies with the new one. While both are configured, payloads carry both signatures.
+pub fn sign(secrets: &[String], id: &str, timestamp: i64, body: &[u8]) -> Result<String, WebhookError> {
+    if secrets.is_empty() {
+        return Err(WebhookError::NoSecrets);
+    }
+
+    let keys = secrets
+        .iter()
+        .map(|secret| webhook_key(secret).ok_or(WebhookError::InvalidSecret))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(signature::sign_webhook(&keys, id, timestamp, body))
+}
+
+/// Verifies a payload signed by [sign], for services that receive webhooks. Fails if no
+/// signature of the header matches the secret or the timestamp is further than `tolerance`
+/// away from now, to reject replayed payloads.
+///
+/// Use like this:
+/// ```ignore
+/// verify(&secret, header(WEBHOOK_ID), header(WEBHOOK_TIMESTAMP), &body, header(WEBHOOK_SIGNATURE), tolerance)?;
+/// ```
+pub fn verify(
+    secret: &str,
+    id: &str,
+    timestamp: &str,
+    body: &[u8],
+    signatures: &str,
+    tolerance: Duration,
+) -> Result<(), WebhookError> {
+    let key = webhook_key(secret).ok_or(WebhookError::InvalidSecret)?;
+    let now = chrono::Utc::now().timestamp();
+
+    match signature::verify_webhook(&key, id, timestamp, body, signatures, now, tolerance) {
+        true => Ok(()),
+        false => Err(WebhookError::InvalidSignature),
+    }
+}
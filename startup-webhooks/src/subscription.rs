@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use url::Url;
+
+use crate::WebhookError;
+
+/// An endpoint that receives the events of the given types.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: String,
+
+    pub url: Url,
+
+    /// Types of the events to deliver, `*` delivers every event.
+    pub events: Vec<String>,
+
+    /// Secrets to sign the payloads with, like `whsec_<base64 key>`, see [sign](crate::sign).
+    /// At least one is required.
+    pub secrets: Vec<String>,
+}
+
+impl Subscription {
+    /// Fails if the subscription has no secret or a secret is not a Standard Webhooks secret.
+    pub fn validate(&self) -> Result<(), WebhookError> {
+        crate::sign(&self.secrets, "", 0, b"").map_err(|source| WebhookError::InvalidSubscription {
+            id: self.id.clone(),
+            source: Box::new(source),
+        })?;
+
+        Ok(())
+    }
+
+    pub fn accepts(&self, event_type: &str) -> bool {
+        self.events
+            .iter()
+            .any(|candidate| candidate == "*" || candidate == event_type)
+    }
+}
+
+/// Provides the active subscriptions. Implemented for a list of subscriptions, e.g. from
+/// the config, and by [DatabaseSubscriptions].
+#[async_trait]
+pub trait SubscriptionStore: Send + Sync + 'static {
+    async fn subscriptions(&self) -> Result<Vec<Subscription>, WebhookError>;
+
+    async fn subscription(&self, id: &str) -> Result<Option<Subscription>, WebhookError> {
+        let subscriptions = self.subscriptions().await?;
+        Ok(subscriptions.into_iter().find(|subscription| subscription.id == id))
+    }
+}
+
+#[async_trait]
+impl SubscriptionStore for Vec<Subscription> {
+    async fn subscriptions(&self) -> Result<Vec<Subscription>, WebhookError> {
+        Ok(self.clone())
+    }
+}
+
+/// Subscriptions managed at runtime, stored in the `startup_webhook_subscriptions` table.
+#[derive(Clone)]
+pub struct DatabaseSubscriptions {
+    pool: PgPool,
+}
+
+impl DatabaseSubscriptions {
+    pub fn new(pool: &PgPool) -> Self {
+        Self { pool: pool.clone() }
+    }
+
+    /// Creates the subscription or replaces the one with the same id. Fails if the subscription
+    /// is invalid, see [Subscription::validate].
+    pub async fn save(&self, subscription: &Subscription) -> Result<(), WebhookError> {
+        subscription.validate()?;
+
+        sqlx::query(
+            "INSERT INTO startup_webhook_subscriptions (id, url, events, secrets)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (id) DO UPDATE SET url = $2, events = $3, secrets = $4, active = true",
+        )
+        .bind(&subscription.id)
+        .bind(subscription.url.as_str())
+        .bind(&subscription.events)
+        .bind(&subscription.secrets)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deactivates the subscription. Pending deliveries to it are cancelled.
+    pub async fn remove(&self, id: &str) -> Result<(), WebhookError> {
+        sqlx::query("UPDATE startup_webhook_subscriptions SET active = false WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SubscriptionStore for DatabaseSubscriptions {
+    async fn subscriptions(&self) -> Result<Vec<Subscription>, WebhookError> {
+        let rows = sqlx::query("SELECT id, url, events, secrets FROM startup_webhook_subscriptions WHERE active")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(subscription_from_row).collect()
+    }
+
+    async fn subscription(&self, id: &str) -> Result<Option<Subscription>, WebhookError> {
+        let row =
+            sqlx::query("SELECT id, url, events, secrets FROM startup_webhook_subscriptions WHERE id = $1 AND active")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        row.as_ref().map(subscription_from_row).transpose()
+    }
+}
+
+fn subscription_from_row(row: &sqlx::postgres::PgRow) -> Result<Subscription, WebhookError> {
+    let url: String = row.try_get("url")?;
+
+    Ok(Subscription {
+        id: row.try_get("id")?,
+        url: url
+            .parse()
+            .map_err(|err: url::ParseError| sqlx::Error::Decode(err.into()))?,
+        events: row.try_get("events")?,
+        secrets: row.try_get("secrets")?,
+    })
+}